@@ -3,22 +3,51 @@
 layout(location = 0) in vec3 f_normal;
 layout(location = 1) in vec2 f_diffuse;
 
+layout(push_constant) uniform PushConstants {
+  layout(offset = 128) float alpha;
+} push_constants;
+
+// set 0: per-frame
+layout(set = 0, binding = 0) uniform FrameUniforms {
+    vec4 light_anim_frames[16];
+    vec4 camera_pos;
+    float time;
+    float sky_time;
+    uint r_lightmap;
+    uint r_fullbrights;
+    uint gl_overbright;
+} frame_uniforms;
+
 // set 1: per-entity
 layout(set = 1, binding = 1) uniform sampler u_diffuse_sampler;
 
 // set 2: per-texture chain
 layout(set = 2, binding = 0) uniform texture2D u_diffuse_texture;
+layout(set = 2, binding = 1) uniform texture2D u_fullbright_texture;
 
 layout(location = 0) out vec4 diffuse_attachment;
 layout(location = 1) out vec4 normal_attachment;
 
+#include "dither.glsl"
+
 void main() {
+  if (dithered_out(push_constants.alpha)) {
+    discard;
+  }
+
   // TODO: get ambient light from uniform
   diffuse_attachment = vec4(texture(
     sampler2D(u_diffuse_texture, u_diffuse_sampler),
     f_diffuse
   ).rgb, 0.25);
 
+  // fullbright texels already have all the light they'll get, so they're left out of dynamic
+  // lights
+  float fullbright = 0.;
+  if (frame_uniforms.r_fullbrights != 0) {
+    fullbright = texture(sampler2D(u_fullbright_texture, u_diffuse_sampler), f_diffuse).r;
+  }
+
   // rescale normal to [0, 1]
-  normal_attachment = vec4(f_normal / 2.0 + 0.5, 1.0);
+  normal_attachment = vec4(f_normal / 2.0 + 0.5, 1.0 - fullbright);
 }
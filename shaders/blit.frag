@@ -0,0 +1,18 @@
+#version 450
+
+layout(location = 0) in vec2 f_texcoord;
+
+layout(set = 0, binding = 0) uniform sampler u_sampler;
+layout(set = 0, binding = 1) uniform texture2D u_source;
+
+layout(push_constant) uniform PushConstants {
+  // the fraction of the source's width and height that the scene was drawn into
+  vec2 source_scale;
+} push_constants;
+
+layout(location = 0) out vec4 color_attachment;
+
+void main() {
+  vec2 texcoord = f_texcoord * push_constants.source_scale;
+  color_attachment = texture(sampler2D(u_source, u_sampler), texcoord);
+}
@@ -9,6 +9,17 @@ const float WARP_AMPLITUDE = 0.15;
 const float WARP_FREQUENCY = 0.25;
 const float WARP_SCALE = 1.0;
 
+// the radius of the sky dome and the size of each sky layer, in texels
+const float SKY_DOME_SIZE = 6. * 63.;
+const float SKY_LAYER_SIZE = 128.;
+
+// the light that fullbright texels are drawn with, whatever the lightmap says
+const float FULLBRIGHT_LIGHT = 0.25;
+
+// with overbright lighting, a lightmap value of 128 is the texture's own color and brighter values
+// light it up to twice that
+const float OVERBRIGHT_SCALE = 2.;
+
 layout(location = 0) in vec3 f_normal;
 layout(location = 1) in vec3 f_diffuse; // also used for fullbright, for sky textures this is the position instead
 layout(location = 2) in vec2 f_lightmap;
@@ -16,6 +27,7 @@ flat layout(location = 3) in uvec4 f_lightmap_anim;
 
 layout(push_constant) uniform PushConstants {
   layout(offset = 128) uint texture_kind;
+  float alpha;
 } push_constants;
 
 // set 0: per-frame
@@ -24,6 +36,9 @@ layout(set = 0, binding = 0) uniform FrameUniforms {
     vec4 camera_pos;
     float time;
     float sky_time;
+    uint r_lightmap;
+    uint r_fullbrights;
+    uint gl_overbright;
 } frame_uniforms;
 
 // set 1: per-entity
@@ -43,57 +58,63 @@ layout(set = 3, binding = 0) uniform texture2D u_lightmap_texture[4];
 layout(location = 0) out vec4 diffuse_attachment;
 layout(location = 1) out vec4 normal_attachment;
 
-vec4 calc_light() {
-    vec4 light = vec4(0.0, 0.0, 0.0, 0.0);
+vec3 calc_light() {
+    vec3 light = vec3(0.0);
     for (int i = 0; i < 4; i++) {
         if (f_lightmap_anim[i] == LIGHTMAP_ANIM_END)
             break;
 
-        float map = texture(
+        vec3 map = texture(
             sampler2D(u_lightmap_texture[i], u_lightmap_sampler),
             f_lightmap
-        ).r;
+        ).rgb;
 
         // range [0, 4]
         ivec2 idx = ivec2(floor(f_lightmap_anim[i] / 4), mod(f_lightmap_anim[i], 4));
         float style = frame_uniforms.light_anim_frames[idx.x][idx.y];
-        light[i] = map * style;
+        light += map * style;
     }
 
-    return light;
-}
-
-// Compute line-plane intersection
-vec3 intersection(vec3 norm, vec3 plane_pos, vec3 plane_norm) {
-    float plane_dot = dot(norm, plane_norm);
-    vec3 w = f_diffuse - plane_pos;
-    float factor = -dot(w, plane_norm) / plane_dot;
+    // the styles add up to no more than a full lightmap, as when they were stored in one
+    light = min(light, vec3(1.));
+    if (frame_uniforms.gl_overbright != 0) {
+        light *= OVERBRIGHT_SCALE;
+    }
 
-    return w + norm * factor + plane_pos;
+    return light;
 }
 
-const mat3 RGB_2_XYZ = mat3(
-    0.4124564, 0.2126729, 0.0193339,
-    0.3575761, 0.7151522, 0.1191920,
-    0.1804375, 0.0721750, 0.9503041
-);
+#include "dither.glsl"
 
 // TODO: Convert this push constant to be separated shaders instead
 void main() {
+    if (dithered_out(push_constants.alpha)) {
+        discard;
+    }
+
+    float fullbright = 0.;
+
     switch (push_constants.texture_kind) {
         case TEXTURE_KIND_REGULAR:
-            float fullbright = texture(
-                sampler2D(u_fullbright_texture, u_diffuse_sampler),
-                f_diffuse.xy
-            ).r;
+            if (frame_uniforms.r_fullbrights != 0) {
+                fullbright = texture(
+                    sampler2D(u_fullbright_texture, u_diffuse_sampler),
+                    f_diffuse.xy
+                ).r;
+            }
 
+            // replacement textures can have partly fullbright texels
+            vec3 light = mix(calc_light(), vec3(FULLBRIGHT_LIGHT), fullbright);
 
-            float light = fullbright == 0. ? dot(calc_light(), vec4(1.)) : 0.25;
+            // the G-buffer only has room for the brightness of the light, so its color tints the
+            // diffuse color instead
+            float intensity = max(light.r, max(light.g, light.b));
+            vec3 tint = intensity > 0. ? light / intensity : vec3(1.);
 
             diffuse_attachment = vec4(texture(
                 sampler2D(u_diffuse_texture, u_diffuse_sampler),
                 f_diffuse.xy
-            ).rgb, light);
+            ).rgb * tint, intensity);
 
             break;
 
@@ -110,47 +131,46 @@ void main() {
                 sampler2D(u_diffuse_texture, u_diffuse_sampler),
                 warp_texcoord
             ).rgb, 0.25);
+
+            // liquids have no lightmap, and dynamic lights don't reach them either
+            fullbright = 1.;
             break;
 
         case TEXTURE_KIND_SKY:
-            // TODO: Convert these into cvars?
-            const float sky_height = 13000.;
-            const float cloud_height = 3000.;
-            const float sky_size = 60.;
-
-            const vec3 sky_plane_pos = vec3(0., 0., sky_height);
-            const vec3 cloud_plane_pos = vec3(0., 0., cloud_height);
-            const vec3 plane_norm = vec3(0., 0., -1);
-
-            // We calculate the diffuse coords here instead of in the vertex shader to prevent incorrect
-            // interpolation when the skybox is not parallel to the sky plane (e.g. for sky-textured walls)
-            // TODO: Is there a more-efficient way to do this?
-            // TODO: We want to make the horizon for the sky be at the bottom of the screen - what is the best way to do this?
-            vec3 dir = normalize(f_diffuse - frame_uniforms.camera_pos.xyz / frame_uniforms.camera_pos.w);
-
-            vec2 size = vec2(textureSize(sampler2D(u_diffuse_texture, u_diffuse_sampler), 0));
-
-            vec2 scroll = vec2(frame_uniforms.sky_time * 10.);
-
-            vec2 sky_coord = intersection(dir, sky_plane_pos, plane_norm).xy;
-            vec2 cloud_coord = intersection(dir, cloud_plane_pos, plane_norm).xy;
-
-            sky_coord = mod((sky_coord + scroll) / size.y / sky_size, 1.) * vec2(0.5, 1.) + vec2(0.5, 0.);
-            cloud_coord = mod((cloud_coord + scroll) / size.y / sky_size, 1.) * vec2(0.5, 1.);
-
-            vec4 sky_color = texture(
+            // the classic sky: the right half of the texture is a solid layer and the left half a
+            // layer of clouds over it, both projected onto a flattened dome around the camera and
+            // scrolled with the clouds going twice as fast
+            vec3 dir = f_diffuse - frame_uniforms.camera_pos.xyz / frame_uniforms.camera_pos.w;
+            dir.z *= 3.;
+            vec2 sky_dir = normalize(dir).xy * SKY_DOME_SIZE;
+
+            vec2 solid_coord = (sky_dir + frame_uniforms.sky_time) / SKY_LAYER_SIZE;
+            vec2 cloud_coord = (sky_dir + 2. * frame_uniforms.sky_time) / SKY_LAYER_SIZE;
+
+            // the halves are sampled with their own derivatives so that wrapping around doesn't
+            // leave seams of the smallest mipmap
+            vec2 dx = dFdx(solid_coord) * vec2(0.5, 1.);
+            vec2 dy = dFdy(solid_coord) * vec2(0.5, 1.);
+
+            vec3 solid_color = textureGrad(
                 sampler2D(u_diffuse_texture, u_diffuse_sampler),
-                sky_coord
-            );
-            vec4 cloud_color = texture(
+                fract(solid_coord) * vec2(0.5, 1.) + vec2(0.5, 0.),
+                dx,
+                dy
+            ).rgb;
+            vec3 cloud_color = textureGrad(
                 sampler2D(u_diffuse_texture, u_diffuse_sampler),
-                cloud_coord
-            );
+                fract(cloud_coord) * vec2(0.5, 1.),
+                dx,
+                dy
+            ).rgb;
+
+            // black texels of the cloud layer are holes through to the solid layer
+            float blend = any(greaterThan(cloud_color, vec3(0.))) ? 1. : 0.;
+            diffuse_attachment = vec4(mix(solid_color, cloud_color, blend), 0.25);
 
-            float lum = (RGB_2_XYZ * cloud_color.rgb).y;
-            float max_blend = 0.1;
-            float blend = clamp(lum, 0., max_blend) / max_blend;
-            diffuse_attachment = vec4(mix(sky_color.rgb, cloud_color.rgb, blend), 0.25);
+            // nor the sky, which is infinitely far away
+            fullbright = 1.;
             break;
 
         // not possible
@@ -158,6 +178,6 @@ void main() {
             break;
     }
 
-    // rescale normal to [0, 1]
-    normal_attachment = vec4(f_normal / 2.0 + 0.5, 1.0);
+    // rescale normal to [0, 1], and keep dynamic lights off fullbright texels
+    normal_attachment = vec4(f_normal / 2.0 + 0.5, 1.0 - fullbright);
 }
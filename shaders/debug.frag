@@ -0,0 +1,22 @@
+#version 450
+
+layout(location = 0) in vec3 f_local;
+
+layout(push_constant) uniform PushConstants {
+  layout(offset = 64) vec4 color;
+} push_constants;
+
+layout(location = 0) out vec4 diffuse_attachment;
+
+void main() {
+  // every fragment lies on one face, so it is on an edge if it is also near a second face
+  vec3 face_dist = min(f_local, 1.0 - f_local);
+  bvec3 near = lessThanEqual(face_dist, fwidth(f_local) * 1.5 + 1e-4);
+  bool edge = int(near.x) + int(near.y) + int(near.z) >= 2;
+
+  if (!edge && push_constants.color.a == 0.0) {
+    discard;
+  }
+
+  diffuse_attachment = vec4(push_constants.color.rgb, edge ? 1.0 : push_constants.color.a);
+}
@@ -0,0 +1,32 @@
+#version 450
+
+// if this is changed, it must also be changed in client::render::world::decal
+const uint DECAL_KIND_COUNT = 3;
+
+layout(location = 0) in vec2 f_texcoord;
+
+layout(push_constant) uniform PushConstants {
+  layout(offset = 64) uint kind;
+  float alpha;
+} push_constants;
+
+layout(set = 0, binding = 0) uniform sampler u_sampler;
+layout(set = 0, binding = 1) uniform texture2D u_texture[DECAL_KIND_COUNT];
+
+// the alpha of the diffuse attachment holds the surface's lighting, which is masked off by the
+// pipeline so that the decal is lit the same as the surface under it
+layout(location = 0) out vec4 diffuse_attachment;
+
+void main() {
+  vec4 tex_color = texture(
+    sampler2D(u_texture[push_constants.kind], u_sampler),
+    f_texcoord
+  );
+
+  float alpha = tex_color.a * push_constants.alpha;
+  if (alpha == 0.0) {
+    discard;
+  }
+
+  diffuse_attachment = vec4(tex_color.rgb, alpha);
+}
@@ -1,27 +1,55 @@
 #version 450
 
 // if this is changed, it must also be changed in client::entity
-const uint MAX_LIGHTS = 32;
+const uint MAX_LIGHTS = 256;
+
+// if these are changed, they must also be changed in client::render::world::deferred
+const uint TILE_COLUMNS = 16;
+const uint TILE_ROWS = 16;
+const uint MAX_LIGHTS_PER_TILE = 32;
+
+const uint TILE_COUNT = TILE_COLUMNS * TILE_ROWS;
 
 layout(location = 0) in vec2 a_texcoord;
 
 layout(set = 0, binding = 0) uniform sampler u_sampler;
 layout(set = 0, binding = 1) uniform sampler u_nearestsampler;
 layout(set = 0, binding = 2) uniform texture2D u_diffuse;
+#ifdef MULTISAMPLED
+layout(set = 0, binding = 3) uniform texture2DMS u_normal;
+layout(set = 0, binding = 4) uniform texture2DMS u_depth;
+#else
 layout(set = 0, binding = 3) uniform texture2D u_normal;
 layout(set = 0, binding = 4) uniform texture2D u_depth;
+#endif
 layout(set = 0, binding = 5) uniform DeferredUniforms {
   mat4 inv_projection;
   uint light_count;
   float exposure;
-  uvec2 _pad2;
+  float fog_density;
+  float overbright_scale;
+  vec4 fog_color;
   vec4 lights[MAX_LIGHTS];
 } u_deferred;
 
+// the indices of the lights that reach each tile of the screen, from left to right and then top
+// to bottom
+layout(std430, set = 0, binding = 6) readonly buffer LightTiles {
+  uint counts[TILE_COUNT];
+  uint lights[TILE_COUNT * MAX_LIGHTS_PER_TILE];
+} u_tiles;
+
 layout(location = 0) out vec4 color_attachment;
 
 const float MIN_LIGHT = 0.01;
 
+// as in Quake, a dynamic light adds its radius less the distance to it in 256ths of a full
+// lightmap, so bigger lights are brighter as well as reaching further
+const float DLIGHT_UNITS = 256.;
+
+// fog densities are given per 64 units, as in other engines
+const float FOG_SCALE = 1. / 64.;
+
 vec3 dlight_origin(vec4 dlight) {
   return dlight.xyz;
 }
@@ -30,6 +58,42 @@ float dlight_radius(vec4 dlight) {
   return dlight.w;
 }
 
+// with `r_scale` the scene only fills the corner of the G-buffer that the quad is drawn over, so
+// the G-buffer is read at the fragment's own pixel rather than at the quad's texture coordinates
+vec2 gbuffer_uv() {
+  return gl_FragCoord.xy / vec2(textureSize(sampler2D(u_diffuse, u_sampler), 0));
+}
+
+#ifdef MULTISAMPLED
+// the diffuse buffer is resolved before it's lit, but the normals and depth can't be averaged
+// meaningfully, so the first sample of each pixel is lit
+vec4 load_normal() {
+  return texelFetch(sampler2DMS(u_normal, u_nearestsampler), ivec2(gl_FragCoord.xy), 0);
+}
+
+float load_depth() {
+  return texelFetch(sampler2DMS(u_depth, u_nearestsampler), ivec2(gl_FragCoord.xy), 0).x;
+}
+#else
+vec4 load_normal() {
+  return texture(sampler2D(u_normal, u_sampler), gbuffer_uv());
+}
+
+float load_depth() {
+  return texture(sampler2D(u_depth, u_nearestsampler), gbuffer_uv()).x;
+}
+#endif
+
+// the quad's texture coordinates cover the scene however much of the G-buffer it fills, as the
+// tiles do
+uint light_tile() {
+  uvec2 tile = min(
+    uvec2(a_texcoord * vec2(TILE_COLUMNS, TILE_ROWS)),
+    uvec2(TILE_COLUMNS - 1, TILE_ROWS - 1)
+  );
+  return tile.y * TILE_COLUMNS + tile.x;
+}
+
 vec3 reconstruct_position(float depth) {
   float x = a_texcoord.s * 2.0 - 1.0;
   float y = (1.0 - a_texcoord.t) * 2.0 - 1.0;
@@ -39,31 +103,48 @@ vec3 reconstruct_position(float depth) {
 }
 
 void main() {
-  vec4 in_diffuse = texture(sampler2D(u_diffuse, u_sampler), a_texcoord);
+  vec4 in_diffuse = texture(sampler2D(u_diffuse, u_sampler), gbuffer_uv());
   vec4 in_color = vec4(in_diffuse.rgb, 1.);
 
+  vec4 normal_texel = load_normal();
+
   // scale from [0, 1] to [-1, 1]
-  vec3 in_normal = 2.0
-    * texture(sampler2D(u_normal, u_sampler), a_texcoord).xyz
-    - 1.0;
+  vec3 in_normal = 2.0 * normal_texel.xyz - 1.0;
 
-  float in_depth = texture(sampler2D(u_depth, u_nearestsampler), a_texcoord).x;
+  // fullbright texels aren't lit any further by dynamic lights
+  float dlight_scale = normal_texel.a;
+
+  float in_depth = load_depth();
   vec3 position = reconstruct_position(in_depth);
 
   vec4 out_color = in_color;
 
   float light = in_diffuse.a;
-  for (uint i = 0; i < u_deferred.light_count && i < MAX_LIGHTS; i++) {
-    vec4 dlight = u_deferred.lights[i];
+  uint tile = light_tile();
+  uint tile_light_count = min(u_tiles.counts[tile], MAX_LIGHTS_PER_TILE);
+  for (uint i = 0; i < tile_light_count; i++) {
+    uint light_id = u_tiles.lights[tile * MAX_LIGHTS_PER_TILE + i];
+    if (light_id >= u_deferred.light_count) {
+      continue;
+    }
+
+    vec4 dlight = u_deferred.lights[light_id];
     vec3 dir = normalize(position - dlight_origin(dlight));
     float dist = abs(distance(dlight_origin(dlight), position));
     float radius = dlight_radius(dlight);
 
     if (dist < radius && dot(dir, in_normal) < 0.0) {
-      // linear attenuation
-      light += (radius - dist) / radius;
+      // linear attenuation, brightened like the lightmaps
+      light += dlight_scale * u_deferred.overbright_scale * (radius - dist) / DLIGHT_UNITS;
     }
   }
 
-  color_attachment = vec4(u_deferred.exposure * max(MIN_LIGHT, light) * out_color.rgb, 1.0);
+  vec3 lit = u_deferred.exposure * max(MIN_LIGHT, light) * out_color.rgb;
+
+  // exponential squared fog, which is the same color however dark the surface behind it is
+  float fog_depth = u_deferred.fog_density * FOG_SCALE * length(position);
+  float visibility = clamp(exp(-fog_depth * fog_depth), 0.0, 1.0);
+  vec3 fog = u_deferred.exposure * u_deferred.fog_color.rgb;
+
+  color_attachment = vec4(mix(fog, lit, visibility), 1.0);
 }
@@ -0,0 +1,14 @@
+// 4x4 ordered dither thresholds
+const float DITHER[16] = float[](
+   0.,  8.,  2., 10.,
+  12.,  4., 14.,  6.,
+   3., 11.,  1.,  9.,
+  15.,  7., 13.,  5.
+);
+
+// The G-buffer can't blend, so translucent surfaces leave out a pattern of pixels instead, more of
+// them the lower `alpha` is. Returns whether this pixel is one of them.
+bool dithered_out(float alpha) {
+  ivec2 cell = ivec2(gl_FragCoord.xy) % 4;
+  return alpha * 16. <= DITHER[cell.y * 4 + cell.x];
+}
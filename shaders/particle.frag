@@ -1,26 +1,20 @@
 #version 450
 
 layout(location = 0) in vec2 f_texcoord;
-
-layout(push_constant) uniform PushConstants {
-  layout(offset = 64) uint color;
-} push_constants;
+layout(location = 1) flat in uint f_color;
 
 layout(set = 0, binding = 0) uniform sampler u_sampler;
-layout(set = 0, binding = 1) uniform texture2D u_texture[128];
+layout(set = 0, binding = 1) uniform texture2D u_shape;
+layout(set = 0, binding = 2) uniform texture2D u_palette;
 
 layout(location = 0) out vec4 diffuse_attachment;
 // layout(location = 1) out vec4 normal_attachment;
 
 void main() {
-  vec4 tex_color = texture(
-    sampler2D(u_texture[push_constants.color], u_sampler),
-    f_texcoord
-  );
-
-  if (tex_color.a == 0.0) {
+  if (texture(sampler2D(u_shape, u_sampler), f_texcoord).a == 0.0) {
     discard;
   }
 
-  diffuse_attachment = vec4(tex_color.rgb, 0.25);
+  vec4 color = texelFetch(sampler2D(u_palette, u_sampler), ivec2(f_color, 0), 0);
+  diffuse_attachment = vec4(color.rgb, 0.25);
 }
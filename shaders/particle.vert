@@ -2,14 +2,26 @@
 
 layout(location = 0) in vec3 a_position;
 layout(location = 1) in vec2 a_texcoord;
+layout(location = 2) in vec3 a_instance_position;
+layout(location = 3) in uint a_instance_color;
+layout(location = 4) in float a_instance_size;
 
 layout(push_constant) uniform PushConstants {
-  mat4 transform;
+  mat4 view_projection;
+  vec4 right;
+  vec4 up;
 } push_constants;
 
 layout(location = 0) out vec2 f_texcoord;
+layout(location = 1) flat out uint f_color;
 
 void main() {
   f_texcoord = a_texcoord;
-  gl_Position = push_constants.transform * vec4(a_position, 1.0);
+  f_color = a_instance_color;
+
+  // the quad is turned to face the camera
+  vec3 offset = a_instance_size * (
+    a_position.x * push_constants.right.xyz + a_position.y * push_constants.up.xyz
+  );
+  gl_Position = push_constants.view_projection * vec4(a_instance_position + offset, 1.0);
 }
@@ -0,0 +1,15 @@
+#version 450
+
+layout(location = 0) in vec3 f_barycentric;
+
+layout(location = 0) out vec4 diffuse_attachment;
+
+void main() {
+  vec3 edge_dist = f_barycentric / (fwidth(f_barycentric) + 1e-6);
+  if (min(edge_dist.x, min(edge_dist.y, edge_dist.z)) > 1.0) {
+    discard;
+  }
+
+  // the alpha channel is the light level, so the lines are drawn at full brightness
+  diffuse_attachment = vec4(1.0);
+}
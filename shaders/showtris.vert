@@ -0,0 +1,24 @@
+#version 450
+
+// only the position is read, so this works with the vertices of both brush and alias models
+layout(location = 0) in vec3 a_position;
+
+layout(push_constant) uniform PushConstants {
+  mat4 transform;
+} push_constants;
+
+// each corner of a triangle gets its own axis, so fragments near an edge have one component
+// near zero
+layout(location = 0) out vec3 f_barycentric;
+
+// convert from Quake coordinates
+vec3 convert(vec3 from) {
+  return vec3(-from.y, from.z, -from.x);
+}
+
+void main() {
+  // every model is drawn as a non-indexed triangle list, so the corner is the vertex index mod 3
+  uint corner = uint(gl_VertexIndex) % 3;
+  f_barycentric = vec3(corner == 0, corner == 1, corner == 2);
+  gl_Position = push_constants.transform * vec4(convert(a_position), 1.0);
+}
@@ -3,6 +3,10 @@
 layout(location = 0) in vec3 f_normal;
 layout(location = 1) in vec2 f_diffuse;
 
+layout(push_constant) uniform PushConstants {
+  float alpha;
+} push_constants;
+
 // set 1: per-entity
 layout(set = 1, binding = 1) uniform sampler u_diffuse_sampler;
 
@@ -12,7 +16,13 @@ layout(set = 2, binding = 0) uniform texture2D u_diffuse_texture;
 layout(location = 0) out vec4 diffuse_attachment;
 layout(location = 1) out vec4 normal_attachment;
 
+#include "dither.glsl"
+
 void main() {
+  if (dithered_out(push_constants.alpha)) {
+    discard;
+  }
+
   diffuse_attachment = vec4(texture(sampler2D(u_diffuse_texture, u_diffuse_sampler), f_diffuse).rgb, 1.);
 
   // rescale normal to [0, 1]
@@ -20,7 +20,6 @@
 
 #![recursion_limit = "256"]
 
-mod capture;
 mod menu;
 
 use std::{path::PathBuf, process::ExitCode};
@@ -39,13 +38,10 @@ use bevy::{
 };
 #[cfg(feature = "auto-exposure")]
 use bevy_mod_auto_exposure::{AutoExposure, AutoExposurePlugin};
-use capture::CapturePlugin;
 use clap::Parser;
-use seismon::{
-    client::SeismonClientPlugin,
-    common::console::{ConsoleInput, RegisterCmdExt as _, RunCmd},
-    server::SeismonServerPlugin,
-};
+#[cfg(feature = "screenrecord")]
+use seismon::client::capture::CapturePlugin;
+use seismon::{common::console::ConsoleInput, prelude::*};
 use serde_lexpr::Value;
 
 #[derive(Parser, Debug)]
@@ -279,9 +275,9 @@ fn main() -> ExitCode {
         base_dir: opt.base_dir.clone(),
         game: opt.game.clone(),
         main_menu: menu::build_main_menu,
+        headless: false,
     })
     .add_plugins(SeismonServerPlugin)
-    .add_plugins(CapturePlugin)
     .cvar_on_set(
         "cl_title",
         "Quake",
@@ -320,6 +316,9 @@ fn main() -> ExitCode {
     ).insert_resource(DefaultOpaqueRendererMethod::deferred())
         .add_systems(Startup, startup(opt));
 
+    #[cfg(feature = "screenrecord")]
+    app.add_plugins(CapturePlugin);
+
     #[cfg(feature = "auto-exposure")]
     app.add_plugins(AutoExposurePlugin).cvar_on_set(
         "r_autoexposure",
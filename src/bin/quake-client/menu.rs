@@ -27,7 +27,10 @@ use bevy::{
     log::warn,
 };
 use seismon::{
-    client::menu::{Menu, MenuBodyView, MenuBuilder, MenuView},
+    client::{
+        menu::{Menu, MenuBodyView, MenuBuilder, MenuView},
+        slist,
+    },
     common::console::{Registry, RunCmd},
 };
 
@@ -82,6 +85,7 @@ fn build_menu_mp(builder: MenuBuilder) -> Result<Menu, Error> {
 fn build_menu_mp_join(builder: MenuBuilder) -> Result<Menu, Error> {
     Ok(builder
         .add_submenu("TCP", build_menu_mp_join_tcp)?
+        .add_submenu(slist::MENU_NAME, slist::build_menu)?
         // .add_textbox // description
         .build(MenuView {
             draw_plaque: true,
@@ -0,0 +1,220 @@
+//! Output for screen readers and text-to-speech, so that blind players can find their way around
+//! the menus and follow what the game prints.
+//!
+//! With `cl_screenreader` set, everything printed to the console and the centre of the screen is
+//! sent again as an [`Announcement`], as is the menu item under the cursor whenever it or its
+//! value changes. Another plugin can read the events and speak them. Setting `cl_screenreader` to
+//! 2 also writes them to the log under the `screenreader` target, for screen readers that follow
+//! a terminal.
+
+use bevy::prelude::*;
+
+use crate::{
+    client::{
+        input::InputFocus,
+        menu::{Item, Menu, MenuState},
+    },
+    common::console::{ConsoleText, Registry, RenderConsoleOutput, Timestamp},
+};
+
+/// Text for a screen reader to read out.
+#[derive(Event, Clone, Debug, PartialEq, Eq)]
+pub enum Announcement {
+    /// A line printed to the console.
+    Console(String),
+    /// A message printed in the centre of the screen.
+    CenterPrint(String),
+    /// The menu item under the cursor, with its value if it has one.
+    Menu(String),
+}
+
+impl Announcement {
+    pub fn text(&self) -> &str {
+        match self {
+            Announcement::Console(text)
+            | Announcement::CenterPrint(text)
+            | Announcement::Menu(text) => text,
+        }
+    }
+}
+
+pub struct SeismonAccessibilityPlugin;
+
+impl Plugin for SeismonAccessibilityPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<Announcement>().add_systems(
+            Update,
+            (
+                (
+                    systems::announce_console,
+                    systems::announce_center_print,
+                    systems::announce_menu,
+                ),
+                systems::write_announcements,
+            )
+                .chain(),
+        );
+    }
+}
+
+/// How a menu item is read out.
+fn describe(name: &str, item: &Item) -> String {
+    match item {
+        Item::Submenu(_) | Item::Action(_) | Item::Command(_) => name.to_owned(),
+        Item::Toggle(toggle) => format!("{}: {}", name, if toggle.get() { "on" } else { "off" }),
+        Item::Enum(e) => format!("{}: {}", name, e.selected_name()),
+        Item::Slider(slider) => format!("{}: {}%", name, (slider.fraction() * 100.).round()),
+        Item::TextField(text) if text.is_empty() => format!("{}: blank", name),
+        Item::TextField(text) => format!("{}: {}", name, text.text()),
+    }
+}
+
+/// How the selected item of `menu` is read out, or `None` if no item is selected.
+fn describe_selection(menu: &Menu) -> Option<String> {
+    let menu = menu.active_submenu().ok()?;
+    let MenuState::Active { index } = menu.state() else {
+        return None;
+    };
+    let item = menu.items().nth(index)?;
+
+    Some(describe(item.name(), item.item()))
+}
+
+/// The lines of console output worth reading out.
+fn lines(text: &str) -> impl Iterator<Item = &str> + '_ {
+    text.lines().map(str::trim).filter(|line| !line.is_empty())
+}
+
+/// The value of `cl_screenreader`: 0 for off, 1 for events only and 2 for the log too.
+fn level(registry: &Registry) -> u8 {
+    registry.read_cvar::<u8>("cl_screenreader").unwrap_or(0)
+}
+
+mod systems {
+    use super::*;
+
+    pub fn announce_console(
+        registry: Res<Registry>,
+        mut printed: EventReader<ConsoleText>,
+        mut announcements: EventWriter<Announcement>,
+    ) {
+        if level(&registry) == 0 {
+            printed.clear();
+            return;
+        }
+
+        for printed in printed.read() {
+            for line in lines(&printed.text.to_str()) {
+                announcements.send(Announcement::Console(line.to_owned()));
+            }
+        }
+    }
+
+    pub fn announce_center_print(
+        registry: Res<Registry>,
+        console: Res<RenderConsoleOutput>,
+        mut last: Local<Timestamp>,
+        mut announcements: EventWriter<Announcement>,
+    ) {
+        let center = &console.center_print;
+        if center.timestamp == *last {
+            return;
+        }
+        *last = center.timestamp;
+
+        if level(&registry) != 0 {
+            let text = lines(&center.text.to_str()).collect::<Vec<_>>().join(" ");
+            if !text.is_empty() {
+                announcements.send(Announcement::CenterPrint(text));
+            }
+        }
+    }
+
+    pub fn announce_menu(
+        registry: Res<Registry>,
+        focus: Res<InputFocus>,
+        menu: Option<Res<Menu>>,
+        mut last: Local<Option<String>>,
+        mut announcements: EventWriter<Announcement>,
+    ) {
+        let selection = match menu {
+            Some(menu) if *focus == InputFocus::Menu && level(&registry) != 0 => {
+                describe_selection(&menu)
+            }
+            // announce the selection again when the menu is next opened
+            _ => None,
+        };
+
+        if selection != *last {
+            if let Some(selection) = &selection {
+                announcements.send(Announcement::Menu(selection.clone()));
+            }
+            *last = selection;
+        }
+    }
+
+    pub fn write_announcements(
+        registry: Res<Registry>,
+        mut announcements: EventReader<Announcement>,
+    ) {
+        if level(&registry) < 2 {
+            announcements.clear();
+            return;
+        }
+
+        for announcement in announcements.read() {
+            info!(target: "screenreader", "{}", announcement.text());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::client::menu::{Enum, EnumItem, Slider, TextField, Toggle};
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(describe("Quit", &Item::Command("quit".into())), "Quit");
+        assert_eq!(
+            describe(
+                "Always Run",
+                &Item::Toggle(Toggle::new(true, "cl_alwaysrun"))
+            ),
+            "Always Run: on"
+        );
+
+        let items = [
+            EnumItem::new("Off", "0").unwrap(),
+            EnumItem::new("On", "1").unwrap(),
+        ];
+        assert_eq!(
+            describe("Lookspring", &Item::Enum(Enum::new(1, "lookspring", items))),
+            "Lookspring: On"
+        );
+
+        let slider = Slider::new(0., 1., 11, 7, "volume".into()).unwrap();
+        assert_eq!(
+            describe("Sound Volume", &Item::Slider(slider)),
+            "Sound Volume: 70%"
+        );
+
+        let blank = TextField::new(None::<String>, None, "hostname");
+        assert_eq!(
+            describe("Hostname", &Item::TextField(blank)),
+            "Hostname: blank"
+        );
+        let name = TextField::new(Some("player"), None, "_cl_name");
+        assert_eq!(describe("Name", &Item::TextField(name)), "Name: player");
+    }
+
+    #[test]
+    fn test_lines() {
+        assert_eq!(
+            lines("Player was gibbed\n\n  Player2 left the game \n").collect::<Vec<_>>(),
+            vec!["Player was gibbed", "Player2 left the game"]
+        );
+        assert_eq!(lines("\n").count(), 0);
+    }
+}
@@ -0,0 +1,154 @@
+//! Switching to a newly picked up weapon when it is better than the one in hand, once turned on
+//! with `cl_autoswitch 1`.
+//!
+//! `cl_weaponpriority` lists weapon numbers (as used by `impulse 1`-`impulse 8`) from most to
+//! least preferred, e.g. `853421`. Weapons missing from the list are never switched to
+//! automatically, which by default keeps the explosive weapons from being selected in close
+//! quarters. Once one of them has been selected by hand, it is kept.
+
+use bevy::prelude::*;
+
+use crate::{
+    client::{Connection, ConnectionKind, Impulse},
+    common::{
+        console::Registry,
+        net::{ClientStat, ItemFlags},
+    },
+};
+
+/// Each weapon's `impulse` number and item flag.
+const WEAPONS: [(u8, ItemFlags); 8] = [
+    (1, ItemFlags::AXE),
+    (2, ItemFlags::SHOTGUN),
+    (3, ItemFlags::SUPER_SHOTGUN),
+    (4, ItemFlags::NAILGUN),
+    (5, ItemFlags::SUPER_NAILGUN),
+    (6, ItemFlags::GRENADE_LAUNCHER),
+    (7, ItemFlags::ROCKET_LAUNCHER),
+    (8, ItemFlags::LIGHTNING),
+];
+
+pub struct SeismonAutoswitchPlugin;
+
+impl Plugin for SeismonAutoswitchPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            systems::autoswitch_weapon.run_if(resource_exists::<Connection>),
+        );
+    }
+}
+
+/// Parses a weapon priority list into weapon numbers, best first. Anything other than a weapon
+/// number is ignored.
+fn parse_priority(priority: &str) -> Vec<u8> {
+    let mut weapons = Vec::new();
+    for num in priority.chars().filter_map(|c| c.to_digit(10)) {
+        let num = num as u8;
+        if WEAPONS.iter().any(|&(n, _)| n == num) && !weapons.contains(&num) {
+            weapons.push(num);
+        }
+    }
+
+    weapons
+}
+
+/// Returns the weapon to switch to after picking up `new_items`, if any of them rank above
+/// the active weapon.
+fn choose_weapon(priority: &[u8], new_items: ItemFlags, active: ItemFlags) -> Option<u8> {
+    let rank = |flag: ItemFlags| {
+        WEAPONS
+            .iter()
+            .find(|&&(_, f)| f == flag)
+            .and_then(|(num, _)| priority.iter().position(|p| p == num))
+    };
+
+    let (best, best_rank) = priority.iter().enumerate().find_map(|(i, num)| {
+        let (_, flag) = WEAPONS.iter().find(|&&(n, _)| n == *num)?;
+        new_items.contains(*flag).then_some((*num, i))
+    })?;
+
+    match rank(active) {
+        Some(active_rank) if active_rank <= best_rank => None,
+        Some(_) => Some(best),
+        // an unranked weapon was picked deliberately, so keep it
+        None if !active.is_empty() => None,
+        None => Some(best),
+    }
+}
+
+mod systems {
+    use super::*;
+
+    pub fn autoswitch_weapon(
+        registry: Res<Registry>,
+        conn: Res<Connection>,
+        mut last_items: Local<Option<ItemFlags>>,
+        mut impulses: EventWriter<Impulse>,
+    ) {
+        let items = conn.state.items();
+        let Some(last) = last_items.replace(items) else {
+            return;
+        };
+
+        // demos already contain the player's choices
+        if !matches!(conn.kind, ConnectionKind::Server { .. }) {
+            return;
+        }
+
+        let new_items = items - last;
+        if new_items.is_empty() || registry.read_cvar::<u8>("cl_autoswitch").unwrap_or(0) == 0 {
+            return;
+        }
+
+        // `serde_lexpr` reads a list of digits as a number, so use its text
+        let priority = match registry.get_cvar("cl_weaponpriority") {
+            Some(cvar) => cvar.value().to_string(),
+            None => return,
+        };
+
+        let active = ItemFlags::from_bits_truncate(
+            conn.state.stats()[ClientStat::ActiveWeapon as usize] as u32,
+        );
+        if let Some(weapon) = choose_weapon(&parse_priority(&priority), new_items, active) {
+            impulses.send(Impulse(weapon));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_choose_weapon() {
+        let priority = parse_priority("853421");
+        assert_eq!(priority, vec![8, 5, 3, 4, 2, 1]);
+
+        // better than the shotgun
+        assert_eq!(
+            choose_weapon(&priority, ItemFlags::NAILGUN, ItemFlags::SHOTGUN),
+            Some(4)
+        );
+        // worse than the super nailgun
+        assert_eq!(
+            choose_weapon(&priority, ItemFlags::NAILGUN, ItemFlags::SUPER_NAILGUN),
+            None
+        );
+        // not in the list
+        assert_eq!(
+            choose_weapon(&priority, ItemFlags::ROCKET_LAUNCHER, ItemFlags::AXE),
+            None
+        );
+        // the rocket launcher isn't ranked, so it is kept once selected
+        assert_eq!(
+            choose_weapon(&priority, ItemFlags::SHOTGUN, ItemFlags::ROCKET_LAUNCHER),
+            None
+        );
+        // ammo and other items don't count
+        assert_eq!(
+            choose_weapon(&priority, ItemFlags::SHELLS, ItemFlags::AXE),
+            None
+        );
+    }
+}
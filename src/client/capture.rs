@@ -0,0 +1,787 @@
+//! Video capture, with `startvideo` and `stopvideo`, and screenshots including the UI, with
+//! `screenshot_ui`.
+//!
+//! Videos are encoded as H.264 along with the sound of the mixer. The sound is written to a WAV
+//! file while recording, and is added to the video by the `ffmpeg` program once recording stops.
+//! If that fails, the WAV file is kept next to the video instead.
+//!
+//! `startvideo --offline` steps the game forward by exactly one video frame every frame rather
+//! than following the clock, so that demos can be rendered to video at a steady frame rate however
+//! long each frame takes to draw. The mixer plays on the audio device, which sets its pace, so the
+//! game is held on a frame until the mixer has played that frame's worth of sound, and exactly that
+//! much is recorded. Every sound then starts on the frame that played it. The mixer can't be
+//! stopped while a frame is drawn, though, so frames that take longer than two video frames to
+//! draw still lose some of their sound.
+//!
+//! Adding the sound to the video needs the `ffmpeg` program to be on the `PATH` when recording
+//! stops.
+
+use clap::Parser;
+use crossbeam_channel::{Receiver, Sender};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{atomic::AtomicBool, Arc},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use bevy::{
+    prelude::*, render::view::screenshot::ScreenshotManager, time::TimeUpdateStrategy,
+    window::PrimaryWindow,
+};
+use byteorder::{LittleEndian, WriteBytesExt as _};
+use chrono::Utc;
+use image::RgbImage;
+
+use crate::{
+    client::sound::GetGlobalAudio,
+    common::console::{ConsoleOutput, RegisterCmdExt as _},
+};
+
+/// The rate that the mixer's output is sampled at, which is the rate that its nodes run at unless
+/// they're told otherwise.
+const AUDIO_SAMPLE_RATE: u32 = 44_100;
+const AUDIO_CHANNELS: u16 = 2;
+/// How long the game is held, offline, for a mixer that has stopped playing before the video is
+/// given up on having sound.
+const MIXER_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct CapturePlugin;
+
+impl Plugin for CapturePlugin {
+    fn build(&self, app: &mut App) {
+        #[derive(Parser)]
+        #[command(
+            name = "screenshot_ui",
+            about = "Take a screenshot of the whole screen, including the console, HUD and menus"
+        )]
+        struct ScreenshotUi {
+            path: Option<PathBuf>,
+        }
+
+        #[derive(Parser)]
+        #[command(name = "startvideo", about = "Start recording a video")]
+        struct StartVideo {
+            path: Option<PathBuf>,
+            #[arg(long)]
+            width: Option<u32>,
+            #[arg(long)]
+            height: Option<u32>,
+            /// Advance the game by one video frame each frame instead of in real time, for
+            /// rendering demos
+            #[arg(long)]
+            offline: bool,
+        }
+
+        #[derive(Parser)]
+        #[command(name = "stopvideo", about = "Stop recording")]
+        struct StopVideo;
+
+        app.init_resource::<Muxing>();
+        app.add_systems(
+            Update,
+            (
+                systems::video_frame.run_if(resource_exists::<VideoCtx>),
+                systems::recv_frame.run_if(resource_exists::<VideoCtxRecv>),
+                systems::finish_mux.run_if(|muxing: Res<Muxing>| !muxing.0.is_empty()),
+            ),
+        )
+        .command(
+            |In(ScreenshotUi { path }),
+             window: Query<Entity, With<PrimaryWindow>>,
+             mut screenshot_manager: ResMut<ScreenshotManager>| {
+                match save_screenshot(&window, &mut screenshot_manager, path) {
+                    Ok(()) => default(),
+                    Err(e) => e.into(),
+                }
+            },
+        )
+        .command(
+            |In(StartVideo {
+                 path,
+                 width,
+                 height,
+                 offline,
+             }),
+             mut commands: Commands,
+             window: Query<&Window, With<PrimaryWindow>>,
+             global_audio: Option<Res<GetGlobalAudio>>,
+             ctx: Option<Res<VideoCtx>>| {
+                fn ceil_to(x: u32, to: u32) -> u32 {
+                    let x = x + (to - 1);
+                    x - (x % to)
+                }
+
+                const LONGEST_SIDE: u32 = 800;
+                const FPS: f64 = 30.;
+
+                if ctx.is_some() {
+                    return "Already recording video".into();
+                }
+
+                let mut path = match path {
+                    // TODO: make default path configurable
+                    None => {
+                        PathBuf::from(format!("richter-{}.mp4", Utc::now().format("%FT%H-%M-%S")))
+                    }
+                    Some(path) => path,
+                };
+                if path.extension().is_none() {
+                    path.set_extension("mp4");
+                }
+
+                let aspect_ratio = window
+                    .get_single()
+                    .map(|w| w.width() / w.height())
+                    .unwrap_or(4. / 3.);
+                let size = match (width, height) {
+                    (Some(w), Some(h)) => [w, h],
+                    (Some(w), None) => [w, (w as f32 / aspect_ratio) as u32],
+                    (None, Some(h)) => [(h as f32 * aspect_ratio) as u32, h],
+                    (None, None) => {
+                        if aspect_ratio < 1. {
+                            [(LONGEST_SIDE as f32 * aspect_ratio) as u32, LONGEST_SIDE]
+                        } else {
+                            [LONGEST_SIDE, (LONGEST_SIDE as f32 / aspect_ratio) as u32]
+                        }
+                    }
+                };
+                let [w, h] = size.map(|x| ceil_to(x, 10));
+
+                let audio = match &global_audio {
+                    Some(global_audio) => {
+                        let audio_path = path.with_extension("wav");
+                        let writer = File::create(&audio_path).and_then(|file| {
+                            WavWriter::new(BufWriter::new(file), AUDIO_SAMPLE_RATE, AUDIO_CHANNELS)
+                        });
+                        match writer {
+                            Ok(writer) => Some(AudioCapture {
+                                writer,
+                                path: audio_path,
+                                last_total: global_audio.total(),
+                            }),
+                            Err(e) => {
+                                return format!("Couldn't create {}: {}", audio_path.display(), e)
+                                    .into()
+                            }
+                        }
+                    }
+                    None => None,
+                };
+
+                // with sound, the video is written to its own file until the sound is added
+                let mux = audio.as_ref().map(|audio| Mux {
+                    video: path.with_extension(format!(
+                        "video.{}",
+                        path.extension().unwrap_or_default().to_string_lossy()
+                    )),
+                    audio: audio.path.clone(),
+                });
+
+                let out = format!(
+                    "Recording a video ({}x{}{}) to {}",
+                    w,
+                    h,
+                    if offline { ", offline" } else { "" },
+                    path.display()
+                );
+
+                let video_path = mux.as_ref().map_or(&path, |mux| &mux.video);
+                let encoder = match video_rs::Encoder::new(
+                    &video_path.clone().into(),
+                    video_rs::EncoderSettings::for_h264_yuv420p(w as _, h as _, true),
+                ) {
+                    Ok(encoder) => encoder,
+                    Err(e) => {
+                        if let Some(AudioCapture {
+                            writer,
+                            path: audio_path,
+                            ..
+                        }) = audio
+                        {
+                            drop(writer);
+                            let _ = fs::remove_file(audio_path);
+                        }
+                        return format!("Couldn't create {}: {}", video_path.display(), e).into();
+                    }
+                };
+
+                let (sender, receiver) = crossbeam_channel::unbounded();
+                let frame_time = Duration::from_secs_f64(FPS.recip());
+
+                if offline {
+                    commands.insert_resource(TimeUpdateStrategy::ManualDuration(frame_time));
+                }
+
+                commands.insert_resource(VideoCtx {
+                    send_frame: sender,
+                    size: (w, h),
+                    frame_time,
+                    last_time: None,
+                    cur_frame: 0,
+                    closed: Arc::new(false.into()),
+                    offline,
+                    held_since: None,
+                    audio,
+                });
+
+                commands.insert_resource(VideoCtxRecv {
+                    recv_frame: Some(receiver),
+                    frame_buf: default(),
+                    encoder,
+                    frame_time: video_rs::Time::from_nth_of_a_second(FPS as _),
+                    cur_frame: 0,
+                    path,
+                    mux,
+                });
+
+                out.into()
+            },
+        )
+        .command(
+            |In(StopVideo), mut commands: Commands, ctx: Option<Res<VideoCtx>>| {
+                if let Some(ctx) = ctx {
+                    stop_video(&mut commands, &ctx);
+                    default()
+                } else {
+                    "Error: no video recording in progress".into()
+                }
+            },
+        );
+    }
+}
+
+/// Saves the next frame drawn in the window to `path`, or to a file named after the time.
+fn save_screenshot(
+    window: &Query<Entity, With<PrimaryWindow>>,
+    screenshot_manager: &mut ScreenshotManager,
+    path: Option<PathBuf>,
+) -> Result<(), String> {
+    let Ok(window) = window.get_single() else {
+        return Err("Can't find primary window".to_owned());
+    };
+
+    let path = match path {
+        // TODO: make default path configurable
+        None => PathBuf::from(format!("richter-{}.png", Utc::now().format("%FT%H-%M-%S"))),
+        Some(path) => path,
+    };
+
+    screenshot_manager
+        .save_screenshot_to_disk(window, path)
+        .map_err(|e| format!("Couldn't take screenshot: {}", e))
+}
+
+/// Stops recording, which finishes the video once the frames already taken have been encoded.
+fn stop_video(commands: &mut Commands, ctx: &VideoCtx) {
+    if ctx.offline {
+        commands.insert_resource(TimeUpdateStrategy::Automatic);
+    }
+    commands.remove_resource::<VideoCtx>();
+}
+
+/// Writes 16-bit PCM samples to a WAV file as they arrive.
+struct WavWriter<W: Write + Seek> {
+    inner: W,
+    channels: u16,
+    data_len: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    const HEADER_LEN: u32 = 44;
+
+    fn new(mut inner: W, sample_rate: u32, channels: u16) -> io::Result<Self> {
+        let block_align = channels * 2;
+
+        // the chunk lengths are filled in by `finish`, once they're known
+        inner.write_all(b"RIFF")?;
+        inner.write_u32::<LittleEndian>(0)?;
+        inner.write_all(b"WAVE")?;
+        inner.write_all(b"fmt ")?;
+        inner.write_u32::<LittleEndian>(16)?;
+        // PCM
+        inner.write_u16::<LittleEndian>(1)?;
+        inner.write_u16::<LittleEndian>(channels)?;
+        inner.write_u32::<LittleEndian>(sample_rate)?;
+        inner.write_u32::<LittleEndian>(sample_rate * block_align as u32)?;
+        inner.write_u16::<LittleEndian>(block_align)?;
+        // bits per sample
+        inner.write_u16::<LittleEndian>(16)?;
+        inner.write_all(b"data")?;
+        inner.write_u32::<LittleEndian>(0)?;
+
+        Ok(WavWriter {
+            inner,
+            channels,
+            data_len: 0,
+        })
+    }
+
+    /// Write interleaved samples, which are clipped to `-1..=1`.
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        for &sample in samples {
+            let sample = (sample.clamp(-1., 1.) * i16::MAX as f32) as i16;
+            self.inner.write_i16::<LittleEndian>(sample)?;
+        }
+        self.data_len += samples.len() as u32 * 2;
+
+        Ok(())
+    }
+
+    /// Write `frames` samples of silence on every channel.
+    fn write_silence(&mut self, frames: usize) -> io::Result<()> {
+        self.write_samples(&vec![0.; frames * self.channels as usize])
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        self.inner.seek(SeekFrom::Start(4))?;
+        self.inner
+            .write_u32::<LittleEndian>(Self::HEADER_LEN - 8 + self.data_len)?;
+        self.inner
+            .seek(SeekFrom::Start(Self::HEADER_LEN as u64 - 4))?;
+        self.inner.write_u32::<LittleEndian>(self.data_len)?;
+        self.inner.flush()?;
+
+        Ok(self.inner)
+    }
+}
+
+struct AudioCapture {
+    writer: WavWriter<BufWriter<File>>,
+    path: PathBuf,
+    /// How many samples the mixer had output when this last took them.
+    last_total: u64,
+}
+
+impl AudioCapture {
+    /// Whether the mixer has played all of a `frame_time` long frame since the last one written.
+    fn frame_ready(&self, global_audio: &GetGlobalAudio, frame_time: Duration) -> bool {
+        global_audio.total() >= self.last_total + frame_samples(frame_time)
+    }
+
+    /// Write the samples that the mixer has output since the last frame.
+    ///
+    /// In offline capture, every frame is `frame_time` long, and exactly that much sound is
+    /// written, carrying on from the end of the last frame. Sound the mixer played more than a
+    /// frame ahead of that is skipped, so that a slow frame doesn't put the sound behind the video.
+    fn write_frame(
+        &mut self,
+        global_audio: &GetGlobalAudio,
+        frame_time: Option<Duration>,
+    ) -> io::Result<()> {
+        let Some(frame_time) = frame_time else {
+            let samples = global_audio.interleaved_since(self.last_total);
+            self.last_total = global_audio.total();
+            return self.writer.write_samples(&samples);
+        };
+
+        let frame_samples = frame_samples(frame_time);
+        let end = self.last_total + frame_samples;
+
+        // anything too old to still be held is replaced with silence where it was
+        let lost = global_audio.oldest().clamp(self.last_total, end) - self.last_total;
+        self.writer.write_silence(lost as usize)?;
+        let samples = global_audio.interleaved(self.last_total + lost, (frame_samples - lost) as _);
+        self.writer.write_samples(&samples)?;
+        let written = lost as usize + samples.len() / AUDIO_CHANNELS as usize;
+        self.writer
+            .write_silence(frame_samples as usize - written)?;
+
+        self.last_total = end.max(global_audio.total().saturating_sub(frame_samples));
+        Ok(())
+    }
+}
+
+/// How many samples of each channel the mixer outputs in `frame_time`.
+fn frame_samples(frame_time: Duration) -> u64 {
+    (AUDIO_SAMPLE_RATE as f64 * frame_time.as_secs_f64()).round() as u64
+}
+
+/// The files that a video and its sound were written to, before the sound is added to the video.
+struct Mux {
+    video: PathBuf,
+    audio: PathBuf,
+}
+
+impl Mux {
+    /// Adds the sound to the video, writing them both to `path` and removing the separate files.
+    /// If that fails, the video is moved to `path` without sound, and the sound is left where it
+    /// is.
+    fn run(self, path: &Path) -> Result<(), String> {
+        // sound that couldn't be written is removed, and that's been reported already
+        if !self.audio.exists() {
+            return self.keep_video(path);
+        }
+
+        let status = Command::new("ffmpeg")
+            .args(["-y", "-v", "error", "-i"])
+            .arg(&self.video)
+            .arg("-i")
+            .arg(&self.audio)
+            .args([
+                "-map",
+                "0:v",
+                "-map",
+                "1:a",
+                "-c:v",
+                "copy",
+                "-c:a",
+                "aac",
+                "-shortest",
+            ])
+            .arg(path)
+            .status();
+
+        let error = match status {
+            Ok(status) if status.success() => {
+                let _ = fs::remove_file(&self.video);
+                let _ = fs::remove_file(&self.audio);
+                return Ok(());
+            }
+            Ok(status) => format!("ffmpeg failed ({})", status),
+            Err(e) => format!("couldn't run ffmpeg: {}", e),
+        };
+
+        self.keep_video(path)?;
+        Err(format!(
+            "Couldn't add sound to {} ({}), it was kept in {}",
+            path.display(),
+            error,
+            self.audio.display()
+        ))
+    }
+
+    fn keep_video(&self, path: &Path) -> Result<(), String> {
+        fs::rename(&self.video, path).map_err(|e| {
+            format!(
+                "Couldn't move {} to {}: {}",
+                self.video.display(),
+                path.display(),
+                e
+            )
+        })
+    }
+}
+
+/// Videos that are having their sound added, which is done on threads of their own as it takes
+/// about as long as encoding them did.
+#[derive(Resource, Default)]
+struct Muxing(Vec<JoinHandle<Result<(), String>>>);
+
+struct VideoFrame {
+    image: RgbImage,
+    frame_id: usize,
+}
+
+#[derive(Resource)]
+struct VideoCtx {
+    /// Sends each frame to be encoded, or the reason it couldn't be taken.
+    send_frame: Sender<Result<VideoFrame, String>>,
+    size: (u32, u32),
+    last_time: Option<Duration>,
+    frame_time: Duration,
+    cur_frame: usize,
+    closed: Arc<AtomicBool>,
+    /// Whether the game is stepped by exactly `frame_time` each frame.
+    offline: bool,
+    /// When the game started being held on the current frame, offline, for the mixer to catch up.
+    held_since: Option<Duration>,
+    audio: Option<AudioCapture>,
+}
+
+impl Drop for VideoCtx {
+    fn drop(&mut self) {
+        if let Some(audio) = self.audio.take() {
+            if let Err(e) = audio.writer.finish() {
+                error!("Couldn't finish writing video audio: {}", e);
+            }
+        }
+    }
+}
+
+#[derive(Resource)]
+struct VideoCtxRecv {
+    recv_frame: Option<Receiver<Result<VideoFrame, String>>>,
+    frame_buf: BTreeMap<usize, RgbImage>,
+    cur_frame: usize,
+    frame_time: video_rs::Time,
+    encoder: video_rs::Encoder,
+    /// Where the finished video goes.
+    path: PathBuf,
+    /// The separate files of the video and its sound, if it has sound.
+    mux: Option<Mux>,
+}
+
+impl VideoCtxRecv {
+    fn encode(&mut self, frame: RgbImage) -> Result<(), String> {
+        let frame = frame.into_flat_samples();
+        let frame_array = ndarray::Array3::<u8>::from_shape_vec(
+            (
+                frame.layout.height as usize,
+                frame.layout.width as usize,
+                frame.layout.channels as usize,
+            ),
+            frame.samples,
+        )
+        .map_err(|e| e.to_string())?;
+        let time = video_rs::Time::new(
+            Some(self.cur_frame as _),
+            self.frame_time.clone().into_parts().1,
+        );
+        self.encoder
+            .encode(&frame_array, &time)
+            .map_err(|e| e.to_string())?;
+        self.cur_frame += 1;
+
+        Ok(())
+    }
+
+    /// Stops taking frames, so that the video ends with the frames already encoded.
+    fn abandon(&mut self) {
+        self.recv_frame = None;
+        self.frame_buf.clear();
+    }
+}
+
+mod systems {
+    use crossbeam_channel::TryRecvError;
+    use std::sync::atomic::Ordering;
+
+    use image::imageops::FilterType;
+
+    use super::*;
+
+    pub fn video_frame(
+        mut commands: Commands,
+        mut screenshot: ResMut<ScreenshotManager>,
+        window: Query<Entity, With<PrimaryWindow>>,
+        time: Res<Time>,
+        real_time: Res<Time<Real>>,
+        mut global_audio: Option<ResMut<GetGlobalAudio>>,
+        mut console: ResMut<ConsoleOutput>,
+        mut ctx: ResMut<VideoCtx>,
+    ) {
+        let Ok(window) = window.get_single() else {
+            stop_video(&mut commands, &ctx);
+            return;
+        };
+
+        if ctx.closed.load(Ordering::SeqCst) {
+            stop_video(&mut commands, &ctx);
+            return;
+        }
+
+        // offline, the game stays on this frame until the mixer has played its sound
+        if ctx.offline {
+            if let (Some(audio), Some(global_audio)) = (&ctx.audio, &mut global_audio) {
+                global_audio.update();
+                let ready = audio.frame_ready(global_audio, ctx.frame_time);
+                let now = real_time.elapsed();
+                let held_since = ctx.held_since;
+                match held_since {
+                    _ if ready => {}
+                    None => {
+                        ctx.held_since = Some(now);
+                        commands
+                            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::ZERO));
+                        return;
+                    }
+                    Some(since) if now - since < MIXER_TIMEOUT => return,
+                    Some(_) => {
+                        let timestamp = chrono::Duration::from_std(now).unwrap();
+                        console.println(
+                            "The mixer stopped playing, so the video is recorded without sound",
+                            timestamp,
+                        );
+                        if let Some(audio) = ctx.audio.take() {
+                            let _ = fs::remove_file(&audio.path);
+                        }
+                    }
+                }
+
+                if ctx.held_since.take().is_some() {
+                    commands.insert_resource(TimeUpdateStrategy::ManualDuration(ctx.frame_time));
+                }
+            }
+        }
+
+        // offline, every frame is exactly one video frame long
+        if ctx.offline
+            || ctx
+                .last_time
+                .map(|t| time.elapsed() >= (t + ctx.frame_time))
+                .unwrap_or(true)
+        {
+            let sender = ctx.send_frame.clone();
+            let frame_id = ctx.cur_frame;
+            let size = ctx.size;
+            let closed = ctx.closed.clone();
+
+            ctx.last_time = Some(time.elapsed());
+
+            if let Ok(_) = screenshot.take_screenshot(window, move |image| {
+                let frame = match image.try_into_dynamic() {
+                    Ok(image) => Ok(VideoFrame {
+                        image: image
+                            .resize_to_fill(size.0, size.1, FilterType::Nearest)
+                            .into_rgb8(),
+                        frame_id,
+                    }),
+                    Err(e) => Err(format!("Couldn't read frame {}: {}", frame_id, e)),
+                };
+
+                if let Err(_) = sender.send(frame) {
+                    closed.store(true, Ordering::SeqCst);
+                }
+            }) {
+                ctx.cur_frame += 1;
+            }
+        }
+
+        let frame_time = ctx.offline.then_some(ctx.frame_time);
+        if let Some(global_audio) = global_audio {
+            let res = match &mut ctx.audio {
+                Some(audio) => audio.write_frame(&global_audio, frame_time),
+                None => Ok(()),
+            };
+            if let Err(e) = res {
+                let timestamp = chrono::Duration::from_std(real_time.elapsed()).unwrap();
+                console.println(format!("Couldn't write video audio: {}", e), timestamp);
+
+                // without its sound, the video is finished on its own
+                if let Some(audio) = ctx.audio.take() {
+                    let _ = fs::remove_file(&audio.path);
+                }
+            }
+        }
+    }
+
+    pub fn recv_frame(
+        mut ctx: ResMut<VideoCtxRecv>,
+        mut commands: Commands,
+        time: Res<Time<Real>>,
+        mut console: ResMut<ConsoleOutput>,
+        mut muxing: ResMut<Muxing>,
+    ) {
+        let timestamp = chrono::Duration::from_std(time.elapsed()).unwrap();
+        loop {
+            let frame = match (ctx.frame_buf.first_key_value(), &ctx.recv_frame) {
+                (Some((frame, _)), _) if *frame == ctx.cur_frame => {
+                    let (_, frame) = ctx.frame_buf.pop_first().unwrap();
+                    frame
+                }
+                (Some(_), None) => {
+                    let (_, frame) = ctx.frame_buf.pop_first().unwrap();
+                    frame
+                }
+                (_, Some(recv)) => {
+                    match recv.try_recv() {
+                        Ok(Ok(next)) => {
+                            ctx.frame_buf.insert(next.frame_id, next.image);
+                        }
+                        Ok(Err(e)) => {
+                            console.println(e, timestamp);
+                            ctx.abandon();
+                        }
+                        Err(TryRecvError::Empty) => break,
+                        Err(TryRecvError::Disconnected) => ctx.recv_frame = None,
+                    }
+
+                    continue;
+                }
+                (None, None) => {
+                    match ctx.encoder.finish() {
+                        Ok(()) => {
+                            if let Some(mux) = ctx.mux.take() {
+                                let path = ctx.path.clone();
+                                muxing.0.push(thread::spawn(move || mux.run(&path)));
+                            }
+                        }
+                        Err(e) => console.println(
+                            format!("Couldn't finish writing {}: {}", ctx.path.display(), e),
+                            timestamp,
+                        ),
+                    }
+                    commands.remove_resource::<VideoCtxRecv>();
+                    break;
+                }
+            };
+
+            // a video that can't be written any further keeps the frames written so far
+            if let Err(e) = ctx.encode(frame) {
+                console.println(format!("Couldn't write video: {}", e), timestamp);
+                ctx.abandon();
+            }
+        }
+    }
+
+    pub fn finish_mux(
+        mut muxing: ResMut<Muxing>,
+        time: Res<Time<Real>>,
+        mut console: ResMut<ConsoleOutput>,
+    ) {
+        let (finished, running): (Vec<_>, Vec<_>) =
+            muxing.0.drain(..).partition(|mux| mux.is_finished());
+        muxing.0 = running;
+
+        for mux in finished {
+            if let Ok(Err(e)) = mux.join() {
+                let timestamp = chrono::Duration::from_std(time.elapsed()).unwrap();
+                console.println(e, timestamp);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_wav_writer() {
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), 44_100, 2).unwrap();
+        writer.write_samples(&[0., 1., -1., 2.]).unwrap();
+        writer.write_silence(1).unwrap();
+        let wav = writer.finish().unwrap().into_inner();
+
+        assert_eq!(wav.len(), 44 + 12);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(wav[4..8].try_into().unwrap()), 36 + 12);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 44_100);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32::from_le_bytes(wav[40..44].try_into().unwrap()), 12);
+
+        let samples = wav[44..]
+            .chunks_exact(2)
+            .map(|s| i16::from_le_bytes([s[0], s[1]]))
+            .collect::<Vec<_>>();
+        // out of range samples are clipped
+        assert_eq!(samples, [0, i16::MAX, -i16::MAX, i16::MAX, 0, 0]);
+    }
+
+    #[test]
+    fn test_mux_without_sound() {
+        let dir = std::env::temp_dir().join(format!("seismon-mux-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mux = Mux {
+            video: dir.join("test.video.mp4"),
+            audio: dir.join("test.wav"),
+        };
+        fs::write(&mux.video, b"video").unwrap();
+
+        // sound that failed to be written leaves the video as it is
+        let path = dir.join("test.mp4");
+        mux.run(&path).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"video");
+        assert!(!dir.join("test.video.mp4").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
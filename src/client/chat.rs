@@ -0,0 +1,252 @@
+//! Showing chat messages from other players, and typing them.
+//!
+//! The server marks a chat message by starting the print with [`CHAT_MARKER`]. As in the original
+//! client, the marker is dropped and the rest of the message is drawn with the gold half of the
+//! console font, so that it stands out from the other messages at the top of the screen.
+//!
+//! `messagemode` and `messagemode2` open a one-line chat input over the game, which takes the
+//! keyboard until the message is sent with Enter as `say` or `say_team`, or dropped with Escape.
+
+use bevy::prelude::*;
+use clap::Parser;
+
+use crate::{
+    client::{input::InputFocus, Connection},
+    common::{
+        console::{AtlasText, Conchars, ExecResult, Gfx, RegisterCmdExt as _, RunCmd},
+        net::CHAT_MARKER,
+    },
+};
+
+/// Characters with this bit set are drawn in gold.
+const HIGHLIGHT: u8 = 0x80;
+
+/// The longest message that can be typed, matching what the server will pass on.
+const MAX_INPUT_LENGTH: usize = 128;
+
+pub struct SeismonChatPlugin;
+
+impl Plugin for SeismonChatPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ChatInput>()
+            .add_systems(Startup, systems::init_chat_input)
+            .add_systems(
+                Update,
+                (
+                    systems::write_chat_input.run_if(resource_changed::<ChatInput>),
+                    systems::update_chat_visibility.run_if(resource_changed::<InputFocus>),
+                ),
+            );
+
+        #[derive(Parser)]
+        #[command(name = "messagemode", about = "Type a chat message to everyone")]
+        struct MessageMode;
+
+        app.command(
+            |In(MessageMode),
+             conn: Option<Res<Connection>>,
+             mut input: ResMut<ChatInput>,
+             mut focus: ResMut<InputFocus>| {
+                open_chat(conn, &mut input, &mut focus, false)
+            },
+        );
+
+        #[derive(Parser)]
+        #[command(name = "messagemode2", about = "Type a chat message to your team")]
+        struct MessageMode2;
+
+        app.command(
+            |In(MessageMode2),
+             conn: Option<Res<Connection>>,
+             mut input: ResMut<ChatInput>,
+             mut focus: ResMut<InputFocus>| {
+                open_chat(conn, &mut input, &mut focus, true)
+            },
+        );
+    }
+}
+
+fn open_chat(
+    conn: Option<Res<Connection>>,
+    input: &mut ChatInput,
+    focus: &mut InputFocus,
+    team: bool,
+) -> ExecResult {
+    if conn.is_none() {
+        return "not connected".into();
+    }
+
+    *input = ChatInput::new(team);
+    *focus = InputFocus::Chat;
+
+    default()
+}
+
+/// The chat message being typed.
+#[derive(Resource, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChatInput {
+    /// Whether the message goes only to the player's team.
+    pub team: bool,
+    text: String,
+}
+
+impl ChatInput {
+    pub fn new(team: bool) -> ChatInput {
+        ChatInput {
+            team,
+            text: String::new(),
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Adds a typed character to the end of the message, if there's room for it.
+    pub fn push(&mut self, c: char) {
+        if !c.is_control() && self.text.len() + c.len_utf8() <= MAX_INPUT_LENGTH {
+            self.text.push(c);
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        self.text.pop();
+    }
+
+    /// The input line as it is drawn, with a cursor at the end.
+    pub fn line(&self) -> String {
+        let prompt = if self.team { "say_team" } else { "say" };
+        format!("{}: {}_", prompt, self.text)
+    }
+
+    /// Takes the typed message, returning the command that sends it. Nothing is sent for an empty
+    /// message.
+    pub fn finish(&mut self) -> Option<RunCmd<'static>> {
+        let text = std::mem::take(&mut self.text);
+        if text.trim().is_empty() {
+            return None;
+        }
+
+        let name = if self.team { "say_team" } else { "say" };
+        Some(RunCmd(name.into(), Box::new([text])))
+    }
+}
+
+/// If `text` is a chat message, returns it as it should be printed.
+pub fn chat_text(text: &[u8]) -> Option<Vec<u8>> {
+    let text = text.strip_prefix(&[CHAT_MARKER])?;
+
+    Some(
+        text.iter()
+            .map(|&c| if c == b'\n' { c } else { c | HIGHLIGHT })
+            .collect(),
+    )
+}
+
+mod systems {
+    use super::*;
+
+    #[derive(Component)]
+    pub struct ChatInputUi;
+
+    pub fn init_chat_input(mut commands: Commands, gfx: Res<Gfx>) {
+        let Conchars {
+            image,
+            layout,
+            glyph_size,
+        } = gfx.conchars.clone();
+
+        commands.spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    left: glyph_size.0 / 2.,
+                    bottom: Val::Percent(25.),
+                    flex_direction: FlexDirection::Column,
+                    ..default()
+                },
+                visibility: Visibility::Hidden,
+                z_index: ZIndex::Global(1),
+                ..default()
+            },
+            AtlasText {
+                text: "".into(),
+                image,
+                layout,
+                line_padding: UiRect::default(),
+                glyph_size,
+                justify: JustifyContent::FlexStart,
+            },
+            ChatInputUi,
+        ));
+    }
+
+    pub fn write_chat_input(
+        input: Res<ChatInput>,
+        mut input_ui: Query<&mut AtlasText, With<ChatInputUi>>,
+    ) {
+        for mut text in input_ui.iter_mut() {
+            text.text.clear();
+            text.text.push_str(input.line());
+        }
+    }
+
+    pub fn update_chat_visibility(
+        mut input_ui: Query<&mut Visibility, With<ChatInputUi>>,
+        focus: Res<InputFocus>,
+    ) {
+        for mut vis in input_ui.iter_mut() {
+            *vis = if *focus == InputFocus::Chat {
+                Visibility::Visible
+            } else {
+                Visibility::Hidden
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chat_text() {
+        assert_eq!(chat_text(b"You got the shells\n"), None);
+        assert_eq!(
+            chat_text(b"\x01a: b\n"),
+            Some(vec![
+                b'a' | 0x80,
+                b':' | 0x80,
+                b' ' | 0x80,
+                b'b' | 0x80,
+                b'\n'
+            ])
+        );
+    }
+
+    #[test]
+    fn test_chat_input() {
+        let mut input = ChatInput::new(true);
+        for c in "gg\u{8}x".chars() {
+            input.push(c);
+        }
+        input.backspace();
+        assert_eq!(input.line(), "say_team: gg_");
+
+        assert_eq!(
+            input.finish(),
+            Some(RunCmd("say_team".into(), Box::new(["gg".to_owned()])))
+        );
+        assert_eq!(input.text(), "");
+
+        // a blank message isn't sent
+        input.push(' ');
+        assert_eq!(input.finish(), None);
+
+        let mut input = ChatInput::new(false);
+        for _ in 0..MAX_INPUT_LENGTH + 10 {
+            input.push('a');
+        }
+        assert_eq!(input.text().len(), MAX_INPUT_LENGTH);
+    }
+}
@@ -7,20 +7,34 @@ use clap::Parser;
 use crate::{
     common::{
         console::{AliasInfo, ExecResult, RegisterCmdExt as _, Registry, RunCmd},
-        net::{ColorShift, QSocket, SignOnStage},
-        vfs::Vfs,
+        engine,
+        net::{
+            background::SocketThread, ClientCmd, ClientId, ClientMessage, ColorShift, MessageKind,
+            SignOnStage,
+        },
+        vfs::{ContentStatus, Vfs},
     },
     server::Session,
 };
 
 use super::{
     connect,
-    demo::DemoServer,
+    demo::{self, DemoRecorder, DemoServer},
     input::InputFocus,
+    progress::ConnectionProgress,
     sound::{MixerEvent, MusicSource},
     state::ClientState,
     ColorShiftCode, Connection, ConnectionKind, ConnectionState, DemoQueue,
 };
+#[cfg(any(feature = "websocket", target_arch = "wasm32"))]
+use crate::common::net::websocket;
+
+#[cfg(any(feature = "websocket", target_arch = "wasm32"))]
+use super::connect_websocket;
+
+/// The address given to the last `connect` that succeeded, for `reconnect`.
+#[derive(Resource, Clone, Debug)]
+pub struct LastServer(pub String);
 
 pub fn register_commands(app: &mut App) {
     #[derive(Parser)]
@@ -32,13 +46,15 @@ pub fn register_commands(app: &mut App) {
         |In(ToggleConsole), conn: Option<Res<Connection>>, mut focus: ResMut<InputFocus>| {
             if conn.is_some() {
                 match &*focus {
-                    InputFocus::Menu | InputFocus::Game => *focus = InputFocus::Console,
+                    InputFocus::Menu | InputFocus::Game | InputFocus::Chat => {
+                        *focus = InputFocus::Console
+                    }
                     InputFocus::Console => *focus = InputFocus::Game,
                 }
             } else {
                 match &*focus {
                     InputFocus::Console => *focus = InputFocus::Menu,
-                    InputFocus::Menu => *focus = InputFocus::Console,
+                    InputFocus::Menu | InputFocus::Chat => *focus = InputFocus::Console,
                     InputFocus::Game => {
                         unreachable!("Game focus is invalid when we are disconnected")
                     }
@@ -58,12 +74,12 @@ pub fn register_commands(app: &mut App) {
             if conn.is_some() {
                 match &*focus {
                     InputFocus::Game => *focus = InputFocus::Menu,
-                    InputFocus::Console => *focus = InputFocus::Menu,
+                    InputFocus::Console | InputFocus::Chat => *focus = InputFocus::Menu,
                     InputFocus::Menu => *focus = InputFocus::Game,
                 }
             } else {
                 match &*focus {
-                    InputFocus::Console => *focus = InputFocus::Menu,
+                    InputFocus::Console | InputFocus::Chat => *focus = InputFocus::Menu,
                     InputFocus::Menu => *focus = InputFocus::Console,
                     InputFocus::Game => {
                         unreachable!("Game focus is invalid when we are disconnected")
@@ -75,20 +91,47 @@ pub fn register_commands(app: &mut App) {
     );
 
     #[derive(Parser)]
-    #[command(name = "connect", about = "Connect to a remote server")]
+    #[command(
+        name = "connect",
+        about = "Connect to a remote server. Use a ws:// address for WebSockets"
+    )]
     struct Connect {
         remote: String,
     }
 
     // set up connection console commands
     app.command(
-        |In(Connect { remote }), mut commands: Commands, mut focus: ResMut<InputFocus>| {
-            match connect(&remote) {
-                Ok((new_conn, new_state)) => {
+        |In(Connect { remote }),
+         mut commands: Commands,
+         #[cfg(any(feature = "websocket", target_arch = "wasm32"))] time: Res<Time<Real>>,
+         mut focus: ResMut<InputFocus>| {
+            // the client stays disconnected until the server responds, which is checked for
+            // each frame
+            #[cfg(any(feature = "websocket", target_arch = "wasm32"))]
+            if let Some(url) = websocket::parse_address(&remote) {
+                return match connect_websocket(&url, time.elapsed()) {
+                    Ok(pending) => {
+                        commands.remove_resource::<SocketThread>();
+                        commands.insert_resource(pending);
+                        commands.insert_resource(ConnectionState::SignOn(SignOnStage::Not));
+                        commands.insert_resource(ConnectionProgress::new(remote.as_str()));
+                        commands.insert_resource(LastServer(remote));
+                        default()
+                    }
+                    Err(e) => format!("{}", e).into(),
+                };
+            }
+
+            match connect(&remote)
+                .and_then(|(qsock, state)| Ok((SocketThread::spawn(qsock)?, state)))
+            {
+                Ok((socket, new_state)) => {
                     *focus = InputFocus::Game;
-                    commands.insert_resource(new_conn);
+                    commands.insert_resource(socket);
                     commands.insert_resource(Connection::new_server());
                     commands.insert_resource(new_state);
+                    commands.insert_resource(ConnectionProgress::new(remote.as_str()));
+                    commands.insert_resource(LastServer(remote));
                     default()
                 }
                 Err(e) => format!("{}", e).into(),
@@ -97,38 +140,61 @@ pub fn register_commands(app: &mut App) {
     );
 
     #[derive(Parser)]
-    #[command(name = "reconnect", about = "Reconnect to the current server")]
+    #[command(
+        name = "reconnect",
+        about = "Sign on to the current server again, or connect to the last server after a disconnect"
+    )]
     struct Reconnect;
 
     app.command(
         |In(Reconnect),
          conn: Option<Res<Connection>>,
+         last_server: Option<Res<LastServer>>,
          mut conn_state: ResMut<ConnectionState>,
-         mut focus: ResMut<InputFocus>| {
+         mut focus: ResMut<InputFocus>,
+         mut console_commands: EventWriter<RunCmd<'static>>| {
             if conn.is_some() {
-                // TODO: clear client state
-                *conn_state = ConnectionState::SignOn(SignOnStage::Prespawn);
+                // the server sends this when it changes level, just before the new level's info,
+                // which already starts the sign-on again
+                if let ConnectionState::Connected(_) = *conn_state {
+                    *conn_state = ConnectionState::SignOn(SignOnStage::Prespawn);
+                }
                 *focus = InputFocus::Game;
                 default()
+            } else if let Some(LastServer(remote)) = last_server.as_deref() {
+                console_commands.send(RunCmd("connect".into(), Box::new([remote.clone()])));
+                default()
             } else {
-                // TODO: log message, e.g. "can't reconnect while disconnected"
-                "not connected".into()
+                "no server to reconnect to".into()
             }
         },
     );
 
     #[derive(Parser)]
-    #[command(name = "reconnect", about = "Disconnect from the current server")]
+    #[command(name = "disconnect", about = "Disconnect from the current server")]
     struct Disconnect;
 
     app.command(
         |In(Disconnect),
          mut commands: Commands,
          conn: Option<Res<Connection>>,
+         mut to_server: EventWriter<ClientMessage>,
          mut focus: ResMut<InputFocus>| {
-            if conn.is_some() {
+            if let Some(conn) = conn {
+                // Let the server free our slot
+                if let ConnectionKind::Server { .. } | ConnectionKind::Replay { .. } = conn.kind {
+                    let mut packet = Vec::new();
+                    if ClientCmd::Disconnect.serialize(&mut packet).is_ok() {
+                        to_server.send(ClientMessage {
+                            client_id: ClientId::LOCAL,
+                            packet,
+                            kind: MessageKind::Reliable,
+                        });
+                    }
+                }
+
                 commands.remove_resource::<Connection>();
-                commands.remove_resource::<QSocket>();
+                commands.remove_resource::<SocketThread>();
                 *focus = InputFocus::Console;
                 default()
             } else {
@@ -149,31 +215,23 @@ pub fn register_commands(app: &mut App) {
          vfs: Res<Vfs>,
          mut focus: ResMut<InputFocus>,
          mut conn_state: ResMut<ConnectionState>| {
-            let (new_conn, new_state) = {
-                let mut demo_file = match vfs.open(format!("{}.dem", demo)) {
-                    Ok(f) => f,
-                    Err(e) => {
-                        return format!("{}", e).into();
-                    }
-                };
-
-                match DemoServer::new(&mut demo_file) {
-                    Ok(d) => (
-                        Connection {
-                            kind: ConnectionKind::Demo(d),
-                            state: ClientState::new(),
-                        },
-                        ConnectionState::SignOn(SignOnStage::Prespawn),
-                    ),
-                    Err(e) => {
-                        return format!("{}", e).into();
-                    }
+            let (new_conn, new_state) = match DemoServer::open(&vfs, &demo) {
+                Ok(d) => (
+                    Connection {
+                        kind: ConnectionKind::Demo(d),
+                        state: ClientState::new(),
+                    },
+                    ConnectionState::SignOn(SignOnStage::Prespawn),
+                ),
+                Err(e) => {
+                    return format!("{}", e).into();
                 }
             };
 
             *focus = InputFocus::Game;
 
             commands.insert_resource(new_conn);
+            commands.insert_resource(ConnectionProgress::default());
             *conn_state = new_state;
 
             default()
@@ -181,7 +239,308 @@ pub fn register_commands(app: &mut App) {
     );
 
     #[derive(Parser)]
-    #[command(name = "startdemos", about = "Play a specific demo")]
+    #[command(
+        name = "demoinfo",
+        about = "Show what a demo contains without playing it"
+    )]
+    struct DemoInfo {
+        demo: String,
+    }
+
+    app.command(|In(DemoInfo { demo }), vfs: Res<Vfs>| -> ExecResult {
+        match DemoServer::open(&vfs, &demo) {
+            Ok(d) => d.info().describe().into(),
+            Err(e) => format!("{}", e).into(),
+        }
+    });
+
+    #[derive(Parser)]
+    #[command(name = "record", about = "Record the game to a demo")]
+    struct Record {
+        /// The name of the demo, which is saved in the game directory
+        demo: String,
+        /// A map to start, so that the demo is recorded from the beginning of the level
+        map: Option<String>,
+    }
+
+    app.command(
+        |In(Record { demo, map }),
+         mut commands: Commands,
+         vfs: Res<Vfs>,
+         conn: Option<Res<Connection>>,
+         recording: Option<Res<DemoRecorder>>|
+         -> ExecResult {
+            if let Some(recording) = recording {
+                return format!("already recording {}", recording.path()).into();
+            }
+            if map.is_some() && conn.is_some() {
+                return "can't start a map while connected, disconnect first".into();
+            }
+
+            let mut recorder = match DemoRecorder::create(&vfs, &demo) {
+                Ok(r) => r,
+                Err(e) => return format!("Couldn't create demo: {}", e).into(),
+            };
+            if let Some(conn) = conn {
+                if let Err(e) = conn.start_recording(&mut recorder) {
+                    return format!("{}", e).into();
+                }
+            }
+
+            let output = format!("Recording to {}", recorder.path()).into();
+            commands.insert_resource(recorder);
+
+            match map {
+                Some(map) => ExecResult {
+                    extra_commands: Box::new([RunCmd("map".into(), Box::new([map]))].into_iter()),
+                    output,
+                    ..default()
+                },
+                None => ExecResult {
+                    output,
+                    ..default()
+                },
+            }
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(name = "stop", about = "Finish recording a demo")]
+    struct Stop;
+
+    app.command(
+        |In(Stop), mut commands: Commands, recording: Option<ResMut<DemoRecorder>>| -> ExecResult {
+            let Some(mut recorder) = recording else {
+                return "not recording a demo".into();
+            };
+
+            commands.remove_resource::<DemoRecorder>();
+            match recorder.finish() {
+                Ok(()) => format!("Completed demo {}", recorder.path()).into(),
+                Err(e) => format!("Couldn't finish demo {}: {}", recorder.path(), e).into(),
+            }
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(
+        name = "instantreplay",
+        about = "Replay the last few seconds of play, then return to the game"
+    )]
+    struct InstantReplay {
+        /// How many seconds to replay, by default the whole of `cl_replaylength`
+        seconds: Option<f32>,
+        /// Playback speed, e.g. 0.5 for half speed
+        #[arg(long, default_value_t = 1.)]
+        speed: f32,
+    }
+
+    app.command(
+        |In(InstantReplay { seconds, speed }),
+         registry: Res<Registry>,
+         conn: Option<ResMut<Connection>>,
+         mut conn_state: ResMut<ConnectionState>|
+         -> ExecResult {
+            let Some(mut conn) = conn else {
+                return "not connected".into();
+            };
+            if !(speed > 0.) {
+                return "speed must be greater than zero".into();
+            }
+
+            let seconds = seconds
+                .unwrap_or_else(|| registry.read_cvar::<f32>("cl_replaylength").unwrap_or(10.));
+            match conn.start_replay(&mut *conn_state, engine::duration_from_f32(seconds), speed) {
+                Ok(()) => default(),
+                Err(e) => format!("{}", e).into(),
+            }
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(
+        name = "demomark",
+        about = "Bookmark the current time in the demo being played or recorded"
+    )]
+    struct DemoMark {
+        /// A note to remember the bookmark by
+        note: Vec<String>,
+    }
+
+    app.command(
+        |In(DemoMark { note }),
+         vfs: Res<Vfs>,
+         conn: Option<ResMut<Connection>>,
+         mut recorder: Option<ResMut<DemoRecorder>>|
+         -> ExecResult {
+            let Some(mut conn) = conn else {
+                return "not connected".into();
+            };
+            let time = conn.state().time;
+
+            let marks = if let Some(demo) = conn.demo_mut() {
+                demo.marks_mut()
+            } else if let Some(recorder) = recorder.as_deref_mut() {
+                recorder.marks_mut()
+            } else {
+                return "not playing or recording a demo".into();
+            };
+            let n = marks.add(time, note.join(" "));
+            if let Err(e) = marks.save(&vfs) {
+                return format!("Couldn't save bookmark: {}", e).into();
+            }
+
+            format!("Bookmark {} at {:.1}s\n", n, engine::duration_to_f32(time)).into()
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(
+        name = "demoseekmark",
+        about = "Jump to a bookmark in the demo being played, or list the bookmarks"
+    )]
+    struct DemoSeekMark {
+        /// The number of the bookmark, as listed by `demoseekmark` on its own
+        n: Option<usize>,
+    }
+
+    app.command(
+        |In(DemoSeekMark { n }),
+         conn: Option<ResMut<Connection>>,
+         mut conn_state: ResMut<ConnectionState>|
+         -> ExecResult {
+            let Some(mut conn) = conn else {
+                return "not connected".into();
+            };
+            let Some(demo) = conn.demo() else {
+                return "not playing a demo".into();
+            };
+
+            let Some(n) = n else {
+                return demo.marks().describe().into();
+            };
+            let Some(time) = demo.marks().get(n).map(|mark| mark.time) else {
+                return format!("No bookmark {} ({} in this demo)", n, demo.marks().len()).into();
+            };
+
+            match conn.seek_demo(&mut *conn_state, time) {
+                Ok(()) => default(),
+                Err(e) => format!("{}", e).into(),
+            }
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(name = "demo_pause", about = "Pause or resume the demo being played")]
+    struct DemoPause;
+
+    app.command(
+        |In(DemoPause), conn: Option<ResMut<Connection>>| -> ExecResult {
+            let Some(mut conn) = conn else {
+                return "not connected".into();
+            };
+            let Some(demo) = conn.demo_mut() else {
+                return "not playing a demo".into();
+            };
+
+            demo.set_paused(!demo.paused());
+            default()
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(
+        name = "demo_speed",
+        about = "Change how fast the demo being played runs"
+    )]
+    struct DemoSpeed {
+        /// Playback speed, e.g. 0.5 for half speed. If omitted, shows the current speed
+        factor: Option<f32>,
+    }
+
+    app.command(
+        |In(DemoSpeed { factor }), conn: Option<ResMut<Connection>>| -> ExecResult {
+            let Some(mut conn) = conn else {
+                return "not connected".into();
+            };
+            let Some(demo) = conn.demo_mut() else {
+                return "not playing a demo".into();
+            };
+
+            let Some(factor) = factor else {
+                return format!("Playing at {}x", demo.speed()).into();
+            };
+            if !(factor > 0. && factor <= demo::MAX_SPEED) {
+                return format!(
+                    "speed must be greater than zero and at most {}",
+                    demo::MAX_SPEED
+                )
+                .into();
+            }
+
+            demo.set_speed(factor);
+            default()
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(name = "demo_seek", about = "Jump to a time in the demo being played")]
+    struct DemoSeek {
+        /// The time to jump to, in seconds from the start of the demo
+        seconds: f32,
+    }
+
+    app.command(
+        |In(DemoSeek { seconds }),
+         conn: Option<ResMut<Connection>>,
+         mut conn_state: ResMut<ConnectionState>|
+         -> ExecResult {
+            let Some(mut conn) = conn else {
+                return "not connected".into();
+            };
+            if !(seconds.is_finite() && seconds >= 0.) {
+                return "time must not be negative".into();
+            }
+
+            match conn.seek_demo(&mut *conn_state, engine::duration_from_f32(seconds)) {
+                Ok(()) => default(),
+                Err(e) => format!("{}", e).into(),
+            }
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(
+        name = "demo_freecam",
+        about = "Fly around the demo being played instead of watching the recorded view"
+    )]
+    struct DemoFreecam;
+
+    app.command(
+        |In(DemoFreecam), conn: Option<ResMut<Connection>>| -> ExecResult {
+            let Some(mut conn) = conn else {
+                return "not connected".into();
+            };
+            let start = conn.state.free_camera();
+            let Some(demo) = conn.demo_mut() else {
+                return "not playing a demo".into();
+            };
+
+            if demo.freecam().is_some() {
+                demo.set_freecam(None);
+                "free camera off".into()
+            } else {
+                demo.set_freecam(Some(start));
+                "free camera on".into()
+            }
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(
+        name = "startdemos",
+        about = "Play a list of demos in a loop while the game is idle"
+    )]
     struct StartDemos {
         demos: Vec<String>,
     }
@@ -203,44 +562,84 @@ pub fn register_commands(app: &mut App) {
             // Only actually start playing the demos if we aren't already running a server
             // (this appears to be Quake's expected behaviour?)
             if server.is_none() {
-                let (new_conn, new_state) = match demo_queue.next() {
-                    Some(demo) => {
-                        let mut demo_file = match vfs
-                            .open(format!("{}.dem", demo))
-                            .or_else(|_| vfs.open(format!("demos/{}.dem", demo)))
-                        {
-                            Ok(f) => f,
-                            Err(e) => {
-                                // log the error, dump the demo queue and disconnect
-                                return format!("{}", e).into();
-                            }
-                        };
-
-                        match DemoServer::new(&mut demo_file) {
-                            Ok(d) => (
-                                Connection {
-                                    kind: ConnectionKind::Demo(d),
-                                    state: ClientState::new(),
-                                },
-                                ConnectionState::SignOn(SignOnStage::Prespawn),
-                            ),
-                            Err(e) => {
-                                return format!("{}", e).into();
-                            }
-                        }
-                    }
-                    None => return "".into(),
-                };
-
-                commands.insert_resource(new_conn);
-                *conn_state = new_state;
-                *focus = InputFocus::Game;
+                return play_next_demo(
+                    &mut commands,
+                    &vfs,
+                    &mut demo_queue,
+                    &mut focus,
+                    &mut conn_state,
+                );
             }
 
             default()
         },
     );
 
+    #[derive(Parser)]
+    #[command(
+        name = "demos",
+        about = "Carry on with the demo loop after it was stopped with stopdemo"
+    )]
+    struct Demos;
+
+    app.command(
+        |In(Demos),
+         mut commands: Commands,
+         vfs: Res<Vfs>,
+         mut demo_queue: ResMut<DemoQueue>,
+         mut focus: ResMut<InputFocus>,
+         mut conn_state: ResMut<ConnectionState>,
+         conn: Option<Res<Connection>>,
+         server: Option<Res<Session>>| {
+            if demo_queue.is_empty() {
+                return "no demos to play, set them with startdemos".into();
+            }
+
+            let in_game = match conn.as_deref().map(|conn| &conn.kind) {
+                Some(ConnectionKind::Demo(_)) | None => false,
+                Some(_) => true,
+            };
+            if in_game || server.is_some() {
+                return "disconnect before playing demos".into();
+            }
+
+            demo_queue.resume();
+            play_next_demo(
+                &mut commands,
+                &vfs,
+                &mut demo_queue,
+                &mut focus,
+                &mut conn_state,
+            )
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(
+        name = "stopdemo",
+        about = "Stop the demo being played, and the demo loop"
+    )]
+    struct StopDemo;
+
+    app.command(
+        |In(StopDemo),
+         mut commands: Commands,
+         conn: Option<Res<Connection>>,
+         mut demo_queue: ResMut<DemoQueue>,
+         mut focus: ResMut<InputFocus>| {
+            demo_queue.stop();
+
+            match conn.as_deref().map(|conn| &conn.kind) {
+                Some(ConnectionKind::Demo(_)) => {
+                    commands.remove_resource::<Connection>();
+                    *focus = InputFocus::Console;
+                    default()
+                }
+                _ => "not playing a demo".into(),
+            }
+        },
+    );
+
     #[derive(Parser)]
     #[command(name = "music", about = "Play a named music track")]
     struct Music {
@@ -416,6 +815,49 @@ pub fn register_commands(app: &mut App) {
         }
     });
 
+    #[derive(Parser)]
+    #[command(
+        name = "checkcontent",
+        about = "Check the game data for corrupt or modified files"
+    )]
+    struct CheckContent;
+
+    app.command(|In(CheckContent), vfs: Res<Vfs>| -> ExecResult {
+        let mut out = String::new();
+        let mut problems = 0;
+        for report in vfs.check_content() {
+            let status = match &report.status {
+                ContentStatus::Ok => "ok".to_owned(),
+                ContentStatus::Unknown => "not part of the original release".to_owned(),
+                ContentStatus::Modified { expected } => {
+                    problems += 1;
+                    format!("MODIFIED (expected {})", expected)
+                }
+                ContentStatus::Overridden { by } => {
+                    problems += 1;
+                    format!("overridden by {}", by.display())
+                }
+                ContentStatus::Missing => {
+                    problems += 1;
+                    "MISSING".to_owned()
+                }
+            };
+
+            out.push_str(&format!(
+                "{} {:<32} {}\n",
+                report
+                    .md5
+                    .as_deref()
+                    .unwrap_or("--------------------------------"),
+                report.name.display(),
+                status
+            ));
+        }
+
+        out.push_str(&format!("{} problem(s) found\n", problems));
+        out.into()
+    });
+
     #[derive(Parser)]
     #[command(name = "bf", about = "Flash the screen")]
     struct Bf;
@@ -432,6 +874,35 @@ pub fn register_commands(app: &mut App) {
         },
     );
 
+    #[derive(Parser)]
+    #[command(
+        name = "fog",
+        about = "Set the fog as <density>, <r> <g> <b> or <density> <r> <g> <b>"
+    )]
+    struct Fog {
+        values: Vec<f32>,
+    }
+
+    app.command(
+        |In(Fog { values }), conn: Option<ResMut<Connection>>| -> ExecResult {
+            let Some(mut conn) = conn else {
+                return "not connected".into();
+            };
+
+            let fog = &mut conn.state.fog;
+            if values.is_empty() {
+                let [r, g, b] = fog.color;
+                return format!("fog is {} {} {} {}", fog.density, r, g, b).into();
+            }
+
+            if fog.set(&values) {
+                default()
+            } else {
+                "usage: fog <density> | <r> <g> <b> | <density> <r> <g> <b>".into()
+            }
+        },
+    );
+
     #[derive(Parser)]
     #[command(name = "name", about = "Set the player name")]
     struct Name {
@@ -446,4 +917,204 @@ pub fn register_commands(app: &mut App) {
             }
         },
     );
+    #[derive(Parser)]
+    #[command(name = "say", about = "Send a chat message to every player")]
+    struct Say {
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        message: Vec<String>,
+    }
+
+    app.command(
+        |In(Say { message }),
+         conn: Option<Res<Connection>>,
+         mut to_server: EventWriter<ClientMessage>| {
+            forward_chat(conn, &mut to_server, "say", &message)
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(
+        name = "say_team",
+        about = "Send a chat message to the players on your team"
+    )]
+    struct SayTeam {
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        message: Vec<String>,
+    }
+
+    app.command(
+        |In(SayTeam { message }),
+         conn: Option<Res<Connection>>,
+         mut to_server: EventWriter<ClientMessage>| {
+            forward_chat(conn, &mut to_server, "say_team", &message)
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(
+        name = "cvar_limit",
+        about = "Restrict a cvar to a range (sent by the server)",
+        allow_negative_numbers = true
+    )]
+    struct CvarLimit {
+        cvar: String,
+        min: f32,
+        max: f32,
+    }
+
+    app.command(
+        move |In(CvarLimit { cvar, min, max }),
+              conn: Option<Res<Connection>>,
+              mut registry: ResMut<Registry>|
+              -> ExecResult {
+            // Limits are cleared on disconnect, so there's no reason to set them beforehand
+            if conn.is_none() {
+                return "not connected".into();
+            }
+
+            match registry.limit_cvar(&cvar, min, max) {
+                Ok(()) => default(),
+                Err(e) => format!("Error: {}", e).into(),
+            }
+        },
+    );
+
+    // cheats are run by the server on our entity
+    #[derive(Parser)]
+    #[command(name = "god", about = "Toggle invulnerability (requires sv_cheats)")]
+    struct God;
+
+    app.command(
+        |In(God), conn: Option<Res<Connection>>, mut to_server: EventWriter<ClientMessage>| {
+            forward_to_server(conn, &mut to_server, "god".into())
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(
+        name = "notarget",
+        about = "Toggle whether monsters notice you (requires sv_cheats)"
+    )]
+    struct NoTarget;
+
+    app.command(
+        |In(NoTarget), conn: Option<Res<Connection>>, mut to_server: EventWriter<ClientMessage>| {
+            forward_to_server(conn, &mut to_server, "notarget".into())
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(
+        name = "noclip",
+        about = "Toggle flying through walls (requires sv_cheats)"
+    )]
+    struct NoClip;
+
+    app.command(
+        |In(NoClip), conn: Option<Res<Connection>>, mut to_server: EventWriter<ClientMessage>| {
+            forward_to_server(conn, &mut to_server, "noclip".into())
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(name = "fly", about = "Toggle flying (requires sv_cheats)")]
+    struct Fly;
+
+    app.command(
+        |In(Fly), conn: Option<Res<Connection>>, mut to_server: EventWriter<ClientMessage>| {
+            forward_to_server(conn, &mut to_server, "fly".into())
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(
+        name = "give",
+        about = "Give yourself a weapon, ammo or health (requires sv_cheats)"
+    )]
+    struct Give {
+        /// A weapon from 2 to 8, or one of s(hells), n(ails), r(ockets), c(ells) or h(ealth)
+        item: String,
+        /// How much ammo or health to set, by default 100
+        amount: Option<u32>,
+    }
+
+    app.command(
+        |In(Give { item, amount }),
+         conn: Option<Res<Connection>>,
+         mut to_server: EventWriter<ClientMessage>| {
+            let cmd = match amount {
+                Some(amount) => format!("give {} {}", item, amount),
+                None => format!("give {}", item),
+            };
+            forward_to_server(conn, &mut to_server, cmd)
+        },
+    );
+}
+
+/// Start playing the next demo of the demo loop, in place of any demo that's already playing.
+fn play_next_demo(
+    commands: &mut Commands,
+    vfs: &Vfs,
+    demo_queue: &mut DemoQueue,
+    focus: &mut InputFocus,
+    conn_state: &mut ConnectionState,
+) -> ExecResult {
+    let Some(demo) = demo_queue.next() else {
+        return default();
+    };
+    let demo = match DemoServer::open(vfs, demo) {
+        Ok(d) => d,
+        Err(e) => return format!("{}", e).into(),
+    };
+
+    commands.insert_resource(Connection {
+        kind: ConnectionKind::Demo(demo),
+        state: ClientState::new(),
+    });
+    commands.insert_resource(ConnectionProgress::default());
+    *conn_state = ConnectionState::SignOn(SignOnStage::Prespawn);
+    *focus = InputFocus::Game;
+
+    default()
+}
+
+/// Send a chat message to the server as `say` or `say_team`.
+fn forward_chat(
+    conn: Option<Res<Connection>>,
+    to_server: &mut EventWriter<ClientMessage>,
+    cmd: &str,
+    message: &[String],
+) -> ExecResult {
+    if message.is_empty() {
+        return default();
+    }
+
+    // the message is sent as one quoted argument, so that semicolons and spacing survive
+    let message = message.join(" ").replace('"', "'");
+    forward_to_server(conn, to_server, format!("{} \"{}\"", cmd, message))
+}
+
+/// Send a command for the server to run on behalf of this client.
+fn forward_to_server(
+    conn: Option<Res<Connection>>,
+    to_server: &mut EventWriter<ClientMessage>,
+    cmd: String,
+) -> ExecResult {
+    match conn.as_deref().map(|conn| &conn.kind) {
+        Some(ConnectionKind::Server { .. }) => {}
+        Some(_) => return "not connected to a server".into(),
+        None => return "not connected".into(),
+    }
+
+    let mut packet = Vec::new();
+    if let Err(e) = (ClientCmd::StringCmd { cmd }).serialize(&mut packet) {
+        return format!("{}", e).into();
+    }
+    to_server.send(ClientMessage {
+        client_id: ClientId::LOCAL,
+        packet,
+        kind: MessageKind::Reliable,
+    });
+
+    default()
 }
@@ -20,9 +20,17 @@
 
 use bevy::app::App;
 
-use crate::common::console::{Cvar, RegisterCmdExt};
+use crate::{
+    client::entity::DEFAULT_NO_LERP_MODELS,
+    common::console::{Cvar, RegisterCmdExt},
+};
 
 pub fn register_cvars(app: &mut App) {
+    app.cvar(
+        "cl_anglesmooth",
+        "0",
+        "the time in seconds over which entity turns are smoothed out, or 0 for no smoothing",
+    );
     app.cvar(
         "cl_anglespeedkey",
         "1.5",
@@ -50,9 +58,29 @@ pub fn register_cvars(app: &mut App) {
     );
     app.cvar(
         "_cl_color",
-        Cvar::new("0").archive(),
+        Cvar::new("0").archive().notify(),
         "the client's colors, as set by the color command - use cl_color instead",
     );
+    app.cvar(
+        "chase_active",
+        "0",
+        "Whether to watch the player from behind instead of through their eyes",
+    );
+    app.cvar(
+        "chase_back",
+        "100",
+        "how far behind the player the chase camera is",
+    );
+    app.cvar(
+        "chase_right",
+        "0",
+        "how far to the player's right the chase camera is",
+    );
+    app.cvar(
+        "chase_up",
+        "16",
+        "how far above the player's view the chase camera is",
+    );
     app.cvar("cl_crossx", "0", "the x offset of the crosshair");
     app.cvar("cl_crossy", "0", "the y offset of the crosshair");
     app.cvar(
@@ -67,14 +95,49 @@ pub fn register_cvars(app: &mut App) {
     );
     app.cvar(
         "_cl_name",
-        Cvar::new("player").archive(),
+        Cvar::new("player").archive().notify(),
         "the player's name - use the name command instead",
     );
+    app.cvar(
+        "cl_allowdownload",
+        Cvar::new("1").archive(),
+        "whether to download maps, models and sounds that the server has and you don't",
+    );
+    app.cvar(
+        "cl_replaylength",
+        "10",
+        "how many seconds of play are kept for instantreplay",
+    );
+    app.cvar(
+        "cl_timeout",
+        "60",
+        "how many seconds to wait for a silent server before disconnecting",
+    );
+    app.cvar(
+        "cl_lerpangles",
+        "1",
+        "whether to interpolate which way entities face between updates from the server",
+    );
+    app.cvar(
+        "cl_lerpmove",
+        "1",
+        "whether to interpolate the movement of entities between updates from the server",
+    );
     app.cvar(
         "cl_nolerp",
         "0",
         "disables/enables location/angle interpolation",
     );
+    app.cvar(
+        "cl_predict",
+        "0",
+        "whether the view moves with the player's input before the server has moved them",
+    );
+    app.cvar(
+        "cl_nolerp_list",
+        Cvar::new(format!("\"{}\"", DEFAULT_NO_LERP_MODELS.join(","))),
+        "models that are never interpolated, separated by commas",
+    );
     app.cvar(
         "cl_pitchspeed",
         "150",
@@ -95,6 +158,61 @@ pub fn register_cvars(app: &mut App) {
         "0",
         "toggle the display of current net status",
     );
+    app.cvar(
+        "cl_autoswitch",
+        Cvar::new("0").archive(),
+        "switch to a newly picked up weapon if it comes before the current one in cl_weaponpriority",
+    );
+    app.cvar(
+        "cl_weaponpriority",
+        Cvar::new("853421").archive(),
+        "weapon numbers from most to least preferred, for cl_autoswitch",
+    );
+    app.cvar(
+        "cl_soundindicators",
+        Cvar::new("0").archive(),
+        "show icons around the crosshair pointing towards nearby sounds",
+    );
+    app.cvar(
+        "cl_screenreader",
+        Cvar::new("0").archive(),
+        "send console text and menu selections to a screen reader (2 also writes them to the log)",
+    );
+    app.cvar(
+        "cl_worldtext",
+        Cvar::new("0").archive(),
+        "show floating damage numbers and pickup labels in the world",
+    );
+    app.cvar(
+        "cl_showroute",
+        Cvar::new("1").archive(),
+        "draw the waypoints added with route_add for the current map",
+    );
+    app.cvar(
+        "cl_master",
+        Cvar::new("\"\"").archive(),
+        "the master server slist asks for public servers, or empty to only search the LAN",
+    );
+    app.cvar(
+        "cl_slist_sort",
+        Cvar::new("ping").archive(),
+        "the order of the server list in the menu: ping, name, map or players",
+    );
+    app.cvar(
+        "cl_slist_hideempty",
+        Cvar::new("0").archive(),
+        "hide servers with no players from the server list in the menu",
+    );
+    app.cvar(
+        "cl_slist_hidefull",
+        Cvar::new("0").archive(),
+        "hide full servers from the server list in the menu",
+    );
+    app.cvar(
+        "cl_slist_favorites",
+        Cvar::new("0").archive(),
+        "only show favorite servers in the server list in the menu",
+    );
     app.cvar(
         "cl_sidespeed",
         "350",
@@ -116,11 +234,26 @@ pub fn register_cvars(app: &mut App) {
         "3",
         "0: no hud, 1: transparent hud, 2: standard hud, 3: standard hud with ammo",
     );
+    app.cvar(
+        "cl_sbar",
+        "1",
+        "draw the backgrounds of the status bar and the inventory bar",
+    );
+    app.cvar(
+        "viewsize",
+        "100",
+        "110 hides the inventory bar, and 120 hides the status bar too",
+    );
     app.cvar(
         "fov",
         "90",
         "sets the camera's field of view angle (in degrees)",
     );
+    app.cvar(
+        "host_timescale",
+        Cvar::new("1").cheat(),
+        "the speed of the game clock, e.g. 0.5 for half speed (requires sv_cheats online)",
+    );
     // TODO: What is the difference between this and `cl_skipCrosshair`?
     app.cvar("crosshair", "1", "Whether to draw the crosshair");
     app.cvar(
@@ -164,10 +297,24 @@ pub fn register_cvars(app: &mut App) {
         "0.5",
         "sets the duration that the pitch and roll are adjusted when player takes damage",
     );
+    app.cvar(
+        "rate",
+        Cvar::new("10000").archive().notify(),
+        "the maximum number of bytes per second the server should send",
+    );
+    app.cvar(
+        "skin",
+        Cvar::new("base").archive().notify(),
+        "the player's skin, for servers that support custom skins",
+    );
     app.cvar(
         "scr_centertime",
         "2",
         "sets the duration that center text remains on the screen",
     );
-    app.cvar("sv_gravity", "800", "sets the server's gravity");
+    app.cvar(
+        "scr_printspeed",
+        "8",
+        "the number of characters per second at which end-of-episode text is typed out",
+    );
 }
@@ -1,18 +1,31 @@
-use std::{io, ops::Range};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{self, BufWriter, Read as _, Write},
+    ops::Range,
+};
 
-use crate::common::{
-    net::{self, NetError},
-    util::read_f32_3,
-    vfs::VirtualFile,
+use crate::{
+    client::view::FreeCamera,
+    common::{
+        engine,
+        net::{self, NetError, Protocol, ServerCmd},
+        util::read_f32_3,
+        vfs::{Vfs, VfsError, VirtualFile},
+    },
 };
 
 use arrayvec::ArrayVec;
-use bevy::log::warn;
-use byteorder::{LittleEndian, ReadBytesExt};
+use bevy::{ecs::system::Resource, log::warn};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use cgmath::{Deg, Vector3};
+use chrono::Duration;
 use io::BufReader;
 use thiserror::Error;
 
+/// The fastest a demo can be played, as a multiple of the speed it was recorded at.
+pub const MAX_SPEED: f32 = 64.0;
+
 /// An error returned by a demo server.
 #[derive(Error, Debug)]
 pub enum DemoServerError {
@@ -26,6 +39,8 @@ pub enum DemoServerError {
     Io(#[from] io::Error),
     #[error("Network error: {0}")]
     Net(#[from] NetError),
+    #[error("{0}")]
+    Vfs(#[from] VfsError),
 }
 
 #[derive(Clone)]
@@ -64,9 +79,40 @@ pub struct DemoServer {
 
     // all message data
     message_data: Vec<u8>,
+
+    /// Bookmarks in the demo, from its sidecar file.
+    marks: DemoMarks,
+
+    /// The time that playback is being fast-forwarded to, if it is seeking.
+    seek: Option<Duration>,
+
+    /// How fast the demo plays, where `1.0` is the speed it was recorded at.
+    speed: f32,
+
+    paused: bool,
+
+    /// The camera the demo is seen from instead of the recorded view, with `demo_freecam`.
+    freecam: Option<FreeCamera>,
 }
 
 impl DemoServer {
+    /// Open the demo called `name` along with its bookmarks, looking in `demos/` if it isn't at
+    /// the top level.
+    pub fn open(vfs: &Vfs, name: &str) -> Result<DemoServer, DemoServerError> {
+        let (path, mut file) = match vfs.open(format!("{}.dem", name)) {
+            Ok(f) => (name.to_owned(), f),
+            Err(_) => {
+                let path = format!("demos/{}", name);
+                let f = vfs.open(format!("{}.dem", path))?;
+                (path, f)
+            }
+        };
+
+        let mut demo = DemoServer::new(&mut file)?;
+        demo.marks = DemoMarks::load(vfs, format!("{}.marks", path));
+        Ok(demo)
+    }
+
     /// Construct a new `DemoServer` from the specified demo file.
     pub fn new(file: &mut VirtualFile) -> Result<DemoServer, DemoServerError> {
         let mut dem_reader = BufReader::new(file);
@@ -148,9 +194,47 @@ impl DemoServer {
             message_id: 0,
             messages,
             message_data,
+            marks: DemoMarks::default(),
+            seek: None,
+            speed: 1.0,
+            paused: false,
+            freecam: None,
         })
     }
 
+    /// Construct a new `DemoServer` from a sequence of server messages and the view angles to
+    /// display each of them with.
+    pub fn from_messages<'a, I>(messages: I) -> DemoServer
+    where
+        I: IntoIterator<Item = (Vector3<Deg<f32>>, &'a [u8])>,
+    {
+        let mut message_data = Vec::new();
+        let messages = messages
+            .into_iter()
+            .map(|(view_angles, message)| {
+                let msg_start = message_data.len();
+                message_data.extend_from_slice(message);
+
+                DemoMessage {
+                    view_angles,
+                    msg_range: msg_start..message_data.len(),
+                }
+            })
+            .collect();
+
+        DemoServer {
+            track_override: None,
+            message_id: 0,
+            messages,
+            message_data,
+            marks: DemoMarks::default(),
+            seek: None,
+            speed: 1.0,
+            paused: false,
+            freecam: None,
+        }
+    }
+
     /// Retrieve the next server message from the currently playing demo.
     ///
     /// If this returns `None`, the demo is complete.
@@ -176,4 +260,712 @@ impl DemoServer {
     pub fn track_override(&self) -> Option<u32> {
         self.track_override
     }
+
+    pub fn marks(&self) -> &DemoMarks {
+        &self.marks
+    }
+
+    pub fn marks_mut(&mut self) -> &mut DemoMarks {
+        &mut self.marks
+    }
+
+    /// Start playing the demo again from its first message.
+    pub fn rewind(&mut self) {
+        self.message_id = 0;
+    }
+
+    /// Fast-forward playback until the demo reaches `time`, or stop seeking if `time` is `None`.
+    pub fn set_seek(&mut self, time: Option<Duration>) {
+        self.seek = time;
+    }
+
+    /// The time that playback is being fast-forwarded to, if any.
+    pub fn seek(&self) -> Option<Duration> {
+        self.seek
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// Play the demo `speed` times as fast as it was recorded, up to [`MAX_SPEED`]. A speed that
+    /// isn't a number is ignored.
+    pub fn set_speed(&mut self, speed: f32) {
+        if !speed.is_nan() {
+            self.speed = speed.clamp(0.0, MAX_SPEED);
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn freecam(&self) -> Option<&FreeCamera> {
+        self.freecam.as_ref()
+    }
+
+    pub fn freecam_mut(&mut self) -> Option<&mut FreeCamera> {
+        self.freecam.as_mut()
+    }
+
+    /// Watch the demo from `freecam`, or from the recorded view if it's `None`.
+    pub fn set_freecam(&mut self, freecam: Option<FreeCamera>) {
+        self.freecam = freecam;
+    }
+
+    /// How much of the demo plays in `real_time`. Seeking isn't slowed down by pausing, as it
+    /// doesn't depend on the frame time.
+    pub fn playback_time(&self, real_time: std::time::Duration) -> std::time::Duration {
+        if self.paused {
+            std::time::Duration::ZERO
+        } else {
+            real_time.mul_f32(self.speed)
+        }
+    }
+
+    /// Read through the demo without playing it to find out what it contains.
+    pub fn info(&self) -> DemoInfo {
+        let mut info = DemoInfo {
+            map: None,
+            title: None,
+            protocol: None,
+            duration: Duration::zero(),
+            cd_track: self.track_override,
+            player: None,
+            messages: self.messages.len(),
+        };
+
+        let mut protocol = Protocol::NETQUAKE;
+        let mut view_player = None;
+        let mut names = HashMap::new();
+        let mut start = None;
+        let mut end = None;
+
+        'messages: for msg in &self.messages {
+            let reader = &mut &self.message_data[msg.msg_range.clone()];
+            loop {
+                let cmd = match ServerCmd::deserialize_with(reader, protocol) {
+                    Ok(Some(cmd)) => cmd,
+                    Ok(None) => break,
+                    // the rest of the message can't be found, but the next one can
+                    Err(e) => {
+                        warn!("Couldn't read demo message: {}", e);
+                        break;
+                    }
+                };
+
+                match cmd {
+                    ServerCmd::ServerInfo {
+                        protocol_version,
+                        protocol_flags,
+                        message,
+                        model_precache,
+                        ..
+                    } => {
+                        info.protocol.get_or_insert(protocol_version);
+                        if info.map.is_none() {
+                            info.map = model_precache
+                                .first()
+                                .map(|model| super::map_name(model).to_owned());
+                            info.title = Some(message.into_string());
+                        }
+
+                        protocol = match Protocol::new(protocol_version, protocol_flags) {
+                            Ok(p) => p,
+                            // nothing after this can be read
+                            Err(_) => break 'messages,
+                        };
+                    }
+                    ServerCmd::SetView { ent_id } => view_player = Some(ent_id - 1),
+                    ServerCmd::UpdateName {
+                        player_id,
+                        new_name,
+                    } if !new_name.is_empty() => {
+                        names.insert(player_id as i16, new_name.into_string());
+                    }
+                    ServerCmd::CdTrack { track, .. } => {
+                        info.cd_track.get_or_insert(track as u32);
+                    }
+                    ServerCmd::Time { time } => {
+                        let time = engine::duration_from_f32(time);
+                        start.get_or_insert(time);
+                        end = Some(time);
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        if let (Some(start), Some(end)) = (start, end) {
+            info.duration = end - start;
+        }
+        info.player = view_player.and_then(|id| names.remove(&id));
+
+        info
+    }
+}
+
+/// What a demo contains, as found by [`DemoServer::info`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct DemoInfo {
+    /// The map the demo starts on, e.g. `e1m1`.
+    pub map: Option<String>,
+    /// The title of that map.
+    pub title: Option<String>,
+    pub protocol: Option<i32>,
+    /// The game time between the first and last messages.
+    pub duration: Duration,
+    /// The CD track given in the header, or else the first one the demo plays.
+    pub cd_track: Option<u32>,
+    /// The name of the player whose view was recorded.
+    pub player: Option<String>,
+    pub messages: usize,
+}
+
+impl DemoInfo {
+    /// A summary of the demo for the console.
+    pub fn describe(&self) -> String {
+        let unknown = || "unknown".to_owned();
+        let secs = engine::duration_to_f32(self.duration);
+
+        format!(
+            "map:      {}{}\n\
+             protocol: {}\n\
+             duration: {}:{:04.1}\n\
+             CD track: {}\n\
+             player:   {}\n\
+             messages: {}\n",
+            self.map.clone().unwrap_or_else(unknown),
+            self.title
+                .as_ref()
+                .map(|title| format!(" ({})", title))
+                .unwrap_or_default(),
+            self.protocol.map(|p| p.to_string()).unwrap_or_else(unknown),
+            (secs / 60.0) as u32,
+            secs % 60.0,
+            self.cd_track
+                .map(|t| t.to_string())
+                .unwrap_or_else(|| "none".to_owned()),
+            self.player.clone().unwrap_or_else(unknown),
+            self.messages,
+        )
+    }
+}
+
+/// Writes the messages received from a server to a demo file, in the format read by
+/// [`DemoServer::new`].
+#[derive(Resource)]
+pub struct DemoRecorder<W = BufWriter<File>> {
+    /// The path of the demo in the game directory.
+    path: String,
+    writer: W,
+    messages: usize,
+    /// Bookmarks made while recording, kept in a sidecar file next to the demo.
+    marks: DemoMarks,
+}
+
+impl DemoRecorder {
+    /// Create the demo called `name` in the game directory, adding the `.dem` extension if it's
+    /// missing.
+    pub fn create(vfs: &Vfs, name: &str) -> Result<DemoRecorder, DemoServerError> {
+        let path = if name.ends_with(".dem") {
+            name.to_owned()
+        } else {
+            format!("{}.dem", name)
+        };
+        let file = vfs.write(&path)?;
+
+        // bookmarks left by an earlier demo of the same name would be at the wrong times
+        let stale_marks = marks_path(&path);
+        if vfs.open(&stale_marks).is_ok() {
+            DemoMarks::new(stale_marks).save(vfs)?;
+        }
+
+        DemoRecorder::new(path, file)
+    }
+}
+
+impl<W: Write> DemoRecorder<W> {
+    /// Start a demo written to `writer`. The demo has no CD track of its own, so the music is
+    /// whatever its messages ask for.
+    pub fn new(path: String, mut writer: W) -> Result<DemoRecorder<W>, DemoServerError> {
+        writer.write_all(b"-1\n")?;
+        Ok(DemoRecorder {
+            marks: DemoMarks::new(marks_path(&path)),
+            path,
+            writer,
+            messages: 0,
+        })
+    }
+
+    /// Write a block of server messages, along with the view angles to display it with.
+    pub fn write_message(
+        &mut self,
+        view_angles: Vector3<Deg<f32>>,
+        message: &[u8],
+    ) -> Result<(), DemoServerError> {
+        self.writer
+            .write_u32::<LittleEndian>(message.len() as u32)?;
+        for angle in [view_angles.x, view_angles.y, view_angles.z] {
+            self.writer.write_f32::<LittleEndian>(angle.0)?;
+        }
+        self.writer.write_all(message)?;
+        self.messages += 1;
+
+        Ok(())
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn marks(&self) -> &DemoMarks {
+        &self.marks
+    }
+
+    pub fn marks_mut(&mut self) -> &mut DemoMarks {
+        &mut self.marks
+    }
+
+    /// The number of message blocks written so far.
+    pub fn messages(&self) -> usize {
+        self.messages
+    }
+
+    /// End the demo with a disconnect, so that playback stops where the recording did, and
+    /// flush it. Nothing more should be written afterwards.
+    pub fn finish(&mut self) -> Result<(), DemoServerError> {
+        let mut disconnect = Vec::new();
+        ServerCmd::Disconnect.serialize(&mut disconnect)?;
+        self.write_message(Vector3::new(Deg(0.), Deg(0.), Deg(0.)), &disconnect)?;
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+/// The path of the sidecar file for the demo at `demo_path`.
+fn marks_path(demo_path: &str) -> String {
+    format!(
+        "{}.marks",
+        demo_path.strip_suffix(".dem").unwrap_or(demo_path)
+    )
+}
+
+/// A bookmark in a demo.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DemoMark {
+    /// The demo time of the bookmark.
+    pub time: Duration,
+    pub note: String,
+}
+
+/// The bookmarks of a demo, which are kept in a sidecar file next to it.
+///
+/// The file has a line for each bookmark, with its time in seconds followed by its note.
+#[derive(Clone, Debug, Default)]
+pub struct DemoMarks {
+    /// The virtual path of the sidecar file, or `None` if the demo has nowhere to keep bookmarks.
+    path: Option<String>,
+    /// Bookmarks in order of time.
+    marks: Vec<DemoMark>,
+}
+
+impl DemoMarks {
+    /// No bookmarks yet, to be kept at `path`.
+    pub fn new<S: Into<String>>(path: S) -> DemoMarks {
+        DemoMarks {
+            path: Some(path.into()),
+            marks: Vec::new(),
+        }
+    }
+
+    /// Load the bookmarks kept at `path`. If there is no such file, there are no bookmarks yet.
+    pub fn load<S: Into<String>>(vfs: &Vfs, path: S) -> DemoMarks {
+        let path = path.into();
+        let mut text = String::new();
+        if let Ok(mut file) = vfs.open(&path) {
+            if let Err(e) = file.read_to_string(&mut text) {
+                warn!("Couldn't read demo bookmarks from {}: {}", path, e);
+            }
+        }
+
+        DemoMarks::parse(path, &text)
+    }
+
+    /// Parse the contents of the sidecar file at `path`, skipping any lines that aren't
+    /// bookmarks.
+    pub fn parse<S: Into<String>>(path: S, text: &str) -> DemoMarks {
+        let mut marks = DemoMarks::new(path);
+
+        for line in text.lines().filter(|l| !l.trim().is_empty()) {
+            let (time, note) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+            match time.parse::<f32>() {
+                Ok(time) if time >= 0. => {
+                    marks.add(engine::duration_from_f32(time), note.trim());
+                }
+                _ => warn!("Bad demo bookmark: {}", line),
+            }
+        }
+
+        marks
+    }
+
+    /// Write the bookmarks to the sidecar file.
+    pub fn save(&self, vfs: &Vfs) -> Result<(), DemoServerError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let mut file = vfs.write(path)?;
+        file.write_all(self.to_text().as_bytes())?;
+        file.flush()?;
+
+        Ok(())
+    }
+
+    /// The contents of the sidecar file.
+    pub fn to_text(&self) -> String {
+        self.marks
+            .iter()
+            .map(|mark| format!("{:.3} {}\n", engine::duration_to_f32(mark.time), mark.note))
+            .collect()
+    }
+
+    /// Add a bookmark at `time`, returning its number.
+    pub fn add<S: Into<String>>(&mut self, time: Duration, note: S) -> usize {
+        // each bookmark is a line of the sidecar file
+        let note = note.into().replace(['\n', '\r'], " ");
+        let index = self.marks.partition_point(|m| m.time <= time);
+        self.marks.insert(index, DemoMark { time, note });
+
+        index + 1
+    }
+
+    /// The bookmark numbered `n`, counting from 1.
+    pub fn get(&self, n: usize) -> Option<&DemoMark> {
+        n.checked_sub(1).and_then(|i| self.marks.get(i))
+    }
+
+    pub fn len(&self) -> usize {
+        self.marks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.marks.is_empty()
+    }
+
+    /// A numbered list of the bookmarks for the console.
+    pub fn describe(&self) -> String {
+        if self.marks.is_empty() {
+            return "No bookmarks in this demo\n".into();
+        }
+
+        self.marks
+            .iter()
+            .enumerate()
+            .map(|(i, mark)| {
+                format!(
+                    "{:>3} {:>8.1}s {}\n",
+                    i + 1,
+                    engine::duration_to_f32(mark.time),
+                    mark.note
+                )
+            })
+            .collect()
+    }
+}
+
+struct ReplayFrame {
+    time: Duration,
+    view_angles: Vector3<Deg<f32>>,
+    message: Vec<u8>,
+}
+
+/// A rolling record of the messages received from a server, which can be played back as a demo.
+#[derive(Default)]
+pub struct ReplayBuffer {
+    /// Every message received while signing on. These are always played back first, so that the
+    /// client knows about the level before the recorded frames begin.
+    signon: Vec<Vec<u8>>,
+
+    /// Messages received since signing on, oldest first.
+    frames: VecDeque<ReplayFrame>,
+}
+
+impl ReplayBuffer {
+    /// Record a message received while signing on.
+    ///
+    /// If any frames have been recorded, this is the start of a new level and the previous
+    /// recording is discarded.
+    pub fn record_signon(&mut self, message: &[u8]) {
+        if !self.frames.is_empty() {
+            self.signon.clear();
+            self.frames.clear();
+        }
+
+        self.signon.push(message.to_owned());
+    }
+
+    /// Record a message received at client time `time`, discarding frames more than `length`
+    /// older than it.
+    pub fn record(
+        &mut self,
+        time: Duration,
+        view_angles: Vector3<Deg<f32>>,
+        message: &[u8],
+        length: Duration,
+    ) {
+        self.frames.push_back(ReplayFrame {
+            time,
+            view_angles,
+            message: message.to_owned(),
+        });
+
+        while self
+            .frames
+            .front()
+            .is_some_and(|frame| time - frame.time > length)
+        {
+            self.frames.pop_front();
+        }
+    }
+
+    /// The messages received while signing on to the current level.
+    pub fn signon(&self) -> &[Vec<u8>] {
+        &self.signon
+    }
+
+    /// Returns the length of play which has been recorded.
+    pub fn duration(&self) -> Duration {
+        match (self.frames.front(), self.frames.back()) {
+            (Some(first), Some(last)) => last.time - first.time,
+            _ => Duration::zero(),
+        }
+    }
+
+    /// Build a demo of the last `length` of recorded play.
+    ///
+    /// Returns `None` if nothing has been recorded since signing on.
+    pub fn demo(&self, length: Duration) -> Option<DemoServer> {
+        let end = self.frames.back()?.time;
+        let signon = self
+            .signon
+            .iter()
+            .map(|message| (Vector3::new(Deg(0.), Deg(0.), Deg(0.)), &message[..]));
+        let frames = self
+            .frames
+            .iter()
+            .skip_while(|frame| end - frame.time > length)
+            .map(|frame| (frame.view_angles, &frame.message[..]));
+
+        Some(DemoServer::from_messages(signon.chain(frames)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(s: i64) -> Duration {
+        Duration::try_seconds(s).unwrap()
+    }
+
+    fn angles() -> Vector3<Deg<f32>> {
+        Vector3::new(Deg(0.), Deg(90.), Deg(0.))
+    }
+
+    fn drain(mut demo: DemoServer) -> Vec<Vec<u8>> {
+        let mut out = Vec::new();
+        while let Some(msg) = demo.next() {
+            out.push(msg.message().to_owned());
+        }
+        out
+    }
+
+    #[test]
+    fn test_replay_buffer_trims_old_frames() {
+        let mut replay = ReplayBuffer::default();
+        replay.record_signon(&[1]);
+        for t in 0..10u8 {
+            replay.record(secs(t as i64), angles(), &[t + 10], secs(3));
+        }
+
+        assert_eq!(replay.duration(), secs(3));
+        assert_eq!(
+            drain(replay.demo(secs(10)).unwrap()),
+            vec![vec![1], vec![16], vec![17], vec![18], vec![19]]
+        );
+        assert_eq!(
+            drain(replay.demo(secs(1)).unwrap()),
+            vec![vec![1], vec![18], vec![19]]
+        );
+    }
+
+    #[test]
+    fn test_replay_buffer_new_signon() {
+        let mut replay = ReplayBuffer::default();
+        assert!(replay.demo(secs(10)).is_none());
+
+        replay.record_signon(&[1]);
+        replay.record(secs(0), angles(), &[2], secs(10));
+        replay.record_signon(&[3]);
+        replay.record_signon(&[4]);
+        assert!(replay.demo(secs(10)).is_none());
+
+        replay.record(secs(1), angles(), &[5], secs(10));
+        assert_eq!(
+            drain(replay.demo(secs(10)).unwrap()),
+            vec![vec![3], vec![4], vec![5]]
+        );
+    }
+
+    #[test]
+    fn test_demo_marks() {
+        let mut marks = DemoMarks::parse(
+            "demo1.marks",
+            "12.5 rocket jump\n\nnonsense\n3.000 start\n-1 before the start\n",
+        );
+        assert_eq!(marks.len(), 2);
+        assert_eq!(
+            marks.get(1),
+            Some(&DemoMark {
+                time: Duration::try_milliseconds(3000).unwrap(),
+                note: "start".into(),
+            })
+        );
+        assert_eq!(marks.get(2).unwrap().note, "rocket jump");
+        assert!(marks.get(0).is_none());
+        assert!(marks.get(3).is_none());
+
+        // bookmarks are numbered in order of time
+        assert_eq!(marks.add(secs(5), "quad"), 2);
+        assert_eq!(marks.get(3).unwrap().note, "rocket jump");
+
+        let reloaded = DemoMarks::parse("demo1.marks", &marks.to_text());
+        assert_eq!(reloaded.marks, marks.marks);
+    }
+
+    #[test]
+    fn test_demo_recorder() {
+        let mut recorder = DemoRecorder::new("demo1.dem".into(), Vec::new()).unwrap();
+        recorder.write_message(angles(), &[1, 2]).unwrap();
+        recorder.write_message(angles(), &[3]).unwrap();
+        assert_eq!(recorder.messages(), 2);
+        recorder.finish().unwrap();
+        let data = recorder.writer;
+
+        let mut demo =
+            DemoServer::new(&mut VirtualFile::PakBacked(io::Cursor::new(&data[..]))).unwrap();
+        assert_eq!(demo.track_override(), None);
+        assert_eq!(demo.next().unwrap().view_angles(), angles());
+
+        let mut disconnect = Vec::new();
+        ServerCmd::Disconnect.serialize(&mut disconnect).unwrap();
+        assert_eq!(drain(demo), vec![vec![3], disconnect]);
+    }
+
+    #[test]
+    fn test_recorder_marks() {
+        let mut recorder = DemoRecorder::new("demos/demo1.dem".into(), Vec::new()).unwrap();
+        assert_eq!(recorder.marks().path.as_deref(), Some("demos/demo1.marks"));
+
+        // notes go in the sidecar file as they are, as nothing runs them
+        recorder.marks_mut().add(secs(2), "quad; quit \"now\"");
+        assert_eq!(recorder.marks().to_text(), "2.000 quad; quit \"now\"\n");
+        recorder.finish().unwrap();
+
+        let mut disconnect = Vec::new();
+        ServerCmd::Disconnect.serialize(&mut disconnect).unwrap();
+        let data = recorder.writer;
+        let demo =
+            DemoServer::new(&mut VirtualFile::PakBacked(io::Cursor::new(&data[..]))).unwrap();
+        assert_eq!(drain(demo), vec![disconnect]);
+    }
+
+    #[test]
+    fn test_rewind() {
+        let mut demo = DemoServer::from_messages([(angles(), &[1][..]), (angles(), &[2][..])]);
+        demo.next();
+        demo.set_seek(Some(secs(1)));
+        demo.rewind();
+
+        assert_eq!(demo.seek(), Some(secs(1)));
+        assert_eq!(drain(demo), vec![vec![1], vec![2]]);
+    }
+
+    #[test]
+    fn test_demo_info() {
+        let message = |cmds: &[ServerCmd]| {
+            let mut msg = Vec::new();
+            for cmd in cmds {
+                cmd.serialize(&mut msg).unwrap();
+            }
+            msg
+        };
+        let messages = [
+            message(&[
+                ServerCmd::ServerInfo {
+                    protocol_version: net::PROTOCOL_VERSION as i32,
+                    max_clients: 4,
+                    game_type: net::GameType::CoOp,
+                    message: "The Slipgate Complex".into(),
+                    protocol_flags: net::ProtocolFlags::empty(),
+                    model_precache: vec!["maps/e1m1.bsp".into()],
+                    sound_precache: Vec::new(),
+                },
+                ServerCmd::CdTrack { track: 6, loop_: 6 },
+                ServerCmd::SetView { ent_id: 2 },
+            ]),
+            message(&[
+                ServerCmd::UpdateName {
+                    player_id: 0,
+                    new_name: "host".into(),
+                },
+                ServerCmd::UpdateName {
+                    player_id: 1,
+                    new_name: "ranger".into(),
+                },
+                ServerCmd::Time { time: 1.0 },
+            ]),
+            message(&[ServerCmd::Time { time: 63.5 }]),
+        ];
+        let demo = DemoServer::from_messages(messages.iter().map(|msg| (angles(), &msg[..])));
+
+        let info = demo.info();
+        assert_eq!(info.map.as_deref(), Some("e1m1"));
+        assert_eq!(info.title.as_deref(), Some("The Slipgate Complex"));
+        assert_eq!(info.protocol, Some(net::PROTOCOL_VERSION as i32));
+        assert_eq!(info.duration, Duration::try_milliseconds(62_500).unwrap());
+        assert_eq!(info.cd_track, Some(6));
+        assert_eq!(info.player.as_deref(), Some("ranger"));
+        assert_eq!(info.messages, 3);
+        assert!(info.describe().contains("duration: 1:02.5\n"));
+    }
+
+    #[test]
+    fn test_playback_time() {
+        let frame = std::time::Duration::from_millis(100);
+        let mut demo = DemoServer::from_messages([]);
+        assert_eq!(demo.playback_time(frame), frame);
+
+        demo.set_speed(2.0);
+        assert_eq!(demo.playback_time(frame), frame * 2);
+
+        // speeds that would overflow the playback time are capped
+        demo.set_speed(f32::INFINITY);
+        assert_eq!(demo.playback_time(frame), frame * MAX_SPEED as u32);
+        demo.set_speed(f32::NAN);
+        assert_eq!(demo.speed(), MAX_SPEED);
+        demo.set_speed(-1.0);
+        assert_eq!(demo.playback_time(frame), std::time::Duration::ZERO);
+
+        demo.set_speed(1.0);
+        demo.set_paused(true);
+        assert_eq!(demo.playback_time(frame), std::time::Duration::ZERO);
+    }
 }
@@ -0,0 +1,278 @@
+//! Fetching the maps, models and sounds that a server uses and the client doesn't have.
+//!
+//! When the precache lists in the server info name files that aren't in the VFS, the client puts
+//! off loading the level and asks the server for each missing file in turn with `download`. The
+//! server sends a file in pieces, and once the last piece is in the file is saved to the game
+//! directory, so that it is already there the next time. When every file has arrived the level is
+//! loaded as normal, and sign-on carries on from wherever the server had got to in the meantime.
+
+use std::collections::VecDeque;
+
+use bevy::asset::AssetServer;
+
+use crate::{
+    client::{
+        progress::{ConnectionProgress, DownloadProgress},
+        state::{ClientState, Precache},
+        ClientError,
+    },
+    common::{
+        bsp,
+        net::{ClientCmd, GameType, Protocol, SignOnStage},
+        vfs::{self, Vfs},
+    },
+};
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum DownloadError {
+    #[error("Server won't send {0}")]
+    Refused(String),
+    #[error("Server sent {0} out of order")]
+    OutOfOrder(String),
+    #[error("Server sent more of {0} than its size")]
+    TooLong(String),
+    #[error("Not allowed to download {0}")]
+    NotDownloadable(String),
+}
+
+/// The server info of a level that can't be loaded until some files have been downloaded.
+#[derive(Clone, Debug)]
+pub struct PendingLevel {
+    pub protocol: Protocol,
+    pub max_clients: u8,
+    pub game_type: GameType,
+    pub model_precache: Vec<String>,
+    pub sound_precache: Vec<String>,
+}
+
+impl PendingLevel {
+    /// Loads the level's models and sounds.
+    pub fn load(
+        self,
+        vfs: &Vfs,
+        asset_server: &AssetServer,
+        progress: &mut ConnectionProgress,
+    ) -> Result<ClientState, ClientError> {
+        ClientState::from_server_info(
+            vfs,
+            asset_server,
+            self.protocol,
+            self.max_clients,
+            self.game_type,
+            self.model_precache,
+            self.sound_precache,
+            progress,
+        )
+    }
+
+    /// Starts loading the level's models and sounds a few at a time.
+    pub fn precache(self, progress: &mut ConnectionProgress) -> Precache {
+        Precache::new(
+            self.protocol,
+            self.max_clients,
+            self.game_type,
+            self.model_precache,
+            self.sound_precache,
+            progress,
+        )
+    }
+}
+
+/// A piece of a file that has arrived.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Received {
+    /// There is more of the file to come.
+    Partial(DownloadProgress),
+    /// The file is complete.
+    File { name: String, data: Vec<u8> },
+}
+
+/// The files being fetched before a level can be loaded.
+#[derive(Clone, Debug)]
+pub struct Downloads {
+    /// The files left to fetch, starting with the one being received.
+    queue: VecDeque<String>,
+    /// What has arrived of the current file.
+    data: Vec<u8>,
+    level: PendingLevel,
+    /// The last sign-on stage the server moved the client to while files were downloading.
+    signon: Option<SignOnStage>,
+}
+
+impl Downloads {
+    /// Starts fetching `files`, which must all be downloadable, before loading `level`.
+    pub fn new(files: Vec<String>, level: PendingLevel) -> Result<Downloads, DownloadError> {
+        if let Some(name) = files.iter().find(|f| !vfs::is_downloadable(f)) {
+            return Err(DownloadError::NotDownloadable(name.clone()));
+        }
+
+        Ok(Downloads {
+            queue: files.into(),
+            data: Vec::new(),
+            level,
+            signon: None,
+        })
+    }
+
+    /// The name of the file being received.
+    pub fn current(&self) -> Option<&str> {
+        self.queue.front().map(String::as_str)
+    }
+
+    /// The command asking the server for the file being received.
+    pub fn request(&self) -> Option<ClientCmd> {
+        self.current().map(|name| ClientCmd::StringCmd {
+            cmd: format!("download \"{}\"", name),
+        })
+    }
+
+    /// Adds a piece of the current file that starts at `offset`, out of a file of `size` bytes.
+    pub fn receive(
+        &mut self,
+        size: Option<u32>,
+        offset: u32,
+        data: &[u8],
+    ) -> Result<Received, DownloadError> {
+        let Some(name) = self.queue.front() else {
+            return Err(DownloadError::OutOfOrder(String::new()));
+        };
+
+        let Some(size) = size else {
+            return Err(DownloadError::Refused(name.clone()));
+        };
+
+        if offset as usize != self.data.len() {
+            return Err(DownloadError::OutOfOrder(name.clone()));
+        }
+
+        if self.data.len() + data.len() > size as usize {
+            return Err(DownloadError::TooLong(name.clone()));
+        }
+
+        self.data.extend_from_slice(data);
+
+        if self.data.len() < size as usize {
+            return Ok(Received::Partial(DownloadProgress {
+                name: name.clone(),
+                received: self.data.len(),
+                size: Some(size as usize),
+            }));
+        }
+
+        let name = self.queue.pop_front().unwrap();
+        Ok(Received::File {
+            name,
+            data: std::mem::take(&mut self.data),
+        })
+    }
+
+    /// Whether every file has arrived.
+    pub fn is_finished(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Records a sign-on stage to move to once the level is loaded.
+    pub fn defer_signon(&mut self, stage: SignOnStage) {
+        self.signon = Some(stage);
+    }
+
+    /// Returns the level to load and the sign-on stage to move to afterwards.
+    pub fn into_level(self) -> (PendingLevel, Option<SignOnStage>) {
+        (self.level, self.signon)
+    }
+}
+
+/// Lists the files in the precache lists that can't be found in `vfs`.
+pub fn missing_files<S>(vfs: &Vfs, model_precache: &[String], sound_precache: &[S]) -> Vec<String>
+where
+    S: AsRef<str>,
+{
+    // model names starting with * are loaded from the world BSP
+    let models = model_precache
+        .iter()
+        .filter(|name| bsp::submodel_index(name).is_none())
+        .cloned();
+    let sounds = sound_precache
+        .iter()
+        .map(|name| format!("sound/{}", name.as_ref()));
+
+    models
+        .chain(sounds)
+        .filter(|name| vfs.open(name).is_err())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level() -> PendingLevel {
+        PendingLevel {
+            protocol: Protocol::NETQUAKE,
+            max_clients: 1,
+            game_type: GameType::CoOp,
+            model_precache: Vec::new(),
+            sound_precache: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_receive_files() {
+        let files = vec!["maps/custom.bsp".to_owned(), "sound/custom.wav".to_owned()];
+        let mut downloads = Downloads::new(files, level()).unwrap();
+        assert_eq!(downloads.current(), Some("maps/custom.bsp"));
+
+        assert_eq!(
+            downloads.receive(Some(5), 0, &[1, 2, 3]).unwrap(),
+            Received::Partial(DownloadProgress {
+                name: "maps/custom.bsp".to_owned(),
+                received: 3,
+                size: Some(5),
+            })
+        );
+        assert_eq!(
+            downloads.receive(Some(5), 3, &[4, 5]).unwrap(),
+            Received::File {
+                name: "maps/custom.bsp".to_owned(),
+                data: vec![1, 2, 3, 4, 5],
+            }
+        );
+
+        assert_eq!(downloads.current(), Some("sound/custom.wav"));
+        assert!(matches!(
+            downloads.receive(Some(0), 0, &[]).unwrap(),
+            Received::File { .. }
+        ));
+        assert!(downloads.is_finished());
+    }
+
+    #[test]
+    fn test_receive_errors() {
+        let files = vec!["maps/custom.bsp".to_owned()];
+        let mut downloads = Downloads::new(files, level()).unwrap();
+
+        assert!(matches!(
+            downloads.receive(Some(5), 2, &[1, 2]),
+            Err(DownloadError::OutOfOrder(_))
+        ));
+        assert!(matches!(
+            downloads.receive(Some(1), 0, &[1, 2]),
+            Err(DownloadError::TooLong(_))
+        ));
+        assert!(matches!(
+            downloads.receive(None, 0, &[]),
+            Err(DownloadError::Refused(_))
+        ));
+    }
+
+    #[test]
+    fn test_refuse_unsafe_names() {
+        let files = vec!["maps/../../autoexec.cfg".to_owned()];
+        assert!(matches!(
+            Downloads::new(files, level()),
+            Err(DownloadError::NotDownloadable(_))
+        ));
+    }
+}
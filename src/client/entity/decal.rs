@@ -0,0 +1,238 @@
+//! Marks left on the world by bullets, blood and explosions.
+//!
+//! Decals are flat quads laid against the brush surface nearest to an impact. The newest
+//! `MAX_DECALS` are kept, with the oldest making way for new ones, and each fades out at the end of
+//! its life.
+
+use cgmath::{InnerSpace as _, Matrix4, Vector3};
+use chrono::Duration;
+
+use crate::common::engine;
+
+/// The most decals that are kept at once.
+pub const MAX_DECALS: usize = 256;
+
+/// The colors of the particles that the progs spray blood with, for ordinary hits and for the
+/// lightning gun.
+pub const BLOOD_PARTICLE_COLORS: [u8; 2] = [73, 225];
+
+/// How far decals are lifted off the surface they're on, so that they aren't hidden by it.
+const SURFACE_OFFSET: f32 = 0.25;
+
+/// How long decals take to fade out at the end of their lives.
+const FADE_TIME_MS: i64 = 2000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DecalKind {
+    /// The hole left by a bullet or nail.
+    BulletHole,
+    /// Blood splashed onto a wall or floor behind whatever was hit.
+    Blood,
+    /// The scorch mark left by an explosion.
+    Scorch,
+}
+
+impl DecalKind {
+    pub const ALL: [DecalKind; 3] = [DecalKind::BulletHole, DecalKind::Blood, DecalKind::Scorch];
+
+    /// Half the width of the decal in world units.
+    pub fn radius(&self) -> f32 {
+        match self {
+            DecalKind::BulletHole => 2.0,
+            DecalKind::Blood => 10.0,
+            DecalKind::Scorch => 28.0,
+        }
+    }
+
+    /// How long the decal stays, including the time it spends fading out.
+    pub fn lifetime(&self) -> Duration {
+        match self {
+            DecalKind::BulletHole => Duration::try_seconds(20).unwrap(),
+            DecalKind::Blood | DecalKind::Scorch => Duration::try_seconds(30).unwrap(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Decal {
+    kind: DecalKind,
+    origin: Vector3<f32>,
+    normal: Vector3<f32>,
+    /// The turn of the decal about its normal, so that decals of the same kind don't all line up.
+    rotation: f32,
+    spawned: Duration,
+}
+
+impl Decal {
+    /// A decal centred on `origin`, on a surface facing `normal`. `rotation` is in radians.
+    pub fn new(
+        time: Duration,
+        kind: DecalKind,
+        origin: Vector3<f32>,
+        normal: Vector3<f32>,
+        rotation: f32,
+    ) -> Decal {
+        Decal {
+            kind,
+            origin,
+            normal: normal.normalize(),
+            rotation,
+            spawned: time,
+        }
+    }
+
+    pub fn kind(&self) -> DecalKind {
+        self.kind
+    }
+
+    pub fn origin(&self) -> Vector3<f32> {
+        self.origin
+    }
+
+    pub fn normal(&self) -> Vector3<f32> {
+        self.normal
+    }
+
+    fn expire(&self) -> Duration {
+        self.spawned + self.kind.lifetime()
+    }
+
+    /// How opaque the decal is at `time`.
+    pub fn alpha(&self, time: Duration) -> f32 {
+        let remaining = engine::duration_to_f32(self.expire() - time);
+        let fade = FADE_TIME_MS as f32 / 1000.0;
+        (remaining / fade).clamp(0.0, 1.0)
+    }
+
+    /// The transform, in Quake's coordinates, from a quad spanning `-1..1` on the x and y axes to
+    /// the decal in the world.
+    pub fn model(&self) -> Matrix4<f32> {
+        // any axis that isn't the normal will do to find the tangents
+        let reference = if self.normal.z.abs() < 0.9 {
+            Vector3::unit_z()
+        } else {
+            Vector3::unit_x()
+        };
+        let tangent = self.normal.cross(reference).normalize();
+        let bitangent = self.normal.cross(tangent);
+
+        let (sin, cos) = self.rotation.sin_cos();
+        let radius = self.kind.radius();
+        let s = (tangent * cos + bitangent * sin) * radius;
+        let t = (bitangent * cos - tangent * sin) * radius;
+        let origin = self.origin + self.normal * SURFACE_OFFSET;
+
+        Matrix4::from_cols(
+            s.extend(0.0),
+            t.extend(0.0),
+            self.normal.extend(0.0),
+            origin.extend(1.0),
+        )
+    }
+}
+
+/// The decals in the world, oldest first.
+#[derive(Clone, Default)]
+pub struct Decals {
+    decals: im::Vector<Decal>,
+}
+
+impl Decals {
+    pub fn new() -> Decals {
+        Decals::default()
+    }
+
+    /// Add a decal, making room by removing the oldest if there are already `MAX_DECALS`.
+    pub fn insert(&mut self, decal: Decal) {
+        while self.decals.len() >= MAX_DECALS {
+            self.decals.pop_front();
+        }
+
+        self.decals.push_back(decal);
+    }
+
+    pub fn clear(&mut self) {
+        self.decals.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Decal> {
+        self.decals.iter()
+    }
+
+    /// Remove the decals that have faded out by `time`.
+    pub fn update(&mut self, time: Duration) {
+        self.decals.retain(|decal| decal.expire() > time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decal_at(seconds: i64, x: f32) -> Decal {
+        Decal::new(
+            Duration::try_seconds(seconds).unwrap(),
+            DecalKind::BulletHole,
+            Vector3::new(x, 0.0, 0.0),
+            Vector3::unit_z(),
+            0.0,
+        )
+    }
+
+    #[test]
+    fn test_decals_ring_buffer() {
+        let mut decals = Decals::new();
+        for i in 0..MAX_DECALS + 10 {
+            decals.insert(decal_at(0, i as f32));
+        }
+
+        assert_eq!(decals.iter().count(), MAX_DECALS);
+        // the first decals were replaced by the last
+        assert_eq!(decals.iter().next().unwrap().origin().x, 10.0);
+        assert_eq!(
+            decals.iter().last().unwrap().origin().x,
+            (MAX_DECALS + 9) as f32
+        );
+    }
+
+    #[test]
+    fn test_decal_fade() {
+        let decal = decal_at(0, 0.0);
+        let lifetime = DecalKind::BulletHole.lifetime();
+        let at = |ms| Duration::try_milliseconds(ms).unwrap();
+
+        assert_eq!(decal.alpha(at(0)), 1.0);
+        assert_eq!(decal.alpha(lifetime - at(FADE_TIME_MS)), 1.0);
+        assert!((decal.alpha(lifetime - at(FADE_TIME_MS / 2)) - 0.5).abs() < 0.001);
+        assert_eq!(decal.alpha(lifetime), 0.0);
+
+        let mut decals = Decals::new();
+        decals.insert(decal);
+        decals.insert(decal_at(10, 1.0));
+        decals.update(lifetime);
+        assert_eq!(decals.iter().count(), 1);
+        assert_eq!(decals.iter().next().unwrap().origin().x, 1.0);
+    }
+
+    #[test]
+    fn test_decal_model() {
+        let decal = Decal::new(
+            Duration::zero(),
+            DecalKind::Scorch,
+            Vector3::new(0.0, 0.0, 64.0),
+            Vector3::new(0.0, 0.0, 2.0),
+            1.0,
+        );
+        let model = decal.model();
+
+        // the quad lies flat against the surface, lifted off it slightly
+        for corner in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+            let point = model * Vector3::new(corner.0, corner.1, 0.0).extend(1.0);
+            assert!((point.z - (64.0 + SURFACE_OFFSET)).abs() < 0.001);
+
+            let distance = point.truncate().truncate().magnitude();
+            let expected = DecalKind::Scorch.radius() * 2f32.sqrt();
+            assert!((distance - expected).abs() < 0.001);
+        }
+    }
+}
@@ -18,25 +18,101 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+pub mod decal;
 pub mod particle;
 
 use std::mem;
 
 use crate::common::{
     engine,
-    net::{EntityEffects, EntityState, EntityUpdate},
+    net::{self, EntityEffects, EntityState, EntityUpdate},
 };
 
 use bevy::ecs::component::Component;
-use cgmath::{Deg, Vector3};
+use cgmath::{Angle as _, Deg, InnerSpace as _, Vector3};
 use chrono::Duration;
+use serde::Deserialize;
 
 // if this is changed, it must also be changed in deferred.frag
-pub const MAX_LIGHTS: usize = 32;
+pub const MAX_LIGHTS: usize = 256;
 pub const MAX_BEAMS: usize = 24;
 pub const MAX_TEMP_ENTITIES: usize = 1 << 7;
 pub const MAX_STATIC_ENTITIES: usize = 128;
 
+/// An entity that moves further than this between two updates is taken to have teleported, and
+/// isn't interpolated.
+const TELEPORT_DISTANCE: f32 = 100.0;
+
+/// How long, in seconds, an entity that moves in steps takes over each step. Walking monsters
+/// think ten times a second.
+const STEP_TIME: f32 = 0.1;
+
+/// The models that `cl_nolerp_list` names by default. Their animations have frames, such as
+/// muzzle flashes and flickering flames, that look wrong when they are blended.
+pub const DEFAULT_NO_LERP_MODELS: &[&str] = &[
+    "progs/flame.mdl",
+    "progs/flame2.mdl",
+    "progs/braztall.mdl",
+    "progs/brazshrt.mdl",
+    "progs/longtrch.mdl",
+    "progs/flame_pyre.mdl",
+    "progs/v_saw.mdl",
+    "progs/v_xfist.mdl",
+    "progs/h2stuff/newfire.mdl",
+];
+
+/// How entities are interpolated between the two most recent updates from the server.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct LerpVars {
+    /// Whether to interpolate where entities are.
+    #[serde(rename(deserialize = "cl_lerpmove"))]
+    pub lerp_move: f32,
+    /// Whether to interpolate which way entities face.
+    #[serde(rename(deserialize = "cl_lerpangles"))]
+    pub lerp_angles: f32,
+    /// If greater than zero, the time in seconds over which turns are further smoothed out.
+    #[serde(rename(deserialize = "cl_anglesmooth"))]
+    pub angle_smooth: f32,
+    /// The models that are never interpolated, from `cl_nolerp_list`.
+    #[serde(skip)]
+    pub no_lerp_models: Vec<String>,
+}
+
+impl LerpVars {
+    /// Whether entities with the model called `model_name` are interpolated at all.
+    pub fn lerps_model(&self, model_name: &str) -> bool {
+        !self.no_lerp_models.iter().any(|name| name == model_name)
+    }
+}
+
+/// Find the point `factor` of the way from `from` to `to`, or just `to` if the distance is so
+/// large that the entity must have teleported.
+pub fn lerp_origin(from: Vector3<f32>, to: Vector3<f32>, factor: f32) -> Vector3<f32> {
+    let delta = to - from;
+    if delta.magnitude2() > TELEPORT_DISTANCE * TELEPORT_DISTANCE {
+        to
+    } else {
+        from + delta * factor
+    }
+}
+
+/// Find the angle `factor` of the way from `from` to `to`, turning whichever way is shorter so
+/// that a turn from 359 to 1 degrees doesn't spin all the way round.
+pub fn lerp_angle(from: Deg<f32>, to: Deg<f32>, factor: f32) -> Deg<f32> {
+    let delta = (to - from).normalize_signed();
+    (from + delta * factor).normalize()
+}
+
+/// Turn `from` towards `to` over `frame_time` seconds, closing the gap exponentially so that
+/// about two thirds of it is closed in `smooth_time` seconds.
+pub fn smooth_angle(from: Deg<f32>, to: Deg<f32>, frame_time: f32, smooth_time: f32) -> Deg<f32> {
+    if smooth_time <= 0.0 {
+        return to;
+    }
+
+    lerp_angle(from, to, 1.0 - (-frame_time / smooth_time).exp())
+}
+
 #[derive(Debug, Clone, Component)]
 pub struct DynamicEntity;
 
@@ -67,6 +143,10 @@ pub struct ClientEntity {
     pub sync_base: Duration,
     pub effects: EntityEffects,
     pub light_id: Option<usize>,
+    alpha: u8,
+    scale: u8,
+    /// For entities that move in steps, the time from which they move to their latest position.
+    step_start: Option<Duration>,
     // vis_frame: usize,
 }
 
@@ -105,10 +185,13 @@ impl ClientEntity {
             model_changed: false,
             frame_id: baseline.frame_id,
             skin_id: baseline.skin_id,
-            colormap: None,
+            colormap: Some(baseline.colormap),
             sync_base: Duration::zero(),
             effects: baseline.effects,
             light_id: None,
+            alpha: baseline.alpha,
+            scale: baseline.scale,
+            step_start: None,
         }
     }
 
@@ -133,6 +216,9 @@ impl ClientEntity {
             sync_base: Duration::zero(),
             effects: EntityEffects::empty(),
             light_id: None,
+            alpha: net::ENTITY_ALPHA_DEFAULT,
+            scale: net::ENTITY_SCALE_DEFAULT,
+            step_start: None,
         }
     }
 
@@ -144,7 +230,7 @@ impl ClientEntity {
         // enable lerping
         self.force_link = false;
 
-        if update.no_lerp || self.msg_time != msg_times[1] {
+        if self.msg_time != msg_times[1] {
             self.force_link = true;
         }
 
@@ -153,10 +239,25 @@ impl ClientEntity {
         // fill in missing values from baseline
         let new_state = update.to_entity_state(&self.baseline);
 
-        self.msg_origins[1] = self.msg_origins[0];
-        self.msg_origins[0] = new_state.origin;
-        self.msg_angles[1] = self.msg_angles[0];
-        self.msg_angles[0] = new_state.angles;
+        // entities that move in steps, such as walking monsters, are sent with `no_lerp`. They
+        // stand still in most messages, so they move from where they were before their latest
+        // step rather than from their previous message
+        let moved =
+            new_state.origin != self.msg_origins[0] || new_state.angles != self.msg_angles[0];
+        if update.no_lerp {
+            if moved || self.step_start.is_none() {
+                self.step_start = Some(msg_times[1]);
+            }
+        } else {
+            self.step_start = None;
+        }
+
+        if moved || !update.no_lerp {
+            self.msg_origins[1] = self.msg_origins[0];
+            self.msg_origins[0] = new_state.origin;
+            self.msg_angles[1] = self.msg_angles[0];
+            self.msg_angles[0] = new_state.angles;
+        }
 
         if self.model_id != new_state.model_id {
             self.model_changed = true;
@@ -167,7 +268,9 @@ impl ClientEntity {
         self.frame_id = new_state.frame_id;
         self.skin_id = new_state.skin_id;
         self.effects = new_state.effects;
-        self.colormap = update.colormap;
+        self.colormap = Some(new_state.colormap);
+        self.alpha = new_state.alpha;
+        self.scale = new_state.scale;
 
         if self.force_link {
             self.msg_origins[1] = self.msg_origins[0];
@@ -177,6 +280,39 @@ impl ClientEntity {
         }
     }
 
+    /// How far the entity should be from its previous position to its latest one at `time`,
+    /// when the client is `factor` of the way between its last two messages. Entities that move
+    /// in steps take [`STEP_TIME`] over each step however often messages arrive.
+    pub fn move_factor(&self, time: Duration, factor: f32) -> f32 {
+        match self.step_start {
+            Some(start) => (engine::duration_to_f32(time - start) / STEP_TIME).clamp(0.0, 1.0),
+            None => factor,
+        }
+    }
+
+    /// Move the entity `factor` of the way from its previous update to its latest one.
+    pub fn lerp(&mut self, factor: f32, vars: &LerpVars) {
+        self.origin = if vars.lerp_move != 0.0 {
+            lerp_origin(self.msg_origins[1], self.msg_origins[0], factor)
+        } else {
+            self.msg_origins[0]
+        };
+
+        for i in 0..3 {
+            self.angles[i] = if vars.lerp_angles != 0.0 {
+                lerp_angle(self.msg_angles[1][i], self.msg_angles[0][i], factor)
+            } else {
+                self.msg_angles[0][i]
+            };
+        }
+    }
+
+    /// Snap the entity to its latest update.
+    pub fn snap(&mut self) {
+        self.origin = self.msg_origins[0];
+        self.angles = self.msg_angles[0];
+    }
+
     /// Sets the entity's most recent message angles to the specified value.
     ///
     /// This is primarily useful for allowing interpolated view angles in demos.
@@ -228,6 +364,21 @@ impl ClientEntity {
     pub fn skin_id(&self) -> usize {
         self.skin_id
     }
+
+    /// Returns the entity's opacity, from 0 (invisible) to 1 (opaque).
+    pub fn alpha(&self) -> f32 {
+        net::decode_alpha(self.alpha)
+    }
+
+    /// Sets the entity's opacity, from 0 (invisible) to 1 (opaque).
+    pub fn set_alpha(&mut self, alpha: f32) {
+        self.alpha = net::encode_alpha(alpha);
+    }
+
+    /// Returns how many times its model's own size the entity is drawn.
+    pub fn scale(&self) -> f32 {
+        net::decode_scale(self.scale)
+    }
 }
 
 /// A descriptor used to spawn dynamic lights.
@@ -296,15 +447,21 @@ impl Light {
     }
 
     /// Returns `true` if the light should be retained at the specified time.
+    ///
+    /// A light is dropped once its time-to-live is up or its radius has decayed away.
     pub fn retain(&mut self, time: Duration) -> bool {
-        self.spawned + self.ttl > time
+        self.spawned + self.ttl > time && self.radius(time) > 0.0
     }
 }
 
 /// A set of active dynamic lights.
+///
+/// Each light has a key which stays the same until the light expires, so that an entity can keep
+/// updating the same light from frame to frame.
 #[derive(Clone)]
 pub struct Lights {
-    lights: im::Vector<Light>,
+    lights: im::Vector<(usize, Light)>,
+    next_key: usize,
 }
 
 impl Lights {
@@ -312,19 +469,24 @@ impl Lights {
     pub fn new() -> Lights {
         Lights {
             lights: Default::default(),
+            next_key: 0,
         }
     }
 
     /// Return a reference to the light with the given key, or `None` if no
     /// such light exists.
     pub fn get(&self, key: usize) -> Option<&Light> {
-        self.lights.get(key)
+        self.lights
+            .iter()
+            .find_map(|(k, light)| (*k == key).then_some(light))
     }
 
     /// Return a mutable reference to the light with the given key, or `None`
     /// if no such light exists.
     pub fn get_mut(&mut self, key: usize) -> Option<&mut Light> {
-        self.lights.get_mut(key)
+        self.lights
+            .iter_mut()
+            .find_map(|(k, light)| (*k == key).then_some(light))
     }
 
     /// Insert a new light into the set of lights.
@@ -335,30 +497,32 @@ impl Lights {
     /// the light will be overwritten with the new value.
     pub fn insert(&mut self, time: Duration, desc: LightDesc, key: Option<usize>) -> usize {
         if let Some(k) = key {
-            if let Some(key_light) = self.lights.get_mut(k) {
+            if let Some(key_light) = self.get_mut(k) {
                 *key_light = Light::from_desc(time, desc);
                 return k;
             }
         }
 
-        self.lights.push_back(Light::from_desc(time, desc));
-        self.lights.len() - 1
+        let key = self.next_key;
+        self.next_key = self.next_key.wrapping_add(1);
+        self.lights.push_back((key, Light::from_desc(time, desc)));
+        key
     }
 
     /// Return an iterator over the active lights.
     pub fn iter(&self) -> impl Iterator<Item = &Light> {
-        self.lights.iter()
+        self.lights.iter().map(|(_, light)| light)
     }
 
     /// Updates the set of dynamic lights for the specified time.
     ///
-    /// This will deallocate any lights which have outlived their time-to-live.
+    /// This will deallocate any lights which have outlived their time-to-live or decayed away.
     pub fn update(&mut self, time: Duration) {
         let lights = mem::take(&mut self.lights)
             .into_iter()
-            .filter_map(|mut light| {
+            .filter_map(|(key, mut light)| {
                 if light.retain(time) {
-                    Some(light)
+                    Some((key, light))
                 } else {
                     None
                 }
@@ -376,3 +540,116 @@ pub struct Beam {
     pub start: Vector3<f32>,
     pub end: Vector3<f32>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_deg_near(a: Deg<f32>, b: Deg<f32>) {
+        assert!(
+            (a - b).normalize_signed().0.abs() < 0.001,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn test_lerp_origin() {
+        let from = Vector3::new(0.0, 0.0, 0.0);
+        assert_eq!(
+            lerp_origin(from, Vector3::new(10.0, 0.0, 0.0), 0.5),
+            Vector3::new(5.0, 0.0, 0.0)
+        );
+
+        let teleported = Vector3::new(500.0, 0.0, 0.0);
+        assert_eq!(lerp_origin(from, teleported, 0.5), teleported);
+    }
+
+    #[test]
+    fn test_lerp_angle() {
+        assert_deg_near(lerp_angle(Deg(10.0), Deg(30.0), 0.5), Deg(20.0));
+        // the short way round crosses zero
+        assert_deg_near(lerp_angle(Deg(350.0), Deg(10.0), 0.5), Deg(0.0));
+        assert_deg_near(lerp_angle(Deg(10.0), Deg(350.0), 0.25), Deg(5.0));
+    }
+
+    #[test]
+    fn test_smooth_angle() {
+        assert_deg_near(smooth_angle(Deg(0.0), Deg(90.0), 0.1, 0.0), Deg(90.0));
+
+        let halfway = smooth_angle(Deg(0.0), Deg(90.0), 0.1 * 2f32.ln(), 0.1);
+        assert_deg_near(halfway, Deg(45.0));
+    }
+
+    #[test]
+    fn test_step_move_factor() {
+        let ms = |ms| Duration::try_milliseconds(ms).unwrap();
+        let baseline = EntityState::uninitialized();
+        let update = |x: f32, no_lerp: bool| EntityUpdate {
+            no_lerp,
+            ..EntityState {
+                origin: Vector3::new(x, 0.0, 0.0),
+                ..baseline.clone()
+            }
+            .make_update(1, &baseline)
+        };
+
+        let assert_near = |a: f32, b: f32| assert!((a - b).abs() < 0.001, "{} != {}", a, b);
+
+        let mut ent = ClientEntity::from_baseline(1, baseline.clone());
+        ent.update([ms(50), ms(0)], update(0.0, true));
+        ent.update([ms(100), ms(50)], update(8.0, true));
+        assert_near(ent.move_factor(ms(100), 0.0), 0.5);
+
+        // standing still between steps carries on with the last one
+        ent.update([ms(150), ms(100)], update(8.0, true));
+        assert_near(ent.move_factor(ms(125), 0.0), 0.75);
+        let vars = LerpVars {
+            lerp_move: 1.0,
+            ..LerpVars::default()
+        };
+        ent.lerp(ent.move_factor(ms(125), 0.0), &vars);
+        assert_near(ent.origin.x, 6.0);
+        assert_near(ent.move_factor(ms(200), 0.0), 1.0);
+
+        ent.update([ms(200), ms(150)], update(16.0, false));
+        assert_near(ent.move_factor(ms(175), 0.5), 0.5);
+    }
+
+    #[test]
+    fn test_no_lerp_models() {
+        let vars = LerpVars {
+            no_lerp_models: vec!["progs/flame.mdl".into()],
+            ..LerpVars::default()
+        };
+        assert!(!vars.lerps_model("progs/flame.mdl"));
+        assert!(vars.lerps_model("progs/player.mdl"));
+    }
+
+    #[test]
+    fn test_light_keys() {
+        let ms = |ms| Duration::try_milliseconds(ms).unwrap();
+        let desc = |ttl| LightDesc {
+            origin: Vector3::new(0.0, 0.0, 0.0),
+            init_radius: 350.0,
+            decay_rate: 300.0,
+            min_radius: None,
+            ttl: ms(ttl),
+        };
+
+        let mut lights = Lights::new();
+        let short = lights.insert(ms(0), desc(100), None);
+        let long = lights.insert(ms(0), desc(500), None);
+
+        // keys stay the same after the lights before them expire
+        lights.update(ms(200));
+        assert!(lights.get(short).is_none());
+        assert_eq!(lights.insert(ms(200), desc(5000), Some(long)), long);
+        assert_eq!(lights.iter().count(), 1);
+
+        // the radius decays away before the light expires
+        lights.update(ms(1400));
+        assert_eq!(lights.iter().count(), 0);
+    }
+}
@@ -54,10 +54,8 @@ lazy_static! {
     static ref EXPLOSION_VELOCITY_DISTRIBUTION: Uniform<f32> = Uniform::new(-256.0, 256.0);
 }
 
-// TODO: make max configurable
-pub const MIN_PARTICLES: usize = 512;
-
-// should be possible to get the whole particle list in cache at once
+// should be possible to get the whole particle list in cache at once, and it's the size of the
+// instance buffer they're drawn from
 pub const MAX_PARTICLES: usize = 16384;
 
 /// An animated color ramp.
@@ -265,7 +263,7 @@ impl Particles {
     // the original engine ignores new particles if at capacity, but it's not ideal
     pub fn insert(&mut self, particle: Particle) -> bool {
         // check capacity
-        if self.particles.len() == MIN_PARTICLES {
+        if self.particles.len() >= MAX_PARTICLES {
             return false;
         }
 
@@ -567,7 +565,7 @@ impl Particles {
             static ref TTL_DISTRIBUTION: Uniform<i64> = Uniform::new(200, 340);
 
             // random grey particles
-            static ref COLOR_DISTRIBUTION: Uniform<u8> = Uniform::new(7, 14);
+            static ref COLOR_DISTRIBUTION: Uniform<u8> = Uniform::new_inclusive(7, 14);
 
             static ref SCATTER_DISTRIBUTION: Uniform<f32> = Uniform::new(0.0, 4.0);
             static ref VELOCITY_DISTRIBUTION: Uniform<f32> = Uniform::new(50.0, 114.0);
@@ -0,0 +1,297 @@
+//! Racing against a recording: a demo of a previous run is played back as a translucent "ghost"
+//! alongside live play.
+//!
+//! Only the recorded player's position is taken from the demo. Both the demo and the live game
+//! count time from the start of the map, so the ghost is kept in step by sampling it at the
+//! current level time. It's drawn as a see-through player model, running whenever it moves.
+
+use bevy::prelude::*;
+use cgmath::{Angle as _, Deg, InnerSpace as _, Vector3};
+use chrono::Duration;
+use clap::Parser;
+
+use crate::{
+    client::{
+        demo::{DemoServer, DemoServerError},
+        entity::ClientEntity,
+        map_name, Connection, ConnectionState,
+    },
+    common::{
+        console::{ExecResult, RegisterCmdExt as _},
+        engine,
+        net::{Protocol, ServerCmd},
+        vfs::Vfs,
+    },
+};
+
+const GHOST_MODEL: &str = "progs/player.mdl";
+const GHOST_ALPHA: f32 = 0.4;
+
+/// The ghost isn't one of the server's entities, so it takes an ID that none of them can have.
+const GHOST_ENTITY_ID: usize = usize::MAX;
+
+/// Frames of the standard player model: standing still, and the cycle of running with a weapon.
+const STAND_FRAME: usize = 12;
+const RUN_FRAMES: std::ops::Range<usize> = 6..12;
+const RUN_FRAME_RATE: f32 = 10.;
+
+/// Slower than this, in units per second, the ghost stands still.
+const RUN_SPEED: f32 = 10.;
+
+pub struct SeismonGhostPlugin;
+
+impl Plugin for SeismonGhostPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            systems::update_ghost.run_if(resource_exists::<Connection>),
+        );
+
+        #[derive(Parser)]
+        #[command(
+            name = "ghost",
+            about = "Race against a demo, or stop showing the ghost if no demo is given"
+        )]
+        struct GhostCmd {
+            demo: Option<String>,
+        }
+
+        app.command(
+            |In(GhostCmd { demo }), mut commands: Commands, vfs: Res<Vfs>| -> ExecResult {
+                let Some(demo) = demo else {
+                    commands.remove_resource::<Ghost>();
+                    return default();
+                };
+
+                let mut demo_file = match vfs.open(format!("{}.dem", demo)) {
+                    Ok(f) => f,
+                    Err(e) => return format!("{}", e).into(),
+                };
+                let ghost = match DemoServer::new(&mut demo_file)
+                    .and_then(|mut server| Ghost::from_demo(&mut server))
+                {
+                    Ok(ghost) => ghost,
+                    Err(e) => return format!("Failed to load ghost: {}", e).into(),
+                };
+
+                let msg = format!(
+                    "ghost of {} loaded: {:.1}s on {}\n",
+                    demo,
+                    engine::duration_to_f32(ghost.duration()),
+                    ghost.map
+                );
+                commands.insert_resource(ghost);
+
+                msg.into()
+            },
+        );
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct GhostFrame {
+    time: Duration,
+    origin: Vector3<f32>,
+    yaw: Deg<f32>,
+}
+
+/// The path of the recorded player through the first map of a demo.
+#[derive(Resource, Debug)]
+pub struct Ghost {
+    map: String,
+    frames: Vec<GhostFrame>,
+}
+
+impl Ghost {
+    /// Reads the recorded player's movement from a demo.
+    ///
+    /// Only the messages up to the first level change are used.
+    pub fn from_demo(demo: &mut DemoServer) -> Result<Ghost, DemoServerError> {
+        let mut map = None;
+        let mut view_entity = None;
+        let mut baseline = None;
+        let mut time = None;
+        let mut current = None;
+        let mut frames = Vec::new();
+        let mut protocol = Protocol::NETQUAKE;
+
+        'messages: while let Some(msg) = demo.next() {
+            let reader = &mut msg.message();
+            while let Some(cmd) = ServerCmd::deserialize_with(reader, protocol)? {
+                match cmd {
+                    ServerCmd::ServerInfo {
+                        protocol_version,
+                        protocol_flags,
+                        model_precache,
+                        ..
+                    } => {
+                        if map.is_some() {
+                            break 'messages;
+                        }
+                        map = model_precache.first().map(|path| map_name(path).to_owned());
+                        protocol = Protocol::new(protocol_version, protocol_flags)?;
+                    }
+
+                    ServerCmd::SetView { ent_id } => view_entity = Some(ent_id as u16),
+
+                    ServerCmd::Time { time: t } => time = Some(engine::duration_from_f32(t)),
+
+                    ServerCmd::SpawnBaseline {
+                        ent_id,
+                        origin,
+                        angles,
+                        ..
+                    } if Some(ent_id) == view_entity => {
+                        baseline = Some((origin, angles.y));
+                        current = baseline;
+                    }
+
+                    ServerCmd::FastUpdate(update) if Some(update.ent_id) == view_entity => {
+                        // fields missing from an update revert to the baseline
+                        let Some((origin, yaw)) = baseline else {
+                            continue;
+                        };
+                        current = Some((
+                            Vector3::new(
+                                update.origin_x.unwrap_or(origin.x),
+                                update.origin_y.unwrap_or(origin.y),
+                                update.origin_z.unwrap_or(origin.z),
+                            ),
+                            update.yaw.unwrap_or(yaw),
+                        ));
+                    }
+
+                    _ => {}
+                }
+            }
+
+            let (Some(time), Some((origin, yaw))) = (time, current) else {
+                continue;
+            };
+            match frames.last() {
+                Some(GhostFrame { time: last, .. }) if *last >= time => {}
+                _ => frames.push(GhostFrame { time, origin, yaw }),
+            }
+        }
+
+        Ok(Ghost {
+            map: map.unwrap_or_default(),
+            frames,
+        })
+    }
+
+    /// Returns the name of the map the ghost was recorded on.
+    pub fn map(&self) -> &str {
+        &self.map
+    }
+
+    /// Returns how long the recorded run lasted, measured from the start of the map.
+    pub fn duration(&self) -> Duration {
+        self.frames.last().map_or(Duration::zero(), |f| f.time)
+    }
+
+    /// Returns the ghost's position and facing at the given level time, interpolated between
+    /// recorded frames, or `None` before the recording starts or after it ends.
+    pub fn sample(&self, time: Duration) -> Option<(Vector3<f32>, Deg<f32>)> {
+        let next = self.frames.partition_point(|f| f.time < time);
+        let to = self.frames.get(next)?;
+        if to.time == time {
+            return Some((to.origin, to.yaw));
+        }
+        let from = self.frames.get(next.checked_sub(1)?)?;
+
+        let span = engine::duration_to_f32(to.time - from.time);
+        let t = engine::duration_to_f32(time - from.time) / span;
+        let origin = from.origin + (to.origin - from.origin) * t;
+        // turn the short way round
+        let yaw = from.yaw + (to.yaw - from.yaw).normalize_signed() * t;
+
+        Some((origin, yaw))
+    }
+}
+
+mod systems {
+    use super::*;
+
+    /// Puts the ghost where the recording had the player at the current level time, or takes it
+    /// away if there's no ghost for this map.
+    pub fn update_ghost(
+        ghost: Option<Res<Ghost>>,
+        mut conn: ResMut<Connection>,
+        conn_state: Res<ConnectionState>,
+    ) {
+        let entity = match (ghost, &*conn_state) {
+            (Some(ghost), ConnectionState::Connected(state))
+                if state.map_name() == Some(ghost.map()) =>
+            {
+                ghost_entity(&ghost, &conn)
+            }
+            _ => None,
+        };
+
+        if entity.is_some() || conn.state.ghost.is_some() {
+            conn.state.ghost = entity;
+        }
+    }
+
+    fn ghost_entity(ghost: &Ghost, conn: &Connection) -> Option<ClientEntity> {
+        let time = conn.state.time();
+        let (origin, yaw) = ghost.sample(time)?;
+        let model_id = *conn.state.model_names.get(GHOST_MODEL)?;
+
+        // how fast the ghost was going over the last tenth of a second
+        let step = Duration::try_milliseconds(100).unwrap();
+        let speed = ghost
+            .sample(time - step)
+            .map_or(0., |(prev, _)| (origin - prev).magnitude() * 10.);
+
+        let mut ent = ClientEntity::uninitialized(GHOST_ENTITY_ID);
+        ent.model_id = model_id;
+        ent.origin = origin;
+        ent.angles = Vector3::new(Deg(0.), yaw, Deg(0.));
+        ent.frame_id = if speed > RUN_SPEED {
+            let run_len = RUN_FRAMES.len();
+            let step = (engine::duration_to_f32(time) * RUN_FRAME_RATE) as usize % run_len;
+            RUN_FRAMES.start + step
+        } else {
+            STAND_FRAME
+        };
+        ent.set_alpha(GHOST_ALPHA);
+
+        Some(ent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(millis: i64, x: f32, yaw: f32) -> GhostFrame {
+        GhostFrame {
+            time: Duration::try_milliseconds(millis).unwrap(),
+            origin: Vector3::new(x, 0., 0.),
+            yaw: Deg(yaw),
+        }
+    }
+
+    #[test]
+    fn test_sample_interpolates() {
+        let ghost = Ghost {
+            map: "e1m1".to_owned(),
+            frames: vec![frame(1000, 0., 350.), frame(1100, 10., 10.)],
+        };
+
+        let ms = |m| Duration::try_milliseconds(m).unwrap();
+        assert_eq!(ghost.sample(ms(900)), None);
+        assert_eq!(
+            ghost.sample(ms(1000)),
+            Some((Vector3::new(0., 0., 0.), Deg(350.)))
+        );
+        assert_eq!(ghost.sample(ms(1200)), None);
+
+        let (origin, yaw) = ghost.sample(ms(1050)).unwrap();
+        assert!((origin.x - 5.).abs() < 1e-4);
+        // crosses 0 rather than sweeping back through 180
+        assert!((yaw.0 - 360.).abs() < 1e-3);
+    }
+}
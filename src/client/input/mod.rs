@@ -43,6 +43,8 @@ impl Plugin for SeismonInputPlugin {
                     )),
                     systems::menu_input
                         .run_if(resource_exists_and_equals::<InputFocus>(InputFocus::Menu)),
+                    systems::chat_input
+                        .run_if(resource_exists_and_equals::<InputFocus>(InputFocus::Chat)),
                 )
                     .run_if(systems::window_is_focused),
             );
@@ -57,6 +59,8 @@ pub enum InputFocus {
     #[default]
     Console,
     Menu,
+    /// Typing a chat message with `messagemode` or `messagemode2`.
+    Chat,
 }
 
 pub mod systems {
@@ -69,11 +73,14 @@ pub mod systems {
     use chrono::TimeDelta;
 
     use crate::{
-        client::menu::Menu,
+        client::{chat::ChatInput, menu::Menu},
         common::console::{to_terminal_key, ConsoleInput, ConsoleOutput, Registry, RunCmd},
     };
 
-    use super::game::{AnyInput, Binding, BindingValidState, GameInput, Trigger};
+    use super::{
+        game::{AnyInput, Binding, BindingValidState, GameInput, Trigger},
+        InputFocus,
+    };
 
     pub fn window_is_focused(windows: Query<&Window, With<PrimaryWindow>>) -> bool {
         let Ok(window) = windows.get_single() else {
@@ -206,6 +213,45 @@ pub mod systems {
         }
     }
 
+    pub fn chat_input(
+        mut reader: ResMut<InputEventReader<KeyboardInput>>,
+        keyboard_events: Res<Events<KeyboardInput>>,
+        mut run_cmds: EventWriter<RunCmd<'static>>,
+        mut chat: ResMut<ChatInput>,
+        mut focus: ResMut<InputFocus>,
+    ) {
+        use bevy::input::keyboard::Key;
+
+        for key in reader.reader.read(&keyboard_events) {
+            // bindings are ignored while typing, so that every key can be part of the message
+            let KeyboardInput {
+                logical_key,
+                state: ButtonState::Pressed,
+                ..
+            } = key
+            else {
+                continue;
+            };
+
+            match logical_key {
+                Key::Escape => {
+                    chat.finish();
+                    *focus = InputFocus::Game;
+                    return;
+                }
+                Key::Enter => {
+                    run_cmds.send_batch(chat.finish());
+                    *focus = InputFocus::Game;
+                    return;
+                }
+                Key::Backspace => chat.backspace(),
+                Key::Space => chat.push(' '),
+                Key::Character(c) => c.chars().for_each(|c| chat.push(c)),
+                _ => (),
+            }
+        }
+    }
+
     pub fn menu_input(
         mut reader: ResMut<InputEventReader<KeyboardInput>>,
         keyboard_events: Res<Events<KeyboardInput>>,
@@ -22,7 +22,7 @@ use std::{fmt::Debug, str::FromStr};
 
 use crate::{
     client::menu::Menu,
-    common::console::{CName, SetCvar},
+    common::console::{CName, RunCmd, SetCvar},
 };
 
 use bevy::ecs::system::{Commands, SystemId};
@@ -33,6 +33,8 @@ use serde_lexpr::Value;
 pub enum Item {
     Submenu(Menu),
     Action(SystemId),
+    /// Runs a console command. Unlike actions, these can be added after the menu is built.
+    Command(RunCmd<'static>),
     Toggle(Toggle),
     Enum(Enum),
     Slider(Slider),
@@ -250,6 +252,11 @@ impl Slider {
     pub fn position(&self) -> f32 {
         self.selected as f32 / self.steps as f32
     }
+
+    /// How far along the slider is, from 0 at the minimum to 1 at the maximum.
+    pub fn fraction(&self) -> f32 {
+        self.selected as f32 / (self.steps - 1) as f32
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -29,7 +29,7 @@ use bevy::{
 };
 use failure::{bail, Error};
 
-use crate::common::console::CName;
+use crate::common::console::{CName, RunCmd};
 
 pub use self::item::{Enum, EnumItem, Item, Slider, TextField, Toggle};
 
@@ -207,15 +207,21 @@ impl Menu {
     /// `MenuState::Active`.
     ///
     /// If this item is an `Action`, executes the function contained in the
-    /// `Action`.
+    /// `Action`. If it is a `Command`, runs the command.
     ///
     /// Otherwise, this has no effect.
     #[must_use]
     pub fn activate(&mut self) -> Result<impl FnOnce(Commands), Error> {
-        fn run(action: Option<SystemId>) -> impl FnOnce(Commands) {
-            move |mut c: Commands| match action {
-                Some(action) => c.run_system(action),
-                None => {}
+        fn run(action: Option<SystemId>, cmd: Option<RunCmd<'static>>) -> impl FnOnce(Commands) {
+            move |mut c: Commands| {
+                if let Some(action) = action {
+                    c.run_system(action);
+                }
+                if let Some(cmd) = cmd {
+                    c.add(move |world: &mut World| {
+                        world.send_event(cmd);
+                    });
+                }
             }
         }
 
@@ -227,18 +233,20 @@ impl Menu {
                     m.state = MenuState::InSubMenu { index };
                     submenu.state = MenuState::Active { index: 0 };
 
-                    Ok(run(None))
+                    Ok(run(None, None))
                 }
 
                 Item::Action(action) => {
                     let action = *action;
-                    Ok(run(Some(action)))
+                    Ok(run(Some(action), None))
                 }
 
-                _ => Ok(run(None)),
+                Item::Command(cmd) => Ok(run(None, Some(cmd.clone()))),
+
+                _ => Ok(run(None, None)),
             }
         } else {
-            Ok(run(None))
+            Ok(run(None, None))
         }
     }
 
@@ -312,6 +320,44 @@ impl Menu {
         self.items.iter()
     }
 
+    /// Finds the submenu with the given name anywhere below this menu.
+    pub fn submenu_mut(&mut self, name: &str) -> Option<&mut Menu> {
+        self.items.iter_mut().find_map(|item| match &mut item.item {
+            Item::Submenu(m) if &*item.name == name => Some(m),
+            Item::Submenu(m) => m.submenu_mut(name),
+            _ => None,
+        })
+    }
+
+    /// Replaces the items of this menu with commands, for menus whose contents change while the
+    /// game runs. The selection stays where it was if that item still exists.
+    ///
+    /// Submenus can't be added this way, so the menu must not be displaying a submenu.
+    pub fn set_commands<I, N>(&mut self, commands: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = (N, RunCmd<'static>)>,
+        N: Into<CName>,
+    {
+        let items = commands
+            .into_iter()
+            .map(|(name, cmd)| NamedMenuItem::new(name, Item::Command(cmd)))
+            .collect::<im::Vector<_>>();
+        if items.is_empty() {
+            bail!("Menu must have at least one item");
+        }
+
+        self.state = match self.state {
+            MenuState::Inactive => MenuState::Inactive,
+            MenuState::Active { index } => MenuState::Active {
+                index: index.min(items.len() - 1),
+            },
+            MenuState::InSubMenu { .. } => bail!("Cannot replace a menu with an open submenu"),
+        };
+        self.items = items;
+
+        Ok(())
+    }
+
     pub fn state(&self) -> MenuState {
         self.state
     }
@@ -378,6 +424,16 @@ impl<'a> MenuBuilder<'a> {
         self
     }
 
+    pub fn add_command<N, C>(mut self, name: N, cmd: C) -> Self
+    where
+        N: Into<CName>,
+        C: Into<RunCmd<'static>>,
+    {
+        self.items
+            .push_back(NamedMenuItem::new(name, Item::Command(cmd.into())));
+        self
+    }
+
     pub fn add_toggle<N, S>(mut self, name: N, init: bool, cvar: S) -> Self
     where
         N: Into<CName>,
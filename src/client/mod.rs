@@ -18,50 +18,78 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+pub mod accessibility;
+pub mod autoswitch;
+#[cfg(feature = "screenrecord")]
+pub mod capture;
+pub mod chat;
 pub mod commands;
 mod cvars;
 pub mod demo;
+pub mod download;
 pub mod entity;
+pub mod ghost;
 pub mod input;
 pub mod menu;
+pub mod progress;
 pub mod render;
+pub mod route;
+pub mod slist;
 pub mod sound;
 pub mod state;
 pub mod trace;
 pub mod view;
+pub mod world_text;
 
 use self::{
+    accessibility::SeismonAccessibilityPlugin,
+    autoswitch::SeismonAutoswitchPlugin,
+    chat::SeismonChatPlugin,
+    ghost::SeismonGhostPlugin,
     input::SeismonInputPlugin,
     menu::{MenuBodyView, MenuBuilder, MenuView},
+    progress::ConnectionProgress,
     render::{RenderResolution, SeismonRenderPlugin},
+    route::SeismonRoutePlugin,
+    slist::SeismonServerBrowserPlugin,
     sound::{MixerEvent, SeismonSoundPlugin},
+    world_text::WorldText,
 };
 
-use std::{iter, mem, net::ToSocketAddrs, ops::Range, path::PathBuf};
+use std::{mem, net::ToSocketAddrs, path::PathBuf};
 
+#[cfg(any(feature = "websocket", target_arch = "wasm32"))]
+use crate::common::net::websocket::WebSocketTransport;
 use crate::{
     client::{
-        demo::{DemoServer, DemoServerError},
-        entity::{ClientEntity, MAX_STATIC_ENTITIES},
+        demo::{DemoRecorder, DemoServer, DemoServerError, ReplayBuffer},
+        download::{DownloadError, Downloads, PendingLevel, Received},
+        entity::{decal::BLOOD_PARTICLE_COLORS, ClientEntity, LerpVars, MAX_STATIC_ENTITIES},
+        progress::DownloadProgress,
         sound::{MusicPlayer, StartSound, StartStaticSound, StopSound},
-        state::{ClientState, PlayerInfo},
+        state::{ClientState, PlayerInfo, Precache},
         trace::{TraceEntity, TraceFrame},
-        view::{IdleVars, KickVars, MouseVars, RollVars},
+        view::{ChaseVars, IdleVars, KickVars, MouseVars, RollVars},
     },
     common::{
         self,
-        console::{ConsoleError, ConsoleOutput, RunCmd, SeismonConsolePlugin},
+        bsp::BspError,
+        console::{ConsoleError, ConsoleOutput, Registry, RunCmd, SeismonConsolePlugin},
         engine,
         model::{Model, ModelError},
         net::{
             self,
-            connect::{ConnectSocket, Request, Response, CONNECT_PROTOCOL_VERSION},
-            BlockingMode, ClientCmd, ClientMessage, ClientStat, EntityEffects, EntityState,
-            GameType, NetError, PlayerColor, QSocket, ServerCmd, ServerMessage, SignOnStage,
+            background::SocketThread,
+            connect::{ConnectFlags, ConnectSocket, Request, Response, CONNECT_PROTOCOL_VERSION},
+            userinfo::{self, UserInfo},
+            ClientCmd, ClientId, ClientMessage, ClientStat, EntityEffects, EntityState, GameType,
+            NetError, PlayerColor, Protocol, ProtocolFlags, QSocket, ServerCmd, ServerMessage,
+            SignOnStage,
         },
         util::QString,
         vfs::{Vfs, VfsError},
     },
+    server::{clock, Session},
 };
 use cgmath::{Deg, Vector3};
 
@@ -74,6 +102,7 @@ use bevy::{
     prelude::*,
     render::extract_resource::ExtractResource,
     time::{Time, Virtual},
+    utils::Instant,
     window::PrimaryWindow,
 };
 use chrono::Duration;
@@ -93,6 +122,17 @@ const MAX_STATS: usize = 32;
 const DEFAULT_SOUND_PACKET_VOLUME: u8 = 255;
 const DEFAULT_SOUND_PACKET_ATTENUATION: f32 = 1.0;
 
+/// How long to wait for a server to respond to a connect request sent over a WebSocket.
+#[cfg(any(feature = "websocket", target_arch = "wasm32"))]
+const WEBSOCKET_CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(7500);
+
+/// How long each frame may spend loading the level's models and sounds while signing on to a
+/// server.
+const PRECACHE_FRAME_TIME: std::time::Duration = std::time::Duration::from_millis(10);
+
+/// The gravity that particles fall with when there's no local server to take `sv_gravity` from.
+const DEFAULT_GRAVITY: f32 = 800.0;
+
 const CONSOLE_DIVIDER: &str = "\
 \n\n\
 \x1D\x1E\x1E\x1E\x1E\x1E\x1E\x1E\
@@ -108,9 +148,12 @@ pub struct SeismonClientPlugin<
     pub base_dir: Option<PathBuf>,
     pub game: Option<String>,
     pub main_menu: F,
+    /// Leave out audio output, so that the client can run where there is no sound device, such as
+    /// in tests.
+    pub headless: bool,
 }
 
-fn build_default(builder: MenuBuilder) -> Result<Menu, failure::Error> {
+pub(crate) fn build_default(builder: MenuBuilder) -> Result<Menu, failure::Error> {
     Ok(builder.build(MenuView {
         draw_plaque: true,
         title_path: "gfx/ttl_main.lmp".into(),
@@ -126,10 +169,44 @@ impl SeismonClientPlugin {
             base_dir: None,
             game: None,
             main_menu: Box::new(build_default),
+            headless: false,
         }
     }
 }
 
+impl<F> SeismonClientPlugin<F> {
+    /// Look for the game data in `base_dir` rather than the default directory.
+    pub fn with_base_dir<P: Into<PathBuf>>(mut self, base_dir: P) -> Self {
+        self.base_dir = Some(base_dir.into());
+        self
+    }
+
+    /// Load the mod in the directory `game` on top of `id1`.
+    pub fn with_game<S: Into<String>>(mut self, game: S) -> Self {
+        self.game = Some(game.into());
+        self
+    }
+
+    /// Build the main menu with `main_menu` instead of the default menu.
+    pub fn with_main_menu<G>(self, main_menu: G) -> SeismonClientPlugin<G>
+    where
+        G: Fn(MenuBuilder) -> Result<Menu, failure::Error> + Clone + Send + Sync + 'static,
+    {
+        SeismonClientPlugin {
+            base_dir: self.base_dir,
+            game: self.game,
+            main_menu,
+            headless: self.headless,
+        }
+    }
+
+    /// Leave out audio output.
+    pub fn headless(mut self) -> Self {
+        self.headless = true;
+        self
+    }
+}
+
 #[derive(Clone, Resource, ExtractResource)]
 pub struct SeismonGameSettings {
     pub base_dir: PathBuf,
@@ -156,7 +233,10 @@ where
             .init_resource::<Vfs>()
             .init_resource::<MusicPlayer>()
             .init_resource::<DemoQueue>()
+            .init_resource::<ConnectionProgress>()
+            .init_resource::<WorldText>()
             .add_event::<Impulse>()
+            .add_event::<GameplayEvent>()
             .add_event::<ClientMessage>()
             .add_event::<ServerMessage>()
             // TODO: Use bevy's state system
@@ -177,6 +257,20 @@ where
                             error!("Error handling frame: {}", e);
                         }
                     }),
+                    systems::finish_recording.run_if(
+                        resource_exists::<DemoRecorder>
+                            .and_then(not(resource_exists::<Connection>)),
+                    ),
+                    systems::update_cheat_protection,
+                    systems::update_timescale,
+                    systems::update_menu_pause,
+                    world_text::update_world_text,
+                    systems::update_userinfo.pipe(|In(res)| {
+                        // TODO: Error handling
+                        if let Err(e) = res {
+                            error!("Error updating userinfo: {}", e);
+                        }
+                    }),
                     systems::process_network_messages
                         .pipe(|In(res)| {
                             // TODO: Error handling
@@ -184,13 +278,31 @@ where
                                 error!("Error handling frame: {}", e);
                             }
                         })
-                        .run_if(resource_exists::<QSocket>),
+                        .run_if(resource_exists::<SocketThread>),
                 ),
             )
             .add_plugins(SeismonConsolePlugin)
             .add_plugins(SeismonRenderPlugin)
-            .add_plugins(SeismonSoundPlugin)
-            .add_plugins(SeismonInputPlugin);
+            .add_plugins(SeismonInputPlugin)
+            .add_plugins(SeismonRoutePlugin)
+            .add_plugins(SeismonServerBrowserPlugin)
+            .add_plugins(SeismonGhostPlugin)
+            .add_plugins(SeismonAutoswitchPlugin)
+            .add_plugins(SeismonChatPlugin)
+            .add_plugins(SeismonAccessibilityPlugin);
+
+        #[cfg(any(feature = "websocket", target_arch = "wasm32"))]
+        app.add_systems(
+            Main,
+            systems::finish_websocket_connect.run_if(resource_exists::<PendingConnect>),
+        );
+
+        if self.headless {
+            // sounds are still started as the server asks, but nothing plays them
+            app.add_event::<MixerEvent>();
+        } else {
+            app.add_plugins(SeismonSoundPlugin);
+        }
 
         cvars::register_cvars(app);
         commands::register_commands(app);
@@ -233,6 +345,12 @@ pub enum ClientError {
     EntityExists(usize),
     #[error("Invalid view entity: {0}")]
     InvalidViewEntity(usize),
+    #[error("Can't start instant replay: {0}")]
+    NoReplay(&'static str),
+    #[error("Not playing a demo")]
+    NotPlayingDemo,
+    #[error("Can't record a demo: {0}")]
+    NoRecording(&'static str),
     #[error("Too many static entities")]
     TooManyStaticEntities,
     #[error("No such lightmap animation: {0}")]
@@ -240,8 +358,12 @@ pub enum ClientError {
     // TODO: wrap PlayError
     #[error("Failed to open audio output stream")]
     OutputStream,
+    #[error("BSP error: {0}")]
+    Bsp(#[from] BspError),
     #[error("Demo server error: {0}")]
     DemoServer(#[from] DemoServerError),
+    #[error("Download failed: {0}")]
+    Download(#[from] DownloadError),
     #[error("Model error: {0}")]
     Model(#[from] ModelError),
     #[error("Network error: {0}")]
@@ -309,6 +431,9 @@ enum ConnectionStatus {
 
     /// Play the next demo in the demo queue.
     NextDemo,
+
+    /// Return to the live connection suspended by an instant replay.
+    Resume,
 }
 
 #[derive(Clone, Debug)]
@@ -317,6 +442,21 @@ pub struct ConnectedState {
     pub worldmodel_id: usize,
 }
 
+impl ConnectedState {
+    /// Returns the name of the current map without directory or extension, e.g. `e1m1`.
+    pub fn map_name(&self) -> Option<&str> {
+        self.model_precache
+            .get(self.worldmodel_id)
+            .map(|model| map_name(model.name()))
+    }
+}
+
+/// Strips the directory and extension from the path of a map, e.g. `maps/e1m1.bsp` -> `e1m1`.
+fn map_name(path: &str) -> &str {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.strip_suffix(".bsp").unwrap_or(name)
+}
+
 /// Indicates the state of an active connection.
 #[derive(Resource, Debug, ExtractResource, Clone)]
 pub enum ConnectionState {
@@ -336,10 +476,36 @@ enum ConnectionKind {
 
         /// The client's packet composition buffer.
         compose: Vec<u8>,
+
+        /// Recently-received messages, for instant replays.
+        replay: ReplayBuffer,
+
+        /// Messages received while an instant replay was playing, to be parsed on resuming.
+        missed: Vec<u8>,
+
+        /// Files being fetched from the server before the level can be loaded.
+        downloads: Option<Downloads>,
+
+        /// The level's models and sounds, while they are loaded over several frames.
+        precache: Option<Precache>,
     },
 
     /// A demo server.
     Demo(DemoServer),
+
+    /// An instant replay of recently-received server messages.
+    Replay {
+        demo: DemoServer,
+
+        /// Playback speed, where `1.0` is realtime.
+        speed: f32,
+
+        /// The live connection, which is resumed once the replay finishes.
+        live: Box<Connection>,
+
+        /// The sign-on state of the live connection.
+        live_state: ConnectionState,
+    },
 }
 
 struct ServerUpdate {
@@ -364,11 +530,14 @@ impl ConnectionKind {
         events: &Events<ServerMessage>,
     ) -> Result<Option<ServerUpdate>, ClientError> {
         match self {
-            Self::Server { reader, .. } => {
-                let mut out = Vec::new();
-                for ServerMessage { client_id, packet } in reader.read(events) {
+            Self::Server { reader, missed, .. } => {
+                let mut out = mem::take(missed);
+                for ServerMessage {
+                    client_id, packet, ..
+                } in reader.read(events)
+                {
                     // TODO: Actually use correct client id
-                    if *client_id == 0 {
+                    if *client_id == ClientId::LOCAL {
                         out.extend(packet);
                     }
                 }
@@ -378,6 +547,16 @@ impl ConnectionKind {
                     ..default()
                 }))
             }
+            Self::Replay { demo, .. } => Ok(demo.next().map(|msg_view| {
+                let mut view_angles = msg_view.view_angles();
+                view_angles.z = -view_angles.z;
+
+                ServerUpdate {
+                    message: msg_view.message().into(),
+                    angles: Some(view_angles),
+                    track_override: None,
+                }
+            })),
             Self::Demo(demo_srv) => {
                 let track_override = demo_srv.track_override();
                 let msg_view = match demo_srv.next() {
@@ -404,18 +583,102 @@ impl ConnectionKind {
         }
     }
 
+    /// Keep up with the messages sent to a live connection suspended by an instant replay, so
+    /// that none are lost by the time it resumes.
+    fn buffer_live(&mut self, events: &Events<ServerMessage>) {
+        if let Self::Replay { live, .. } = self {
+            if let Self::Server { reader, missed, .. } = &mut live.kind {
+                for ServerMessage {
+                    client_id, packet, ..
+                } in reader.read(events)
+                {
+                    if *client_id == ClientId::LOCAL {
+                        missed.extend(packet);
+                    }
+                }
+            }
+        }
+    }
+
     fn is_demo(&self) -> bool {
         match self {
-            Self::Demo(_) => true,
+            Self::Demo(_) | Self::Replay { .. } => true,
             Self::Server { .. } => false,
         }
     }
 }
 
 #[derive(Clone, Debug)]
-pub struct ClientVars<'a> {
-    pub name: &'a str,
-    pub color: u8,
+pub struct ClientVars {
+    pub userinfo: UserInfo,
+    /// Whether to fetch missing files from the server rather than giving up on the level.
+    pub allow_download: bool,
+    /// Whether the server is reached over the network, and so may be another engine's.
+    pub remote: bool,
+}
+
+/// The `name` and `color` commands that other engines' servers take in place of userinfo, which
+/// they don't understand.
+fn legacy_userinfo_cmds(userinfo: &UserInfo) -> [ClientCmd; 2] {
+    let colors = userinfo.colors();
+    [
+        ClientCmd::StringCmd {
+            cmd: format!("name \"{}\"", userinfo.name()),
+        },
+        ClientCmd::StringCmd {
+            cmd: format!("color {} {}", colors.top(), colors.bottom()),
+        },
+    ]
+}
+
+/// Cvars which are sent to the server as part of the userinfo string, along with their keys.
+const USERINFO_CVARS: &[(&str, &str)] = &[
+    (userinfo::USERINFO_NAME, "_cl_name"),
+    (userinfo::USERINFO_COLORS, "_cl_color"),
+    (userinfo::USERINFO_RATE, "rate"),
+    (userinfo::USERINFO_SKIN, "skin"),
+];
+
+/// Build the userinfo string from the current values of the userinfo cvars.
+pub fn userinfo_from_cvars(registry: &Registry) -> UserInfo {
+    let mut info = UserInfo::new();
+
+    for (key, cvar_name) in USERINFO_CVARS {
+        let Some(cvar) = registry.get_cvar(cvar_name) else {
+            continue;
+        };
+
+        // `serde_lexpr` parses bare words as symbols, so handle those separately from numbers
+        let value = cvar.value();
+        let value = match value.as_name().or_else(|| value.as_str()) {
+            Some(s) => s.to_owned(),
+            None => value.to_string(),
+        };
+
+        if let Err(e) = info.set(*key, value) {
+            warn!("Invalid value for {}: {}", cvar_name, e);
+        }
+    }
+
+    info
+}
+
+/// The models named by `cl_nolerp_list`, which may be separated by commas or spaces.
+fn no_lerp_models(registry: &Registry) -> Vec<String> {
+    let Some(cvar) = registry.get_cvar("cl_nolerp_list") else {
+        return Vec::new();
+    };
+
+    let value = cvar.value();
+    let list = match value.as_name().or_else(|| value.as_str()) {
+        Some(s) => s.to_owned(),
+        None => value.to_string(),
+    };
+
+    list.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|name| !name.is_empty())
+        .map(ToOwned::to_owned)
+        .collect()
 }
 
 /// A connection to a game server of some kind.
@@ -434,16 +697,162 @@ impl Connection {
             kind: ConnectionKind::Server {
                 reader: default(),
                 compose: default(),
+                replay: default(),
+                missed: default(),
+                downloads: None,
+                precache: None,
             },
         }
     }
 }
 
 impl Connection {
+    /// Suspend the live connection and play back the last `length` of received messages at the
+    /// given speed, returning to live play afterwards.
+    ///
+    /// `conn_state` is the sign-on state of the live connection, and is reset so that the replay
+    /// can sign on.
+    pub fn start_replay(
+        &mut self,
+        conn_state: &mut ConnectionState,
+        length: Duration,
+        speed: f32,
+    ) -> Result<(), ClientError> {
+        let demo = match &self.kind {
+            ConnectionKind::Server { replay, .. } => replay.demo(length),
+            ConnectionKind::Replay { .. } => {
+                return Err(ClientError::NoReplay("a replay is already playing"))
+            }
+            ConnectionKind::Demo(_) => {
+                return Err(ClientError::NoReplay("not connected to a server"))
+            }
+        };
+        let Some(demo) = demo else {
+            return Err(ClientError::NoReplay("nothing has been recorded yet"));
+        };
+
+        let live = mem::replace(self, Connection::new_server());
+        *self = Connection {
+            state: ClientState::new(),
+            kind: ConnectionKind::Replay {
+                demo,
+                speed,
+                live: Box::new(live),
+                live_state: mem::replace(
+                    conn_state,
+                    ConnectionState::SignOn(SignOnStage::Prespawn),
+                ),
+            },
+        };
+
+        Ok(())
+    }
+
+    /// The demo being played, if this is a demo. Instant replays don't count, as they have
+    /// nowhere to keep bookmarks.
+    pub fn demo(&self) -> Option<&DemoServer> {
+        match &self.kind {
+            ConnectionKind::Demo(demo) => Some(demo),
+            _ => None,
+        }
+    }
+
+    pub fn demo_mut(&mut self) -> Option<&mut DemoServer> {
+        match &mut self.kind {
+            ConnectionKind::Demo(demo) => Some(demo),
+            _ => None,
+        }
+    }
+
+    /// Whether the game has stopped, either because the server paused it or because the demo
+    /// being played is paused.
+    pub fn paused(&self) -> bool {
+        self.state.paused || self.demo().is_some_and(DemoServer::paused)
+    }
+
+    /// Jump to `time` in the demo being played.
+    ///
+    /// The client can only read a demo forwards, so if `time` has already passed the demo starts
+    /// again from the beginning, and `conn_state` is reset so that it can sign on.
+    pub fn seek_demo(
+        &mut self,
+        conn_state: &mut ConnectionState,
+        time: Duration,
+    ) -> Result<(), ClientError> {
+        let ConnectionKind::Demo(demo) = &mut self.kind else {
+            return Err(ClientError::NotPlayingDemo);
+        };
+
+        if time < self.state.time {
+            demo.rewind();
+            self.state = ClientState::new();
+            *conn_state = ConnectionState::SignOn(SignOnStage::Prespawn);
+        }
+        demo.set_seek(Some(time));
+
+        Ok(())
+    }
+
+    /// Start recording this connection to `recorder`, which is given the messages of the level
+    /// so far so that the demo can sign on.
+    pub fn start_recording(&self, recorder: &mut DemoRecorder) -> Result<(), ClientError> {
+        let signon = match &self.kind {
+            ConnectionKind::Server { replay, .. } => replay.signon(),
+            ConnectionKind::Demo(_) | ConnectionKind::Replay { .. } => {
+                return Err(ClientError::NoRecording("not connected to a server"))
+            }
+        };
+
+        for message in signon {
+            recorder.write_message(Vector3::new(Deg(0.), Deg(0.), Deg(0.)), message)?;
+        }
+
+        Ok(())
+    }
+
+    /// The view angles to write to a demo along with the messages of this frame.
+    pub fn recorded_view_angles(&self, state: &ConnectionState) -> Vector3<Deg<f32>> {
+        match state {
+            ConnectionState::SignOn(_) => Vector3::new(Deg(0.), Deg(0.), Deg(0.)),
+            ConnectionState::Connected(_) => {
+                let angles = self.state.view.input_angles();
+                // demos store roll inverted, see `ConnectionKind::recv`
+                Vector3::new(angles.pitch, angles.yaw, -angles.roll)
+            }
+        }
+    }
+
+    /// End an instant replay, returning the sign-on state of the resumed live connection.
+    ///
+    /// Returns `None` if no replay is playing.
+    fn resume(&mut self) -> Option<ConnectionState> {
+        match mem::replace(self, Connection::new_server()) {
+            Connection {
+                kind:
+                    ConnectionKind::Replay {
+                        live, live_state, ..
+                    },
+                ..
+            } => {
+                *self = *live;
+                Some(live_state)
+            }
+            other => {
+                *self = other;
+                None
+            }
+        }
+    }
+
     pub fn view_entity_id(&self) -> usize {
         self.state.view_entity_id()
     }
 
+    /// The client's copy of the level, as last updated by the server.
+    pub fn state(&self) -> &ClientState {
+        &self.state
+    }
+
     pub fn trace<'a, I>(&self, entity_ids: I) -> Result<TraceFrame, ClientError>
     where
         I: IntoIterator<Item = &'a usize>,
@@ -488,14 +897,73 @@ impl Connection {
         Ok(trace)
     }
 
+    /// Loads more of the level's models and sounds, for up to [`PRECACHE_FRAME_TIME`], and
+    /// carries on signing on once they are all loaded.
+    fn continue_precache(
+        &mut self,
+        state: Mut<ConnectionState>,
+        mut progress: Mut<ConnectionProgress>,
+        vfs: &Vfs,
+        asset_server: &AssetServer,
+        mixer_events: &mut EventWriter<MixerEvent>,
+        client_vars: &ClientVars,
+    ) -> Result<(), ClientError> {
+        let ConnectionKind::Server { precache, .. } = &mut self.kind else {
+            return Ok(());
+        };
+        let Some(pending) = precache else {
+            return Ok(());
+        };
+
+        let start = Instant::now();
+        while !pending.is_finished() && start.elapsed() < PRECACHE_FRAME_TIME {
+            pending.step(vfs, asset_server, &mut progress)?;
+        }
+        if !pending.is_finished() {
+            return Ok(());
+        }
+
+        let pending = precache.take().unwrap();
+        let signon = pending.deferred_signon();
+        // the view entity may have been set while the level was loading
+        let view_entity_id = self.state.view_entity_id();
+        self.state = pending.finish(vfs, asset_server, &mut progress)?;
+        self.state.view.set_entity_id(view_entity_id);
+        self.state.start_level_sounds(mixer_events);
+
+        if let Some(stage) = signon {
+            self.handle_signon(client_vars, state, progress, stage)?;
+        }
+
+        Ok(())
+    }
+
     fn handle_signon(
         &mut self,
         client_vars: &ClientVars,
         mut state: Mut<ConnectionState>,
+        mut progress: Mut<ConnectionProgress>,
         new_stage: SignOnStage,
     ) -> Result<(), ClientError> {
         use SignOnStage::*;
 
+        // the level isn't loaded yet, so answer once it is
+        if let ConnectionKind::Server {
+            downloads,
+            precache,
+            ..
+        } = &mut self.kind
+        {
+            if let Some(downloads) = downloads {
+                downloads.defer_signon(new_stage);
+                return Ok(());
+            }
+            if let Some(precache) = precache {
+                precache.defer_signon(new_stage);
+                return Ok(());
+            }
+        }
+
         let new_conn_state = match &*state {
             // TODO: validate stage transition
             ConnectionState::SignOn(_) => {
@@ -512,19 +980,15 @@ impl Connection {
                             .serialize(compose)?;
                         }
                         ClientInfo => {
-                            // TODO: fill in client info here
                             ClientCmd::StringCmd {
-                                cmd: format!("name \"{}\"\n", &client_vars.name),
+                                cmd: format!("userinfo \"{}\"", client_vars.userinfo),
                             }
                             .serialize(compose)?;
-                            ClientCmd::StringCmd {
-                                cmd: format!(
-                                    "color {} {}",
-                                    client_vars.color >> 4,
-                                    client_vars.color & ((1 << 4) - 1)
-                                ),
+                            if client_vars.remote {
+                                for cmd in legacy_userinfo_cmds(&client_vars.userinfo) {
+                                    cmd.serialize(compose)?;
+                                }
                             }
-                            .serialize(compose)?;
                             // TODO: need default spawn parameters?
                             ClientCmd::StringCmd {
                                 cmd: format!("spawn {}", ""),
@@ -563,6 +1027,7 @@ impl Connection {
         };
 
         *state = new_conn_state;
+        progress.set_stage(new_stage);
 
         Ok(())
     }
@@ -570,15 +1035,19 @@ impl Connection {
     fn parse_server_msg(
         &mut self,
         mut state: Mut<ConnectionState>,
+        mut progress: Mut<ConnectionProgress>,
         time: Time,
         vfs: &Vfs,
         asset_server: &AssetServer,
         server_events: &Events<ServerMessage>,
         mixer_events: &mut EventWriter<MixerEvent>,
+        gameplay_events: &mut EventWriter<GameplayEvent>,
         console_commands: &mut EventWriter<RunCmd<'static>>,
         mut console_output: Mut<ConsoleOutput>,
         kick_vars: KickVars,
         client_vars: ClientVars,
+        replay_length: Duration,
+        recording: Option<&mut DemoRecorder>,
     ) -> Result<ConnectionStatus, ClientError> {
         use ConnectionStatus::*;
 
@@ -588,16 +1057,19 @@ impl Connection {
             return Ok(Maintain);
         }
 
+        // sounds from the part of a demo that is skipped over would all start at once
+        let seeking = self.demo().and_then(DemoServer::seek).is_some();
+
         let Some(ServerUpdate {
             message,
             angles: demo_view_angles,
             track_override,
         }) = self.kind.recv(server_events)?
         else {
-            return if self.kind.is_demo() {
-                Ok(NextDemo)
-            } else {
-                Ok(Maintain)
+            return match self.kind {
+                ConnectionKind::Demo(_) => Ok(NextDemo),
+                ConnectionKind::Replay { .. } => Ok(Resume),
+                ConnectionKind::Server { .. } => Ok(Maintain),
             };
         };
 
@@ -609,7 +1081,7 @@ impl Connection {
         let reader = &mut message.as_slice();
 
         loop {
-            let cmd = match ServerCmd::deserialize(reader) {
+            let cmd = match ServerCmd::deserialize_with(reader, self.state.protocol) {
                 Err(e) => {
                     error!("{}", e);
                     break;
@@ -626,11 +1098,14 @@ impl Connection {
                 ServerCmd::NoOp => {}
 
                 ServerCmd::CdTrack { track, .. } => {
+                    let track = match (track_override, self.state.map_music()) {
+                        (Some(t), _) => t as usize,
+                        // the map's own music started with the level
+                        (None, Some(_)) => continue,
+                        (None, None) => track as usize,
+                    };
                     mixer_events.send(MixerEvent::StartMusic(Some(sound::MusicSource::TrackId(
-                        match track_override {
-                            Some(t) => t as usize,
-                            None => track as usize,
-                        },
+                        track,
                     ))));
                 }
 
@@ -638,9 +1113,18 @@ impl Connection {
                     console_output.set_center_print(text, time);
                 }
 
-                ServerCmd::PlayerData(player_data) => self.state.update_player(player_data),
+                ServerCmd::PlayerData(player_data) => {
+                    let origin = world_text::pickup_origin(
+                        self.state.view.final_origin(),
+                        self.state.view.final_angles().yaw,
+                    );
+                    for label in self.state.update_player(player_data) {
+                        gameplay_events.send(GameplayEvent::Pickup { label, origin });
+                    }
+                }
 
                 ServerCmd::Cutscene { text } => {
+                    console_output.set_finale_print(text.clone(), time);
                     self.state.intermission = Some(IntermissionKind::Cutscene { text });
                     self.state.completion_time = Some(self.state.time);
                 }
@@ -649,18 +1133,31 @@ impl Connection {
                     armor,
                     blood,
                     source,
-                } => self.state.handle_damage(armor, blood, source, kick_vars),
+                } => {
+                    self.state.handle_damage(armor, blood, source, kick_vars);
+                    gameplay_events.send(GameplayEvent::Damage {
+                        armor,
+                        blood,
+                        source,
+                    });
+                }
 
                 ServerCmd::Disconnect => {
                     return Ok(match self.kind {
                         ConnectionKind::Demo(_) => NextDemo,
+                        ConnectionKind::Replay { .. } => Resume,
                         ConnectionKind::Server { .. } => Disconnect,
                     });
                 }
 
                 ServerCmd::FastUpdate(ent_update) => {
                     // first update signals the last sign-on stage
-                    self.handle_signon(&client_vars, state.reborrow(), SignOnStage::Done)?;
+                    self.handle_signon(
+                        &client_vars,
+                        state.reborrow(),
+                        progress.reborrow(),
+                        SignOnStage::Done,
+                    )?;
 
                     let ent_id = ent_update.ent_id as usize;
                     self.state.update_entity(ent_id, ent_update)?;
@@ -674,6 +1171,7 @@ impl Connection {
                 }
 
                 ServerCmd::Finale { text } => {
+                    console_output.set_finale_print(text.clone(), time);
                     self.state.intermission = Some(IntermissionKind::Finale { text });
                     self.state.completion_time = Some(self.state.time);
                 }
@@ -714,23 +1212,49 @@ impl Connection {
                             count as usize,
                         ),
                     }
+
+                    // the progs spray blood as particles, which splashes onto the wall behind
+                    if BLOOD_PARTICLE_COLORS.contains(&color) {
+                        self.state.spawn_blood_decal(origin, direction);
+                    }
+                    if let Some(damage) = blood_damage(color, count) {
+                        gameplay_events.send(GameplayEvent::Hit { damage, origin });
+                    }
                 }
 
-                ServerCmd::Print { text } => console_output.print_alert(text.raw, time),
+                ServerCmd::Print { text } => {
+                    if let Some(chat) = chat::chat_text(&text.raw) {
+                        self.state.play_talk_sound(mixer_events);
+                        console_output.print_alert(chat, time);
+                        continue;
+                    }
+
+                    console_output.print_alert(text.raw, time);
+                }
 
                 ServerCmd::ServerInfo {
                     protocol_version,
                     max_clients,
                     game_type,
                     message,
+                    protocol_flags,
                     model_precache,
                     sound_precache,
                 } => {
                     // check protocol version
-                    if protocol_version != net::PROTOCOL_VERSION as i32 {
-                        Err(ClientError::UnrecognizedProtocol(protocol_version))?;
+                    let protocol = Protocol::new(protocol_version, protocol_flags)
+                        .map_err(|_| ClientError::UnrecognizedProtocol(protocol_version))?;
+
+                    progress.set_message(message.to_str());
+
+                    // a server changing level sends the new level's info to clients that are
+                    // already in the game, which then sign on again
+                    if let ConnectionState::Connected(_) = *state {
+                        *state = ConnectionState::SignOn(SignOnStage::Not);
+                        progress.set_stage(SignOnStage::Not);
                     }
 
+                    console_output.clear_center_print(time);
                     console_output.println_alert(CONSOLE_DIVIDER, time);
                     console_output.println_alert(message.raw, time);
                     console_output.println_alert(CONSOLE_DIVIDER, time);
@@ -740,13 +1264,99 @@ impl Connection {
                         _game_type: game_type,
                     };
 
-                    self.state = ClientState::from_server_info(
-                        vfs,
-                        asset_server,
+                    let missing = download::missing_files(vfs, &model_precache, &sound_precache);
+                    let level = PendingLevel {
+                        protocol,
                         max_clients,
+                        game_type,
                         model_precache,
                         sound_precache,
-                    )?;
+                    };
+
+                    match &mut self.kind {
+                        ConnectionKind::Server {
+                            compose, downloads, ..
+                        } if client_vars.allow_download && !missing.is_empty() => {
+                            let pending = Downloads::new(missing, level)?;
+                            if let Some(request) = pending.request() {
+                                request.serialize(compose)?;
+                            }
+                            progress.set_download(pending.current().map(|name| DownloadProgress {
+                                name: name.to_owned(),
+                                received: 0,
+                                size: None,
+                            }));
+                            *downloads = Some(pending);
+
+                            // the rest of the sign-on still has to be read with the level's
+                            // protocol
+                            self.state = ClientState {
+                                protocol,
+                                max_players: max_clients as usize,
+                                ..ClientState::new()
+                            };
+                        }
+
+                        // the server waits for the client to sign on before sending anything that
+                        // needs the level, so it can be loaded over the next few frames
+                        ConnectionKind::Server { precache, .. } => {
+                            let pending = level.precache(&mut progress);
+                            self.state = pending.placeholder();
+                            *precache = Some(pending);
+                        }
+
+                        // demos carry on with the level straight away
+                        _ => {
+                            self.state = level.load(vfs, asset_server, &mut progress)?;
+                            self.state.start_level_sounds(mixer_events);
+                        }
+                    }
+                }
+
+                ServerCmd::Download { size, offset, data } => {
+                    let ConnectionKind::Server {
+                        compose,
+                        downloads,
+                        precache,
+                        ..
+                    } = &mut self.kind
+                    else {
+                        // a recording may hold the files sent to the client that made it
+                        continue;
+                    };
+                    let Some(pending) = downloads else {
+                        warn!("Server sent a download that wasn't asked for");
+                        continue;
+                    };
+
+                    let (name, data) = match pending.receive(size, offset, &data)? {
+                        Received::Partial(received) => {
+                            progress.set_download(Some(received));
+                            continue;
+                        }
+                        Received::File { name, data } => (name, data),
+                    };
+
+                    vfs.save(&name, &data)?;
+                    console_output.println(format!("Downloaded {}", name), time);
+
+                    if let Some(request) = pending.request() {
+                        request.serialize(compose)?;
+                        progress.set_download(pending.current().map(|name| DownloadProgress {
+                            name: name.to_owned(),
+                            received: 0,
+                            size: None,
+                        }));
+                        continue;
+                    }
+
+                    progress.set_download(None);
+                    let (level, signon) = downloads.take().unwrap().into_level();
+                    let mut pending = level.precache(&mut progress);
+                    if let Some(stage) = signon {
+                        pending.defer_signon(stage);
+                    }
+                    *precache = Some(pending);
                 }
 
                 ServerCmd::SetAngle { angles } => self.state.set_view_angles(angles),
@@ -760,7 +1370,7 @@ impl Connection {
                 }
 
                 ServerCmd::SignOnStage { stage } => {
-                    self.handle_signon(&client_vars, state.reborrow(), stage)?;
+                    self.handle_signon(&client_vars, state.reborrow(), progress.reborrow(), stage)?;
                 }
 
                 ServerCmd::Sound {
@@ -786,6 +1396,10 @@ impl Connection {
                         break;
                     }
 
+                    if seeking {
+                        continue;
+                    }
+
                     let volume = volume.unwrap_or(DEFAULT_SOUND_PACKET_VOLUME);
                     let attenuation = attenuation.unwrap_or(DEFAULT_SOUND_PACKET_ATTENUATION);
                     // TODO: apply volume, attenuation, spatialization
@@ -966,6 +1580,7 @@ impl Connection {
                             name: new_name.into_string().into(),
                             colors: PlayerColor::new(0, 0),
                             frags: 0,
+                            ping: None,
                         });
                     }
                 }
@@ -979,7 +1594,7 @@ impl Connection {
                 }
 
                 ServerCmd::Version { version } => {
-                    if version != net::PROTOCOL_VERSION as i32 {
+                    if Protocol::new(version, ProtocolFlags::empty()).is_err() {
                         // TODO: handle with an error
                         error!(
                             "Incompatible server version: server's is {}, client's is {}",
@@ -990,7 +1605,7 @@ impl Connection {
                     }
                 }
 
-                ServerCmd::SetPause { .. } => {}
+                ServerCmd::SetPause { paused } => self.state.paused = paused,
 
                 ServerCmd::StopSound { entity_id, channel } => {
                     mixer_events.send(MixerEvent::StopSound(StopSound {
@@ -1002,55 +1617,132 @@ impl Connection {
             }
         }
 
+        let view_angles = self.recorded_view_angles(&state);
+
+        if let Some(recorder) = recording {
+            if !self.kind.is_demo() {
+                recorder.write_message(view_angles, &message)?;
+            }
+        }
+
+        if let ConnectionKind::Server { replay, .. } = &mut self.kind {
+            match &*state {
+                ConnectionState::SignOn(_) => replay.record_signon(&message),
+                ConnectionState::Connected(_) => {
+                    replay.record(self.state.time, view_angles, &message, replay_length)
+                }
+            }
+        }
+
         Ok(Maintain)
     }
 
     fn frame(
         &mut self,
         mut state: Mut<ConnectionState>,
+        mut progress: Mut<ConnectionProgress>,
         time: Time,
         vfs: &Vfs,
         asset_server: &AssetServer,
         from_server: &Events<ServerMessage>,
         to_server: &mut EventWriter<ClientMessage>,
         mixer_events: &mut EventWriter<MixerEvent>,
+        gameplay_events: &mut EventWriter<GameplayEvent>,
         console_commands: &mut EventWriter<RunCmd<'static>>,
         mut console: Mut<ConsoleOutput>,
         idle_vars: IdleVars,
         kick_vars: KickVars,
         roll_vars: RollVars,
         bob_vars: BobVars,
+        chase_vars: ChaseVars,
+        lerp_vars: &LerpVars,
         client_vars: ClientVars,
         cl_nolerp: bool,
         sv_gravity: f32,
+        replay_length: Duration,
+        mut recording: Option<&mut DemoRecorder>,
     ) -> Result<ConnectionStatus, ClientError> {
-        let frame_time = Duration::from_std(time.delta()).unwrap();
+        let frame_time = match &self.kind {
+            ConnectionKind::Replay { speed, .. } => time.delta().mul_f32(*speed),
+            ConnectionKind::Demo(demo) => demo.playback_time(time.delta()),
+            _ => time.delta(),
+        };
+        let frame_time = Duration::from_std(frame_time).unwrap();
         debug!("frame time: {}ms", frame_time.num_milliseconds());
 
+        self.kind.buffer_live(from_server);
+
+        self.continue_precache(
+            state.reborrow(),
+            progress.reborrow(),
+            vfs,
+            asset_server,
+            mixer_events,
+            &client_vars,
+        )?;
+
         // do this _before_ parsing server messages so that we know when to
         // request the next message from the demo server.
         self.state.advance_time(frame_time);
+
+        // fast-forward to the time that `demoseekmark` asked for, reading messages as quickly as
+        // they can be parsed
+        while let Some(target) = self.demo().and_then(DemoServer::seek) {
+            if self.state.msg_times[0] >= target {
+                if let Some(demo) = self.demo_mut() {
+                    demo.set_seek(None);
+                }
+                break;
+            }
+
+            self.state.time = self.state.msg_times[0];
+            match self.parse_server_msg(
+                state.reborrow(),
+                progress.reborrow(),
+                time,
+                vfs,
+                asset_server,
+                from_server,
+                mixer_events,
+                gameplay_events,
+                console_commands,
+                console.reborrow(),
+                kick_vars,
+                client_vars.clone(),
+                replay_length,
+                recording.as_deref_mut(),
+            )? {
+                ConnectionStatus::Maintain => {}
+                s => return Ok(s),
+            }
+        }
+
         match self.parse_server_msg(
             state.reborrow(),
+            progress.reborrow(),
             time,
             vfs,
             asset_server,
             from_server,
             mixer_events,
+            gameplay_events,
             console_commands,
             console.reborrow(),
             kick_vars,
             client_vars,
+            replay_length,
+            recording,
         )? {
             ConnectionStatus::Maintain => {}
-            // if Disconnect or NextDemo, delegate up the chain
+            // if Disconnect, NextDemo or Resume, delegate up the chain
             s => return Ok(s),
         };
 
         self.state.update_interp_ratio(cl_nolerp);
 
         // interpolate entity data and spawn particle effects, lights
-        self.state.update_entities()?;
+        self.state
+            .update_entities(frame_time, lerp_vars, chase_vars.chase_active != 0.)?;
 
         // update temp entities (lightning, etc.)
         self.state.update_temp_entities()?;
@@ -1058,6 +1750,9 @@ impl Connection {
         // remove expired lights
         self.state.lights.update(self.state.time);
 
+        // remove decals that have faded out
+        self.state.decals.update(self.state.time);
+
         // apply particle physics and remove expired particles
         self.state
             .particles
@@ -1086,6 +1781,7 @@ impl Connection {
                 } else {
                     default()
                 },
+                chase_vars,
             );
 
             // update camera color shifts for new position/effects
@@ -1096,37 +1792,61 @@ impl Connection {
     }
 }
 
-#[derive(Resource, ExtractResource, Clone)]
+/// The demos that are played one after another while the client is idle, as set by
+/// `startdemos`. After the last demo the loop starts again from the first.
+#[derive(Resource, ExtractResource, Clone, Default)]
 pub struct DemoQueue {
     values: Vec<String>,
-    indices: iter::Peekable<iter::Cycle<Range<usize>>>,
-}
-
-impl Default for DemoQueue {
-    fn default() -> Self {
-        Self::new(default())
-    }
+    /// The index of the demo to play next.
+    index: usize,
+    /// Whether the loop has been stopped, by `stopdemo` or a demo that couldn't be played.
+    stopped: bool,
 }
 
 impl DemoQueue {
     pub fn new(inner: Vec<String>) -> Self {
-        let range = (0..inner.len()).cycle().peekable();
         Self {
             values: inner,
-            indices: range,
+            index: 0,
+            stopped: false,
         }
     }
 
+    /// Returns the demo to play next and moves on to the one after it, or `None` if there are no
+    /// demos or the loop is stopped.
     pub fn next(&mut self) -> Option<&str> {
-        self.indices.next().map(|i| &self.values[i][..])
+        if self.stopped || self.values.is_empty() {
+            return None;
+        }
+
+        let i = self.index;
+        self.index = (i + 1) % self.values.len();
+        Some(&self.values[i][..])
+    }
+
+    /// The number of demos in the loop.
+    pub fn len(&self) -> usize {
+        self.values.len()
     }
 
-    pub fn index(&mut self) -> Option<usize> {
-        self.indices.peek().copied()
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
     }
 
+    /// Start the loop again from the first demo.
     pub fn reset(&mut self) {
-        self.indices = (0..self.values.len()).cycle().peekable();
+        self.index = 0;
+        self.stopped = false;
+    }
+
+    /// Stop the loop. It keeps its place, so [`DemoQueue::resume`] carries on from the demo
+    /// that would have been played next.
+    pub fn stop(&mut self) {
+        self.stopped = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.stopped = false;
     }
 }
 
@@ -1149,7 +1869,12 @@ where
             MAX_CONNECT_ATTEMPTS
         );
         con_sock.send_request(
-            Request::connect(net::GAME_NAME, CONNECT_PROTOCOL_VERSION),
+            Request::connect(
+                net::GAME_NAME,
+                CONNECT_PROTOCOL_VERSION,
+                &net::SUPPORTED_PROTOCOLS,
+                ConnectFlags::COMPRESS,
+            ),
             server_addr,
         )?;
 
@@ -1178,39 +1903,116 @@ where
         }
     }
 
-    let port = match response.ok_or(ClientError::NoResponse)? {
+    let (port, flags) = check_response(response.ok_or(ClientError::NoResponse)?)?;
+
+    let mut new_addr = server_addr;
+    new_addr.set_port(port);
+
+    // we're done with the connection socket, so turn it into a QSocket with the new address
+    let mut qsock = con_sock.into_qsocket(new_addr);
+    qsock.set_compression(flags.contains(ConnectFlags::COMPRESS));
+
+    Ok((qsock, ConnectionState::SignOn(SignOnStage::Prespawn)))
+}
+
+/// Checks the server's response to a connect request, returning the port and features it
+/// accepted the connection with.
+fn check_response(response: Response) -> Result<(u16, ConnectFlags), ClientError> {
+    match response {
         Response::Accept(accept) => {
             // validate port number
             if accept.port < 0 || accept.port >= std::u16::MAX as i32 {
                 Err(ClientError::InvalidConnectPort(accept.port))?;
             }
 
-            debug!("Connection accepted on port {}", accept.port);
-            accept.port as u16
+            // servers that predate negotiation always use protocol 15
+            let protocol = accept.protocol.unwrap_or(net::PROTOCOL_VERSION as i32);
+            if !net::SUPPORTED_PROTOCOLS.contains(&protocol) {
+                Err(ClientError::UnrecognizedProtocol(protocol))?;
+            }
+
+            debug!(
+                "Connection accepted on port {} with protocol {} and {:?}",
+                accept.port, protocol, accept.flags
+            );
+            Ok((accept.port as u16, accept.flags))
         }
 
         // our request was rejected.
         Response::Reject(reject) => Err(ClientError::ConnectionRejected(
             reject.message.into_string(),
-        ))?,
+        )),
 
         // the server sent back a response that doesn't make sense here (i.e. something other
         // than an Accept or Reject).
-        _ => Err(ClientError::InvalidConnectResponse)?,
-    };
-
-    let mut new_addr = server_addr;
-    new_addr.set_port(port);
+        _ => Err(ClientError::InvalidConnectResponse),
+    }
+}
 
-    // we're done with the connection socket, so turn it into a QSocket with the new address
-    let qsock = con_sock.into_qsocket(new_addr);
+/// A connection over a WebSocket that is waiting for the server to accept it. Browsers can't
+/// block while they wait, so the response is checked for once a frame instead.
+#[cfg(any(feature = "websocket", target_arch = "wasm32"))]
+#[derive(Resource)]
+pub struct PendingConnect {
+    transport: Option<WebSocketTransport>,
+    /// When to give up waiting, in real time.
+    give_up: std::time::Duration,
+}
 
-    Ok((qsock, ConnectionState::SignOn(SignOnStage::Prespawn)))
+/// Opens a WebSocket to the server at `url` and asks to connect. The connection is finished by
+/// `systems::finish_websocket_connect` once the server responds.
+#[cfg(any(feature = "websocket", target_arch = "wasm32"))]
+fn connect_websocket(url: &str, now: std::time::Duration) -> Result<PendingConnect, ClientError> {
+    let mut transport = WebSocketTransport::connect(url)?;
+    transport.send_request(&Request::connect(
+        net::GAME_NAME,
+        CONNECT_PROTOCOL_VERSION,
+        &net::SUPPORTED_PROTOCOLS,
+        ConnectFlags::COMPRESS,
+    ))?;
+
+    Ok(PendingConnect {
+        transport: Some(transport),
+        give_up: now + WEBSOCKET_CONNECT_TIMEOUT,
+    })
 }
 
 #[derive(Event)]
 pub struct Impulse(pub u8);
 
+/// Something that happened to the player, as told by the server.
+#[derive(Event, Clone, Debug)]
+pub enum GameplayEvent {
+    /// The player took damage from something at `source`.
+    Damage {
+        armor: u8,
+        blood: u8,
+        source: Vector3<f32>,
+    },
+
+    /// Something other than the player took `damage`, spraying blood at `origin`.
+    Hit { damage: u32, origin: Vector3<f32> },
+
+    /// The player picked up the item described by `label`, which floats up from `origin`.
+    Pickup { label: String, origin: Vector3<f32> },
+}
+
+/// The damage behind a spray of blood particles. The standard progs spray twice as many particles
+/// as the damage dealt, or four times as many for the lightning gun, and a count of 255 is an
+/// explosion rather than blood.
+fn blood_damage(color: u8, count: u8) -> Option<u32> {
+    let per_point = match color {
+        73 => 2,
+        225 => 4,
+        _ => return None,
+    };
+
+    match count {
+        0 | 255 => None,
+        count => Some((count as u32 / per_point).max(1)),
+    }
+}
+
 mod systems {
     use common::net::MessageKind;
     use serde::Deserialize;
@@ -1247,17 +2049,19 @@ mod systems {
                 kind: ConnectionKind::Server { .. },
                 ..
             }) => {
-                let move_cmd = state.handle_input(
-                    &*registry,
-                    Duration::from_std(frame_time.delta()).unwrap(),
-                    move_vars,
-                    mouse_vars,
-                    impulse,
-                );
+                let frame_time = Duration::from_std(frame_time.delta()).unwrap();
+                let move_cmd =
+                    state.handle_input(&*registry, frame_time, move_vars, mouse_vars, impulse);
+                if registry.read_cvar::<f32>("cl_predict").unwrap_or(0.) != 0. {
+                    // a remote server doesn't send its movement settings, so the local ones
+                    // stand in for them
+                    let pmove_vars = registry.read_cvars().unwrap_or_default();
+                    state.predict_player_move(&move_cmd, &pmove_vars, frame_time)?;
+                }
                 let mut msg = Vec::new();
                 move_cmd.serialize(&mut msg)?;
                 client_events.send(ClientMessage {
-                    client_id: 0,
+                    client_id: ClientId::LOCAL,
                     packet: msg,
                     kind: MessageKind::Unreliable,
                 });
@@ -1265,6 +2069,20 @@ mod systems {
                 // TODO: Refresh input (e.g. mouse movement)
             }
 
+            // nothing is sent while watching a demo, but the free camera flies with the same keys
+            Some(Connection {
+                kind: ConnectionKind::Demo(demo),
+                ..
+            }) => {
+                if let Some(freecam) = demo.freecam_mut() {
+                    freecam.handle_input(
+                        Duration::from_std(frame_time.delta()).unwrap(),
+                        &*registry,
+                        move_vars,
+                    );
+                }
+            }
+
             _ => (),
         }
 
@@ -1275,8 +2093,8 @@ mod systems {
     struct NetworkVars {
         #[serde(rename(deserialize = "cl_nolerp"))]
         disable_lerp: f32,
-        #[serde(rename(deserialize = "sv_gravity"))]
-        gravity: f32,
+        #[serde(rename(deserialize = "cl_replaylength"))]
+        replay_length: f32,
     }
 
     pub fn frame(
@@ -1286,41 +2104,49 @@ mod systems {
         time: Res<Time<Virtual>>,
         asset_server: Res<AssetServer>,
         mut mixer_events: EventWriter<MixerEvent>,
+        mut gameplay_events: EventWriter<GameplayEvent>,
         from_server: Res<Events<ServerMessage>>,
         mut to_server: EventWriter<ClientMessage>,
         mut console: ResMut<ConsoleOutput>,
         mut console_commands: EventWriter<RunCmd<'static>>,
         mut demo_queue: ResMut<DemoQueue>,
         mut focus: ResMut<InputFocus>,
-        mut conn: Option<ResMut<Connection>>,
+        (mut conn, mut recording, socket): (
+            Option<ResMut<Connection>>,
+            Option<ResMut<DemoRecorder>>,
+            Option<Res<SocketThread>>,
+        ),
         mut conn_state: ResMut<ConnectionState>,
+        mut progress: ResMut<ConnectionProgress>,
     ) -> Result<(), ClientError> {
         let NetworkVars {
             disable_lerp,
-            gravity,
+            replay_length,
         } = cvars.read_cvars().map_err(|c| ClientError::Cvar(c))?;
+        // `sv_gravity` belongs to the server, and is only there when hosting
+        let gravity = cvars
+            .read_cvar::<f32>("sv_gravity")
+            .unwrap_or(DEFAULT_GRAVITY);
         let idle_vars: IdleVars = cvars.read_cvars().map_err(|c| ClientError::Cvar(c))?;
         let kick_vars: KickVars = cvars.read_cvars().map_err(|c| ClientError::Cvar(c))?;
         let roll_vars: RollVars = cvars.read_cvars().map_err(|c| ClientError::Cvar(c))?;
         let bob_vars: BobVars = cvars.read_cvars().map_err(|c| ClientError::Cvar(c))?;
-        // `serde_lexpr` doesn't allow us to configure deserialising strings and doesn't recognise symbols
-        // as valid strings, so we need to use `.value().as_name()` and can't use `read_cvars`.
-        let client_vars: ClientVars = ClientVars {
-            name: cvars
-                .get_cvar("_cl_name")
-                .ok_or(ClientError::Cvar(ConsoleError::CvarParseInvalid {
-                    backtrace: snafu::Backtrace::capture(),
-                }))?
-                .value()
-                .as_name()
-                .unwrap_or("player"),
-            color: cvars.read_cvar("_cl_color")?,
+        let chase_vars: ChaseVars = cvars.read_cvars().map_err(|c| ClientError::Cvar(c))?;
+        let lerp_vars = LerpVars {
+            no_lerp_models: no_lerp_models(&cvars),
+            ..cvars.read_cvars().map_err(|c| ClientError::Cvar(c))?
+        };
+        let client_vars = ClientVars {
+            userinfo: userinfo_from_cvars(&cvars),
+            allow_download: cvars.read_cvar::<u8>("cl_allowdownload").unwrap_or(0) != 0,
+            remote: socket.is_some(),
         };
 
         let status = match conn.as_deref_mut() {
             Some(ref mut conn) => conn.frame(
                 conn_state.reborrow(),
-                if cvars.read_cvar::<u8>("sv_paused").unwrap() != 0 {
+                progress.reborrow(),
+                if conn.state.paused {
                     default()
                 } else {
                     time.as_generic()
@@ -1330,15 +2156,20 @@ mod systems {
                 &*from_server,
                 &mut to_server,
                 &mut mixer_events,
+                &mut gameplay_events,
                 &mut console_commands,
                 console.reborrow(),
                 idle_vars,
                 kick_vars,
                 roll_vars,
                 bob_vars,
+                chase_vars,
+                &lerp_vars,
                 client_vars,
                 disable_lerp != 0.,
                 gravity,
+                engine::duration_from_f32(replay_length),
+                recording.as_deref_mut(),
             )?,
             None => ConnectionStatus::Disconnect,
         };
@@ -1346,6 +2177,11 @@ mod systems {
         use ConnectionStatus::*;
         match status {
             Maintain => (),
+            Resume => {
+                if let Some(live_state) = conn.as_deref_mut().and_then(Connection::resume) {
+                    *conn_state = live_state;
+                }
+            }
             _ => {
                 let time = Duration::from_std(time.elapsed()).unwrap();
                 let new_conn = match status {
@@ -1353,48 +2189,43 @@ mod systems {
                     Disconnect => None,
 
                     // get the next demo from the queue
-                    NextDemo => loop {
-                        match demo_queue.next() {
-                            Some(demo) => {
-                                // TODO: Extract this to a separate function so we don't duplicate the logic to find the demos in different places
-                                let mut demo_file = match vfs
-                                    .open(format!("{}.dem", demo))
-                                    .or_else(|_| vfs.open(format!("demos/{}.dem", demo)))
-                                {
-                                    Ok(f) => Some(f),
-                                    Err(e) => {
-                                        // log the error, dump the demo queue and disconnect
+                    NextDemo => {
+                        let mut missing = 0;
+                        loop {
+                            match demo_queue.next() {
+                                Some(demo) => match DemoServer::open(&vfs, demo) {
+                                    Ok(d) => {
+                                        break Some(Connection {
+                                            kind: ConnectionKind::Demo(d),
+                                            state: ClientState::new(),
+                                        })
+                                    }
+                                    Err(e @ DemoServerError::Vfs(_)) => {
+                                        // log the error, skip the missing demo and disconnect if
+                                        // every demo in the loop is missing
                                         console.println(format!("{}", e), time);
 
-                                        match demo_queue.index() {
-                                            Some(0) => break None,
-                                            _ => continue,
+                                        missing += 1;
+                                        if missing >= demo_queue.len() {
+                                            demo_queue.stop();
+                                            break None;
                                         }
                                     }
-                                };
-
-                                break demo_file.as_mut().and_then(|df| {
-                                    match DemoServer::new(df) {
-                                        Ok(d) => Some(Connection {
-                                            kind: ConnectionKind::Demo(d),
-                                            state: ClientState::new(),
-                                        }),
-                                        Err(e) => {
-                                            console.println(format!("{}", e), time);
-                                            demo_queue.reset();
-                                            None
-                                        }
+                                    Err(e) => {
+                                        console.println(format!("{}", e), time);
+                                        demo_queue.stop();
+                                        break None;
                                     }
-                                });
-                            }
+                                },
 
-                            // if there are no more demos in the queue, disconnect
-                            None => break None,
+                                // if there are no demos in the queue, disconnect
+                                None => break None,
+                            }
                         }
-                    },
+                    }
 
                     // covered in first match
-                    Maintain => unreachable!(),
+                    Maintain | Resume => unreachable!(),
                 };
 
                 // don't allow game focus when disconnected
@@ -1406,10 +2237,12 @@ mod systems {
                     (Some(mut conn), Some(new_conn)) => {
                         *conn = new_conn;
                         *conn_state = ConnectionState::SignOn(SignOnStage::Prespawn);
+                        *progress = default();
                     }
                     (None, Some(new_conn)) => {
                         commands.insert_resource(new_conn);
                         *conn_state = ConnectionState::SignOn(SignOnStage::Prespawn);
+                        *progress = default();
                     }
                     (Some(_), None) => {
                         commands.remove_resource::<Connection>();
@@ -1423,6 +2256,144 @@ mod systems {
         Ok(())
     }
 
+    /// Finish the demo being recorded once the connection it was recording has closed.
+    pub fn finish_recording(
+        mut commands: Commands,
+        time: Res<Time<Virtual>>,
+        mut console: ResMut<ConsoleOutput>,
+        mut recorder: ResMut<DemoRecorder>,
+    ) {
+        // a demo started before connecting waits for the connection
+        if recorder.messages() == 0 {
+            return;
+        }
+
+        let time = Duration::from_std(time.elapsed()).unwrap();
+        let text = match recorder.finish() {
+            Ok(()) => format!("Completed demo {}", recorder.path()),
+            Err(e) => format!("Couldn't finish demo {}: {}", recorder.path(), e),
+        };
+        console.println(text, time);
+        commands.remove_resource::<DemoRecorder>();
+    }
+
+    /// Send `setinfo` commands to the server when any of the userinfo cvars change, along with
+    /// `name` and `color` for remote servers that may not understand them.
+    pub fn update_userinfo(
+        registry: Res<Registry>,
+        conn: Option<Res<Connection>>,
+        conn_state: Res<ConnectionState>,
+        socket: Option<Res<SocketThread>>,
+        mut sent: Local<Option<UserInfo>>,
+        mut to_server: EventWriter<ClientMessage>,
+    ) -> Result<(), ClientError> {
+        let connected = matches!(*conn_state, ConnectionState::Connected(_))
+            && conn.map(|c| !c.kind.is_demo()).unwrap_or(false);
+
+        if !connected {
+            // The full userinfo string is sent during signon
+            *sent = None;
+            return Ok(());
+        }
+
+        let userinfo = userinfo_from_cvars(&registry);
+
+        let Some(last) = &*sent else {
+            *sent = Some(userinfo);
+            return Ok(());
+        };
+
+        if *last == userinfo {
+            return Ok(());
+        }
+
+        let mut packet = Vec::new();
+        for (key, value) in userinfo.diff(last) {
+            ClientCmd::StringCmd {
+                cmd: format!("setinfo {} \"{}\"", key, value),
+            }
+            .serialize(&mut packet)?;
+        }
+
+        let renamed = userinfo.name() != last.name() || userinfo.colors() != last.colors();
+        if socket.is_some() && renamed {
+            for cmd in legacy_userinfo_cmds(&userinfo) {
+                cmd.serialize(&mut packet)?;
+            }
+        }
+
+        to_server.send(ClientMessage {
+            client_id: ClientId::LOCAL,
+            packet,
+            kind: MessageKind::Reliable,
+        });
+
+        *sent = Some(userinfo);
+
+        Ok(())
+    }
+
+    /// Disallow cheat cvars while connected to a server without `sv_cheats`, and clear any cvar
+    /// limits set by the server when the connection changes. Demos can't be cheated in, so they
+    /// allow them too.
+    pub fn update_cheat_protection(
+        conn: Option<Res<Connection>>,
+        server: Option<Res<Session>>,
+        mut registry: ResMut<Registry>,
+    ) {
+        if conn.as_ref().map(|c| c.is_added()).unwrap_or(false) || conn.is_none() {
+            if registry.has_cvar_limits() {
+                registry.clear_cvar_limits();
+            }
+        }
+
+        // Remote servers don't tell us whether cheats are enabled, so only allow them locally
+        let allowed = conn.is_none()
+            || conn.as_ref().is_some_and(|c| c.demo().is_some())
+            || (server.is_some() && registry.read_cvar::<u8>("sv_cheats").unwrap_or(0) != 0);
+
+        if registry.cheats_allowed() != allowed {
+            registry.set_cheats_allowed(allowed);
+        }
+    }
+
+    /// Run the virtual clock at the speed set by `host_timescale`. The in-process server ticks by
+    /// a clock of its own, which follows the cvar separately.
+    pub fn update_timescale(registry: Res<Registry>, mut time: ResMut<Time<Virtual>>) {
+        let scale = clock::timescale(&registry);
+        if time.relative_speed() != scale {
+            time.set_relative_speed(scale);
+        }
+    }
+
+    /// Pause a single-player game for as long as the menu is open. The in-process server stops
+    /// while `sv_paused` is set, and tells the client to stop with it, so play carries on exactly
+    /// where it stopped. A game paused some other way is left paused when the menu closes.
+    pub fn update_menu_pause(
+        focus: Res<InputFocus>,
+        server: Option<Res<Session>>,
+        mut registry: ResMut<Registry>,
+        mut paused: Local<bool>,
+    ) {
+        let pause =
+            *focus == InputFocus::Menu && server.is_some_and(|server| server.is_single_player());
+        if pause == *paused {
+            return;
+        }
+
+        let server_paused = registry.read_cvar::<u8>("sv_paused").unwrap_or(0) != 0;
+        if pause {
+            if server_paused {
+                return;
+            }
+            registry.set_cvar("sv_paused", "1").unwrap();
+        } else {
+            registry.set_cvar("sv_paused", "0").unwrap();
+        }
+
+        *paused = pause;
+    }
+
     pub fn set_resolution(
         window: Query<&Window, With<PrimaryWindow>>,
         mut target_resource: ResMut<RenderResolution>,
@@ -1434,33 +2405,164 @@ mod systems {
         }
     }
 
+    /// Finish connecting over a WebSocket once the server accepts or rejects us.
+    #[cfg(any(feature = "websocket", target_arch = "wasm32"))]
+    pub fn finish_websocket_connect(
+        mut commands: Commands,
+        mut pending: ResMut<PendingConnect>,
+        real_time: Res<Time<Real>>,
+        time: Res<Time<Virtual>>,
+        mut focus: ResMut<InputFocus>,
+        mut console: ResMut<ConsoleOutput>,
+    ) {
+        let PendingConnect { transport, give_up } = &mut *pending;
+        let Some(socket) = transport else {
+            return;
+        };
+
+        let accepted = match socket.recv_response(&net::BlockingMode::NonBlocking) {
+            Ok(None) if real_time.elapsed() < *give_up => return,
+            Ok(None) => Err(ClientError::NoResponse),
+            Ok(Some(response)) => check_response(response),
+            Err(e) => Err(e.into()),
+        };
+
+        commands.remove_resource::<PendingConnect>();
+        let flags = match accepted {
+            Ok((_, flags)) => flags,
+            Err(e) => {
+                let time = Duration::from_std(time.elapsed()).unwrap();
+                console.println(format!("{}", e), time);
+                return;
+            }
+        };
+
+        // the server answers on the same socket, so the port it sends is ignored
+        let mut qsock = QSocket::with_transport(Box::new(transport.take().unwrap()));
+        qsock.set_compression(flags.contains(ConnectFlags::COMPRESS));
+        let socket = match SocketThread::spawn(qsock) {
+            Ok(socket) => socket,
+            Err(e) => {
+                let time = Duration::from_std(time.elapsed()).unwrap();
+                console.println(format!("{}", e), time);
+                return;
+            }
+        };
+
+        *focus = InputFocus::Game;
+        commands.insert_resource(socket);
+        commands.insert_resource(Connection::new_server());
+        commands.insert_resource(ConnectionState::SignOn(SignOnStage::Prespawn));
+    }
+
+    /// Whether the server has been silent for longer than `cl_timeout` allows.
+    fn timed_out(registry: &Registry, silence: std::time::Duration) -> bool {
+        let timeout = registry.read_cvar::<f32>("cl_timeout").unwrap_or(0.);
+        timeout > 0. && silence.as_secs_f32() > timeout
+    }
+
+    /// Drop a connection to a server that has stopped responding, saying why in the console.
+    fn drop_connection(
+        reason: &str,
+        commands: &mut Commands,
+        focus: &mut InputFocus,
+        console: &mut ConsoleOutput,
+        time: &Time<Virtual>,
+    ) {
+        let time = Duration::from_std(time.elapsed()).unwrap();
+        warn!("{}", reason);
+        console.println(reason, time);
+        commands.remove_resource::<Connection>();
+        commands.remove_resource::<SocketThread>();
+        *focus = InputFocus::Console;
+    }
+
+    /// Pass messages between the game and the socket of a remote server. The socket waits for
+    /// packets on a thread of its own, so a slow server never holds up the frame.
     pub fn process_network_messages(
-        state: Res<ConnectionState>,
-        mut qsock: ResMut<QSocket>,
+        mut commands: Commands,
+        registry: Res<Registry>,
+        real_time: Res<Time<Real>>,
+        time: Res<Time<Virtual>>,
+        mut focus: ResMut<InputFocus>,
+        mut console: ResMut<ConsoleOutput>,
+        mut socket: ResMut<SocketThread>,
+        mut last_received: Local<std::time::Duration>,
         mut server_events: EventWriter<ServerMessage>,
         mut client_events: EventReader<ClientMessage>,
     ) -> Result<(), NetError> {
-        let blocking_mode = match &*state {
-            // if we're in the game, don't block waiting for messages
-            ConnectionState::Connected(_) => BlockingMode::NonBlocking,
+        let now = real_time.elapsed();
+        if socket.is_added() {
+            *last_received = now;
+        }
 
-            // otherwise, give the server some time to respond
-            // TODO: might make sense to make this a future or something
-            ConnectionState::SignOn(_) => BlockingMode::Timeout(Duration::try_seconds(5).unwrap()),
-        };
+        for event in client_events.read() {
+            socket.send(event.kind, event.packet.clone())?;
+        }
 
-        server_events.send(ServerMessage {
-            client_id: 0,
-            packet: qsock.recv_msg(blocking_mode)?,
-        });
+        while let Some(packet) = socket.try_recv()? {
+            *last_received = now;
+            // the socket doesn't say how each message was sent, and it no longer matters
+            server_events.send(ServerMessage {
+                client_id: ClientId::LOCAL,
+                packet,
+                kind: MessageKind::Reliable,
+            });
+        }
 
-        for event in client_events.read() {
-            match event.kind {
-                MessageKind::Unreliable => qsock.send_msg_unreliable(&event.packet)?,
-                MessageKind::Reliable => qsock.begin_send_msg(&event.packet)?,
-            }
+        if socket.stopped() {
+            drop_connection(
+                "Lost connection to server",
+                &mut commands,
+                &mut focus,
+                &mut console,
+                &time,
+            );
+        } else if timed_out(&registry, now.saturating_sub(*last_received)) {
+            drop_connection(
+                "Server timed out",
+                &mut commands,
+                &mut focus,
+                &mut console,
+                &time,
+            );
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_demo_queue() {
+        let mut queue = DemoQueue::new(vec!["demo1".into(), "demo2".into()]);
+        assert_eq!(queue.next(), Some("demo1"));
+        assert_eq!(queue.next(), Some("demo2"));
+        // the loop starts again after the last demo
+        assert_eq!(queue.next(), Some("demo1"));
+
+        queue.stop();
+        assert_eq!(queue.next(), None);
+        queue.resume();
+        assert_eq!(queue.next(), Some("demo2"));
+
+        queue.reset();
+        assert_eq!(queue.next(), Some("demo1"));
+
+        assert_eq!(DemoQueue::default().next(), None);
+    }
+
+    #[test]
+    fn test_blood_damage() {
+        // a shotgun pellet
+        assert_eq!(blood_damage(73, 8), Some(4));
+        // a lightning bolt
+        assert_eq!(blood_damage(225, 120), Some(30));
+        // an explosion, and a spark off a wall
+        assert_eq!(blood_damage(73, 255), None);
+        assert_eq!(blood_damage(0, 20), None);
+    }
+}
@@ -0,0 +1,247 @@
+//! How far the client has got in joining a server, for the connection screen.
+//!
+//! The sign-on code records each stage the server moves the client through, and the loader counts
+//! off the models and sounds in the precache lists as it loads them, so that the screen can show
+//! something better than a frozen view while a large map loads.
+
+use bevy::{prelude::*, render::extract_resource::ExtractResource};
+
+use crate::common::net::SignOnStage;
+
+/// Width of the progress bar in characters, not including its ends.
+const BAR_WIDTH: usize = 24;
+
+// the ends and middle of the slider in conchars, and its handle which marks the filled part
+const BAR_LEFT: char = '\u{80}';
+const BAR_MIDDLE: char = '\u{81}';
+const BAR_RIGHT: char = '\u{82}';
+const BAR_FILLED: char = '\u{83}';
+
+/// A file being downloaded from the server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DownloadProgress {
+    pub name: String,
+    pub received: usize,
+    /// The size of the file, if the server has said.
+    pub size: Option<usize>,
+}
+
+#[derive(Resource, ExtractResource, Clone, Debug)]
+pub struct ConnectionProgress {
+    /// The address of the server, `local` for a server in the same process, or empty for a
+    /// demo.
+    server: String,
+    /// The server's message, usually the name of the level.
+    message: String,
+    stage: SignOnStage,
+    /// The number of models and sounds that have been loaded.
+    loaded: usize,
+    /// The number of models and sounds in the precache lists.
+    total: usize,
+    /// The resource that is being loaded.
+    loading: Option<String>,
+    download: Option<DownloadProgress>,
+}
+
+impl Default for ConnectionProgress {
+    fn default() -> Self {
+        ConnectionProgress::new("")
+    }
+}
+
+impl ConnectionProgress {
+    pub fn new<S: Into<String>>(server: S) -> ConnectionProgress {
+        ConnectionProgress {
+            server: server.into(),
+            message: String::new(),
+            stage: SignOnStage::Not,
+            loaded: 0,
+            total: 0,
+            loading: None,
+            download: None,
+        }
+    }
+
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    pub fn stage(&self) -> SignOnStage {
+        self.stage
+    }
+
+    pub fn set_stage(&mut self, stage: SignOnStage) {
+        self.stage = stage;
+    }
+
+    pub fn set_message<S: Into<String>>(&mut self, message: S) {
+        self.message = message.into();
+    }
+
+    /// Starts loading the `total` models and sounds that the level needs.
+    pub fn begin_precache(&mut self, total: usize) {
+        self.loaded = 0;
+        self.total = total;
+        self.loading = None;
+    }
+
+    /// Records that the loader has moved on to the resource called `name`.
+    pub fn loading<S: Into<String>>(&mut self, name: S) {
+        if self.loading.is_some() {
+            self.loaded = (self.loaded + 1).min(self.total);
+        }
+        self.loading = Some(name.into());
+    }
+
+    /// Records that every resource in the precache lists has been loaded.
+    pub fn finish_precache(&mut self) {
+        self.loaded = self.total;
+        self.loading = None;
+    }
+
+    pub fn download(&self) -> Option<&DownloadProgress> {
+        self.download.as_ref()
+    }
+
+    pub fn set_download(&mut self, download: Option<DownloadProgress>) {
+        self.download = download;
+    }
+
+    /// The proportion of the precache lists that has been loaded, between 0 and 1.
+    pub fn precache_fraction(&self) -> f32 {
+        match self.total {
+            0 => 0.0,
+            total => self.loaded as f32 / total as f32,
+        }
+    }
+
+    /// The lines of text for the connection screen.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        lines.push(match self.server.as_str() {
+            // demos don't have a server
+            "" => "Loading...".to_owned(),
+            server => format!("Connecting to {}", server),
+        });
+        if !self.message.is_empty() {
+            lines.push(self.message.clone());
+        }
+        lines.push(String::new());
+
+        lines.push(
+            match self.stage {
+                SignOnStage::Not => "Waiting for server",
+                SignOnStage::Prespawn => "Loading level",
+                SignOnStage::ClientInfo => "Sending player info",
+                SignOnStage::Begin => "Spawning",
+                SignOnStage::Done => "Entering game",
+            }
+            .to_owned(),
+        );
+
+        if self.total > 0 {
+            lines.push(format!(
+                "{} {}/{}",
+                bar(self.precache_fraction()),
+                self.loaded,
+                self.total
+            ));
+            if let Some(name) = &self.loading {
+                lines.push(name.clone());
+            }
+        }
+
+        if let Some(DownloadProgress {
+            name,
+            received,
+            size,
+        }) = &self.download
+        {
+            lines.push(String::new());
+            lines.push(format!("Downloading {}", name));
+            lines.push(match size {
+                Some(size) if *size > 0 => format!(
+                    "{} {}k/{}k",
+                    bar(*received as f32 / *size as f32),
+                    received / 1024,
+                    size / 1024
+                ),
+                _ => format!("{}k", received / 1024),
+            });
+        }
+
+        lines
+    }
+}
+
+/// A progress bar drawn with the slider from the menus.
+fn bar(fraction: f32) -> String {
+    let filled = ((fraction.clamp(0.0, 1.0) * BAR_WIDTH as f32).round() as usize).min(BAR_WIDTH);
+
+    let mut bar = String::with_capacity(BAR_WIDTH + 2);
+    bar.push(BAR_LEFT);
+    bar.extend((0..BAR_WIDTH).map(|i| if i < filled { BAR_FILLED } else { BAR_MIDDLE }));
+    bar.push(BAR_RIGHT);
+    bar
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_precache_progress() {
+        let mut progress = ConnectionProgress::new("192.168.1.2:26000");
+        progress.set_stage(SignOnStage::Prespawn);
+        progress.set_message("The Slipgate Complex");
+        progress.begin_precache(4);
+        assert_eq!(progress.precache_fraction(), 0.0);
+
+        progress.loading("maps/e1m1.bsp");
+        progress.loading("progs/player.mdl");
+        assert_eq!(progress.precache_fraction(), 0.25);
+
+        let lines = progress.lines();
+        assert_eq!(lines[0], "Connecting to 192.168.1.2:26000");
+        assert_eq!(lines[1], "The Slipgate Complex");
+        assert_eq!(lines[3], "Loading level");
+        assert!(lines[4].ends_with(" 1/4"));
+        assert_eq!(lines[5], "progs/player.mdl");
+
+        progress.finish_precache();
+        assert_eq!(progress.precache_fraction(), 1.0);
+        assert!(progress.lines()[4].ends_with(" 4/4"));
+    }
+
+    #[test]
+    fn test_bar() {
+        let empty = bar(0.0);
+        assert_eq!(empty.chars().count(), BAR_WIDTH + 2);
+        assert!(!empty.contains(BAR_FILLED));
+
+        let half = bar(0.5);
+        assert_eq!(
+            half.chars().filter(|c| *c == BAR_FILLED).count(),
+            BAR_WIDTH / 2
+        );
+
+        // out of range values are clamped
+        assert_eq!(bar(2.0), bar(1.0));
+    }
+
+    #[test]
+    fn test_download_lines() {
+        let mut progress = ConnectionProgress::default();
+        progress.set_download(Some(DownloadProgress {
+            name: "maps/custom.bsp".into(),
+            received: 2048,
+            size: Some(8192),
+        }));
+
+        let lines = progress.lines();
+        assert_eq!(lines[0], "Loading...");
+        assert!(lines.contains(&"Downloading maps/custom.bsp".to_owned()));
+        assert!(lines.last().unwrap().ends_with(" 2k/8k"));
+    }
+}
@@ -20,25 +20,115 @@
 
 use bevy::prelude::*;
 
-use crate::common::console::RegisterCmdExt;
+use serde_lexpr::Value;
+
+use crate::common::console::{Cvar, RegisterCmdExt};
+
+fn set_msaa_samples(In(samples): In<Value>, msaa: Option<ResMut<Msaa>>) {
+    let Some(mut msaa) = msaa else {
+        return;
+    };
+
+    let new_msaa = match serde_lexpr::from_value::<u32>(&samples) {
+        Ok(1) => Msaa::Off,
+        Ok(2) => Msaa::Sample2,
+        Ok(4) => Msaa::Sample4,
+        Ok(8) => Msaa::Sample8,
+        _ => {
+            warn!("r_msaa_samples must be 1, 2, 4 or 8");
+            return;
+        }
+    };
+
+    // only mark it as changed when it is, since every pipeline that draws the world is rebuilt
+    if *msaa != new_msaa {
+        *msaa = new_msaa;
+    }
+}
 
 pub fn register_cvars(app: &mut App) {
     // TODO: Implement this
     app.cvar(
         "r_lightmap",
-        "0",
+        Cvar::new("0").cheat(),
         "only render the lightmap, and not the main texture",
     )
-    // TODO: Re-implement MSAA
+    // TODO: Implement this
+    .cvar(
+        "r_fullbright",
+        Cvar::new("0").cheat(),
+        "render the world without lighting",
+    )
+    .cvar(
+        "r_fullbrights",
+        "1",
+        "draw the glowing parts of textures and skins at full brightness, whatever the light",
+    )
+    .cvar(
+        "r_drawviewmodel",
+        "1",
+        "draw the weapon that the player is holding",
+    )
+    .cvar(
+        "r_dynamic",
+        "1",
+        "light the world with dynamic lights from explosions, rockets and muzzle flashes",
+    )
+    .cvar(
+        "r_lerplightstyles",
+        "1",
+        "blend animated lights between their steps: 0 steps as in Quake, 1 blends all but sudden changes so flickering lights stay sharp, 2 always blends",
+    )
+    .cvar(
+        "r_decals",
+        "1",
+        "leave bullet holes, blood and scorch marks on the walls where they hit",
+    )
+    .cvar(
+        "r_speeds",
+        "0",
+        "show how many polygons and draw calls each frame takes, and how long each pass takes",
+    )
+    .cvar(
+        "r_showbboxes",
+        Cvar::new("0").cheat(),
+        "draw the bounding boxes of solid entities (listen server only) and the boxes that entities are culled with",
+    )
+    .cvar(
+        "r_showtris",
+        Cvar::new("0").cheat(),
+        "outline the triangles of the world and entities, including those hidden behind walls",
+    )
     .cvar(
+        "r_showtriggers",
+        Cvar::new("0").cheat(),
+        "draw trigger volumes (listen server only)",
+    )
+    .cvar_on_set(
         "r_msaa_samples",
         "1",
-        "set the multi-sampled anti-aliasing sample count",
+        set_msaa_samples,
+        "set the multi-sampled anti-aliasing sample count (1, 2, 4 or 8)",
+    )
+    .cvar(
+        "r_scale",
+        "1",
+        "draw the world at this fraction of the screen resolution and scale it up unfiltered, for a low-resolution look (0.25 gives 320x200 at 1280x800)",
     )
     .cvar(
         "r_sky_scollspeed",
-        "32",
-        "Skybox texture scroll speed (in texels)",
+        "8",
+        "how fast the sky scrolls, in texels per second (the clouds move twice as fast)",
+    )
+    .cvar(
+        "gl_overbright",
+        "1",
+        "let lightmaps brighten surfaces up to twice their texture color, as the maps were lit for",
+    )
+    .cvar(
+        "gl_cshiftpercent",
+        "100",
+        "how strongly damage, pickups, powerups and liquids tint the screen, in percent",
     )
     .cvar(
         "post_blendmode",
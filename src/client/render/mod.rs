@@ -35,18 +35,21 @@
 ///     - `QuadPipeline`
 ///     - `GlyphPipeline`
 ///   - Output: `DeferredPassTarget`
+/// - Blit, upscaling the world when it's drawn at a lower resolution with `r_scale`
+///   - Inputs:
+///     - `BlitPipeline`
+///   - Output: `ViewTarget`
 /// - Final pass
 ///   - Inputs:
 ///     - `PostProcessPipeline`
 ///   - Output: `FinalPassTarget`
-/// - Blit to swap chain
-///   - Inputs:
-///     - `BlitPipeline`
-///   - Output: `SwapChainTarget`
 mod cvars;
 mod error;
 pub mod palette;
 mod pipeline;
+mod preset;
+mod screenshot;
+mod speeds;
 mod target;
 mod ui;
 mod uniform;
@@ -72,12 +75,13 @@ use bevy::{
             TextureView,
         },
         renderer::{RenderDevice, RenderQueue},
-        view::ViewTarget,
+        view::{ViewTarget, VisibilitySystems},
         Render, RenderApp, RenderSet,
     },
     ui::graph::NodeUi,
     window::PrimaryWindow,
 };
+use cgmath::Deg;
 pub use cvars::register_cvars;
 pub use error::{RenderError, RenderErrorKind};
 pub use palette::Palette;
@@ -85,11 +89,13 @@ use parking_lot::RwLock;
 pub use pipeline::Pipeline;
 pub use postprocess::PostProcessBindGroup;
 use serde::{Deserialize, Serialize};
+pub use speeds::{RenderSpeeds, SpeedsPass, SpeedsReport};
 pub use target::{PreferredFormat, RenderTarget, RenderTargetResolve};
-pub use ui::{hud::HudState, UiRenderer, UiState};
+pub use ui::{hud::HudState, HideUi, UiRenderer, UiState};
 pub use world::{
+    debug::DebugDraw,
     deferred::{DeferredRenderer, DeferredUniforms, PointLight},
-    Camera,
+    Camera, HeldWeapon,
 };
 
 use std::{
@@ -108,31 +114,44 @@ use crate::{
             uniform::DynamicUniformBuffer,
             world::{
                 alias::AliasPipeline,
+                blit::BlitPipeline,
                 brush::BrushPipeline,
+                debug::{self, DebugBoxPipeline},
+                decal::DecalPipeline,
                 deferred::DeferredPipeline,
-                particle::ParticlePipeline,
+                particle::{self, ParticlePipeline},
                 postprocess::{self, PostProcessPipeline, PostProcessVars},
+                showtris::{AliasShowTrisPipeline, BrushShowTrisPipeline},
                 sprite::SpritePipeline,
                 EntityUniforms,
             },
         },
+        sound::SoundIndicators,
+        view::{FreeCamera, ViewModel},
+        world_text::WorldText,
     },
     common::{console::Registry, vfs::Vfs, wad::Wad},
+    server::DebugBounds,
 };
 
 use self::{
+    screenshot::{ScreenshotLabel, ScreenshotNode, ScreenshotReadbacks, ScreenshotRequests},
     target::{InitPass, InitPassLabel},
     ui::{UiPass, UiPassLabel},
     world::{
+        blit::{BlitPass, BlitPassLabel},
         deferred::{DeferredPass, DeferredPassLabel},
         extract_world_renderer,
         postprocess::{PostProcessPass, PostProcessPassLabel},
+        prepare_player_skins, WorldRenderer,
     },
 };
 
 use failure::Error;
 
-use super::{state::ClientState, Connection, ConnectionKind, ConnectionState};
+use super::{
+    progress::ConnectionProgress, state::ClientState, Connection, ConnectionKind, ConnectionState,
+};
 
 pub struct SeismonRenderPlugin;
 
@@ -157,15 +176,33 @@ impl Plugin for SeismonRenderPlugin {
             ExtractResourcePlugin::<HudVars>::default(),
             ExtractResourcePlugin::<PostProcessVars>::default(),
             ExtractResourcePlugin::<ConnectionState>::default(),
+            ExtractResourcePlugin::<ConnectionProgress>::default(),
+            ExtractResourcePlugin::<DebugDraw>::default(),
+            ExtractResourcePlugin::<SoundIndicators>::default(),
+            ExtractResourcePlugin::<WorldText>::default(),
+            ExtractResourcePlugin::<HideUi>::default(),
             // TODO: Do all loading on the main thread (this is currently just for the palette and gfx wad)
             ExtractResourcePlugin::<Vfs>::default(),
         ));
 
+        app.init_resource::<DebugDraw>()
+            .init_resource::<HideUi>()
+            .init_resource::<ui::HiddenUiNodes>()
+            .add_systems(First, (debug::clear_debug_draw, ui::restore_ui_nodes))
+            .add_systems(Update, systems::draw_debug_bounds)
+            .add_systems(
+                PostUpdate,
+                ui::hide_ui_nodes.before(VisibilitySystems::VisibilityPropagate),
+            );
+
         register_cvars(app);
+        preset::register_preset_commands(app);
+        screenshot::register_screenshot_command(app);
 
         extract_now::<Menu, Menu>(app);
         extract_now::<Vfs, Vfs>(app);
         extract_now::<ConnectionState, ConnectionState>(app);
+        extract_now::<ScreenshotRequests, ScreenshotRequests>(app);
     }
 
     fn finish(&self, app: &mut bevy::prelude::App) {
@@ -176,6 +213,8 @@ impl Plugin for SeismonRenderPlugin {
         };
 
         render_app
+            .init_resource::<RenderSpeeds>()
+            .init_resource::<ScreenshotReadbacks>()
             .init_resource::<PostProcessPipeline>()
             .init_resource::<SpecializedRenderPipelines<PostProcessPipeline>>()
             .add_systems(
@@ -185,6 +224,9 @@ impl Plugin for SeismonRenderPlugin {
                         not(resource_exists::<GraphicsState>)
                             .or_else(resource_changed::<RenderResolution>),
                     ),
+                    systems::update_sample_count.run_if(
+                        resource_exists::<GraphicsState>.and_then(resource_changed::<Msaa>),
+                    ),
                     systems::create_menu_renderer.run_if(
                         resource_exists::<GraphicsState>.and_then(
                             not(resource_exists::<UiRenderer>).or_else(resource_changed::<Menu>),
@@ -194,31 +236,52 @@ impl Plugin for SeismonRenderPlugin {
                         resource_changed::<ConnectionState>
                             .and_then(resource_exists::<GraphicsState>),
                     ),
+                    prepare_player_skins.run_if(
+                        resource_exists::<WorldRenderer>.and_then(resource_exists::<RenderState>),
+                    ),
+                    particle::prepare_particles.run_if(
+                        resource_exists::<GraphicsState>.and_then(resource_exists::<RenderState>),
+                    ),
                 )
                     .chain()
                     .in_set(RenderSet::Prepare),
             )
+            .add_systems(
+                Render,
+                screenshot::read_back_screenshots.in_set(RenderSet::Cleanup),
+            )
             .add_render_graph_node::<ViewNodeRunner<InitPass>>(Core3d, InitPassLabel)
             .add_render_graph_node::<ViewNodeRunner<DeferredPass>>(Core3d, DeferredPassLabel)
+            .add_render_graph_node::<ViewNodeRunner<BlitPass>>(Core3d, BlitPassLabel)
             .add_render_graph_node::<ViewNodeRunner<PostProcessPass>>(Core3d, PostProcessPassLabel)
             .add_render_graph_node::<ViewNodeRunner<UiPass>>(Core3d, UiPassLabel)
+            .add_render_graph_node::<ViewNodeRunner<ScreenshotNode>>(Core3d, ScreenshotLabel)
             .add_render_graph_edges(
                 Core3d,
                 (
                     Node3d::MainOpaquePass,
                     InitPassLabel,
                     DeferredPassLabel,
+                    BlitPassLabel,
                     PostProcessPassLabel,
                     Node3d::EndMainPass,
                 ),
             )
-            .add_render_graph_edges(Core3d, (NodeUi::UiPass, UiPassLabel, Node3d::Upscaling));
+            .add_render_graph_edges(Core3d, (NodeUi::UiPass, UiPassLabel, Node3d::Upscaling))
+            .add_render_graph_edges(
+                Core3d,
+                (
+                    Node3d::EndMainPassPostProcessing,
+                    ScreenshotLabel,
+                    NodeUi::UiPass,
+                ),
+            );
     }
 }
 
 const DIFFUSE_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
 const FULLBRIGHT_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
-const LIGHTMAP_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+const LIGHTMAP_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
 
 /// Create a `wgpu::TextureDescriptor` appropriate for the provided texture data.
 pub fn texture_descriptor<'a>(
@@ -384,7 +447,7 @@ impl FromWorld for RenderResolution {
 
 pub enum RenderConnectionKind {
     Server,
-    Demo,
+    Demo { freecam: Option<FreeCamera> },
 }
 
 #[derive(Resource)]
@@ -394,6 +457,42 @@ pub struct RenderState {
     kind: RenderConnectionKind,
 }
 
+impl RenderState {
+    /// The camera the world is drawn from. Demos are seen from the recorded view angles, unless
+    /// the free camera is flying.
+    pub fn camera(&self, aspect: f32, fov: Deg<f32>) -> Camera {
+        match &self.kind {
+            RenderConnectionKind::Demo {
+                freecam: Some(freecam),
+            } => freecam.camera(aspect, fov),
+            RenderConnectionKind::Demo { freecam: None } => self.state.demo_camera(aspect, fov),
+            RenderConnectionKind::Server => self.state.camera(aspect, fov),
+        }
+    }
+
+    /// The weapon to draw in front of the camera. There's none when the camera has left the
+    /// player's eyes, for the chase camera or a demo's free camera.
+    pub fn viewmodel(&self, vars: &RenderVars) -> Option<ViewModel> {
+        let freecam = matches!(self.kind, RenderConnectionKind::Demo { freecam: Some(_) });
+        if vars.draw_viewmodel == 0 || vars.chase_active != 0. || freecam {
+            return None;
+        }
+
+        self.state.viewmodel()
+    }
+
+    /// The weapon held by the view entity, for when the view entity is drawn with a model that
+    /// has a tag to hold it by. No other entity's weapon is ever known.
+    pub fn held_weapon(&self) -> Option<HeldWeapon> {
+        let viewmodel = self.state.viewmodel()?;
+
+        Some(HeldWeapon {
+            entity_id: self.state.view_entity_id(),
+            model_id: viewmodel.model_id,
+        })
+    }
+}
+
 impl ExtractResource for RenderState {
     type Source = Connection;
 
@@ -404,7 +503,10 @@ impl ExtractResource for RenderState {
             state: state.clone(),
             kind: match kind {
                 ConnectionKind::Server { .. } => RenderConnectionKind::Server,
-                ConnectionKind::Demo(_) => RenderConnectionKind::Demo,
+                ConnectionKind::Demo(demo) => RenderConnectionKind::Demo {
+                    freecam: demo.freecam().copied(),
+                },
+                ConnectionKind::Replay { .. } => RenderConnectionKind::Demo { freecam: None },
             },
         }
     }
@@ -428,7 +530,13 @@ pub struct GraphicsState {
     brush_pipeline: BrushPipeline,
     sprite_pipeline: SpritePipeline,
     deferred_pipeline: DeferredPipeline,
+    blit_pipeline: BlitPipeline,
+    screenshot_pipeline: BlitPipeline,
     particle_pipeline: ParticlePipeline,
+    decal_pipeline: DecalPipeline,
+    debug_box_pipeline: DebugBoxPipeline,
+    brush_showtris_pipeline: BrushShowTrisPipeline,
+    alias_showtris_pipeline: AliasShowTrisPipeline,
     glyph_pipeline: GlyphPipeline,
     quad_pipeline: QuadPipeline,
 
@@ -437,6 +545,11 @@ pub struct GraphicsState {
 
     palette: Palette,
     gfx_wad: Wad,
+
+    diffuse_format: wgpu::TextureFormat,
+    normal_format: wgpu::TextureFormat,
+    /// The MSAA sample count of the G-buffer that the world is drawn into.
+    sample_count: u32,
 }
 
 thread_local! {
@@ -555,7 +668,13 @@ impl GraphicsState {
             brush_pipeline,
             sprite_pipeline,
             deferred_pipeline,
+            blit_pipeline,
+            screenshot_pipeline,
             particle_pipeline,
+            decal_pipeline,
+            debug_box_pipeline,
+            brush_showtris_pipeline,
+            alias_showtris_pipeline,
             quad_pipeline,
             glyph_pipeline,
         ) = COMPILER.with_borrow_mut(|compiler| {
@@ -592,17 +711,56 @@ impl GraphicsState {
                 sample_count,
                 &palette,
             );
+            let decal_pipeline = DecalPipeline::new(
+                device,
+                &queue,
+                compiler,
+                diffuse_format,
+                normal_format,
+                sample_count,
+            );
+            let debug_box_pipeline = DebugBoxPipeline::new(
+                device,
+                compiler,
+                diffuse_format,
+                normal_format,
+                sample_count,
+            );
+            let brush_showtris_pipeline = BrushShowTrisPipeline::new(
+                device,
+                compiler,
+                diffuse_format,
+                normal_format,
+                sample_count,
+            );
+            let alias_showtris_pipeline = AliasShowTrisPipeline::new(
+                device,
+                compiler,
+                diffuse_format,
+                normal_format,
+                sample_count,
+            );
             let deferred_pipeline =
                 DeferredPipeline::new(device, compiler, diffuse_format, sample_count);
-            let quad_pipeline = QuadPipeline::new(device, compiler, diffuse_format, sample_count);
-            let glyph_pipeline = GlyphPipeline::new(device, compiler, diffuse_format, sample_count);
+            let blit_pipeline = BlitPipeline::new(device, compiler, diffuse_format);
+            let screenshot_pipeline =
+                BlitPipeline::new(device, compiler, screenshot::SCREENSHOT_FORMAT);
+            // the UI is drawn over the resolved image
+            let quad_pipeline = QuadPipeline::new(device, compiler, diffuse_format, 1);
+            let glyph_pipeline = GlyphPipeline::new(device, compiler, diffuse_format, 1);
 
             (
                 alias_pipeline,
                 brush_pipeline,
                 sprite_pipeline,
                 deferred_pipeline,
+                blit_pipeline,
+                screenshot_pipeline,
                 particle_pipeline,
+                decal_pipeline,
+                debug_box_pipeline,
+                brush_showtris_pipeline,
+                alias_showtris_pipeline,
                 quad_pipeline,
                 glyph_pipeline,
             )
@@ -615,7 +773,7 @@ impl GraphicsState {
             1,
             1,
             &TextureData::Lightmap(LightmapData {
-                lightmap: (&[0xFF][..]).into(),
+                lightmap: (&[0xFF; 4][..]).into(),
             }),
         );
         let default_lightmap_view = default_lightmap.create_view(&Default::default());
@@ -631,7 +789,13 @@ impl GraphicsState {
             brush_pipeline,
             sprite_pipeline,
             deferred_pipeline,
+            blit_pipeline,
+            screenshot_pipeline,
             particle_pipeline,
+            decal_pipeline,
+            debug_box_pipeline,
+            brush_showtris_pipeline,
+            alias_showtris_pipeline,
             glyph_pipeline,
             quad_pipeline,
 
@@ -643,9 +807,95 @@ impl GraphicsState {
             default_lightmap_view,
             palette,
             gfx_wad,
+
+            diffuse_format,
+            normal_format,
+            sample_count,
         })
     }
 
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    /// Rebuild the pipelines that draw into or read from the G-buffer for a new MSAA sample
+    /// count.
+    pub fn set_sample_count(&mut self, device: &RenderDevice, sample_count: u32) {
+        if sample_count == self.sample_count {
+            return;
+        }
+
+        let diffuse_format = self.diffuse_format;
+        let normal_format = self.normal_format;
+        COMPILER.with_borrow_mut(|compiler| {
+            self.alias_pipeline.rebuild(
+                device,
+                compiler,
+                diffuse_format,
+                normal_format,
+                &self.world_bind_group_layouts,
+                sample_count,
+            );
+            self.brush_pipeline.rebuild(
+                device,
+                compiler,
+                diffuse_format,
+                normal_format,
+                &self.world_bind_group_layouts,
+                sample_count,
+            );
+            self.sprite_pipeline.rebuild(
+                device,
+                compiler,
+                diffuse_format,
+                normal_format,
+                &self.world_bind_group_layouts,
+                sample_count,
+            );
+            self.particle_pipeline.rebuild(
+                device,
+                compiler,
+                diffuse_format,
+                normal_format,
+                sample_count,
+            );
+            self.decal_pipeline.rebuild(
+                device,
+                compiler,
+                diffuse_format,
+                normal_format,
+                sample_count,
+            );
+            self.debug_box_pipeline.rebuild(
+                device,
+                compiler,
+                diffuse_format,
+                normal_format,
+                sample_count,
+            );
+            self.brush_showtris_pipeline.rebuild(
+                device,
+                compiler,
+                diffuse_format,
+                normal_format,
+                sample_count,
+            );
+            self.alias_showtris_pipeline.rebuild(
+                device,
+                compiler,
+                diffuse_format,
+                normal_format,
+                sample_count,
+            );
+            // reading a multisampled G-buffer needs different bindings, so the deferred pipeline
+            // can't just be rebuilt with its old layout
+            self.deferred_pipeline =
+                DeferredPipeline::new(device, compiler, diffuse_format, sample_count);
+        });
+
+        self.sample_count = sample_count;
+    }
+
     pub fn create_texture<'a>(
         &self,
         device: &RenderDevice,
@@ -720,10 +970,39 @@ impl GraphicsState {
         &self.deferred_pipeline
     }
 
+    pub fn blit_pipeline(&self) -> &BlitPipeline {
+        &self.blit_pipeline
+    }
+
+    /// Converts the view to 8-bit sRGB for screenshots.
+    pub fn screenshot_pipeline(&self) -> &BlitPipeline {
+        &self.screenshot_pipeline
+    }
+
     pub fn particle_pipeline(&self) -> &ParticlePipeline {
         &self.particle_pipeline
     }
 
+    pub fn particle_pipeline_mut(&mut self) -> &mut ParticlePipeline {
+        &mut self.particle_pipeline
+    }
+
+    pub fn decal_pipeline(&self) -> &DecalPipeline {
+        &self.decal_pipeline
+    }
+
+    pub fn debug_box_pipeline(&self) -> &DebugBoxPipeline {
+        &self.debug_box_pipeline
+    }
+
+    pub fn brush_showtris_pipeline(&self) -> &BrushShowTrisPipeline {
+        &self.brush_showtris_pipeline
+    }
+
+    pub fn alias_showtris_pipeline(&self) -> &AliasShowTrisPipeline {
+        &self.alias_showtris_pipeline
+    }
+
     pub fn glyph_pipeline(&self) -> &GlyphPipeline {
         &self.glyph_pipeline
     }
@@ -746,10 +1025,32 @@ pub struct RenderVars {
     pub fov: f32,
     #[serde(rename(deserialize = "r_lightmap"))]
     pub lightmap: u8,
+    #[serde(rename(deserialize = "r_fullbrights"))]
+    pub fullbrights: u8,
+    #[serde(rename(deserialize = "gl_overbright"))]
+    pub overbright: u8,
     #[serde(rename(deserialize = "r_sky_scollspeed"))]
     pub sky_scroll_speed: f32,
-    #[serde(rename(deserialize = "r_msaa_samples"))]
-    pub msaa_samples: u32,
+    #[serde(rename(deserialize = "r_drawviewmodel"))]
+    pub draw_viewmodel: u8,
+    #[serde(rename(deserialize = "r_dynamic"))]
+    pub dynamic_lights: u8,
+    #[serde(rename(deserialize = "r_lerplightstyles"))]
+    pub lerp_lightstyles: u8,
+    #[serde(rename(deserialize = "r_speeds"))]
+    pub speeds: u8,
+    #[serde(rename(deserialize = "r_showtris"))]
+    pub show_tris: u8,
+    #[serde(rename(deserialize = "r_showbboxes"))]
+    pub show_bboxes: u8,
+    #[serde(rename(deserialize = "r_decals"))]
+    pub decals: u8,
+    /// The fraction of the screen resolution that the world is drawn at.
+    #[serde(rename(deserialize = "r_scale"))]
+    pub scale: f32,
+    pub chase_active: f32,
+    #[serde(rename(deserialize = "gl_cshiftpercent"))]
+    pub cshift_percent: f32,
 }
 
 impl Default for RenderVars {
@@ -757,8 +1058,19 @@ impl Default for RenderVars {
         Self {
             fov: 90.,
             lightmap: 0,
-            sky_scroll_speed: 32.,
-            msaa_samples: 1,
+            fullbrights: 1,
+            overbright: 1,
+            sky_scroll_speed: 8.,
+            draw_viewmodel: 1,
+            dynamic_lights: 1,
+            lerp_lightstyles: 1,
+            speeds: 0,
+            show_tris: 0,
+            show_bboxes: 0,
+            decals: 1,
+            scale: 1.,
+            chase_active: 0.,
+            cshift_percent: 100.,
         }
     }
 }
@@ -774,15 +1086,31 @@ impl ExtractResource for RenderVars {
 mod systems {
     use super::*;
 
+    const BBOX_COLOR: [f32; 4] = [0.25, 1.0, 0.25, 0.0];
+    const TRIGGER_COLOR: [f32; 4] = [1.0, 0.5, 0.0, 0.2];
+
+    pub fn draw_debug_bounds(bounds: Res<DebugBounds>, mut draw: ResMut<DebugDraw>) {
+        for debug_box in &bounds.boxes {
+            let color = if debug_box.trigger {
+                TRIGGER_COLOR
+            } else {
+                BBOX_COLOR
+            };
+            draw.aabb(debug_box.mins, debug_box.maxs, color);
+        }
+    }
+
     pub fn create_graphics_state(
         targets: Query<&ViewTarget, With<Camera3d>>,
         mut commands: Commands,
         device: Res<RenderDevice>,
         queue: Res<RenderQueue>,
         vfs: Res<Vfs>,
-        render_vars: Res<RenderVars>,
+        msaa: Res<Msaa>,
     ) {
-        let sample_count = render_vars.msaa_samples;
+        // the G-buffer is made up of Bevy's view target and prepass textures, so it has the same
+        // sample count as them
+        let sample_count = msaa.samples();
 
         if let Ok(view_target) = targets.get_single() {
             match GraphicsState::new(&*device, &*queue, view_target, sample_count, &*vfs) {
@@ -796,6 +1124,14 @@ mod systems {
         }
     }
 
+    pub fn update_sample_count(
+        mut state: ResMut<GraphicsState>,
+        device: Res<RenderDevice>,
+        msaa: Res<Msaa>,
+    ) {
+        state.set_sample_count(&device, msaa.samples());
+    }
+
     pub fn create_menu_renderer(
         mut commands: Commands,
         state: Option<Res<GraphicsState>>,
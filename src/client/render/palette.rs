@@ -2,7 +2,7 @@ use std::io::BufReader;
 
 use crate::{
     client::render::{DiffuseData, FullbrightData},
-    common::vfs::Vfs,
+    common::{net::PlayerColor, vfs::Vfs},
 };
 
 use beef::Cow;
@@ -19,6 +19,11 @@ use byteorder::ReadBytesExt;
 use futures::AsyncReadExt;
 use wgpu::{Extent3d, TextureUsages};
 
+/// The first index of the palette row that a player's shirt color replaces in their skin.
+const TOP_RANGE: u8 = 16;
+/// The first index of the palette row that a player's pants color replaces in their skin.
+const BOTTOM_RANGE: u8 = 96;
+
 #[derive(Default)]
 struct PaletteLoader;
 
@@ -187,3 +192,37 @@ impl Palette {
         )
     }
 }
+
+/// Maps each palette index of a player's skin to the index it's drawn with in `colors`. The
+/// shirt and pants rows are replaced by the rows of the player's colors, and everything else is
+/// left alone.
+pub fn player_translation(colors: PlayerColor) -> [u8; 256] {
+    let mut table = std::array::from_fn(|i| i as u8);
+    for (range, color) in [(TOP_RANGE, colors.top()), (BOTTOM_RANGE, colors.bottom())] {
+        let row = (color & 0x0F) * 16;
+        for i in 0..16 {
+            // the rows in the second half of the palette run the other way
+            table[(range + i) as usize] = if row < 128 { row + i } else { row + 15 - i };
+        }
+    }
+
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_player_translation() {
+        // the colors of the skin itself change nothing
+        let table = player_translation(PlayerColor::new(1, 6));
+        assert!(table.iter().enumerate().all(|(i, t)| i == *t as usize));
+
+        let table = player_translation(PlayerColor::new(13, 4));
+        assert_eq!(table[TOP_RANGE as usize], 13 * 16 + 15);
+        assert_eq!(table[TOP_RANGE as usize + 15], 13 * 16);
+        assert_eq!(table[BOTTOM_RANGE as usize + 3], 4 * 16 + 3);
+        assert_eq!(table[0xFF], 0xFF);
+    }
+}
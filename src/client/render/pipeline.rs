@@ -32,6 +32,28 @@ use wgpu::BindGroupLayoutEntry;
 
 use crate::common::util::{any_as_bytes, Pod};
 
+/// Files that shaders can `#include`, by name.
+const SHADER_INCLUDES: &[(&str, &str)] = &[(
+    "dither.glsl",
+    include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/dither.glsl")),
+)];
+
+fn resolve_include(
+    name: &str,
+    _kind: shaderc::IncludeType,
+    _from: &str,
+    _depth: usize,
+) -> shaderc::IncludeCallbackResult {
+    SHADER_INCLUDES
+        .iter()
+        .find(|(include, _)| *include == name)
+        .map(|(include, content)| shaderc::ResolvedInclude {
+            resolved_name: include.to_string(),
+            content: content.to_string(),
+        })
+        .ok_or_else(|| format!("no shader include named {}", name))
+}
+
 /// The `Pipeline` trait, which allows render pipelines to be defined more-or-less declaratively.
 
 fn create_shader<S>(
@@ -40,13 +62,19 @@ fn create_shader<S>(
     name: S,
     kind: shaderc::ShaderKind,
     source: S,
+    defines: &[(&str, &str)],
 ) -> wgpu::ShaderModule
 where
     S: AsRef<str>,
 {
     debug!("creating shader {}", name.as_ref());
+    let mut options = shaderc::CompileOptions::new().unwrap();
+    options.set_include_callback(resolve_include);
+    for (name, value) in defines {
+        options.add_macro_definition(name, Some(value));
+    }
     let spirv = compiler
-        .compile_into_spirv(source.as_ref(), kind, name.as_ref(), "main", None)
+        .compile_into_spirv(source.as_ref(), kind, name.as_ref(), "main", Some(&options))
         .unwrap();
     device.create_shader_module(wgpu::ShaderModuleDescriptor {
         label: Some(name.as_ref()),
@@ -85,6 +113,19 @@ pub trait Pipeline {
     /// The `BindGroupLayoutDescriptor`s describing the bindings used in the pipeline.
     fn bind_group_layout_descriptors() -> Vec<Vec<BindGroupLayoutEntry>>;
 
+    /// The `BindGroupLayoutDescriptor`s describing the bindings used in the pipeline, for
+    /// pipelines whose bindings depend on their arguments.
+    fn bind_group_layout_descriptors_with_args(
+        _args: &Self::Args,
+    ) -> Vec<Vec<BindGroupLayoutEntry>> {
+        Self::bind_group_layout_descriptors()
+    }
+
+    /// Preprocessor macros defined when compiling the pipeline's shaders.
+    fn shader_defines(_args: &Self::Args) -> Vec<(&'static str, &'static str)> {
+        Vec::new()
+    }
+
     /// The GLSL source of the pipeline's vertex shader.
     fn vertex_shader() -> &'static str;
 
@@ -197,7 +238,7 @@ pub trait Pipeline {
         Self::validate_push_constant_types(device.limits());
 
         info!("Creating {} pipeline", Self::name());
-        let bind_group_layouts = Self::bind_group_layout_descriptors()
+        let bind_group_layouts = Self::bind_group_layout_descriptors_with_args(&args)
             .iter()
             .map(|desc| device.create_bind_group_layout(None, desc))
             .collect::<Vec<_>>();
@@ -231,6 +272,7 @@ pub trait Pipeline {
             format!("{}.vert", Self::name()).as_str(),
             shaderc::ShaderKind::Vertex,
             Self::vertex_shader(),
+            &Self::shader_defines(&args),
         );
         let fragment_shader = create_shader(
             device,
@@ -238,6 +280,7 @@ pub trait Pipeline {
             format!("{}.frag", Self::name()).as_str(),
             shaderc::ShaderKind::Fragment,
             Self::fragment_shader(),
+            &Self::shader_defines(&args),
         );
 
         info!("create_render_pipeline");
@@ -285,10 +328,7 @@ pub trait Pipeline {
                 .into_iter()
                 .map(BindGroupLayout::value)
                 .collect::<Vec<_>>(),
-            push_constant_ranges: &[
-                Self::vertex_push_constant_range(),
-                Self::fragment_push_constant_range(),
-            ],
+            push_constant_ranges: &Self::push_constant_ranges(),
         });
         let vertex_shader = create_shader(
             device,
@@ -296,6 +336,7 @@ pub trait Pipeline {
             format!("{}.vert", Self::name()).as_str(),
             shaderc::ShaderKind::Vertex,
             Self::vertex_shader(),
+            &Self::shader_defines(&args),
         );
         let fragment_shader = create_shader(
             device,
@@ -303,6 +344,7 @@ pub trait Pipeline {
             format!("{}.frag", Self::name()).as_str(),
             shaderc::ShaderKind::Fragment,
             Self::fragment_shader(),
+            &Self::shader_defines(&args),
         );
         let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some(&format!("{} pipeline", Self::name())),
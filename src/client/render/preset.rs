@@ -0,0 +1,128 @@
+//! Graphics presets, which set every quality-related render cvar in one step.
+//!
+//! Besides the built-in presets, `preset_save <name>` writes the current values of those cvars to
+//! `presets/<name>.cfg`, which `preset <name>` will then run.
+
+use std::{fs, io::Write as _};
+
+use bevy::prelude::*;
+use clap::Parser;
+
+use crate::common::{
+    console::{ExecResult, RegisterCmdExt as _, Registry, RunCmd},
+    vfs::Vfs,
+};
+
+/// The cvars set by a preset.
+const PRESET_CVARS: &[&str] = &["r_msaa_samples", "r_scale", "r_dynamic", "r_decals"];
+
+/// Values of each of `PRESET_CVARS`, in the same order.
+const PRESETS: &[(&str, [&str; 4])] = &[
+    ("low", ["1", "0.5", "0", "0"]),
+    ("medium", ["1", "0.75", "1", "1"]),
+    ("high", ["4", "1", "1", "1"]),
+    ("ultra", ["8", "1", "1", "1"]),
+];
+
+fn preset_path(name: &str) -> String {
+    format!("presets/{}.cfg", name)
+}
+
+pub fn register_preset_commands(app: &mut App) {
+    #[derive(Parser)]
+    #[command(
+        name = "preset",
+        about = "Apply a graphics preset: low, medium, high, ultra, or one saved with preset_save"
+    )]
+    struct Preset {
+        name: Option<String>,
+    }
+
+    app.command(
+        |In(Preset { name }), mut registry: ResMut<Registry>| -> ExecResult {
+            let Some(name) = name else {
+                let names = PRESETS.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+                return format!("built-in presets: {}\n", names.join(", ")).into();
+            };
+
+            let Some((_, values)) = PRESETS.iter().find(|(preset, _)| *preset == name) else {
+                // user presets are ordinary config files
+                return ExecResult {
+                    extra_commands: Box::new(
+                        [RunCmd("exec".into(), vec![preset_path(&name)].into())].into_iter(),
+                    ),
+                    ..default()
+                };
+            };
+
+            let mut out = String::new();
+            for (cvar, value) in PRESET_CVARS.iter().zip(values) {
+                if let Err(e) = registry.set_cvar(cvar, value) {
+                    out.push_str(&format!("Failed to set {}: {}\n", cvar, e));
+                }
+            }
+
+            out.into()
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(
+        name = "preset_save",
+        about = "Save the current graphics settings as a preset"
+    )]
+    struct PresetSave {
+        name: String,
+    }
+
+    app.command(
+        |In(PresetSave { name }), registry: Res<Registry>, vfs: Res<Vfs>| -> ExecResult {
+            if PRESETS.iter().any(|(preset, _)| *preset == name) {
+                return format!("{} is a built-in preset", name).into();
+            }
+
+            let path = preset_path(&name);
+            let res = (|| -> Result<(), failure::Error> {
+                if let Some(dir) = vfs.find_writable_filename(&path)?.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+
+                let mut file = vfs.write(&path)?;
+                for &cvar in PRESET_CVARS {
+                    if let Some(value) = registry.get_cvar(cvar).map(|c| c.value()) {
+                        writeln!(file, "{} \"{}\"", cvar, value)?;
+                    }
+                }
+                file.flush()?;
+
+                Ok(())
+            })();
+
+            match res {
+                Ok(()) => default(),
+                Err(e) => format!("Failed to save preset {}: {}", name, e).into(),
+            }
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preset_cvars_registered() {
+        let mut app = App::new();
+        app.init_resource::<Registry>();
+        super::super::register_cvars(&mut app);
+
+        let registry = app.world.resource::<Registry>();
+        for cvar in PRESET_CVARS {
+            assert!(
+                registry.get_cvar(cvar).is_some(),
+                "{} isn't registered",
+                cvar
+            );
+        }
+    }
+}
@@ -0,0 +1,273 @@
+//! The `screenshot` command, which saves the view to a PNG in the game directory.
+//!
+//! The view is copied once it has been tonemapped but before the UI is drawn over it, so the
+//! console, HUD and menus are left out. It's drawn into an 8-bit sRGB texture with the blit
+//! pipeline to convert it, copied to a buffer, and read back and encoded on the IO task pool once
+//! the frame has been submitted.
+
+use std::{collections::VecDeque, path::PathBuf, sync::Arc};
+
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::ExtractResource,
+        render_graph::{RenderLabel, ViewNode},
+        render_resource::{Buffer, RenderPassDescriptor},
+        view::ViewTarget,
+    },
+    tasks::IoTaskPool,
+};
+use chrono::Utc;
+use clap::Parser;
+use failure::{format_err, Error};
+use parking_lot::Mutex;
+
+use crate::{
+    client::render::{world::blit::FragmentPushConstants, GraphicsState},
+    common::{
+        console::{ExecResult, RegisterCmdExt as _},
+        vfs::Vfs,
+    },
+};
+
+/// The format that screenshots are converted to before they're read back.
+pub const SCREENSHOT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+const BYTES_PER_PIXEL: u32 = 4;
+
+/// The paths of the screenshots asked for with `screenshot`, which are taken by the render world.
+#[derive(Resource, ExtractResource, Clone, Default)]
+pub struct ScreenshotRequests(Arc<Mutex<VecDeque<PathBuf>>>);
+
+impl ScreenshotRequests {
+    fn push(&self, path: PathBuf) {
+        self.0.lock().push_back(path);
+    }
+
+    fn pop(&self) -> Option<PathBuf> {
+        self.0.lock().pop_front()
+    }
+}
+
+/// A screenshot copied into a buffer, which can be read once the frame has been submitted.
+struct Readback {
+    buffer: Buffer,
+    width: u32,
+    height: u32,
+    path: PathBuf,
+}
+
+impl Readback {
+    async fn save(self) -> Result<(), Error> {
+        let (sender, receiver) = futures::channel::oneshot::channel();
+        self.buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |res| {
+                let _ = sender.send(res);
+            });
+        receiver.await??;
+
+        let pixels = unpad_rows(
+            &self.buffer.slice(..).get_mapped_range(),
+            self.width * BYTES_PER_PIXEL,
+            padded_row_bytes(self.width),
+        );
+        self.buffer.unmap();
+
+        let image = image::RgbaImage::from_raw(self.width, self.height, pixels)
+            .ok_or_else(|| format_err!("screenshot buffer is too small"))?;
+        // the view's alpha isn't meaningful, so it's left out rather than making parts of the
+        // screenshot transparent
+        image::DynamicImage::ImageRgba8(image)
+            .into_rgb8()
+            .save_with_format(&self.path, image::ImageFormat::Png)?;
+
+        Ok(())
+    }
+}
+
+/// Screenshots copied out of the view this frame, to be read back once it's been submitted.
+#[derive(Resource, Default)]
+pub struct ScreenshotReadbacks(Mutex<Vec<Readback>>);
+
+/// The length of each row of a screenshot `width` pixels wide in the buffer it's copied to, which
+/// must be a multiple of `wgpu::COPY_BYTES_PER_ROW_ALIGNMENT`.
+fn padded_row_bytes(width: u32) -> u32 {
+    let row_bytes = width * BYTES_PER_PIXEL;
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    (row_bytes + align - 1) / align * align
+}
+
+/// Remove the padding from the end of each row of `data`.
+fn unpad_rows(data: &[u8], row_bytes: u32, padded_row_bytes: u32) -> Vec<u8> {
+    data.chunks_exact(padded_row_bytes as usize)
+        .flat_map(|row| &row[..row_bytes as usize])
+        .copied()
+        .collect()
+}
+
+pub fn register_screenshot_command(app: &mut App) {
+    #[derive(Parser)]
+    #[command(
+        name = "screenshot",
+        about = "Save a PNG of the view, without the console, HUD or menus, to the game directory"
+    )]
+    struct Screenshot {
+        /// The file to save to, named after the time if it's left out
+        path: Option<PathBuf>,
+    }
+
+    app.init_resource::<ScreenshotRequests>().command(
+        |In(Screenshot { path }), vfs: Res<Vfs>, requests: Res<ScreenshotRequests>| -> ExecResult {
+            let mut path = path.unwrap_or_else(|| {
+                PathBuf::from(format!("richter-{}", Utc::now().format("%FT%H-%M-%S")))
+            });
+            if path.extension().is_none() {
+                path.set_extension("png");
+            }
+
+            match vfs.find_writable_filename(path.to_string_lossy()) {
+                Ok(path) => {
+                    requests.push(path);
+                    default()
+                }
+                Err(e) => format!("Couldn't take screenshot: {}", e).into(),
+            }
+        },
+    );
+}
+
+/// Starts reading back the screenshots copied this frame, which have been submitted by now.
+pub fn read_back_screenshots(readbacks: Res<ScreenshotReadbacks>) {
+    for readback in readbacks.0.lock().drain(..) {
+        IoTaskPool::get()
+            .spawn(async move {
+                let path = readback.path.clone();
+                match readback.save().await {
+                    Ok(()) => info!("Saved screenshot to {}", path.display()),
+                    Err(e) => error!("Couldn't save screenshot to {}: {}", path.display(), e),
+                }
+            })
+            .detach();
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+pub struct ScreenshotLabel;
+
+#[derive(Default)]
+pub struct ScreenshotNode;
+
+impl ViewNode for ScreenshotNode {
+    type ViewQuery = &'static ViewTarget;
+
+    fn run<'w>(
+        &self,
+        _graph: &mut bevy::render::render_graph::RenderGraphContext,
+        render_context: &mut bevy::render::renderer::RenderContext<'w>,
+        target: &ViewTarget,
+        world: &'w World,
+    ) -> Result<(), bevy::render::render_graph::NodeRunError> {
+        let Some(gfx_state) = world.get_resource::<GraphicsState>() else {
+            return Ok(());
+        };
+        let Some(path) = world.resource::<ScreenshotRequests>().pop() else {
+            return Ok(());
+        };
+
+        let device = render_context.render_device().clone();
+        let size = target.main_texture().size();
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("screenshot"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: SCREENSHOT_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&default());
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("screenshot buffer"),
+            size: padded_row_bytes(size.width) as u64 * size.height as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let screenshot_pipeline = gfx_state.screenshot_pipeline();
+        let bind_group = screenshot_pipeline.create_bind_group(
+            &device,
+            gfx_state.nearest_sampler(),
+            target.main_texture_view(),
+        );
+        let push_constants = FragmentPushConstants {
+            source_scale: [1., 1.],
+        };
+
+        {
+            let mut pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                label: Some("screenshot"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: default(),
+                })],
+                ..default()
+            });
+            screenshot_pipeline.record_draw(
+                &mut pass,
+                gfx_state.quad_pipeline().vertex_buffer(),
+                &bind_group,
+                &push_constants,
+            );
+        }
+
+        render_context.command_encoder().copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes(size.width)),
+                    rows_per_image: None,
+                },
+            },
+            size,
+        );
+
+        world
+            .resource::<ScreenshotReadbacks>()
+            .0
+            .lock()
+            .push(Readback {
+                buffer,
+                width: size.width,
+                height: size.height,
+                path,
+            });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unpad_rows() {
+        assert_eq!(padded_row_bytes(1), 256);
+        assert_eq!(padded_row_bytes(64), 256);
+        assert_eq!(padded_row_bytes(65), 512);
+
+        // two rows of two pixels, each padded out to 256 bytes
+        let mut data = vec![0xff; 512];
+        data[..8].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        data[256..264].copy_from_slice(&[9, 10, 11, 12, 13, 14, 15, 16]);
+        assert_eq!(
+            unpad_rows(&data, 8, padded_row_bytes(2)),
+            (1..=16).collect::<Vec<u8>>()
+        );
+    }
+}
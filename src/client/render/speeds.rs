@@ -0,0 +1,192 @@
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use bevy::prelude::*;
+use parking_lot::Mutex;
+
+/// The render graph nodes that [`RenderSpeeds`] times.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SpeedsPass {
+    Init = 0,
+    Deferred = 1,
+    Blit = 2,
+    PostProcess = 3,
+    Ui = 4,
+}
+
+impl SpeedsPass {
+    pub const ALL: [SpeedsPass; 5] = [
+        SpeedsPass::Init,
+        SpeedsPass::Deferred,
+        SpeedsPass::Blit,
+        SpeedsPass::PostProcess,
+        SpeedsPass::Ui,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SpeedsPass::Init => "init",
+            SpeedsPass::Deferred => "deferred",
+            SpeedsPass::Blit => "blit",
+            SpeedsPass::PostProcess => "postprocess",
+            SpeedsPass::Ui => "ui",
+        }
+    }
+}
+
+/// The counts for one frame, as shown by `r_speeds`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SpeedsReport {
+    /// Faces drawn from the world and from brush entities.
+    pub world_polys: u32,
+    /// Triangles drawn from alias and MD3 models, including the view model.
+    pub alias_polys: u32,
+    pub draw_calls: u32,
+    /// Lightmaps uploaded during the frame. Light styles are animated in the brush shader and
+    /// dynamic lights are applied in the deferred pass, so this is only nonzero while lightmaps
+    /// are being built.
+    pub lightmap_uploads: u32,
+    /// Dynamic lights applied in the deferred pass.
+    pub dynamic_lights: u32,
+    /// The CPU time spent recording each pass, in microseconds, indexed by [`SpeedsPass`].
+    pub pass_micros: [u32; 5],
+}
+
+impl SpeedsReport {
+    /// The lines of text that `r_speeds` draws.
+    pub fn lines(&self) -> Vec<String> {
+        let total: u32 = self.pass_micros.iter().sum();
+        let mut lines = vec![
+            format!(
+                "{:4} wpoly {:5} epoly {:4} draws",
+                self.world_polys, self.alias_polys, self.draw_calls
+            ),
+            format!(
+                "{:4} lmaps {:5} dlights",
+                self.lightmap_uploads, self.dynamic_lights
+            ),
+        ];
+        for pass in SpeedsPass::ALL {
+            let micros = self.pass_micros[pass as usize];
+            lines.push(format!(
+                "{:>11} {:3}.{:03} ms",
+                pass.name(),
+                micros / 1000,
+                micros % 1000
+            ));
+        }
+        lines.push(format!(
+            "{:>11} {:3}.{:03} ms",
+            "total",
+            total / 1000,
+            total % 1000
+        ));
+
+        lines
+    }
+}
+
+/// Per-frame counts of what the renderer draws, gathered by the render graph nodes while they
+/// record their passes.
+///
+/// The counts for the frame being drawn are kept separately from those of the last finished
+/// frame, which is what's reported, so that the UI pass can show timings that include its own.
+#[derive(Resource, Default)]
+pub struct RenderSpeeds {
+    world_polys: AtomicU32,
+    alias_polys: AtomicU32,
+    draw_calls: AtomicU32,
+    lightmap_uploads: AtomicU32,
+    dynamic_lights: AtomicU32,
+    pass_micros: [AtomicU32; 5],
+    last: Mutex<SpeedsReport>,
+}
+
+impl RenderSpeeds {
+    /// Count a draw call that draws `polys` world faces.
+    pub fn add_world_polys(&self, polys: u32) {
+        self.world_polys.fetch_add(polys, Ordering::Relaxed);
+        self.add_draw_call();
+    }
+
+    /// Count a draw call that draws `polys` alias model triangles.
+    pub fn add_alias_polys(&self, polys: u32) {
+        self.alias_polys.fetch_add(polys, Ordering::Relaxed);
+        self.add_draw_call();
+    }
+
+    pub fn add_draw_call(&self) {
+        self.draw_calls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_lightmap_uploads(&self, count: u32) {
+        self.lightmap_uploads.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn set_dynamic_lights(&self, count: u32) {
+        self.dynamic_lights.store(count, Ordering::Relaxed);
+    }
+
+    pub fn set_pass_time(&self, pass: SpeedsPass, time: Duration) {
+        self.pass_micros[pass as usize].store(
+            time.as_micros().try_into().unwrap_or(u32::MAX),
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Returns the counts of the last finished frame.
+    pub fn last(&self) -> SpeedsReport {
+        self.last.lock().clone()
+    }
+
+    /// Keep the counts of the frame that was just drawn to be reported, and start counting the
+    /// next one from zero.
+    pub fn finish_frame(&self) {
+        let take = |count: &AtomicU32| count.swap(0, Ordering::Relaxed);
+        *self.last.lock() = SpeedsReport {
+            world_polys: take(&self.world_polys),
+            alias_polys: take(&self.alias_polys),
+            draw_calls: take(&self.draw_calls),
+            lightmap_uploads: take(&self.lightmap_uploads),
+            dynamic_lights: take(&self.dynamic_lights),
+            pass_micros: std::array::from_fn(|i| take(&self.pass_micros[i])),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_finish_frame() {
+        let speeds = RenderSpeeds::default();
+        speeds.add_world_polys(1);
+        speeds.add_world_polys(1);
+        speeds.add_alias_polys(100);
+        speeds.add_draw_call();
+        speeds.add_lightmap_uploads(5);
+        speeds.set_dynamic_lights(3);
+        speeds.set_pass_time(SpeedsPass::Deferred, Duration::from_micros(1500));
+        assert_eq!(speeds.last(), SpeedsReport::default());
+
+        speeds.finish_frame();
+        let report = speeds.last();
+        assert_eq!(report.world_polys, 2);
+        assert_eq!(report.alias_polys, 100);
+        assert_eq!(report.draw_calls, 4);
+        assert_eq!(report.lightmap_uploads, 5);
+        assert_eq!(report.dynamic_lights, 3);
+        assert_eq!(report.pass_micros, [0, 1500, 0, 0, 0]);
+        assert!(report
+            .lines()
+            .iter()
+            .any(|l| l.contains("deferred   1.500 ms")));
+
+        // the next frame starts again from zero
+        speeds.finish_frame();
+        assert_eq!(speeds.last(), SpeedsReport::default());
+    }
+}
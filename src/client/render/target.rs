@@ -18,7 +18,7 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use std::cell::RefCell;
+use std::{cell::RefCell, time::Instant};
 
 use bevy::{
     core_pipeline::{core_3d::Camera3d, prepass::ViewPrepassTextures},
@@ -34,8 +34,8 @@ use bumpalo::Bump;
 use cgmath::Deg;
 
 use crate::client::render::{
-    world::WorldRenderer, GraphicsState, RenderConnectionKind, RenderResolution, RenderState,
-    RenderVars,
+    world::{blit, debug::DebugDraw, DebugViews, WorldRenderer},
+    GraphicsState, RenderResolution, RenderSpeeds, RenderState, RenderVars, SpeedsPass,
 };
 
 /// Intermediate object that can generate `RenderPassDescriptor`s.
@@ -106,6 +106,7 @@ impl ViewNode for InitPass {
         (target, prepass, _): (&ViewTarget, &ViewPrepassTextures, &Camera3d),
         world: &'w bevy::prelude::World,
     ) -> Result<(), bevy::render::render_graph::NodeRunError> {
+        let start = Instant::now();
         let gfx_state = world.resource::<GraphicsState>();
         let queue = world.resource::<RenderQueue>();
         let device = world.resource::<RenderDevice>();
@@ -113,8 +114,18 @@ impl ViewNode for InitPass {
         let world_renderer = world.get_resource::<WorldRenderer>();
         let &RenderResolution(width, height) = world.resource::<RenderResolution>();
         let render_vars = world.resource::<RenderVars>();
+        let speeds = world.resource::<RenderSpeeds>();
+        let debug_shapes = world
+            .get_resource::<DebugDraw>()
+            .map_or(&[][..], DebugDraw::shapes);
 
-        let diffuse_target = target.get_unsampled_color_attachment().view;
+        // with MSAA enabled the G-buffer diffuse is drawn multisampled and resolved into the main
+        // texture, which the deferred pass reads
+        let RenderPassColorAttachment {
+            view: diffuse_target,
+            resolve_target: diffuse_resolve_target,
+            ..
+        } = target.get_color_attachment();
         let ViewPrepassTextures {
             normal: Some(normal_target),
             depth: Some(depth_target),
@@ -138,27 +149,16 @@ impl ViewNode for InitPass {
 
         BUMP.with_borrow_mut(|bump| bump.reset());
         BUMP.with_borrow(|bump| {
-            if let (
-                Some(RenderState {
-                    state: ref cl_state,
-                    kind,
-                }),
-                Some(world),
-            ) = (render_state, world_renderer)
-            {
+            if let (Some(render_state), Some(world)) = (render_state, world_renderer) {
+                let cl_state = &render_state.state;
                 // if client is fully connected, draw world
-                let camera = match kind {
-                    RenderConnectionKind::Demo => {
-                        cl_state.demo_camera(width as f32 / height as f32, Deg(render_vars.fov))
-                    }
-                    RenderConnectionKind::Server => {
-                        cl_state.camera(width as f32 / height as f32, Deg(render_vars.fov))
-                    }
-                };
+                let camera =
+                    render_state.camera(width as f32 / height as f32, Deg(render_vars.fov));
 
                 // initial render pass
                 {
-                    let lightstyle_values = cl_state.lightstyle_values();
+                    let lightstyle_values =
+                        cl_state.lightstyle_values(render_vars.lerp_lightstyles);
                     world.update_uniform_buffers(
                         gfx_state,
                         queue,
@@ -176,7 +176,7 @@ impl ViewNode for InitPass {
                             color_attachments: &[
                                 Some(wgpu::RenderPassColorAttachment {
                                     view: diffuse_target,
-                                    resolve_target: None,
+                                    resolve_target: diffuse_resolve_target,
                                     ops: wgpu::Operations {
                                         load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
                                         store: wgpu::StoreOp::Store,
@@ -199,6 +199,10 @@ impl ViewNode for InitPass {
                         }),
                     );
 
+                    if let Some((width, height)) = blit::scene_viewport(target, render_vars.scale) {
+                        init_pass.set_viewport(0., 0., width as f32, height as f32, 0., 1.);
+                    }
+
                     world.render_pass(
                         gfx_state,
                         &mut init_pass,
@@ -206,17 +210,23 @@ impl ViewNode for InitPass {
                         &camera,
                         cl_state.time(),
                         cl_state.iter_visible_entities(),
-                        cl_state.iter_particles(),
-                        if cl_state.intermission().is_none() {
-                            Some(cl_state.viewmodel_id())
-                        } else {
-                            None
+                        cl_state.players(),
+                        cl_state.iter_decals().filter(|_| render_vars.decals != 0),
+                        debug_shapes,
+                        render_state.viewmodel(render_vars),
+                        render_state.held_weapon(),
+                        DebugViews {
+                            show_tris: render_vars.show_tris != 0,
+                            entity_boxes: render_vars.show_bboxes != 0,
                         },
+                        speeds,
                     );
                 }
             }
         });
 
+        speeds.set_pass_time(SpeedsPass::Init, start.elapsed());
+
         Ok(())
     }
 }
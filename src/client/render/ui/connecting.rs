@@ -0,0 +1,35 @@
+use crate::client::{
+    progress::ConnectionProgress,
+    render::ui::{
+        glyph::{GlyphRendererCommand, GLYPH_HEIGHT},
+        layout::{Anchor, ScreenPosition},
+    },
+};
+
+/// Generate render commands to draw the connection screen, with the lines of text from `progress`
+/// centered on the screen.
+pub fn generate_commands(
+    progress: &ConnectionProgress,
+    scale: f32,
+    glyph_cmds: &mut Vec<GlyphRendererCommand>,
+) {
+    let lines = progress.lines();
+    let top = (lines.len() * GLYPH_HEIGHT) as i32 / 2;
+
+    for (i, line) in lines.into_iter().enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+
+        glyph_cmds.push(GlyphRendererCommand::Text {
+            text: line,
+            position: ScreenPosition::Relative {
+                anchor: Anchor::CENTER,
+                x_ofs: 0,
+                y_ofs: top - (i * GLYPH_HEIGHT) as i32,
+            },
+            anchor: Anchor::TOP_CENTER,
+            scale,
+        });
+    }
+}
@@ -8,6 +8,8 @@ use crate::{
             },
             GraphicsState,
         },
+        sound::indicator::SoundKind,
+        state::PlayerInfo,
         IntermissionKind,
     },
     common::{
@@ -25,6 +27,7 @@ use bevy::{
         renderer::{RenderDevice, RenderQueue},
     },
 };
+use cgmath::{Angle as _, Deg};
 use chrono::Duration;
 use hashbrown::HashMap;
 use num::FromPrimitive as _;
@@ -42,17 +45,52 @@ const OVERLAY_Y_OFS: i32 = -OVERLAY_HEIGHT / 2;
 
 const OVERLAY_ANCHOR: Anchor = Anchor::CENTER;
 
+// distance of the sound indicators from the crosshair, before scaling
+const SOUND_INDICATOR_RADIUS: f32 = 48.0;
+
+// scoreboard layout, relative to the top-left corner of the overlay
+const SCOREBOARD_X: i32 = 80;
+const SCOREBOARD_Y: i32 = 40;
+const SCOREBOARD_ROW_HEIGHT: i32 = 10;
+
+/// The players to rank on the scoreboard.
+#[derive(Clone, Copy)]
+pub struct Scoreboard<'a> {
+    pub players: &'a [Option<PlayerInfo>],
+    /// The slot of the player on this client, whose frags are bracketed.
+    pub local_player: Option<usize>,
+}
+
+impl<'a> Scoreboard<'a> {
+    /// The players in order of frags, most first. Players with the same frags stay in slot order.
+    fn ranking(&self) -> Vec<(usize, &'a PlayerInfo)> {
+        let mut ranking = self
+            .players
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, info)| Some((slot, info.as_ref()?)))
+            .collect::<Vec<_>>();
+        ranking.sort_by_key(|(_, info)| std::cmp::Reverse(info.frags));
+        ranking
+    }
+}
+
 pub enum HudState<'a> {
     InGame {
         items: ItemFlags,
         item_pickup_time: &'a [Duration],
         stats: &'a [i32],
         face_anim_time: Duration,
+        sound_indicators: &'a [(SoundKind, Deg<f32>)],
+        /// Set while `+showscores` is held.
+        scoreboard: Option<Scoreboard<'a>>,
     },
     Intermission {
         kind: &'a IntermissionKind,
         completion_duration: Duration,
         stats: &'a [i32],
+        /// Set in deathmatch, where the scoreboard is shown in place of the level stats.
+        scoreboard: Option<Scoreboard<'a>>,
     },
 }
 
@@ -68,6 +106,7 @@ enum HudTextureId {
     Item { id: ItemId },
     Sigil { id: usize },
     Face { id: FaceId },
+    Disc,
     StatusBar,
     InvBar,
     ScoreBar,
@@ -75,6 +114,9 @@ enum HudTextureId {
     // these are not in gfx.wad
     Complete,
     Intermission,
+    Ranking,
+    // a solid block of one of the 16 player colors
+    PlayerColor { color: u8 },
 }
 
 impl std::fmt::Display for HudTextureId {
@@ -91,6 +133,7 @@ impl std::fmt::Display for HudTextureId {
             Item { id } => write!(f, "SB_{}", id),
             Sigil { id } => write!(f, "SB_SIGIL{}", id + 1),
             Face { id } => write!(f, "{}", id),
+            Disc => write!(f, "DISC"),
             StatusBar => write!(f, "SBAR"),
             InvBar => write!(f, "IBAR"),
             ScoreBar => write!(f, "SCOREBAR"),
@@ -98,6 +141,8 @@ impl std::fmt::Display for HudTextureId {
             // these are not in gfx.wad
             Complete => write!(f, "gfx/complete.lmp"),
             Intermission => write!(f, "gfx/inter.lmp"),
+            Ranking => write!(f, "gfx/ranking.lmp"),
+            PlayerColor { color } => write!(f, "player color {}", color),
         }
     }
 }
@@ -198,15 +243,26 @@ impl std::fmt::Display for FaceId {
 #[derive(Resource, Deserialize)]
 pub struct HudVars {
     pub crosshair: u8,
+    /// Whether `+showscores` is held.
+    #[serde(skip)]
+    pub show_scores: bool,
     #[serde(rename(deserialize = "cl_hud"))]
     pub hud_style: u8,
+    /// At 110 the inventory bar is hidden, and at 120 the whole status bar.
+    pub viewsize: f32,
+    /// Whether to draw the backgrounds of the status and inventory bars.
+    #[serde(rename(deserialize = "cl_sbar"))]
+    pub sbar_background: u8,
 }
 
 impl Default for HudVars {
     fn default() -> Self {
         Self {
             crosshair: 1,
+            show_scores: false,
             hud_style: 3,
+            viewsize: 100.,
+            sbar_background: 1,
         }
     }
 }
@@ -215,7 +271,10 @@ impl ExtractResource for HudVars {
     type Source = Registry;
 
     fn extract_resource(source: &Self::Source) -> Self {
-        source.read_cvars().unwrap_or_default()
+        HudVars {
+            show_scores: source.is_pressed("showscores"),
+            ..source.read_cvars().unwrap_or_default()
+        }
     }
 }
 
@@ -280,7 +339,7 @@ impl HudRenderer {
         );
 
         // unit variants
-        ids.extend(vec![Colon, Slash, StatusBar, InvBar, ScoreBar].into_iter());
+        ids.extend(vec![Colon, Slash, Disc, StatusBar, InvBar, ScoreBar].into_iter());
 
         let mut textures = HashMap::default();
         for id in ids.into_iter() {
@@ -291,13 +350,22 @@ impl HudRenderer {
         }
 
         // new id list for textures not in gfx.wad
-        let ids = vec![Complete, Intermission];
+        let ids = vec![Complete, Intermission, Ranking];
         for id in ids.into_iter() {
             debug!("Opening {}", id);
             let qpic = QPic::load(vfs.open(&format!("{}", id)).unwrap()).unwrap();
             textures.insert(id, QuadTexture::from_qpic(state, device, queue, &qpic));
         }
 
+        // each player color is a row of the palette, and the middle of the row is shown
+        for color in 0..16 {
+            let qpic = QPic::fill(1, 1, color * 16 + 8);
+            textures.insert(
+                PlayerColor { color },
+                QuadTexture::from_qpic(state, device, queue, &qpic),
+            );
+        }
+
         HudRenderer { textures }
     }
 
@@ -423,14 +491,125 @@ impl HudRenderer {
             return;
         }
 
-        let sbar = self.textures.get(&StatusBar).unwrap();
-        let sbar_x_ofs = -(sbar.width() as i32) / 2;
+        // crosshair
+        if hud_cvars.crosshair != 0 {
+            glyph_cmds.push(GlyphRendererCommand::Glyph {
+                glyph_id: '+' as u8,
+                position: ScreenPosition::Absolute(Anchor::CENTER),
+                anchor: Anchor::TOP_LEFT,
+                scale,
+            });
+        }
+
+        if hud_cvars.viewsize >= 120. {
+            return;
+        }
+
+        let backgrounds = hud_cvars.sbar_background != 0 && hud_cvars.hud_style != 1;
 
         // status bar background
-        self.cmd_sbar_quad(StatusBar, 0, 0, scale, quad_cmds);
+        if backgrounds {
+            self.cmd_sbar_quad(StatusBar, 0, 0, scale, quad_cmds);
+        }
+
+        if hud_cvars.viewsize < 110. {
+            self.cmd_inventory(
+                time,
+                items,
+                item_pickup_time,
+                stats,
+                scale,
+                hud_cvars,
+                backgrounds,
+                quad_cmds,
+                glyph_cmds,
+            );
+        }
+
+        // armor
+        let armor_width = self.textures.get(&Armor { id: 0 }).unwrap().width() as i32;
+        if items.contains(ItemFlags::INVULNERABILITY) {
+            self.cmd_sbar_number(666, true, 3, armor_width, 0, scale, quad_cmds);
+            self.cmd_sbar_quad(Disc, 0, 0, scale, quad_cmds);
+        } else {
+            let armor = stats[ClientStat::Armor as usize];
+            self.cmd_sbar_number(armor, armor <= 25, 3, armor_width, 0, scale, quad_cmds);
+
+            let mut armor_id = None;
+            for i in (0..3).rev() {
+                if items.contains(ItemFlags::from_bits(ItemFlags::ARMOR_1.bits() << i).unwrap()) {
+                    armor_id = Some(Armor { id: i });
+                    break;
+                }
+            }
+
+            if let Some(a) = armor_id {
+                self.cmd_sbar_quad(a, 0, 0, scale, quad_cmds);
+            }
+        }
+
+        // health
+        let health = stats[ClientStat::Health as usize];
+        self.cmd_sbar_number(health, health <= 25, 3, 136, 0, scale, quad_cmds);
+
+        // the kind of ammo the current weapon uses
+        let ammo_id = (0..4)
+            .find(|i| items.contains(ItemFlags::from_bits(ItemFlags::SHELLS.bits() << i).unwrap()));
+        if let Some(id) = ammo_id.and_then(AmmoId::from_usize) {
+            self.cmd_sbar_quad(Ammo { id }, 224, 0, scale, quad_cmds);
+        }
+
+        let ammo = stats[ClientStat::Ammo as usize];
+        self.cmd_sbar_number(ammo, ammo <= 10, 3, 248, 0, scale, quad_cmds);
+
+        let face = if items.contains(ItemFlags::INVISIBILITY | ItemFlags::INVULNERABILITY) {
+            FaceId::InvisibleInvulnerable
+        } else if items.contains(ItemFlags::QUAD) {
+            FaceId::QuadDamage
+        } else if items.contains(ItemFlags::INVISIBILITY) {
+            FaceId::Invisible
+        } else if items.contains(ItemFlags::INVULNERABILITY) {
+            FaceId::Invulnerable
+        } else {
+            let health = stats[ClientStat::Health as usize];
+            let frame = 4 - if health >= 100 {
+                4
+            } else {
+                health.max(0) as usize / 20
+            };
+
+            FaceId::Normal {
+                pain: face_anim_time > time,
+                frame,
+            }
+        };
+
+        self.cmd_sbar_quad(Face { id: face }, 112, 0, scale, quad_cmds);
+    }
+
+    // Draw the inventory bar above the status bar: weapons, ammo counts, keys, powerups and
+    // sigils.
+    fn cmd_inventory<'a>(
+        &'a self,
+        time: Duration,
+        items: ItemFlags,
+        item_pickup_time: &'a [Duration],
+        stats: &'a [i32],
+        scale: f32,
+        hud_cvars: &HudVars,
+        background: bool,
+        quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
+        glyph_cmds: &mut Vec<GlyphRendererCommand>,
+    ) {
+        use HudTextureId::*;
+
+        let sbar = self.textures.get(&StatusBar).unwrap();
+        let sbar_x_ofs = -(sbar.width() as i32) / 2;
 
         // inventory bar background
-        self.cmd_sbar_quad(InvBar, 0, sbar.height() as i32, scale, quad_cmds);
+        if background {
+            self.cmd_sbar_quad(InvBar, 0, sbar.height() as i32, scale, quad_cmds);
+        }
 
         // weapon slots
         for i in 0..7 {
@@ -438,7 +617,8 @@ impl HudRenderer {
                 let id = WeaponId::from_usize(i).unwrap();
                 let pickup_time = item_pickup_time[i];
                 let delta = time - pickup_time;
-                let frame = if delta >= Duration::try_milliseconds(100).unwrap() {
+                // a weapon that was just picked up flashes for a second
+                let frame = if delta >= Duration::try_seconds(1).unwrap() {
                     if stats[ClientStat::ActiveWeapon as usize] as u32
                         == ItemFlags::SHOTGUN.bits() << i
                     {
@@ -448,7 +628,7 @@ impl HudRenderer {
                     }
                 } else {
                     WeaponFrame::Pickup {
-                        frame: (delta.num_milliseconds() * 100) as usize % 5,
+                        frame: (delta.num_milliseconds() / 100) as usize % 5,
                     }
                 };
 
@@ -472,7 +652,7 @@ impl HudRenderer {
                             glyph_id: 18 + chr as u8 - '0' as u8,
                             position: ScreenPosition::Relative {
                                 anchor: Anchor::BOTTOM_CENTER,
-                                x_ofs: sbar_x_ofs + 8 * (6 * i + chr_id) as i32 + 10,
+                                x_ofs: sbar_x_ofs + 8 * (6 * i + chr_id) as i32 + 6,
                                 y_ofs: sbar.height() as i32 + 16,
                             },
                             anchor: Anchor::BOTTOM_LEFT,
@@ -523,66 +703,26 @@ impl HudRenderer {
                 });
             }
         }
+    }
 
-        // armor
-        let armor_width = self.textures.get(&Armor { id: 0 }).unwrap().width() as i32;
-        if items.contains(ItemFlags::INVULNERABILITY) {
-            self.cmd_sbar_number(666, true, 3, armor_width, 0, scale, quad_cmds);
-        // TODO draw_disc
-        } else {
-            let armor = stats[ClientStat::Armor as usize];
-            self.cmd_sbar_number(armor, armor <= 25, 3, armor_width, 0, scale, quad_cmds);
-
-            let mut armor_id = None;
-            for i in (0..3).rev() {
-                if items.contains(ItemFlags::from_bits(ItemFlags::ARMOR_1.bits() << i).unwrap()) {
-                    armor_id = Some(Armor { id: i });
-                    break;
-                }
-            }
-
-            if let Some(a) = armor_id {
-                self.cmd_sbar_quad(a, 0, 0, scale, quad_cmds);
-            }
-        }
-
-        // health
-        let health = stats[ClientStat::Health as usize];
-        self.cmd_sbar_number(health, health <= 25, 3, 136, 0, scale, quad_cmds);
-
-        let ammo = stats[ClientStat::Ammo as usize];
-        self.cmd_sbar_number(ammo, ammo <= 10, 3, 248, 0, scale, quad_cmds);
-
-        let face = if items.contains(ItemFlags::INVISIBILITY | ItemFlags::INVULNERABILITY) {
-            FaceId::InvisibleInvulnerable
-        } else if items.contains(ItemFlags::QUAD) {
-            FaceId::QuadDamage
-        } else if items.contains(ItemFlags::INVISIBILITY) {
-            FaceId::Invisible
-        } else if items.contains(ItemFlags::INVULNERABILITY) {
-            FaceId::Invulnerable
-        } else {
-            let health = stats[ClientStat::Health as usize];
-            let frame = 4 - if health >= 100 {
-                4
-            } else {
-                health.max(0) as usize / 20
-            };
-
-            FaceId::Normal {
-                pain: face_anim_time > time,
-                frame,
-            }
-        };
-
-        self.cmd_sbar_quad(Face { id: face }, 112, 0, scale, quad_cmds);
-
-        // crosshair
-        if hud_cvars.crosshair != 0 {
+    // Draw an icon for each recent sound on a ring around the crosshair, in the sound's direction.
+    fn cmd_sound_indicators(
+        &self,
+        indicators: &[(SoundKind, Deg<f32>)],
+        scale: f32,
+        glyph_cmds: &mut Vec<GlyphRendererCommand>,
+    ) {
+        let radius = SOUND_INDICATOR_RADIUS * scale;
+        for (kind, angle) in indicators {
+            // straight ahead is the top of the ring and angles increase to the left
             glyph_cmds.push(GlyphRendererCommand::Glyph {
-                glyph_id: '+' as u8,
-                position: ScreenPosition::Absolute(Anchor::CENTER),
-                anchor: Anchor::TOP_LEFT,
+                glyph_id: kind.glyph(),
+                position: ScreenPosition::Relative {
+                    anchor: Anchor::CENTER,
+                    x_ofs: (-angle.sin() * radius) as i32,
+                    y_ofs: (angle.cos() * radius) as i32,
+                },
+                anchor: Anchor::CENTER,
                 scale,
             });
         }
@@ -640,6 +780,109 @@ impl HudRenderer {
         );
     }
 
+    // Draw a block of a player color on the scoreboard.
+    fn cmd_scoreboard_color<'a>(
+        &'a self,
+        color: u8,
+        x_ofs: i32,
+        y_ofs: i32,
+        scale: f32,
+        quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
+    ) {
+        quad_cmds.push(QuadRendererCommand {
+            texture: self
+                .textures
+                .get(&HudTextureId::PlayerColor { color: color & 0xF })
+                .unwrap(),
+            layout: Layout {
+                position: ScreenPosition::Relative {
+                    anchor: Anchor::TOP_CENTER,
+                    x_ofs: OVERLAY_X_OFS + x_ofs,
+                    y_ofs: -y_ofs,
+                },
+                anchor: Anchor::TOP_LEFT,
+                size: Size::Scale {
+                    factor: 4.0 * scale,
+                },
+            },
+        });
+    }
+
+    // Draw the ranking of players by frags, at the top of the screen.
+    fn cmd_scoreboard<'a>(
+        &'a self,
+        scoreboard: &Scoreboard<'a>,
+        scale: f32,
+        quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
+        glyph_cmds: &mut Vec<GlyphRendererCommand>,
+    ) {
+        let ranking = self.textures.get(&HudTextureId::Ranking).unwrap();
+        quad_cmds.push(QuadRendererCommand {
+            texture: ranking,
+            layout: Layout {
+                position: ScreenPosition::Relative {
+                    anchor: Anchor::TOP_CENTER,
+                    x_ofs: -(ranking.width() as i32) / 2,
+                    y_ofs: -8,
+                },
+                anchor: Anchor::TOP_LEFT,
+                size: Size::Scale { factor: scale },
+            },
+        });
+
+        let text = |text: String, x_ofs: i32, y_ofs: i32| GlyphRendererCommand::Text {
+            text,
+            position: ScreenPosition::Relative {
+                anchor: Anchor::TOP_CENTER,
+                x_ofs: OVERLAY_X_OFS + x_ofs,
+                y_ofs: -y_ofs,
+            },
+            anchor: Anchor::TOP_LEFT,
+            scale,
+        };
+
+        for (row, (slot, info)) in scoreboard.ranking().into_iter().enumerate() {
+            let y = SCOREBOARD_Y + SCOREBOARD_ROW_HEIGHT * row as i32;
+            let x = SCOREBOARD_X;
+
+            // the shirt color is drawn over the pants color, 40 units wide
+            for i in 0..10 {
+                self.cmd_scoreboard_color(info.colors.top(), x + 4 * i, y, scale, quad_cmds);
+                self.cmd_scoreboard_color(info.colors.bottom(), x + 4 * i, y + 4, scale, quad_cmds);
+            }
+
+            glyph_cmds.push(text(format!("{: >3}", info.frags), x + 8, y));
+            if scoreboard.local_player == Some(slot) {
+                glyph_cmds.push(GlyphRendererCommand::Glyph {
+                    glyph_id: 16,
+                    position: ScreenPosition::Relative {
+                        anchor: Anchor::TOP_CENTER,
+                        x_ofs: OVERLAY_X_OFS + x,
+                        y_ofs: -y,
+                    },
+                    anchor: Anchor::TOP_LEFT,
+                    scale,
+                });
+                glyph_cmds.push(GlyphRendererCommand::Glyph {
+                    glyph_id: 17,
+                    position: ScreenPosition::Relative {
+                        anchor: Anchor::TOP_CENTER,
+                        x_ofs: OVERLAY_X_OFS + x + 32,
+                        y_ofs: -y,
+                    },
+                    anchor: Anchor::TOP_LEFT,
+                    scale,
+                });
+            }
+
+            glyph_cmds.push(text(info.name.to_str().into_owned(), x + 64, y));
+
+            if let Some(ping) = info.ping {
+                glyph_cmds.push(text(format!("{: >4}", ping.num_milliseconds()), x - 40, y));
+            }
+        }
+    }
+
     // Draw the intermission overlay.
     fn cmd_intermission_overlay<'a>(
         &'a self,
@@ -700,6 +943,8 @@ impl HudRenderer {
                 item_pickup_time,
                 stats,
                 face_anim_time,
+                sound_indicators,
+                scoreboard,
             } => {
                 self.cmd_sbar(
                     time,
@@ -712,14 +957,61 @@ impl HudRenderer {
                     quad_cmds,
                     glyph_cmds,
                 );
+                self.cmd_sound_indicators(sound_indicators, scale, glyph_cmds);
+                if let Some(scoreboard) = scoreboard {
+                    self.cmd_scoreboard(scoreboard, scale, quad_cmds, glyph_cmds);
+                }
+            }
+            HudState::Intermission {
+                scoreboard: Some(scoreboard),
+                ..
+            } => {
+                self.cmd_scoreboard(scoreboard, scale, quad_cmds, glyph_cmds);
             }
             HudState::Intermission {
                 kind,
                 completion_duration,
                 stats,
+                scoreboard: None,
             } => {
                 self.cmd_intermission_overlay(kind, *completion_duration, stats, scale, quad_cmds);
             }
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::net::PlayerColor;
+
+    fn player(name: &str, frags: i32) -> Option<PlayerInfo> {
+        Some(PlayerInfo {
+            name: name.to_owned().into(),
+            frags,
+            colors: PlayerColor::new(0, 0),
+            ping: None,
+        })
+    }
+
+    #[test]
+    fn test_scoreboard_ranking() {
+        let players = [
+            player("one", 3),
+            None,
+            player("two", 10),
+            player("three", 3),
+        ];
+        let scoreboard = Scoreboard {
+            players: &players,
+            local_player: Some(0),
+        };
+
+        let slots = scoreboard
+            .ranking()
+            .into_iter()
+            .map(|(slot, _)| slot)
+            .collect::<Vec<_>>();
+        assert_eq!(slots, vec![2, 0, 3]);
+    }
+}
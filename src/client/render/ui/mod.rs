@@ -1,36 +1,47 @@
+pub mod connecting;
 pub mod glyph;
 pub mod hud;
 pub mod layout;
 pub mod menu;
 pub mod quad;
+pub mod speeds;
+pub mod world_text;
 
 use crate::{
     client::{
         input::InputFocus,
         menu::Menu,
+        progress::ConnectionProgress,
         render::{
             ui::{
                 glyph::{GlyphRenderer, GlyphRendererCommand},
-                hud::{HudRenderer, HudState},
+                hud::{HudRenderer, HudState, Scoreboard},
                 menu::MenuRenderer,
                 quad::{QuadRenderer, QuadRendererCommand},
+                world_text::ScreenLabel,
             },
-            Extent2d, GraphicsState,
+            Extent2d, GraphicsState, RenderSpeeds, RenderVars, SpeedsPass, SpeedsReport,
         },
+        sound::SoundIndicators,
+        world_text::WorldText,
+        ConnectionState,
     },
     common::vfs::Vfs,
 };
 
+use std::time::Instant;
+
 use bevy::{
     prelude::*,
     render::{
+        extract_resource::ExtractResource,
         render_graph::{RenderLabel, ViewNode},
         render_phase::TrackedRenderPass,
         renderer::{RenderDevice, RenderQueue},
         view::ViewTarget,
     },
 };
-use cgmath::{Matrix4, Vector2};
+use cgmath::{Deg, Matrix4, Vector2};
 use chrono::Duration;
 
 use self::hud::HudVars;
@@ -86,8 +97,15 @@ pub enum UiState<'a> {
     Title {
         overlay: Option<&'a Menu>,
     },
+    Connecting {
+        progress: &'a ConnectionProgress,
+        overlay: Option<&'a Menu>,
+    },
     InGame {
         hud: HudState<'a>,
+        world_text: &'a [ScreenLabel],
+        /// The counts of the last frame, if `r_speeds` is set.
+        speeds: Option<&'a SpeedsReport>,
         overlay: Option<&'a Menu>,
     },
 }
@@ -128,9 +146,28 @@ impl UiRenderer {
         quad_commands: &'a mut Vec<QuadRendererCommand<'this>>,
         glyph_commands: &'a mut Vec<GlyphRendererCommand>,
     ) {
+        // TODO: get from cvar
+        let scale = 2.0;
+
         let (hud_state, overlay) = match ui_state {
             UiState::Title { overlay } => (None, overlay.as_ref()),
-            UiState::InGame { hud, overlay } => (Some(hud), overlay.as_ref()),
+            UiState::Connecting { progress, overlay } => {
+                connecting::generate_commands(progress, scale, glyph_commands);
+                (None, overlay.as_ref())
+            }
+            UiState::InGame {
+                hud,
+                world_text: labels,
+                speeds: report,
+                overlay,
+            } => {
+                // before the HUD, so that the HUD is drawn over it
+                world_text::generate_commands(labels, target_size, scale, glyph_commands);
+                if let Some(report) = report {
+                    speeds::generate_commands(report, scale, glyph_commands);
+                }
+                (Some(hud), overlay.as_ref())
+            }
         };
 
         if let Some(hstate) = hud_state {
@@ -156,6 +193,51 @@ impl UiRenderer {
     }
 }
 
+/// Set to draw the next frame without any UI, such as for a screenshot of just the view. The
+/// HUD and menus drawn by [`UiPass`] are skipped, and the Bevy UI nodes, which include the
+/// console and the chat line, are hidden. It is cleared again once the frame has been drawn.
+#[derive(Resource, ExtractResource, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HideUi(pub bool);
+
+/// The UI nodes hidden for a frame drawn with [`HideUi`], with their visibility from before.
+#[derive(Resource, Default)]
+pub struct HiddenUiNodes(Vec<(Entity, Visibility)>);
+
+/// Hides every UI node for a frame drawn with [`HideUi`]. This has to run after everything else
+/// that sets the visibility of nodes during the frame.
+pub fn hide_ui_nodes(
+    hide_ui: Res<HideUi>,
+    mut hidden: ResMut<HiddenUiNodes>,
+    mut nodes: Query<(Entity, &mut Visibility), (With<Node>, Without<Parent>)>,
+) {
+    if !hide_ui.0 {
+        return;
+    }
+
+    for (entity, mut visibility) in &mut nodes {
+        hidden.0.push((entity, *visibility));
+        *visibility = Visibility::Hidden;
+    }
+}
+
+/// Shows the nodes hidden by [`hide_ui_nodes`] again and clears [`HideUi`], once the frame
+/// without UI has been drawn.
+pub fn restore_ui_nodes(
+    mut hide_ui: ResMut<HideUi>,
+    mut hidden: ResMut<HiddenUiNodes>,
+    mut nodes: Query<&mut Visibility>,
+) {
+    for (entity, visibility) in hidden.0.drain(..) {
+        if let Ok(mut node) = nodes.get_mut(entity) {
+            *node = visibility;
+        }
+    }
+
+    if hide_ui.0 {
+        hide_ui.0 = false;
+    }
+}
+
 #[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
 pub struct UiPassLabel;
 
@@ -166,12 +248,36 @@ impl ViewNode for UiPass {
     type ViewQuery = (&'static ViewTarget, &'static Camera3d);
 
     fn run<'w>(
+        &self,
+        graph: &mut bevy::render::render_graph::RenderGraphContext,
+        render_context: &mut bevy::render::renderer::RenderContext<'w>,
+        view: (&ViewTarget, &Camera3d),
+        world: &'w World,
+    ) -> Result<(), bevy::render::render_graph::NodeRunError> {
+        // this is the last pass of the frame, so the frame's counts are finished here
+        let start = Instant::now();
+        let result = self.draw(graph, render_context, view, world);
+        if let Some(speeds) = world.get_resource::<RenderSpeeds>() {
+            speeds.set_pass_time(SpeedsPass::Ui, start.elapsed());
+            speeds.finish_frame();
+        }
+
+        result
+    }
+}
+
+impl UiPass {
+    fn draw<'w>(
         &self,
         _graph: &mut bevy::render::render_graph::RenderGraphContext,
         render_context: &mut bevy::render::renderer::RenderContext<'w>,
         (view_target, _): (&ViewTarget, &Camera3d),
         world: &'w World,
     ) -> Result<(), bevy::render::render_graph::NodeRunError> {
+        if let Some(HideUi(true)) = world.get_resource::<HideUi>() {
+            return Ok(());
+        }
+
         let gfx_state = world.resource::<GraphicsState>();
         let ui_renderer = world.resource::<UiRenderer>();
         let hud_cvars = world.resource::<HudVars>();
@@ -184,6 +290,32 @@ impl ViewNode for UiPass {
         };
         let menu = world.get_resource::<Menu>();
         let focus = world.resource::<InputFocus>();
+        let progress = match world.get_resource::<ConnectionState>() {
+            Some(ConnectionState::SignOn(_)) => world.get_resource::<ConnectionProgress>(),
+            _ => None,
+        };
+        let sound_indicators = world
+            .get_resource::<SoundIndicators>()
+            .map(|indicators| indicators.directions())
+            .unwrap_or_default();
+        let world_labels = match (conn, world.get_resource::<WorldText>()) {
+            (Some(render_state), Some(text)) if !text.labels().is_empty() => {
+                let fov = world
+                    .get_resource::<RenderVars>()
+                    .map(|vars| vars.fov)
+                    .unwrap_or(90.);
+                let camera = render_state.camera(width as f32 / height as f32, Deg(fov));
+                world_text::project(&camera, text.labels())
+            }
+            _ => Vec::new(),
+        };
+        let speeds = match (
+            world.get_resource::<RenderVars>(),
+            world.get_resource::<RenderSpeeds>(),
+        ) {
+            (Some(vars), Some(speeds)) if vars.speeds != 0 => Some(speeds.last()),
+            _ => None,
+        };
 
         let mut quad_commands = Vec::new();
         let mut glyph_commands = Vec::new();
@@ -202,34 +334,51 @@ impl ViewNode for UiPass {
             let mut final_pass = TrackedRenderPass::new(device, final_pass);
 
             if let Some(RenderState { .. }) = conn {
-                let ui_state = match conn {
-                    Some(RenderState {
-                        state: cl_state, ..
-                    }) => UiState::InGame {
-                        hud: match cl_state.intermission() {
-                            Some(kind) => HudState::Intermission {
-                                kind,
-                                completion_duration: cl_state.completion_time().unwrap()
-                                    - cl_state.start_time(),
-                                stats: cl_state.stats(),
-                            },
+                let overlay = match (focus, menu) {
+                    (InputFocus::Menu, menu) => menu,
+                    _ => None,
+                };
 
-                            None => HudState::InGame {
-                                items: cl_state.items(),
-                                item_pickup_time: cl_state.item_pickup_times(),
-                                stats: cl_state.stats(),
-                                face_anim_time: cl_state.face_anim_time(),
-                            },
-                        },
+                let ui_state = match (conn, progress) {
+                    // the world isn't drawn until sign-on has finished
+                    (Some(_), Some(progress)) => UiState::Connecting { progress, overlay },
+                    (
+                        Some(RenderState {
+                            state: cl_state, ..
+                        }),
+                        None,
+                    ) => {
+                        let scoreboard = Scoreboard {
+                            players: cl_state.players(),
+                            local_player: cl_state.view_entity_id().checked_sub(1),
+                        };
 
-                        overlay: match (focus, menu) {
-                            (InputFocus::Game, _) => None,
-                            (InputFocus::Menu, menu) => menu,
-                            _ => None,
-                        },
-                    },
+                        UiState::InGame {
+                            hud: match cl_state.intermission() {
+                                Some(kind) => HudState::Intermission {
+                                    kind,
+                                    completion_duration: cl_state.completion_time().unwrap()
+                                        - cl_state.start_time(),
+                                    stats: cl_state.stats(),
+                                    scoreboard: cl_state.deathmatch().then_some(scoreboard),
+                                },
+
+                                None => HudState::InGame {
+                                    items: cl_state.items(),
+                                    item_pickup_time: cl_state.item_pickup_times(),
+                                    stats: cl_state.stats(),
+                                    face_anim_time: cl_state.face_anim_time(),
+                                    sound_indicators,
+                                    scoreboard: hud_cvars.show_scores.then_some(scoreboard),
+                                },
+                            },
+                            world_text: &world_labels,
+                            speeds: speeds.as_ref(),
+                            overlay,
+                        }
+                    }
 
-                    None => UiState::Title {
+                    (None, _) => UiState::Title {
                         overlay: match (focus, menu) {
                             (InputFocus::Menu, menu) => menu,
                             (InputFocus::Game, _) => unreachable!(),
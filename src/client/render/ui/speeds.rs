@@ -0,0 +1,28 @@
+use crate::client::render::{
+    ui::{
+        glyph::{GlyphRendererCommand, GLYPH_HEIGHT},
+        layout::{Anchor, ScreenPosition},
+    },
+    SpeedsReport,
+};
+
+/// Generate render commands to draw the `r_speeds` counts of the last frame in the top left
+/// corner of the screen.
+pub fn generate_commands(
+    report: &SpeedsReport,
+    scale: f32,
+    glyph_cmds: &mut Vec<GlyphRendererCommand>,
+) {
+    for (i, line) in report.lines().into_iter().enumerate() {
+        glyph_cmds.push(GlyphRendererCommand::Text {
+            text: line,
+            position: ScreenPosition::Relative {
+                anchor: Anchor::TOP_LEFT,
+                x_ofs: 0,
+                y_ofs: -((i * GLYPH_HEIGHT) as i32),
+            },
+            anchor: Anchor::TOP_LEFT,
+            scale,
+        });
+    }
+}
@@ -0,0 +1,91 @@
+use cgmath::{Vector2, Vector3};
+
+use crate::client::{
+    render::{
+        ui::{
+            glyph::GlyphRendererCommand,
+            layout::{Anchor, ScreenPosition},
+        },
+        world::Camera,
+        Extent2d,
+    },
+    world_text::{WorldLabel, WorldTextKind},
+};
+
+/// Text this far from the camera is drawn at the same size as the HUD. Nearer text is larger and
+/// further text smaller, within limits that keep it readable.
+const REFERENCE_DISTANCE: f32 = 128.;
+
+/// A label from the world, projected onto the screen.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScreenLabel {
+    pub kind: WorldTextKind,
+    pub text: String,
+    /// Position in normalized device coordinates, with `y` pointing up.
+    pub position: Vector2<f32>,
+    /// Distance from the camera along the view direction.
+    pub depth: f32,
+}
+
+/// Projects the labels that are in view onto the screen. Labels behind the camera or off the
+/// edges of the screen are left out.
+pub fn project(camera: &Camera, labels: &[WorldLabel]) -> Vec<ScreenLabel> {
+    labels
+        .iter()
+        .filter_map(|label| {
+            // the camera works in wgpu coordinates, see `Camera::new`
+            let origin = label.origin;
+            let converted = Vector3::new(-origin.y, origin.z, -origin.x);
+            let clip = camera.view_projection() * converted.extend(1.);
+            if clip.w <= 0. {
+                return None;
+            }
+
+            let position = Vector2::new(clip.x / clip.w, clip.y / clip.w);
+            if position.x.abs() > 1. || position.y.abs() > 1. {
+                return None;
+            }
+
+            Some(ScreenLabel {
+                kind: label.kind,
+                text: label.text.clone(),
+                position,
+                depth: clip.w,
+            })
+        })
+        .collect()
+}
+
+/// Generate render commands to draw `labels`, where `scale` is the scale of the HUD.
+pub fn generate_commands(
+    labels: &[ScreenLabel],
+    target_size: Extent2d,
+    scale: f32,
+    glyph_cmds: &mut Vec<GlyphRendererCommand>,
+) {
+    for label in labels {
+        let label_scale = (scale * REFERENCE_DISTANCE / label.depth).clamp(scale / 2., scale * 2.);
+
+        let text = match label.kind {
+            // damage is drawn in the console's alternate, coloured characters
+            WorldTextKind::Damage | WorldTextKind::Hit => {
+                label.text.bytes().map(|b| char::from(b | 0x80)).collect()
+            }
+            WorldTextKind::Pickup => label.text.clone(),
+        };
+
+        // offsets are multiplied by the scale of the text
+        let half_width = target_size.width as f32 / 2. / label_scale;
+        let half_height = target_size.height as f32 / 2. / label_scale;
+        glyph_cmds.push(GlyphRendererCommand::Text {
+            text,
+            position: ScreenPosition::Relative {
+                anchor: Anchor::CENTER,
+                x_ofs: (label.position.x * half_width) as i32,
+                y_ofs: (label.position.y * half_height) as i32,
+            },
+            anchor: Anchor::CENTER,
+            scale: label_scale,
+        });
+    }
+}
@@ -2,11 +2,13 @@ use std::{mem::size_of, ops::Range};
 
 use crate::{
     client::render::{
+        palette,
         world::{BindGroupLayoutId, WorldPipelineBase},
-        GraphicsState, Pipeline, TextureData,
+        DiffuseData, FullbrightData, GraphicsState, Pipeline, RenderSpeeds, TextureData,
     },
     common::{
         mdl::{self, AliasModel},
+        net::PlayerColor,
         util::any_slice_as_bytes,
     },
 };
@@ -25,6 +27,7 @@ use bevy::{
 use cgmath::{InnerSpace as _, Matrix4, Vector3, Zero as _};
 use chrono::Duration;
 use failure::Error;
+use hashbrown::HashMap;
 use lazy_static::lazy_static;
 
 pub struct AliasPipeline {
@@ -92,6 +95,13 @@ pub struct VertexPushConstants {
     pub model_view: Matrix4<f32>,
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct FragmentPushConstants {
+    /// Opacity of the model, from 0 to 1.
+    pub alpha: f32,
+}
+
 lazy_static! {
     static ref VERTEX_ATTRIBUTES: [wgpu::VertexAttribute; 3] =
         wgpu::vertex_attr_array![
@@ -109,7 +119,7 @@ lazy_static! {
 impl Pipeline for AliasPipeline {
     type VertexPushConstants = VertexPushConstants;
     type SharedPushConstants = ();
-    type FragmentPushConstants = ();
+    type FragmentPushConstants = FragmentPushConstants;
 
     type Args = <WorldPipelineBase as Pipeline>::Args;
 
@@ -140,6 +150,17 @@ impl Pipeline for AliasPipeline {
                     },
                     count: None,
                 },
+                // fullbright mask, the same size as the diffuse texture
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        multisampled: false,
+                    },
+                    count: None,
+                },
             ],
         ]
     }
@@ -173,12 +194,15 @@ type DiffuseTexcoord = [f32; 2];
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
-struct AliasVertex {
-    position: Position,
-    normal: Normal,
-    diffuse_texcoord: DiffuseTexcoord,
+pub(super) struct AliasVertex {
+    pub(super) position: Position,
+    pub(super) normal: Normal,
+    pub(super) diffuse_texcoord: DiffuseTexcoord,
 }
 
+/// The size of an [`AliasVertex`], for pipelines that only read its position.
+pub(super) const VERTEX_STRIDE: u64 = size_of::<AliasVertex>() as u64;
+
 enum Keyframe {
     Static {
         vertex_range: Range<u32>,
@@ -214,13 +238,16 @@ impl Keyframe {
     }
 }
 
+/// The diffuse and fullbright textures of a skin.
+pub(super) type SkinTextures = [CachedTexture; 2];
+
 enum Texture {
     Static {
-        _diffuse_texture: CachedTexture,
+        _textures: SkinTextures,
         bind_group: BindGroup,
     },
     Animated {
-        _diffuse_textures: Vec<CachedTexture>,
+        _textures: Vec<SkinTextures>,
         bind_groups: Vec<BindGroup>,
         total_duration: Duration,
         durations: Vec<Duration>,
@@ -252,10 +279,100 @@ impl Texture {
     }
 }
 
+/// Upload a skin and create the bind group that draws with it.
+fn create_skin(
+    state: &GraphicsState,
+    device: &RenderDevice,
+    queue: &RenderQueue,
+    width: u32,
+    height: u32,
+    indices: &[u8],
+) -> (SkinTextures, BindGroup) {
+    let (diffuse_data, fullbright_data) = state.palette.translate(indices);
+    create_rgba_skin(
+        state,
+        device,
+        queue,
+        width,
+        height,
+        diffuse_data,
+        fullbright_data,
+    )
+}
+
+/// Upload a skin that's already in color along with its fullbright mask, and create the bind group
+/// that draws with it.
+pub(super) fn create_rgba_skin(
+    state: &GraphicsState,
+    device: &RenderDevice,
+    queue: &RenderQueue,
+    width: u32,
+    height: u32,
+    diffuse_data: DiffuseData,
+    fullbright_data: FullbrightData,
+) -> (SkinTextures, BindGroup) {
+    let diffuse_texture = state.create_texture(
+        device,
+        queue,
+        None,
+        width,
+        height,
+        &TextureData::Diffuse(diffuse_data),
+    );
+    let fullbright_texture = state.create_texture(
+        device,
+        queue,
+        None,
+        width,
+        height,
+        &TextureData::Fullbright(fullbright_data),
+    );
+    let diffuse_view = diffuse_texture.create_view(&Default::default());
+    let fullbright_view = fullbright_texture.create_view(&Default::default());
+    let bind_group = device.create_bind_group(
+        None,
+        // TODO: per-pipeline bind group layout ids
+        &state.alias_pipeline().bind_group_layouts()[BindGroupLayoutId::PerTexture as usize - 2],
+        &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&diffuse_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&fullbright_view),
+            },
+        ],
+    );
+
+    (
+        [
+            CachedTexture {
+                texture: diffuse_texture,
+                default_view: diffuse_view,
+            },
+            CachedTexture {
+                texture: fullbright_texture,
+                default_view: fullbright_view,
+            },
+        ],
+        bind_group,
+    )
+}
+
 #[derive(Component)]
 pub struct AliasRenderer {
     keyframes: Vec<Keyframe>,
+    /// The minimum and maximum extent of each keyframe.
+    bounds: Vec<(Vector3<f32>, Vector3<f32>)>,
     textures: Vec<Texture>,
+    /// The width and height of every skin.
+    texture_size: (u32, u32),
+    /// The palette indices of each skin, or of the first frame of an animated one, which are
+    /// recolored for the players wearing it.
+    skin_indices: Vec<Box<[u8]>>,
+    /// Skins recolored for players, by skin and [`PlayerColor::bits`].
+    player_skins: HashMap<(usize, u8), Texture>,
     vertex_buffer: Buffer,
 }
 
@@ -364,77 +481,41 @@ impl AliasRenderer {
         });
 
         let mut textures = Vec::new();
+        let mut skin_indices = Vec::new();
         for texture in alias_model.textures() {
             match *texture {
                 mdl::Texture::Static(ref tex) => {
-                    let (diffuse_data, _fullbright_data) = state.palette.translate(tex.indices());
-                    let diffuse_texture = state.create_texture(
-                        device,
-                        queue,
-                        None,
-                        w,
-                        h,
-                        &TextureData::Diffuse(diffuse_data),
-                    );
-                    let diffuse_view = diffuse_texture.create_view(&Default::default());
-                    let bind_group = device.create_bind_group(
-                        None,
-                        // TODO: per-pipeline bind group layout ids
-                        &state.alias_pipeline().bind_group_layouts()
-                            [BindGroupLayoutId::PerTexture as usize - 2],
-                        &[wgpu::BindGroupEntry {
-                            binding: 0,
-                            resource: wgpu::BindingResource::TextureView(&diffuse_view),
-                        }],
-                    );
+                    let (skin_textures, bind_group) =
+                        create_skin(state, device, queue, w, h, tex.indices());
                     textures.push(Texture::Static {
-                        _diffuse_texture: CachedTexture {
-                            texture: diffuse_texture,
-                            default_view: diffuse_view,
-                        },
+                        _textures: skin_textures,
                         bind_group,
                     });
+                    skin_indices.push(tex.indices().into());
                 }
                 mdl::Texture::Animated(ref tex) => {
                     let mut total_duration = Duration::zero();
                     let mut durations = Vec::new();
-                    let mut diffuse_textures = Vec::new();
+                    let mut skin_textures = Vec::new();
                     let mut bind_groups = Vec::new();
 
                     for frame in tex.frames() {
                         total_duration = total_duration + frame.duration();
                         durations.push(frame.duration());
 
-                        let (diffuse_data, _fullbright_data) =
-                            state.palette.translate(frame.indices());
-                        let diffuse_texture = state.create_texture(
-                            device,
-                            queue,
-                            None,
-                            w,
-                            h,
-                            &TextureData::Diffuse(diffuse_data),
-                        );
-                        let diffuse_view = diffuse_texture.create_view(&Default::default());
-                        let bind_group = device.create_bind_group(
-                            None,
-                            &state.alias_pipeline().bind_group_layouts()
-                                [BindGroupLayoutId::PerTexture as usize - 2],
-                            &[wgpu::BindGroupEntry {
-                                binding: 0,
-                                resource: wgpu::BindingResource::TextureView(&diffuse_view),
-                            }],
-                        );
-
-                        diffuse_textures.push(CachedTexture {
-                            texture: diffuse_texture,
-                            default_view: diffuse_view,
-                        });
+                        let (frame_textures, bind_group) =
+                            create_skin(state, device, queue, w, h, frame.indices());
+                        skin_textures.push(frame_textures);
                         bind_groups.push(bind_group);
                     }
+                    skin_indices.push(
+                        tex.frames()
+                            .first()
+                            .map_or_else(Default::default, |frame| frame.indices().into()),
+                    );
 
                     textures.push(Texture::Animated {
-                        _diffuse_textures: diffuse_textures,
+                        _textures: skin_textures,
                         bind_groups,
                         total_duration,
                         durations,
@@ -445,11 +526,74 @@ impl AliasRenderer {
 
         Ok(AliasRenderer {
             keyframes,
+            bounds: alias_model
+                .keyframes()
+                .map(|keyframe| (keyframe.min(), keyframe.max()))
+                .collect(),
             textures,
+            texture_size: (w, h),
+            skin_indices,
+            player_skins: HashMap::new(),
             vertex_buffer,
         })
     }
 
+    /// Recolor skin `texture_id` in a player's colors, unless that's already been done, so that
+    /// the player can be drawn in them.
+    pub fn prepare_player_skin(
+        &mut self,
+        state: &GraphicsState,
+        device: &RenderDevice,
+        queue: &RenderQueue,
+        texture_id: usize,
+        colors: PlayerColor,
+    ) {
+        let key = (texture_id, colors.bits());
+        if self.player_skins.contains_key(&key) {
+            return;
+        }
+        let Some(indices) = self.skin_indices.get(texture_id) else {
+            return;
+        };
+
+        let translation = palette::player_translation(colors);
+        let indices = indices
+            .iter()
+            .map(|i| translation[*i as usize])
+            .collect::<Vec<_>>();
+        let (w, h) = self.texture_size;
+        let (skin_textures, bind_group) = create_skin(state, device, queue, w, h, &indices);
+        self.player_skins.insert(
+            key,
+            Texture::Static {
+                _textures: skin_textures,
+                bind_group,
+            },
+        );
+    }
+
+    /// Returns the minimum and maximum extent of the keyframe `keyframe_id` relative to the model
+    /// origin, covering all of its subframes.
+    pub fn bounds(&self, keyframe_id: usize) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        self.bounds.get(keyframe_id).copied()
+    }
+
+    /// Outline the triangles of keyframe `keyframe_id`, with the showtris pipeline and its push
+    /// constants already set.
+    pub fn record_showtris_draw<'a>(
+        &'a self,
+        pass: &mut TrackedRenderPass<'a>,
+        time: Duration,
+        keyframe_id: usize,
+    ) {
+        let Some(keyframe) = self.keyframes.get(keyframe_id).map(|k| k.animate(time)) else {
+            return;
+        };
+
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.draw(keyframe, 0..1);
+    }
+
     pub fn record_draw<'a>(
         &'a self,
         state: &'a GraphicsState,
@@ -457,11 +601,16 @@ impl AliasRenderer {
         time: Duration,
         keyframe_id: usize,
         texture_id: usize,
+        colors: Option<PlayerColor>,
+        speeds: &RenderSpeeds,
     ) {
         let Some(keyframe) = self.keyframes.get(keyframe_id).map(|k| k.animate(time)) else {
             return;
         };
-        let Some(tex) = self.textures.get(texture_id) else {
+        // players whose skin hasn't been recolored yet are drawn with the plain one
+        let player_skin =
+            colors.and_then(|colors| self.player_skins.get(&(texture_id, colors.bits())));
+        let Some(tex) = player_skin.or_else(|| self.textures.get(texture_id)) else {
             return;
         };
 
@@ -471,6 +620,7 @@ impl AliasRenderer {
         let tex = tex.animate(time);
 
         pass.set_bind_group(BindGroupLayoutId::PerTexture as usize, tex, &[]);
+        speeds.add_alias_polys(keyframe.len() as u32 / 3);
         pass.draw(keyframe, 0..1)
     }
 }
@@ -0,0 +1,283 @@
+//! Upscaling of the world for `r_scale`.
+//!
+//! With `r_scale` below 1 the initial and deferred passes only draw into the top-left corner of
+//! their targets, and this pass stretches that corner over the whole view without filtering, for
+//! the blocky look of software-rendered Quake at low resolutions. The UI is drawn afterwards, at
+//! the full resolution.
+
+use std::time::Instant;
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_graph::{RenderLabel, ViewNode},
+        render_phase::TrackedRenderPass,
+        render_resource::{
+            BindGroup, BindGroupLayout, BindGroupLayoutEntry, Buffer, RenderPassDescriptor,
+            RenderPipeline, Sampler, TextureView,
+        },
+        renderer::RenderDevice,
+        view::{PostProcessWrite, ViewTarget},
+    },
+};
+
+use crate::client::render::{
+    pipeline::{Pipeline, PushConstantUpdate},
+    ui::quad::QuadPipeline,
+    GraphicsState, RenderSpeeds, RenderState, RenderVars, SpeedsPass,
+};
+
+/// The size of a `width` by `height` target scaled down by `scale`, or `None` if the scene fills
+/// the whole target.
+pub fn scaled_size(width: u32, height: u32, scale: f32) -> Option<(u32, u32)> {
+    // this also leaves the scene unscaled if `scale` is NaN
+    if !(scale < 1.) || width == 0 || height == 0 {
+        return None;
+    }
+
+    let scaled = |size: u32| ((size as f32 * scale).round() as u32).clamp(1, size);
+    Some((scaled(width), scaled(height)))
+}
+
+/// The size of the top-left corner of `target` that the scene is drawn into at `r_scale` `scale`,
+/// or `None` if it fills the whole target.
+pub fn scene_viewport(target: &ViewTarget, scale: f32) -> Option<(u32, u32)> {
+    let wgpu::Extent3d { width, height, .. } = target.main_texture().size();
+    scaled_size(width, height, scale)
+}
+
+const BIND_GROUP_LAYOUT_ENTRIES: &[wgpu::BindGroupLayoutEntry] = &[
+    // sampler
+    wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+        count: None,
+    },
+    // source image
+    wgpu::BindGroupLayoutEntry {
+        binding: 1,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            multisampled: false,
+        },
+        count: None,
+    },
+];
+
+pub struct BlitPipeline {
+    pipeline: RenderPipeline,
+    bind_group_layouts: Vec<BindGroupLayout>,
+}
+
+impl BlitPipeline {
+    pub fn new(
+        device: &RenderDevice,
+        compiler: &mut shaderc::Compiler,
+        format: wgpu::TextureFormat,
+    ) -> BlitPipeline {
+        // the view is always resolved by the time the world is upscaled
+        let (pipeline, bind_group_layouts) = BlitPipeline::create(device, compiler, &[], 1, format);
+
+        BlitPipeline {
+            pipeline,
+            bind_group_layouts,
+        }
+    }
+
+    pub fn pipeline(&self) -> &RenderPipeline {
+        &self.pipeline
+    }
+
+    pub fn bind_group_layouts(&self) -> &[BindGroupLayout] {
+        &self.bind_group_layouts
+    }
+
+    /// Create the bind group that reads the image to be blitted from `source`.
+    pub fn create_bind_group(
+        &self,
+        device: &RenderDevice,
+        sampler: &Sampler,
+        source: &TextureView,
+    ) -> BindGroup {
+        device.create_bind_group(
+            Some("blit bind group"),
+            &self.bind_group_layouts[0],
+            &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+            ],
+        )
+    }
+
+    /// Draw the image bound by `bind_group` over the whole target, stretching the part of it given
+    /// by `push_constants`.
+    pub fn record_draw<'a>(
+        &'a self,
+        pass: &mut TrackedRenderPass<'a>,
+        quad_vertices: &'a Buffer,
+        bind_group: &'a BindGroup,
+        push_constants: &'a FragmentPushConstants,
+    ) {
+        use PushConstantUpdate::*;
+
+        pass.set_render_pipeline(&self.pipeline);
+        Self::set_push_constants(pass, Clear, Clear, Update(push_constants));
+        pass.set_vertex_buffer(0, quad_vertices.slice(..));
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.draw(0..6, 0..1);
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct FragmentPushConstants {
+    /// The fraction of the source's width and height that the scene was drawn into.
+    pub source_scale: [f32; 2],
+}
+
+impl Pipeline for BlitPipeline {
+    type VertexPushConstants = ();
+    type SharedPushConstants = ();
+    type FragmentPushConstants = FragmentPushConstants;
+
+    type Args = wgpu::TextureFormat;
+
+    fn name() -> &'static str {
+        "blit"
+    }
+
+    fn bind_group_layout_descriptors() -> Vec<Vec<BindGroupLayoutEntry>> {
+        vec![BIND_GROUP_LAYOUT_ENTRIES.to_owned()]
+    }
+
+    fn vertex_shader() -> &'static str {
+        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/blit.vert"))
+    }
+
+    fn fragment_shader() -> &'static str {
+        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/blit.frag"))
+    }
+
+    fn primitive_state() -> wgpu::PrimitiveState {
+        QuadPipeline::primitive_state()
+    }
+
+    fn color_target_states_with_args(format: Self::Args) -> Vec<Option<wgpu::ColorTargetState>> {
+        vec![Some(wgpu::ColorTargetState {
+            format,
+            blend: Some(wgpu::BlendState::REPLACE),
+            write_mask: wgpu::ColorWrites::ALL,
+        })]
+    }
+
+    fn depth_stencil_state() -> Option<wgpu::DepthStencilState> {
+        None
+    }
+
+    fn vertex_buffer_layouts() -> Vec<wgpu::VertexBufferLayout<'static>> {
+        QuadPipeline::vertex_buffer_layouts()
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+pub struct BlitPassLabel;
+
+#[derive(Default)]
+pub struct BlitPass;
+
+impl ViewNode for BlitPass {
+    type ViewQuery = &'static ViewTarget;
+
+    fn run<'w>(
+        &self,
+        _graph: &mut bevy::render::render_graph::RenderGraphContext,
+        render_context: &mut bevy::render::renderer::RenderContext<'w>,
+        target: &ViewTarget,
+        world: &'w World,
+    ) -> Result<(), bevy::render::render_graph::NodeRunError> {
+        let start = Instant::now();
+        let gfx_state = world.resource::<GraphicsState>();
+        let render_vars = world.resource::<RenderVars>();
+        let speeds = world.resource::<RenderSpeeds>();
+
+        // nothing was drawn into the corner if the world wasn't drawn
+        if world.get_resource::<RenderState>().is_none() {
+            return Ok(());
+        }
+
+        let Some((width, height)) = scene_viewport(target, render_vars.scale) else {
+            return Ok(());
+        };
+        let wgpu::Extent3d {
+            width: target_width,
+            height: target_height,
+            ..
+        } = target.main_texture().size();
+        let push_constants = FragmentPushConstants {
+            source_scale: [
+                width as f32 / target_width as f32,
+                height as f32 / target_height as f32,
+            ],
+        };
+
+        let PostProcessWrite {
+            source,
+            destination,
+        } = target.post_process_write();
+
+        // TODO: Cache
+        let blit_pipeline = gfx_state.blit_pipeline();
+        let bind_group = blit_pipeline.create_bind_group(
+            render_context.render_device(),
+            gfx_state.nearest_sampler(),
+            source,
+        );
+
+        let mut blit_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("blit"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: destination,
+                resolve_target: None,
+                ops: default(),
+            })],
+            ..default()
+        });
+
+        blit_pipeline.record_draw(
+            &mut blit_pass,
+            gfx_state.quad_pipeline().vertex_buffer(),
+            &bind_group,
+            &push_constants,
+        );
+        speeds.add_draw_call();
+        speeds.set_pass_time(SpeedsPass::Blit, start.elapsed());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scaled_size() {
+        assert_eq!(scaled_size(1280, 800, 1.), None);
+        assert_eq!(scaled_size(1280, 800, 2.), None);
+        assert_eq!(scaled_size(1280, 800, f32::NAN), None);
+        assert_eq!(scaled_size(1280, 800, 0.25), Some((320, 200)));
+        assert_eq!(scaled_size(1919, 1079, 0.5), Some((960, 540)));
+        // there's always at least one pixel to draw into
+        assert_eq!(scaled_size(1280, 800, 0.), Some((1, 1)));
+        assert_eq!(scaled_size(1280, 800, -1.), Some((1, 1)));
+    }
+}
@@ -19,6 +19,7 @@
 // SOFTWARE.
 
 use std::{
+    io::Read as _,
     mem::size_of,
     num::NonZeroU32,
     ops::Range,
@@ -33,15 +34,17 @@ use crate::{
         pipeline::PushConstantUpdate,
         warp,
         world::{BindGroupLayoutId, WorldPipelineBase},
-        Camera, GraphicsState, LightmapData, Pipeline, TextureData,
+        Camera, DiffuseData, FullbrightData, GraphicsState, LightmapData, Pipeline, RenderSpeeds,
+        TextureData,
     },
     common::{
         bsp::{
             self, BspData, BspFace, BspLeaf, BspModel, BspTexInfo, BspTexture, BspTextureKind,
-            BspTextureMipmap,
+            BspTextureMipmap, SurfaceKind,
         },
         math,
         util::any_slice_as_bytes,
+        vfs::Vfs,
     },
 };
 
@@ -62,9 +65,14 @@ use cgmath::{InnerSpace as _, Matrix4, Vector3};
 use chrono::Duration;
 use failure::Error;
 use hashbrown::HashMap;
+use image::{imageops::FilterType, ImageFormat, RgbaImage};
 use lazy_static::lazy_static;
 use num::Zero;
 
+/// The image formats that texture replacements are looked for in, in order.
+const REPLACEMENT_FORMATS: [(&str, ImageFormat); 2] =
+    [("tga", ImageFormat::Tga), ("png", ImageFormat::Png)];
+
 pub struct BrushPipeline {
     pipeline: RenderPipeline,
     bind_group_layouts: Vec<BindGroupLayout>,
@@ -142,6 +150,13 @@ pub struct SharedPushConstants {
     pub texture_kind: u32,
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct FragmentPushConstants {
+    /// Opacity of the model, from 0 to 1.
+    pub alpha: f32,
+}
+
 const BIND_GROUP_LAYOUT_ENTRIES: &[&[BindGroupLayoutEntry]] = &[
     &[
         // diffuse texture, updated once per face
@@ -201,7 +216,7 @@ lazy_static! {
 impl Pipeline for BrushPipeline {
     type VertexPushConstants = VertexPushConstants;
     type SharedPushConstants = SharedPushConstants;
-    type FragmentPushConstants = ();
+    type FragmentPushConstants = FragmentPushConstants;
 
     type Args = <WorldPipelineBase as Pipeline>::Args;
 
@@ -281,6 +296,9 @@ struct BrushVertex {
     lightmap_anim: LightmapAnim,
 }
 
+/// The size of a [`BrushVertex`], for pipelines that only read its position.
+pub(super) const VERTEX_STRIDE: u64 = size_of::<BrushVertex>() as u64;
+
 #[repr(u32)]
 #[derive(Clone, Copy, Debug)]
 pub enum TextureKind {
@@ -289,6 +307,89 @@ pub enum TextureKind {
     Sky = 2,
 }
 
+/// A high-resolution image to draw in place of a texture embedded in the BSP file.
+struct TextureReplacement {
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+    fullbright: Vec<u8>,
+}
+
+/// Finds replacements for the textures of a level in the VFS.
+///
+/// The replacement for a texture is `textures/<name>.tga` or `textures/<name>.png`, with the `*`
+/// of liquid textures written as `#`. Replacements have no fullbright texels unless they come with
+/// a `textures/<name>_luma` image, whose lit texels are drawn fullbright. Every brush model of a
+/// level shares the same textures, so each is only decoded once.
+pub struct TextureReplacements<'a> {
+    vfs: &'a Vfs,
+    replacements: HashMap<String, Option<TextureReplacement>>,
+}
+
+impl<'a> TextureReplacements<'a> {
+    pub fn new(vfs: &'a Vfs) -> TextureReplacements<'a> {
+        TextureReplacements {
+            vfs,
+            replacements: HashMap::new(),
+        }
+    }
+
+    fn get(&mut self, name: &str) -> Option<&TextureReplacement> {
+        let vfs = self.vfs;
+        self.replacements
+            .entry(name.to_owned())
+            .or_insert_with(|| {
+                let stem = format!("textures/{}", name.replace('*', "#"));
+                let diffuse = load_replacement_image(vfs, &stem)?;
+                let (width, height) = diffuse.dimensions();
+
+                let fullbright = match load_replacement_image(vfs, &format!("{}_luma", stem)) {
+                    Some(luma) => {
+                        let luma = if luma.dimensions() == (width, height) {
+                            luma
+                        } else {
+                            image::imageops::resize(&luma, width, height, FilterType::Triangle)
+                        };
+
+                        luma.pixels().map(|p| p[0].max(p[1]).max(p[2])).collect()
+                    }
+                    None => vec![0; (width * height) as usize],
+                };
+
+                Some(TextureReplacement {
+                    width,
+                    height,
+                    rgba: diffuse.into_raw(),
+                    fullbright,
+                })
+            })
+            .as_ref()
+    }
+}
+
+pub(super) fn load_replacement_image(vfs: &Vfs, stem: &str) -> Option<RgbaImage> {
+    REPLACEMENT_FORMATS.iter().find_map(|(ext, format)| {
+        let path = format!("{}.{}", stem, ext);
+        let mut data = Vec::new();
+        vfs.open(&path).ok()?.read_to_end(&mut data).ok()?;
+
+        match image::load_from_memory_with_format(&data, *format) {
+            Ok(image) => Some(image.into_rgba8()),
+            Err(e) => {
+                warn!("Couldn't load texture replacement {}: {}", path, e);
+                None
+            }
+        }
+    })
+}
+
+/// The name of frame `frame` of the animated texture with the stem `name`. Alternate frames are
+/// lettered rather than numbered.
+fn animation_frame_name(name: &str, frame: usize, alternate: bool) -> String {
+    let first = if alternate { b'a' } else { b'0' };
+    format!("+{}{}", (first + frame as u8) as char, name)
+}
+
 /// A single frame of a brush texture.
 pub struct BrushTextureFrame {
     bind_group_id: usize,
@@ -359,6 +460,7 @@ where
 pub struct BrushRendererBuilder {
     bsp_data: Arc<BspData>,
     face_range: Range<usize>,
+    bounds: (Vector3<f32>, Vector3<f32>),
 
     leaves: Option<Vec<BrushLeaf>>,
 
@@ -378,6 +480,7 @@ impl BrushRendererBuilder {
         BrushRendererBuilder {
             bsp_data: bsp_model.bsp_data(),
             face_range: bsp_model.face_id..bsp_model.face_id + bsp_model.face_count,
+            bounds: (bsp_model.min(), bsp_model.max()),
             leaves: if worldmodel {
                 Some(
                     bsp_model
@@ -424,7 +527,7 @@ impl BrushRendererBuilder {
             }
         }
 
-        if tex.name().starts_with("*") {
+        if tex.surface().is_warped() {
             // tessellate the surface so we can do texcoord warping
             let verts = warp::subdivide(no_collinear);
             let normal = match &*verts {
@@ -496,7 +599,7 @@ impl BrushRendererBuilder {
         let mut lightmap_ids = Vec::new();
         for lightmap in lightmaps {
             let lightmap_data = TextureData::Lightmap(LightmapData {
-                lightmap: Cow::borrowed(lightmap.data()),
+                lightmap: Cow::owned(lightmap.rgba()),
             });
 
             let texture = state.create_texture(
@@ -581,22 +684,33 @@ impl BrushRendererBuilder {
         )
     }
 
-    fn create_brush_texture_frame<S>(
+    fn create_brush_texture_frame(
         &mut self,
         state: &GraphicsState,
         device: &RenderDevice,
         queue: &RenderQueue,
+        replacement: Option<&TextureReplacement>,
         mipmap: &[u8],
         width: u32,
         height: u32,
-        name: S,
-    ) -> BrushTextureFrame
-    where
-        S: AsRef<str>,
-    {
-        let name = name.as_ref();
-
-        let (diffuse_data, fullbright_data) = state.palette().translate(mipmap);
+        surface: SurfaceKind,
+    ) -> BrushTextureFrame {
+        let (diffuse_data, fullbright_data, width, height) = match replacement {
+            Some(replacement) => (
+                DiffuseData {
+                    rgba: Cow::borrowed(&replacement.rgba),
+                },
+                FullbrightData {
+                    fullbright: Cow::borrowed(&replacement.fullbright),
+                },
+                replacement.width,
+                replacement.height,
+            ),
+            None => {
+                let (diffuse_data, fullbright_data) = state.palette().translate(mipmap);
+                (diffuse_data, fullbright_data, width, height)
+            }
+        };
         let diffuse = state.create_texture(
             device,
             queue,
@@ -614,12 +728,10 @@ impl BrushRendererBuilder {
             &TextureData::Fullbright(fullbright_data),
         );
 
-        let kind = if name.starts_with("sky") {
-            TextureKind::Sky
-        } else if name.starts_with("*") {
-            TextureKind::Warp
-        } else {
-            TextureKind::Normal
+        let kind = match surface {
+            SurfaceKind::Sky => TextureKind::Sky,
+            SurfaceKind::Liquid(_) => TextureKind::Warp,
+            _ => TextureKind::Normal,
         };
 
         let diffuse_view = diffuse.create_view(&default());
@@ -652,6 +764,7 @@ impl BrushRendererBuilder {
         state: &GraphicsState,
         device: &RenderDevice,
         queue: &RenderQueue,
+        replacements: &mut TextureReplacements,
         tex: &BspTexture,
     ) -> BrushTexture {
         // TODO: upload mipmaps
@@ -662,30 +775,34 @@ impl BrushRendererBuilder {
             BspTextureKind::Animated { primary, alternate } => {
                 let primary_frames: Vec<_> = primary
                     .iter()
-                    .map(|f| {
+                    .enumerate()
+                    .map(|(i, f)| {
                         self.create_brush_texture_frame(
                             state,
                             device,
                             queue,
+                            replacements.get(&animation_frame_name(tex.name(), i, false)),
                             f.mipmap(BspTextureMipmap::Full),
                             width,
                             height,
-                            tex.name(),
+                            tex.surface(),
                         )
                     })
                     .collect();
 
                 let alternate_frames: Option<Vec<_>> = alternate.as_ref().map(|a| {
                     a.iter()
-                        .map(|f| {
+                        .enumerate()
+                        .map(|(i, f)| {
                             self.create_brush_texture_frame(
                                 state,
                                 device,
                                 queue,
+                                replacements.get(&animation_frame_name(tex.name(), i, true)),
                                 f.mipmap(BspTextureMipmap::Full),
                                 width,
                                 height,
-                                tex.name(),
+                                tex.surface(),
                             )
                         })
                         .collect()
@@ -702,10 +819,11 @@ impl BrushRendererBuilder {
                     state,
                     device,
                     queue,
+                    replacements.get(tex.name()),
                     bsp_tex.mipmap(BspTextureMipmap::Full),
                     tex.width(),
                     tex.height(),
-                    tex.name(),
+                    tex.surface(),
                 ))
             }
         }
@@ -716,10 +834,11 @@ impl BrushRendererBuilder {
         state: &GraphicsState,
         device: &RenderDevice,
         queue: &RenderQueue,
+        replacements: &mut TextureReplacements,
     ) -> Result<BrushRenderer, Error> {
         // create the diffuse and fullbright textures
         for tex in self.bsp_data.clone().textures().iter() {
-            let tex = self.create_brush_texture(state, device, queue, tex);
+            let tex = self.create_brush_texture(state, device, queue, replacements, tex);
             self.textures.push(tex);
         }
 
@@ -731,12 +850,15 @@ impl BrushRendererBuilder {
             let face = self.create_face(state, device, queue, bsp_face_id);
             self.faces.push(face);
 
+            // faces that are never drawn are kept so that face ids line up, but left out of the
+            // texture chains
             let face_tex_id = self.faces[face_id].texture_id;
-            // update the corresponding texture chain
-            self.texture_chains
-                .entry(face_tex_id)
-                .or_insert(Vec::new())
-                .push(face_id);
+            if self.bsp_data.face_surface(bsp_face_id).is_drawn() {
+                self.texture_chains
+                    .entry(face_tex_id)
+                    .or_insert(Vec::new())
+                    .push(face_id);
+            }
 
             // generate face bind group
             let per_face_bind_group = self.create_per_face_bind_group(state, device, face_id);
@@ -751,6 +873,7 @@ impl BrushRendererBuilder {
 
         Ok(BrushRenderer {
             bsp_data: self.bsp_data,
+            bounds: self.bounds,
             vertex_buffer,
             leaves: self.leaves,
             per_texture_bind_groups: self.per_texture_bind_groups,
@@ -758,7 +881,7 @@ impl BrushRendererBuilder {
             texture_chains: self.texture_chains,
             faces: self.faces,
             textures: self.textures,
-            _lightmaps: self.lightmaps,
+            lightmaps: self.lightmaps,
         })
     }
 }
@@ -766,6 +889,7 @@ impl BrushRendererBuilder {
 #[derive(Component)]
 pub struct BrushRenderer {
     bsp_data: Arc<BspData>,
+    bounds: (Vector3<f32>, Vector3<f32>),
 
     leaves: Option<Vec<BrushLeaf>>,
 
@@ -778,10 +902,85 @@ pub struct BrushRenderer {
     texture_chains: HashMap<usize, Vec<usize>>,
     faces: Vec<BrushFace>,
     textures: Vec<BrushTexture>,
-    _lightmaps: Vec<Texture>,
+    lightmaps: Vec<Texture>,
 }
 
 impl BrushRenderer {
+    /// Returns the number of lightmaps uploaded for this model.
+    pub fn lightmap_count(&self) -> usize {
+        self.lightmaps.len()
+    }
+
+    /// Returns the minimum and maximum extent of this model relative to its origin.
+    pub fn bounds(&self) -> (Vector3<f32>, Vector3<f32>) {
+        self.bounds
+    }
+
+    /// If this is a worldmodel, returns which of its leaves are potentially visible from
+    /// `origin`, or `None` if the leaf containing `origin` has no visibility data.
+    pub fn visible_leaves(&self, origin: Vector3<f32>) -> Option<Vec<bool>> {
+        let leaves = self.leaves.as_ref()?;
+        let leaf_id = self.bsp_data.find_leaf(origin);
+        if leaf_id == 0 || self.bsp_data.leaves().get(leaf_id)?.vis_offset.is_none() {
+            return None;
+        }
+
+        let mut visible = vec![false; leaves.len()];
+        visible[leaf_id] = true;
+        for visible_id in self.bsp_data.get_pvs(leaf_id, leaves.len()) {
+            if let Some(v) = visible.get_mut(visible_id) {
+                *v = true;
+            }
+        }
+
+        Some(visible)
+    }
+
+    /// Returns whether the box from `min` to `max` reaches any of the `visible` leaves of this
+    /// model, as returned by [`BrushRenderer::visible_leaves`].
+    pub fn box_in_leaves(&self, visible: &[bool], min: Vector3<f32>, max: Vector3<f32>) -> bool {
+        self.bsp_data.box_touches_leaf(min, max, |leaf_id| {
+            visible.get(leaf_id).copied().unwrap_or(false)
+        })
+    }
+
+    /// If this is a worldmodel, mark the faces in the PVS of the camera to be drawn.
+    fn mark_visible_faces(&self, camera: &Camera) {
+        let Some(ref leaves) = self.leaves else {
+            return;
+        };
+
+        let pvs = self
+            .bsp_data
+            .get_pvs(self.bsp_data.find_leaf(camera.origin), leaves.len());
+
+        // only draw faces in pvs
+        for leaf_id in pvs {
+            for facelist_id in leaves[leaf_id].facelist_ids.clone() {
+                let face = &self.faces[self.bsp_data.facelist()[facelist_id]];
+
+                // TODO: frustum culling
+                face.draw_flag.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Outline the triangles of the faces that [`BrushRenderer::record_draw`] draws, with the
+    /// showtris pipeline and its push constants already set.
+    pub fn record_showtris_draw<'a>(&'a self, pass: &mut TrackedRenderPass<'a>, camera: &Camera) {
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        self.mark_visible_faces(camera);
+
+        for face_id in self.texture_chains.values().flatten() {
+            let face = &self.faces[*face_id];
+            if self.leaves.is_some() && !face.draw_flag.swap(false, Ordering::SeqCst) {
+                continue;
+            }
+
+            pass.draw(face.vertices.clone(), 0..1);
+        }
+    }
+
     /// Record the draw commands for this brush model to the given `wgpu::RenderPass`.
     pub fn record_draw<'a>(
         &'a self,
@@ -791,26 +990,11 @@ impl BrushRenderer {
         time: Duration,
         camera: &Camera,
         frame_id: usize,
+        speeds: &RenderSpeeds,
     ) {
         pass.set_render_pipeline(state.brush_pipeline().pipeline());
         pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-
-        // if this is a worldmodel, mark faces to be drawn
-        if let Some(ref leaves) = self.leaves {
-            let pvs = self
-                .bsp_data
-                .get_pvs(self.bsp_data.find_leaf(camera.origin), leaves.len());
-
-            // only draw faces in pvs
-            for leaf_id in pvs {
-                for facelist_id in leaves[leaf_id].facelist_ids.clone() {
-                    let face = &self.faces[self.bsp_data.facelist()[facelist_id]];
-
-                    // TODO: frustum culling
-                    face.draw_flag.store(true, Ordering::SeqCst);
-                }
-            }
-        }
+        self.mark_visible_faces(camera);
 
         for (tex_id, face_ids) in self.texture_chains.iter() {
             use PushConstantUpdate::*;
@@ -865,6 +1049,7 @@ impl BrushRenderer {
                 );
 
                 pass.draw(face.vertices.clone(), 0..1);
+                speeds.add_world_polys(1);
             }
         }
     }
@@ -0,0 +1,311 @@
+//! Debug drawing of outlined boxes, lines and markers in the world.
+//!
+//! Any system can add shapes to [`DebugDraw`] during `Update`; they are drawn for one frame.
+
+use std::mem::size_of;
+
+use crate::{
+    client::render::{
+        pipeline::{Pipeline, PushConstantUpdate},
+        world::{Camera, WorldPipelineBase},
+        RenderSpeeds,
+    },
+    common::util::any_slice_as_bytes,
+};
+
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::ExtractResource,
+        render_phase::TrackedRenderPass,
+        render_resource::{BindGroupLayout, BindGroupLayoutEntry, Buffer, RenderPipeline},
+        renderer::RenderDevice,
+    },
+};
+use bumpalo::Bump;
+use cgmath::{InnerSpace as _, Matrix4, Vector3, Vector4};
+use lazy_static::lazy_static;
+
+/// A box spanning `0..1` on every axis, transformed into the world.
+#[derive(Clone, Copy, Debug)]
+pub struct DebugShape {
+    /// Transform from the unit box to world space, in Quake coordinates.
+    pub model: Matrix4<f32>,
+
+    /// Color of the edges. The alpha channel is the opacity of the faces, so `0.0` draws a
+    /// wireframe and `1.0` a solid box.
+    pub color: [f32; 4],
+}
+
+/// Shapes to draw over the world this frame.
+#[derive(Resource, ExtractResource, Clone, Debug, Default)]
+pub struct DebugDraw {
+    shapes: Vec<DebugShape>,
+}
+
+impl DebugDraw {
+    pub fn clear(&mut self) {
+        self.shapes.clear();
+    }
+
+    pub fn shapes(&self) -> &[DebugShape] {
+        &self.shapes
+    }
+
+    /// Draw an axis-aligned box.
+    pub fn aabb(&mut self, mins: Vector3<f32>, maxs: Vector3<f32>, color: [f32; 4]) {
+        let size = maxs - mins;
+        self.shapes.push(DebugShape {
+            model: Matrix4::from_translation(mins)
+                * Matrix4::from_nonuniform_scale(size.x, size.y, size.z),
+            color,
+        });
+    }
+
+    /// Draw a solid cube of the given size centered on `origin`.
+    pub fn marker(&mut self, origin: Vector3<f32>, size: f32, color: [f32; 3]) {
+        let half = Vector3::new(size, size, size) / 2.;
+        self.aabb(
+            origin - half,
+            origin + half,
+            [color[0], color[1], color[2], 1.],
+        );
+    }
+
+    /// Draw a line between two points as a thin solid bar.
+    pub fn line(&mut self, start: Vector3<f32>, end: Vector3<f32>, color: [f32; 3]) {
+        const WIDTH: f32 = 1.;
+
+        let along = end - start;
+        if along.magnitude2() == 0. {
+            return;
+        }
+
+        // any two axes perpendicular to the line will do
+        let reference = if along.x.abs() < along.z.abs() {
+            Vector3::unit_x()
+        } else {
+            Vector3::unit_z()
+        };
+        let side = along.cross(reference).normalize_to(WIDTH);
+        let up = along.cross(side).normalize_to(WIDTH);
+        let corner = start - (side + up) / 2.;
+
+        self.shapes.push(DebugShape {
+            model: Matrix4::from_cols(
+                along.extend(0.),
+                side.extend(0.),
+                up.extend(0.),
+                corner.extend(1.),
+            ),
+            color: [color[0], color[1], color[2], 1.],
+        });
+    }
+}
+
+pub fn clear_debug_draw(mut draw: ResMut<DebugDraw>) {
+    draw.clear();
+}
+
+lazy_static! {
+    static ref VERTEX_ATTRIBUTES: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![
+        // position
+        0 => Float32x3,
+    ];
+
+    /// Converts from Quake's coordinate system to the renderer's.
+    static ref QUAKE_TO_WGPU: Matrix4<f32> = Matrix4::from_cols(
+        Vector4::new(0.0, 0.0, -1.0, 0.0),
+        Vector4::new(-1.0, 0.0, 0.0, 0.0),
+        Vector4::new(0.0, 1.0, 0.0, 0.0),
+        Vector4::new(0.0, 0.0, 0.0, 1.0),
+    );
+}
+
+/// Builds the triangles of a box spanning `0..1` on every axis.
+fn unit_cube() -> Vec<DebugVertex> {
+    // each face as a pair of axes spanning it, plus the fixed coordinate on the remaining axis
+    const FACES: [(usize, usize, usize); 3] = [(0, 1, 2), (1, 2, 0), (2, 0, 1)];
+
+    let mut vertices = Vec::with_capacity(36);
+    for (u, v, fixed) in FACES {
+        for side in [0.0, 1.0] {
+            for (du, dv) in [(0., 0.), (1., 0.), (1., 1.), (0., 0.), (1., 1.), (0., 1.)] {
+                let mut position = [0.0; 3];
+                position[u] = du;
+                position[v] = dv;
+                position[fixed] = side;
+                vertices.push(DebugVertex { position });
+            }
+        }
+    }
+
+    vertices
+}
+
+pub struct DebugBoxPipeline {
+    pipeline: RenderPipeline,
+    bind_group_layouts: Vec<BindGroupLayout>,
+    vertex_buffer: Buffer,
+    vertex_count: u32,
+}
+
+impl DebugBoxPipeline {
+    pub fn new(
+        device: &RenderDevice,
+        compiler: &mut shaderc::Compiler,
+        diffuse_format: wgpu::TextureFormat,
+        normal_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> DebugBoxPipeline {
+        let (pipeline, bind_group_layouts) = DebugBoxPipeline::create(
+            device,
+            compiler,
+            &[],
+            sample_count,
+            (diffuse_format, normal_format),
+        );
+
+        let vertices = unit_cube();
+        let vertex_buffer = device.create_buffer_with_data(&wgpu::util::BufferInitDescriptor {
+            label: Some("debug box vertex buffer"),
+            contents: unsafe { any_slice_as_bytes(&vertices) },
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        DebugBoxPipeline {
+            pipeline,
+            bind_group_layouts,
+            vertex_buffer,
+            vertex_count: vertices.len() as u32,
+        }
+    }
+
+    pub fn rebuild(
+        &mut self,
+        device: &RenderDevice,
+        compiler: &mut shaderc::Compiler,
+        diffuse_format: wgpu::TextureFormat,
+        normal_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) {
+        let layout_refs = self.bind_group_layouts.iter();
+        self.pipeline = DebugBoxPipeline::recreate(
+            device,
+            compiler,
+            layout_refs,
+            sample_count,
+            (diffuse_format, normal_format),
+        );
+    }
+
+    pub fn pipeline(&self) -> &RenderPipeline {
+        &self.pipeline
+    }
+
+    pub fn record_draw<'a>(
+        &'a self,
+        pass: &mut TrackedRenderPass<'a>,
+        bump: &'a Bump,
+        camera: &Camera,
+        shapes: &[DebugShape],
+        speeds: &RenderSpeeds,
+    ) {
+        use PushConstantUpdate::*;
+
+        pass.set_render_pipeline(self.pipeline());
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+
+        for shape in shapes {
+            Self::set_push_constants(
+                pass,
+                Update(bump.alloc(VertexPushConstants {
+                    transform: camera.view_projection() * *QUAKE_TO_WGPU * shape.model,
+                })),
+                Retain,
+                Update(bump.alloc(FragmentPushConstants { color: shape.color })),
+            );
+
+            pass.draw(0..self.vertex_count, 0..1);
+            speeds.add_draw_call();
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct VertexPushConstants {
+    pub transform: Matrix4<f32>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct FragmentPushConstants {
+    /// Edge color; the alpha channel is the opacity of the faces.
+    pub color: [f32; 4],
+}
+
+impl Pipeline for DebugBoxPipeline {
+    type VertexPushConstants = VertexPushConstants;
+    type SharedPushConstants = ();
+    type FragmentPushConstants = FragmentPushConstants;
+
+    type Args = <WorldPipelineBase as Pipeline>::Args;
+
+    fn name() -> &'static str {
+        "debug box"
+    }
+
+    fn vertex_shader() -> &'static str {
+        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/debug.vert"))
+    }
+
+    fn fragment_shader() -> &'static str {
+        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/debug.frag"))
+    }
+
+    fn bind_group_layout_descriptors() -> Vec<Vec<BindGroupLayoutEntry>> {
+        Vec::new()
+    }
+
+    fn primitive_state() -> wgpu::PrimitiveState {
+        WorldPipelineBase::primitive_state()
+    }
+
+    fn color_target_states_with_args(
+        (diffuse_format, normal_format): Self::Args,
+    ) -> Vec<Option<wgpu::ColorTargetState>> {
+        vec![
+            Some(wgpu::ColorTargetState {
+                format: diffuse_format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            }),
+            // leave the normals of whatever is behind the box untouched
+            Some(wgpu::ColorTargetState {
+                format: normal_format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::empty(),
+            }),
+        ]
+    }
+
+    fn depth_stencil_state() -> Option<wgpu::DepthStencilState> {
+        let mut desc = WorldPipelineBase::depth_stencil_state().unwrap();
+        desc.depth_write_enabled = false;
+        Some(desc)
+    }
+
+    // NOTE: if the vertex format is changed, this descriptor must also be changed accordingly.
+    fn vertex_buffer_layouts() -> Vec<wgpu::VertexBufferLayout<'static>> {
+        vec![wgpu::VertexBufferLayout {
+            array_stride: size_of::<DebugVertex>() as u64,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &VERTEX_ATTRIBUTES[..],
+        }]
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct DebugVertex {
+    position: [f32; 3],
+}
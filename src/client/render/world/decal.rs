@@ -0,0 +1,355 @@
+//! Drawing of bullet holes, blood and scorch marks over the world, for `r_decals`.
+//!
+//! Decals are blended into the diffuse G-buffer once the world and entities have been drawn, so
+//! that they're lit along with the surface they're on. Their textures are generated rather than
+//! loaded, since Quake has none.
+
+use std::{f32::consts::TAU, num::NonZeroU32};
+
+use crate::client::{
+    entity::decal::{Decal, DecalKind},
+    render::{
+        create_texture,
+        pipeline::{Pipeline, PushConstantUpdate},
+        world::{particle::ParticlePipeline, Camera, WorldPipelineBase, QUAKE_TO_RENDER},
+        DiffuseData, RenderSpeeds, TextureData,
+    },
+};
+
+use bevy::render::{
+    render_phase::TrackedRenderPass,
+    render_resource::{
+        BindGroup, BindGroupLayout, BindGroupLayoutEntry, Buffer, RenderPipeline, Texture,
+    },
+    renderer::{RenderDevice, RenderQueue},
+};
+use bumpalo::Bump;
+use cgmath::{Array as _, Matrix4, Vector3};
+use chrono::Duration;
+
+/// The width and height of each decal texture.
+const DECAL_TEXTURE_SIZE: u32 = 32;
+
+// if this is changed, it must also be changed in decal.frag
+const DECAL_KIND_COUNT: usize = DecalKind::ALL.len();
+
+/// The sRGB color and opacity of a decal of `kind` at `r`, from 0 at its centre to 1 at the middle
+/// of its edges, and at `angle` radians around it.
+fn decal_color(kind: DecalKind, r: f32, angle: f32) -> [f32; 4] {
+    match kind {
+        // a dark hole with a sooty ring around it
+        DecalKind::BulletHole => {
+            if r < 0.45 {
+                [0.06, 0.06, 0.06, 1.0]
+            } else {
+                let alpha = (1.0 - (r - 0.45) / 0.55).clamp(0.0, 1.0);
+                [0.16, 0.14, 0.12, 0.8 * alpha]
+            }
+        }
+
+        // a splash with a few lobes, and a hard edge
+        DecalKind::Blood => {
+            let edge = 0.65 + 0.2 * (5.0 * angle).cos() + 0.1 * (9.0 * angle + 1.0).cos();
+            let alpha = ((edge - r) * 8.0).clamp(0.0, 1.0);
+            [0.38, 0.03, 0.02, 0.9 * alpha]
+        }
+
+        // soot that thins out towards a ragged edge
+        DecalKind::Scorch => {
+            let edge = 0.85 + 0.1 * (7.0 * angle).cos();
+            let alpha = ((edge - r) / edge).clamp(0.0, 1.0).sqrt();
+            [0.03, 0.02, 0.02, 0.9 * alpha]
+        }
+    }
+}
+
+/// Generate the RGBA texture of decals of `kind`.
+fn decal_pixels(kind: DecalKind) -> Vec<u8> {
+    let size = DECAL_TEXTURE_SIZE;
+    let mut pixels = Vec::with_capacity((size * size * 4) as usize);
+    for y in 0..size {
+        for x in 0..size {
+            // sample at the centre of each texel, from -1 to 1 across the texture
+            let u = (x as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+            let v = (y as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+            let r = (u * u + v * v).sqrt();
+            let angle = v.atan2(u).rem_euclid(TAU);
+
+            let color = decal_color(kind, r, angle);
+            pixels.extend(color.map(|c| (c * 255.0).round() as u8));
+        }
+    }
+
+    pixels
+}
+
+pub struct DecalPipeline {
+    pipeline: RenderPipeline,
+    bind_group_layouts: Vec<BindGroupLayout>,
+    _textures: Vec<Texture>,
+    bind_group: BindGroup,
+}
+
+impl DecalPipeline {
+    pub fn new(
+        device: &RenderDevice,
+        queue: &RenderQueue,
+        compiler: &mut shaderc::Compiler,
+        diffuse_format: wgpu::TextureFormat,
+        normal_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> DecalPipeline {
+        let (pipeline, bind_group_layouts) = DecalPipeline::create(
+            device,
+            compiler,
+            &[],
+            sample_count,
+            (diffuse_format, normal_format),
+        );
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("decal sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_max_clamp: 1000.0,
+            compare: None,
+            ..Default::default()
+        });
+
+        let textures: Vec<_> = DecalKind::ALL
+            .iter()
+            .map(|&kind| {
+                create_texture(
+                    device,
+                    queue,
+                    Some(&format!("decal texture {:?}", kind)),
+                    DECAL_TEXTURE_SIZE,
+                    DECAL_TEXTURE_SIZE,
+                    &TextureData::Diffuse(DiffuseData {
+                        rgba: decal_pixels(kind).into(),
+                    }),
+                )
+            })
+            .collect();
+        let texture_views: Vec<_> = textures
+            .iter()
+            .map(|t| t.create_view(&Default::default()))
+            .collect();
+        let texture_view_refs = texture_views.iter().map(|t| &**t).collect::<Vec<_>>();
+
+        let bind_group = device.create_bind_group(
+            Some("decal bind group"),
+            &bind_group_layouts[0],
+            &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureViewArray(&texture_view_refs[..]),
+                },
+            ],
+        );
+
+        DecalPipeline {
+            pipeline,
+            bind_group_layouts,
+            _textures: textures,
+            bind_group,
+        }
+    }
+
+    pub fn rebuild(
+        &mut self,
+        device: &RenderDevice,
+        compiler: &mut shaderc::Compiler,
+        diffuse_format: wgpu::TextureFormat,
+        normal_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) {
+        let layout_refs = self.bind_group_layouts.iter();
+        self.pipeline = DecalPipeline::recreate(
+            device,
+            compiler,
+            layout_refs,
+            sample_count,
+            (diffuse_format, normal_format),
+        );
+    }
+
+    pub fn pipeline(&self) -> &RenderPipeline {
+        &self.pipeline
+    }
+
+    /// Draw `decals` as they are at `time`, with the quad that particles are drawn with.
+    pub fn record_draw<'a, 'b, D>(
+        &'a self,
+        pass: &mut TrackedRenderPass<'a>,
+        bump: &'a Bump,
+        camera: &Camera,
+        time: Duration,
+        quad_vertices: &'a Buffer,
+        decals: D,
+        speeds: &RenderSpeeds,
+    ) where
+        D: Iterator<Item = &'b Decal>,
+    {
+        use PushConstantUpdate::*;
+
+        pass.set_render_pipeline(self.pipeline());
+        pass.set_vertex_buffer(0, quad_vertices.slice(..));
+        pass.set_bind_group(0, &self.bind_group, &[]);
+
+        for decal in decals {
+            let alpha = decal.alpha(time);
+            let extent = Vector3::from_value(decal.kind().radius());
+            if alpha <= 0.0 || camera.cull_box(decal.origin() - extent, decal.origin() + extent) {
+                continue;
+            }
+
+            Self::set_push_constants(
+                pass,
+                Update(bump.alloc(VertexPushConstants {
+                    transform: camera.view_projection() * QUAKE_TO_RENDER * decal.model(),
+                })),
+                Retain,
+                Update(bump.alloc(FragmentPushConstants {
+                    kind: decal.kind() as u32,
+                    alpha,
+                })),
+            );
+
+            pass.draw(0..6, 0..1);
+            speeds.add_draw_call();
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct VertexPushConstants {
+    pub transform: Matrix4<f32>,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct FragmentPushConstants {
+    /// The index of the decal's texture, which is its `DecalKind`.
+    pub kind: u32,
+    /// How far the decal has faded, from 0 when it's gone to 1.
+    pub alpha: f32,
+}
+
+const BIND_GROUP_LAYOUT_ENTRIES: &[wgpu::BindGroupLayoutEntry] = &[
+    wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+        count: None,
+    },
+    // one texture for each kind of decal
+    wgpu::BindGroupLayoutEntry {
+        binding: 1,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            multisampled: false,
+        },
+        count: NonZeroU32::new(DECAL_KIND_COUNT as u32),
+    },
+];
+
+impl Pipeline for DecalPipeline {
+    type VertexPushConstants = VertexPushConstants;
+    type SharedPushConstants = ();
+    type FragmentPushConstants = FragmentPushConstants;
+
+    type Args = <WorldPipelineBase as Pipeline>::Args;
+
+    fn name() -> &'static str {
+        "decal"
+    }
+
+    fn vertex_shader() -> &'static str {
+        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/decal.vert"))
+    }
+
+    fn fragment_shader() -> &'static str {
+        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/decal.frag"))
+    }
+
+    fn bind_group_layout_descriptors() -> Vec<Vec<BindGroupLayoutEntry>> {
+        vec![BIND_GROUP_LAYOUT_ENTRIES.to_owned()]
+    }
+
+    fn primitive_state() -> wgpu::PrimitiveState {
+        WorldPipelineBase::primitive_state()
+    }
+
+    fn color_target_states_with_args(
+        (diffuse_format, normal_format): Self::Args,
+    ) -> Vec<Option<wgpu::ColorTargetState>> {
+        vec![
+            // the alpha channel holds the surface's lighting, which is left as it is
+            Some(wgpu::ColorTargetState {
+                format: diffuse_format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::COLOR,
+            }),
+            Some(wgpu::ColorTargetState {
+                format: normal_format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::empty(),
+            }),
+        ]
+    }
+
+    fn depth_stencil_state() -> Option<wgpu::DepthStencilState> {
+        let mut desc = WorldPipelineBase::depth_stencil_state().unwrap();
+        desc.depth_write_enabled = false;
+        Some(desc)
+    }
+
+    // decals are drawn one at a time with the particle quad, without its instances
+    fn vertex_buffer_layouts() -> Vec<wgpu::VertexBufferLayout<'static>> {
+        ParticlePipeline::vertex_buffer_layouts()[..1].to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decal_pixels() {
+        let texel = |pixels: &[u8], x: u32, y: u32| {
+            let i = ((y * DECAL_TEXTURE_SIZE + x) * 4) as usize;
+            [pixels[i], pixels[i + 1], pixels[i + 2], pixels[i + 3]]
+        };
+        let middle = DECAL_TEXTURE_SIZE / 2;
+
+        for kind in DecalKind::ALL {
+            let pixels = decal_pixels(kind);
+            assert_eq!(
+                pixels.len(),
+                (DECAL_TEXTURE_SIZE * DECAL_TEXTURE_SIZE * 4) as usize
+            );
+
+            // every decal is solid in the middle and fades out before the corners, so that the
+            // shape of the quad doesn't show
+            assert!(texel(&pixels, middle, middle)[3] > 200, "{:?}", kind);
+            assert_eq!(texel(&pixels, 0, 0)[3], 0, "{:?}", kind);
+            assert_eq!(
+                texel(&pixels, DECAL_TEXTURE_SIZE - 1, DECAL_TEXTURE_SIZE - 1)[3],
+                0,
+                "{:?}",
+                kind
+            );
+        }
+    }
+}
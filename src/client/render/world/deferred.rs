@@ -1,4 +1,11 @@
-use std::{mem::size_of, num::NonZeroU64, slice};
+//! Lighting of the G-buffer, with fog and dynamic lights.
+//!
+//! The screen is split into a grid of tiles, and each dynamic light is assigned to the tiles that
+//! its sphere covers before the pass is drawn. Each pixel then only loops over the lights in its
+//! own tile, so many lights can be on screen at once as long as only a few overlap any one part of
+//! it.
+
+use std::{mem::size_of, num::NonZeroU64, ops::RangeInclusive, slice, time::Instant};
 
 use bevy::{
     core_pipeline::prepass::ViewPrepassTextures,
@@ -16,30 +23,154 @@ use bevy::{
         view::{PostProcessWrite, ViewTarget},
     },
 };
-use cgmath::{Deg, Matrix4, SquareMatrix as _, Vector3};
+use cgmath::{Deg, InnerSpace as _, Matrix4, SquareMatrix as _, Vector3};
 
 use crate::client::{
     entity::MAX_LIGHTS,
     render::{
-        pipeline::Pipeline, ui::quad::QuadPipeline, GraphicsState, RenderConnectionKind,
-        RenderResolution, RenderState, RenderVars,
+        pipeline::Pipeline, ui::quad::QuadPipeline, world::blit, GraphicsState, RenderResolution,
+        RenderSpeeds, RenderState, RenderVars, SpeedsPass,
     },
 };
 
+/// How much overbright lighting brightens the world, which must match `OVERBRIGHT_SCALE` in
+/// `brush.frag`.
+const OVERBRIGHT_SCALE: f32 = 2.;
+
+// if these are changed, they must also be changed in deferred.frag
+const TILE_COLUMNS: usize = 16;
+const TILE_ROWS: usize = 16;
+const MAX_LIGHTS_PER_TILE: usize = 32;
+
+const TILE_COUNT: usize = TILE_COLUMNS * TILE_ROWS;
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug, bytemuck::Zeroable, bytemuck::Pod)]
 pub struct PointLight {
+    /// The origin of the light in view space.
     pub origin: [f32; 3],
     pub radius: f32,
 }
 
+impl PointLight {
+    /// How far the nearest point that the light reaches is from the camera.
+    fn distance(&self) -> f32 {
+        Vector3::from(self.origin).magnitude() - self.radius
+    }
+
+    /// The columns and rows of the tiles that the light reaches, or `None` if it's off screen.
+    fn tiles(
+        &self,
+        projection: Matrix4<f32>,
+    ) -> Option<(RangeInclusive<usize>, RangeInclusive<usize>)> {
+        let origin = Vector3::from(self.origin);
+        let all_tiles = (0..=TILE_COLUMNS - 1, 0..=TILE_ROWS - 1);
+
+        // the camera looks down -z, so the light is entirely behind it
+        if origin.z - self.radius >= 0.0 {
+            return None;
+        }
+
+        // project the corners of the box around the light's sphere, which bound it on screen
+        let (mut min, mut max) = ([f32::INFINITY; 2], [f32::NEG_INFINITY; 2]);
+        for corner in 0..8 {
+            let offset = Vector3::new(
+                if corner & 1 == 0 { -1.0 } else { 1.0 },
+                if corner & 2 == 0 { -1.0 } else { 1.0 },
+                if corner & 4 == 0 { -1.0 } else { 1.0 },
+            ) * self.radius;
+            let clip = projection * (origin + offset).extend(1.0);
+
+            // the box reaches behind the camera, where it can't be projected, so the light could
+            // be anywhere on screen
+            if clip.w <= f32::EPSILON {
+                return Some(all_tiles);
+            }
+
+            for axis in 0..2 {
+                let ndc = clip[axis] / clip.w;
+                min[axis] = min[axis].min(ndc);
+                max[axis] = max[axis].max(ndc);
+            }
+        }
+
+        if max[0] < -1.0 || min[0] > 1.0 || max[1] < -1.0 || min[1] > 1.0 {
+            return None;
+        }
+
+        // rows count down from the top of the screen, where y is 1
+        let tile = |ndc: f32, count: usize| {
+            ((ndc.clamp(-1.0, 1.0) + 1.0) / 2.0 * count as f32).min(count as f32 - 1.0) as usize
+        };
+        Some((
+            tile(min[0], TILE_COLUMNS)..=tile(max[0], TILE_COLUMNS),
+            tile(-max[1], TILE_ROWS)..=tile(-min[1], TILE_ROWS),
+        ))
+    }
+}
+
+/// The lights that reach each of the tiles that the screen is split into, from left to right and
+/// then top to bottom.
+pub struct LightTiles {
+    /// The number of lights in each tile.
+    counts: Vec<u32>,
+    /// The indices into `DeferredUniforms::lights` of the lights in each tile, with room for
+    /// `MAX_LIGHTS_PER_TILE` per tile.
+    lights: Vec<u32>,
+}
+
+impl LightTiles {
+    /// The size of the light tile buffer, which holds the counts followed by the indices.
+    const SIZE: usize = TILE_COUNT * (1 + MAX_LIGHTS_PER_TILE) * size_of::<u32>();
+
+    /// Assign `lights` to the tiles they reach when seen through `projection`. If more than
+    /// `MAX_LIGHTS_PER_TILE` reach a tile, the first of them are kept.
+    pub fn new(lights: &[PointLight], projection: Matrix4<f32>) -> LightTiles {
+        let mut tiles = LightTiles {
+            counts: vec![0; TILE_COUNT],
+            lights: vec![0; TILE_COUNT * MAX_LIGHTS_PER_TILE],
+        };
+
+        for (light_id, light) in lights.iter().enumerate() {
+            let Some((columns, rows)) = light.tiles(projection) else {
+                continue;
+            };
+
+            for row in rows {
+                for column in columns.clone() {
+                    let tile = row * TILE_COLUMNS + column;
+                    let count = tiles.counts[tile] as usize;
+                    if count < MAX_LIGHTS_PER_TILE {
+                        tiles.lights[tile * MAX_LIGHTS_PER_TILE + count] = light_id as u32;
+                        tiles.counts[tile] += 1;
+                    }
+                }
+            }
+        }
+
+        tiles
+    }
+
+    /// The indices of the lights in the tile at `column` and `row`.
+    pub fn tile(&self, column: usize, row: usize) -> &[u32] {
+        let tile = row * TILE_COLUMNS + column;
+        let start = tile * MAX_LIGHTS_PER_TILE;
+        &self.lights[start..start + self.counts[tile] as usize]
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug, bytemuck::Zeroable, bytemuck::Pod)]
 pub struct DeferredUniforms {
     pub inv_projection: [[f32; 4]; 4],
     pub light_count: u32,
     pub exposure: f32,
-    pub _pad: [u32; 2],
+    pub fog_density: f32,
+    /// How many times brighter than the lightmap level the world is lit, 2 with overbright
+    /// lighting and 1 without.
+    pub overbright_scale: f32,
+    /// The color of the fog, with the last component unused.
+    pub fog_color: [f32; 4],
     pub lights: [PointLight; MAX_LIGHTS],
 }
 
@@ -47,17 +178,21 @@ pub struct DeferredPipeline {
     pipeline: RenderPipeline,
     bind_group_layouts: Vec<BindGroupLayout>,
     uniform_buffer: Buffer,
+    light_tile_buffer: Buffer,
+    gbuffer_sample_count: u32,
 }
 
 impl DeferredPipeline {
+    /// Create the pipeline that lights a G-buffer with `gbuffer_sample_count` samples per pixel.
+    /// The lit image itself is never multisampled.
     pub fn new(
         device: &RenderDevice,
         compiler: &mut shaderc::Compiler,
         format: wgpu::TextureFormat,
-        sample_count: u32,
+        gbuffer_sample_count: u32,
     ) -> DeferredPipeline {
         let (pipeline, bind_group_layouts) =
-            DeferredPipeline::create(device, compiler, &[], sample_count, format);
+            DeferredPipeline::create(device, compiler, &[], 1, (format, gbuffer_sample_count));
 
         let uniform_buffer = device.create_buffer_with_data(&wgpu::util::BufferInitDescriptor {
             label: None,
@@ -65,7 +200,9 @@ impl DeferredPipeline {
                 inv_projection: Matrix4::identity().into(),
                 light_count: 0,
                 exposure: 0.,
-                _pad: default(),
+                fog_density: 0.,
+                overbright_scale: 1.,
+                fog_color: [0.; 4],
                 lights: [PointLight {
                     origin: [0.; 3],
                     radius: 0.0,
@@ -73,11 +210,19 @@ impl DeferredPipeline {
             }]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
+        let light_tile_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("light tile buffer"),
+            size: LightTiles::SIZE as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
 
         DeferredPipeline {
             pipeline,
             bind_group_layouts,
             uniform_buffer,
+            light_tile_buffer,
+            gbuffer_sample_count,
         }
     }
 
@@ -86,10 +231,15 @@ impl DeferredPipeline {
         device: &RenderDevice,
         compiler: &mut shaderc::Compiler,
         format: wgpu::TextureFormat,
-        sample_count: u32,
     ) {
         let layout_refs = self.bind_group_layouts.iter();
-        let pipeline = Self::recreate(device, compiler, layout_refs, sample_count, format);
+        let pipeline = Self::recreate(
+            device,
+            compiler,
+            layout_refs,
+            1,
+            (format, self.gbuffer_sample_count),
+        );
         self.pipeline = pipeline;
     }
 
@@ -104,6 +254,10 @@ impl DeferredPipeline {
     pub fn uniform_buffer(&self) -> &wgpu::Buffer {
         &self.uniform_buffer
     }
+
+    pub fn light_tile_buffer(&self) -> &wgpu::Buffer {
+        &self.light_tile_buffer
+    }
 }
 
 const BIND_GROUP_LAYOUT_ENTRIES: &[wgpu::BindGroupLayoutEntry] = &[
@@ -164,6 +318,17 @@ const BIND_GROUP_LAYOUT_ENTRIES: &[wgpu::BindGroupLayoutEntry] = &[
         },
         count: None,
     },
+    // light tile buffer
+    wgpu::BindGroupLayoutEntry {
+        binding: 6,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: NonZeroU64::new(LightTiles::SIZE as u64),
+        },
+        count: None,
+    },
 ];
 
 impl Pipeline for DeferredPipeline {
@@ -171,7 +336,8 @@ impl Pipeline for DeferredPipeline {
     type SharedPushConstants = ();
     type FragmentPushConstants = ();
 
-    type Args = wgpu::TextureFormat;
+    /// The format of the lit image and the sample count of the G-buffer.
+    type Args = (wgpu::TextureFormat, u32);
 
     fn name() -> &'static str {
         "deferred"
@@ -181,6 +347,33 @@ impl Pipeline for DeferredPipeline {
         vec![BIND_GROUP_LAYOUT_ENTRIES.to_owned()]
     }
 
+    fn bind_group_layout_descriptors_with_args(
+        &(_, gbuffer_sample_count): &Self::Args,
+    ) -> Vec<Vec<BindGroupLayoutEntry>> {
+        let mut entries = BIND_GROUP_LAYOUT_ENTRIES.to_owned();
+        if gbuffer_sample_count > 1 {
+            // the normal and depth buffers are multisampled, and read one sample at a time
+            for entry in &mut entries[3..=4] {
+                entry.ty = wgpu::BindingType::Texture {
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    multisampled: true,
+                };
+            }
+        }
+
+        vec![entries]
+    }
+
+    fn shader_defines(
+        &(_, gbuffer_sample_count): &Self::Args,
+    ) -> Vec<(&'static str, &'static str)> {
+        match gbuffer_sample_count {
+            1 => Vec::new(),
+            _ => vec![("MULTISAMPLED", "1")],
+        }
+    }
+
     fn vertex_shader() -> &'static str {
         include_str!(concat!(
             env!("CARGO_MANIFEST_DIR"),
@@ -199,7 +392,9 @@ impl Pipeline for DeferredPipeline {
         QuadPipeline::primitive_state()
     }
 
-    fn color_target_states_with_args(format: Self::Args) -> Vec<Option<wgpu::ColorTargetState>> {
+    fn color_target_states_with_args(
+        (format, _): Self::Args,
+    ) -> Vec<Option<wgpu::ColorTargetState>> {
         vec![Some(wgpu::ColorTargetState {
             format,
             blend: Some(wgpu::BlendState::REPLACE),
@@ -267,6 +462,15 @@ impl DeferredRenderer {
                         size: None,
                     }),
                 },
+                // light tile buffer
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: state.deferred_pipeline().light_tile_buffer(),
+                        offset: 0,
+                        size: None,
+                    }),
+                },
             ],
         )
     }
@@ -301,6 +505,7 @@ impl DeferredRenderer {
         state: &GraphicsState,
         queue: &RenderQueue,
         uniforms: DeferredUniforms,
+        light_tiles: &LightTiles,
     ) {
         // update color shift
         queue.write_buffer(
@@ -308,6 +513,18 @@ impl DeferredRenderer {
             0,
             bytemuck::cast_slice(slice::from_ref(&uniforms)),
         );
+
+        let light_tile_buffer = state.deferred_pipeline().light_tile_buffer();
+        queue.write_buffer(
+            light_tile_buffer,
+            0,
+            bytemuck::cast_slice(&light_tiles.counts),
+        );
+        queue.write_buffer(
+            light_tile_buffer,
+            (TILE_COUNT * size_of::<u32>()) as u64,
+            bytemuck::cast_slice(&light_tiles.lights),
+        );
     }
 
     pub fn record_draw<'this, 'a>(
@@ -316,8 +533,9 @@ impl DeferredRenderer {
         queue: &'a RenderQueue,
         pass: &'a mut TrackedRenderPass<'this>,
         uniforms: DeferredUniforms,
+        light_tiles: &LightTiles,
     ) {
-        self.update_uniform_buffers(state, queue, uniforms);
+        self.update_uniform_buffers(state, queue, uniforms, light_tiles);
         pass.set_render_pipeline(state.deferred_pipeline().pipeline());
         pass.set_vertex_buffer(0, state.quad_pipeline().vertex_buffer().slice(..));
         pass.set_bind_group(0, &self.bind_group, &[]);
@@ -351,6 +569,7 @@ impl ViewNode for DeferredPass {
         // indoor and so that seems to make most physical sense.
         const EXPOSURE_MULTIPLIER: f32 = 200.;
 
+        let start = Instant::now();
         let gfx_state = world.resource::<GraphicsState>();
         let conn = world.get_resource::<RenderState>();
         let queue = world.resource::<RenderQueue>();
@@ -360,14 +579,12 @@ impl ViewNode for DeferredPass {
             return Ok(());
         };
         let render_vars = world.resource::<RenderVars>();
+        let speeds = world.resource::<RenderSpeeds>();
 
-        let Some(RenderState {
-            state: cl_state,
-            kind,
-        }) = conn
-        else {
+        let Some(render_state) = conn else {
             return Ok(());
         };
+        let cl_state = &render_state.state;
 
         let PostProcessWrite {
             source: diffuse_input,
@@ -405,14 +622,7 @@ impl ViewNode for DeferredPass {
         let encoder = render_context.command_encoder();
 
         // if client is fully connected, draw world
-        let camera = match kind {
-            RenderConnectionKind::Demo => {
-                cl_state.demo_camera(width as f32 / height as f32, Deg(render_vars.fov as _))
-            }
-            RenderConnectionKind::Server => {
-                cl_state.camera(width as f32 / height as f32, Deg(render_vars.fov as _))
-            }
-        };
+        let camera = render_state.camera(width as f32 / height as f32, Deg(render_vars.fov as _));
 
         let deferred_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Deferred pass"),
@@ -429,33 +639,104 @@ impl ViewNode for DeferredPass {
         });
 
         let mut deferred_pass = TrackedRenderPass::new(device, deferred_pass);
+        if let Some((width, height)) = blit::scene_viewport(target, render_vars.scale) {
+            deferred_pass.set_viewport(0., 0., width as f32, height as f32, 0., 1.);
+        }
+
+        let mut active_lights: Vec<_> = cl_state
+            .iter_lights()
+            .filter(|_| render_vars.dynamic_lights != 0)
+            .map(|light| (light.origin(), light.radius(cl_state.time())))
+            .filter(|&(_, radius)| radius > 0.0)
+            .map(|(light_origin, radius)| {
+                let converted_origin =
+                    Vector3::new(-light_origin.y, light_origin.z, -light_origin.x);
+                PointLight {
+                    origin: (camera.view() * converted_origin.extend(1.0))
+                        .truncate()
+                        .into(),
+                    radius,
+                }
+            })
+            .collect();
+
+        // nearer lights come first, so that they're the ones kept when there are too many for the
+        // uniform buffer or for a tile
+        active_lights.sort_by(|a, b| a.distance().total_cmp(&b.distance()));
+        active_lights.truncate(MAX_LIGHTS);
+        let light_count = active_lights.len() as u32;
+        let light_tiles = LightTiles::new(&active_lights, camera.projection());
 
         let mut lights = [PointLight {
             origin: [0.; 3],
             radius: 0.0,
         }; MAX_LIGHTS];
+        lights[..active_lights.len()].copy_from_slice(&active_lights);
 
-        let mut light_count = 0;
-        for (light_id, light) in cl_state.iter_lights().enumerate().take(MAX_LIGHTS) {
-            light_count += 1;
-            let light_origin = light.origin();
-            let converted_origin = Vector3::new(-light_origin.y, light_origin.z, -light_origin.x);
-            lights[light_id].origin = (camera.view() * converted_origin.extend(1.0))
-                .truncate()
-                .into();
-            lights[light_id].radius = light.radius(cl_state.time());
-        }
-
+        let [fog_r, fog_g, fog_b] = cl_state.fog.color;
         let uniforms = DeferredUniforms {
             inv_projection: camera.inverse_projection().into(),
             light_count,
             exposure: EXPOSURE_MULTIPLIER * extracted_camera.exposure,
-            _pad: default(),
+            fog_density: cl_state.fog.density,
+            overbright_scale: match render_vars.overbright {
+                0 => 1.,
+                _ => OVERBRIGHT_SCALE,
+            },
+            fog_color: [fog_r, fog_g, fog_b, 0.],
             lights,
         };
 
-        deferred_renderer.record_draw(gfx_state, queue, &mut deferred_pass, uniforms);
+        deferred_renderer.record_draw(gfx_state, queue, &mut deferred_pass, uniforms, &light_tiles);
+        speeds.add_draw_call();
+        speeds.set_dynamic_lights(light_count);
+        speeds.set_pass_time(SpeedsPass::Deferred, start.elapsed());
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn light(x: f32, y: f32, z: f32, radius: f32) -> PointLight {
+        PointLight {
+            origin: [x, y, z],
+            radius,
+        }
+    }
+
+    #[test]
+    fn test_light_tiles() {
+        let projection = cgmath::perspective(Deg(90.), 1., 4.0, 4096.0);
+        let lights = [
+            // straight ahead, covering only the middle of the screen
+            light(0., 0., -100., 10.),
+            // behind the camera
+            light(0., 0., 100., 10.),
+            // around the camera, so it could light anything on screen
+            light(0., 0., 0., 50.),
+            // ahead and to the upper left
+            light(-90., 90., -100., 5.),
+        ];
+        let tiles = LightTiles::new(&lights, projection);
+
+        let (middle_column, middle_row) = (TILE_COLUMNS / 2, TILE_ROWS / 2);
+        assert_eq!(tiles.tile(middle_column, middle_row), &[0, 2]);
+        assert_eq!(tiles.tile(middle_column - 1, middle_row - 1), &[0, 2]);
+        assert_eq!(tiles.tile(0, 0), &[2, 3]);
+        assert_eq!(tiles.tile(TILE_COLUMNS - 1, TILE_ROWS - 1), &[2]);
+        assert_eq!(tiles.tile(0, TILE_ROWS - 1), &[2]);
+    }
+
+    #[test]
+    fn test_light_tiles_overflow() {
+        let projection = cgmath::perspective(Deg(90.), 1., 4.0, 4096.0);
+        let lights = vec![light(0., 0., -100., 10.); MAX_LIGHTS_PER_TILE + 4];
+        let tiles = LightTiles::new(&lights, projection);
+
+        let expected: Vec<u32> = (0..MAX_LIGHTS_PER_TILE as u32).collect();
+        assert_eq!(tiles.tile(TILE_COLUMNS / 2, TILE_ROWS / 2), &expected[..]);
+    }
+}
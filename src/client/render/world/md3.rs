@@ -0,0 +1,208 @@
+use std::ops::Range;
+
+use crate::{
+    client::render::{
+        world::{
+            alias::{self, AliasVertex, SkinTextures},
+            brush, BindGroupLayoutId,
+        },
+        DiffuseData, FullbrightData, GraphicsState, RenderSpeeds,
+    },
+    common::{
+        md3::{Md3Model, Md3Tag},
+        util::any_slice_as_bytes,
+        vfs::Vfs,
+    },
+};
+
+use beef::Cow;
+use bevy::{
+    prelude::*,
+    render::{
+        render_phase::TrackedRenderPass,
+        render_resource::{BindGroup, Buffer},
+        renderer::{RenderDevice, RenderQueue},
+    },
+};
+use cgmath::Vector3;
+
+struct Md3SurfaceRenderer {
+    /// The vertices of each frame.
+    frames: Vec<Range<u32>>,
+    _skin: SkinTextures,
+    bind_group: BindGroup,
+}
+
+/// Draws an MD3 model with the alias pipeline, one surface at a time.
+pub struct Md3Renderer {
+    surfaces: Vec<Md3SurfaceRenderer>,
+    /// The minimum and maximum extent of each frame.
+    bounds: Vec<(Vector3<f32>, Vector3<f32>)>,
+    tags: Vec<Vec<Md3Tag>>,
+    /// The vertices of every frame of every surface, or `None` if the model has none, as a buffer
+    /// can't be empty.
+    vertex_buffer: Option<Buffer>,
+}
+
+impl Md3Renderer {
+    /// Upload the model `name`. The skins of its surfaces are loaded from the images its shaders
+    /// name, or else from an image named after the surface next to the model, and surfaces with
+    /// neither are drawn white.
+    pub fn new(
+        state: &GraphicsState,
+        device: &RenderDevice,
+        queue: &RenderQueue,
+        vfs: &Vfs,
+        name: &str,
+        md3_model: &Md3Model,
+    ) -> Md3Renderer {
+        let model_dir = name.rsplit_once('/').map_or("", |(dir, _)| dir);
+
+        let mut vertices = Vec::new();
+        let mut surfaces = Vec::new();
+        for surface in md3_model.surfaces() {
+            let mut frames = Vec::new();
+            for frame_id in 0..md3_model.frames().len() {
+                let frame_vertices = surface.vertices(frame_id).unwrap_or_default();
+                let vertex_start = vertices.len() as u32;
+                for triangle in surface.triangles() {
+                    for index in triangle {
+                        let (Some(vertex), Some(texcoord)) = (
+                            frame_vertices.get(*index as usize),
+                            surface.texcoords().get(*index as usize),
+                        ) else {
+                            continue;
+                        };
+
+                        vertices.push(AliasVertex {
+                            position: vertex.position.into(),
+                            normal: vertex.normal.into(),
+                            diffuse_texcoord: *texcoord,
+                        });
+                    }
+                }
+                frames.push(vertex_start..vertices.len() as u32);
+            }
+
+            let image = surface
+                .shaders()
+                .iter()
+                .map(|shader| match shader.rsplit_once('.') {
+                    Some((stem, ext)) if !ext.contains('/') => stem.to_owned(),
+                    _ => shader.clone(),
+                })
+                .chain(std::iter::once(format!("{}/{}", model_dir, surface.name())))
+                .find_map(|stem| brush::load_replacement_image(vfs, &stem));
+            let (width, height, rgba) = match image {
+                Some(image) => (image.width(), image.height(), image.into_raw()),
+                None => {
+                    warn!("No skin for surface {} of {}", surface.name(), name);
+                    (1, 1, vec![0xFF; 4])
+                }
+            };
+            // replacement skins have no fullbright texels
+            let fullbright = vec![0; (width * height) as usize];
+            let (skin, bind_group) = alias::create_rgba_skin(
+                state,
+                device,
+                queue,
+                width,
+                height,
+                DiffuseData {
+                    rgba: Cow::owned(rgba),
+                },
+                FullbrightData {
+                    fullbright: Cow::owned(fullbright),
+                },
+            );
+
+            surfaces.push(Md3SurfaceRenderer {
+                frames,
+                _skin: skin,
+                bind_group,
+            });
+        }
+
+        let vertex_buffer = if vertices.is_empty() {
+            warn!("{} has no triangles to draw", name);
+            None
+        } else {
+            Some(
+                device.create_buffer_with_data(&wgpu::util::BufferInitDescriptor {
+                    label: None,
+                    contents: unsafe { any_slice_as_bytes(vertices.as_slice()) },
+                    usage: wgpu::BufferUsages::VERTEX,
+                }),
+            )
+        };
+
+        Md3Renderer {
+            surfaces,
+            bounds: md3_model
+                .frames()
+                .iter()
+                .map(|frame| (frame.min(), frame.max()))
+                .collect(),
+            tags: md3_model.tags().to_vec(),
+            vertex_buffer,
+        }
+    }
+
+    /// Returns the minimum and maximum extent of the frame `frame_id` relative to the model
+    /// origin.
+    pub fn bounds(&self, frame_id: usize) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        self.bounds.get(frame_id).copied()
+    }
+
+    /// Returns the tag called `name` as it's placed in frame `frame_id`.
+    pub fn tag(&self, frame_id: usize, name: &str) -> Option<&Md3Tag> {
+        self.tags
+            .get(frame_id)?
+            .iter()
+            .find(|tag| tag.name() == name)
+    }
+
+    /// Outline the triangles of frame `frame_id`, with the showtris pipeline and its push
+    /// constants already set.
+    pub fn record_showtris_draw<'a>(&'a self, pass: &mut TrackedRenderPass<'a>, frame_id: usize) {
+        let Some(vertex_buffer) = self.vertex_buffer.as_ref() else {
+            return;
+        };
+        if frame_id >= self.bounds.len() {
+            return;
+        }
+
+        pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        for surface in &self.surfaces {
+            pass.draw(surface.frames[frame_id].clone(), 0..1);
+        }
+    }
+
+    pub fn record_draw<'a>(
+        &'a self,
+        state: &'a GraphicsState,
+        pass: &mut TrackedRenderPass<'a>,
+        frame_id: usize,
+        speeds: &RenderSpeeds,
+    ) {
+        let Some(vertex_buffer) = self.vertex_buffer.as_ref() else {
+            return;
+        };
+        if frame_id >= self.bounds.len() {
+            return;
+        }
+
+        pass.set_render_pipeline(state.alias_pipeline().pipeline());
+        pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        for surface in &self.surfaces {
+            pass.set_bind_group(
+                BindGroupLayoutId::PerTexture as usize,
+                &surface.bind_group,
+                &[],
+            );
+            let vertices = surface.frames[frame_id].clone();
+            speeds.add_alias_polys(vertices.len() as u32 / 3);
+            pass.draw(vertices, 0..1);
+        }
+    }
+}
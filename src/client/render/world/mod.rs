@@ -1,33 +1,45 @@
 pub mod alias;
+pub mod blit;
 pub mod brush;
+pub mod debug;
+pub mod decal;
 pub mod deferred;
+pub mod md3;
 pub mod particle;
 pub mod postprocess;
+pub mod showtris;
 pub mod sprite;
 
-use std::mem::size_of;
+use std::{cmp::Ordering, mem::size_of};
 
 use crate::{
     client::{
-        entity::particle::Particle,
+        entity::decal::Decal,
         render::{
             pipeline::{Pipeline, PushConstantUpdate},
             uniform::{DynamicUniformBufferBlock, UniformBool},
             world::{
                 alias::{AliasPipeline, AliasRenderer},
-                brush::{BrushPipeline, BrushRenderer, BrushRendererBuilder},
+                brush::{BrushPipeline, BrushRenderer, BrushRendererBuilder, TextureReplacements},
+                debug::{DebugDraw, DebugShape},
+                md3::Md3Renderer,
+                showtris::{AliasShowTrisPipeline, BrushShowTrisPipeline},
                 sprite::{SpritePipeline, SpriteRenderer},
             },
             GraphicsState,
         },
+        state::PlayerInfo,
+        view::ViewModel,
         ClientEntity, ConnectionState,
     },
     common::{
         engine,
         math::Angles,
         model::{Model, ModelKind},
+        net::PlayerColor,
         sprite::SpriteKind,
         util::any_as_bytes,
+        vfs::Vfs,
     },
 };
 
@@ -41,12 +53,27 @@ use bevy::{
     },
 };
 use bumpalo::Bump;
-use cgmath::{Euler, InnerSpace, Matrix4, SquareMatrix as _, Vector3, Vector4};
+use cgmath::{Euler, InnerSpace, Matrix as _, Matrix4, SquareMatrix as _, Vector3, Vector4};
 use chrono::Duration;
 use lazy_static::lazy_static;
 use parking_lot::RwLock;
 
-use super::RenderVars;
+use super::{RenderSpeeds, RenderState, RenderVars};
+
+/// The part of the depth range that the view weapon is squeezed into.
+const VIEWMODEL_DEPTH_RANGE: f32 = 0.3;
+
+/// The tag of an MD3 model that its weapon is held by.
+const WEAPON_TAG: &str = "tag_weapon";
+
+/// Turns Quake's axes into the renderer's, as the alias vertex shader does to every vertex.
+#[rustfmt::skip]
+const QUAKE_TO_RENDER: Matrix4<f32> = Matrix4::new(
+    0.0, 0.0, -1.0, 0.0,
+    -1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.0, 1.0,
+);
 
 lazy_static! {
     static ref BIND_GROUP_LAYOUT_DESCRIPTOR_BINDINGS: [Vec<BindGroupLayoutEntry>; 2] = [
@@ -272,6 +299,31 @@ impl Camera {
 
         false
     }
+
+    /// Determines whether a box in world space falls entirely outside the viewing frustum.
+    pub fn cull_box(&self, min: Vector3<f32>, max: Vector3<f32>) -> bool {
+        let corners: [Vector4<f32>; 8] = std::array::from_fn(|i| {
+            let x = if i & 1 == 0 { min.x } else { max.x };
+            let y = if i & 2 == 0 { min.y } else { max.y };
+            let z = if i & 4 == 0 { min.z } else { max.z };
+
+            // convert coordinates, as in `Camera::new`
+            self.view_projection * Vector4::new(-y, z, -x, 1.0)
+        });
+
+        // the box is out of view if every corner is outside the same clipping plane
+        let outside: [fn(&Vector4<f32>) -> bool; 6] = [
+            |c| c.x < -c.w,
+            |c| c.x > c.w,
+            |c| c.y < -c.w,
+            |c| c.y > c.w,
+            |c| c.z < -c.w,
+            |c| c.z > c.w,
+        ];
+        outside
+            .iter()
+            .any(|outside| corners.iter().all(|c| outside(c)))
+    }
 }
 
 #[repr(C, align(256))]
@@ -284,6 +336,8 @@ pub struct FrameUniforms {
 
     // TODO: pack flags into a bit string
     r_lightmap: UniformBool,
+    r_fullbrights: UniformBool,
+    gl_overbright: UniformBool,
 }
 
 #[repr(C, align(256))]
@@ -300,9 +354,37 @@ enum EntityRenderer {
     Alias(AliasRenderer),
     Brush(BrushRenderer),
     Sprite(SpriteRenderer),
+    Md3(Md3Renderer),
     None,
 }
 
+/// The weapon that an entity holds, which is drawn at the weapon tag of the entity's model if it
+/// has one.
+///
+/// Only the view entity's weapon is known, as the server only sends each client the weapon of its
+/// own player, so other players are drawn empty-handed even when their model has the tag.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HeldWeapon {
+    pub entity_id: usize,
+    /// The index of the weapon's model, not counting the world model.
+    pub model_id: usize,
+}
+
+/// Debug views drawn over the world, which are set by cheat cvars.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DebugViews {
+    /// Outline every triangle of the world and entities that's drawn (`r_showtris`).
+    pub show_tris: bool,
+    /// Draw the box that each entity is culled with (`r_showbboxes`).
+    pub entity_boxes: bool,
+}
+
+/// The color of the culling boxes of entities that are drawn.
+const DRAWN_BOX_COLOR: [f32; 4] = [0.25, 0.75, 1.0, 0.0];
+
+/// The color of the culling boxes of entities that are culled.
+const CULLED_BOX_COLOR: [f32; 4] = [1.0, 0.25, 0.25, 0.0];
+
 static NO_ENTITY_RENDERER: EntityRenderer = EntityRenderer::None;
 
 /// Top-level renderer.
@@ -321,7 +403,9 @@ pub fn extract_world_renderer(
     mut gfx_state: ResMut<GraphicsState>,
     device: Res<RenderDevice>,
     queue: Res<RenderQueue>,
+    vfs: Res<Vfs>,
     game_state: Res<ConnectionState>,
+    speeds: Res<RenderSpeeds>,
 ) {
     info!("Updating world renderer");
     match &*game_state {
@@ -330,9 +414,11 @@ pub fn extract_world_renderer(
                 &mut *gfx_state,
                 &*device,
                 &*queue,
+                &vfs,
                 state.model_precache.iter(),
                 state.worldmodel_id,
             );
+            speeds.add_lightmap_uploads(new_renderer.lightmap_count() as u32);
             match world_renderer {
                 // TODO: Actually track changes to the connection
                 Some(mut world_renderer) => *world_renderer = new_renderer,
@@ -345,14 +431,62 @@ pub fn extract_world_renderer(
     }
 }
 
+/// Recolor the skins of the players in view before they're drawn.
+pub fn prepare_player_skins(
+    mut world_renderer: ResMut<WorldRenderer>,
+    gfx_state: Res<GraphicsState>,
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    render_state: Res<RenderState>,
+) {
+    let cl_state = &render_state.state;
+    for ent in cl_state.iter_visible_entities() {
+        let Some(colors) = player_colors(cl_state.players(), ent) else {
+            continue;
+        };
+
+        let renderer = world_renderer
+            .entity_renderers
+            .get_mut(ent.model_id().saturating_sub(1));
+        if let Some(EntityRenderer::Alias(alias)) = renderer {
+            alias.prepare_player_skin(&gfx_state, &device, &queue, ent.skin_id(), colors);
+        }
+    }
+}
+
+/// The colors of the player that `ent` is drawn as. Player entities have the player's slot plus
+/// one as their colormap, and everything else has 0.
+fn player_colors(players: &[Option<PlayerInfo>], ent: &ClientEntity) -> Option<PlayerColor> {
+    let slot = ent.colormap()?.checked_sub(1)?;
+    players.get(slot as usize)?.as_ref().map(|info| info.colors)
+}
+
+/// The order entities are drawn in: opaque entities first, in the order they came, and then
+/// translucent entities from the farthest to the nearest, so that each is drawn over whatever is
+/// behind it.
+fn draw_order(camera: &Camera, a: &ClientEntity, b: &ClientEntity) -> Ordering {
+    let translucent_distance = |ent: &ClientEntity| {
+        (ent.alpha() < 1.).then(|| (ent.get_origin() - camera.origin()).magnitude2())
+    };
+
+    match (translucent_distance(a), translucent_distance(b)) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Less,
+        (Some(_), None) => Ordering::Greater,
+        (Some(a), Some(b)) => b.total_cmp(&a),
+    }
+}
+
 impl WorldRenderer {
     pub fn new<'a, M: Iterator<Item = &'a Model>>(
         state: &'a mut GraphicsState,
         device: &RenderDevice,
         queue: &RenderQueue,
+        vfs: &Vfs,
         models: M,
         worldmodel_id: usize,
     ) -> WorldRenderer {
+        let mut replacements = TextureReplacements::new(vfs);
         let mut worldmodel_renderer = None;
         let mut entity_renderers = Vec::new();
 
@@ -367,7 +501,7 @@ impl WorldRenderer {
                     ModelKind::Brush(ref bmodel) => {
                         worldmodel_renderer = Some(
                             BrushRendererBuilder::new(bmodel, true)
-                                .build(state, device, queue)
+                                .build(state, device, queue, &mut replacements)
                                 .unwrap(),
                         );
                     }
@@ -382,7 +516,7 @@ impl WorldRenderer {
                     ModelKind::Brush(ref bmodel) => {
                         entity_renderers.push(EntityRenderer::Brush(
                             BrushRendererBuilder::new(bmodel, false)
-                                .build(state, device, queue)
+                                .build(state, device, queue, &mut replacements)
                                 .unwrap(),
                         ));
                     }
@@ -393,6 +527,17 @@ impl WorldRenderer {
                         )));
                     }
 
+                    ModelKind::Md3(ref md3_model) => {
+                        entity_renderers.push(EntityRenderer::Md3(Md3Renderer::new(
+                            state,
+                            device,
+                            queue,
+                            vfs,
+                            model.name(),
+                            md3_model,
+                        )));
+                    }
+
                     _ => {
                         warn!("Non-brush renderers not implemented!");
                         entity_renderers.push(EntityRenderer::None);
@@ -443,6 +588,8 @@ impl WorldRenderer {
                 time: time_secs,
                 sky_time: time_secs * render_vars.sky_scroll_speed as f32,
                 r_lightmap: UniformBool::new(render_vars.lightmap != 0),
+                r_fullbrights: UniformBool::new(render_vars.fullbrights != 0),
+                gl_overbright: UniformBool::new(render_vars.overbright != 0),
             })
         });
 
@@ -475,7 +622,21 @@ impl WorldRenderer {
         state.entity_uniform_buffer().flush(queue);
     }
 
-    pub fn render_pass<'a, E, P>(
+    /// Returns the number of lightmaps uploaded for the world and brush entity models.
+    pub fn lightmap_count(&self) -> usize {
+        let entity_lightmaps = self
+            .entity_renderers
+            .iter()
+            .map(|renderer| match renderer {
+                EntityRenderer::Brush(bmodel) => bmodel.lightmap_count(),
+                _ => 0,
+            })
+            .sum::<usize>();
+
+        self.worldmodel_renderer.lightmap_count() + entity_lightmaps
+    }
+
+    pub fn render_pass<'a, E, D>(
         &'a self,
         state: &'a GraphicsState,
         pass: &mut TrackedRenderPass<'a>,
@@ -483,11 +644,16 @@ impl WorldRenderer {
         camera: &Camera,
         time: Duration,
         entities: E,
-        particles: P,
-        viewmodel_id: Option<usize>,
+        players: &[Option<PlayerInfo>],
+        decals: D,
+        debug_shapes: &'a [DebugShape],
+        viewmodel: Option<ViewModel>,
+        held_weapon: Option<HeldWeapon>,
+        debug_views: DebugViews,
+        speeds: &RenderSpeeds,
     ) where
         E: Iterator<Item = &'a ClientEntity>,
-        P: Iterator<Item = &'a Particle>,
+        D: Iterator<Item = &'a Decal>,
     {
         use PushConstantUpdate::*;
         info!("Updating uniform buffers");
@@ -508,7 +674,7 @@ impl WorldRenderer {
                 model_view: camera.view(),
             })),
             Clear,
-            Clear,
+            Update(bump.alloc(brush::FragmentPushConstants { alpha: 1. })),
         );
         pass.set_bind_group(
             BindGroupLayoutId::PerEntity as usize,
@@ -523,11 +689,34 @@ impl WorldRenderer {
             time,
             camera,
             ((engine::duration_to_f32(time) + (0.05 / 2.)) / 0.05) as usize,
+            speeds,
         );
 
-        // draw entities
+        // draw entities, keeping the position of each to find its uniforms
         info!("Drawing entities");
-        for (ent_pos, ent) in entities.enumerate() {
+        let visible_leaves = self.worldmodel_renderer.visible_leaves(camera.origin());
+        let mut entities = entities
+            .enumerate()
+            .filter(|(_, ent)| ent.alpha() > 0.)
+            .collect::<Vec<_>>();
+        let mut entity_boxes = DebugDraw::default();
+        entities.retain(|(_, ent)| {
+            let in_view = self.entity_in_view(camera, visible_leaves.as_deref(), ent);
+            if debug_views.entity_boxes {
+                if let Some((min, max)) = self.entity_box(ent) {
+                    let color = if in_view {
+                        DRAWN_BOX_COLOR
+                    } else {
+                        CULLED_BOX_COLOR
+                    };
+                    entity_boxes.aabb(min, max, color);
+                }
+            }
+
+            in_view
+        });
+        entities.sort_by(|(_, a), (_, b)| draw_order(camera, a, b));
+        for &(ent_pos, ent) in &entities {
             if let Some(uniforms) = self.entity_uniform_blocks.read().get(ent_pos) {
                 pass.set_bind_group(
                     BindGroupLayoutId::PerEntity as usize,
@@ -545,9 +734,9 @@ impl WorldRenderer {
                                 model_view: self.calculate_mv_transform(camera, ent),
                             })),
                             Clear,
-                            Clear,
+                            Update(bump.alloc(brush::FragmentPushConstants { alpha: ent.alpha() })),
                         );
-                        bmodel.record_draw(state, pass, &bump, time, camera, ent.frame_id);
+                        bmodel.record_draw(state, pass, &bump, time, camera, ent.frame_id, speeds);
                     }
                     EntityRenderer::Alias(ref alias) => {
                         pass.set_render_pipeline(state.alias_pipeline().pipeline());
@@ -558,56 +747,245 @@ impl WorldRenderer {
                                 model_view: self.calculate_mv_transform(camera, ent),
                             })),
                             Clear,
+                            Update(bump.alloc(alias::FragmentPushConstants { alpha: ent.alpha() })),
+                        );
+                        alias.record_draw(
+                            state,
+                            pass,
+                            time,
+                            ent.frame_id(),
+                            ent.skin_id(),
+                            player_colors(players, ent),
+                            speeds,
+                        );
+                    }
+                    EntityRenderer::Md3(ref md3) => {
+                        AliasPipeline::set_push_constants(
+                            pass,
+                            Update(bump.alloc(alias::VertexPushConstants {
+                                transform: self.calculate_mvp_transform(camera, ent),
+                                model_view: self.calculate_mv_transform(camera, ent),
+                            })),
                             Clear,
+                            Update(bump.alloc(alias::FragmentPushConstants { alpha: ent.alpha() })),
                         );
-                        alias.record_draw(state, pass, time, ent.frame_id(), ent.skin_id());
+                        md3.record_draw(state, pass, ent.frame_id(), speeds);
+
+                        if let Some(weapon) = held_weapon.filter(|w| w.entity_id == ent.id) {
+                            if let Some(tag) = md3.tag(ent.frame_id(), WEAPON_TAG) {
+                                let model = self.calculate_model_transform(camera, ent)
+                                    * QUAKE_TO_RENDER
+                                    * tag.transform()
+                                    * QUAKE_TO_RENDER.transpose();
+                                self.record_weapon_draw(
+                                    state,
+                                    pass,
+                                    bump,
+                                    time,
+                                    weapon.model_id,
+                                    // weapons in the world don't animate
+                                    0,
+                                    alias::VertexPushConstants {
+                                        transform: camera.view_projection() * model,
+                                        model_view: camera.view() * model,
+                                    },
+                                    ent.alpha(),
+                                    speeds,
+                                );
+                            }
+                        }
                     }
                     EntityRenderer::Sprite(ref sprite) => {
                         pass.set_render_pipeline(state.sprite_pipeline().pipeline());
-                        SpritePipeline::set_push_constants(pass, Clear, Clear, Clear);
-                        sprite.record_draw(state, pass, ent.frame_id(), time);
+                        SpritePipeline::set_push_constants(
+                            pass,
+                            Clear,
+                            Clear,
+                            Update(
+                                bump.alloc(sprite::FragmentPushConstants { alpha: ent.alpha() }),
+                            ),
+                        );
+                        sprite.record_draw(state, pass, ent.frame_id(), time, speeds);
                     }
                     EntityRenderer::None => {}
                 }
             }
         }
 
-        let viewmodel_orig = camera.origin();
-        let cam_angles = camera.angles();
-        let viewmodel_mat = Matrix4::from_translation(Vector3::new(
-            -viewmodel_orig.y,
-            viewmodel_orig.z,
-            -viewmodel_orig.x,
-        )) * Matrix4::from_angle_y(cam_angles.yaw)
-            * Matrix4::from_angle_x(-cam_angles.pitch)
-            * Matrix4::from_angle_z(cam_angles.roll);
-        match viewmodel_id.and_then(|vid| self.entity_renderers.get(vid)) {
-            Some(EntityRenderer::Alias(ref alias)) => {
-                pass.set_render_pipeline(state.alias_pipeline().pipeline());
-                AliasPipeline::set_push_constants(
-                    pass,
-                    Update(bump.alloc(alias::VertexPushConstants {
-                        transform: camera.view_projection() * viewmodel_mat,
-                        model_view: camera.view() * viewmodel_mat,
-                    })),
-                    Clear,
-                    Clear,
-                );
-                alias.record_draw(state, pass, time, 0, 0);
-            }
-            Some(EntityRenderer::Brush(..)) => {
-                unreachable!("Viewmodel is brush - this should never happen")
-            }
-            Some(EntityRenderer::Sprite(..)) => {
-                // TODO: This is actually ok, how should we handle it?
-            }
-            None | Some(EntityRenderer::None) => {}
+        if let Some(viewmodel) = viewmodel {
+            self.record_viewmodel_draw(state, pass, bump, camera, time, viewmodel, speeds);
         }
 
+        debug!("Drawing decals");
+        state.decal_pipeline().record_draw(
+            pass,
+            &bump,
+            camera,
+            time,
+            state.particle_pipeline().vertex_buffer(),
+            decals,
+            speeds,
+        );
+
         debug!("Drawing particles");
         state
             .particle_pipeline()
-            .record_draw(pass, &bump, camera, particles);
+            .record_draw(pass, &bump, camera, speeds);
+
+        if debug_views.show_tris {
+            debug!("Drawing triangle outlines");
+            self.record_showtris_draw(state, pass, bump, camera, time, &entities);
+        }
+
+        if !debug_shapes.is_empty() || !entity_boxes.shapes().is_empty() {
+            let shapes = debug_shapes.iter().chain(entity_boxes.shapes()).copied();
+            state.debug_box_pipeline().record_draw(
+                pass,
+                &bump,
+                camera,
+                &shapes.collect::<Vec<_>>(),
+                speeds,
+            );
+        }
+    }
+
+    /// Outline the triangles of the world and of `entities` over everything drawn so far.
+    fn record_showtris_draw<'a>(
+        &'a self,
+        state: &'a GraphicsState,
+        pass: &mut TrackedRenderPass<'a>,
+        bump: &'a Bump,
+        camera: &Camera,
+        time: Duration,
+        entities: &[(usize, &ClientEntity)],
+    ) {
+        use PushConstantUpdate::*;
+
+        pass.set_render_pipeline(state.brush_showtris_pipeline().pipeline());
+        BrushShowTrisPipeline::set_push_constants(
+            pass,
+            Update(bump.alloc(showtris::VertexPushConstants {
+                transform: camera.view_projection(),
+            })),
+            Clear,
+            Clear,
+        );
+        self.worldmodel_renderer.record_showtris_draw(pass, camera);
+
+        for &(_, ent) in entities {
+            let transform = showtris::VertexPushConstants {
+                transform: self.calculate_mvp_transform(camera, ent),
+            };
+            match self.renderer_for_entity(ent) {
+                EntityRenderer::Brush(bmodel) => {
+                    pass.set_render_pipeline(state.brush_showtris_pipeline().pipeline());
+                    BrushShowTrisPipeline::set_push_constants(
+                        pass,
+                        Update(bump.alloc(transform)),
+                        Clear,
+                        Clear,
+                    );
+                    bmodel.record_showtris_draw(pass, camera);
+                }
+                EntityRenderer::Alias(alias) => {
+                    pass.set_render_pipeline(state.alias_showtris_pipeline().pipeline());
+                    AliasShowTrisPipeline::set_push_constants(
+                        pass,
+                        Update(bump.alloc(transform)),
+                        Clear,
+                        Clear,
+                    );
+                    alias.record_showtris_draw(pass, time, ent.frame_id());
+                }
+                EntityRenderer::Md3(md3) => {
+                    pass.set_render_pipeline(state.alias_showtris_pipeline().pipeline());
+                    AliasShowTrisPipeline::set_push_constants(
+                        pass,
+                        Update(bump.alloc(transform)),
+                        Clear,
+                        Clear,
+                    );
+                    md3.record_showtris_draw(pass, ent.frame_id());
+                }
+                EntityRenderer::Sprite(_) | EntityRenderer::None => {}
+            }
+        }
+    }
+
+    /// Draw the weapon held in front of the camera. Its depth is squeezed into the nearest part of
+    /// the depth range, so that it's drawn over walls that it would otherwise poke into.
+    fn record_viewmodel_draw<'a>(
+        &'a self,
+        state: &'a GraphicsState,
+        pass: &mut TrackedRenderPass<'a>,
+        bump: &'a Bump,
+        camera: &Camera,
+        time: Duration,
+        viewmodel: ViewModel,
+        speeds: &RenderSpeeds,
+    ) {
+        let origin = viewmodel.origin;
+        let angles = viewmodel.angles;
+        let model = Matrix4::from_translation(Vector3::new(-origin.y, origin.z, -origin.x))
+            * Matrix4::from_angle_y(angles.yaw)
+            * Matrix4::from_angle_x(-angles.pitch)
+            * Matrix4::from_angle_z(angles.roll);
+        let depth_hack = Matrix4::from_nonuniform_scale(1.0, 1.0, VIEWMODEL_DEPTH_RANGE);
+
+        self.record_weapon_draw(
+            state,
+            pass,
+            bump,
+            time,
+            viewmodel.model_id,
+            viewmodel.frame_id,
+            alias::VertexPushConstants {
+                transform: depth_hack * camera.view_projection() * model,
+                model_view: camera.view() * model,
+            },
+            1.,
+            speeds,
+        );
+    }
+
+    /// Draw frame `frame_id` of the weapon model `model_id`, which is either an alias or an MD3
+    /// model.
+    fn record_weapon_draw<'a>(
+        &'a self,
+        state: &'a GraphicsState,
+        pass: &mut TrackedRenderPass<'a>,
+        bump: &'a Bump,
+        time: Duration,
+        model_id: usize,
+        frame_id: usize,
+        transforms: alias::VertexPushConstants,
+        alpha: f32,
+        speeds: &RenderSpeeds,
+    ) {
+        use PushConstantUpdate::*;
+
+        let renderer = self.entity_renderers.get(model_id);
+        if !matches!(
+            renderer,
+            Some(EntityRenderer::Alias(_) | EntityRenderer::Md3(_))
+        ) {
+            return;
+        }
+
+        pass.set_render_pipeline(state.alias_pipeline().pipeline());
+        AliasPipeline::set_push_constants(
+            pass,
+            Update(bump.alloc(transforms)),
+            Clear,
+            Update(bump.alloc(alias::FragmentPushConstants { alpha })),
+        );
+        match renderer {
+            Some(EntityRenderer::Alias(alias)) => {
+                alias.record_draw(state, pass, time, frame_id, 0, None, speeds)
+            }
+            Some(EntityRenderer::Md3(md3)) => md3.record_draw(state, pass, frame_id, speeds),
+            _ => unreachable!(),
+        }
     }
 
     fn renderer_for_entity(&self, ent: &ClientEntity) -> &EntityRenderer {
@@ -618,6 +996,51 @@ impl WorldRenderer {
         }
     }
 
+    /// Returns a box in world space that contains `ent` as it's drawn this frame, if its model
+    /// has known bounds.
+    fn entity_box(&self, ent: &ClientEntity) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        let renderer = self.renderer_for_entity(ent);
+        let (min, max) = match renderer {
+            EntityRenderer::Alias(alias) => alias.bounds(ent.frame_id())?,
+            EntityRenderer::Md3(md3) => md3.bounds(ent.frame_id())?,
+            EntityRenderer::Brush(bmodel) => bmodel.bounds(),
+            EntityRenderer::Sprite(sprite) => sprite.bounds(),
+            EntityRenderer::None => return None,
+        };
+
+        let origin = ent.get_origin();
+        let scale = ent.scale();
+        let angles = ent.get_angles();
+        let unrotated = angles.x.0 == 0. && angles.y.0 == 0. && angles.z.0 == 0.;
+        if unrotated && !matches!(renderer, EntityRenderer::Sprite(_)) {
+            return Some((origin + min * scale, origin + max * scale));
+        }
+
+        // sprites turn to face the camera and other entities may be rotated, so use a box that
+        // fits them at any angle
+        let radius = min.magnitude().max(max.magnitude()) * scale;
+        let extent = Vector3::new(radius, radius, radius);
+        Some((origin - extent, origin + extent))
+    }
+
+    /// Determines whether `ent` can be seen by `camera`: whether any of it is inside the view
+    /// frustum and, if the camera's leaf has visibility data, in a leaf that's visible from it.
+    fn entity_in_view(
+        &self,
+        camera: &Camera,
+        visible_leaves: Option<&[bool]>,
+        ent: &ClientEntity,
+    ) -> bool {
+        let Some((min, max)) = self.entity_box(ent) else {
+            return true;
+        };
+
+        !camera.cull_box(min, max)
+            && visible_leaves.map_or(true, |visible| {
+                self.worldmodel_renderer.box_in_leaves(visible, min, max)
+            })
+    }
+
     fn calculate_mvp_transform(&self, camera: &Camera, entity: &ClientEntity) -> Matrix4<f32> {
         let model_transform = self.calculate_model_transform(camera, entity);
 
@@ -654,6 +1077,70 @@ impl WorldRenderer {
             _ => Matrix4::from(Euler::new(angles.x, angles.y, angles.z)),
         };
 
-        Matrix4::from_translation(Vector3::new(-origin.y, origin.z, -origin.x)) * rotation
+        Matrix4::from_translation(Vector3::new(-origin.y, origin.z, -origin.x))
+            * rotation
+            * Matrix4::from_scale(entity.scale())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::common::net::{self, EntityState};
+    use cgmath::Deg;
+
+    #[test]
+    fn test_cull_box() {
+        let camera = Camera::new(
+            Vector3::new(0., 0., 0.),
+            Angles::default(),
+            cgmath::perspective(Deg(73.74), 4. / 3., 4.0, 4096.0),
+        );
+        let extent = Vector3::new(16., 16., 16.);
+        let cull_at = |x, y| {
+            let origin = Vector3::new(x, y, 0.);
+            camera.cull_box(origin - extent, origin + extent)
+        };
+
+        // in front, and off to the side but still in view
+        assert!(!cull_at(100., 0.));
+        assert!(!cull_at(100., 90.));
+        // surrounding the camera
+        assert!(!cull_at(0., 0.));
+        // behind, beside and beyond the far plane
+        assert!(cull_at(-100., 0.));
+        assert!(cull_at(0., 100.));
+        assert!(cull_at(5000., 0.));
+    }
+
+    #[test]
+    fn test_draw_order() {
+        let camera = Camera::new(
+            Vector3::new(0., 0., 0.),
+            Angles::default(),
+            cgmath::perspective(Deg(73.74), 4. / 3., 4.0, 4096.0),
+        );
+        let entity = |id, x, alpha| {
+            ClientEntity::from_baseline(
+                id,
+                EntityState {
+                    origin: Vector3::new(x, 0., 0.),
+                    alpha: net::encode_alpha(alpha),
+                    ..EntityState::uninitialized()
+                },
+            )
+        };
+
+        let mut entities = vec![
+            entity(0, 100., 0.5),
+            entity(1, 300., 1.),
+            entity(2, 200., 0.5),
+            entity(3, 50., 1.),
+            entity(4, 400., 0.5),
+        ];
+        entities.sort_by(|a, b| draw_order(&camera, a, b));
+        let ids = entities.iter().map(|ent| ent.id).collect::<Vec<_>>();
+        assert_eq!(ids, [1, 3, 4, 2, 0]);
     }
 }
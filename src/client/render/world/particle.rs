@@ -1,40 +1,37 @@
-use std::{mem::size_of, num::NonZeroU32};
+//! Particles, which are simulated on the CPU and drawn in a single instanced draw.
+//!
+//! The live particles are uploaded to an instance buffer once per frame, before the world is
+//! drawn, and colored from the palette on the GPU.
+
+use std::mem::size_of;
 
 use crate::{
     client::{
-        entity::particle::Particle,
+        entity::particle::{Particle, MAX_PARTICLES},
         render::{
             create_texture,
             pipeline::{Pipeline, PushConstantUpdate},
             world::{Camera, WorldPipelineBase},
-            Palette, TextureData,
+            GraphicsState, Palette, RenderSpeeds, RenderState, TextureData,
         },
     },
     common::{math::Angles, util::any_slice_as_bytes},
 };
 
-use bevy::render::{
-    render_phase::TrackedRenderPass,
-    render_resource::{
-        BindGroup, BindGroupLayout, BindGroupLayoutEntry, Buffer, RenderPipeline, Texture,
+use bevy::{
+    prelude::*,
+    render::{
+        render_phase::TrackedRenderPass,
+        render_resource::{
+            BindGroup, BindGroupLayout, BindGroupLayoutEntry, Buffer, RenderPipeline, Texture,
+        },
+        renderer::{RenderDevice, RenderQueue},
     },
-    renderer::{RenderDevice, RenderQueue},
 };
 use bumpalo::Bump;
-use cgmath::Matrix4;
+use cgmath::{Matrix4, Vector4};
 use lazy_static::lazy_static;
 
-lazy_static! {
-    static ref VERTEX_BUFFER_ATTRIBUTES: [Vec<wgpu::VertexAttribute>; 1] = [
-        wgpu::vertex_attr_array![
-            // position
-            0 => Float32x3,
-            // texcoord
-            1 => Float32x2,
-        ].to_vec(),
-    ];
-}
-
 #[rustfmt::skip]
 const PARTICLE_TEXTURE_PIXELS: [u8; 64] = [
     0, 0, 1, 1, 1, 1, 0, 0,
@@ -47,11 +44,16 @@ const PARTICLE_TEXTURE_PIXELS: [u8; 64] = [
     0, 0, 1, 1, 1, 1, 0, 0,
 ];
 
+/// Half the width of each particle in world units.
+const PARTICLE_SIZE: f32 = 1.0;
+
 pub struct ParticlePipeline {
     pipeline: RenderPipeline,
     bind_group_layouts: Vec<BindGroupLayout>,
     _textures: Vec<Texture>,
     vertex_buffer: Buffer,
+    instance_buffer: Buffer,
+    instance_count: u32,
     bind_group: BindGroup,
 }
 
@@ -79,6 +81,13 @@ impl ParticlePipeline {
             usage: wgpu::BufferUsages::VERTEX,
         });
 
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("particle instance buffer"),
+            size: (MAX_PARTICLES * size_of::<ParticleInstance>()) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             label: Some("particle sampler"),
             address_mode_u: wgpu::AddressMode::ClampToEdge,
@@ -92,37 +101,31 @@ impl ParticlePipeline {
             ..Default::default()
         });
 
-        let textures: Vec<_> = (0..128)
-            .map(|i| {
-                let mut pixels = PARTICLE_TEXTURE_PIXELS;
-
-                // set up palette translation
-                for pix in pixels.iter_mut() {
-                    if *pix == 0 {
-                        *pix = 0xFF;
-                    } else {
-                        *pix *= i as u8;
-                    }
-                }
-
-                let (diffuse_data, _) = palette.translate(&pixels);
-
-                create_texture(
-                    device,
-                    queue,
-                    Some(&format!("particle texture {}", i)),
-                    8,
-                    8,
-                    &TextureData::Diffuse(diffuse_data),
-                )
-            })
-            .collect();
-        let texture_views: Vec<_> = textures
-            .iter()
-            .map(|t| t.create_view(&Default::default()))
-            .collect();
-        let texture_view_refs = texture_views.iter().map(|t| &**t).collect::<Vec<_>>();
+        // every particle has the same shape, and is colored from the palette by its instance
+        let shape_pixels = PARTICLE_TEXTURE_PIXELS.map(|pix| if pix == 0 { 0xFF } else { 0 });
+        let (shape_data, _) = palette.translate(&shape_pixels);
+        let shape_texture = create_texture(
+            device,
+            queue,
+            Some("particle shape texture"),
+            8,
+            8,
+            &TextureData::Diffuse(shape_data),
+        );
 
+        let palette_indices: Vec<u8> = (0..=255).collect();
+        let (palette_data, _) = palette.translate(&palette_indices);
+        let palette_texture = create_texture(
+            device,
+            queue,
+            Some("particle palette texture"),
+            256,
+            1,
+            &TextureData::Diffuse(palette_data),
+        );
+
+        let shape_view = shape_texture.create_view(&Default::default());
+        let palette_view = palette_texture.create_view(&Default::default());
         let bind_group = device.create_bind_group(
             Some("particle bind group"),
             &bind_group_layouts[0],
@@ -133,7 +136,11 @@ impl ParticlePipeline {
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::TextureViewArray(&texture_view_refs[..]),
+                    resource: wgpu::BindingResource::TextureView(&shape_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&palette_view),
                 },
             ],
         );
@@ -141,9 +148,11 @@ impl ParticlePipeline {
         ParticlePipeline {
             pipeline,
             bind_group_layouts,
-            _textures: textures,
+            _textures: vec![shape_texture, palette_texture],
             bind_group,
             vertex_buffer,
+            instance_buffer,
+            instance_count: 0,
         }
     }
 
@@ -177,20 +186,35 @@ impl ParticlePipeline {
         &self.vertex_buffer
     }
 
-    pub fn record_draw<'a, 'b, P>(
+    /// Upload the particles to draw this frame, of which only the first `MAX_PARTICLES` are kept.
+    pub fn update_instances<'a, P>(&mut self, queue: &RenderQueue, particles: P)
+    where
+        P: Iterator<Item = &'a Particle>,
+    {
+        let instances: Vec<_> = particles
+            .take(MAX_PARTICLES)
+            .map(ParticleInstance::new)
+            .collect();
+
+        queue.write_buffer(&self.instance_buffer, 0, unsafe {
+            any_slice_as_bytes(&instances)
+        });
+        self.instance_count = instances.len() as u32;
+    }
+
+    /// Draw the particles uploaded this frame in a single instanced draw.
+    pub fn record_draw<'a>(
         &'a self,
         pass: &mut TrackedRenderPass<'a>,
         bump: &'a Bump,
         camera: &Camera,
-        particles: P,
-    ) where
-        P: Iterator<Item = &'b Particle>,
-    {
+        speeds: &RenderSpeeds,
+    ) {
         use PushConstantUpdate::*;
 
-        pass.set_render_pipeline(self.pipeline());
-        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-        pass.set_bind_group(0, &self.bind_group, &[]);
+        if self.instance_count == 0 {
+            return;
+        }
 
         // face toward camera
         let Angles { pitch, yaw, roll } = camera.angles();
@@ -201,34 +225,66 @@ impl ParticlePipeline {
         }
         .mat4_wgpu();
 
-        for particle in particles {
-            let q_origin = particle.origin();
-            let translation =
-                Matrix4::from_translation([-q_origin.y, q_origin.z, -q_origin.x].into());
-            Self::set_push_constants(
-                pass,
-                Update(bump.alloc(VertexPushConstants {
-                    transform: camera.view_projection() * translation * rotation,
-                })),
-                Retain,
-                Update(bump.alloc(FragmentPushConstants {
-                    color: particle.color() as u32,
-                })),
-            );
-
-            pass.draw(0..6, 0..1);
-        }
+        pass.set_render_pipeline(self.pipeline());
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        Self::set_push_constants(
+            pass,
+            Update(bump.alloc(VertexPushConstants {
+                view_projection: camera.view_projection(),
+                right: rotation.x,
+                up: rotation.y,
+            })),
+            Clear,
+            Clear,
+        );
+
+        pass.draw(0..6, 0..self.instance_count);
+        speeds.add_draw_call();
     }
 }
 
+/// Upload the particles of the frame about to be drawn.
+pub fn prepare_particles(
+    mut gfx_state: ResMut<GraphicsState>,
+    queue: Res<RenderQueue>,
+    render_state: Res<RenderState>,
+) {
+    gfx_state
+        .particle_pipeline_mut()
+        .update_instances(&queue, render_state.state.iter_particles());
+}
+
+#[repr(C)]
 #[derive(Copy, Clone, Debug)]
 pub struct VertexPushConstants {
-    pub transform: Matrix4<f32>,
+    pub view_projection: Matrix4<f32>,
+    /// The directions that the particle quads are stretched along, so that they face the camera.
+    pub right: Vector4<f32>,
+    pub up: Vector4<f32>,
 }
 
+/// A single particle as it's drawn.
+#[repr(C)]
 #[derive(Copy, Clone, Debug)]
-pub struct FragmentPushConstants {
-    pub color: u32,
+pub struct ParticleInstance {
+    position: [f32; 3],
+    /// The particle's palette index.
+    color: u32,
+    size: f32,
+}
+
+impl ParticleInstance {
+    fn new(particle: &Particle) -> ParticleInstance {
+        let origin = particle.origin();
+        ParticleInstance {
+            // convert coordinates, as in `Camera::new`
+            position: [-origin.y, origin.z, -origin.x],
+            color: particle.color() as u32,
+            size: PARTICLE_SIZE,
+        }
+    }
 }
 
 const BIND_GROUP_LAYOUT_ENTRIES: &[wgpu::BindGroupLayoutEntry] = &[
@@ -238,7 +294,7 @@ const BIND_GROUP_LAYOUT_ENTRIES: &[wgpu::BindGroupLayoutEntry] = &[
         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
         count: None,
     },
-    // per-index texture array
+    // particle shape
     wgpu::BindGroupLayoutEntry {
         binding: 1,
         visibility: wgpu::ShaderStages::FRAGMENT,
@@ -247,31 +303,44 @@ const BIND_GROUP_LAYOUT_ENTRIES: &[wgpu::BindGroupLayoutEntry] = &[
             sample_type: wgpu::TextureSampleType::Float { filterable: true },
             multisampled: false,
         },
-        count: NonZeroU32::new(128),
+        count: None,
+    },
+    // palette, one texel per color
+    wgpu::BindGroupLayoutEntry {
+        binding: 2,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            multisampled: false,
+        },
+        count: None,
     },
 ];
 
 lazy_static! {
-    static ref VERTEX_ATTRIBUTES: [[wgpu::VertexAttribute; 2]; 2] = [
+    static ref VERTEX_ATTRIBUTES: [Vec<wgpu::VertexAttribute>; 2] = [
         wgpu::vertex_attr_array![
             // position
             0 => Float32x3,
             // texcoord
             1 => Float32x2,
-        ],
+        ].to_vec(),
         wgpu::vertex_attr_array![
             // instance position
             2 => Float32x3,
             // color index
             3 => Uint32,
-        ]
+            // instance size
+            4 => Float32,
+        ].to_vec(),
     ];
 }
 
 impl Pipeline for ParticlePipeline {
     type VertexPushConstants = VertexPushConstants;
     type SharedPushConstants = ();
-    type FragmentPushConstants = FragmentPushConstants;
+    type FragmentPushConstants = ();
 
     type Args = <WorldPipelineBase as Pipeline>::Args;
 
@@ -318,11 +387,18 @@ impl Pipeline for ParticlePipeline {
 
     // NOTE: if the vertex format is changed, this descriptor must also be changed accordingly.
     fn vertex_buffer_layouts() -> Vec<wgpu::VertexBufferLayout<'static>> {
-        vec![wgpu::VertexBufferLayout {
-            array_stride: size_of::<ParticleVertex>() as u64,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &VERTEX_ATTRIBUTES[0],
-        }]
+        vec![
+            wgpu::VertexBufferLayout {
+                array_stride: size_of::<ParticleVertex>() as u64,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &VERTEX_ATTRIBUTES[0],
+            },
+            wgpu::VertexBufferLayout {
+                array_stride: size_of::<ParticleInstance>() as u64,
+                step_mode: wgpu::VertexStepMode::Instance,
+                attributes: &VERTEX_ATTRIBUTES[1],
+            },
+        ]
     }
 }
 
@@ -359,3 +435,24 @@ pub const VERTICES: [ParticleVertex; 6] = [
         texcoord: [1.0, 1.0],
     },
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instance_layout() {
+        // the instance attributes are packed with nothing between them
+        let attributes_size: u64 = VERTEX_ATTRIBUTES[1]
+            .iter()
+            .map(|attr| attr.format.size())
+            .sum();
+        assert_eq!(size_of::<ParticleInstance>() as u64, attributes_size);
+
+        let last = VERTEX_ATTRIBUTES[1].last().unwrap();
+        assert_eq!(
+            last.offset + last.format.size(),
+            size_of::<ParticleInstance>() as u64
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use std::{mem::size_of, num::NonZeroU64};
+use std::{mem::size_of, num::NonZeroU64, time::Instant};
 
 use bevy::{
     core_pipeline::fullscreen_vertex_shader::fullscreen_shader_vertex_state,
@@ -20,8 +20,11 @@ use serde::Deserialize;
 use wgpu::{BindGroupLayoutEntry, BlendState, ColorTargetState, ColorWrites, PrimitiveState};
 
 use crate::{
-    client::render::{pipeline::Pipeline, ui::quad::QuadPipeline, GraphicsState, RenderState},
-    common::{console::Registry, net::ColorShift, util::any_as_bytes},
+    client::render::{
+        pipeline::Pipeline, ui::quad::QuadPipeline, GraphicsState, RenderSpeeds, RenderState,
+        RenderVars, SpeedsPass,
+    },
+    common::{console::Registry, util::any_as_bytes},
 };
 
 #[repr(C, align(256))]
@@ -342,11 +345,14 @@ impl ViewNode for PostProcessPass {
         target: &ViewTarget,
         world: &'w bevy::prelude::World,
     ) -> Result<(), bevy::render::render_graph::NodeRunError> {
+        let start = Instant::now();
         let gfx_state = world.resource::<GraphicsState>();
         let queue = world.resource::<RenderQueue>();
         let pipeline_cache = world.resource::<PipelineCache>();
         let post_pipeline = world.resource::<PostProcessPipeline>();
+        let render_vars = world.resource::<RenderVars>();
         let conn = world.get_resource::<RenderState>();
+        let speeds = world.resource::<RenderSpeeds>();
 
         let Some(conn) = conn else {
             return Ok(());
@@ -359,12 +365,8 @@ impl ViewNode for PostProcessPass {
             return Ok(());
         };
 
-        if conn
-            .state
-            .color_shifts
-            .iter()
-            .all(|ColorShift { percent, .. }| *percent == 0)
-        {
+        let color_shift = conn.state.color_shift_blends(render_vars.cshift_percent);
+        if color_shift.iter().all(|[.., alpha]| *alpha == 0.) {
             return Ok(());
         }
 
@@ -393,20 +395,10 @@ impl ViewNode for PostProcessPass {
             ..default()
         });
 
-        bind_group.update_uniform_buffers(
-            queue,
-            post_pipeline,
-            conn.state
-                .color_shifts
-                .map(
-                    |ColorShift {
-                         dest_color: [r, g, b],
-                         percent,
-                     }| [r, g, b, ((percent * 256) / 100).min(255) as u8],
-                )
-                .map(|rgba| rgba.map(|v| v as f32 / 255.)),
-        );
+        bind_group.update_uniform_buffers(queue, post_pipeline, color_shift);
         bind_group.record_draw(pipeline, &mut post_pass);
+        speeds.add_draw_call();
+        speeds.set_pass_time(SpeedsPass::PostProcess, start.elapsed());
 
         Ok(())
     }
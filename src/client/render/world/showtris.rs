@@ -0,0 +1,156 @@
+//! Outlines of every triangle drawn in the world, for `r_showtris`.
+//!
+//! The outlines are drawn over everything, so that the triangles hidden behind walls show up as
+//! well as the ones in view.
+
+use crate::client::render::{
+    world::{alias, brush, WorldPipelineBase},
+    Pipeline,
+};
+
+use bevy::render::{
+    render_resource::{BindGroupLayout, BindGroupLayoutEntry, RenderPipeline},
+    renderer::RenderDevice,
+};
+use cgmath::Matrix4;
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref VERTEX_ATTRIBUTES: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![
+        // position
+        0 => Float32x3,
+    ];
+}
+
+/// Draws the outlines of the triangles in vertex buffers whose vertices are `STRIDE` bytes long
+/// and start with their position.
+pub struct ShowTrisPipeline<const STRIDE: u64> {
+    pipeline: RenderPipeline,
+    bind_group_layouts: Vec<BindGroupLayout>,
+}
+
+/// Outlines brush model triangles.
+pub type BrushShowTrisPipeline = ShowTrisPipeline<{ brush::VERTEX_STRIDE }>;
+
+/// Outlines alias and MD3 model triangles.
+pub type AliasShowTrisPipeline = ShowTrisPipeline<{ alias::VERTEX_STRIDE }>;
+
+impl<const STRIDE: u64> ShowTrisPipeline<STRIDE> {
+    pub fn new(
+        device: &RenderDevice,
+        compiler: &mut shaderc::Compiler,
+        diffuse_format: wgpu::TextureFormat,
+        normal_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let (pipeline, bind_group_layouts) = Self::create(
+            device,
+            compiler,
+            &[],
+            sample_count,
+            (diffuse_format, normal_format),
+        );
+
+        ShowTrisPipeline {
+            pipeline,
+            bind_group_layouts,
+        }
+    }
+
+    pub fn rebuild(
+        &mut self,
+        device: &RenderDevice,
+        compiler: &mut shaderc::Compiler,
+        diffuse_format: wgpu::TextureFormat,
+        normal_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) {
+        let layout_refs = self.bind_group_layouts.iter();
+        self.pipeline = Self::recreate(
+            device,
+            compiler,
+            layout_refs,
+            sample_count,
+            (diffuse_format, normal_format),
+        );
+    }
+
+    pub fn pipeline(&self) -> &RenderPipeline {
+        &self.pipeline
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct VertexPushConstants {
+    pub transform: Matrix4<f32>,
+}
+
+impl<const STRIDE: u64> Pipeline for ShowTrisPipeline<STRIDE> {
+    type VertexPushConstants = VertexPushConstants;
+    type SharedPushConstants = ();
+    type FragmentPushConstants = ();
+
+    type Args = <WorldPipelineBase as Pipeline>::Args;
+
+    fn name() -> &'static str {
+        "showtris"
+    }
+
+    fn vertex_shader() -> &'static str {
+        include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/shaders/showtris.vert"
+        ))
+    }
+
+    fn fragment_shader() -> &'static str {
+        include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/shaders/showtris.frag"
+        ))
+    }
+
+    fn bind_group_layout_descriptors() -> Vec<Vec<BindGroupLayoutEntry>> {
+        Vec::new()
+    }
+
+    fn primitive_state() -> wgpu::PrimitiveState {
+        wgpu::PrimitiveState {
+            // the back faces of triangles behind walls are outlined too
+            cull_mode: None,
+            ..WorldPipelineBase::primitive_state()
+        }
+    }
+
+    fn color_target_states_with_args(
+        (diffuse_format, normal_format): Self::Args,
+    ) -> Vec<Option<wgpu::ColorTargetState>> {
+        vec![
+            Some(wgpu::ColorTargetState {
+                format: diffuse_format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            }),
+            Some(wgpu::ColorTargetState {
+                format: normal_format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::empty(),
+            }),
+        ]
+    }
+
+    fn depth_stencil_state() -> Option<wgpu::DepthStencilState> {
+        let mut desc = WorldPipelineBase::depth_stencil_state().unwrap();
+        desc.depth_write_enabled = false;
+        desc.depth_compare = wgpu::CompareFunction::Always;
+        Some(desc)
+    }
+
+    fn vertex_buffer_layouts() -> Vec<wgpu::VertexBufferLayout<'static>> {
+        vec![wgpu::VertexBufferLayout {
+            array_stride: STRIDE,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &VERTEX_ATTRIBUTES[..],
+        }]
+    }
+}
@@ -3,7 +3,7 @@ use std::mem::size_of;
 use crate::{
     client::render::{
         world::{BindGroupLayoutId, WorldPipelineBase},
-        GraphicsState, Pipeline, TextureData,
+        GraphicsState, Pipeline, RenderSpeeds, TextureData,
     },
     common::{
         sprite::{SpriteFrame, SpriteKind, SpriteModel, SpriteSubframe},
@@ -22,6 +22,7 @@ use bevy::{
         renderer::{RenderDevice, RenderQueue},
     },
 };
+use cgmath::Vector3;
 use chrono::Duration;
 use lazy_static::lazy_static;
 
@@ -95,6 +96,13 @@ impl SpritePipeline {
     }
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct FragmentPushConstants {
+    /// Opacity of the sprite, from 0 to 1.
+    pub alpha: f32,
+}
+
 lazy_static! {
     static ref VERTEX_BUFFER_ATTRIBUTES: [wgpu::VertexAttribute; 3] =
         wgpu::vertex_attr_array![
@@ -110,7 +118,7 @@ lazy_static! {
 impl Pipeline for SpritePipeline {
     type VertexPushConstants = ();
     type SharedPushConstants = ();
-    type FragmentPushConstants = ();
+    type FragmentPushConstants = FragmentPushConstants;
 
     type Args = <WorldPipelineBase as Pipeline>::Args;
 
@@ -326,6 +334,7 @@ impl Frame {
 #[derive(Component)]
 pub struct SpriteRenderer {
     kind: SpriteKind,
+    bounds: (Vector3<f32>, Vector3<f32>),
     frames: Vec<Frame>,
 }
 
@@ -343,6 +352,7 @@ impl SpriteRenderer {
 
         SpriteRenderer {
             kind: sprite.kind(),
+            bounds: (sprite.min(), sprite.max()),
             frames,
         }
     }
@@ -353,6 +363,7 @@ impl SpriteRenderer {
         pass: &mut TrackedRenderPass<'a>,
         frame_id: usize,
         time: Duration,
+        speeds: &RenderSpeeds,
     ) {
         pass.set_render_pipeline(state.sprite_pipeline().pipeline());
         pass.set_vertex_buffer(0, state.sprite_pipeline().vertex_buffer().slice(..));
@@ -362,9 +373,16 @@ impl SpriteRenderer {
             &[],
         );
         pass.draw(0..VERTICES.len() as u32, 0..1);
+        speeds.add_draw_call();
     }
 
     pub fn kind(&self) -> SpriteKind {
         self.kind
     }
+
+    /// Returns the minimum and maximum extent of the largest frame relative to the sprite
+    /// origin, before it's turned to face the camera.
+    pub fn bounds(&self) -> (Vector3<f32>, Vector3<f32>) {
+        self.bounds
+    }
 }
@@ -0,0 +1,223 @@
+//! Per-map lists of waypoints, drawn in the world as a path to follow.
+//!
+//! Routes are stored in the game directory as `routes/<map>.txt`, one `x y z` position per line.
+
+use std::{
+    fs,
+    io::{Read as _, Write as _},
+};
+
+use bevy::prelude::*;
+use cgmath::Vector3;
+use clap::Parser;
+
+use crate::{
+    client::{render::DebugDraw, Connection, ConnectionState},
+    common::{
+        console::{ExecResult, RegisterCmdExt as _, Registry},
+        vfs::{Vfs, VfsError},
+    },
+};
+
+const MARKER_SIZE: f32 = 8.;
+const MARKER_COLOR: [f32; 3] = [1.0, 0.8, 0.1];
+const LINE_COLOR: [f32; 3] = [0.2, 0.6, 1.0];
+
+pub struct SeismonRoutePlugin;
+
+impl Plugin for SeismonRoutePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Route>().add_systems(
+            Update,
+            (
+                systems::load_route.run_if(resource_changed::<ConnectionState>),
+                systems::draw_route,
+            )
+                .chain(),
+        );
+
+        #[derive(Parser)]
+        #[command(
+            name = "route_add",
+            about = "Add a waypoint at the player's position to the route for this map"
+        )]
+        struct RouteAdd;
+
+        app.command(
+            |In(RouteAdd),
+             conn: Option<Res<Connection>>,
+             vfs: Res<Vfs>,
+             mut route: ResMut<Route>|
+             -> ExecResult {
+                let Some(conn) = conn else {
+                    return "not connected".into();
+                };
+                if route.map.is_empty() {
+                    return "no map loaded".into();
+                }
+                let Some(player) = conn.state.entities.get(conn.state.view_entity_id()) else {
+                    return "no player entity".into();
+                };
+
+                route.waypoints.push(player.origin());
+                match route.save(&vfs) {
+                    Ok(()) => format!("waypoint {} added\n", route.waypoints.len()).into(),
+                    Err(e) => format!("Failed to save route: {}", e).into(),
+                }
+            },
+        );
+
+        #[derive(Parser)]
+        #[command(name = "route_clear", about = "Remove all waypoints for this map")]
+        struct RouteClear;
+
+        app.command(
+            |In(RouteClear), vfs: Res<Vfs>, mut route: ResMut<Route>| -> ExecResult {
+                if route.map.is_empty() {
+                    return "no map loaded".into();
+                }
+
+                route.waypoints.clear();
+                match route.save(&vfs) {
+                    Ok(()) => default(),
+                    Err(e) => format!("Failed to save route: {}", e).into(),
+                }
+            },
+        );
+    }
+}
+
+/// Waypoints for the map the client is currently on.
+#[derive(Resource, Default, Debug)]
+pub struct Route {
+    /// The map name without directory or extension, e.g. `e1m1`. Empty when no map is loaded.
+    map: String,
+    waypoints: Vec<Vector3<f32>>,
+}
+
+impl Route {
+    fn path(map: &str) -> String {
+        format!("routes/{}.txt", map)
+    }
+
+    /// Reads the route for `map` from the game directory, or an empty route if there is none.
+    pub fn load(vfs: &Vfs, map: &str) -> Result<Route, failure::Error> {
+        let path = Self::path(map);
+
+        let mut text = String::new();
+        match vfs.open(&path) {
+            Ok(mut file) => {
+                file.read_to_string(&mut text)?;
+            }
+            Err(VfsError::NoSuchFile(_)) => {
+                return Ok(Route {
+                    map: map.to_owned(),
+                    waypoints: Vec::new(),
+                })
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        let mut waypoints = Vec::new();
+        for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            match parse_waypoint(line) {
+                Some(waypoint) => waypoints.push(waypoint),
+                None => warn!("Ignoring invalid waypoint in {}: {}", path, line),
+            }
+        }
+
+        Ok(Route {
+            map: map.to_owned(),
+            waypoints,
+        })
+    }
+
+    /// Writes the route to the game directory.
+    pub fn save(&self, vfs: &Vfs) -> Result<(), failure::Error> {
+        let path = Self::path(&self.map);
+        if let Some(dir) = vfs.find_writable_filename(&path)?.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut file = vfs.write(&path)?;
+        for waypoint in &self.waypoints {
+            writeln!(file, "{} {} {}", waypoint.x, waypoint.y, waypoint.z)?;
+        }
+        file.flush()?;
+
+        Ok(())
+    }
+
+    pub fn map(&self) -> &str {
+        &self.map
+    }
+
+    pub fn waypoints(&self) -> &[Vector3<f32>] {
+        &self.waypoints
+    }
+}
+
+fn parse_waypoint(line: &str) -> Option<Vector3<f32>> {
+    let mut coords = line.split_whitespace().map(|c| c.parse::<f32>().ok());
+    match (coords.next(), coords.next(), coords.next(), coords.next()) {
+        (Some(Some(x)), Some(Some(y)), Some(Some(z)), None) => Some(Vector3::new(x, y, z)),
+        _ => None,
+    }
+}
+
+mod systems {
+    use super::*;
+
+    pub fn load_route(conn_state: Res<ConnectionState>, vfs: Res<Vfs>, mut route: ResMut<Route>) {
+        let map = match &*conn_state {
+            ConnectionState::Connected(state) => state.map_name().unwrap_or_default().to_owned(),
+            ConnectionState::SignOn(_) => String::new(),
+        };
+
+        if map == route.map {
+            return;
+        }
+
+        *route = if map.is_empty() {
+            default()
+        } else {
+            Route::load(&vfs, &map).unwrap_or_else(|e| {
+                error!("Failed to load route for {}: {}", map, e);
+                Route {
+                    map,
+                    waypoints: Vec::new(),
+                }
+            })
+        };
+    }
+
+    pub fn draw_route(registry: Res<Registry>, route: Res<Route>, mut draw: ResMut<DebugDraw>) {
+        if registry.read_cvar::<u8>("cl_showroute").unwrap_or(0) == 0 {
+            return;
+        }
+
+        for &waypoint in &route.waypoints {
+            draw.marker(waypoint, MARKER_SIZE, MARKER_COLOR);
+        }
+
+        for pair in route.waypoints.windows(2) {
+            draw.line(pair[0], pair[1], LINE_COLOR);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_waypoint() {
+        assert_eq!(
+            parse_waypoint("  480 -352.5 88 "),
+            Some(Vector3::new(480., -352.5, 88.))
+        );
+        assert_eq!(parse_waypoint("480 -352.5"), None);
+        assert_eq!(parse_waypoint("480 -352.5 88 1"), None);
+        assert_eq!(parse_waypoint("480 north 88"), None);
+    }
+}
@@ -0,0 +1,979 @@
+//! Finding servers to join.
+//!
+//! `slist` broadcasts a query on the LAN and, if `cl_master` names a master server, asks it for
+//! the public servers as well. The favorite and recently joined servers, which are kept in
+//! [`SERVERS_PATH`] in the game directory, are asked directly. Every server that answers is
+//! listed with its ping, map and player count, both in the console and on the server list page of
+//! the menu, where selecting one connects to it. The menu page can sort the list and hide empty,
+//! full or non-favorite servers, which it does through the `cl_slist_*` cvars.
+//!
+//! Every server is asked from the same non-blocking socket, so a slow server doesn't hold up the
+//! others, and host names are looked up on a thread of their own, as a slow name server would
+//! otherwise stall the game until it answered.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{ErrorKind, Read as _, Write as _},
+    net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    str::FromStr,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+use clap::Parser;
+
+use crate::{
+    client::{
+        commands::LastServer,
+        menu::{Menu, MenuBodyView, MenuBuilder, MenuView},
+    },
+    common::{
+        console::{CmdName, ConsoleOutput, ExecResult, RegisterCmdExt as _, Registry, RunCmd},
+        net::{
+            self,
+            connect::{ConnectSocket, Request, Response, ResponseServerInfo, DEFAULT_PORT},
+            master, NetError, MAX_MESSAGE,
+        },
+        vfs::{Vfs, VfsError},
+    },
+};
+
+/// The name of the server list page in the menu that contains it. Menus that include the page
+/// from [`build_menu`] must use this name for it to be filled in.
+pub const MENU_NAME: &str = "Search for games...";
+
+/// The file in the game directory that holds the favorite and recent servers.
+pub const SERVERS_PATH: &str = "servers.txt";
+
+/// How long to wait for servers to answer.
+const SEARCH_TIME: Duration = Duration::from_secs(3);
+
+/// How long a single server has to answer a ping.
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How many recently joined servers are remembered.
+const MAX_RECENT: usize = 8;
+
+pub struct SeismonServerBrowserPlugin;
+
+impl Plugin for SeismonServerBrowserPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ServerBrowser>()
+            .add_systems(Startup, systems::load_known_servers)
+            .add_systems(
+                Update,
+                (
+                    systems::poll_servers.run_if(|browser: Res<ServerBrowser>| browser.searching()),
+                    systems::update_favorites
+                        .run_if(|browser: Res<ServerBrowser>| browser.favorite_lookup.is_some()),
+                    systems::update_menu,
+                    systems::record_recent.run_if(
+                        resource_exists::<LastServer>.and_then(resource_changed::<LastServer>),
+                    ),
+                )
+                    .chain(),
+            );
+
+        #[derive(Parser)]
+        #[command(
+            name = "slist",
+            about = "Search for servers on the LAN and the master server"
+        )]
+        struct Slist;
+
+        app.command(
+            |In(Slist),
+             mut browser: ResMut<ServerBrowser>,
+             registry: Res<Registry>,
+             time: Res<Time<Real>>|
+             -> ExecResult {
+                let master = cvar_text(&registry, "cl_master").filter(|v| !v.is_empty());
+
+                match browser.start(master.as_deref(), time.elapsed()) {
+                    Ok(()) => "Looking for Quake servers...\n".into(),
+                    Err(e) => format!("Failed to search for servers: {}\n", e).into(),
+                }
+            },
+        );
+
+        #[derive(Parser)]
+        #[command(
+            name = "favorite",
+            about = "Add a server to the favorites, or the last server connected to if none is given"
+        )]
+        struct Favorite {
+            address: Option<String>,
+        }
+
+        app.command(
+            |In(Favorite { address }),
+             mut browser: ResMut<ServerBrowser>,
+             last_server: Option<Res<LastServer>>,
+             vfs: Res<Vfs>|
+             -> ExecResult {
+                let Some(address) = address.or_else(|| last_server.map(|s| s.0.clone())) else {
+                    return "no server given".into();
+                };
+
+                if !browser.add_favorite(&address) {
+                    return format!("{} is already a favorite\n", address).into();
+                }
+                match browser.known().save(&vfs) {
+                    Ok(()) => format!("{} added to favorites\n", address).into(),
+                    Err(e) => format!("Failed to save favorites: {}", e).into(),
+                }
+            },
+        );
+
+        #[derive(Parser)]
+        #[command(name = "unfavorite", about = "Remove a server from the favorites")]
+        struct Unfavorite {
+            address: String,
+        }
+
+        app.command(
+            |In(Unfavorite { address }),
+             mut browser: ResMut<ServerBrowser>,
+             vfs: Res<Vfs>|
+             -> ExecResult {
+                if !browser.remove_favorite(&address) {
+                    return format!("{} is not a favorite\n", address).into();
+                }
+                match browser.known().save(&vfs) {
+                    Ok(()) => format!("{} removed from favorites\n", address).into(),
+                    Err(e) => format!("Failed to save favorites: {}", e).into(),
+                }
+            },
+        );
+    }
+}
+
+/// The value of a cvar as plain text, whether it was set as a symbol or a string.
+fn cvar_text(registry: &Registry, name: &str) -> Option<String> {
+    registry.get_cvar(name).map(|cvar| {
+        let value = cvar.value();
+        match value.as_name().or_else(|| value.as_str()) {
+            Some(s) => s.to_owned(),
+            None => value.to_string(),
+        }
+    })
+}
+
+/// Finds the address of a server given as `host` or `host:port`.
+fn resolve(name: &str, default_port: u16) -> Option<SocketAddr> {
+    name.to_socket_addrs()
+        .or_else(|_| (name, default_port).to_socket_addrs())
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+}
+
+/// Takes the result of a lookup once its thread has finished.
+fn finished<T>(lookup: &mut Option<JoinHandle<T>>) -> Option<T> {
+    if !lookup.as_ref()?.is_finished() {
+        return None;
+    }
+
+    // a lookup that panicked found nothing
+    lookup.take()?.join().ok()
+}
+
+/// Builds the menu page listing the servers found by the last search. Add it to a menu under
+/// the name [`MENU_NAME`].
+pub fn build_menu(builder: MenuBuilder) -> Result<Menu, failure::Error> {
+    Ok(builder
+        .add_command("Search again", "slist")
+        .build(MenuView {
+            draw_plaque: true,
+            title_path: "gfx/p_multi.lmp".into(),
+            body: MenuBodyView::Dynamic,
+        }))
+}
+
+/// A server that answered a search.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerEntry {
+    pub addr: SocketAddr,
+    pub hostname: String,
+    pub map: String,
+    pub players: u8,
+    pub max_players: u8,
+    pub ping: Duration,
+}
+
+impl ServerEntry {
+    fn from_info(addr: SocketAddr, info: ResponseServerInfo, ping: Duration) -> ServerEntry {
+        ServerEntry {
+            addr,
+            hostname: info.hostname,
+            map: info.levelname,
+            players: info.client_count,
+            max_players: info.client_max,
+            ping,
+        }
+    }
+
+    /// The label of the server on the menu page, which has room for about 24 characters.
+    /// Favorites are marked with a star.
+    fn menu_label(&self, favorite: bool) -> String {
+        format!(
+            "{}{:<10.10} {:<7.7} {}/{}",
+            if favorite { '*' } else { ' ' },
+            self.hostname,
+            self.map,
+            self.players,
+            self.max_players
+        )
+    }
+
+    fn connect_cmd(&self) -> RunCmd<'static> {
+        RunCmd("connect".into(), Box::new([self.addr.to_string()]))
+    }
+}
+
+/// The order in which the menu lists servers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    #[default]
+    Ping,
+    Name,
+    Map,
+    /// The fullest servers first.
+    Players,
+}
+
+impl SortOrder {
+    pub const ALL: [SortOrder; 4] = [
+        SortOrder::Ping,
+        SortOrder::Name,
+        SortOrder::Map,
+        SortOrder::Players,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SortOrder::Ping => "ping",
+            SortOrder::Name => "name",
+            SortOrder::Map => "map",
+            SortOrder::Players => "players",
+        }
+    }
+
+    /// The order after this one, for cycling through them in the menu.
+    pub fn next(self) -> SortOrder {
+        let index = SortOrder::ALL.iter().position(|o| *o == self).unwrap_or(0);
+        SortOrder::ALL[(index + 1) % SortOrder::ALL.len()]
+    }
+}
+
+impl FromStr for SortOrder {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<SortOrder, failure::Error> {
+        SortOrder::ALL
+            .into_iter()
+            .find(|o| o.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| failure::format_err!("unknown sort order {}", s))
+    }
+}
+
+/// Which servers the menu lists, and in what order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ListOptions {
+    pub sort: SortOrder,
+    pub hide_empty: bool,
+    pub hide_full: bool,
+    pub favorites_only: bool,
+}
+
+impl ListOptions {
+    /// Reads the options from the `cl_slist_*` cvars. Bad values are taken as the defaults.
+    pub fn from_registry(registry: &Registry) -> ListOptions {
+        let flag = |name: &str| registry.read_cvar::<u8>(name).unwrap_or(0) != 0;
+
+        ListOptions {
+            sort: cvar_text(registry, "cl_slist_sort")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or_default(),
+            hide_empty: flag("cl_slist_hideempty"),
+            hide_full: flag("cl_slist_hidefull"),
+            favorites_only: flag("cl_slist_favorites"),
+        }
+    }
+
+    /// The menu items that change the options, each labelled with the current setting.
+    fn menu_items(&self) -> [(String, RunCmd<'static>); 4] {
+        fn set(cvar: &'static str, value: impl ToString) -> RunCmd<'static> {
+            RunCmd(CmdName::from(cvar), Box::new([value.to_string()]))
+        }
+        fn on_off(value: bool) -> &'static str {
+            if value {
+                "on"
+            } else {
+                "off"
+            }
+        }
+
+        [
+            (
+                format!("Sort by: {}", self.sort.name()),
+                set("cl_slist_sort", self.sort.next().name()),
+            ),
+            (
+                format!("Hide empty: {}", on_off(self.hide_empty)),
+                set("cl_slist_hideempty", u8::from(!self.hide_empty)),
+            ),
+            (
+                format!("Hide full: {}", on_off(self.hide_full)),
+                set("cl_slist_hidefull", u8::from(!self.hide_full)),
+            ),
+            (
+                format!("Favorites only: {}", on_off(self.favorites_only)),
+                set("cl_slist_favorites", u8::from(!self.favorites_only)),
+            ),
+        ]
+    }
+}
+
+/// The favorite servers and the servers joined most recently, as they were given to `connect`
+/// or `favorite`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KnownServers {
+    pub favorites: Vec<String>,
+    /// The most recent first.
+    pub recent: Vec<String>,
+}
+
+impl KnownServers {
+    /// Parses the contents of [`SERVERS_PATH`], which has a `favorite <address>` or
+    /// `recent <address>` line for each server.
+    pub fn parse(text: &str) -> Result<KnownServers, failure::Error> {
+        let cmds = RunCmd::parse_many(text)
+            .map_err(|e| failure::format_err!("couldn't parse {}: {}", SERVERS_PATH, e))?;
+
+        let mut known = KnownServers::default();
+        for RunCmd(CmdName { name, .. }, args) in cmds {
+            match (&*name, &*args) {
+                ("favorite", [addr]) => known.favorites.push(addr.clone()),
+                ("recent", [addr]) => known.recent.push(addr.clone()),
+                (other, _) => failure::bail!("bad line in {}: {}", SERVERS_PATH, other),
+            }
+        }
+        known.recent.truncate(MAX_RECENT);
+
+        Ok(known)
+    }
+
+    /// Returns the contents of [`SERVERS_PATH`] for these servers.
+    pub fn to_text(&self) -> String {
+        let favorites = self.favorites.iter().map(|a| format!("favorite {:?}\n", a));
+        let recent = self.recent.iter().map(|a| format!("recent {:?}\n", a));
+        favorites.chain(recent).collect()
+    }
+
+    /// Reads the servers from [`SERVERS_PATH`], if there is one.
+    pub fn load(vfs: &Vfs) -> Result<KnownServers, failure::Error> {
+        let mut text = String::new();
+        match vfs.open(SERVERS_PATH) {
+            Ok(mut file) => file.read_to_string(&mut text)?,
+            Err(VfsError::NoSuchFile(_)) => return Ok(KnownServers::default()),
+            Err(e) => return Err(e.into()),
+        };
+
+        KnownServers::parse(&text)
+    }
+
+    /// Writes the servers to [`SERVERS_PATH`].
+    pub fn save(&self, vfs: &Vfs) -> Result<(), failure::Error> {
+        let mut file = vfs.write(SERVERS_PATH)?;
+        file.write_all(self.to_text().as_bytes())?;
+        file.flush()?;
+
+        Ok(())
+    }
+
+    /// Moves `addr` to the front of the recent servers. Returns `false` if it was there already.
+    pub fn add_recent(&mut self, addr: &str) -> bool {
+        if self.recent.first().is_some_and(|a| a == addr) {
+            return false;
+        }
+
+        self.recent.retain(|a| a != addr);
+        self.recent.insert(0, addr.to_owned());
+        self.recent.truncate(MAX_RECENT);
+        true
+    }
+
+    /// All of the servers, without repeats.
+    fn all(&self) -> impl Iterator<Item = &str> {
+        let mut seen = HashSet::new();
+        self.favorites
+            .iter()
+            .chain(&self.recent)
+            .map(String::as_str)
+            .filter(move |a| seen.insert(*a))
+    }
+}
+
+/// The addresses of the servers to ask at the start of a search.
+struct Lookup {
+    servers: Vec<SocketAddr>,
+    /// The master server's name and its address, if it has one.
+    master: Option<(String, Option<SocketAddr>)>,
+}
+
+/// Sends the master server its query from a socket of its own.
+fn query_master(addr: SocketAddr) -> Result<UdpSocket, NetError> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_nonblocking(true)?;
+    socket.connect(addr)?;
+    socket.send(&master::query())?;
+    Ok(socket)
+}
+
+struct Search {
+    socket: ConnectSocket,
+    master: Option<UdpSocket>,
+    started: Duration,
+    /// The servers that have been pinged, so that each is only pinged once.
+    queried: HashSet<SocketAddr>,
+    /// The servers that haven't answered their ping yet, and when it was sent.
+    pings: HashMap<SocketAddr, Instant>,
+    lookup: Option<JoinHandle<Lookup>>,
+}
+
+impl Search {
+    fn query(&mut self, addr: SocketAddr) -> Result<(), NetError> {
+        if self.queried.insert(addr) {
+            self.socket
+                .send_request(Request::server_info(net::GAME_NAME), addr)?;
+            self.pings.insert(addr, Instant::now());
+        }
+
+        Ok(())
+    }
+
+    /// Pings a server that was found during the search. Some servers being unreachable doesn't
+    /// stop the others from being listed.
+    fn query_found(&mut self, addr: SocketAddr) {
+        if let Err(e) = self.query(addr) {
+            warn!("Couldn't ping {}: {}", addr, e);
+        }
+    }
+}
+
+/// The servers found by `slist`, and the search in progress if there is one.
+#[derive(Resource, Default)]
+pub struct ServerBrowser {
+    search: Option<Search>,
+    servers: Vec<ServerEntry>,
+    known: KnownServers,
+    /// The addresses of the favorites, found when the list of favorites changes.
+    favorite_addrs: HashSet<SocketAddr>,
+    favorite_lookup: Option<JoinHandle<HashSet<SocketAddr>>>,
+}
+
+impl ServerBrowser {
+    pub fn searching(&self) -> bool {
+        self.search.is_some()
+    }
+
+    /// The servers found so far, in the order they answered.
+    pub fn servers(&self) -> &[ServerEntry] {
+        &self.servers
+    }
+
+    /// The servers found so far that pass the filters in `options`, in the order it gives.
+    pub fn list(&self, options: &ListOptions) -> Vec<&ServerEntry> {
+        let mut list = self
+            .servers
+            .iter()
+            .filter(|s| !(options.hide_empty && s.players == 0))
+            .filter(|s| !(options.hide_full && s.players >= s.max_players))
+            .filter(|s| !options.favorites_only || self.is_favorite(s.addr))
+            .collect::<Vec<_>>();
+
+        match options.sort {
+            SortOrder::Ping => list.sort_by_key(|s| s.ping),
+            SortOrder::Name => list.sort_by_key(|s| s.hostname.to_lowercase()),
+            SortOrder::Map => list.sort_by(|a, b| a.map.cmp(&b.map)),
+            SortOrder::Players => list.sort_by(|a, b| b.players.cmp(&a.players)),
+        }
+
+        list
+    }
+
+    pub fn known(&self) -> &KnownServers {
+        &self.known
+    }
+
+    pub fn set_known(&mut self, known: KnownServers) {
+        self.known = known;
+        self.update_favorite_addrs();
+    }
+
+    pub fn is_favorite(&self, addr: SocketAddr) -> bool {
+        self.favorite_addrs.contains(&addr)
+    }
+
+    /// Adds a server to the favorites. Returns `false` if it was one already.
+    pub fn add_favorite(&mut self, addr: &str) -> bool {
+        if self.known.favorites.iter().any(|a| a == addr) {
+            return false;
+        }
+
+        self.known.favorites.push(addr.to_owned());
+        self.update_favorite_addrs();
+        true
+    }
+
+    /// Removes a server from the favorites. Returns `false` if it wasn't one.
+    pub fn remove_favorite(&mut self, addr: &str) -> bool {
+        let count = self.known.favorites.len();
+        self.known.favorites.retain(|a| a != addr);
+        if self.known.favorites.len() == count {
+            return false;
+        }
+
+        self.update_favorite_addrs();
+        true
+    }
+
+    /// Remembers a server that was just joined. Returns `true` if the recent servers changed.
+    pub fn add_recent(&mut self, addr: &str) -> bool {
+        self.known.add_recent(addr)
+    }
+
+    fn update_favorite_addrs(&mut self) {
+        let favorites = self.known.favorites.clone();
+        self.favorite_lookup = Some(thread::spawn(move || {
+            favorites
+                .iter()
+                .filter_map(|a| resolve(a, DEFAULT_PORT))
+                .collect()
+        }));
+    }
+
+    /// Takes the addresses of the favorites once they've been looked up. Returns `true` if they
+    /// were.
+    fn poll_favorites(&mut self) -> bool {
+        match finished(&mut self.favorite_lookup) {
+            Some(addrs) => {
+                self.favorite_addrs = addrs;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Forgets the servers from the last search and starts a new one at the time `now`.
+    pub fn start(&mut self, master: Option<&str>, now: Duration) -> Result<(), NetError> {
+        self.search = None;
+        self.servers.clear();
+
+        let socket = ConnectSocket::bind("0.0.0.0:0")?;
+        socket.set_nonblocking(true)?;
+        socket.set_broadcast(true)?;
+
+        let mut search = Search {
+            socket,
+            master: None,
+            started: now,
+            queried: HashSet::new(),
+            pings: HashMap::new(),
+            lookup: None,
+        };
+
+        // a machine with no LAN can still reach the master, so this isn't fatal
+        let lan = SocketAddr::from((Ipv4Addr::BROADCAST, DEFAULT_PORT));
+        if let Err(e) = search
+            .socket
+            .send_request(Request::server_info(net::GAME_NAME), lan)
+        {
+            warn!("Couldn't broadcast server query: {}", e);
+        }
+
+        let names = self.known.all().map(str::to_owned).collect::<Vec<_>>();
+        let master = master.map(str::to_owned);
+        if !names.is_empty() || master.is_some() {
+            search.lookup = Some(thread::spawn(move || Lookup {
+                // WebSocket addresses don't resolve, and those servers wouldn't answer
+                servers: names
+                    .iter()
+                    .filter_map(|a| resolve(a, DEFAULT_PORT))
+                    .collect(),
+                master: master.map(|name| {
+                    let addr = resolve(&name, master::DEFAULT_PORT);
+                    (name, addr)
+                }),
+            }));
+        }
+
+        self.search = Some(search);
+
+        Ok(())
+    }
+
+    /// Pings a single server as part of the current search.
+    pub fn query(&mut self, addr: SocketAddr) -> Result<(), NetError> {
+        match &mut self.search {
+            Some(search) => search.query(addr),
+            None => Err(NetError::with_msg("Not searching for servers")),
+        }
+    }
+
+    fn add_entry(&mut self, entry: ServerEntry) {
+        // servers on the LAN can also be listed by the master or be favorites
+        match self.servers.iter_mut().find(|s| s.addr == entry.addr) {
+            Some(existing) => *existing = entry,
+            None => self.servers.push(entry),
+        }
+    }
+
+    /// Collects the answers that have arrived by the time `now`. Returns `true` if the list of
+    /// servers changed.
+    ///
+    /// Once the search has run for long enough and every ping has been answered or timed out, it
+    /// is stopped, and the list is final.
+    pub fn poll(&mut self, now: Duration) -> Result<bool, NetError> {
+        let Some(search) = &mut self.search else {
+            return Ok(false);
+        };
+
+        if let Some(lookup) = finished(&mut search.lookup) {
+            for addr in lookup.servers {
+                search.query_found(addr);
+            }
+
+            match lookup.master {
+                Some((_, Some(addr))) => match query_master(addr) {
+                    Ok(socket) => search.master = Some(socket),
+                    Err(e) => warn!("Couldn't query master server: {}", e),
+                },
+                Some((name, None)) => warn!("Bad master server {}", name),
+                None => (),
+            }
+        }
+
+        let mut found = Vec::new();
+        if let Some(master) = &search.master {
+            let mut buf = [0; MAX_MESSAGE];
+            loop {
+                let len = match master.recv(&mut buf) {
+                    Ok(len) => len,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(e) => return Err(e.into()),
+                };
+
+                match master::parse_response(&buf[..len]) {
+                    Ok((addrs, _)) => found.extend(addrs),
+                    Err(e) => warn!("Bad reply from master server: {}", e),
+                }
+            }
+        }
+
+        for addr in found {
+            search.query_found(addr);
+        }
+
+        let mut entries = Vec::new();
+        loop {
+            let (response, remote) = match search.socket.recv_response(None) {
+                Ok(Some(response)) => response,
+                Ok(None) => break,
+                Err(e @ NetError::Io { .. }) => return Err(e),
+                Err(e) => {
+                    warn!("Bad reply to server query: {}", e);
+                    continue;
+                }
+            };
+
+            if let Response::ServerInfo(info) = response {
+                // servers that weren't pinged directly answered the broadcast
+                let ping = match search.pings.remove(&remote) {
+                    Some(sent) => sent.elapsed(),
+                    None => now.saturating_sub(search.started),
+                };
+                entries.push(ServerEntry::from_info(remote, info, ping));
+            }
+        }
+
+        search.pings.retain(|_, sent| sent.elapsed() < PING_TIMEOUT);
+
+        if now.saturating_sub(search.started) >= SEARCH_TIME
+            && search.lookup.is_none()
+            && search.pings.is_empty()
+        {
+            self.search = None;
+        }
+
+        let changed = !entries.is_empty();
+        for entry in entries {
+            self.add_entry(entry);
+        }
+
+        Ok(changed)
+    }
+
+    /// The results of the search as text for the console.
+    pub fn describe(&self) -> String {
+        if self.servers.is_empty() {
+            return "No Quake servers found.\n".into();
+        }
+
+        let mut out = format!(
+            "{:<15} {:<8} {:>5} {:>4} {}\n",
+            "Server", "Map", "Users", "Ping", "Address"
+        );
+        for server in &self.servers {
+            out.push_str(&format!(
+                "{:<15.15} {:<8.8} {:>2}/{:<2} {:>4} {}{}\n",
+                server.hostname,
+                server.map,
+                server.players,
+                server.max_players,
+                server.ping.as_millis(),
+                server.addr,
+                if self.is_favorite(server.addr) {
+                    " *"
+                } else {
+                    ""
+                },
+            ));
+        }
+
+        out
+    }
+}
+
+mod systems {
+    use super::*;
+
+    pub fn load_known_servers(vfs: Res<Vfs>, mut browser: ResMut<ServerBrowser>) {
+        match KnownServers::load(&vfs) {
+            Ok(known) => browser.set_known(known),
+            Err(e) => warn!("Couldn't load {}: {}", SERVERS_PATH, e),
+        }
+    }
+
+    pub fn poll_servers(
+        mut browser: ResMut<ServerBrowser>,
+        time: Res<Time<Real>>,
+        mut console: ResMut<ConsoleOutput>,
+    ) {
+        // the menu is only rebuilt when the list changes
+        let changed = match browser.bypass_change_detection().poll(time.elapsed()) {
+            Ok(changed) => changed,
+            Err(e) => {
+                error!("Server search failed: {}", e);
+                browser.search = None;
+                false
+            }
+        };
+
+        if changed {
+            browser.set_changed();
+        }
+
+        if !browser.searching() {
+            let timestamp = chrono::Duration::from_std(time.elapsed()).unwrap();
+            console.print(browser.describe(), timestamp);
+        }
+    }
+
+    pub fn update_favorites(mut browser: ResMut<ServerBrowser>) {
+        // the menu marks the favorites, so it's rebuilt once they're known
+        if browser.bypass_change_detection().poll_favorites() {
+            browser.set_changed();
+        }
+    }
+
+    /// Fills in the server list page of the menu when the servers or the list options change.
+    pub fn update_menu(
+        browser: Res<ServerBrowser>,
+        registry: Res<Registry>,
+        menu: Option<ResMut<Menu>>,
+        mut shown: Local<Option<ListOptions>>,
+    ) {
+        let options = ListOptions::from_registry(&registry);
+        if !browser.is_changed() && shown.as_ref() == Some(&options) {
+            return;
+        }
+
+        let Some(page) = menu.and_then(|m| m.into_inner().submenu_mut(MENU_NAME)) else {
+            return;
+        };
+
+        let items = [("Search again".to_owned(), RunCmd::from("slist"))]
+            .into_iter()
+            .chain(options.menu_items())
+            .chain(
+                browser
+                    .list(&options)
+                    .into_iter()
+                    .map(|s| (s.menu_label(browser.is_favorite(s.addr)), s.connect_cmd())),
+            );
+        if let Err(e) = page.set_commands(items) {
+            warn!("Couldn't update server list: {}", e);
+        }
+
+        *shown = Some(options);
+    }
+
+    pub fn record_recent(
+        last_server: Res<LastServer>,
+        vfs: Res<Vfs>,
+        mut browser: ResMut<ServerBrowser>,
+    ) {
+        let LastServer(addr) = &*last_server;
+        if browser.add_recent(addr) {
+            if let Err(e) = browser.known().save(&vfs) {
+                warn!("Couldn't save {}: {}", SERVERS_PATH, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::common::net::connect::ConnectListener;
+
+    fn entry(hostname: &str, players: u8, ping: u64) -> ServerEntry {
+        ServerEntry {
+            addr: SocketAddr::from(([10, 0, 0, players], DEFAULT_PORT)),
+            hostname: hostname.into(),
+            map: "e1m1".into(),
+            players,
+            max_players: 4,
+            ping: Duration::from_millis(ping),
+        }
+    }
+
+    #[test]
+    fn test_search() {
+        let listener = ConnectListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut browser = ServerBrowser::default();
+        browser.start(None, Duration::ZERO).unwrap();
+        browser.query(addr).unwrap();
+
+        let (request, remote) = listener.recv_request().unwrap();
+        assert!(matches!(request, Request::ServerInfo(_)));
+        listener
+            .send_response(
+                Response::ServerInfo(ResponseServerInfo {
+                    address: addr.to_string(),
+                    hostname: "test server".into(),
+                    levelname: "e1m1".into(),
+                    client_count: 2,
+                    client_max: 8,
+                    protocol_version: 3,
+                }),
+                remote,
+            )
+            .unwrap();
+
+        let mut waited = 0;
+        while !browser.poll(Duration::ZERO).unwrap() {
+            waited += 1;
+            assert!(waited < 100, "Server didn't answer");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        // the ping is timed from when it was sent, so only its bounds are known
+        let ping = browser.servers()[0].ping;
+        assert!(ping < PING_TIMEOUT);
+        assert_eq!(
+            browser.servers(),
+            &[ServerEntry {
+                addr,
+                hostname: "test server".into(),
+                map: "e1m1".into(),
+                players: 2,
+                max_players: 8,
+                ping,
+            }]
+        );
+        assert_eq!(
+            browser.servers()[0].connect_cmd(),
+            RunCmd("connect".into(), Box::new([addr.to_string()]))
+        );
+        assert!(browser.searching());
+
+        browser.poll(SEARCH_TIME).unwrap();
+        assert!(!browser.searching());
+        assert!(browser.describe().contains("test server"));
+    }
+
+    #[test]
+    fn test_favorites() {
+        let mut browser = ServerBrowser::default();
+        assert!(browser.add_favorite("127.0.0.1"));
+        assert!(!browser.add_favorite("127.0.0.1"));
+
+        let mut waited = 0;
+        while !browser.poll_favorites() {
+            waited += 1;
+            assert!(waited < 100, "Favorites weren't looked up");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        assert!(browser.is_favorite(SocketAddr::from(([127, 0, 0, 1], DEFAULT_PORT))));
+        assert!(browser.remove_favorite("127.0.0.1"));
+    }
+
+    #[test]
+    fn test_list() {
+        let mut browser = ServerBrowser::default();
+        browser.servers = vec![entry("b", 4, 30), entry("a", 0, 50), entry("C", 2, 10)];
+
+        let names = |options: &ListOptions| {
+            browser
+                .list(options)
+                .into_iter()
+                .map(|s| s.hostname.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let mut options = ListOptions::default();
+        assert_eq!(names(&options), ["C", "b", "a"]);
+
+        options.sort = SortOrder::Name;
+        assert_eq!(names(&options), ["a", "b", "C"]);
+
+        options.sort = SortOrder::Players;
+        options.hide_empty = true;
+        options.hide_full = true;
+        assert_eq!(names(&options), ["C"]);
+
+        assert_eq!(SortOrder::Players.next(), SortOrder::Ping);
+        assert_eq!("MAP".parse::<SortOrder>().unwrap(), SortOrder::Map);
+    }
+
+    #[test]
+    fn test_known_servers() {
+        let text = "favorite \"10.0.0.1:26000\"\nrecent \"qw://10.0.0.2\"\n";
+        let mut known = KnownServers::parse(text).unwrap();
+        assert_eq!(known.favorites, ["10.0.0.1:26000"]);
+        assert_eq!(known.recent, ["qw://10.0.0.2"]);
+        assert_eq!(known.to_text(), text);
+
+        assert!(known.add_recent("10.0.0.1:26000"));
+        assert!(!known.add_recent("10.0.0.1:26000"));
+        assert_eq!(known.recent, ["10.0.0.1:26000", "qw://10.0.0.2"]);
+        assert_eq!(
+            known.all().collect::<Vec<_>>(),
+            ["10.0.0.1:26000", "qw://10.0.0.2"]
+        );
+
+        for i in 0..MAX_RECENT * 2 {
+            known.add_recent(&format!("10.0.1.{}", i));
+        }
+        assert_eq!(known.recent.len(), MAX_RECENT);
+
+        assert!(KnownServers::parse("server 10.0.0.1\n").is_err());
+    }
+}
@@ -0,0 +1,241 @@
+//! Visual sound indicators: icons around the crosshair pointing towards nearby sounds, for players
+//! who can't hear them. Enabled with `cl_soundindicators`.
+//!
+//! Sounds are told apart by the entity channel they are played on, following the QuakeC
+//! convention: weapons fire on channel 1, pain and sight sounds use channel 2, item pickups
+//! channel 3 and jumping and landing channel 4.
+
+use bevy::{prelude::*, render::extract_resource::ExtractResource};
+use cgmath::{Angle as _, Deg, InnerSpace as _, Vector3};
+use chrono::Duration;
+
+use crate::{
+    client::{
+        sound::{Listener, MixerEvent, StartSound},
+        Connection,
+    },
+    common::console::Registry,
+};
+
+/// How long an indicator stays on screen after its sound starts.
+const INDICATOR_MILLIS: i64 = 1500;
+
+/// Sounds quieter than this where the player is standing aren't shown.
+const MIN_VOLUME: f32 = 0.1;
+
+/// The oldest indicators are dropped beyond this many, so that a firefight doesn't fill the
+/// screen.
+const MAX_INDICATORS: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SoundKind {
+    Weapon,
+    Voice,
+    Item,
+    Movement,
+    Other,
+}
+
+impl SoundKind {
+    pub fn from_channel(channel: i8) -> SoundKind {
+        match channel {
+            1 => SoundKind::Weapon,
+            2 => SoundKind::Voice,
+            3 => SoundKind::Item,
+            4 => SoundKind::Movement,
+            _ => SoundKind::Other,
+        }
+    }
+
+    /// The console character drawn for sounds of this kind.
+    pub fn glyph(&self) -> u8 {
+        match self {
+            SoundKind::Weapon => b'*',
+            SoundKind::Voice => b'!',
+            SoundKind::Item => b'+',
+            SoundKind::Movement => b'=',
+            SoundKind::Other => b'o',
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct HeardSound {
+    kind: SoundKind,
+    origin: Vector3<f32>,
+    time: Duration,
+}
+
+#[derive(Resource, Clone, Debug, Default)]
+pub struct SoundIndicators {
+    heard: Vec<HeardSound>,
+    directions: Vec<(SoundKind, Deg<f32>)>,
+}
+
+impl ExtractResource for SoundIndicators {
+    type Source = Self;
+
+    fn extract_resource(source: &Self::Source) -> Self {
+        source.clone()
+    }
+}
+
+impl SoundIndicators {
+    /// The kind of each recent sound and its direction as of the last update, measured
+    /// anticlockwise from straight ahead.
+    pub fn directions(&self) -> &[(SoundKind, Deg<f32>)] {
+        &self.directions
+    }
+
+    fn hear(&mut self, start: &StartSound, listener: &Listener, time: Duration) {
+        // sounds without attenuation play at full volume everywhere, so they have no direction
+        if start.attenuation == 0. {
+            return;
+        }
+
+        let origin = Vector3::from(start.origin);
+        if listener.attenuate(origin, start.volume, start.attenuation) < MIN_VOLUME {
+            return;
+        }
+
+        if self.heard.len() >= MAX_INDICATORS {
+            self.heard.remove(0);
+        }
+
+        self.heard.push(HeardSound {
+            kind: SoundKind::from_channel(start.ent_channel),
+            origin,
+            time,
+        });
+    }
+
+    /// Forget sounds which have expired and work out where the rest are relative to the current
+    /// view.
+    fn update(&mut self, listener: &Listener, time: Duration) {
+        let max_age = Duration::try_milliseconds(INDICATOR_MILLIS).unwrap();
+
+        // the clock restarts on a level change
+        self.heard
+            .retain(|sound| sound.time <= time && time - sound.time < max_age);
+
+        self.directions = self
+            .heard
+            .iter()
+            .filter_map(|sound| Some((sound.kind, direction(listener, sound.origin)?)))
+            .collect();
+    }
+
+    fn clear(&mut self) {
+        self.heard.clear();
+        self.directions.clear();
+    }
+}
+
+/// The horizontal angle from the direction the listener is facing to `origin`, positive to the
+/// left. Returns `None` if `origin` is directly above or below the listener.
+fn direction(listener: &Listener, origin: Vector3<f32>) -> Option<Deg<f32>> {
+    let left = listener.left_ear() - listener.right_ear();
+    let left = Vector3::new(left.x, left.y, 0.);
+    let forward = left.cross(Vector3::unit_z());
+
+    let to_sound = origin - listener.origin();
+    let (x, y) = (to_sound.dot(forward), to_sound.dot(left));
+    if left.magnitude2() == 0. || (x == 0. && y == 0.) {
+        return None;
+    }
+
+    Some(Deg::atan2(y, x))
+}
+
+pub fn update_sound_indicators(
+    registry: Res<Registry>,
+    listener: Res<Listener>,
+    conn: Option<Res<Connection>>,
+    mut events: EventReader<MixerEvent>,
+    mut indicators: ResMut<SoundIndicators>,
+) {
+    let enabled = registry.read_cvar::<u8>("cl_soundindicators").unwrap_or(0) != 0;
+    let Some(conn) = conn.filter(|_| enabled) else {
+        events.clear();
+        indicators.clear();
+        return;
+    };
+
+    let time = conn.state.time();
+    for event in events.read() {
+        if let MixerEvent::StartSound(start) = event {
+            // the player's own sounds are obvious from what they're doing
+            if start.ent_id != Some(conn.state.view_entity_id()) {
+                indicators.hear(start, &listener, time);
+            }
+        }
+    }
+
+    indicators.update(&listener, time);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: i64) -> Duration {
+        Duration::try_milliseconds(millis).unwrap()
+    }
+
+    // standing at the origin, facing along +x
+    fn listener() -> Listener {
+        Listener {
+            origin: Vector3::new(0., 0., 0.),
+            left_ear: Vector3::new(0., 4., 22.),
+            right_ear: Vector3::new(0., -4., 22.),
+        }
+    }
+
+    fn sound_at(channel: i8, origin: [f32; 3]) -> StartSound {
+        StartSound {
+            ent_channel: channel,
+            volume: 1.,
+            attenuation: 1.,
+            origin,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn test_direction() {
+        let listener = listener();
+        let angle = |origin: [f32; 3]| direction(&listener, origin.into()).unwrap().0;
+
+        assert!(angle([100., 0., 0.]).abs() < 1e-3);
+        assert!((angle([0., 100., 0.]) - 90.).abs() < 1e-3);
+        assert!((angle([0., -100., 50.]) + 90.).abs() < 1e-3);
+        assert!((angle([-100., 0., 0.]).abs() - 180.).abs() < 1e-3);
+        assert!(direction(&listener, Vector3::new(0., 0., 100.)).is_none());
+    }
+
+    #[test]
+    fn test_indicators_expire() {
+        let listener = listener();
+        let mut indicators = SoundIndicators::default();
+
+        indicators.hear(&sound_at(1, [100., 0., 0.]), &listener, ms(0));
+        // too far away to be heard
+        indicators.hear(&sound_at(3, [5000., 0., 0.]), &listener, ms(0));
+        indicators.hear(&sound_at(3, [0., 100., 0.]), &listener, ms(1000));
+
+        indicators.update(&listener, ms(1000));
+        let kinds = indicators
+            .directions()
+            .iter()
+            .map(|(kind, _)| *kind)
+            .collect::<Vec<_>>();
+        assert_eq!(kinds, vec![SoundKind::Weapon, SoundKind::Item]);
+
+        indicators.update(&listener, ms(2000));
+        assert_eq!(indicators.directions().len(), 1);
+
+        // a new level
+        indicators.update(&listener, ms(10));
+        assert!(indicators.directions().is_empty());
+    }
+}
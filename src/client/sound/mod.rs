@@ -18,7 +18,9 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+pub mod indicator;
 mod music;
+pub mod soundscape;
 use bevy::{
     app::{Main, Plugin},
     asset::{AssetServer, Handle},
@@ -30,7 +32,9 @@ use bevy::{
         component::Component,
         entity::Entity,
         event::{Event, EventReader},
-        system::{Commands, Query, Res, ResMut, Resource},
+        query::{Or, With},
+        schedule::IntoSystemConfigs as _,
+        system::{Commands, Local, Query, Res, ResMut, Resource},
     },
 };
 use fundsp::snoop::{Snoop, SnoopBackend};
@@ -40,6 +44,7 @@ use bevy_mod_dynamicaudio::{
     AddAudioMixer,
 };
 
+pub use indicator::SoundIndicators;
 pub use music::MusicPlayer;
 
 use std::io::{self, Read as _};
@@ -51,6 +56,10 @@ use thiserror::Error;
 
 pub const DISTANCE_ATTENUATION_FACTOR: f32 = 0.001;
 
+/// How many samples of each channel of the mixer's output are kept for [`GetGlobalAudio`]. This
+/// is over a second's worth, so that recording a video doesn't lose any on a slow frame.
+const SNOOP_CAPACITY: usize = 1 << 16;
+
 #[derive(Error, Debug)]
 pub enum SoundError {
     #[error("No such music track: {0}")]
@@ -59,6 +68,8 @@ pub enum SoundError {
     Io(#[from] io::Error),
     #[error("Virtual filesystem error: {0}")]
     Vfs(#[from] VfsError),
+    #[error("Invalid soundscape: {0}")]
+    Soundscape(String),
 }
 
 /// Data needed for sound spatialization.
@@ -155,8 +166,8 @@ pub struct SeismonSoundPlugin;
 
 impl Plugin for SeismonSoundPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
-        let (snoop_l, send_l) = Snoop::new(1024);
-        let (snoop_r, send_r) = Snoop::new(1024);
+        let (snoop_l, send_l) = Snoop::new(SNOOP_CAPACITY);
+        let (snoop_r, send_r) = Snoop::new(SNOOP_CAPACITY);
         let mixer = create_mixer(send_l, send_r);
 
         let global_audio = GetGlobalAudio {
@@ -175,6 +186,7 @@ impl Plugin for SeismonSoundPlugin {
             .insert_resource(global_audio)
             .init_resource::<MusicPlayer>()
             .init_resource::<Listener>()
+            .init_resource::<SoundIndicators>()
             .add_event::<MixerEvent>()
             .add_systems(
                 Main,
@@ -183,6 +195,8 @@ impl Plugin for SeismonSoundPlugin {
                     systems::update_mixer,
                     systems::update_listener,
                     systems::write_audio,
+                    systems::pause_sounds,
+                    indicator::update_sound_indicators.after(systems::update_listener),
                 ),
             );
     }
@@ -354,6 +368,8 @@ pub enum MixerEvent {
     StartSound(StartSound),
     StopSound(StopSound),
     StartStaticSound(StartStaticSound),
+    /// Stops every sound started with `StartStaticSound`.
+    StopStaticSounds,
     /// If None, restarts already-playing music
     StartMusic(Option<MusicSource>),
     PauseMusic,
@@ -366,6 +382,45 @@ pub struct GetGlobalAudio {
     pub right: Snoop<f32>,
 }
 
+impl GetGlobalAudio {
+    /// How many samples of each channel the mixer has output so far.
+    pub fn total(&self) -> u64 {
+        self.left.total()
+    }
+
+    /// How many samples of each channel the mixer had output when the oldest sample still held
+    /// by the snoops was output.
+    pub fn oldest(&self) -> u64 {
+        self.total().saturating_sub(self.left.capacity() as u64)
+    }
+
+    /// Takes the samples the mixer has output since this was last called.
+    pub fn update(&mut self) {
+        self.left.update();
+        self.right.update();
+    }
+
+    /// The samples output since the mixer had output `total`, oldest first, with the left and
+    /// right channels interleaved. Samples too old to still be held by the snoops are left out.
+    pub fn interleaved_since(&self, total: u64) -> Vec<f32> {
+        self.interleaved(total, usize::MAX)
+    }
+
+    /// Up to `count` samples of each channel, starting from the one output once the mixer had
+    /// output `start`, with the channels interleaved. As with
+    /// [`interleaved_since`](Self::interleaved_since), samples too old to still be held are left
+    /// out.
+    pub fn interleaved(&self, start: u64, count: usize) -> Vec<f32> {
+        let total = self.total();
+        let start = start.max(self.oldest());
+        let end = start.saturating_add(count as u64).min(total);
+        (start..end)
+            .map(|n| (total - 1 - n) as usize)
+            .flat_map(|i| [self.left.at(i), self.right.at(i)])
+            .collect()
+    }
+}
+
 mod systems {
     use bevy_mod_dynamicaudio::audio::AudioTarget;
 
@@ -383,6 +438,7 @@ mod systems {
         mut events: EventReader<MixerEvent>,
         mut commands: Commands,
         all_sounds: Query<&AudioSink>,
+        static_sounds: Query<Entity, With<StaticSound>>,
     ) {
         for event in events.read() {
             match *event {
@@ -431,6 +487,11 @@ mod systems {
                 MixerEvent::StartStaticSound(ref static_sound) => {
                     commands.spawn(StaticSoundBundle::new(static_sound, &*listener));
                 }
+                MixerEvent::StopStaticSounds => {
+                    for e in static_sounds.iter() {
+                        commands.entity(e).despawn();
+                    }
+                }
                 MixerEvent::StartMusic(Some(MusicSource::Named(ref named))) => {
                     // TODO: Error handling
                     music_player
@@ -466,6 +527,30 @@ mod systems {
         }
     }
 
+    /// Hold the sounds of the level while the server is paused, picking up where they left off
+    /// once it resumes. Music and sounds started while paused, such as those of the menu, carry
+    /// on playing.
+    pub fn pause_sounds(
+        conn: Option<Res<Connection>>,
+        sounds: Query<&AudioSink, Or<(With<Channel>, With<StaticSound>)>>,
+        mut paused: Local<bool>,
+    ) {
+        let pause = conn.is_some_and(|conn| conn.paused());
+        if pause == *paused {
+            return;
+        }
+
+        for sink in sounds.iter() {
+            if pause {
+                sink.pause();
+            } else {
+                sink.play();
+            }
+        }
+
+        *paused = pause;
+    }
+
     pub fn update_entities(
         mut entities: Query<(&mut AudioSink, Option<&EntityChannel>, &mut Channel)>,
         listener: Res<Listener>,
@@ -490,9 +575,7 @@ mod systems {
         }
     }
 
-    // TODO: Use this for `startvideo`
     pub fn write_audio(mut global_audio: ResMut<GetGlobalAudio>) {
-        global_audio.left.update();
-        global_audio.right.update();
+        global_audio.update();
     }
 }
@@ -0,0 +1,179 @@
+//! Music and ambient sounds chosen by the map.
+//!
+//! The server can only pick one of the CD tracks for a level. A map can instead name any music
+//! file, and add sounds that loop everywhere in the level, with a `maps/<name>.music` file next to
+//! its BSP:
+//!
+//! ```text
+//! // the music to play, as accepted by the `music` command
+//! music "mymap_theme"
+//! // a sound to loop, with an optional volume from 0 to 1
+//! ambient "ambience/wind2.wav" 0.5
+//! ambient "ambience/drip1.wav"
+//! ```
+//!
+//! Maps without such a file can set the `music` and `ambient` keys of their worldspawn, with the
+//! same meanings. Whichever way it is given, the map's music takes the place of the CD track that
+//! the server asks for, unless a demo is being played with a track of its own.
+
+use crate::{
+    client::sound::SoundError,
+    common::{
+        console::{CmdName, RunCmd},
+        vfs::{Vfs, VfsError},
+    },
+};
+
+use std::io::Read as _;
+
+/// The volume of an ambient sound that doesn't give one.
+const DEFAULT_AMBIENT_VOLUME: f32 = 1.0;
+
+/// A sound that loops everywhere in a level.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ambient {
+    /// The path of the sound, relative to `sound/`.
+    pub sound: String,
+    pub volume: f32,
+}
+
+/// The music and ambient sounds of a map.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Soundscape {
+    /// The music to play instead of the CD track.
+    pub music: Option<String>,
+    pub ambients: Vec<Ambient>,
+}
+
+impl Soundscape {
+    /// Parses the contents of a `.music` file.
+    pub fn parse(text: &str) -> Result<Soundscape, SoundError> {
+        let cmds = RunCmd::parse_many(text)
+            .map_err(|e| SoundError::Soundscape(format!("couldn't parse: {}", e)))?;
+
+        let mut soundscape = Soundscape::default();
+        for RunCmd(CmdName { name, .. }, args) in cmds {
+            match (&*name, &*args) {
+                ("music", [track]) => soundscape.music = Some(track.clone()),
+                ("ambient", [sound, rest @ ..]) if rest.len() <= 1 => {
+                    let volume = match rest.first() {
+                        Some(volume) => volume.parse::<f32>().map_err(|_| {
+                            SoundError::Soundscape(format!("invalid volume {}", volume))
+                        })?,
+                        None => DEFAULT_AMBIENT_VOLUME,
+                    };
+
+                    soundscape.ambients.push(Ambient {
+                        sound: sound.clone(),
+                        volume: volume.clamp(0.0, 1.0),
+                    });
+                }
+                ("music" | "ambient", _) => {
+                    return Err(SoundError::Soundscape(format!(
+                        "wrong number of arguments to {}",
+                        name
+                    )))
+                }
+                (other, _) => {
+                    return Err(SoundError::Soundscape(format!("unknown setting {}", other)))
+                }
+            }
+        }
+
+        Ok(soundscape)
+    }
+
+    /// Reads the settings from the keys of a map's worldspawn.
+    pub fn from_worldspawn<'a, I>(keys: I) -> Soundscape
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let mut soundscape = Soundscape::default();
+        for (key, value) in keys {
+            match key {
+                "music" => soundscape.music = Some(value.to_owned()),
+                "ambient" => soundscape.ambients.push(Ambient {
+                    sound: value.to_owned(),
+                    volume: DEFAULT_AMBIENT_VOLUME,
+                }),
+                _ => (),
+            }
+        }
+
+        soundscape
+    }
+
+    /// Finds the settings for the map at `map_path` (such as `maps/e1m1.bsp`), using its
+    /// worldspawn if it has no `.music` file.
+    pub fn load<'a, I>(vfs: &Vfs, map_path: &str, worldspawn: I) -> Result<Soundscape, SoundError>
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let path = match map_path.strip_suffix(".bsp") {
+            Some(stem) => format!("{}.music", stem),
+            None => format!("{}.music", map_path),
+        };
+
+        match vfs.open(&path) {
+            Ok(mut file) => {
+                let mut text = String::new();
+                file.read_to_string(&mut text)?;
+                Soundscape::parse(&text)
+            }
+            Err(VfsError::NoSuchFile(_)) => Ok(Soundscape::from_worldspawn(worldspawn)),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = r#"// e1m1
+music "e1m1_theme"
+ambient ambience/wind2.wav 0.5
+ambient "ambience/drip1.wav"
+"#;
+
+        assert_eq!(
+            Soundscape::parse(text).unwrap(),
+            Soundscape {
+                music: Some("e1m1_theme".to_owned()),
+                ambients: vec![
+                    Ambient {
+                        sound: "ambience/wind2.wav".to_owned(),
+                        volume: 0.5,
+                    },
+                    Ambient {
+                        sound: "ambience/drip1.wav".to_owned(),
+                        volume: 1.0,
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Soundscape::parse("volume 1\n").is_err());
+        assert!(Soundscape::parse("music\n").is_err());
+        assert!(Soundscape::parse("ambient wind.wav loud\n").is_err());
+    }
+
+    #[test]
+    fn test_from_worldspawn() {
+        let keys = [
+            ("classname", "worldspawn"),
+            ("sounds", "6"),
+            ("music", "base_theme"),
+            ("ambient", "ambience/wind2.wav"),
+        ];
+
+        let soundscape = Soundscape::from_worldspawn(keys);
+        assert_eq!(soundscape.music.as_deref(), Some("base_theme"));
+        assert_eq!(soundscape.ambients.len(), 1);
+    }
+}
@@ -1,15 +1,24 @@
-use std::{io::Read, iter};
+use std::{collections::VecDeque, io::Read, iter};
 
 use super::{sound::MixerEvent, view::BobVars};
 use crate::{
     client::{
         entity::{
+            decal::{Decal, DecalKind, Decals},
             particle::{Particle, Particles, TrailKind},
-            Beam, ClientEntity, Light, LightDesc, Lights, MAX_BEAMS, MAX_TEMP_ENTITIES,
+            smooth_angle, Beam, ClientEntity, LerpVars, Light, LightDesc, Lights, MAX_BEAMS,
+            MAX_TEMP_ENTITIES,
         },
+        progress::ConnectionProgress,
         render::Camera,
-        sound::{Listener, StartSound},
-        view::{IdleVars, KickVars, MouseVars, RollVars, View},
+        sound::{
+            self, soundscape::Soundscape, Listener, MusicSource, StartSound, StartStaticSound,
+        },
+        view::{
+            self, ChaseVars, Fog, FreeCamera, IdleVars, KickVars, MouseVars, RollVars, View,
+            ViewModel,
+        },
+        world_text::{self, Inventory},
         ClientError, ColorShiftCode, IntermissionKind, MoveVars, MAX_STATS,
     },
     common::{
@@ -19,9 +28,11 @@ use crate::{
         math::{self, Angles},
         model::{Model, ModelFlags, ModelKind, SyncType},
         net::{
-            self, BeamEntityKind, ButtonFlags, ColorShift, EntityEffects, ItemFlags, PlayerData,
-            PointEntityKind, TempEntity,
+            self, BeamEntityKind, ButtonFlags, ColorShift, EntityEffects, GameType, ItemFlags,
+            PlayerData, PointEntityKind, Protocol, SignOnStage, TempEntity,
         },
+        parse,
+        physics::pmove::{self, MoveCmd, MoveTrace, MoveWorld, PlayerState},
         util::QString,
         vfs::Vfs,
     },
@@ -41,6 +52,7 @@ use rand::{
 
 const CACHED_SOUND_NAMES: &[&str] = &[
     "hknight/hit.wav",
+    "misc/talk.wav",
     "weapons/r_exp3.wav",
     "weapons/ric1.wav",
     "weapons/ric2.wav",
@@ -51,12 +63,330 @@ const CACHED_SOUND_NAMES: &[&str] = &[
 
 const MAX_LIGHT_STYLES: usize = 64;
 
+/// How far the chase camera stays from walls, so that they aren't cut by the near plane.
+const CHASE_WALL_DISTANCE: f32 = 8.0;
+
+/// How far from a bullet or nail impact to look for the surface it hit. The progs move impacts a
+/// few units back from the wall.
+const IMPACT_DECAL_REACH: f32 = 8.0;
+
+/// How far from an explosion to look for a surface to scorch.
+const SCORCH_DECAL_REACH: f32 = 32.0;
+
+/// How far blood flies past what was hit to splash onto a surface behind it.
+const BLOOD_DECAL_REACH: f32 = 64.0;
+
+/// The world model's collision hulls, for predicting the player's movement.
+struct HullWorld {
+    /// The hull sized for the player's bounding box.
+    hull: bsp::BspCollisionHull,
+    /// The hull for a single point.
+    point_hull: bsp::BspCollisionHull,
+}
+
+impl HullWorld {
+    fn new(bmodel: &bsp::BspModel) -> Result<HullWorld, bsp::BspError> {
+        Ok(HullWorld {
+            hull: bmodel.hull(1)?,
+            point_hull: bmodel.hull(0)?,
+        })
+    }
+}
+
+impl MoveWorld for HullWorld {
+    type Error = bsp::BspError;
+
+    fn trace(&mut self, start: Vector3<f32>, end: Vector3<f32>) -> Result<MoveTrace, Self::Error> {
+        // the hull is already grown by the size of the player, but it may not be centred on
+        // their origin
+        let offset = self.hull.min() - pmove::PLAYER_MIN;
+        pmove::solid_trace(
+            |start, end| {
+                Ok(self
+                    .hull
+                    .trace(start - offset, end - offset)?
+                    .adjust(offset))
+            },
+            start,
+            end,
+        )
+    }
+
+    fn contents(&mut self, point: Vector3<f32>) -> Result<bsp::BspLeafContents, Self::Error> {
+        self.point_hull.contents_at_point(point)
+    }
+}
+
+/// The models and sounds of a level, loaded one at a time so that the connection screen keeps
+/// being drawn while a large map loads.
+pub struct Precache {
+    protocol: Protocol,
+    max_clients: u8,
+    game_type: GameType,
+    /// The models left to load, in precache order.
+    model_queue: VecDeque<String>,
+    /// The sounds left to load, in precache order, starting with the null sound.
+    sound_queue: VecDeque<String>,
+    models: im::Vector<Model>,
+    model_names: im::HashMap<String, usize>,
+    sounds: im::Vector<Handle<AudioSource>>,
+    /// The music and ambient sounds of the first BSP, which is the level itself.
+    soundscape: Option<Soundscape>,
+    fog: Fog,
+    /// The last sign-on stage the server moved the client to while the level was loading.
+    signon: Option<SignOnStage>,
+}
+
+impl Precache {
+    pub fn new<SName: AsRef<str>>(
+        protocol: Protocol,
+        max_clients: u8,
+        game_type: GameType,
+        model_precache: Vec<String>,
+        sound_precache: Vec<SName>,
+        progress: &mut ConnectionProgress,
+    ) -> Precache {
+        let sound_queue: VecDeque<_> = iter::once("misc/null.wav")
+            .chain(sound_precache.iter().map(AsRef::as_ref))
+            .map(str::to_owned)
+            .collect();
+        progress.begin_precache(model_precache.len() + sound_queue.len());
+
+        Precache {
+            protocol,
+            max_clients,
+            game_type,
+            model_queue: model_precache.into(),
+            sound_queue,
+            models: iter::once(Model::none()).collect(),
+            model_names: im::HashMap::new(),
+            sounds: im::Vector::new(),
+            soundscape: None,
+            fog: Fog::default(),
+            signon: None,
+        }
+    }
+
+    /// The state to parse the rest of the server's messages into until loading finishes.
+    pub fn placeholder(&self) -> ClientState {
+        ClientState {
+            protocol: self.protocol,
+            max_players: self.max_clients as usize,
+            ..ClientState::new()
+        }
+    }
+
+    /// Whether every model and sound in the precache lists has been loaded.
+    pub fn is_finished(&self) -> bool {
+        self.model_queue.is_empty() && self.sound_queue.is_empty()
+    }
+
+    /// Loads the next model or sound.
+    pub fn step(
+        &mut self,
+        vfs: &Vfs,
+        asset_server: &AssetServer,
+        progress: &mut ConnectionProgress,
+    ) -> Result<(), ClientError> {
+        if let Some(mod_name) = self.model_queue.pop_front() {
+            return self.load_model(vfs, mod_name, progress);
+        }
+
+        if let Some(snd_name) = self.sound_queue.pop_front() {
+            debug!("Loading sound {}: {}", self.sounds.len(), snd_name);
+            progress.loading(format!("sound/{}", snd_name));
+
+            let mut data = Vec::new();
+            vfs.open(format!("sound/{}", snd_name))?
+                .read_to_end(&mut data)
+                .unwrap();
+            self.sounds
+                .push_back(asset_server.add(AudioSource { bytes: data.into() }));
+        }
+
+        Ok(())
+    }
+
+    fn load_model(
+        &mut self,
+        vfs: &Vfs,
+        mod_name: String,
+        progress: &mut ConnectionProgress,
+    ) -> Result<(), ClientError> {
+        progress.loading(&*mod_name);
+
+        // BSPs can have more than one model
+        if mod_name.ends_with(".bsp") {
+            let bsp_data = vfs.open(&mod_name)?;
+            let lit = load_lit(vfs, &mod_name);
+            let (mut brush_models, ent_string) = bsp::load_with_lit(bsp_data, lit).unwrap();
+            // the first BSP is the level itself
+            if self.soundscape.is_none() {
+                let entities = parse::entities(&ent_string).unwrap_or_default();
+                let worldspawn = entities.into_iter().next().unwrap_or_default();
+
+                if let Some(value) = worldspawn.get("fog") {
+                    self.fog = Fog::parse(value).unwrap_or_else(|| {
+                        warn!("Ignoring invalid fog \"{}\" in {}", value, mod_name);
+                        Fog::default()
+                    });
+                }
+                self.soundscape = Some(load_soundscape(vfs, &mod_name, &worldspawn));
+            }
+
+            for bmodel in brush_models.drain(..) {
+                let id = self.models.len();
+                let name = bmodel.name().to_owned();
+                self.models.push_back(bmodel);
+                self.model_names.insert(name, id);
+            }
+        } else if bsp::submodel_index(&mod_name).is_none() {
+            // model names starting with * are loaded from the world BSP
+            debug!("Loading model {}", mod_name);
+            let id = self.models.len();
+            self.models
+                .push_back(Model::load_with_replacement(vfs, &mod_name)?);
+            self.model_names.insert(mod_name, id);
+        }
+
+        Ok(())
+    }
+
+    /// Records a sign-on stage to move to once the level is loaded.
+    pub fn defer_signon(&mut self, stage: SignOnStage) {
+        self.signon = Some(stage);
+    }
+
+    /// The sign-on stage to move to once the level is loaded.
+    pub fn deferred_signon(&self) -> Option<SignOnStage> {
+        self.signon
+    }
+
+    /// Loads the sounds that every level uses and builds the state of the loaded level.
+    pub fn finish(
+        self,
+        vfs: &Vfs,
+        asset_server: &AssetServer,
+        progress: &mut ConnectionProgress,
+    ) -> Result<ClientState, ClientError> {
+        let cached_sounds = CACHED_SOUND_NAMES
+            .iter()
+            .map(|name| {
+                let mut data = Vec::new();
+                vfs.open(format!("sound/{}", name))?
+                    .read_to_end(&mut data)
+                    .unwrap();
+
+                Ok((
+                    name.to_string(),
+                    asset_server.add(AudioSource { bytes: data.into() }),
+                ))
+            })
+            .collect::<Result<_, ClientError>>()?;
+
+        let Soundscape { music, ambients } = self.soundscape.unwrap_or_default();
+        let ambient_sounds = ambients
+            .into_iter()
+            .filter_map(|ambient| match sound::load(vfs, &ambient.sound) {
+                Ok(source) => Some(StartStaticSound {
+                    src: asset_server.add(source),
+                    origin: Vector3::zero(),
+                    volume: ambient.volume,
+                    // heard the same everywhere
+                    attenuation: 0.0,
+                }),
+                Err(e) => {
+                    warn!("Couldn't load ambient sound {}: {}", ambient.sound, e);
+                    None
+                }
+            })
+            .collect();
+
+        progress.finish_precache();
+
+        Ok(ClientState {
+            models: self.models,
+            model_names: self.model_names,
+            sounds: self.sounds,
+            cached_sounds,
+            map_music: music,
+            ambient_sounds,
+            fog: self.fog,
+            max_players: self.max_clients as usize,
+            deathmatch: self.game_type == GameType::Deathmatch,
+            protocol: self.protocol,
+            ..ClientState::new()
+        })
+    }
+}
+
+/// How many times a second light styles step to their next brightness.
+const LIGHT_STYLE_FPS: f32 = 10.0;
+
+/// The smallest change in a light style's brightness, in letters, that `r_lerplightstyles 1`
+/// leaves sharp, so that flickering and strobing lights don't turn into a slow pulse.
+const ABRUPT_LIGHT_STYLE_CHANGE: u8 = (b'm' - b'a') / 2;
+
+/// The brightness of light style `style` at `time` seconds, from 0 to 2 with 1 as normal.
+///
+/// With `lerp` at 0 the style steps from letter to letter as in Quake, at 1 it blends smoothly
+/// between letters that are close but steps between ones that are far apart, and at 2 it always
+/// blends.
+fn lightstyle_value(style: &str, time: f32, lerp: u8) -> f32 {
+    // 'z' - 'a' = 25, so divide by 12.5 to get range [0, 2]
+    let factor = ((b'z' - b'a') as f32 / 2.).recip();
+    let style = style.as_bytes();
+    if style.is_empty() {
+        return 1.;
+    }
+
+    let steps = time * LIGHT_STYLE_FPS;
+    let frame = steps as usize % style.len();
+    let current = style[frame].saturating_sub(b'a');
+    let next = style[(frame + 1) % style.len()].saturating_sub(b'a');
+
+    let blend = match lerp {
+        0 => false,
+        1 => current.abs_diff(next) < ABRUPT_LIGHT_STYLE_CHANGE,
+        _ => true,
+    };
+    let value = if blend {
+        let t = steps.fract();
+        current as f32 * (1. - t) + next as f32 * t
+    } else {
+        current as f32
+    };
+
+    value * factor
+}
+
+/// Finds the music and ambient sounds of the map at `map_path`, whose entities are `ent_string`.
+fn load_soundscape(vfs: &Vfs, map_path: &str, worldspawn: &HashMap<&str, &str>) -> Soundscape {
+    let keys = worldspawn.iter().map(|(k, v)| (*k, *v));
+    Soundscape::load(vfs, map_path, keys).unwrap_or_else(|e| {
+        warn!("Couldn't load the soundscape of {}: {}", map_path, e);
+        Soundscape::default()
+    })
+}
+
+/// Read the colored lightmaps of the map at `map_path` from the `.lit` file beside it, if it has
+/// one.
+fn load_lit(vfs: &Vfs, map_path: &str) -> Option<Box<[u8]>> {
+    let lit_path = format!("{}.lit", map_path.strip_suffix(".bsp").unwrap_or(map_path));
+    let lit_data = vfs.open(&lit_path).ok()?;
+
+    bsp::read_lit(lit_data)
+        .map_err(|e| warn!("Couldn't load colored lightmaps from {}: {}", lit_path, e))
+        .ok()
+}
+
 #[derive(Clone)]
 pub struct PlayerInfo {
     pub name: QString,
     pub frags: i32,
     pub colors: PlayerColor,
-    // translations: [u8; VID_GRADES],
+    /// Left empty, as NetQuake has no message for other players' pings.
+    pub ping: Option<Duration>,
 }
 
 // TODO: We clone this into the render world but this is inefficient
@@ -85,12 +415,16 @@ pub struct ClientState {
     pub entities: im::Vector<ClientEntity>,
     pub static_entities: im::Vector<ClientEntity>,
     pub temp_entities: im::Vector<ClientEntity>,
+    // the recorded run being raced against, see `client::ghost`
+    pub ghost: Option<ClientEntity>,
     // dynamic point lights
     pub lights: Lights,
     // lightning bolts and grappling hook cable
     pub beams: [Option<Beam>; MAX_BEAMS],
     // particle effects
     pub particles: Particles,
+    // bullet holes, blood and scorch marks
+    pub decals: Decals,
 
     // visible entities, rebuilt per-frame
     pub visible_entity_ids: im::Vector<usize>,
@@ -101,8 +435,13 @@ pub struct ClientState {
     pub stats: [i32; MAX_STATS],
 
     pub max_players: usize,
+    /// Whether the server is running a deathmatch rather than single player or co-op.
+    deathmatch: bool,
     pub player_info: [Option<PlayerInfo>; net::MAX_CLIENTS],
 
+    // the encoding the server uses for this level, given in the server info
+    pub protocol: Protocol,
+
     // the last two timestamps sent by the server (for lerping)
     pub msg_times: [Duration; 2],
     pub time: Duration,
@@ -117,12 +456,25 @@ pub struct ClientState {
     pub msg_velocity: [Vector3<f32>; 2],
     pub velocity: Vector3<f32>,
 
-    // paused: bool,
+    /// Whether the server has paused the game.
+    pub paused: bool,
     pub on_ground: bool,
     pub in_water: bool,
+    /// Where the player is predicted to be, carried on from the last update from the server by
+    /// the moves sent since. The view follows it rather than the player entity while there is one.
+    predicted: Option<PlayerState>,
     pub intermission: Option<IntermissionKind>,
     pub start_time: Duration,
     pub completion_time: Option<Duration>,
+
+    /// The player's items and stats as of the last update, for finding what they pick up.
+    inventory: Option<Inventory>,
+
+    /// The music the map plays in place of the CD track, if it has its own.
+    map_music: Option<String>,
+    /// The sounds the map loops everywhere.
+    ambient_sounds: Vec<StartStaticSound>,
+    pub fog: Fog,
 }
 
 impl Default for ClientState {
@@ -149,14 +501,18 @@ impl ClientState {
             entities: default(),
             static_entities: default(),
             temp_entities: default(),
+            ghost: None,
             lights: Lights::new(),
             beams: [None; MAX_BEAMS],
             particles: Particles::new(),
+            decals: Decals::new(),
             visible_entity_ids: default(),
             light_styles: iter::repeat_n("".into(), MAX_LIGHT_STYLES).collect(),
             stats: [0; MAX_STATS],
             max_players: 0,
+            deathmatch: false,
             player_info: default(),
+            protocol: Protocol::NETQUAKE,
             msg_times: [Duration::zero(), Duration::zero()],
             time: Duration::zero(),
             lerp_factor: 0.0,
@@ -167,85 +523,45 @@ impl ClientState {
             face_anim_time: Duration::zero(),
             msg_velocity: [Vector3::zero(), Vector3::zero()],
             velocity: Vector3::zero(),
+            paused: false,
             on_ground: false,
             in_water: false,
+            predicted: None,
             intermission: None,
             start_time: Duration::zero(),
             completion_time: None,
+            inventory: None,
+            map_music: None,
+            ambient_sounds: Vec::new(),
+            fog: Fog::default(),
         }
     }
 
+    /// Loads a level's models and sounds all at once. Connections that can wait use a
+    /// [`Precache`] instead, which loads them over several frames.
     pub fn from_server_info<SName: AsRef<str>>(
         vfs: &Vfs,
         asset_server: &AssetServer,
+        protocol: Protocol,
         max_clients: u8,
+        game_type: GameType,
         model_precache: Vec<String>,
         sound_precache: Vec<SName>,
+        progress: &mut ConnectionProgress,
     ) -> Result<ClientState, ClientError> {
-        // TODO: validate submodel names
-        let mut models: im::Vector<_> = iter::once(Model::none()).collect();
-        let mut model_names = im::HashMap::new();
-        for mod_name in model_precache {
-            // BSPs can have more than one model
-            if mod_name.ends_with(".bsp") {
-                let bsp_data = vfs.open(&mod_name)?;
-                let (mut brush_models, _) = bsp::load(bsp_data).unwrap();
-                for bmodel in brush_models.drain(..) {
-                    let id = models.len();
-                    let name = bmodel.name().to_owned();
-                    models.push_back(bmodel);
-                    model_names.insert(name, id);
-                }
-            } else if !mod_name.starts_with("*") {
-                // model names starting with * are loaded from the world BSP
-                debug!("Loading model {}", mod_name);
-                let id = models.len();
-                models.push_back(Model::load(vfs, &mod_name)?);
-                model_names.insert(mod_name, id);
-            }
-
-            // TODO: send keepalive message?
+        let mut precache = Precache::new(
+            protocol,
+            max_clients,
+            game_type,
+            model_precache,
+            sound_precache,
+            progress,
+        );
+        while !precache.is_finished() {
+            precache.step(vfs, asset_server, progress)?;
         }
 
-        let sounds = iter::once("misc/null.wav")
-            .chain(sound_precache.iter().map(AsRef::as_ref))
-            .enumerate()
-            .map(|(i, snd_name)| {
-                debug!("Loading sound {}: {}", i, snd_name);
-
-                let mut data = Vec::new();
-                vfs.open(format!("sound/{}", snd_name))?
-                    .read_to_end(&mut data)
-                    .unwrap();
-
-                Ok(asset_server.add(AudioSource { bytes: data.into() }))
-                // TODO: send keepalive message?
-            })
-            .collect::<Result<_, ClientError>>()?;
-
-        let cached_sounds = CACHED_SOUND_NAMES
-            .iter()
-            .map(|name| {
-                let mut data = Vec::new();
-                vfs.open(format!("sound/{}", name))?
-                    .read_to_end(&mut data)
-                    .unwrap();
-
-                Ok((
-                    name.to_string(),
-                    asset_server.add(AudioSource { bytes: data.into() }),
-                ))
-            })
-            .collect::<Result<_, ClientError>>()?;
-
-        Ok(ClientState {
-            models,
-            model_names,
-            sounds,
-            cached_sounds,
-            max_players: max_clients as usize,
-            ..ClientState::new()
-        })
+        precache.finish(vfs, asset_server, progress)
     }
 
     /// Advance the simulation time by the specified amount.
@@ -321,13 +637,23 @@ impl ClientState {
     ///   message
     /// - Spawning particles on entities with particle effects
     /// - Spawning dynamic lights on entities with lighting effects
-    pub fn update_entities(&mut self) -> Result<(), ClientError> {
+    ///
+    /// The view entity is only drawn if `draw_view_entity` is set, as the camera is otherwise
+    /// inside it.
+    pub fn update_entities(
+        &mut self,
+        frame_time: Duration,
+        lerp_vars: &LerpVars,
+        draw_view_entity: bool,
+    ) -> Result<(), ClientError> {
         lazy_static! {
             static ref MFLASH_DIMLIGHT_DISTRIBUTION: Uniform<f32> = Uniform::new(200.0, 232.0);
             static ref BRIGHTLIGHT_DISTRIBUTION: Uniform<f32> = Uniform::new(400.0, 432.0);
         }
 
         let lerp_factor = self.lerp_factor;
+        let time = self.time;
+        let frame_time = engine::duration_to_f32(frame_time);
 
         self.velocity =
             self.msg_velocity[1] + lerp_factor * (self.msg_velocity[0] - self.msg_velocity[1]);
@@ -353,41 +679,26 @@ impl ClientState {
             }
 
             let prev_origin = ent.origin;
+            let prev_angles = ent.angles;
 
+            let model = &self.models[ent.model_id];
             if ent.force_link {
                 trace!("force link on entity {}", ent.id);
-                ent.origin = ent.msg_origins[0];
-                ent.angles = ent.msg_angles[0];
-            } else {
-                let origin_delta = ent.msg_origins[0] - ent.msg_origins[1];
-                let ent_lerp_factor = if origin_delta.magnitude2() > 10_000.0 {
-                    // if the entity moved more than 100 units in one frame,
-                    // assume it was teleported and don't lerp anything
-                    1.0
-                } else {
-                    lerp_factor
-                };
-
-                ent.origin = ent.msg_origins[1] + ent_lerp_factor * origin_delta;
-
-                // assume that entities will not whip around 180+ degrees in one
-                // frame and adjust the delta accordingly. this avoids a bug
-                // where small turns between 0 <-> 359 cause the demo camera to
-                // face backwards for one frame.
+                ent.snap();
+            } else if lerp_vars.lerps_model(model.name()) {
+                ent.lerp(ent.move_factor(time, lerp_factor), lerp_vars);
                 for i in 0..3 {
-                    let mut angle_delta = ent.msg_angles[0][i] - ent.msg_angles[1][i];
-                    if angle_delta > Deg(180.0) {
-                        angle_delta = Deg(360.0) - angle_delta;
-                    } else if angle_delta < Deg(-180.0) {
-                        angle_delta = Deg(360.0) + angle_delta;
-                    }
-
-                    ent.angles[i] =
-                        (ent.msg_angles[1][i] + angle_delta * ent_lerp_factor).normalize();
+                    ent.angles[i] = smooth_angle(
+                        prev_angles[i],
+                        ent.angles[i],
+                        frame_time,
+                        lerp_vars.angle_smooth,
+                    );
                 }
+            } else {
+                ent.snap();
             }
 
-            let model = &self.models[ent.model_id];
             if model.has_flag(ModelFlags::ROTATE) {
                 ent.angles[1] = obj_rotate;
             }
@@ -398,11 +709,15 @@ impl ClientState {
 
             // TODO: factor out EntityEffects->LightDesc mapping
             if ent.effects.contains(EntityEffects::MUZZLE_FLASH) {
-                // TODO: angle and move origin to muzzle
+                let (forward, _) = view::forward_right(Angles {
+                    pitch: ent.angles.x,
+                    yaw: ent.angles.y,
+                    roll: ent.angles.z,
+                });
                 ent.light_id = Some(self.lights.insert(
                     self.time,
                     LightDesc {
-                        origin: ent.origin + Vector3::new(0.0, 0.0, 16.0),
+                        origin: ent.origin + Vector3::new(0.0, 0.0, 16.0) + forward * 18.0,
                         init_radius: MFLASH_DIMLIGHT_DISTRIBUTION.sample(&mut self.rng),
                         decay_rate: 0.0,
                         min_radius: Some(32.0),
@@ -477,7 +792,7 @@ impl ClientState {
             }
 
             // don't render the player model
-            if self.view.entity_id() != ent.id {
+            if draw_view_entity || self.view.entity_id() != ent.id {
                 // mark entity for rendering
                 self.visible_entity_ids.push_back(ent.id);
             }
@@ -547,11 +862,13 @@ impl ClientState {
                 let forward = (vec.x.powf(2.0) + vec.y.powf(2.0)).sqrt();
                 let pitch = Deg::from(cgmath::Rad(vec.z.atan2(forward))).normalize();
 
+                // the bolt model is drawn once every 30 units, all the way to the end
                 let len = vec.magnitude();
                 let direction = vec.normalize();
-                for interval in 0..(len / 30.0) as i32 {
+                for interval in 0..(len / 30.0).ceil() as i32 {
                     let id = self.temp_entities.len();
                     let mut ent = ClientEntity::uninitialized(id);
+                    ent.model_id = beam.model_id;
                     ent.origin = beam.start + 30.0 * interval as f32 * direction;
                     ent.angles =
                         Vector3::new(pitch, yaw, Deg(ANGLE_DISTRIBUTION.sample(&mut self.rng)));
@@ -568,7 +885,16 @@ impl ClientState {
         Ok(())
     }
 
-    pub fn update_player(&mut self, update: PlayerData) {
+    /// Applies an update of the player's state, returning labels for what they picked up since
+    /// the last one.
+    pub fn update_player(&mut self, update: PlayerData) -> Vec<String> {
+        let inventory = Inventory::new(&update);
+        let pickups = match self.inventory.replace(inventory) {
+            Some(before) => world_text::pickup_labels(&before, &inventory),
+            // the first update of a level carries over what the player already had
+            None => Vec::new(),
+        };
+
         self.view
             .set_view_height(update.view_height.unwrap_or(net::DEFAULT_VIEWHEIGHT));
         self.view
@@ -601,6 +927,9 @@ impl ClientState {
         self.on_ground = update.on_ground;
         self.in_water = update.in_water;
 
+        // the server has caught up with the moves predicted so far
+        self.predicted = None;
+
         self.stats[ClientStat::WeaponFrame as usize] =
             update.weapon_frame.unwrap_or_default() as i32;
         self.stats[ClientStat::Armor as usize] = update.armor.unwrap_or_default() as i32;
@@ -615,6 +944,8 @@ impl ClientState {
         // TODO: this behavior assumes the `standard_quake` behavior and will likely
         // break with the mission packs
         self.stats[ClientStat::ActiveWeapon as usize] = update.active_weapon as i32;
+
+        pickups
     }
 
     pub fn handle_input(
@@ -702,7 +1033,7 @@ impl ClientState {
         self.face_anim_time = self.time + Duration::try_milliseconds(200).unwrap();
 
         let dmg_factor = (armor + health).min(20) as f32 / 2.0;
-        let mut cshift = self.color_shifts[ColorShiftCode::Damage as usize];
+        let cshift = &mut self.color_shifts[ColorShiftCode::Damage as usize];
         cshift.percent += 3 * dmg_factor as i32;
         cshift.percent = cshift.percent.clamp(0, 150);
 
@@ -739,6 +1070,7 @@ impl ClientState {
         kick_vars: KickVars,
         roll_vars: RollVars,
         bob_vars: BobVars,
+        chase_vars: ChaseVars,
     ) {
         self.view.calc_final_angles(
             self.time,
@@ -749,8 +1081,38 @@ impl ClientState {
             roll_vars,
         );
         if let Some(e) = self.entities.get(self.view.entity_id()) {
+            let origin = self
+                .predicted
+                .map_or(e.origin, |predicted| predicted.origin);
             self.view
-                .calc_final_origin(self.time, e.origin, self.velocity, bob_vars);
+                .calc_final_origin(self.time, origin, self.velocity, bob_vars);
+        }
+
+        if chase_vars.chase_active != 0.0 && self.intermission.is_none() {
+            let origin = self.view.final_origin();
+            let target = view::chase_target(origin, self.view.final_angles(), chase_vars);
+            self.view
+                .set_final_origin(self.chase_origin(origin, target));
+        }
+    }
+
+    /// Move the chase camera from the view at `origin` towards `target`, stopping short of any
+    /// wall in the way.
+    fn chase_origin(&self, origin: Vector3<f32>, target: Vector3<f32>) -> Vector3<f32> {
+        let Some(ModelKind::Brush(bmodel)) = self.models.get(self.worldmodel_id).map(|m| m.kind())
+        else {
+            return target;
+        };
+
+        let trace = bmodel.hull(0).and_then(|hull| {
+            pmove::solid_trace(|start, end| hull.trace(start, end), origin, target)
+        });
+        match trace {
+            Ok(trace) if trace.fraction < 1.0 => {
+                let dist = (target - origin).magnitude() * trace.fraction;
+                origin + (target - origin).normalize_to((dist - CHASE_WALL_DISTANCE).max(0.0))
+            }
+            _ => target,
         }
     }
 
@@ -837,9 +1199,9 @@ impl ClientState {
         }
 
         let mut spike_sound = || match ZERO_ONE_DISTRIBUTION.sample(&mut self.rng) {
-            x if x < 0.2 => "weapons/tink1.wav",
-            x if x < 0.4667 => "weapons/ric1.wav",
-            x if x < 0.7333 => "weapons/ric2.wav",
+            x if x < 0.8 => "weapons/tink1.wav",
+            x if x < 0.85 => "weapons/ric1.wav",
+            x if x < 0.9 => "weapons/ric2.wav",
             _ => "weapons/ric3.wav",
         };
 
@@ -850,16 +1212,11 @@ impl ClientState {
                     // projectile impacts
                     WizSpike | KnightSpike | Spike | SuperSpike | Gunshot => {
                         let (color, count, sound) = match kind {
-                            // TODO: start wizard/hit.wav
                             WizSpike => (20, 30, Some("wizard/hit.wav")),
 
                             KnightSpike => (226, 20, Some("hknight/hit.wav")),
 
-                            // TODO: for Spike and SuperSpike, start one of:
-                            // - 26.67%: weapons/tink1.wav
-                            // - 20.0%: weapons/ric1.wav
-                            // - 20.0%: weapons/ric2.wav
-                            // - 20.0%: weapons/ric3.wav
+                            // nails mostly tink off walls, and sometimes ricochet
                             Spike => (0, 10, Some(spike_sound())),
                             SuperSpike => (0, 20, Some(spike_sound())),
 
@@ -876,6 +1233,11 @@ impl ClientState {
                             count,
                         );
 
+                        // the wizard's and death knight's spikes are magic and leave no mark
+                        if matches!(kind, Spike | SuperSpike | Gunshot) {
+                            self.spawn_decal(DecalKind::BulletHole, *origin, IMPACT_DECAL_REACH);
+                        }
+
                         if let Some(snd) = sound {
                             events.send(MixerEvent::StartSound(StartSound {
                                 src: self.cached_sounds.get(snd).unwrap().clone(),
@@ -890,6 +1252,7 @@ impl ClientState {
 
                     Explosion => {
                         self.particles.create_explosion(self.time, *origin);
+                        self.spawn_decal(DecalKind::Scorch, *origin, SCORCH_DECAL_REACH);
                         self.lights.insert(
                             self.time,
                             LightDesc {
@@ -925,6 +1288,7 @@ impl ClientState {
                             *origin,
                             (*color_start)..=(*color_start + *color_len - 1),
                         );
+                        self.spawn_decal(DecalKind::Scorch, *origin, SCORCH_DECAL_REACH);
                         self.lights.insert(
                             self.time,
                             LightDesc {
@@ -981,25 +1345,98 @@ impl ClientState {
             } => {
                 use BeamEntityKind::*;
                 let model_name = match kind {
-                    Lightning { model_id } => format!(
-                        "progs/bolt{}.mdl",
-                        match model_id {
-                            1 => "",
-                            2 => "2",
-                            3 => "3",
-                            x => panic!("invalid lightning model id: {}", x),
-                        }
-                    ),
-                    Grapple => "progs/beam.mdl".to_string(),
+                    Lightning { model_id: 1 } => "progs/bolt.mdl",
+                    Lightning { model_id: 2 } => "progs/bolt2.mdl",
+                    Lightning { model_id: 3 } => "progs/bolt3.mdl",
+                    Lightning { model_id } => {
+                        warn!("Invalid lightning model id: {}", model_id);
+                        return;
+                    }
+                    Grapple => "progs/beam.mdl",
                 };
 
-                if let Some(beam) = self.model_names.get(&model_name) {
+                if let Some(beam) = self.model_names.get(model_name) {
                     self.spawn_beam(self.time, *entity_id as usize, *beam, *start, *end);
                 }
             }
         }
     }
 
+    /// Find where the line from `start` to `end` first hits the world, returning the point it
+    /// hits, the normal of the surface there and how far along the line it is.
+    ///
+    /// Only the world itself is traced against, so doors, platforms and other brush entities
+    /// don't get decals.
+    fn trace_world(
+        &self,
+        start: Vector3<f32>,
+        end: Vector3<f32>,
+    ) -> Option<(Vector3<f32>, Vector3<f32>, f32)> {
+        let Some(ModelKind::Brush(bmodel)) = self.models.get(self.worldmodel_id).map(|m| m.kind())
+        else {
+            return None;
+        };
+
+        let trace = bmodel
+            .hull(0)
+            .and_then(|hull| pmove::solid_trace(|start, end| hull.trace(start, end), start, end))
+            .ok()?;
+        if trace.all_solid || trace.fraction >= 1.0 {
+            return None;
+        }
+
+        trace
+            .normal
+            .map(|normal| (trace.end, normal, trace.fraction))
+    }
+
+    /// Leave a decal on the surface nearest to `origin` along any axis, if there's one within
+    /// `reach`.
+    pub fn spawn_decal(&mut self, kind: DecalKind, origin: Vector3<f32>, reach: f32) {
+        let nearest = [
+            Vector3::unit_x(),
+            -Vector3::unit_x(),
+            Vector3::unit_y(),
+            -Vector3::unit_y(),
+            Vector3::unit_z(),
+            -Vector3::unit_z(),
+        ]
+        .into_iter()
+        .filter_map(|dir| self.trace_world(origin, origin + dir * reach))
+        .min_by(|(_, _, a), (_, _, b)| a.total_cmp(b));
+
+        if let Some((point, normal, _)) = nearest {
+            self.insert_decal(kind, point, normal);
+        }
+    }
+
+    /// Splash blood onto whatever surface is behind `origin` in `direction`, or below it if the
+    /// blood isn't moving.
+    pub fn spawn_blood_decal(&mut self, origin: Vector3<f32>, direction: Vector3<f32>) {
+        let direction = if direction.magnitude2() > 0.0 {
+            direction.normalize()
+        } else {
+            -Vector3::unit_z()
+        };
+
+        if let Some((point, normal, _)) =
+            self.trace_world(origin, origin + direction * BLOOD_DECAL_REACH)
+        {
+            self.insert_decal(DecalKind::Blood, point, normal);
+        }
+    }
+
+    fn insert_decal(&mut self, kind: DecalKind, origin: Vector3<f32>, normal: Vector3<f32>) {
+        lazy_static! {
+            static ref ROTATION_DISTRIBUTION: Uniform<f32> =
+                Uniform::new(0.0, std::f32::consts::TAU);
+        }
+
+        let rotation = ROTATION_DISTRIBUTION.sample(&mut self.rng);
+        self.decals
+            .insert(Decal::new(self.time, kind, origin, normal, rotation));
+    }
+
     pub fn spawn_beam(
         &mut self,
         time: Duration,
@@ -1018,6 +1455,7 @@ impl ClientState {
                     beam.expire = time + Duration::try_milliseconds(200).unwrap();
                     beam.start = start;
                     beam.end = end;
+                    return;
                 }
             } else if free.is_none() {
                 free = Some(i);
@@ -1038,6 +1476,41 @@ impl ClientState {
     }
 
     #[must_use]
+    /// The music the map plays in place of the CD track, if it has its own.
+    pub fn map_music(&self) -> Option<&str> {
+        self.map_music.as_deref()
+    }
+
+    /// Stops the looping sounds of the last level and starts the map's music and ambient sounds.
+    pub fn start_level_sounds(&self, events: &mut EventWriter<MixerEvent>) {
+        events.send(MixerEvent::StopStaticSounds);
+        for ambient in &self.ambient_sounds {
+            events.send(MixerEvent::StartStaticSound(ambient.clone()));
+        }
+
+        if let Some(music) = &self.map_music {
+            events.send(MixerEvent::StartMusic(Some(MusicSource::Named(
+                music.clone(),
+            ))));
+        }
+    }
+
+    /// Plays the sound that announces a chat message, wherever the player is.
+    pub fn play_talk_sound(&self, events: &mut EventWriter<MixerEvent>) {
+        let Some(src) = self.cached_sounds.get("misc/talk.wav") else {
+            return;
+        };
+
+        events.send(MixerEvent::StartSound(StartSound {
+            src: src.clone(),
+            ent_id: None,
+            ent_channel: 0,
+            volume: 1.0,
+            attenuation: 0.0,
+            origin: self.view.final_origin().into(),
+        }));
+    }
+
     pub fn update_listener(&self) -> Option<Listener> {
         // TODO: update to self.view_origin()
         let origin = self.entities.get(self.view.entity_id()).map(|e| e.origin)?;
@@ -1058,6 +1531,77 @@ impl ClientState {
         })
     }
 
+    /// Predicts where the server will put the player once it has run `cmd` for `frame_time`, using
+    /// the same movement code as the server. Each prediction carries on from the last until the
+    /// next update from the server, which starts them again from where the server put the player.
+    ///
+    /// Only the world geometry is taken into account, not other entities.
+    pub fn predict_player_move(
+        &mut self,
+        cmd: &ClientCmd,
+        vars: &pmove::MoveVars,
+        frame_time: Duration,
+    ) -> Result<(), ClientError> {
+        let ClientCmd::Move {
+            angles,
+            fwd_move,
+            side_move,
+            up_move,
+            ..
+        } = *cmd
+        else {
+            return Ok(());
+        };
+
+        if self.intermission.is_some() {
+            return Ok(());
+        }
+
+        let Some(ModelKind::Brush(bmodel)) = self.models.get(self.worldmodel_id).map(|m| m.kind())
+        else {
+            return Ok(());
+        };
+        let mut world = HullWorld::new(bmodel)?;
+
+        let mut state = match self.predicted {
+            Some(state) => state,
+            None => {
+                let Some(ent) = self.entities.get(self.view.entity_id()) else {
+                    return Ok(());
+                };
+
+                let origin = ent.msg_origins[0];
+                PlayerState {
+                    origin,
+                    velocity: self.msg_velocity[0],
+                    on_ground: self.on_ground,
+                    water_level: pmove::water_level(&mut world, origin)?,
+                }
+            }
+        };
+
+        let cmd = MoveCmd {
+            angles: Angles {
+                pitch: angles.x,
+                roll: angles.z,
+                yaw: angles.y,
+            },
+            forward: fwd_move as f32,
+            side: side_move as f32,
+            up: up_move as f32,
+        };
+        pmove::player_move(
+            &mut state,
+            &cmd,
+            vars,
+            engine::duration_to_f32(frame_time),
+            &mut world,
+        )?;
+
+        self.predicted = Some(state);
+        Ok(())
+    }
+
     fn view_leaf_contents(&self) -> Result<bsp::BspLeafContents, ClientError> {
         match self.models.get(1).map(|m| m.kind()) {
             Some(ModelKind::Brush(ref bmodel)) => {
@@ -1198,18 +1742,42 @@ impl ClientState {
         }
     }
 
+    /// The weapon the player is holding, unless there's nothing to draw: the player has no
+    /// weapon, is dead or invisible, or the level is over.
+    pub fn viewmodel(&self) -> Option<ViewModel> {
+        if self.stats[ClientStat::Weapon as usize] <= 0
+            || self.stats[ClientStat::Health as usize] <= 0
+            || self.items.contains(ItemFlags::INVISIBILITY)
+            || self.intermission.is_some()
+        {
+            return None;
+        }
+
+        Some(ViewModel {
+            model_id: self.viewmodel_id(),
+            frame_id: self.stats[ClientStat::WeaponFrame as usize].max(0) as usize,
+            origin: self.view.viewmodel_origin(),
+            angles: self.view.viewmodel_angle(),
+        })
+    }
+
     pub fn iter_visible_entities(&self) -> impl Iterator<Item = &ClientEntity> {
         self.visible_entity_ids
             .iter()
             .map(move |i| &self.entities[*i])
             .chain(self.temp_entities.iter())
             .chain(self.static_entities.iter())
+            .chain(self.ghost.iter())
     }
 
     pub fn iter_particles(&self) -> impl Iterator<Item = &Particle> {
         self.particles.iter()
     }
 
+    pub fn iter_decals(&self) -> impl Iterator<Item = &Decal> {
+        self.decals.iter()
+    }
+
     pub fn iter_lights(&self) -> impl Iterator<Item = &Light> {
         self.lights.iter()
     }
@@ -1233,39 +1801,37 @@ impl ClientState {
 
     pub fn demo_camera(&self, aspect: f32, fov: Deg<f32>) -> Camera {
         let fov_y = math::fov_x_to_fov_y(fov, aspect).unwrap();
-        let angles = self
-            .entities
+        Camera::new(
+            self.view.final_origin(),
+            self.demo_view_angles(),
+            cgmath::perspective(fov_y, aspect, 4.0, 4096.0),
+        )
+    }
+
+    /// A free camera starting from where the demo is currently seen.
+    pub fn free_camera(&self) -> FreeCamera {
+        FreeCamera::new(self.view.final_origin(), self.demo_view_angles())
+    }
+
+    /// Demos are seen from the angles of the view entity, which follow the recorded view angles.
+    fn demo_view_angles(&self) -> Angles {
+        self.entities
             .get(self.view.entity_id())
             .map(|e| Angles {
                 pitch: e.angles.x,
                 roll: e.angles.z,
                 yaw: e.angles.y,
             })
-            .unwrap_or_default();
-        Camera::new(
-            self.view.final_origin(),
-            angles,
-            cgmath::perspective(fov_y, aspect, 4.0, 4096.0),
-        )
+            .unwrap_or_default()
     }
 
-    pub fn lightstyle_values(&self) -> ArrayVec<f32, MAX_LIGHT_STYLES> {
+    /// The brightness of each light style now, blended between its steps as `r_lerplightstyles`
+    /// `lerp` says.
+    pub fn lightstyle_values(&self, lerp: u8) -> ArrayVec<f32, MAX_LIGHT_STYLES> {
         let float_time = engine::duration_to_f32(self.time);
-        // 'z' - 'a' = 25, so divide by 12.5 to get range [0, 2]
-        let factor = ((b'z' - b'a') as f32 / 2.).recip();
         self.light_styles
             .iter()
-            .map(move |ls| {
-                let frame = if ls.len() == 0 {
-                    None
-                } else {
-                    Some((float_time * 10.0) as usize % ls.len())
-                };
-
-                frame
-                    .map(|f| (ls.as_bytes()[f] - b'a') as f32 * factor)
-                    .unwrap_or(1.)
-            })
+            .map(|ls| lightstyle_value(ls, float_time, lerp))
             .collect()
     }
 
@@ -1297,6 +1863,16 @@ impl ClientState {
         self.face_anim_time
     }
 
+    /// The players on the server, by slot.
+    pub fn players(&self) -> &[Option<PlayerInfo>] {
+        &self.player_info
+    }
+
+    /// Whether the server is running a deathmatch.
+    pub fn deathmatch(&self) -> bool {
+        self.deathmatch
+    }
+
     pub fn color_shift(&self) -> [f32; 4] {
         self.color_shifts.iter().fold([0.0; 4], |accum, elem| {
             let elem_a = elem.percent as f32 / 255.0 / 2.0;
@@ -1317,6 +1893,25 @@ impl ClientState {
         })
     }
 
+    /// The color and opacity of each screen color shift, in the order they're blended. A shift's
+    /// `percent` is out of 255, and is scaled by `cshift_percent` (`gl_cshiftpercent`).
+    pub fn color_shift_blends(&self, cshift_percent: f32) -> [[f32; 4]; 4] {
+        self.color_shifts.map(
+            |ColorShift {
+                 dest_color: [r, g, b],
+                 percent,
+             }| {
+                let alpha = percent as f32 / 255.0 * cshift_percent / 100.0;
+                [
+                    r as f32 / 255.0,
+                    g as f32 / 255.0,
+                    b as f32 / 255.0,
+                    alpha.clamp(0.0, 1.0),
+                ]
+            },
+        )
+    }
+
     pub fn check_entity_id(&self, id: usize) -> Result<(), ClientError> {
         match id {
             0 => Err(ClientError::NullEntity),
@@ -1337,3 +1932,113 @@ impl ClientState {
 }
 
 pub mod systems {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: f32, b: f32) {
+        assert!((a - b).abs() < 0.001, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_precache_counts_every_resource() {
+        let mut progress = ConnectionProgress::default();
+        let precache = Precache::new(
+            Protocol::FITZQUAKE,
+            8,
+            GameType::Deathmatch,
+            vec!["maps/e1m1.bsp".to_owned(), "progs/player.mdl".to_owned()],
+            vec!["weapons/r_exp3.wav"],
+            &mut progress,
+        );
+        assert!(!precache.is_finished());
+        // the null sound is loaded along with the precached ones
+        assert!(progress.lines().iter().any(|line| line.ends_with(" 0/4")));
+
+        // messages that arrive while the level loads are read with its protocol
+        let state = precache.placeholder();
+        assert_eq!(state.protocol, Protocol::FITZQUAKE);
+        assert_eq!(state.max_players, 8);
+    }
+
+    #[test]
+    fn test_prediction_matches_server() {
+        use crate::server::{
+            progs::EntityId,
+            world::{PlayerMoveWorld, World},
+        };
+
+        // a floor with its top at z = 0
+        let min = Vector3::new(-4096.0, -4096.0, -64.0);
+        let max = Vector3::new(4096.0, 4096.0, 0.0);
+        let mut server = World::from_box(min, max);
+        let bmodel = bsp::box_model(min, max);
+        let mut client = HullWorld::new(&bmodel).unwrap();
+
+        let run = |forward, side, yaw| MoveCmd {
+            angles: Angles {
+                pitch: Deg(0.0),
+                roll: Deg(0.0),
+                yaw: Deg(yaw),
+            },
+            forward,
+            side,
+            up: 0.0,
+        };
+        // the player drops onto the floor, runs, turns while strafing and then stops
+        let cmds = [
+            run(0.0, 0.0, 0.0),
+            run(320.0, 0.0, 0.0),
+            run(200.0, -350.0, 45.0),
+            run(0.0, 0.0, 45.0),
+        ];
+
+        let vars = pmove::MoveVars::default();
+        let frame_time = 1.0 / 72.0;
+        let mut server_state = PlayerState::new(Vector3::new(0.0, 0.0, 100.0));
+        let mut client_state = server_state;
+        for cmd in cmds {
+            for _ in 0..36 {
+                pmove::player_move(
+                    &mut server_state,
+                    &cmd,
+                    &vars,
+                    frame_time,
+                    &mut PlayerMoveWorld {
+                        world: &mut server,
+                        e_id: EntityId(1),
+                    },
+                )
+                .unwrap();
+                pmove::player_move(&mut client_state, &cmd, &vars, frame_time, &mut client)
+                    .unwrap();
+                assert_eq!(client_state, server_state);
+            }
+        }
+
+        assert!(server_state.on_ground);
+        assert!(server_state.origin.x > 100.0);
+    }
+
+    #[test]
+    fn test_lightstyle_value() {
+        // 'a' is dark, 'm' is normal and 'z' is double brightness
+        assert_near(lightstyle_value("m", 0.0, 0), 0.96);
+        assert_near(lightstyle_value("z", 0.0, 2), 2.0);
+        assert_near(lightstyle_value("", 0.0, 2), 1.0);
+
+        // halfway between 'a' and 'c'
+        let time = 0.05;
+        assert_near(lightstyle_value("ac", time, 0), 0.0);
+        assert_near(lightstyle_value("ac", time, 1), 0.08);
+        assert_near(lightstyle_value("ac", time, 2), 0.08);
+
+        // halfway between 'a' and 'm', which is too sudden to blend unless forced to
+        assert_near(lightstyle_value("am", time, 1), 0.0);
+        assert_near(lightstyle_value("am", time, 2), 0.48);
+
+        // the last step blends back into the first
+        assert_near(lightstyle_value("ac", 0.15, 2), 0.08);
+    }
+}
@@ -6,11 +6,17 @@ use crate::common::{
     math::{self, Angles},
 };
 
-use super::IntermissionKind;
+use super::{render::Camera, IntermissionKind, MoveVars};
 use cgmath::{Angle as _, Deg, InnerSpace as _, Vector3, Zero as _};
 use chrono::Duration;
 use serde::Deserialize;
 
+/// How far the weapon moves forward for each unit that the view bobs up.
+const VIEWMODEL_BOB_FORWARD: f32 = 0.4;
+
+/// The color of fog that is given without one.
+const DEFAULT_FOG_COLOR: [f32; 3] = [0.3, 0.3, 0.3];
+
 #[derive(Clone)]
 pub struct View {
     // entity "holding" the camera
@@ -39,6 +45,9 @@ pub struct View {
 
     // final origin accounting for view bob
     final_origin: Vector3<f32>,
+
+    // origin of the weapon held in front of the view, which bobs forward as well as up
+    viewmodel_origin: Vector3<f32>,
 }
 
 impl View {
@@ -53,6 +62,7 @@ impl View {
             punch_angles: Angles::zero(),
             final_angles: Angles::zero(),
             final_origin: Vector3::zero(),
+            viewmodel_origin: Vector3::zero(),
         }
     }
 
@@ -214,20 +224,126 @@ impl View {
         // offset the view by 1/32 unit to keep it from intersecting liquid planes
         let plane_offset = Vector3::new(1.0 / 32.0, 1.0 / 32.0, 1.0 / 32.0);
         let height_offset = Vector3::new(0.0, 0.0, self.view_height);
-        let bob_offset = Vector3::new(0.0, 0.0, bob(time, velocity, bob_vars));
-        self.final_origin = origin + plane_offset + height_offset + bob_offset;
+        let bob = bob(time, velocity, bob_vars);
+        self.final_origin = origin + plane_offset + height_offset + Vector3::unit_z() * bob;
+
+        let (forward, _) = forward_right(self.final_angles);
+        self.viewmodel_origin = self.final_origin + forward * bob * VIEWMODEL_BOB_FORWARD;
     }
 
     pub fn final_origin(&self) -> Vector3<f32> {
         self.final_origin
     }
 
+    /// Move the camera away from the view entity, e.g. for the chase camera.
+    pub fn set_final_origin(&mut self, origin: Vector3<f32>) {
+        self.final_origin = origin;
+    }
+
+    pub fn viewmodel_origin(&self) -> Vector3<f32> {
+        self.viewmodel_origin
+    }
+
     pub fn viewmodel_angle(&self) -> Angles {
         // TODO
         self.final_angles()
     }
 }
 
+/// The weapon drawn in front of the camera.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ViewModel {
+    /// The index of the weapon's model, not counting the world model.
+    pub model_id: usize,
+    pub frame_id: usize,
+    pub origin: Vector3<f32>,
+    pub angles: Angles,
+}
+
+/// A camera that flies freely through the level, away from the view entity.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FreeCamera {
+    pub origin: Vector3<f32>,
+    pub angles: Angles,
+}
+
+impl FreeCamera {
+    pub fn new(origin: Vector3<f32>, angles: Angles) -> FreeCamera {
+        FreeCamera {
+            origin,
+            // the camera flies level, whatever the view was rolled by
+            angles: Angles {
+                roll: Deg(0.0),
+                ..angles
+            },
+        }
+    }
+
+    /// Turn and move the camera with the same bindings and speeds as the player.
+    pub fn handle_input(&mut self, frame_time: Duration, registry: &Registry, move_vars: MoveVars) {
+        let frame_time = duration_to_f32(frame_time);
+        let (turn_time, move_time) = if registry.is_pressed("speed") {
+            (
+                frame_time * move_vars.cl_anglespeedkey,
+                frame_time * move_vars.cl_movespeedkey,
+            )
+        } else {
+            (frame_time, frame_time)
+        };
+        let key = |name: &str| registry.is_pressed(name) as i32 as f32;
+
+        let strafe = registry.is_pressed("strafe");
+        if !strafe {
+            self.angles.yaw +=
+                Deg(turn_time * move_vars.cl_yawspeed * (key("left") - key("right")));
+            self.angles.yaw = self.angles.yaw.normalize();
+        }
+        self.angles.pitch +=
+            Deg(turn_time * move_vars.cl_pitchspeed * (key("lookdown") - key("lookup")));
+        self.angles.pitch = math::clamp_deg(self.angles.pitch, Deg(-89.0), Deg(89.0));
+
+        let mut side = key("moveright") - key("moveleft");
+        if strafe {
+            side += key("right") - key("left");
+        }
+
+        let forward =
+            move_vars.cl_forwardspeed * key("forward") - move_vars.cl_backspeed * key("back");
+        let side = move_vars.cl_sidespeed * side.clamp(-1.0, 1.0);
+        let up = move_vars.cl_upspeed * (key("moveup") - key("movedown"));
+
+        self.fly(move_time, forward, side, up);
+    }
+
+    /// Move the camera for `time` seconds at `forward` units per second along the direction it
+    /// faces, `side` to its right and `up` straight up.
+    pub fn fly(&mut self, time: f32, forward: f32, side: f32, up: f32) {
+        let (dir_forward, dir_right) = forward_right(self.angles);
+        self.origin += (dir_forward * forward + dir_right * side + Vector3::unit_z() * up) * time;
+    }
+
+    pub fn camera(&self, aspect: f32, fov: Deg<f32>) -> Camera {
+        let fov_y = math::fov_x_to_fov_y(fov, aspect).unwrap();
+        Camera::new(
+            self.origin,
+            self.angles,
+            cgmath::perspective(fov_y, aspect, 4.0, 4096.0),
+        )
+    }
+}
+
+/// The direction that `angles` face and the direction to their right, ignoring roll.
+pub fn forward_right(angles: Angles) -> (Vector3<f32>, Vector3<f32>) {
+    let (sin_yaw, cos_yaw) = angles.yaw.sin_cos();
+    let (sin_pitch, cos_pitch) = angles.pitch.sin_cos();
+
+    // positive pitch looks down
+    let forward = Vector3::new(cos_pitch * cos_yaw, cos_pitch * sin_yaw, -sin_pitch);
+    let right = Vector3::new(sin_yaw, -cos_yaw, 0.0);
+
+    (forward, right)
+}
+
 #[derive(Clone, Copy, Debug, Deserialize)]
 pub struct MouseVars {
     #[serde(rename(deserialize = "m_pitch"))]
@@ -271,6 +387,23 @@ pub fn bob(time: Duration, velocity: Vector3<f32>, vars: BobVars) -> f32 {
     bob.max(-7.0).min(4.0)
 }
 
+#[derive(Deserialize, Clone, Copy, Debug, Default)]
+pub struct ChaseVars {
+    pub chase_active: f32,
+    pub chase_back: f32,
+    pub chase_up: f32,
+    pub chase_right: f32,
+}
+
+/// Where the chase camera goes for a view at `origin` facing `angles`, if nothing is in the way.
+/// It stays at the same height above the view whichever way the view is pitched.
+pub fn chase_target(origin: Vector3<f32>, angles: Angles, vars: ChaseVars) -> Vector3<f32> {
+    let (forward, right) = forward_right(angles);
+    let mut target = origin - forward * vars.chase_back + right * vars.chase_right;
+    target.z = origin.z + vars.chase_up;
+    target
+}
+
 #[derive(Deserialize, Clone, Copy, Debug)]
 pub struct RollVars {
     pub cl_rollangle: f32,
@@ -311,3 +444,144 @@ pub fn idle(time: Duration, vars: IdleVars) -> Angles {
 
     Angles { pitch, roll, yaw }
 }
+
+/// Distance fog, set by the `fog` key of a map's worldspawn or the `fog` command.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fog {
+    /// How quickly the fog thickens with distance. There is no fog when this is 0.
+    pub density: f32,
+    pub color: [f32; 3],
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Fog {
+            density: 0.,
+            color: DEFAULT_FOG_COLOR,
+        }
+    }
+}
+
+impl Fog {
+    /// Reads fog given as `<density> [<r> <g> <b>]`, as in the `fog` key of a worldspawn.
+    pub fn parse(s: &str) -> Option<Fog> {
+        let values = s
+            .split_whitespace()
+            .map(|v| v.parse().ok())
+            .collect::<Option<Vec<f32>>>()?;
+
+        let mut fog = Fog::default();
+        match values[..] {
+            [_] | [_, _, _, _] => fog.set(&values).then_some(fog),
+            _ => None,
+        }
+    }
+
+    /// Changes the fog to `<density>`, `<r> <g> <b>` or `<density> <r> <g> <b>`, keeping whatever
+    /// isn't given. Returns `false` and leaves the fog as it is for any other number of values.
+    pub fn set(&mut self, values: &[f32]) -> bool {
+        let (density, color) = match *values {
+            [density] => (density, self.color),
+            [r, g, b] => (self.density, [r, g, b]),
+            [density, r, g, b] => (density, [r, g, b]),
+            _ => return false,
+        };
+
+        self.density = density.max(0.);
+        self.color = color.map(|c| c.clamp(0., 1.));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 0.001, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_fog() {
+        assert_eq!(
+            Fog::parse("0.05 0.1 0.2 0.3"),
+            Some(Fog {
+                density: 0.05,
+                color: [0.1, 0.2, 0.3],
+            })
+        );
+        assert_eq!(
+            Fog::parse("0.5"),
+            Some(Fog {
+                density: 0.5,
+                color: DEFAULT_FOG_COLOR,
+            })
+        );
+        assert_eq!(Fog::parse("0.1 0.2 0.3"), None);
+        assert_eq!(Fog::parse("thick"), None);
+
+        let mut fog = Fog::parse("0.5").unwrap();
+        assert!(fog.set(&[1., 2., -1.]));
+        assert_eq!(fog.density, 0.5);
+        assert_eq!(fog.color, [1., 1., 0.]);
+        assert!(!fog.set(&[]));
+        assert!(fog.set(&[-1.]));
+        assert_eq!(fog.density, 0.);
+    }
+
+    #[test]
+    fn test_free_camera_fly() {
+        let mut cam = FreeCamera::new(
+            Vector3::zero(),
+            Angles {
+                pitch: Deg(0.0),
+                roll: Deg(30.0),
+                yaw: Deg(90.0),
+            },
+        );
+        assert_eq!(cam.angles.roll, Deg(0.0));
+
+        // facing along +y, so right is +x
+        cam.fly(0.5, 200.0, 100.0, 0.0);
+        assert_near(cam.origin, Vector3::new(50.0, 100.0, 0.0));
+
+        cam.angles = Angles {
+            pitch: Deg(90.0),
+            roll: Deg(0.0),
+            yaw: Deg(0.0),
+        };
+        cam.fly(1.0, 10.0, 0.0, 5.0);
+        assert_near(cam.origin, Vector3::new(50.0, 100.0, -5.0));
+    }
+
+    #[test]
+    fn test_chase_target() {
+        let vars = ChaseVars {
+            chase_active: 1.0,
+            chase_back: 100.0,
+            chase_up: 16.0,
+            chase_right: 10.0,
+        };
+        let origin = Vector3::new(0.0, 0.0, 50.0);
+
+        let level = Angles {
+            pitch: Deg(0.0),
+            roll: Deg(0.0),
+            yaw: Deg(0.0),
+        };
+        assert_near(
+            chase_target(origin, level, vars),
+            Vector3::new(-100.0, -10.0, 66.0),
+        );
+
+        // looking down pulls the camera in, but not down
+        let down = Angles {
+            pitch: Deg(60.0),
+            ..level
+        };
+        assert_near(
+            chase_target(origin, down, vars),
+            Vector3::new(-50.0, -10.0, 66.0),
+        );
+    }
+}
@@ -0,0 +1,416 @@
+//! Damage numbers and pickup labels floating in the world, for players who want a more modern
+//! HUD. Enabled with `cl_worldtext`.
+//!
+//! Both are driven by the [`GameplayEvent`]s read from server messages. The damage the player
+//! takes rises from wherever it came from, and the damage that anything else takes rises from the
+//! blood it sprays. What the player picks up is found by comparing their items and stats between
+//! updates, and rises from just in front of them. The text is drawn with the console font and
+//! always faces the camera.
+
+use bevy::{prelude::*, render::extract_resource::ExtractResource};
+use cgmath::{Angle as _, Deg, InnerSpace as _, Vector3};
+use chrono::Duration;
+
+use crate::{
+    client::{Connection, GameplayEvent},
+    common::{
+        console::Registry,
+        net::{ItemFlags, PlayerData},
+    },
+};
+
+/// How long text stays in the world.
+const TEXT_MILLIS: i64 = 1500;
+
+/// How far text rises over its lifetime, in world units.
+const RISE: f32 = 32.;
+
+/// How far in front of the player pickup labels appear.
+const PICKUP_DISTANCE: f32 = 64.;
+
+/// The oldest text is dropped beyond this many, so that a firefight doesn't fill the screen.
+const MAX_TEXTS: usize = 16;
+
+/// Hits closer together than this in the same frame are shown as one number, so that a shotgun
+/// blast shows its total rather than a number for each pellet.
+const HIT_MERGE_DISTANCE: f32 = 48.;
+
+/// The items worth a label when picked up. Ammo, armor and health are labelled with how much was
+/// gained instead.
+const ITEM_NAMES: [(ItemFlags, &str); 16] = [
+    (ItemFlags::SHOTGUN, "Shotgun"),
+    (ItemFlags::SUPER_SHOTGUN, "Super Shotgun"),
+    (ItemFlags::NAILGUN, "Nailgun"),
+    (ItemFlags::SUPER_NAILGUN, "Super Nailgun"),
+    (ItemFlags::GRENADE_LAUNCHER, "Grenade Launcher"),
+    (ItemFlags::ROCKET_LAUNCHER, "Rocket Launcher"),
+    (ItemFlags::LIGHTNING, "Thunderbolt"),
+    (ItemFlags::KEY_1, "Silver Key"),
+    (ItemFlags::KEY_2, "Gold Key"),
+    (ItemFlags::INVISIBILITY, "Ring of Shadows"),
+    (ItemFlags::INVULNERABILITY, "Pentagram of Protection"),
+    (ItemFlags::SUIT, "Biosuit"),
+    (ItemFlags::QUAD, "Quad Damage"),
+    (ItemFlags::SIGIL_1, "Rune"),
+    (ItemFlags::SIGIL_2, "Rune"),
+    (ItemFlags::SIGIL_3, "Rune"),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WorldTextKind {
+    /// Damage taken by the player.
+    Damage,
+    /// Damage taken by anything else.
+    Hit,
+    Pickup,
+}
+
+/// A piece of text in the world, as of the last update.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorldLabel {
+    pub kind: WorldTextKind,
+    pub text: String,
+    pub origin: Vector3<f32>,
+}
+
+#[derive(Clone, Debug)]
+struct FloatingText {
+    label: WorldLabel,
+    time: Duration,
+}
+
+#[derive(Resource, Clone, Debug, Default)]
+pub struct WorldText {
+    texts: Vec<FloatingText>,
+    labels: Vec<WorldLabel>,
+}
+
+impl ExtractResource for WorldText {
+    type Source = Self;
+
+    fn extract_resource(source: &Self::Source) -> Self {
+        source.clone()
+    }
+}
+
+impl WorldText {
+    /// The text to draw, where it has risen to as of the last update.
+    pub fn labels(&self) -> &[WorldLabel] {
+        &self.labels
+    }
+
+    fn add(&mut self, kind: WorldTextKind, text: String, origin: Vector3<f32>, time: Duration) {
+        if self.texts.len() >= MAX_TEXTS {
+            self.texts.remove(0);
+        }
+
+        self.texts.push(FloatingText {
+            label: WorldLabel { kind, text, origin },
+            time,
+        });
+    }
+
+    /// Adds the text for a gameplay event at client time `time`.
+    fn handle(&mut self, event: &GameplayEvent, time: Duration) {
+        match event {
+            GameplayEvent::Damage {
+                armor,
+                blood,
+                source,
+            } => {
+                let total = *armor as u32 + *blood as u32;
+                if total > 0 {
+                    self.add(WorldTextKind::Damage, format!("-{}", total), *source, time);
+                }
+            }
+
+            GameplayEvent::Hit { damage, origin } => self.add_hit(*damage, *origin, time),
+
+            GameplayEvent::Pickup { label, origin } => {
+                self.add(WorldTextKind::Pickup, label.clone(), *origin, time)
+            }
+        }
+    }
+
+    /// Adds `damage` to a hit nearby in the same frame, or shows it on its own.
+    fn add_hit(&mut self, damage: u32, origin: Vector3<f32>, time: Duration) {
+        let nearby = self.texts.iter_mut().find(|text| {
+            text.time == time
+                && text.label.kind == WorldTextKind::Hit
+                && (text.label.origin - origin).magnitude() < HIT_MERGE_DISTANCE
+        });
+
+        match nearby {
+            Some(text) => {
+                let total = text.label.text.parse::<u32>().unwrap_or(0) + damage;
+                text.label.text = total.to_string();
+            }
+            None => self.add(WorldTextKind::Hit, damage.to_string(), origin, time),
+        }
+    }
+
+    /// Forgets text which has expired and moves the rest up.
+    fn update(&mut self, time: Duration) {
+        let max_age = Duration::try_milliseconds(TEXT_MILLIS).unwrap();
+
+        // the clock restarts on a level change
+        self.texts
+            .retain(|text| text.time <= time && time - text.time < max_age);
+
+        self.labels = self
+            .texts
+            .iter()
+            .map(|text| {
+                let age = (time - text.time).num_milliseconds() as f32 / TEXT_MILLIS as f32;
+                WorldLabel {
+                    origin: text.label.origin + Vector3::unit_z() * (age * RISE),
+                    ..text.label.clone()
+                }
+            })
+            .collect();
+    }
+
+    fn clear(&mut self) {
+        self.texts.clear();
+        self.labels.clear();
+    }
+}
+
+/// The items and stats that picking something up can change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Inventory {
+    items: ItemFlags,
+    health: i16,
+    armor: u16,
+    /// Shells, nails, rockets and cells.
+    ammo: [u16; 4],
+}
+
+impl Inventory {
+    pub fn new(data: &PlayerData) -> Inventory {
+        Inventory {
+            items: data.items,
+            health: data.health,
+            armor: data.armor.unwrap_or_default(),
+            ammo: [
+                data.ammo_shells,
+                data.ammo_nails,
+                data.ammo_rockets,
+                data.ammo_cells,
+            ],
+        }
+    }
+}
+
+/// Labels for what the player picked up between two updates of their inventory. Nothing is
+/// labelled when they come back to life, as respawning hands them a fresh inventory.
+pub fn pickup_labels(before: &Inventory, after: &Inventory) -> Vec<String> {
+    if before.health <= 0 {
+        return Vec::new();
+    }
+
+    let new_items = after.items - before.items;
+    let items = ITEM_NAMES
+        .iter()
+        .filter(|(item, _)| new_items.contains(*item))
+        .map(|(_, name)| name.to_string());
+
+    let gains = [
+        ("health", before.health as i32, after.health as i32),
+        ("armor", before.armor as i32, after.armor as i32),
+    ]
+    .into_iter()
+    .chain(
+        ["shells", "nails", "rockets", "cells"]
+            .into_iter()
+            .zip(before.ammo.iter().zip(after.ammo.iter()))
+            .map(|(name, (&b, &a))| (name, b as i32, a as i32)),
+    )
+    .filter(|(_, b, a)| a > b)
+    .map(|(name, b, a)| format!("+{} {}", a - b, name));
+
+    items.chain(gains).collect()
+}
+
+/// Where a pickup label for a player at `view_origin` looking towards `yaw` appears.
+pub fn pickup_origin(view_origin: Vector3<f32>, yaw: Deg<f32>) -> Vector3<f32> {
+    view_origin + Vector3::new(yaw.cos(), yaw.sin(), 0.) * PICKUP_DISTANCE
+}
+
+pub fn update_world_text(
+    registry: Res<Registry>,
+    conn: Option<Res<Connection>>,
+    mut events: EventReader<GameplayEvent>,
+    mut world_text: ResMut<WorldText>,
+) {
+    let enabled = registry.read_cvar::<u8>("cl_worldtext").unwrap_or(0) != 0;
+    let Some(conn) = conn.filter(|_| enabled) else {
+        events.clear();
+        world_text.clear();
+        return;
+    };
+
+    let time = conn.state.time();
+    for event in events.read() {
+        world_text.handle(event, time);
+    }
+
+    world_text.update(time);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: i64) -> Duration {
+        Duration::try_milliseconds(millis).unwrap()
+    }
+
+    fn inventory(items: ItemFlags, health: i16, shells: u16) -> Inventory {
+        Inventory {
+            items,
+            health,
+            armor: 0,
+            ammo: [shells, 0, 0, 0],
+        }
+    }
+
+    #[test]
+    fn test_pickup_labels() {
+        let start = inventory(ItemFlags::SHOTGUN | ItemFlags::AXE, 100, 25);
+
+        let rocket_launcher = inventory(
+            start.items | ItemFlags::ROCKET_LAUNCHER | ItemFlags::ROCKETS,
+            100,
+            25,
+        );
+        assert_eq!(
+            pickup_labels(&start, &rocket_launcher),
+            vec!["Rocket Launcher".to_owned()]
+        );
+
+        let shells_and_health = inventory(start.items, 115, 45);
+        assert_eq!(
+            pickup_labels(&start, &shells_and_health),
+            vec!["+15 health".to_owned(), "+20 shells".to_owned()]
+        );
+
+        // spending ammo and taking damage aren't pickups
+        assert!(pickup_labels(&start, &inventory(start.items, 90, 24)).is_empty());
+
+        // nor is respawning
+        let dead = inventory(ItemFlags::empty(), -5, 0);
+        assert!(pickup_labels(&dead, &start).is_empty());
+    }
+
+    #[test]
+    fn test_hits_merge() {
+        let mut world_text = WorldText::default();
+        let target = Vector3::new(100., 0., 0.);
+
+        // a shotgun blast's pellets in one frame
+        for i in 0..3 {
+            world_text.handle(
+                &GameplayEvent::Hit {
+                    damage: 4,
+                    origin: target + Vector3::unit_y() * i as f32,
+                },
+                ms(0),
+            );
+        }
+        // another target
+        world_text.handle(
+            &GameplayEvent::Hit {
+                damage: 4,
+                origin: target + Vector3::unit_y() * 200.,
+            },
+            ms(0),
+        );
+        // the next frame
+        world_text.handle(
+            &GameplayEvent::Hit {
+                damage: 4,
+                origin: target,
+            },
+            ms(100),
+        );
+
+        world_text.update(ms(100));
+        let texts: Vec<_> = world_text
+            .labels()
+            .iter()
+            .map(|label| (label.kind, label.text.as_str()))
+            .collect();
+        assert_eq!(
+            texts,
+            vec![
+                (WorldTextKind::Hit, "12"),
+                (WorldTextKind::Hit, "4"),
+                (WorldTextKind::Hit, "4")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_world_text_rises_and_expires() {
+        let mut world_text = WorldText::default();
+        let source = Vector3::new(100., 0., 0.);
+
+        world_text.handle(
+            &GameplayEvent::Damage {
+                armor: 5,
+                blood: 10,
+                source,
+            },
+            ms(0),
+        );
+        // nothing was taken, so there's nothing to show
+        world_text.handle(
+            &GameplayEvent::Damage {
+                armor: 0,
+                blood: 0,
+                source,
+            },
+            ms(0),
+        );
+        world_text.handle(
+            &GameplayEvent::Pickup {
+                label: "Quad Damage".into(),
+                origin: source,
+            },
+            ms(750),
+        );
+
+        world_text.update(ms(750));
+        assert_eq!(
+            world_text.labels(),
+            &[
+                WorldLabel {
+                    kind: WorldTextKind::Damage,
+                    text: "-15".into(),
+                    origin: source + Vector3::unit_z() * (RISE / 2.),
+                },
+                WorldLabel {
+                    kind: WorldTextKind::Pickup,
+                    text: "Quad Damage".into(),
+                    origin: source,
+                },
+            ]
+        );
+
+        world_text.update(ms(1500));
+        assert_eq!(world_text.labels().len(), 1);
+
+        // a new level
+        world_text.update(ms(10));
+        assert!(world_text.labels().is_empty());
+    }
+
+    #[test]
+    fn test_pickup_origin() {
+        let origin = pickup_origin(Vector3::new(0., 0., 22.), Deg(90.));
+        assert!(origin.x.abs() < 1e-3);
+        assert!((origin.y - PICKUP_DISTANCE).abs() < 1e-3);
+        assert_eq!(origin.z, 22.);
+    }
+}
@@ -33,7 +33,7 @@ use crate::common::{
     util::read_f32_3,
 };
 
-use super::{BspTextureFrame, BspTextureKind};
+use super::{submodel_name, BspTextureFrame, BspTextureKind};
 use bevy::prelude::*;
 use byteorder::{LittleEndian, ReadBytesExt};
 use cgmath::{InnerSpace, Vector3};
@@ -371,9 +371,47 @@ where
     })
 }
 
+/// The magic number at the start of a `.lit` file.
+const LIT_MAGIC: [u8; 4] = *b"QLIT";
+const LIT_VERSION: i32 = 1;
+
 /// Load a BSP file, returning the models it contains and a `String` describing the entities
 /// it contains.
 pub fn load<R>(data: R) -> Result<(Vec<Model>, String), failure::Error>
+where
+    R: Read + Seek,
+{
+    load_with_lit(data, None)
+}
+
+/// Read the colored lightmaps of a `.lit` file, which has an RGB triplet for each byte of the
+/// lightmaps in the BSP file that it goes with.
+pub fn read_lit<R>(mut data: R) -> Result<Box<[u8]>, failure::Error>
+where
+    R: Read,
+{
+    let mut magic = [0; 4];
+    data.read_exact(&mut magic)?;
+    ensure!(magic == LIT_MAGIC, "Not a .lit file");
+    let version = data.read_i32::<LittleEndian>()?;
+    ensure!(
+        version == LIT_VERSION,
+        "Unsupported .lit version {}",
+        version
+    );
+
+    let mut rgb = Vec::new();
+    data.read_to_end(&mut rgb)?;
+
+    Ok(rgb.into_boxed_slice())
+}
+
+/// Load a BSP file like [`load`], lighting it with the colored lightmaps `lit` from
+/// [`read_lit`] if there are any.
+pub fn load_with_lit<R>(
+    data: R,
+    lit: Option<Box<[u8]>>,
+) -> Result<(Vec<Model>, String), failure::Error>
 where
     R: Read + Seek,
 {
@@ -1002,6 +1040,15 @@ where
         maxs: Vector3::new(0.0, 0.0, 0.0),
     };
 
+    // colored lightmaps of a different size were made for some other version of the map
+    let colored_lightmaps = lit.filter(|rgb| {
+        let matches = rgb.len() == lightmaps.len() * 3;
+        if !matches {
+            warn!("Ignoring colored lightmaps that don't match the map");
+        }
+        matches
+    });
+
     let bsp_data = Arc::new(BspData {
         planes: planes_rc.clone(),
         textures: textures.into_boxed_slice(),
@@ -1011,6 +1058,7 @@ where
         texinfo: texinfo.into_boxed_slice(),
         faces: faces.into_boxed_slice(),
         lightmaps: lightmaps.into_boxed_slice(),
+        colored_lightmaps,
         hulls: [hull_0, hull_1, hull_2],
         leaves: leaves.into_boxed_slice(),
         facelist: facelist.into_boxed_slice(),
@@ -1092,7 +1140,7 @@ where
     let models = brush_models
         .into_iter()
         .enumerate()
-        .map(|(i, bmodel)| Model::from_brush_model(format!("*{}", i), bmodel))
+        .map(|(i, bmodel)| Model::from_brush_model(submodel_name(i), bmodel))
         .collect();
 
     Ok((models, ent_string))
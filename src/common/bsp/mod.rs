@@ -130,7 +130,7 @@ use cgmath::Vector3;
 use chrono::Duration;
 use num_derive::FromPrimitive;
 
-pub use self::load::{load, BspFileError};
+pub use self::load::{load, load_with_lit, read_lit, BspFileError};
 
 // this is 4 in the original source, but the 4th hull is never used.
 const MAX_HULLS: usize = 3;
@@ -243,6 +243,119 @@ impl BspTexture {
     pub fn kind(&self) -> &BspTextureKind {
         &self.kind
     }
+
+    /// Returns how surfaces with this texture are drawn and collided with.
+    pub fn surface(&self) -> SurfaceKind {
+        SurfaceKind::from_texture_name(&self.name)
+    }
+}
+
+/// A kind of liquid, named by the texture on its surface.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LiquidKind {
+    Water,
+    Slime,
+    Lava,
+    /// The swirling surface of a teleporter. It is drawn like a liquid but the brush is usually a
+    /// trigger, so it has no contents of its own.
+    Teleport,
+}
+
+impl LiquidKind {
+    /// Returns the kind of liquid that fills a leaf with the given contents, if any.
+    ///
+    /// Currents count as water.
+    pub fn from_contents(contents: BspLeafContents) -> Option<LiquidKind> {
+        use BspLeafContents::*;
+
+        match contents {
+            Water | Current0 | Current90 | Current180 | Current270 | CurrentUp | CurrentDown => {
+                Some(LiquidKind::Water)
+            }
+            Slime => Some(LiquidKind::Slime),
+            Lava => Some(LiquidKind::Lava),
+            _ => None,
+        }
+    }
+}
+
+/// How a surface is drawn, which Quake decides from the texture name.
+///
+/// The map compiler uses the same names to give brushes their contents, but those end up in the
+/// leaves of the BSP tree, which is what collision goes by.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SurfaceKind {
+    /// An ordinary lightmapped surface.
+    Normal,
+    /// The two-layer scrolling sky. Textures are named `sky*`.
+    Sky,
+    /// The surface of a liquid, drawn with warped texture coordinates and no lightmap. Textures
+    /// are named `*<name>`.
+    Liquid(LiquidKind),
+    /// A surface that is never drawn, such as the outside of a trigger brush.
+    NoDraw,
+    /// The surface of a brush that only blocks movement. It is removed from the drawing hull by the
+    /// map compiler, but some compilers leave its faces in.
+    Clip,
+}
+
+impl SurfaceKind {
+    pub fn from_texture_name<S>(name: S) -> SurfaceKind
+    where
+        S: AsRef<str>,
+    {
+        let name = name.as_ref().to_ascii_lowercase();
+
+        if let Some(liquid) = name.strip_prefix('*') {
+            let kind = if liquid.starts_with("lava") {
+                LiquidKind::Lava
+            } else if liquid.starts_with("slime") {
+                LiquidKind::Slime
+            } else if liquid.starts_with("tele") {
+                LiquidKind::Teleport
+            } else {
+                LiquidKind::Water
+            };
+
+            return SurfaceKind::Liquid(kind);
+        }
+
+        if name.starts_with("sky") {
+            SurfaceKind::Sky
+        } else if name == "clip" {
+            SurfaceKind::Clip
+        } else if matches!(&*name, "trigger" | "nodraw" | "skip") {
+            SurfaceKind::NoDraw
+        } else {
+            SurfaceKind::Normal
+        }
+    }
+
+    /// Whether surfaces of this kind are drawn at all.
+    pub fn is_drawn(&self) -> bool {
+        !matches!(self, SurfaceKind::NoDraw | SurfaceKind::Clip)
+    }
+
+    /// Whether the texture coordinates of this surface are warped.
+    pub fn is_warped(&self) -> bool {
+        matches!(self, SurfaceKind::Liquid(_))
+    }
+}
+
+/// Returns the name of the `index`th brush model in a BSP file.
+///
+/// The world is always `*0`, and the rest are the models of doors, platforms and other brush
+/// entities, which refer to them by this name.
+pub fn submodel_name(index: usize) -> String {
+    format!("*{}", index)
+}
+
+/// Returns the index of the brush model named `name`, if it is the name of one.
+pub fn submodel_index<S>(name: S) -> Option<usize>
+where
+    S: AsRef<str>,
+{
+    name.as_ref().strip_prefix('*')?.parse().ok()
 }
 
 #[derive(Debug)]
@@ -682,6 +795,8 @@ pub struct BspLightmap<'a> {
     width: u32,
     height: u32,
     data: &'a [u8],
+    /// The RGB triplets of the map's `.lit` file for the same texels, if it has one.
+    rgb: Option<&'a [u8]>,
 }
 
 impl<'a> BspLightmap<'a> {
@@ -696,6 +811,17 @@ impl<'a> BspLightmap<'a> {
     pub fn data(&self) -> &[u8] {
         self.data
     }
+
+    /// The lightmap as RGBA texels. Maps without colored lightmaps are lit with white light.
+    pub fn rgba(&self) -> Vec<u8> {
+        match self.rgb {
+            Some(rgb) => rgb
+                .chunks_exact(3)
+                .flat_map(|c| [c[0], c[1], c[2], 0xFF])
+                .collect(),
+            None => self.data.iter().flat_map(|l| [*l, *l, *l, 0xFF]).collect(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -708,6 +834,8 @@ pub struct BspData {
     pub(crate) texinfo: Box<[BspTexInfo]>,
     pub(crate) faces: Box<[BspFace]>,
     pub(crate) lightmaps: Box<[u8]>,
+    /// Three bytes for every byte of `lightmaps`, from the map's `.lit` file.
+    pub(crate) colored_lightmaps: Option<Box<[u8]>>,
     pub(crate) leaves: Box<[BspLeaf]>,
     pub(crate) facelist: Box<[usize]>,
     pub(crate) edges: Box<[BspEdge]>,
@@ -753,6 +881,10 @@ impl BspData {
         &self.texinfo[self.faces[face_id].texinfo_id]
     }
 
+    pub fn face_surface(&self, face_id: usize) -> SurfaceKind {
+        self.textures[self.face_texinfo(face_id).tex_id].surface()
+    }
+
     pub fn face_lightmaps(&self, face_id: usize) -> Vec<BspLightmap> {
         let face = &self.faces[face_id];
         match face.lightmap_id {
@@ -772,6 +904,10 @@ impl BspData {
                             width: lightmap_w,
                             height: lightmap_h,
                             data: &self.lightmaps[start..end],
+                            rgb: self
+                                .colored_lightmaps
+                                .as_ref()
+                                .map(|rgb| &rgb[start * 3..end * 3]),
                         }
                     })
                     .collect()
@@ -828,6 +964,35 @@ impl BspData {
         }
     }
 
+    /// Returns whether any of the leaves that the box from `min` to `max` reaches satisfies
+    /// `pred`.
+    pub fn box_touches_leaf<F>(&self, min: Vector3<f32>, max: Vector3<f32>, mut pred: F) -> bool
+    where
+        F: FnMut(usize) -> bool,
+    {
+        let mut children = vec![&BspRenderNodeChild::Node(0)];
+        while let Some(child) = children.pop() {
+            match *child {
+                BspRenderNodeChild::Node(node_id) => {
+                    let node = &self.render_nodes[node_id];
+                    let sides = self.planes[node.plane_id].box_sides(min, max);
+                    for (child, reached) in node.children.iter().zip(sides) {
+                        if reached {
+                            children.push(child);
+                        }
+                    }
+                }
+                BspRenderNodeChild::Leaf(leaf_id) => {
+                    if pred(leaf_id) {
+                        return true;
+                    }
+                }
+            }
+        }
+
+        false
+    }
+
     pub fn get_pvs(&self, leaf_id: usize, leaf_count: usize) -> Vec<usize> {
         // leaf 0 is outside the map, everything is visible
         if leaf_id == 0 {
@@ -1010,6 +1175,62 @@ impl BspModel {
 
 impl BspData {}
 
+/// A map that is nothing but a solid box from `min` to `max`, with the collision hulls a map
+/// compiler would build for it, for testing movement through the world.
+#[cfg(test)]
+pub(crate) fn box_model(min: Vector3<f32>, max: Vector3<f32>) -> BspModel {
+    // each hull is the box grown by the size of the objects it's for, so that they can be traced
+    // as points
+    let hull = |clip_min: Vector3<f32>, clip_max: Vector3<f32>| {
+        let mut hull = BspCollisionHull::for_bounds(min - clip_max, max - clip_min).unwrap();
+        hull.mins = clip_min;
+        hull.maxs = clip_max;
+        hull
+    };
+    let hulls = [
+        hull(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0)),
+        hull(
+            Vector3::new(-16.0, -16.0, -24.0),
+            Vector3::new(16.0, 16.0, 32.0),
+        ),
+        hull(
+            Vector3::new(-32.0, -32.0, -24.0),
+            Vector3::new(32.0, 32.0, 64.0),
+        ),
+    ];
+    let node_count = hulls[0].nodes.len();
+
+    let bsp_data = BspData {
+        planes: hulls[0].planes.clone(),
+        textures: Default::default(),
+        vertices: Default::default(),
+        visibility: Default::default(),
+        render_nodes: Default::default(),
+        texinfo: Default::default(),
+        faces: Default::default(),
+        lightmaps: Default::default(),
+        colored_lightmaps: None,
+        leaves: Default::default(),
+        facelist: Default::default(),
+        edges: Default::default(),
+        edgelist: Default::default(),
+        hulls,
+    };
+
+    BspModel {
+        bsp_data: Arc::new(bsp_data),
+        min,
+        max,
+        origin: Vector3::new(0.0, 0.0, 0.0),
+        collision_node_ids: [0; MAX_HULLS],
+        collision_node_counts: [node_count; MAX_HULLS],
+        leaf_id: 0,
+        leaf_count: 0,
+        face_id: 0,
+        face_count: 0,
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -1067,4 +1288,58 @@ mod test {
             );
         }
     }
+
+    #[test]
+    fn test_surface_from_texture_name() {
+        let cases = [
+            ("city4_2", SurfaceKind::Normal),
+            ("sky4", SurfaceKind::Sky),
+            ("*water0", SurfaceKind::Liquid(LiquidKind::Water)),
+            ("*04water1", SurfaceKind::Liquid(LiquidKind::Water)),
+            ("*lava1", SurfaceKind::Liquid(LiquidKind::Lava)),
+            ("*slime0", SurfaceKind::Liquid(LiquidKind::Slime)),
+            ("*teleport", SurfaceKind::Liquid(LiquidKind::Teleport)),
+            ("trigger", SurfaceKind::NoDraw),
+            ("CLIP", SurfaceKind::Clip),
+        ];
+
+        for (name, kind) in cases {
+            assert_eq!(SurfaceKind::from_texture_name(name), kind, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_lightmap_rgba() {
+        let white = BspLightmap {
+            width: 2,
+            height: 1,
+            data: &[0x10, 0x80],
+            rgb: None,
+        };
+        assert_eq!(
+            white.rgba(),
+            [0x10, 0x10, 0x10, 0xFF, 0x80, 0x80, 0x80, 0xFF]
+        );
+
+        let colored = BspLightmap {
+            rgb: Some(&[1, 2, 3, 4, 5, 6]),
+            ..white
+        };
+        assert_eq!(colored.rgba(), [1, 2, 3, 0xFF, 4, 5, 6, 0xFF]);
+
+        let mut lit = b"QLIT".to_vec();
+        lit.extend_from_slice(&1i32.to_le_bytes());
+        lit.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(&*read_lit(&lit[..]).unwrap(), &[1, 2, 3]);
+        assert!(read_lit(&b"QLIT\x02\0\0\0"[..]).is_err());
+    }
+
+    #[test]
+    fn test_submodel_names() {
+        assert_eq!(submodel_name(3), "*3");
+        assert_eq!(submodel_index("*0"), Some(0));
+        assert_eq!(submodel_index(submodel_name(12)), Some(12));
+        assert_eq!(submodel_index("progs/player.mdl"), None);
+        assert_eq!(submodel_index("*"), None);
+    }
 }
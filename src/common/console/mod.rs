@@ -112,6 +112,7 @@ impl Plugin for SeismonConsolePlugin {
             .init_resource::<ConsoleAlertSettings>()
             .init_resource::<Gfx>()
             .add_event::<RunCmd<'static>>()
+            .add_event::<ConsoleText>()
             .add_systems(
                 Startup,
                 (
@@ -217,6 +218,8 @@ pub enum ConsoleError {
     NoSuchAlias { name: CName },
     #[snafu(display("No such cvar: {name}"))]
     NoSuchCvar { name: CName },
+    #[snafu(display("{name} is cheat protected"))]
+    CheatProtected { name: CName },
 }
 
 impl serde::de::Error for ConsoleError {
@@ -734,6 +737,8 @@ pub struct Registry {
     commands: HashMap<CName, (CommandImpl, Vec<CommandImpl>)>,
     changed_cvars: HashMap<EqHack<SystemId<Value>>, Value>,
     names: BTreeSet<CName>,
+    /// If true, cvars marked as `cheat` cannot be changed from their defaults
+    cheats_locked: bool,
 }
 
 impl Registry {
@@ -964,12 +969,21 @@ impl Registry {
     where
         N: AsRef<str>,
     {
+        let cheats_locked = self.cheats_locked;
         let (cvar, on_set) =
             self.get_cvar_mut(name.as_ref())
                 .ok_or_else(|| ConsoleError::NoSuchCvar {
                     name: name.as_ref().to_owned().into(),
                 })?;
 
+        if cheats_locked && cvar.cheat && value != cvar.default {
+            return Err(ConsoleError::CheatProtected {
+                name: name.as_ref().to_owned().into(),
+            });
+        }
+
+        let value = cvar.clamp(value);
+
         let to_insert = if let Some(sys) = on_set {
             if cvar.value.as_ref().unwrap_or(&cvar.default) != &value {
                 let value = value.clone();
@@ -1002,6 +1016,82 @@ impl Registry {
         self.set_cvar_raw(name, value)
     }
 
+    /// Whether cvars marked as `cheat` can currently be changed.
+    pub fn cheats_allowed(&self) -> bool {
+        !self.cheats_locked
+    }
+
+    /// Allow or disallow changing cvars marked as `cheat`.
+    ///
+    /// When cheats are disallowed, every cheat cvar is reset to its default value.
+    pub fn set_cheats_allowed(&mut self, allowed: bool) {
+        if self.cheats_locked == !allowed {
+            return;
+        }
+
+        self.cheats_locked = !allowed;
+
+        if self.cheats_locked {
+            let cheat_cvars = self
+                .names
+                .iter()
+                .filter(|name| self.get_cvar(name).map(|cvar| cvar.cheat) == Some(true))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            for name in cheat_cvars {
+                self.reset_cvar(name).unwrap();
+            }
+        }
+    }
+
+    /// Restrict a numeric cvar to the range `min..=max`, clamping its current value.
+    ///
+    /// Limits can only be narrowed - if the cvar already has limits, the new limits are
+    /// intersected with them. Use [`Registry::clear_cvar_limits`] to remove them.
+    pub fn limit_cvar<N>(&mut self, name: N, min: f32, max: f32) -> Result<(), ConsoleError>
+    where
+        N: AsRef<str>,
+    {
+        let name = name.as_ref();
+        let (cvar, _) = self
+            .get_cvar_mut(name)
+            .ok_or_else(|| ConsoleError::NoSuchCvar {
+                name: name.to_owned().into(),
+            })?;
+
+        let (min, max) = match cvar.limit {
+            Some((old_min, old_max)) => (min.max(old_min), max.min(old_max)),
+            None => (min, max),
+        };
+        cvar.limit = Some((min, max.max(min)));
+
+        let value = cvar.value().clone();
+        if cvar.clamp(value.clone()) != value {
+            self.set_cvar_raw(name, value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether any cvar currently has limits set with [`Registry::limit_cvar`].
+    pub fn has_cvar_limits(&self) -> bool {
+        self.names
+            .iter()
+            .any(|name| self.get_cvar(name).map(|cvar| cvar.limit.is_some()) == Some(true))
+    }
+
+    /// Remove all limits set with [`Registry::limit_cvar`].
+    pub fn clear_cvar_limits(&mut self) {
+        for (first, rest) in self.commands.values_mut() {
+            for cmd in iter::once(first).chain(rest) {
+                if let CmdKind::Cvar { cvar, .. } = &mut cmd.kind {
+                    cvar.limit = None;
+                }
+            }
+        }
+    }
+
     /// Deserialize a single value from cvars
     pub fn read_cvar<'a, V: serde::Deserialize<'a>>(
         &'a self,
@@ -1487,6 +1577,12 @@ pub struct Cvar {
     // - If a client cvar, update userinfo
     pub notify: bool,
 
+    // If true, this variable can only be changed when cheats are allowed (see `sv_cheats`)
+    pub cheat: bool,
+
+    // If set, the server has restricted this variable to the given range
+    pub limit: Option<(f32, f32)>,
+
     // The default value of this variable
     pub default: Value,
 }
@@ -1497,6 +1593,8 @@ impl Default for Cvar {
             value: default(),
             archive: default(),
             notify: default(),
+            cheat: default(),
+            limit: default(),
             default: Value::Nil,
         }
     }
@@ -1529,9 +1627,30 @@ impl Cvar {
         self
     }
 
+    pub fn cheat(mut self) -> Self {
+        self.cheat = true;
+
+        self
+    }
+
     pub fn value(&self) -> &Value {
         self.value.as_ref().unwrap_or(&self.default)
     }
+
+    /// Clamp a value to this variable's limits, if it has any and the value is numeric.
+    fn clamp(&self, value: Value) -> Value {
+        match (self.limit, value.as_f64()) {
+            (Some((min, max)), Some(val)) if val < min as f64 || val > max as f64 => {
+                let clamped = val.clamp(min as f64, max as f64);
+                if value.is_i64() && clamped.fract() == 0. {
+                    Value::from(clamped as i64)
+                } else {
+                    Value::from(clamped)
+                }
+            }
+            _ => value,
+        }
+    }
 }
 
 /// The line of text currently being edited in the console.
@@ -1779,7 +1898,9 @@ impl ConsoleInput {
     }
 }
 
-#[derive(Debug, Clone)]
+/// Text printed to the console. Text is also sent as an event when it reaches the console, for
+/// anything else that wants to follow what is printed.
+#[derive(Event, Debug, Clone)]
 pub struct ConsoleText {
     pub output_type: OutputType,
     pub text: QString,
@@ -1800,10 +1921,23 @@ impl Timestamp {
     }
 }
 
+/// The widest a line of a centerprint gets, in characters.
+const CENTER_PRINT_WIDTH: usize = 40;
+
+/// Text printed in the middle of the screen.
+#[derive(Clone, Debug, Default)]
+pub struct CenterPrint {
+    pub timestamp: Timestamp,
+    pub text: QString,
+    /// Whether the text is typed out a character at a time, as with the text at the end of an
+    /// episode, rather than shown all at once for `scr_centertime`.
+    pub slow_reveal: bool,
+}
+
 #[derive(Resource, Default, Debug)]
 pub struct ConsoleOutput {
     generation: u16,
-    center_print: Option<(Timestamp, QString)>,
+    center_print: Option<CenterPrint>,
     buffer_ty: OutputType,
     buffer: QString,
     last_timestamp: i64,
@@ -1813,7 +1947,9 @@ pub struct ConsoleOutput {
 #[derive(Resource, Default)]
 pub struct RenderConsoleOutput {
     pub text_chunks: BTreeMap<Timestamp, ConsoleText>,
-    pub center_print: (Timestamp, QString),
+    pub center_print: CenterPrint,
+    /// How many bytes of the centerprint are on the screen.
+    pub center_print_visible: usize,
 }
 
 impl ConsoleOutput {
@@ -1896,14 +2032,29 @@ impl ConsoleOutput {
     }
 
     pub fn set_center_print<S: Into<QString>>(&mut self, print: S, timestamp: Duration) {
+        self.push_center_print(print.into(), timestamp, false);
+    }
+
+    /// Print text in the middle of the screen that is revealed at `scr_printspeed` characters per
+    /// second, and stays until it is replaced or cleared.
+    pub fn set_finale_print<S: Into<QString>>(&mut self, print: S, timestamp: Duration) {
+        self.push_center_print(print.into(), timestamp, true);
+    }
+
+    pub fn clear_center_print(&mut self, timestamp: Duration) {
+        self.push_center_print(QString::default(), timestamp, false);
+    }
+
+    fn push_center_print(&mut self, print: QString, timestamp: Duration, slow_reveal: bool) {
         let generation = self.generation();
-        self.center_print = Some((
-            Timestamp::new(timestamp.num_milliseconds(), generation),
-            print.into(),
-        ));
+        self.center_print = Some(CenterPrint {
+            timestamp: Timestamp::new(timestamp.num_milliseconds(), generation),
+            text: wrap_center_print(&print).into(),
+            slow_reveal,
+        });
     }
 
-    pub fn drain_center_print(&mut self) -> Option<(Timestamp, QString)> {
+    pub fn drain_center_print(&mut self) -> Option<CenterPrint> {
         self.center_print.take()
     }
 
@@ -1922,8 +2073,8 @@ impl RenderConsoleOutput {
     }
 
     pub fn center_print(&self, since: Duration) -> Option<QStr> {
-        if self.center_print.0.timestamp >= since.num_milliseconds() {
-            Some(self.center_print.1.reborrow())
+        if self.center_print.timestamp.timestamp >= since.num_milliseconds() {
+            Some(self.center_print.text.reborrow())
         } else {
             None
         }
@@ -2060,6 +2211,8 @@ impl FromWorld for Gfx {
 }
 
 // TODO: Extract this so that it can be used elsewhere in the UI
+pub use self::console_text::AtlasText;
+
 mod console_text {
     use super::*;
 
@@ -2135,6 +2288,44 @@ mod console_text {
     }
 }
 
+/// Breaks the lines of a centerprint that are too wide for the screen, between words where there
+/// are any.
+fn wrap_center_print(text: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(text.len());
+    for (i, mut line) in text.split(|c| *c == b'\n').enumerate() {
+        if i > 0 {
+            out.push(b'\n');
+        }
+
+        while line.len() > CENTER_PRINT_WIDTH {
+            // spaces are the same in both colors of the character set
+            let (head, rest) = match line[..=CENTER_PRINT_WIDTH]
+                .iter()
+                .rposition(|c| c & 0x7f == b' ')
+            {
+                Some(space) if space > 0 => (&line[..space], &line[space + 1..]),
+                _ => line.split_at(CENTER_PRINT_WIDTH),
+            };
+            out.extend_from_slice(head);
+            out.push(b'\n');
+            line = rest;
+        }
+        out.extend_from_slice(line);
+    }
+
+    out
+}
+
+/// The number of bytes of `text` that are shown once `chars` characters have been revealed. Line
+/// breaks are passed over for free.
+fn revealed_len(text: &[u8], chars: usize) -> usize {
+    text.iter()
+        .enumerate()
+        .filter(|(_, c)| **c != b'\n')
+        .nth(chars)
+        .map_or(text.len(), |(i, _)| i)
+}
+
 mod systems {
     use std::collections::VecDeque;
 
@@ -2338,7 +2529,7 @@ mod systems {
                 InputFocus::Console => {
                     *vis = Visibility::Visible;
                 }
-                InputFocus::Game | InputFocus::Menu => {
+                InputFocus::Game | InputFocus::Menu | InputFocus::Chat => {
                     *vis = Visibility::Hidden;
                 }
             }
@@ -2363,6 +2554,7 @@ mod systems {
         mut render_out: ResMut<RenderConsoleOutput>,
         console_in: Res<ConsoleInput>,
         mut render_in: ResMut<RenderConsoleInput>,
+        mut printed: EventWriter<ConsoleText>,
         time: Res<Time<Virtual>>,
         registry: Res<Registry>,
     ) {
@@ -2370,17 +2562,29 @@ mod systems {
             render_out.center_print = center;
         }
 
-        let center_time = registry.read_cvar::<f32>("scr_centertime").unwrap_or(2.);
-        if !render_out.center_print.1.is_empty()
-            && (time.elapsed().as_millis() as i64)
-                > (render_out.center_print.0.timestamp + (center_time * 1000.) as i64)
-        {
-            render_out.center_print.1.clear();
+        let center = &render_out.center_print;
+        let shown_ms = time.elapsed().as_millis() as i64 - center.timestamp.timestamp;
+        let visible = if center.slow_reveal {
+            let print_speed = registry.read_cvar::<f32>("scr_printspeed").unwrap_or(8.);
+            let chars = (shown_ms.max(0) as f32 / 1000. * print_speed) as usize;
+            revealed_len(&center.text, chars)
+        } else {
+            let center_time = registry.read_cvar::<f32>("scr_centertime").unwrap_or(2.);
+            if shown_ms > (center_time * 1000.) as i64 {
+                0
+            } else {
+                center.text.len()
+            }
+        };
+
+        // only touch the resource when the text on screen changes, so it isn't redrawn every frame
+        if render_out.center_print_visible != visible {
+            render_out.center_print_visible = visible;
         }
 
-        let new_text = console_out.drain_unwritten();
-        if new_text.len() > 0 {
-            render_out.text_chunks.extend(new_text);
+        for (timestamp, text) in console_out.drain_unwritten() {
+            printed.send(text.clone());
+            render_out.text_chunks.insert(timestamp, text);
         }
 
         if !itertools::equal(render_in.cur_text.chars(), console_in.get_text()) {
@@ -2415,8 +2619,10 @@ mod systems {
                 text.text.clear();
             }
 
-            if !console_out.center_print.1.is_empty() {
-                text.text.push_bytes(&*console_out.center_print.1.raw);
+            let visible = console_out.center_print_visible;
+            if visible > 0 {
+                text.text
+                    .push_bytes(&console_out.center_print.text[..visible]);
             }
         }
     }
@@ -2491,6 +2697,7 @@ mod systems {
         while let Some(RunCmd(CmdName { name, trigger }, args)) = commands.pop_front() {
             let mut name = Cow::from(name);
             loop {
+                let cheats_allowed = world.resource::<Registry>().cheats_allowed();
                 let (output, output_ty) = match world.resource_mut::<Registry>().get_mut(&*name) {
                     Some(CommandImpl { kind, .. }) => {
                         match (trigger, kind) {
@@ -2505,16 +2712,27 @@ mod systems {
                                             Value::String(new_value.clone().into())
                                         });
 
-                                    if cvar.value() != &new_value {
-                                        if let Some(on_set) = on_set {
-                                            changed_cvars
-                                                .push((EqHack(on_set.clone()), new_value.clone()));
+                                    if cvar.cheat && !cheats_allowed && new_value != cvar.default {
+                                        (
+                                            Cow::from(format!("{} is cheat protected", name)),
+                                            OutputType::Console,
+                                        )
+                                    } else {
+                                        let new_value = cvar.clamp(new_value);
+
+                                        if cvar.value() != &new_value {
+                                            if let Some(on_set) = on_set {
+                                                changed_cvars.push((
+                                                    EqHack(on_set.clone()),
+                                                    new_value.clone(),
+                                                ));
+                                            }
+
+                                            cvar.value = Some(new_value);
                                         }
 
-                                        cvar.value = Some(new_value);
+                                        break;
                                     }
-
-                                    break;
                                 }
                                 Some(_) => (
                                     Cow::from("Too many arguments, expected 1"),
@@ -2626,3 +2844,70 @@ mod systems {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cheat_cvar_locked() {
+        let mut registry = Registry::new();
+        registry.cvar("r_fullbright", Cvar::new("0").cheat(), None, "");
+        registry.cvar("fov", "90", None, "");
+
+        registry.set_cvar("r_fullbright", "1").unwrap();
+        registry.set_cheats_allowed(false);
+
+        // Disallowing cheats resets cheat cvars to their defaults
+        assert_eq!(registry.read_cvar::<u8>("r_fullbright").unwrap(), 0);
+        assert!(matches!(
+            registry.set_cvar("r_fullbright", "1"),
+            Err(ConsoleError::CheatProtected { .. })
+        ));
+        assert!(registry.set_cvar("fov", "100").is_ok());
+    }
+
+    #[test]
+    fn test_cvar_limits() {
+        let mut registry = Registry::new();
+        registry.cvar("gamma", "2", None, "");
+
+        registry.limit_cvar("gamma", 0., 1.).unwrap();
+        assert_eq!(registry.read_cvar::<u8>("gamma").unwrap(), 1);
+
+        // Limits can only be narrowed
+        registry.limit_cvar("gamma", -1., 4.).unwrap();
+        registry.set_cvar("gamma", "3").unwrap();
+        assert_eq!(registry.read_cvar::<u8>("gamma").unwrap(), 1);
+
+        registry.clear_cvar_limits();
+        registry.set_cvar("gamma", "3").unwrap();
+        assert_eq!(registry.read_cvar::<u8>("gamma").unwrap(), 3);
+    }
+
+    #[test]
+    fn test_wrap_center_print() {
+        assert_eq!(wrap_center_print(b"short\nlines"), b"short\nlines");
+
+        let long = b"The Elder God Shub-Niggurath has been banished from this dimension";
+        let wrapped = wrap_center_print(long);
+        assert_eq!(
+            wrapped,
+            b"The Elder God Shub-Niggurath has been\nbanished from this dimension"
+        );
+
+        // words too long for a line are broken anywhere
+        let wrapped = wrap_center_print(&[b'a'; 50]);
+        assert_eq!(wrapped.iter().position(|c| *c == b'\n'), Some(40));
+        assert_eq!(wrapped.len(), 51);
+    }
+
+    #[test]
+    fn test_revealed_len() {
+        let text = b"ab\ncd";
+        assert_eq!(revealed_len(text, 0), 0);
+        assert_eq!(revealed_len(text, 2), 2);
+        assert_eq!(revealed_len(text, 3), 4);
+        assert_eq!(revealed_len(text, 10), text.len());
+    }
+}
@@ -472,6 +472,32 @@ impl Hyperplane {
         }
     }
 
+    /// Calculates which sides of this hyperplane the axis-aligned box from `min` to `max` reaches,
+    /// indexed by [`HyperplaneSide`]. A box that touches the plane reaches the positive side, as
+    /// a point on it would.
+    pub fn box_sides(&self, min: Vector3<f32>, max: Vector3<f32>) -> [bool; 2] {
+        match self.alignment {
+            Alignment::Axis(a) => [max[a as usize] >= self.dist, min[a as usize] < self.dist],
+            Alignment::Normal(n) => {
+                // the corners farthest along and against the normal
+                let mut front = min;
+                let mut back = max;
+                for i in 0..3 {
+                    if n[i] >= 0.0 {
+                        front[i] = max[i];
+                        back[i] = min[i];
+                    }
+                }
+
+                let n = Vector3::from(n);
+                [
+                    front.dot(n) - self.dist >= 0.0,
+                    back.dot(n) - self.dist < 0.0,
+                ]
+            }
+        }
+    }
+
     /// Calculates the intersection of a line segment with this hyperplane.
     pub fn line_segment_intersection(
         &self,
@@ -702,6 +728,29 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_hyperplane_box_sides() {
+        let min = Vector3::new(-1.0, -1.0, -1.0);
+        let max = Vector3::new(1.0, 1.0, 1.0);
+
+        let plane = Hyperplane::axis_x(0.0);
+        assert_eq!(plane.box_sides(min, max), [true, true]);
+        assert_eq!(
+            plane.box_sides(min + Vector3::unit_x() * 2.0, max * 3.0),
+            [true, false]
+        );
+        assert_eq!(
+            plane.box_sides(min * 3.0, max - Vector3::unit_x()),
+            [true, true]
+        );
+        assert_eq!(plane.box_sides(min * 3.0, -max * 2.0), [false, true]);
+
+        let diagonal = Hyperplane::new(Vector3::new(1.0, 1.0, 0.0), 2.0);
+        assert_eq!(diagonal.box_sides(min, max), [false, true]);
+        assert_eq!(diagonal.box_sides(min, max * 2.0), [true, true]);
+        assert_eq!(diagonal.box_sides(max * 2.0, max * 3.0), [true, false]);
+    }
+
     #[test]
     fn test_hyperplane_point_dist_x() {
         let plane = Hyperplane::axis_x(1.0);
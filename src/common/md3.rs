@@ -0,0 +1,564 @@
+//! Quake III `.md3` models, which replacement model packs use in place of `.mdl` models.
+//!
+//! An MD3 model is made of surfaces, each with its own triangles, texture coordinates, skin and a
+//! set of vertices for every frame. Each frame also places the model's tags, named points with
+//! their own axes that other models are attached to, such as a weapon in a player's hand.
+
+use std::{
+    f32::consts::TAU,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+};
+
+use crate::common::util::read_f32_3;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use cgmath::{Matrix4, Vector3};
+use thiserror::Error;
+
+pub const MAGIC: [u8; 4] = *b"IDP3";
+pub const VERSION: i32 = 15;
+
+/// The length of the names of models, tags, surfaces and shaders.
+const NAME_LEN: usize = 64;
+/// The length of the names of frames.
+const FRAME_NAME_LEN: usize = 16;
+/// Vertex positions are stored in 64ths of a unit.
+const XYZ_SCALE: f32 = 1.0 / 64.0;
+
+#[derive(Error, Debug)]
+pub enum Md3FileError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Invalid magic number: {0:?}")]
+    InvalidMagicNumber([u8; 4]),
+    #[error("Unrecognized version: {0}")]
+    UnrecognizedVersion(i32),
+    #[error("Invalid {kind} count: {count}")]
+    InvalidCount { kind: &'static str, count: i32 },
+    #[error("Surface {surface} has {found} frames, but the model has {expected}")]
+    FrameCountMismatch {
+        surface: String,
+        found: usize,
+        expected: usize,
+    },
+    #[error("Invalid vertex index: {0}")]
+    InvalidVertexIndex(i32),
+}
+
+#[derive(Clone, Debug)]
+pub struct Md3Frame {
+    name: String,
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+}
+
+impl Md3Frame {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the minimum extent of this frame relative to the model origin.
+    pub fn min(&self) -> Vector3<f32> {
+        self.min
+    }
+
+    /// Returns the maximum extent of this frame relative to the model origin.
+    pub fn max(&self) -> Vector3<f32> {
+        self.max
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Md3Tag {
+    name: String,
+    origin: Vector3<f32>,
+    axes: [Vector3<f32>; 3],
+}
+
+impl Md3Tag {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn origin(&self) -> Vector3<f32> {
+        self.origin
+    }
+
+    /// Returns the forward, left and up axes of the tag.
+    pub fn axes(&self) -> [Vector3<f32>; 3] {
+        self.axes
+    }
+
+    /// Returns the transform from the space of a model attached to this tag to the space of the
+    /// model the tag belongs to, both in Quake coordinates.
+    pub fn transform(&self) -> Matrix4<f32> {
+        let [forward, left, up] = self.axes;
+        Matrix4::from_cols(
+            forward.extend(0.0),
+            left.extend(0.0),
+            up.extend(0.0),
+            self.origin.extend(1.0),
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Md3Vertex {
+    pub position: Vector3<f32>,
+    pub normal: Vector3<f32>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Md3Surface {
+    name: String,
+    shaders: Vec<String>,
+    triangles: Vec<[u32; 3]>,
+    texcoords: Vec<[f32; 2]>,
+    /// The vertices of each frame.
+    frames: Vec<Box<[Md3Vertex]>>,
+}
+
+impl Md3Surface {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the names of the images the surface is drawn with, which usually have a file
+    /// extension.
+    pub fn shaders(&self) -> &[String] {
+        &self.shaders
+    }
+
+    /// Returns the vertex indices of each triangle.
+    pub fn triangles(&self) -> &[[u32; 3]] {
+        &self.triangles
+    }
+
+    /// Returns the texture coordinates of each vertex, which are the same in every frame.
+    pub fn texcoords(&self) -> &[[f32; 2]] {
+        &self.texcoords
+    }
+
+    /// Returns the vertices of frame `frame_id`, if the model has that frame.
+    pub fn vertices(&self, frame_id: usize) -> Option<&[Md3Vertex]> {
+        self.frames.get(frame_id).map(|v| &**v)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Md3Model {
+    frames: Vec<Md3Frame>,
+    /// The tags of each frame.
+    tags: Vec<Vec<Md3Tag>>,
+    surfaces: Vec<Md3Surface>,
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+}
+
+impl Md3Model {
+    pub fn frames(&self) -> &[Md3Frame] {
+        &self.frames
+    }
+
+    pub fn frame(&self, frame_id: usize) -> Option<&Md3Frame> {
+        self.frames.get(frame_id)
+    }
+
+    pub fn surfaces(&self) -> &[Md3Surface] {
+        &self.surfaces
+    }
+
+    /// Returns the tags of every frame.
+    pub fn tags(&self) -> &[Vec<Md3Tag>] {
+        &self.tags
+    }
+
+    /// Returns the tag called `name` as it's placed in frame `frame_id`.
+    pub fn tag(&self, frame_id: usize, name: &str) -> Option<&Md3Tag> {
+        self.tags.get(frame_id)?.iter().find(|tag| tag.name == name)
+    }
+
+    /// Returns the minimum extent of all frames relative to the model origin.
+    pub fn min(&self) -> Vector3<f32> {
+        self.min
+    }
+
+    /// Returns the maximum extent of all frames relative to the model origin.
+    pub fn max(&self) -> Vector3<f32> {
+        self.max
+    }
+}
+
+pub fn load<R>(data: R) -> Result<Md3Model, Md3FileError>
+where
+    R: Read + Seek,
+{
+    let mut reader = BufReader::new(data);
+
+    read_magic(&mut reader)?;
+    let version = reader.read_i32::<LittleEndian>()?;
+    if version != VERSION {
+        Err(Md3FileError::UnrecognizedVersion(version))?;
+    }
+
+    let _name = read_name(&mut reader, NAME_LEN)?;
+    let _flags = reader.read_i32::<LittleEndian>()?;
+    let frame_count = read_count(&mut reader, "frame")?;
+    if frame_count == 0 {
+        Err(Md3FileError::InvalidCount {
+            kind: "frame",
+            count: 0,
+        })?;
+    }
+    let tag_count = read_count(&mut reader, "tag")?;
+    let surface_count = read_count(&mut reader, "surface")?;
+    let _skin_count = reader.read_i32::<LittleEndian>()?;
+    let frames_offset = read_offset(&mut reader)?;
+    let tags_offset = read_offset(&mut reader)?;
+    let surfaces_offset = read_offset(&mut reader)?;
+
+    reader.seek(SeekFrom::Start(frames_offset))?;
+    let mut frames = Vec::with_capacity(frame_count);
+    for _ in 0..frame_count {
+        let min = read_f32_3(&mut reader)?.into();
+        let max = read_f32_3(&mut reader)?.into();
+        let _local_origin = read_f32_3(&mut reader)?;
+        let _radius = reader.read_f32::<LittleEndian>()?;
+        let name = read_name(&mut reader, FRAME_NAME_LEN)?;
+        frames.push(Md3Frame { name, min, max });
+    }
+
+    reader.seek(SeekFrom::Start(tags_offset))?;
+    let mut tags = Vec::with_capacity(frame_count);
+    for _ in 0..frame_count {
+        let mut frame_tags = Vec::with_capacity(tag_count);
+        for _ in 0..tag_count {
+            let name = read_name(&mut reader, NAME_LEN)?;
+            let origin = read_f32_3(&mut reader)?.into();
+            let mut axes = [Vector3::new(0.0, 0.0, 0.0); 3];
+            for axis in axes.iter_mut() {
+                *axis = read_f32_3(&mut reader)?.into();
+            }
+            frame_tags.push(Md3Tag { name, origin, axes });
+        }
+        tags.push(frame_tags);
+    }
+
+    let mut surfaces = Vec::with_capacity(surface_count);
+    let mut surface_start = surfaces_offset;
+    for _ in 0..surface_count {
+        let (surface, len) = read_surface(&mut reader, surface_start, frame_count)?;
+        surfaces.push(surface);
+        surface_start += len;
+    }
+
+    let (min, max) = frames
+        .iter()
+        .fold((frames[0].min, frames[0].max), |(min, max), frame| {
+            (
+                Vector3::new(
+                    min.x.min(frame.min.x),
+                    min.y.min(frame.min.y),
+                    min.z.min(frame.min.z),
+                ),
+                Vector3::new(
+                    max.x.max(frame.max.x),
+                    max.y.max(frame.max.y),
+                    max.z.max(frame.max.z),
+                ),
+            )
+        });
+
+    Ok(Md3Model {
+        frames,
+        tags,
+        surfaces,
+        min,
+        max,
+    })
+}
+
+/// Read the surface that starts at `start`, returning it and its length in bytes.
+fn read_surface<R>(
+    reader: &mut R,
+    start: u64,
+    model_frame_count: usize,
+) -> Result<(Md3Surface, u64), Md3FileError>
+where
+    R: Read + Seek,
+{
+    reader.seek(SeekFrom::Start(start))?;
+    read_magic(reader)?;
+
+    let name = read_name(reader, NAME_LEN)?;
+    let _flags = reader.read_i32::<LittleEndian>()?;
+    let frame_count = read_count(reader, "surface frame")?;
+    if frame_count != model_frame_count {
+        Err(Md3FileError::FrameCountMismatch {
+            surface: name.clone(),
+            found: frame_count,
+            expected: model_frame_count,
+        })?;
+    }
+    let shader_count = read_count(reader, "shader")?;
+    let vertex_count = read_count(reader, "vertex")?;
+    let triangle_count = read_count(reader, "triangle")?;
+    let triangles_offset = read_offset(reader)?;
+    let shaders_offset = read_offset(reader)?;
+    let texcoords_offset = read_offset(reader)?;
+    let vertices_offset = read_offset(reader)?;
+    let len = read_offset(reader)?;
+
+    reader.seek(SeekFrom::Start(start + shaders_offset))?;
+    let mut shaders = Vec::with_capacity(shader_count);
+    for _ in 0..shader_count {
+        shaders.push(read_name(reader, NAME_LEN)?);
+        let _shader_index = reader.read_i32::<LittleEndian>()?;
+    }
+
+    reader.seek(SeekFrom::Start(start + triangles_offset))?;
+    let mut triangles = Vec::with_capacity(triangle_count);
+    for _ in 0..triangle_count {
+        let mut triangle = [0; 3];
+        for index in triangle.iter_mut() {
+            *index = match reader.read_i32::<LittleEndian>()? {
+                i if i < 0 || i as usize >= vertex_count => {
+                    Err(Md3FileError::InvalidVertexIndex(i))?
+                }
+                i => i as u32,
+            };
+        }
+        triangles.push(triangle);
+    }
+
+    reader.seek(SeekFrom::Start(start + texcoords_offset))?;
+    let mut texcoords = Vec::with_capacity(vertex_count);
+    for _ in 0..vertex_count {
+        let s = reader.read_f32::<LittleEndian>()?;
+        let t = reader.read_f32::<LittleEndian>()?;
+        texcoords.push([s, t]);
+    }
+
+    reader.seek(SeekFrom::Start(start + vertices_offset))?;
+    let mut frames = Vec::with_capacity(frame_count);
+    for _ in 0..frame_count {
+        let mut vertices = Vec::with_capacity(vertex_count);
+        for _ in 0..vertex_count {
+            let mut position = [0.0; 3];
+            for c in position.iter_mut() {
+                *c = reader.read_i16::<LittleEndian>()? as f32 * XYZ_SCALE;
+            }
+            let normal = decode_normal(reader.read_u16::<LittleEndian>()?);
+            vertices.push(Md3Vertex {
+                position: position.into(),
+                normal,
+            });
+        }
+        frames.push(vertices.into_boxed_slice());
+    }
+
+    Ok((
+        Md3Surface {
+            name,
+            shaders,
+            triangles,
+            texcoords,
+            frames,
+        },
+        len,
+    ))
+}
+
+/// Normals are stored as a latitude in the high byte and a longitude in the low byte, each a
+/// fraction of a full turn.
+fn decode_normal(normal: u16) -> Vector3<f32> {
+    let lat = (normal >> 8) as f32 * TAU / 255.0;
+    let lng = (normal & 0xFF) as f32 * TAU / 255.0;
+    Vector3::new(lat.cos() * lng.sin(), lat.sin() * lng.sin(), lng.cos())
+}
+
+fn read_magic<R>(reader: &mut R) -> Result<(), Md3FileError>
+where
+    R: Read,
+{
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        Err(Md3FileError::InvalidMagicNumber(magic))?;
+    }
+
+    Ok(())
+}
+
+fn read_count<R>(reader: &mut R, kind: &'static str) -> Result<usize, Md3FileError>
+where
+    R: Read,
+{
+    match reader.read_i32::<LittleEndian>()? {
+        count if count < 0 => Err(Md3FileError::InvalidCount { kind, count }),
+        count => Ok(count as usize),
+    }
+}
+
+fn read_offset<R>(reader: &mut R) -> Result<u64, Md3FileError>
+where
+    R: Read,
+{
+    Ok(read_count(reader, "offset")? as u64)
+}
+
+/// Read a name padded with nulls to `len` bytes.
+fn read_name<R>(reader: &mut R, len: usize) -> io::Result<String>
+where
+    R: Read,
+{
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(len);
+    Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use byteorder::WriteBytesExt as _;
+
+    use super::*;
+
+    fn write_name(out: &mut Vec<u8>, name: &str, len: usize) {
+        let start = out.len();
+        out.extend_from_slice(name.as_bytes());
+        out.resize(start + len, 0);
+    }
+
+    fn write_i32s(out: &mut Vec<u8>, values: &[i32]) {
+        for v in values {
+            out.write_i32::<LittleEndian>(*v).unwrap();
+        }
+    }
+
+    fn write_f32s(out: &mut Vec<u8>, values: &[f32]) {
+        for v in values {
+            out.write_f32::<LittleEndian>(*v).unwrap();
+        }
+    }
+
+    /// A model with one frame, one tag and a surface with a single triangle.
+    fn triangle_md3() -> Vec<u8> {
+        const HEADER_LEN: i32 = 108;
+        const FRAME_LEN: i32 = 56;
+        const TAG_LEN: i32 = 112;
+        const SURFACE_HEADER_LEN: i32 = 108;
+
+        let surfaces_offset = HEADER_LEN + FRAME_LEN + TAG_LEN;
+        let shaders_offset = SURFACE_HEADER_LEN;
+        let triangles_offset = shaders_offset + 68;
+        let texcoords_offset = triangles_offset + 12;
+        let vertices_offset = texcoords_offset + 3 * 8;
+        let surface_len = vertices_offset + 3 * 8;
+
+        let mut out = Vec::new();
+        out.extend_from_slice(&MAGIC);
+        write_i32s(&mut out, &[VERSION]);
+        write_name(&mut out, "models/test.md3", NAME_LEN);
+        write_i32s(
+            &mut out,
+            &[
+                0,
+                1,
+                1,
+                1,
+                0,
+                HEADER_LEN,
+                HEADER_LEN + FRAME_LEN,
+                surfaces_offset,
+                surfaces_offset + surface_len,
+            ],
+        );
+
+        write_f32s(
+            &mut out,
+            &[0.0, 0.0, 0.0, 2.0, 2.0, 0.0, 0.0, 0.0, 0.0, 2.0],
+        );
+        write_name(&mut out, "frame0", FRAME_NAME_LEN);
+
+        write_name(&mut out, "tag_weapon", NAME_LEN);
+        write_f32s(
+            &mut out,
+            &[1.0, 2.0, 3.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+        );
+
+        out.extend_from_slice(&MAGIC);
+        write_name(&mut out, "body", NAME_LEN);
+        write_i32s(
+            &mut out,
+            &[
+                0,
+                1,
+                1,
+                3,
+                1,
+                triangles_offset,
+                shaders_offset,
+                texcoords_offset,
+                vertices_offset,
+                surface_len,
+            ],
+        );
+        write_name(&mut out, "models/test/body.tga", NAME_LEN);
+        write_i32s(&mut out, &[0]);
+        write_i32s(&mut out, &[0, 1, 2]);
+        write_f32s(&mut out, &[0.0, 0.0, 1.0, 0.0, 0.0, 1.0]);
+        for (x, y) in [(0, 0), (128, 0), (0, 128)] {
+            for c in [x, y, 0] {
+                out.write_i16::<LittleEndian>(c).unwrap();
+            }
+            // pointing straight up
+            out.write_u16::<LittleEndian>(0).unwrap();
+        }
+
+        out
+    }
+
+    #[test]
+    fn test_load() {
+        let model = load(Cursor::new(triangle_md3())).unwrap();
+
+        assert_eq!(model.frames().len(), 1);
+        assert_eq!(model.frame(0).unwrap().name(), "frame0");
+        assert_eq!(model.max(), Vector3::new(2.0, 2.0, 0.0));
+
+        let tag = model.tag(0, "tag_weapon").unwrap();
+        assert_eq!(tag.origin(), Vector3::new(1.0, 2.0, 3.0));
+        assert_eq!(
+            tag.transform() * Vector3::new(1.0, 0.0, 0.0).extend(1.0),
+            Vector3::new(2.0, 2.0, 3.0).extend(1.0)
+        );
+        assert!(model.tag(0, "tag_head").is_none());
+        assert!(model.tag(1, "tag_weapon").is_none());
+
+        let surface = &model.surfaces()[0];
+        assert_eq!(surface.name(), "body");
+        assert_eq!(surface.shaders(), ["models/test/body.tga"]);
+        assert_eq!(surface.triangles(), [[0, 1, 2]]);
+        let vertices = surface.vertices(0).unwrap();
+        assert_eq!(vertices[1].position, Vector3::new(2.0, 0.0, 0.0));
+        assert_eq!(vertices[0].normal, Vector3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_load_invalid() {
+        assert!(matches!(
+            load(Cursor::new(b"IDPO".to_vec())),
+            Err(Md3FileError::InvalidMagicNumber(_))
+        ));
+
+        let mut data = triangle_md3();
+        data[4] = 16;
+        assert!(matches!(
+            load(Cursor::new(data)),
+            Err(Md3FileError::UnrecognizedVersion(16))
+        ));
+    }
+}
@@ -18,7 +18,7 @@
 use std::io::{self, BufReader, Read, Seek, SeekFrom};
 
 use crate::common::{
-    engine,
+    engine, math,
     model::{ModelFlags, SyncType},
     util::read_f32_3,
 };
@@ -250,6 +250,26 @@ pub enum Keyframe {
     Animated(AnimatedKeyframe),
 }
 
+impl Keyframe {
+    /// Returns the minimum extent of this keyframe relative to the model origin. For an animated
+    /// keyframe this covers all of its subframes.
+    pub fn min(&self) -> Vector3<f32> {
+        match self {
+            Keyframe::Static(k) => k.min(),
+            Keyframe::Animated(k) => k.min(),
+        }
+    }
+
+    /// Returns the maximum extent of this keyframe relative to the model origin. For an animated
+    /// keyframe this covers all of its subframes.
+    pub fn max(&self) -> Vector3<f32> {
+        match self {
+            Keyframe::Static(k) => k.max(),
+            Keyframe::Animated(k) => k.max(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct AliasModel {
     origin: Vector3<f32>,
@@ -260,6 +280,8 @@ pub struct AliasModel {
     texcoords: Vec<Texcoord>,
     polygons: Vec<IndexedPolygon>,
     keyframes: Vec<Keyframe>,
+    min: Vector3<f32>,
+    max: Vector3<f32>,
     flags: ModelFlags,
 }
 
@@ -296,6 +318,21 @@ impl AliasModel {
         self.keyframes.iter()
     }
 
+    /// Returns the keyframe with index `id`, if there is one.
+    pub fn keyframe(&self, id: usize) -> Option<&Keyframe> {
+        self.keyframes.get(id)
+    }
+
+    /// Returns the minimum extent of all keyframes relative to the model origin.
+    pub fn min(&self) -> Vector3<f32> {
+        self.min
+    }
+
+    /// Returns the maximum extent of all keyframes relative to the model origin.
+    pub fn max(&self) -> Vector3<f32> {
+        self.max
+    }
+
     pub fn flags(&self) -> ModelFlags {
         self.flags
     }
@@ -477,9 +514,10 @@ where
         .map(|_| {
             Ok(match reader.read_i32::<LittleEndian>()? {
                 0 => {
-                    let min = read_vertex(&mut reader, scale, origin)?;
+                    // discard the stored bounds, see below
+                    read_vertex(&mut reader, scale, origin)?;
                     reader.read_u8()?; // discard vertex normal
-                    let max = read_vertex(&mut reader, scale, origin)?;
+                    read_vertex(&mut reader, scale, origin)?;
                     reader.read_u8()?; // discard vertex normal
 
                     let name = {
@@ -500,6 +538,9 @@ where
                         reader.read_u8()?; // discard vertex normal
                     }
 
+                    // the stored bounds are often stale, so find them from the vertices instead
+                    let (min, max) = math::bounds(&vertices);
+
                     Keyframe::Static(StaticKeyframe {
                         name,
                         min,
@@ -514,9 +555,10 @@ where
                         s => s,
                     };
 
-                    let abs_min = read_vertex(&mut reader, scale, origin)?;
+                    // discard the overall bounds, which are found from the subframes below
+                    read_vertex(&mut reader, scale, origin)?;
                     reader.read_u8()?; // discard vertex normal
-                    let abs_max = read_vertex(&mut reader, scale, origin)?;
+                    read_vertex(&mut reader, scale, origin)?;
                     reader.read_u8()?; // discard vertex normal
 
                     let mut durations = Vec::new();
@@ -528,9 +570,10 @@ where
 
                     let mut subframes = Vec::new();
                     for subframe_id in 0..subframe_count {
-                        let min = read_vertex(&mut reader, scale, origin)?;
+                        // discard the stored bounds, as for static keyframes
+                        read_vertex(&mut reader, scale, origin)?;
                         reader.read_u8()?; // discard vertex normal
-                        let max = read_vertex(&mut reader, scale, origin)?;
+                        read_vertex(&mut reader, scale, origin)?;
                         reader.read_u8()?; // discard vertex normal
 
                         let name = {
@@ -552,6 +595,8 @@ where
                             reader.read_u8()?; // discard vertex normal
                         }
 
+                        let (min, max) = math::bounds(&vertices);
+
                         subframes.push(AnimatedKeyframeFrame {
                             min,
                             max,
@@ -561,9 +606,16 @@ where
                         })
                     }
 
+                    let (min, max) = math::bounds(
+                        &subframes
+                            .iter()
+                            .flat_map(|subframe| [subframe.min, subframe.max])
+                            .collect::<Vec<_>>(),
+                    );
+
                     Keyframe::Animated(AnimatedKeyframe {
-                        min: abs_min,
-                        max: abs_max,
+                        min,
+                        max,
                         frames: subframes.into_boxed_slice(),
                     })
                 }
@@ -571,7 +623,14 @@ where
                 x => panic!("Bad frame kind value: {}", x),
             })
         })
-        .collect::<Result<_, MdlFileError>>()?;
+        .collect::<Result<Vec<_>, MdlFileError>>()?;
+
+    let (min, max) = math::bounds(
+        &keyframes
+            .iter()
+            .flat_map(|k| [k.min(), k.max()])
+            .collect::<Vec<_>>(),
+    );
 
     if reader.seek(SeekFrom::Current(0))? != reader.seek(SeekFrom::End(0))? {
         warn!("Misaligned read on MDL file");
@@ -586,6 +645,8 @@ where
         texcoords,
         polygons,
         keyframes,
+        min,
+        max,
         flags,
     })
 }
@@ -25,11 +25,13 @@ pub mod console;
 pub mod engine;
 pub mod host;
 pub mod math;
+pub mod md3;
 pub mod mdl;
 pub mod model;
 pub mod net;
 pub mod pak;
 pub mod parse;
+pub mod physics;
 pub mod sprite;
 pub mod util;
 pub mod vfs;
@@ -17,6 +17,7 @@
 
 use crate::common::{
     bsp::{BspFileError, BspModel},
+    md3::{self, Md3FileError, Md3Model},
     mdl::{self, AliasModel, MdlFileError},
     sprite::{self, SpriteModel},
     vfs::{Vfs, VfsError},
@@ -34,6 +35,8 @@ pub enum ModelError {
     BspFile(#[from] BspFileError),
     #[error("MDL file error: {0}")]
     MdlFile(#[from] MdlFileError),
+    #[error("MD3 file error: {0}")]
+    Md3File(#[from] Md3FileError),
     #[error("SPR file error")]
     SprFile,
     #[error("Virtual filesystem error: {0}")]
@@ -74,6 +77,7 @@ pub enum ModelKind {
     Brush(BspModel),
     Alias(AliasModel),
     Sprite(SpriteModel),
+    Md3(Md3Model),
 }
 
 impl Model {
@@ -99,6 +103,8 @@ impl Model {
             panic!("BSP files may contain multiple models, use bsp::load for this");
         } else if name.ends_with(".mdl") {
             Ok(Model::from_alias_model(name, mdl::load(vfs.open(name)?)?))
+        } else if name.ends_with(".md3") {
+            Ok(Model::from_md3_model(name, md3::load(vfs.open(name)?)?))
         } else if name.ends_with(".spr") {
             Ok(Model::from_sprite_model(
                 name,
@@ -109,6 +115,41 @@ impl Model {
         }
     }
 
+    /// Load the model `name`, or the `.md3` model of the same name in its place if `name` is an
+    /// `.mdl` model and one exists.
+    ///
+    /// The replacement keeps the name and flags of the original model, so that it's still found
+    /// by name and leaves the same trails.
+    pub fn load_with_replacement<S>(vfs: &Vfs, name: S) -> Result<Model, ModelError>
+    where
+        S: AsRef<str>,
+    {
+        let name = name.as_ref();
+        let model = Model::load(vfs, name)?;
+
+        let Some(stem) = name.strip_suffix(".mdl") else {
+            return Ok(model);
+        };
+        let md3_name = format!("{}.md3", stem);
+        let Ok(file) = vfs.open(&md3_name) else {
+            return Ok(model);
+        };
+
+        match md3::load(file) {
+            Ok(md3_model) => {
+                debug!("Replacing {} with {}", name, md3_name);
+                Ok(Model {
+                    kind: ModelKind::Md3(md3_model),
+                    ..model
+                })
+            }
+            Err(e) => {
+                warn!("Ignoring replacement model {}: {}", md3_name, e);
+                Ok(model)
+            }
+        }
+    }
+
     /// Construct a new generic model from a brush model.
     pub fn from_brush_model<S>(name: S, brush_model: BspModel) -> Model
     where
@@ -147,6 +188,18 @@ impl Model {
         }
     }
 
+    /// Construct a new generic model from an MD3 model.
+    pub fn from_md3_model<S>(name: S, md3_model: Md3Model) -> Model
+    where
+        S: AsRef<str>,
+    {
+        Model {
+            name: name.as_ref().into(),
+            kind: ModelKind::Md3(md3_model),
+            flags: ModelFlags::empty(),
+        }
+    }
+
     /// Return the name of this model.
     pub fn name(&self) -> &str {
         &self.name
@@ -159,10 +212,8 @@ impl Model {
             ModelKind::None => panic!("attempted to take min() of NULL model"),
             ModelKind::Brush(ref bmodel) => bmodel.min(),
             ModelKind::Sprite(ref smodel) => smodel.min(),
-
-            // TODO: maybe change this?
-            // https://github.com/id-Software/Quake/blob/master/WinQuake/gl_model.c#L1625
-            ModelKind::Alias(_) => Vector3::new(-16.0, -16.0, -16.0),
+            ModelKind::Alias(ref amodel) => amodel.min(),
+            ModelKind::Md3(ref md3_model) => md3_model.min(),
         }
     }
 
@@ -173,10 +224,26 @@ impl Model {
             ModelKind::None => panic!("attempted to take max() of NULL model"),
             ModelKind::Brush(ref bmodel) => bmodel.max(),
             ModelKind::Sprite(ref smodel) => smodel.max(),
+            ModelKind::Alias(ref amodel) => amodel.max(),
+            ModelKind::Md3(ref md3_model) => md3_model.max(),
+        }
+    }
 
-            // TODO: maybe change this?
-            // https://github.com/id-Software/Quake/blob/master/WinQuake/gl_model.c#L1625
-            ModelKind::Alias(_) => Vector3::new(16.0, 16.0, 16.0),
+    /// Return the minimum and maximum extent of this model in the frame `frame_id`.
+    ///
+    /// Only alias and MD3 models have bounds for each frame; other models, and frames the model
+    /// doesn't have, use the bounds of the whole model.
+    pub fn frame_bounds(&self, frame_id: usize) -> (Vector3<f32>, Vector3<f32>) {
+        match self.kind {
+            ModelKind::Alias(ref amodel) => match amodel.keyframe(frame_id) {
+                Some(keyframe) => (keyframe.min(), keyframe.max()),
+                None => (amodel.min(), amodel.max()),
+            },
+            ModelKind::Md3(ref md3_model) => match md3_model.frame(frame_id) {
+                Some(frame) => (frame.min(), frame.max()),
+                None => (md3_model.min(), md3_model.max()),
+            },
+            _ => (self.min(), self.max()),
         }
     }
 
@@ -188,6 +255,7 @@ impl Model {
             ModelKind::Sprite(ref _smodel) => SyncType::Sync,
             // TODO: expose sync_type in Mdl and reflect it here
             ModelKind::Alias(ref _amodel) => SyncType::Sync,
+            ModelKind::Md3(_) => SyncType::Sync,
         }
     }
 
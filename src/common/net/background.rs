@@ -0,0 +1,279 @@
+//! Running a [`QSocket`] away from the frame loop.
+//!
+//! Receiving from a `QSocket` can block for as long as the peer takes to answer, which during
+//! sign-on may be seconds. A [`SocketThread`] owns the socket on a thread of its own, which waits
+//! for packets and passes each whole message back over a channel, and sends the messages queued
+//! from the other end in between. Neither end ever waits for the other.
+//!
+//! Browsers have no threads, so in wasm32 builds the socket is polled without blocking whenever
+//! messages are read instead.
+//!
+//! Only one reliable message can be in flight at a time. Those queued while it waits to be
+//! acknowledged are joined and sent together once it has been, as the original engine did with
+//! its reliable message buffer.
+
+use std::collections::VecDeque;
+
+use bevy::{prelude::*, utils::Instant};
+use crossbeam_channel::{Receiver, Sender, TryRecvError};
+
+use crate::common::net::{BlockingMode, MessageKind, NetError, QSocket, MAX_MESSAGE};
+
+/// How long the thread waits for a packet before it checks for messages to send.
+#[cfg(not(target_arch = "wasm32"))]
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(2);
+
+/// How long a reliable message may go unacknowledged before it is sent again.
+const RESEND_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// A message to send, and how to send it.
+type Outgoing = (MessageKind, Vec<u8>);
+
+/// Moves messages between a [`QSocket`] and the channels of a [`SocketThread`].
+struct Pump {
+    qsock: QSocket,
+    incoming: Sender<Result<Vec<u8>, NetError>>,
+    outgoing: Receiver<Outgoing>,
+    /// Reliable messages waiting for the one in flight to be acknowledged.
+    reliable: VecDeque<Vec<u8>>,
+    /// When the reliable message in flight was last sent.
+    sent_at: Option<Instant>,
+}
+
+impl Pump {
+    fn new(
+        qsock: QSocket,
+        incoming: Sender<Result<Vec<u8>, NetError>>,
+        outgoing: Receiver<Outgoing>,
+    ) -> Pump {
+        Pump {
+            qsock,
+            incoming,
+            outgoing,
+            reliable: VecDeque::new(),
+            sent_at: None,
+        }
+    }
+
+    /// Sends the queued messages, then waits as long as `block` allows for a message to arrive.
+    /// Returns `false` once the [`SocketThread`] has been dropped or the socket has failed.
+    fn run_once(&mut self, block: BlockingMode) -> bool {
+        loop {
+            let (kind, packet) = match self.outgoing.try_recv() {
+                Ok(msg) => msg,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return false,
+            };
+
+            match kind {
+                MessageKind::Unreliable => {
+                    if let Err(e) = self.qsock.send_msg_unreliable(&packet) {
+                        if self.incoming.send(Err(e)).is_err() {
+                            return false;
+                        }
+                    }
+                }
+                MessageKind::Reliable => self.reliable.push_back(packet),
+            }
+        }
+
+        if let Err(e) = self.send_reliable() {
+            if self.incoming.send(Err(e)).is_err() {
+                return false;
+            }
+        }
+
+        let (result, fatal) = match self.qsock.recv_msg(block) {
+            Ok(msg) if msg.is_empty() => return true,
+            Ok(msg) => (Ok(msg), false),
+            // a bad packet only loses that packet, but a broken socket won't recover
+            Err(e) => {
+                let fatal = matches!(e, NetError::Io { .. });
+                (Err(e), fatal)
+            }
+        };
+
+        self.incoming.send(result).is_ok() && !fatal
+    }
+
+    /// Sends the queued reliable messages if the last one has been acknowledged, or sends the last
+    /// one again if it has waited too long. Only whole messages are joined, so the peer never sees
+    /// a command split between two of them.
+    fn send_reliable(&mut self) -> Result<(), NetError> {
+        if !self.qsock.can_send() {
+            if self.sent_at.is_some_and(|t| t.elapsed() >= RESEND_INTERVAL) {
+                self.sent_at = Some(Instant::now());
+                self.qsock.resend_msg()?;
+            }
+
+            return Ok(());
+        }
+
+        self.sent_at = None;
+        let Some(mut msg) = self.reliable.pop_front() else {
+            return Ok(());
+        };
+        while let Some(next) = self.reliable.front() {
+            if msg.len() + next.len() > MAX_MESSAGE {
+                break;
+            }
+
+            msg.extend_from_slice(next);
+            self.reliable.pop_front();
+        }
+
+        self.sent_at = Some(Instant::now());
+        self.qsock.begin_send_msg(&msg)
+    }
+}
+
+/// A [`QSocket`] that sends and receives in the background.
+///
+/// Dropping this stops the thread once the messages already queued have been sent.
+#[derive(Resource)]
+pub struct SocketThread {
+    incoming: Receiver<Result<Vec<u8>, NetError>>,
+    outgoing: Sender<Outgoing>,
+    stopped: bool,
+    #[cfg(target_arch = "wasm32")]
+    pump: Pump,
+}
+
+impl SocketThread {
+    /// Starts running `qsock`, which should already be connected to its peer.
+    pub fn spawn(qsock: QSocket) -> Result<SocketThread, NetError> {
+        let (incoming_tx, incoming) = crossbeam_channel::unbounded();
+        let (outgoing, outgoing_rx) = crossbeam_channel::unbounded();
+        let pump = Pump::new(qsock, incoming_tx, outgoing_rx);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let poll = chrono::Duration::from_std(POLL_INTERVAL).unwrap();
+            std::thread::Builder::new()
+                .name("qsocket".into())
+                .spawn(move || {
+                    let mut pump = pump;
+                    while pump.run_once(BlockingMode::Timeout(poll)) {}
+                })?;
+
+            Ok(SocketThread {
+                incoming,
+                outgoing,
+                stopped: false,
+            })
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        Ok(SocketThread {
+            incoming,
+            outgoing,
+            stopped: false,
+            pump,
+        })
+    }
+
+    /// Queues a message to be sent to the peer.
+    pub fn send(&self, kind: MessageKind, packet: Vec<u8>) -> Result<(), NetError> {
+        self.outgoing
+            .send((kind, packet))
+            .map_err(|_| NetError::with_msg("Socket thread has stopped"))
+    }
+
+    /// Returns the next message that has arrived, or `None` if there isn't one yet. Errors from
+    /// sending and receiving are returned in the order they happened.
+    pub fn try_recv(&mut self) -> Result<Option<Vec<u8>>, NetError> {
+        #[cfg(target_arch = "wasm32")]
+        if !self.stopped && !self.pump.run_once(BlockingMode::NonBlocking) {
+            self.stopped = true;
+        }
+
+        match self.incoming.try_recv() {
+            Ok(result) => result.map(Some),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => {
+                self.stopped = true;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Whether the socket has failed and stopped. The messages that arrived before it stopped
+    /// are still returned by [`SocketThread::try_recv`], and this is only set once they have all
+    /// been read.
+    pub fn stopped(&self) -> bool {
+        self.stopped
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{net::UdpSocket, time::Duration};
+
+    use super::*;
+
+    #[test]
+    fn test_socket_thread() {
+        let local = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let remote = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let local_addr = local.local_addr().unwrap();
+        let remote_addr = remote.local_addr().unwrap();
+
+        let mut thread = SocketThread::spawn(QSocket::new(local, remote_addr)).unwrap();
+        let mut peer = QSocket::new(remote, local_addr);
+
+        thread
+            .send(MessageKind::Unreliable, b"hello".to_vec())
+            .unwrap();
+        let msg = peer
+            .recv_msg(BlockingMode::Timeout(
+                chrono::Duration::try_seconds(5).unwrap(),
+            ))
+            .unwrap();
+        assert_eq!(msg, b"hello");
+
+        peer.send_msg_unreliable(b"world").unwrap();
+        let mut waited = 0;
+        let msg = loop {
+            if let Some(msg) = thread.try_recv().unwrap() {
+                break msg;
+            }
+
+            waited += 1;
+            assert!(waited < 500, "Message didn't arrive");
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(msg, b"world");
+        assert!(!thread.stopped());
+    }
+
+    #[test]
+    fn test_socket_thread_queues_reliable() {
+        let local = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let remote = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let local_addr = local.local_addr().unwrap();
+        let remote_addr = remote.local_addr().unwrap();
+
+        let thread = SocketThread::spawn(QSocket::new(local, remote_addr)).unwrap();
+        let mut peer = QSocket::new(remote, local_addr);
+
+        // the second is queued until the first is acknowledged, rather than failing
+        for msg in [&b"one"[..], b"two", b"three"] {
+            thread.send(MessageKind::Reliable, msg.to_vec()).unwrap();
+        }
+
+        let mut received = Vec::new();
+        let mut waited = 0;
+        while received.len() < b"onetwothree".len() {
+            let msg = peer
+                .recv_msg(BlockingMode::Timeout(
+                    chrono::Duration::try_milliseconds(10).unwrap(),
+                ))
+                .unwrap();
+            received.extend(msg);
+
+            waited += 1;
+            assert!(waited < 500, "Messages didn't arrive");
+        }
+        assert_eq!(received, b"onetwothree");
+    }
+}
@@ -0,0 +1,121 @@
+//! Compression of reliable messages.
+//!
+//! A client that can decompress messages says so with a flag in its connection request, and the
+//! server sets the same flag in its accept response if it will compress them. From then on, every
+//! reliable message on the connection starts with a byte giving the encoding of the rest, so that
+//! messages which deflate can't shrink are still sent as they are. The sign-on messages of big
+//! maps, which would otherwise be split into many datagrams, shrink the most.
+
+use std::io::{Read as _, Write as _};
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use num::FromPrimitive;
+use num_derive::FromPrimitive;
+
+use crate::common::net::NetError;
+
+/// The longest message that can be compressed. Messages are still limited to `MAX_MESSAGE` once
+/// compressed, but this also bounds the memory a peer can make us use by sending a tiny message
+/// that expands enormously.
+pub const MAX_UNCOMPRESSED: usize = 65536;
+
+/// Messages shorter than this are never worth compressing.
+const MIN_COMPRESS: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, FromPrimitive)]
+enum Encoding {
+    Raw = 0,
+    Deflate = 1,
+}
+
+/// Encodes a reliable message for a connection with compression enabled.
+pub fn compress(msg: &[u8]) -> Result<Vec<u8>, NetError> {
+    if msg.len() > MAX_UNCOMPRESSED {
+        return Err(NetError::with_msg(format!(
+            "Message too long to compress ({} bytes)",
+            msg.len()
+        )));
+    }
+
+    if msg.len() >= MIN_COMPRESS {
+        let mut encoder = DeflateEncoder::new(vec![Encoding::Deflate as u8], Compression::fast());
+        encoder.write_all(msg)?;
+        let compressed = encoder.finish()?;
+
+        if compressed.len() <= msg.len() {
+            return Ok(compressed);
+        }
+    }
+
+    let mut raw = Vec::with_capacity(msg.len() + 1);
+    raw.push(Encoding::Raw as u8);
+    raw.extend_from_slice(msg);
+    Ok(raw)
+}
+
+/// Decodes a reliable message received on a connection with compression enabled.
+pub fn decompress(msg: &[u8]) -> Result<Vec<u8>, NetError> {
+    let Some((&code, body)) = msg.split_first() else {
+        return Err(NetError::invalid_data("empty compressed message"));
+    };
+
+    match Encoding::from_u8(code) {
+        Some(Encoding::Raw) => Ok(body.to_owned()),
+
+        Some(Encoding::Deflate) => {
+            let mut out = Vec::with_capacity(body.len() * 4);
+            DeflateDecoder::new(body)
+                .take(MAX_UNCOMPRESSED as u64 + 1)
+                .read_to_end(&mut out)
+                .map_err(|e| NetError::invalid_data(format!("bad compressed message: {}", e)))?;
+
+            if out.len() > MAX_UNCOMPRESSED {
+                return Err(NetError::invalid_data(
+                    "compressed message expands past MAX_UNCOMPRESSED",
+                ));
+            }
+
+            Ok(out)
+        }
+
+        None => Err(NetError::invalid_data(format!(
+            "unknown message encoding {}",
+            code
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compress_round_trip() {
+        let signon: Vec<u8> = (0..20000).map(|i| (i % 7) as u8).collect();
+        let compressed = compress(&signon).unwrap();
+        assert_eq!(compressed[0], Encoding::Deflate as u8);
+        assert!(compressed.len() < signon.len() / 10);
+        assert_eq!(decompress(&compressed).unwrap(), signon);
+    }
+
+    #[test]
+    fn test_short_messages_sent_raw() {
+        let msg = b"\x08say hi\0";
+        let compressed = compress(msg).unwrap();
+        assert_eq!(compressed[0], Encoding::Raw as u8);
+        assert_eq!(&compressed[1..], msg);
+        assert_eq!(decompress(&compressed).unwrap(), msg);
+    }
+
+    #[test]
+    fn test_decompress_bad_data() {
+        assert!(decompress(&[]).is_err());
+        assert!(decompress(&[7, 1, 2, 3]).is_err());
+        assert!(compress(&[0; MAX_UNCOMPRESSED + 1]).is_err());
+
+        // a message that expands past the limit is refused
+        let mut encoder = DeflateEncoder::new(vec![Encoding::Deflate as u8], Compression::best());
+        encoder.write_all(&[0; MAX_UNCOMPRESSED + 1]).unwrap();
+        assert!(decompress(&encoder.finish().unwrap()).is_err());
+    }
+}
@@ -19,22 +19,26 @@
 // SOFTWARE.
 
 use std::{
-    io::{BufReader, Cursor, ErrorKind},
+    io::{BufRead as _, BufReader, Cursor, ErrorKind},
     mem::size_of,
     net::{SocketAddr, ToSocketAddrs, UdpSocket},
 };
 
 use crate::common::{
-    net::{NetError, QSocket, MAX_MESSAGE},
+    net::{std_timeout, NetError, QSocket, MAX_MESSAGE, PROTOCOL_VERSION},
     util::{self, QString},
 };
 
+use bitflags::bitflags;
 use byteorder::{LittleEndian, NetworkEndian, ReadBytesExt, WriteBytesExt};
 use chrono::Duration;
 use num::FromPrimitive;
 use num_derive::FromPrimitive;
 
 pub const CONNECT_PROTOCOL_VERSION: u8 = 3;
+
+/// The port servers listen for connections and queries on.
+pub const DEFAULT_PORT: u16 = 26000;
 const CONNECT_CONTROL: i32 = 1 << 31;
 const CONNECT_LENGTH_MASK: i32 = 0x0000FFFF;
 
@@ -81,6 +85,16 @@ pub trait ConnectPacket {
     }
 }
 
+bitflags! {
+    /// Optional features of a connection. The client offers those it supports in its request,
+    /// and the server answers with those it will use.
+    #[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+    pub struct ConnectFlags: u8 {
+        /// Reliable messages are compressed, see [`compress`](super::compress).
+        const COMPRESS = 1 << 0;
+    }
+}
+
 #[derive(Debug, FromPrimitive)]
 pub enum RequestCode {
     Connect = 1,
@@ -93,6 +107,10 @@ pub enum RequestCode {
 pub struct RequestConnect {
     pub game_name: String,
     pub proto_ver: u8,
+    /// The game protocols the client can speak, most preferred first. Clients that predate
+    /// negotiation send none, and only speak protocol 15.
+    pub protocols: Vec<i32>,
+    pub flags: ConnectFlags,
 }
 
 impl ConnectPacket for RequestConnect {
@@ -109,6 +127,16 @@ impl ConnectPacket for RequestConnect {
         // protocol version
         len += size_of::<u8>();
 
+        // count of offered game protocols, followed by the protocols
+        if !self.protocols.is_empty() || !self.flags.is_empty() {
+            len += size_of::<u8>() + self.protocols.len() * size_of::<i32>();
+        }
+
+        // supported features
+        if !self.flags.is_empty() {
+            len += size_of::<u8>();
+        }
+
         len
     }
 
@@ -119,6 +147,22 @@ impl ConnectPacket for RequestConnect {
         writer.write_all(self.game_name.as_bytes())?;
         writer.write_u8(0)?;
         writer.write_u8(self.proto_ver)?;
+
+        // the original engine ignores anything after the version, so this is left off when
+        // there's nothing to offer
+        if !self.protocols.is_empty() || !self.flags.is_empty() {
+            let count = u8::try_from(self.protocols.len())
+                .map_err(|_| NetError::with_msg("Too many protocols to offer"))?;
+            writer.write_u8(count)?;
+            for protocol in &self.protocols {
+                writer.write_i32::<LittleEndian>(*protocol)?;
+            }
+        }
+
+        if !self.flags.is_empty() {
+            writer.write_u8(self.flags.bits())?;
+        }
+
         Ok(())
     }
 }
@@ -207,13 +251,20 @@ pub enum Request {
 }
 
 impl Request {
-    pub fn connect<S>(game_name: S, proto_ver: u8) -> Request
+    pub fn connect<S>(
+        game_name: S,
+        proto_ver: u8,
+        protocols: &[i32],
+        flags: ConnectFlags,
+    ) -> Request
     where
         S: AsRef<str>,
     {
         Request::Connect(RequestConnect {
             game_name: game_name.as_ref().to_owned(),
             proto_ver,
+            protocols: protocols.to_vec(),
+            flags,
         })
     }
 
@@ -238,6 +289,93 @@ impl Request {
             prev_cvar: prev_cvar.as_ref().to_string(),
         })
     }
+
+    /// Reads a request from a whole packet.
+    pub fn from_bytes(packet: &[u8]) -> Result<Request, NetError> {
+        let mut reader = BufReader::new(packet);
+
+        let control = reader.read_i32::<NetworkEndian>()?;
+
+        // TODO: figure out what a control value of -1 means
+        if control == -1 {
+            return Err(NetError::with_msg("Control value is -1"));
+        }
+
+        // high 4 bits must be 0x8000 (CONNECT_CONTROL)
+        if control & !CONNECT_LENGTH_MASK != CONNECT_CONTROL {
+            return Err(NetError::invalid_data(format!(
+                "control value {:X}",
+                control & !CONNECT_LENGTH_MASK
+            )));
+        }
+
+        // low 4 bits must be total length of packet
+        let control_len = (control & CONNECT_LENGTH_MASK) as usize;
+        if control_len != packet.len() {
+            return Err(NetError::invalid_data(format!(
+                "Actual packet length ({}) differs from header value ({})",
+                packet.len(),
+                control_len,
+            )));
+        }
+
+        // validate request code
+        let request_byte = reader.read_u8()?;
+        let request_code = match RequestCode::from_u8(request_byte) {
+            Some(r) => r,
+            None => {
+                return Err(NetError::invalid_data(format!(
+                    "request code {}",
+                    request_byte
+                )))
+            }
+        };
+
+        let request = match request_code {
+            RequestCode::Connect => {
+                let game_name = util::read_cstring(&mut reader)?.into_string();
+                let proto_ver = reader.read_u8()?;
+
+                let mut protocols = Vec::new();
+                if reader.has_data_left()? {
+                    let count = reader.read_u8()?;
+                    for _ in 0..count {
+                        protocols.push(reader.read_i32::<LittleEndian>()?);
+                    }
+                }
+
+                let flags = if reader.has_data_left()? {
+                    ConnectFlags::from_bits_truncate(reader.read_u8()?)
+                } else {
+                    ConnectFlags::empty()
+                };
+
+                Request::Connect(RequestConnect {
+                    game_name,
+                    proto_ver,
+                    protocols,
+                    flags,
+                })
+            }
+
+            RequestCode::ServerInfo => {
+                let game_name = util::read_cstring(&mut reader)?.into_string();
+                Request::ServerInfo(RequestServerInfo { game_name })
+            }
+
+            RequestCode::PlayerInfo => {
+                let player_id = reader.read_u8()?;
+                Request::PlayerInfo(RequestPlayerInfo { player_id })
+            }
+
+            RequestCode::RuleInfo => {
+                let prev_cvar = util::read_cstring(&mut reader)?.into_string();
+                Request::RuleInfo(RequestRuleInfo { prev_cvar })
+            }
+        };
+
+        Ok(request)
+    }
 }
 
 impl ConnectPacket for Request {
@@ -287,6 +425,11 @@ pub enum ResponseCode {
 #[derive(Debug)]
 pub struct ResponseAccept {
     pub port: i32,
+    /// The game protocol the server chose from those the client offered. Servers that predate
+    /// negotiation don't send this.
+    pub protocol: Option<i32>,
+    /// The features the server chose from those the client offered.
+    pub flags: ConnectFlags,
 }
 
 impl ConnectPacket for ResponseAccept {
@@ -295,8 +438,22 @@ impl ConnectPacket for ResponseAccept {
     }
 
     fn content_len(&self) -> usize {
+        let mut len = 0;
+
         // port number
-        size_of::<i32>()
+        len += size_of::<i32>();
+
+        // chosen game protocol
+        if self.protocol.is_some() || !self.flags.is_empty() {
+            len += size_of::<i32>();
+        }
+
+        // chosen features
+        if !self.flags.is_empty() {
+            len += size_of::<u8>();
+        }
+
+        len
     }
 
     fn write_content<W>(&self, writer: &mut W) -> Result<(), NetError>
@@ -304,6 +461,17 @@ impl ConnectPacket for ResponseAccept {
         W: WriteBytesExt,
     {
         writer.write_i32::<LittleEndian>(self.port)?;
+
+        // the flags come after the protocol, so it has to be sent for them even if it wasn't
+        // negotiated
+        if self.protocol.is_some() || !self.flags.is_empty() {
+            let protocol = self.protocol.unwrap_or(PROTOCOL_VERSION as i32);
+            writer.write_i32::<LittleEndian>(protocol)?;
+        }
+        if !self.flags.is_empty() {
+            writer.write_u8(self.flags.bits())?;
+        }
+
         Ok(())
     }
 }
@@ -488,6 +656,131 @@ pub enum Response {
     RuleInfo(ResponseRuleInfo),
 }
 
+impl Response {
+    /// Reads a response from a whole packet.
+    pub fn from_bytes(packet: &[u8]) -> Result<Response, NetError> {
+        let mut reader = BufReader::new(packet);
+
+        let control = reader.read_i32::<NetworkEndian>()?;
+
+        // TODO: figure out what a control value of -1 means
+        if control == -1 {
+            return Err(NetError::with_msg("Control value is -1"));
+        }
+
+        // high 4 bits must be 0x8000 (CONNECT_CONTROL)
+        if control & !CONNECT_LENGTH_MASK != CONNECT_CONTROL {
+            return Err(NetError::invalid_data(format!(
+                "control value {:X}",
+                control & !CONNECT_LENGTH_MASK
+            )));
+        }
+
+        // low 4 bits must be total length of packet
+        let control_len = (control & CONNECT_LENGTH_MASK) as usize;
+        if control_len != packet.len() {
+            return Err(NetError::with_msg(format!(
+                "Actual packet length ({}) differs from header value ({})",
+                packet.len(),
+                control_len,
+            )));
+        }
+
+        let response_byte = reader.read_u8()?;
+        let response_code = match ResponseCode::from_u8(response_byte) {
+            Some(r) => r,
+            None => {
+                return Err(NetError::invalid_data(format!(
+                    "response code {}",
+                    response_byte
+                )))
+            }
+        };
+
+        let response = match response_code {
+            ResponseCode::Accept => {
+                let port = reader.read_i32::<LittleEndian>()?;
+                let protocol = if reader.has_data_left()? {
+                    Some(reader.read_i32::<LittleEndian>()?)
+                } else {
+                    None
+                };
+                let flags = if reader.has_data_left()? {
+                    ConnectFlags::from_bits_truncate(reader.read_u8()?)
+                } else {
+                    ConnectFlags::empty()
+                };
+
+                Response::Accept(ResponseAccept {
+                    port,
+                    protocol,
+                    flags,
+                })
+            }
+
+            ResponseCode::Reject => {
+                let message = util::read_cstring(&mut reader)?;
+                Response::Reject(ResponseReject { message })
+            }
+
+            ResponseCode::ServerInfo => {
+                let address = util::read_cstring(&mut reader)?.into_string();
+                let hostname = util::read_cstring(&mut reader)?.into_string();
+                let levelname = util::read_cstring(&mut reader)?.into_string();
+                let client_count = reader.read_u8()?;
+                let client_max = reader.read_u8()?;
+                let protocol_version = reader.read_u8()?;
+
+                Response::ServerInfo(ResponseServerInfo {
+                    address,
+                    hostname,
+                    levelname,
+                    client_count,
+                    client_max,
+                    protocol_version,
+                })
+            }
+
+            ResponseCode::PlayerInfo => {
+                let player_id = reader.read_u8()?;
+                let player_name = util::read_cstring(&mut reader)?.into_string();
+                let colors = reader.read_i32::<LittleEndian>()?;
+                let frags = reader.read_i32::<LittleEndian>()?;
+                let connect_duration = reader.read_i32::<LittleEndian>()?;
+                let address = util::read_cstring(&mut reader)?.into_string();
+
+                Response::PlayerInfo(ResponsePlayerInfo {
+                    player_id,
+                    player_name,
+                    colors,
+                    frags,
+                    connect_duration,
+                    address,
+                })
+            }
+
+            ResponseCode::RuleInfo => {
+                // the original server answers a request past the last cvar with an empty reply
+                let (cvar_name, cvar_val) = if reader.has_data_left()? {
+                    (
+                        util::read_cstring(&mut reader)?.into_string(),
+                        util::read_cstring(&mut reader)?.into_string(),
+                    )
+                } else {
+                    (String::new(), String::new())
+                };
+
+                Response::RuleInfo(ResponseRuleInfo {
+                    cvar_name,
+                    cvar_val,
+                })
+            }
+        };
+
+        Ok(response)
+    }
+}
+
 impl ConnectPacket for Response {
     fn code(&self) -> u8 {
         use self::Response::*;
@@ -542,77 +835,39 @@ impl ConnectListener {
         Ok(ConnectListener { socket })
     }
 
+    /// The address the listener is bound to, including the port chosen by the OS for port 0.
+    pub fn local_addr(&self) -> Result<SocketAddr, NetError> {
+        Ok(self.socket.local_addr()?)
+    }
+
     /// Receives a request and returns it along with its remote address.
     pub fn recv_request(&self) -> Result<(Request, SocketAddr), NetError> {
         // Original engine receives connection requests in `net_message`,
         // allocated at https://github.com/id-Software/Quake/blob/master/WinQuake/net_main.c#L851
         let mut recv_buf = [0u8; MAX_MESSAGE];
         let (len, remote) = self.socket.recv_from(&mut recv_buf)?;
-        let mut reader = BufReader::new(&recv_buf[..len]);
-
-        let control = reader.read_i32::<NetworkEndian>()?;
-
-        // TODO: figure out what a control value of -1 means
-        if control == -1 {
-            return Err(NetError::with_msg("Control value is -1"));
-        }
-
-        // high 4 bits must be 0x8000 (CONNECT_CONTROL)
-        if control & !CONNECT_LENGTH_MASK != CONNECT_CONTROL {
-            return Err(NetError::invalid_data(format!(
-                "control value {:X}",
-                control & !CONNECT_LENGTH_MASK
-            )));
-        }
+        let request = Request::from_bytes(&recv_buf[..len])?;
 
-        // low 4 bits must be total length of packet
-        let control_len = (control & CONNECT_LENGTH_MASK) as usize;
-        if control_len != len {
-            return Err(NetError::invalid_data(format!(
-                "Actual packet length ({}) differs from header value ({})",
-                len, control_len,
-            )));
-        }
-
-        // validate request code
-        let request_byte = reader.read_u8()?;
-        let request_code = match RequestCode::from_u8(request_byte) {
-            Some(r) => r,
-            None => {
-                return Err(NetError::invalid_data(format!(
-                    "request code {}",
-                    request_byte
-                )))
-            }
-        };
-
-        let request = match request_code {
-            RequestCode::Connect => {
-                let game_name = util::read_cstring(&mut reader)?.into_string();
-                let proto_ver = reader.read_u8()?;
-                Request::Connect(RequestConnect {
-                    game_name,
-                    proto_ver,
-                })
-            }
+        Ok((request, remote))
+    }
 
-            RequestCode::ServerInfo => {
-                let game_name = util::read_cstring(&mut reader)?.into_string();
-                Request::ServerInfo(RequestServerInfo { game_name })
-            }
+    /// In nonblocking mode, `recv_request` fails with `WouldBlock` at once if nothing has
+    /// arrived. [`ConnectListener::try_recv_request`] returns `None` instead.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<(), NetError> {
+        self.socket.set_nonblocking(nonblocking)?;
+        Ok(())
+    }
 
-            RequestCode::PlayerInfo => {
-                let player_id = reader.read_u8()?;
-                Request::PlayerInfo(RequestPlayerInfo { player_id })
+    /// Receives a request if one has arrived on a nonblocking listener.
+    pub fn try_recv_request(&self) -> Result<Option<(Request, SocketAddr)>, NetError> {
+        match self.recv_request() {
+            Err(NetError::Io { source, .. })
+                if matches!(source.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+            {
+                Ok(None)
             }
-
-            RequestCode::RuleInfo => {
-                let prev_cvar = util::read_cstring(&mut reader)?.into_string();
-                Request::RuleInfo(RequestRuleInfo { prev_cvar })
-            }
-        };
-
-        Ok((request, remote))
+            result => result.map(Some),
+        }
     }
 
     pub fn send_response(&self, response: Response, remote: SocketAddr) -> Result<(), NetError> {
@@ -635,6 +890,18 @@ impl ConnectSocket {
         Ok(ConnectSocket { socket })
     }
 
+    /// Allows requests to be sent to a broadcast address, to find servers on the LAN.
+    pub fn set_broadcast(&self, broadcast: bool) -> Result<(), NetError> {
+        self.socket.set_broadcast(broadcast)?;
+        Ok(())
+    }
+
+    /// In nonblocking mode, `recv_response` returns `None` at once if nothing has arrived.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<(), NetError> {
+        self.socket.set_nonblocking(nonblocking)?;
+        Ok(())
+    }
+
     pub fn into_qsocket(self, remote: SocketAddr) -> QSocket {
         QSocket::new(self.socket, remote)
     }
@@ -657,7 +924,7 @@ impl ConnectSocket {
 
         // if a timeout was specified, apply it for this recv
         self.socket
-            .set_read_timeout(timeout.map(|d| d.to_std().unwrap()))?;
+            .set_read_timeout(timeout.map(std_timeout).transpose()?)?;
         let (len, remote) = match self.socket.recv_from(&mut recv_buf) {
             Err(e) => match e.kind() {
                 ErrorKind::WouldBlock | ErrorKind::TimedOut => return Ok(None),
@@ -667,75 +934,7 @@ impl ConnectSocket {
         };
         self.socket.set_read_timeout(None)?;
 
-        let mut reader = BufReader::new(&recv_buf[..len]);
-
-        let control = reader.read_i32::<NetworkEndian>()?;
-
-        // TODO: figure out what a control value of -1 means
-        if control == -1 {
-            return Err(NetError::with_msg("Control value is -1"));
-        }
-
-        // high 4 bits must be 0x8000 (CONNECT_CONTROL)
-        if control & !CONNECT_LENGTH_MASK != CONNECT_CONTROL {
-            return Err(NetError::invalid_data(format!(
-                "control value {:X}",
-                control & !CONNECT_LENGTH_MASK
-            )));
-        }
-
-        // low 4 bits must be total length of packet
-        let control_len = (control & CONNECT_LENGTH_MASK) as usize;
-        if control_len != len {
-            return Err(NetError::with_msg(format!(
-                "Actual packet length ({}) differs from header value ({})",
-                len, control_len,
-            )));
-        }
-
-        let response_byte = reader.read_u8()?;
-        let response_code = match ResponseCode::from_u8(response_byte) {
-            Some(r) => r,
-            None => {
-                return Err(NetError::invalid_data(format!(
-                    "response code {}",
-                    response_byte
-                )))
-            }
-        };
-
-        let response = match response_code {
-            ResponseCode::Accept => {
-                let port = reader.read_i32::<LittleEndian>()?;
-                Response::Accept(ResponseAccept { port })
-            }
-
-            ResponseCode::Reject => {
-                let message = util::read_cstring(&mut reader)?;
-                Response::Reject(ResponseReject { message })
-            }
-
-            ResponseCode::ServerInfo => {
-                let address = util::read_cstring(&mut reader)?.into_string();
-                let hostname = util::read_cstring(&mut reader)?.into_string();
-                let levelname = util::read_cstring(&mut reader)?.into_string();
-                let client_count = reader.read_u8()?;
-                let client_max = reader.read_u8()?;
-                let protocol_version = reader.read_u8()?;
-
-                Response::ServerInfo(ResponseServerInfo {
-                    address,
-                    hostname,
-                    levelname,
-                    client_count,
-                    client_max,
-                    protocol_version,
-                })
-            }
-
-            ResponseCode::PlayerInfo => unimplemented!(),
-            ResponseCode::RuleInfo => unimplemented!(),
-        };
+        let response = Response::from_bytes(&recv_buf[..len])?;
 
         Ok(Some((response, remote)))
     }
@@ -754,6 +953,8 @@ mod test {
         let request_connect = RequestConnect {
             game_name: String::from("QUAKE"),
             proto_ver: CONNECT_PROTOCOL_VERSION,
+            protocols: vec![999, 666, 15],
+            flags: ConnectFlags::COMPRESS,
         };
 
         let packet_len = request_connect.packet_len() as usize;
@@ -791,7 +992,11 @@ mod test {
 
     #[test]
     fn test_response_accept_packet_len() {
-        let response_accept = ResponseAccept { port: 26000 };
+        let response_accept = ResponseAccept {
+            port: 26000,
+            protocol: Some(666),
+            flags: ConnectFlags::COMPRESS,
+        };
         let packet_len = response_accept.packet_len() as usize;
         let packet = response_accept.to_bytes().unwrap();
         assert_eq!(packet_len, packet.len());
@@ -837,6 +1042,159 @@ mod test {
         assert_eq!(packet_len, packet.len());
     }
 
+    #[test]
+    fn test_response_player_info_round_trip() {
+        let packet = ResponsePlayerInfo {
+            player_id: 3,
+            player_name: String::from("player"),
+            colors: 0x4d,
+            frags: -2,
+            connect_duration: 120,
+            address: String::from("127.0.0.1:26001"),
+        }
+        .to_bytes()
+        .unwrap();
+
+        let Response::PlayerInfo(info) = Response::from_bytes(&packet).unwrap() else {
+            panic!("Expected a player info response");
+        };
+        assert_eq!(info.player_id, 3);
+        assert_eq!(info.player_name, "player");
+        assert_eq!(info.colors, 0x4d);
+        assert_eq!(info.frags, -2);
+        assert_eq!(info.connect_duration, 120);
+        assert_eq!(info.address, "127.0.0.1:26001");
+
+        // a truncated reply is an error rather than a panic
+        let mut truncated = packet[..packet.len() - 4].to_vec();
+        let control = CONNECT_CONTROL | truncated.len() as i32;
+        truncated[..4].copy_from_slice(&control.to_be_bytes());
+        assert!(Response::from_bytes(&truncated).is_err());
+    }
+
+    #[test]
+    fn test_response_rule_info_round_trip() {
+        let packet = ResponseRuleInfo {
+            cvar_name: String::from("sv_gravity"),
+            cvar_val: String::from("800"),
+        }
+        .to_bytes()
+        .unwrap();
+
+        let Response::RuleInfo(rule) = Response::from_bytes(&packet).unwrap() else {
+            panic!("Expected a rule info response");
+        };
+        assert_eq!(rule.cvar_name, "sv_gravity");
+        assert_eq!(rule.cvar_val, "800");
+
+        // the reply after the last rule has no content
+        let control = CONNECT_CONTROL | 5;
+        let mut end = control.to_be_bytes().to_vec();
+        end.push(ResponseCode::RuleInfo as u8);
+        let Response::RuleInfo(rule) = Response::from_bytes(&end).unwrap() else {
+            panic!("Expected a rule info response");
+        };
+        assert!(rule.cvar_name.is_empty());
+    }
+
+    #[test]
+    fn test_connect_negotiation_round_trip() {
+        let listener = ConnectListener::bind("127.0.0.1:0").unwrap();
+        let server_addr = listener.socket.local_addr().unwrap();
+        let mut socket = ConnectSocket::bind("127.0.0.1:0").unwrap();
+
+        socket
+            .send_request(
+                Request::connect(
+                    "QUAKE",
+                    CONNECT_PROTOCOL_VERSION,
+                    &[999, 666, 15],
+                    ConnectFlags::COMPRESS,
+                ),
+                server_addr,
+            )
+            .unwrap();
+        let (request, client_addr) = listener.recv_request().unwrap();
+        let Request::Connect(connect) = request else {
+            panic!("Expected a connect request, got {:?}", request);
+        };
+        assert_eq!(connect.protocols, vec![999, 666, 15]);
+        assert_eq!(connect.flags, ConnectFlags::COMPRESS);
+
+        listener
+            .send_response(
+                Response::Accept(ResponseAccept {
+                    port: 26001,
+                    protocol: Some(666),
+                    flags: ConnectFlags::COMPRESS,
+                }),
+                client_addr,
+            )
+            .unwrap();
+        let (response, _) = socket
+            .recv_response(Some(Duration::try_seconds(1).unwrap()))
+            .unwrap()
+            .unwrap();
+        let Response::Accept(accept) = response else {
+            panic!("Expected an accept response, got {:?}", response);
+        };
+        assert_eq!(accept.protocol, Some(666));
+        assert_eq!(accept.flags, ConnectFlags::COMPRESS);
+
+        // requests from the original engine stop after the version
+        socket
+            .send_request(
+                Request::connect("QUAKE", 3, &[], ConnectFlags::empty()),
+                server_addr,
+            )
+            .unwrap();
+        let (Request::Connect(connect), _) = listener.recv_request().unwrap() else {
+            panic!("Expected a connect request");
+        };
+        assert!(connect.protocols.is_empty());
+        assert!(connect.flags.is_empty());
+    }
+
+    #[test]
+    fn test_connect_flags_without_protocols() {
+        let listener = ConnectListener::bind("127.0.0.1:0").unwrap();
+        let server_addr = listener.socket.local_addr().unwrap();
+        let mut socket = ConnectSocket::bind("127.0.0.1:0").unwrap();
+
+        socket
+            .send_request(
+                Request::connect("QUAKE", 3, &[], ConnectFlags::COMPRESS),
+                server_addr,
+            )
+            .unwrap();
+        let (Request::Connect(connect), client_addr) = listener.recv_request().unwrap() else {
+            panic!("Expected a connect request");
+        };
+        assert!(connect.protocols.is_empty());
+        assert_eq!(connect.flags, ConnectFlags::COMPRESS);
+
+        // the protocol has to be sent ahead of the flags
+        listener
+            .send_response(
+                Response::Accept(ResponseAccept {
+                    port: 26001,
+                    protocol: None,
+                    flags: ConnectFlags::COMPRESS,
+                }),
+                client_addr,
+            )
+            .unwrap();
+        let (Response::Accept(accept), _) = socket
+            .recv_response(Some(Duration::try_seconds(1).unwrap()))
+            .unwrap()
+            .unwrap()
+        else {
+            panic!("Expected an accept response");
+        };
+        assert_eq!(accept.protocol, Some(PROTOCOL_VERSION as i32));
+        assert_eq!(accept.flags, ConnectFlags::COMPRESS);
+    }
+
     #[test]
     fn test_connect_listener_bind() {
         let _listener = ConnectListener::bind("127.0.0.1:26000").unwrap();
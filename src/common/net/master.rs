@@ -0,0 +1,110 @@
+//! Queries to master servers, which keep a list of the public servers on the internet.
+//!
+//! This is the protocol of `dpmaster`, shared by DarkPlaces and most other NetQuake engines. The
+//! client asks for servers running a game and receives their addresses in one or more replies,
+//! which it can then query for their details like any server found on the LAN.
+
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+
+use crate::common::net::NetError;
+
+/// The port master servers listen on, if the address doesn't give one.
+pub const DEFAULT_PORT: u16 = 27950;
+
+/// The game name that NetQuake servers register under.
+pub const GAME_NAME: &str = "DarkPlaces-Quake";
+
+/// The protocol version that NetQuake servers register with.
+const PROTOCOL_VERSION: i32 = 3;
+
+/// Starts every packet to or from a master server, in place of a sequence number.
+const OUT_OF_BAND: &[u8] = b"\xff\xff\xff\xff";
+
+const RESPONSE_HEADER: &[u8] = b"\xff\xff\xff\xffgetserversResponse";
+
+/// Marks the end of the list, in the place of an address.
+const END_OF_LIST: &[u8] = b"EOT\0\0\0";
+
+/// Length of an address in a reply: four bytes of IP followed by a big-endian port.
+const ADDR_LEN: usize = 6;
+
+/// The request for the servers of a game, including those which are empty or full.
+pub fn query() -> Vec<u8> {
+    let text = format!("getservers {} {} empty full", GAME_NAME, PROTOCOL_VERSION);
+    [OUT_OF_BAND, text.as_bytes()].concat()
+}
+
+/// Reads the addresses from a master server's reply.
+///
+/// Lists too long for one packet are split across several replies, of which only the last ends
+/// with `EOT`. Returns the addresses and whether this was the last reply.
+pub fn parse_response(packet: &[u8]) -> Result<(Vec<SocketAddr>, bool), NetError> {
+    let Some(mut body) = packet.strip_prefix(RESPONSE_HEADER) else {
+        return Err(NetError::invalid_data("not a master server response"));
+    };
+
+    let mut addrs = Vec::new();
+    while let Some(rest) = body.strip_prefix(b"\\") {
+        if rest.starts_with(END_OF_LIST) {
+            return Ok((addrs, true));
+        }
+
+        if rest.len() < ADDR_LEN {
+            return Err(NetError::invalid_data(format!(
+                "truncated server address ({} bytes)",
+                rest.len()
+            )));
+        }
+
+        let (addr, tail) = rest.split_at(ADDR_LEN);
+        let ip = Ipv4Addr::new(addr[0], addr[1], addr[2], addr[3]);
+        let port = u16::from_be_bytes([addr[4], addr[5]]);
+        // masters pad their replies with zeroed entries
+        if !ip.is_unspecified() && port != 0 {
+            addrs.push(SocketAddr::V4(SocketAddrV4::new(ip, port)));
+        }
+
+        body = tail;
+    }
+
+    Ok((addrs, false))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_query() {
+        assert_eq!(
+            query(),
+            b"\xff\xff\xff\xffgetservers DarkPlaces-Quake 3 empty full"
+        );
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let mut packet = RESPONSE_HEADER.to_vec();
+        packet.extend(b"\\\x0a\x00\x00\x01\x65\x90");
+        packet.extend(b"\\\x00\x00\x00\x00\x00\x00");
+        packet.extend(b"\\\xc0\xa8\x01\x02\x65\x91");
+
+        let (addrs, done) = parse_response(&packet).unwrap();
+        assert_eq!(
+            addrs,
+            vec![
+                "10.0.0.1:26000".parse::<SocketAddr>().unwrap(),
+                "192.168.1.2:26001".parse().unwrap(),
+            ]
+        );
+        assert!(!done);
+
+        packet.extend(b"\\EOT\0\0\0");
+        let (addrs, done) = parse_response(&packet).unwrap();
+        assert_eq!(addrs.len(), 2);
+        assert!(done);
+
+        assert!(parse_response(b"\xff\xff\xff\xffgetserversResponse\\\x0a\x00").is_err());
+        assert!(parse_response(b"\xff\xff\xff\xffprint\nhello").is_err());
+    }
+}
@@ -18,9 +18,16 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-// TODO: need to figure out an equivalence relation for read_/write_coord and read_/write_angle
-
+pub mod background;
+pub mod compress;
 pub mod connect;
+#[cfg(test)]
+mod fuzz;
+pub mod master;
+pub mod transport;
+pub mod userinfo;
+#[cfg(any(feature = "websocket", target_arch = "wasm32"))]
+pub mod websocket;
 
 use std::{
     collections::VecDeque,
@@ -33,6 +40,8 @@ use std::{
 
 use crate::common::{engine, util};
 
+use self::transport::{Transport, UdpTransport};
+
 use bevy::prelude::*;
 use bitflags::bitflags;
 use byteorder::{LittleEndian, NetworkEndian, ReadBytesExt, WriteBytesExt};
@@ -51,10 +60,33 @@ const MAX_PACKET: usize = HEADER_SIZE + MAX_DATAGRAM;
 
 pub const PROTOCOL_VERSION: u8 = 15;
 
+/// FitzQuake's protocol, which raises the limits on model, frame and sound indices.
+pub const PROTOCOL_FITZQUAKE: i32 = 666;
+
+/// RMQ's protocol, which adds the coordinate and angle encodings in `ProtocolFlags` to
+/// FitzQuake's.
+pub const PROTOCOL_RMQ: i32 = 999;
+
+/// The protocols this engine can speak, most preferred first. Clients offer these when they
+/// connect, and the server turns away those that [`Protocol::negotiate`] doesn't settle on the
+/// level's protocol for.
+pub const SUPPORTED_PROTOCOLS: [i32; 3] =
+    [PROTOCOL_RMQ, PROTOCOL_FITZQUAKE, PROTOCOL_VERSION as i32];
+
 const FAST_UPDATE_FLAG: u8 = 0x80;
 
 const VELOCITY_READ_FACTOR: f32 = 16.0;
-const VELOCITY_WRITE_FACTOR: f32 = 1.0 / VELOCITY_READ_FACTOR;
+
+/// Steps per unit of a NetQuake coordinate.
+const COORD_STEPS: f32 = 8.0;
+/// Steps per unit of a coordinate sent with `ProtocolFlags::INT32_COORD`.
+const INT32_COORD_STEPS: f32 = 16.0;
+/// Steps per unit in the fraction of a coordinate sent with `ProtocolFlags::COORD_24BIT`.
+const COORD_24BIT_STEPS: f32 = 255.0;
+/// Steps per turn of a NetQuake angle.
+const ANGLE_STEPS: f32 = 256.0;
+/// Steps per turn of an angle sent with `ProtocolFlags::SHORT_ANGLE`.
+const SHORT_ANGLE_STEPS: f32 = 65536.0;
 
 const PARTICLE_DIRECTION_READ_FACTOR: f32 = 1.0 / 16.0;
 const PARTICLE_DIRECTION_WRITE_FACTOR: f32 = 1.0 / PARTICLE_DIRECTION_READ_FACTOR;
@@ -62,16 +94,41 @@ const PARTICLE_DIRECTION_WRITE_FACTOR: f32 = 1.0 / PARTICLE_DIRECTION_READ_FACTO
 const SOUND_ATTENUATION_WRITE_FACTOR: u8 = 64;
 const SOUND_ATTENUATION_READ_FACTOR: f32 = 1.0 / SOUND_ATTENUATION_WRITE_FACTOR as f32;
 
+/// The most bytes of a file the server sends in one [`ServerCmd::Download`].
+pub const MAX_DOWNLOAD_CHUNK: usize = 1024;
+
+/// A print that starts with this byte is a chat message from another player.
+pub const CHAT_MARKER: u8 = 1;
+
 pub static GAME_NAME: &str = "QUAKE";
 pub const MAX_CLIENTS: usize = 16;
 pub const MAX_ITEMS: usize = 32;
 
 pub const DEFAULT_VIEWHEIGHT: f32 = 22.0;
 
+/// Identifies a client of a server by the slot it occupies, which is also its player number on
+/// the scoreboard.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ClientId(pub usize);
+
+impl ClientId {
+    /// The client running in the same process as the server.
+    pub const LOCAL: ClientId = ClientId(0);
+}
+
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 #[derive(Event)]
 pub struct ServerMessage {
-    pub client_id: usize,
+    pub client_id: ClientId,
     pub packet: Vec<u8>,
+    /// How the message is sent to a client on the network. Entity updates are sent unreliably,
+    /// since each is replaced by the next, and reliable messages are sent one at a time.
+    pub kind: MessageKind,
 }
 
 #[derive(PartialEq, Eq, Copy, Clone, Hash, Default)]
@@ -83,7 +140,7 @@ pub enum MessageKind {
 
 #[derive(Event, Default, Clone)]
 pub struct ClientMessage {
-    pub client_id: usize,
+    pub client_id: ClientId,
     pub packet: Vec<u8>,
     pub kind: MessageKind,
 }
@@ -123,6 +180,302 @@ impl NetError {
     }
 }
 
+bitflags! {
+    /// Encodings chosen by the server under protocol 999, sent after the version in `ServerInfo`.
+    #[derive(Copy, Clone, Debug, Default, Eq, Hash, PartialEq)]
+    pub struct ProtocolFlags: u32 {
+        const SHORT_ANGLE = 1 << 1;
+        const FLOAT_ANGLE = 1 << 2;
+        const COORD_24BIT = 1 << 3;
+        const FLOAT_COORD = 1 << 4;
+        const EDICT_SCALE = 1 << 5;
+        const ALPHA_SANITY = 1 << 6;
+        const INT32_COORD = 1 << 7;
+        const MORE_FLAGS = 1 << 31;
+    }
+}
+
+/// The protocol a server uses for a level, which decides how coordinates, angles and indices are
+/// written.
+///
+/// Every message sent during the level uses the same protocol, which the server announces in
+/// `ServerInfo`.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Protocol {
+    version: i32,
+    flags: ProtocolFlags,
+}
+
+impl Default for Protocol {
+    fn default() -> Self {
+        Protocol::NETQUAKE
+    }
+}
+
+impl Protocol {
+    pub const NETQUAKE: Protocol = Protocol {
+        version: PROTOCOL_VERSION as i32,
+        flags: ProtocolFlags::empty(),
+    };
+
+    pub const FITZQUAKE: Protocol = Protocol {
+        version: PROTOCOL_FITZQUAKE,
+        flags: ProtocolFlags::empty(),
+    };
+
+    /// Protocol 999 with the encodings QuakeSpasm servers use, which cover any map size.
+    pub const RMQ: Protocol = Protocol {
+        version: PROTOCOL_RMQ,
+        flags: ProtocolFlags::INT32_COORD.union(ProtocolFlags::SHORT_ANGLE),
+    };
+
+    /// Returns the protocol with the given version. `flags` are only used by protocol 999, and
+    /// may choose at most one coordinate and one angle encoding.
+    pub fn new(version: i32, flags: ProtocolFlags) -> Result<Protocol, NetError> {
+        match version {
+            PROTOCOL_RMQ => {
+                let coords = flags
+                    & (ProtocolFlags::COORD_24BIT
+                        | ProtocolFlags::FLOAT_COORD
+                        | ProtocolFlags::INT32_COORD);
+                let angles = flags & (ProtocolFlags::SHORT_ANGLE | ProtocolFlags::FLOAT_ANGLE);
+                if coords.bits().count_ones() > 1 || angles.bits().count_ones() > 1 {
+                    return Err(NetError::invalid_data(format!(
+                        "Conflicting protocol flags: {:?}",
+                        flags
+                    )));
+                }
+
+                Ok(Protocol { version, flags })
+            }
+            PROTOCOL_FITZQUAKE => Ok(Protocol::FITZQUAKE),
+            v if v == PROTOCOL_VERSION as i32 => Ok(Protocol::NETQUAKE),
+            _ => Err(NetError::invalid_data(format!(
+                "Unsupported protocol {}",
+                version
+            ))),
+        }
+    }
+
+    /// Chooses the protocol for a client that offered the versions in `offered`.
+    ///
+    /// This is the server's `preferred` protocol if the client speaks it, and otherwise the first
+    /// of the client's choices that this engine supports. Clients which don't offer anything
+    /// predate negotiation, and only speak protocol 15.
+    pub fn negotiate(preferred: Protocol, offered: &[i32]) -> Option<Protocol> {
+        if offered.is_empty() {
+            return Some(Protocol::NETQUAKE);
+        }
+
+        if offered.contains(&preferred.version) {
+            return Some(preferred);
+        }
+
+        offered.iter().find_map(|&version| match version {
+            PROTOCOL_RMQ => Some(Protocol::RMQ),
+            version => Protocol::new(version, ProtocolFlags::empty()).ok(),
+        })
+    }
+
+    pub fn version(&self) -> i32 {
+        self.version
+    }
+
+    pub fn flags(&self) -> ProtocolFlags {
+        self.flags
+    }
+
+    /// Whether FitzQuake's larger indices and extra entity fields can be sent.
+    pub fn is_extended(&self) -> bool {
+        self.version != PROTOCOL_VERSION as i32
+    }
+
+    /// Whether `index` can be sent as a model, frame or sound index.
+    pub fn supports_index(&self, index: usize) -> bool {
+        self.is_extended() || index <= u8::MAX as usize
+    }
+
+    /// Returns an error if `index` is too large to send with this protocol.
+    fn check_index(&self, index: u16, what: &str) -> io::Result<()> {
+        if !self.supports_index(index as usize) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} {} needs protocol {}", what, index, PROTOCOL_FITZQUAKE),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// The distance between neighbouring coordinates that can be sent, or 0 if they are sent as
+    /// floats.
+    pub fn coord_precision(&self) -> f32 {
+        if self.flags.contains(ProtocolFlags::FLOAT_COORD) {
+            0.0
+        } else if self.flags.contains(ProtocolFlags::INT32_COORD) {
+            1.0 / INT32_COORD_STEPS
+        } else if self.flags.contains(ProtocolFlags::COORD_24BIT) {
+            1.0 / COORD_24BIT_STEPS
+        } else {
+            1.0 / COORD_STEPS
+        }
+    }
+
+    /// The distance between neighbouring angles that can be sent, or 0 if they are sent as
+    /// floats.
+    pub fn angle_precision(&self) -> Deg<f32> {
+        if self.flags.contains(ProtocolFlags::FLOAT_ANGLE) {
+            Deg(0.0)
+        } else if self.flags.contains(ProtocolFlags::SHORT_ANGLE) {
+            Deg(360.0 / SHORT_ANGLE_STEPS)
+        } else {
+            Deg(360.0 / ANGLE_STEPS)
+        }
+    }
+
+    /// Returns the coordinate that a client reads when `coord` is sent with this protocol.
+    pub fn quantize_coord(&self, coord: f32) -> f32 {
+        if self.flags.contains(ProtocolFlags::FLOAT_COORD) {
+            coord
+        } else if self.flags.contains(ProtocolFlags::INT32_COORD) {
+            decode_coord_i32(encode_coord_i32(coord))
+        } else if self.flags.contains(ProtocolFlags::COORD_24BIT) {
+            let (whole, frac) = encode_coord_24bit(coord);
+            decode_coord_24bit(whole, frac)
+        } else {
+            decode_coord(encode_coord(coord))
+        }
+    }
+
+    pub fn quantize_coord_vector3(&self, coords: Vector3<f32>) -> Vector3<f32> {
+        coords.map(|c| self.quantize_coord(c))
+    }
+
+    /// Returns the angle that a client reads when `angle` is sent with this protocol. Angles are
+    /// read back between -180 and 180 degrees unless they are sent as floats.
+    pub fn quantize_angle(&self, angle: Deg<f32>) -> Deg<f32> {
+        if self.flags.contains(ProtocolFlags::FLOAT_ANGLE) {
+            angle
+        } else if self.flags.contains(ProtocolFlags::SHORT_ANGLE) {
+            decode_angle_i16(encode_angle_i16(angle))
+        } else {
+            decode_angle(encode_angle(angle))
+        }
+    }
+
+    pub fn quantize_angle_vector3(&self, angles: Vector3<Deg<f32>>) -> Vector3<Deg<f32>> {
+        angles.map(|a| self.quantize_angle(a))
+    }
+
+    pub fn read_coord<R>(&self, reader: &mut R) -> io::Result<f32>
+    where
+        R: Read,
+    {
+        if self.flags.contains(ProtocolFlags::FLOAT_COORD) {
+            reader.read_f32::<LittleEndian>()
+        } else if self.flags.contains(ProtocolFlags::INT32_COORD) {
+            Ok(decode_coord_i32(reader.read_i32::<LittleEndian>()?))
+        } else if self.flags.contains(ProtocolFlags::COORD_24BIT) {
+            let whole = reader.read_i16::<LittleEndian>()?;
+            let frac = reader.read_u8()?;
+            Ok(decode_coord_24bit(whole, frac))
+        } else {
+            read_coord(reader)
+        }
+    }
+
+    pub fn read_coord_vector3<R>(&self, reader: &mut R) -> io::Result<Vector3<f32>>
+    where
+        R: Read,
+    {
+        Ok(Vector3::new(
+            self.read_coord(reader)?,
+            self.read_coord(reader)?,
+            self.read_coord(reader)?,
+        ))
+    }
+
+    pub fn write_coord<W>(&self, writer: &mut W, coord: f32) -> io::Result<()>
+    where
+        W: Write,
+    {
+        if self.flags.contains(ProtocolFlags::FLOAT_COORD) {
+            writer.write_f32::<LittleEndian>(coord)
+        } else if self.flags.contains(ProtocolFlags::INT32_COORD) {
+            writer.write_i32::<LittleEndian>(encode_coord_i32(coord))
+        } else if self.flags.contains(ProtocolFlags::COORD_24BIT) {
+            let (whole, frac) = encode_coord_24bit(coord);
+            writer.write_i16::<LittleEndian>(whole)?;
+            writer.write_u8(frac)
+        } else {
+            write_coord(writer, coord)
+        }
+    }
+
+    pub fn write_coord_vector3<W>(&self, writer: &mut W, coords: Vector3<f32>) -> io::Result<()>
+    where
+        W: Write,
+    {
+        for coord in &coords[..] {
+            self.write_coord(writer, *coord)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read_angle<R>(&self, reader: &mut R) -> io::Result<Deg<f32>>
+    where
+        R: Read,
+    {
+        if self.flags.contains(ProtocolFlags::FLOAT_ANGLE) {
+            Ok(Deg(reader.read_f32::<LittleEndian>()?))
+        } else if self.flags.contains(ProtocolFlags::SHORT_ANGLE) {
+            Ok(decode_angle_i16(reader.read_i16::<LittleEndian>()?))
+        } else {
+            read_angle(reader)
+        }
+    }
+
+    pub fn read_angle_vector3<R>(&self, reader: &mut R) -> io::Result<Vector3<Deg<f32>>>
+    where
+        R: Read,
+    {
+        Ok(Vector3::new(
+            self.read_angle(reader)?,
+            self.read_angle(reader)?,
+            self.read_angle(reader)?,
+        ))
+    }
+
+    pub fn write_angle<W>(&self, writer: &mut W, angle: Deg<f32>) -> io::Result<()>
+    where
+        W: Write,
+    {
+        if self.flags.contains(ProtocolFlags::FLOAT_ANGLE) {
+            writer.write_f32::<LittleEndian>(angle.0)
+        } else if self.flags.contains(ProtocolFlags::SHORT_ANGLE) {
+            writer.write_i16::<LittleEndian>(encode_angle_i16(angle))
+        } else {
+            write_angle(writer, angle)
+        }
+    }
+
+    pub fn write_angle_vector3<W>(
+        &self,
+        writer: &mut W,
+        angles: Vector3<Deg<f32>>,
+    ) -> io::Result<()>
+    where
+        W: Write,
+    {
+        for angle in &angles[..] {
+            self.write_angle(writer, *angle)?;
+        }
+
+        Ok(())
+    }
+}
+
 // the original engine treats these as bitflags, but all of them are mutually exclusive except for
 // NETFLAG_DATA (reliable message) and NETFLAG_EOM (end of reliable message).
 #[derive(Debug, Eq, FromPrimitive, PartialEq)]
@@ -136,7 +489,7 @@ pub enum MsgKind {
 
 bitflags! {
     #[derive(Copy, Clone, Ord, Debug, Eq, PartialOrd, PartialEq)]
-    pub struct UpdateFlags: u16 {
+    pub struct UpdateFlags: u32 {
         const MORE_BITS = 1 << 0;
         const ORIGIN_X = 1 << 1;
         const ORIGIN_Y = 1 << 2;
@@ -152,6 +505,15 @@ bitflags! {
         const SKIN = 1 << 12;
         const EFFECTS = 1 << 13;
         const LONG_ENTITY = 1 << 14;
+
+        // FitzQuake extensions
+        const EXTEND_1 = 1 << 15;
+        const ALPHA = 1 << 16;
+        const FRAME_2 = 1 << 17;
+        const MODEL_2 = 1 << 18;
+        const LERP_FINISH = 1 << 19;
+        const SCALE = 1 << 20;
+        const EXTEND_2 = 1 << 23;
     }
 }
 
@@ -171,14 +533,16 @@ impl UpdateFlags {
                 | Self::COLORMAP
                 | Self::SKIN
                 | Self::EFFECTS
-                | Self::ORIGIN_X))
+                | Self::ORIGIN_X
+                | Self::ALPHA
+                | Self::SCALE))
             .is_empty()
     }
 }
 
 bitflags! {
     #[derive(Copy, Clone, Ord, Debug, Eq, PartialOrd, PartialEq)]
-    pub struct ClientUpdateFlags: u16 {
+    pub struct ClientUpdateFlags: u32 {
         const VIEW_HEIGHT = 1 << 0;
         const IDEAL_PITCH = 1 << 1;
         const PUNCH_PITCH = 1 << 2;
@@ -194,6 +558,19 @@ bitflags! {
         const WEAPON_FRAME = 1 << 12;
         const ARMOR = 1 << 13;
         const WEAPON = 1 << 14;
+
+        // FitzQuake extensions, carrying the high bytes of values which don't fit in one
+        const EXTEND_1 = 1 << 15;
+        const WEAPON_2 = 1 << 16;
+        const ARMOR_2 = 1 << 17;
+        const AMMO_2 = 1 << 18;
+        const SHELLS_2 = 1 << 19;
+        const NAILS_2 = 1 << 20;
+        const ROCKETS_2 = 1 << 21;
+        const CELLS_2 = 1 << 22;
+        const EXTEND_2 = 1 << 23;
+        const WEAPON_FRAME_2 = 1 << 24;
+        const WEAPON_ALPHA = 1 << 25;
     }
 }
 
@@ -203,6 +580,18 @@ bitflags! {
         const VOLUME = 1 << 0;
         const ATTENUATION = 1 << 1;
         const LOOPING = 1 << 2;
+        const LARGE_ENTITY = 1 << 3;
+        const LARGE_SOUND = 1 << 4;
+    }
+}
+
+bitflags! {
+    /// Flags for FitzQuake's `SpawnBaseline2` and `SpawnStatic2`.
+    #[derive(Copy, Clone, Ord, Debug, Eq, PartialOrd, PartialEq)]
+    pub struct BaselineFlags: u8 {
+        const LARGE_MODEL = 1 << 0;
+        const LARGE_FRAME = 1 << 1;
+        const ALPHA = 1 << 2;
     }
 }
 
@@ -284,6 +673,16 @@ impl PlayerColor {
     pub fn bits(&self) -> u8 {
         self.top << 4 | (self.bottom & 0x0F)
     }
+
+    /// The color of the player's shirt.
+    pub fn top(&self) -> u8 {
+        self.top
+    }
+
+    /// The color of the player's pants, which also decides their team.
+    pub fn bottom(&self) -> u8 {
+        self.bottom
+    }
 }
 
 impl ::std::convert::From<u8> for PlayerColor {
@@ -379,7 +778,7 @@ pub enum TempEntity {
 }
 
 impl TempEntity {
-    pub fn read_temp_entity<R>(reader: &mut R) -> Result<TempEntity, NetError>
+    pub fn read_temp_entity<R>(reader: &mut R, protocol: Protocol) -> Result<TempEntity, NetError>
     where
         R: Read,
     {
@@ -419,10 +818,10 @@ impl TempEntity {
                     Code::Teleport => PointEntityKind::Teleport,
                     _ => unreachable!(),
                 },
-                origin: read_coord_vector3(reader)?,
+                origin: protocol.read_coord_vector3(reader)?,
             },
             Code::ColorExplosion => {
-                let origin = read_coord_vector3(reader)?;
+                let origin = protocol.read_coord_vector3(reader)?;
                 let color_start = reader.read_u8()?;
                 let color_len = reader.read_u8()?;
 
@@ -444,19 +843,19 @@ impl TempEntity {
                     },
                 },
                 entity_id: reader.read_i16::<LittleEndian>()?,
-                start: read_coord_vector3(reader)?,
-                end: read_coord_vector3(reader)?,
+                start: protocol.read_coord_vector3(reader)?,
+                end: protocol.read_coord_vector3(reader)?,
             },
             Code::Grapple => Beam {
                 kind: BeamEntityKind::Grapple,
                 entity_id: reader.read_i16::<LittleEndian>()?,
-                start: read_coord_vector3(reader)?,
-                end: read_coord_vector3(reader)?,
+                start: protocol.read_coord_vector3(reader)?,
+                end: protocol.read_coord_vector3(reader)?,
             },
         })
     }
 
-    pub fn write_temp_entity<W>(&self, writer: &mut W) -> Result<(), NetError>
+    pub fn write_temp_entity<W>(&self, writer: &mut W, protocol: Protocol) -> Result<(), NetError>
     where
         W: WriteBytesExt,
     {
@@ -491,18 +890,22 @@ impl TempEntity {
                         // write code
                         writer.write_u8(code as u8)?;
                     }
-                    PointEntityKind::ColorExplosion {
-                        color_start,
-                        color_len,
-                    } => {
-                        // write code and colors
+                    PointEntityKind::ColorExplosion { .. } => {
                         writer.write_u8(Code::ColorExplosion as u8)?;
-                        writer.write_u8(color_start)?;
-                        writer.write_u8(color_len)?;
                     }
                 };
 
-                write_coord_vector3(writer, origin)?;
+                protocol.write_coord_vector3(writer, origin)?;
+
+                // colors are written after the origin
+                if let PointEntityKind::ColorExplosion {
+                    color_start,
+                    color_len,
+                } = kind
+                {
+                    writer.write_u8(color_start)?;
+                    writer.write_u8(color_len)?;
+                }
             }
 
             TempEntity::Beam {
@@ -521,10 +924,10 @@ impl TempEntity {
                     },
                     BeamEntityKind::Grapple => Code::Grapple,
                 };
-                writer.write_i16::<LittleEndian>(entity_id)?;
                 writer.write_u8(code as u8)?;
-                write_coord_vector3(writer, start)?;
-                write_coord_vector3(writer, end)?;
+                writer.write_i16::<LittleEndian>(entity_id)?;
+                protocol.write_coord_vector3(writer, start)?;
+                protocol.write_coord_vector3(writer, end)?;
             }
         }
 
@@ -551,6 +954,46 @@ bitflags! {
     }
 }
 
+/// The encoded alpha of an entity that doesn't set one, which is drawn opaque.
+pub const ENTITY_ALPHA_DEFAULT: u8 = 0;
+/// The encoded scale of an entity drawn at its model's own size.
+pub const ENTITY_SCALE_DEFAULT: u8 = 16;
+
+/// Encodes the value of an entity's `.alpha` field as FitzQuake does: 0 is the default, and
+/// anything else is an opacity from 0 to 1.
+pub fn encode_alpha(alpha: f32) -> u8 {
+    if alpha == 0. {
+        ENTITY_ALPHA_DEFAULT
+    } else {
+        (alpha * 254. + 1.).round().clamp(1., 255.) as u8
+    }
+}
+
+/// Decodes an entity alpha into an opacity from 0 to 1.
+pub fn decode_alpha(alpha: u8) -> f32 {
+    match alpha {
+        ENTITY_ALPHA_DEFAULT => 1.,
+        a => (a - 1) as f32 / 254.,
+    }
+}
+
+/// Encodes the value of an entity's `.scale` field, where 0 is the default and 1 is the model's
+/// own size.
+pub fn encode_scale(scale: f32) -> u8 {
+    if scale == 0. {
+        ENTITY_SCALE_DEFAULT
+    } else {
+        (scale * ENTITY_SCALE_DEFAULT as f32)
+            .round()
+            .clamp(1., 255.) as u8
+    }
+}
+
+/// Decodes an entity scale into a factor of the model's own size.
+pub fn decode_scale(scale: u8) -> f32 {
+    scale as f32 / ENTITY_SCALE_DEFAULT as f32
+}
+
 #[derive(Clone, Debug)]
 pub struct EntityState {
     pub origin: Vector3<f32>,
@@ -562,6 +1005,10 @@ pub struct EntityState {
     pub colormap: u8,
     pub skin_id: usize,
     pub effects: EntityEffects,
+    /// Opacity as encoded by [`encode_alpha`].
+    pub alpha: u8,
+    /// Size as encoded by [`encode_scale`].
+    pub scale: u8,
 }
 
 impl EntityState {
@@ -574,6 +1021,8 @@ impl EntityState {
             colormap: 0,
             skin_id: 0,
             effects: EntityEffects::empty(),
+            alpha: ENTITY_ALPHA_DEFAULT,
+            scale: ENTITY_SCALE_DEFAULT,
         }
     }
 
@@ -589,11 +1038,37 @@ impl EntityState {
         }
     }
 
+    /// Returns the state as a client reads it when it is sent with `protocol`. Servers compare
+    /// quantized states, so that they only send changes which clients can see.
+    pub fn quantize(&self, protocol: Protocol) -> EntityState {
+        let (alpha, scale) = match protocol.is_extended() {
+            true => (self.alpha, self.scale),
+            false => (ENTITY_ALPHA_DEFAULT, ENTITY_SCALE_DEFAULT),
+        };
+
+        EntityState {
+            origin: protocol.quantize_coord_vector3(self.origin),
+            angles: protocol.quantize_angle_vector3(self.angles),
+            alpha,
+            scale,
+            ..self.clone()
+        }
+    }
+
+    /// Returns the state as a client knows it from a baseline, which has no alpha or scale.
+    pub fn baseline(&self) -> EntityState {
+        EntityState {
+            alpha: ENTITY_ALPHA_DEFAULT,
+            scale: ENTITY_SCALE_DEFAULT,
+            ..self.clone()
+        }
+    }
+
     pub fn make_update(&self, ent_id: u16, baseline: &Self) -> EntityUpdate {
         EntityUpdate {
             ent_id,
-            model_id: Some(self.model_id as _).filter(|v| *v != baseline.model_id as u8),
-            frame_id: Some(self.frame_id as _).filter(|v| *v != baseline.frame_id as u8),
+            model_id: Some(self.model_id as _).filter(|v| *v != baseline.model_id as u16),
+            frame_id: Some(self.frame_id as _).filter(|v| *v != baseline.frame_id as u16),
             colormap: Some(self.colormap as _).filter(|v| *v != baseline.colormap as u8),
             skin_id: Some(self.skin_id as _).filter(|v| *v != baseline.skin_id as u8),
             effects: Some(self.effects).filter(|v| *v != baseline.effects),
@@ -603,8 +1078,9 @@ impl EntityState {
             yaw: Some(self.angles[1]).filter(|v| *v != baseline.angles[1]),
             origin_z: Some(self.origin[2]).filter(|v| *v != baseline.origin[2]),
             roll: Some(self.angles[2]).filter(|v| *v != baseline.angles[2]),
-            // TODO: When should this be set?
-            no_lerp: true,
+            alpha: Some(self.alpha).filter(|v| *v != baseline.alpha),
+            scale: Some(self.scale).filter(|v| *v != baseline.scale),
+            no_lerp: false,
         }
     }
 }
@@ -612,8 +1088,8 @@ impl EntityState {
 #[derive(Clone, Debug, PartialEq)]
 pub struct EntityUpdate {
     pub ent_id: u16,
-    pub model_id: Option<u8>,
-    pub frame_id: Option<u8>,
+    pub model_id: Option<u16>,
+    pub frame_id: Option<u16>,
     pub colormap: Option<u8>,
     pub skin_id: Option<u8>,
     pub effects: Option<EntityEffects>,
@@ -623,11 +1099,19 @@ pub struct EntityUpdate {
     pub yaw: Option<Deg<f32>>,
     pub origin_z: Option<f32>,
     pub roll: Option<Deg<f32>>,
+    /// Opacity, where 0 means the default, 1 is invisible and 255 is opaque. Needs protocol 666.
+    pub alpha: Option<u8>,
+    /// Size in sixteenths, where 16 is the model's own size. Needs protocol 666.
+    pub scale: Option<u8>,
     pub no_lerp: bool,
 }
 
 impl EntityUpdate {
-    pub fn read<R>(reader: &mut R, update_flags: UpdateFlags) -> io::Result<Self>
+    pub fn read<R>(
+        reader: &mut R,
+        update_flags: UpdateFlags,
+        protocol: Protocol,
+    ) -> io::Result<Self>
     where
         R: Read,
     {
@@ -638,16 +1122,16 @@ impl EntityUpdate {
             ent_id = reader.read_u8()? as u16;
         }
 
-        let model_id;
+        let mut model_id;
         if update_flags.contains(UpdateFlags::MODEL) {
-            model_id = Some(reader.read_u8()?);
+            model_id = Some(reader.read_u8()? as u16);
         } else {
             model_id = None;
         }
 
-        let frame_id;
+        let mut frame_id;
         if update_flags.contains(UpdateFlags::FRAME) {
-            frame_id = Some(reader.read_u8()?);
+            frame_id = Some(reader.read_u8()? as u16);
         } else {
             frame_id = None;
         }
@@ -677,48 +1161,74 @@ impl EntityUpdate {
 
         let origin_x;
         if update_flags.contains(UpdateFlags::ORIGIN_X) {
-            origin_x = Some(read_coord(reader)?);
+            origin_x = Some(protocol.read_coord(reader)?);
         } else {
             origin_x = None;
         }
 
         let pitch;
         if update_flags.contains(UpdateFlags::PITCH) {
-            pitch = Some(read_angle(reader)?);
+            pitch = Some(protocol.read_angle(reader)?);
         } else {
             pitch = None;
         }
 
         let origin_y;
         if update_flags.contains(UpdateFlags::ORIGIN_Y) {
-            origin_y = Some(read_coord(reader)?);
+            origin_y = Some(protocol.read_coord(reader)?);
         } else {
             origin_y = None;
         }
 
         let yaw;
         if update_flags.contains(UpdateFlags::YAW) {
-            yaw = Some(read_angle(reader)?);
+            yaw = Some(protocol.read_angle(reader)?);
         } else {
             yaw = None;
         }
 
         let origin_z;
         if update_flags.contains(UpdateFlags::ORIGIN_Z) {
-            origin_z = Some(read_coord(reader)?);
+            origin_z = Some(protocol.read_coord(reader)?);
         } else {
             origin_z = None;
         }
 
         let roll;
         if update_flags.contains(UpdateFlags::ROLL) {
-            roll = Some(read_angle(reader)?);
+            roll = Some(protocol.read_angle(reader)?);
         } else {
             roll = None;
         }
 
         let no_lerp = update_flags.contains(UpdateFlags::NO_LERP);
 
+        // the extension flags can only have been read with an extended protocol
+        let alpha = match update_flags.contains(UpdateFlags::ALPHA) {
+            true => Some(reader.read_u8()?),
+            false => None,
+        };
+
+        let scale = match update_flags.contains(UpdateFlags::SCALE) {
+            true => Some(reader.read_u8()?),
+            false => None,
+        };
+
+        if update_flags.contains(UpdateFlags::FRAME_2) {
+            let high = (reader.read_u8()? as u16) << 8;
+            frame_id = Some(frame_id.unwrap_or_default() | high);
+        }
+
+        if update_flags.contains(UpdateFlags::MODEL_2) {
+            let high = (reader.read_u8()? as u16) << 8;
+            model_id = Some(model_id.unwrap_or_default() | high);
+        }
+
+        // the time to finish interpolating is ignored, as the client interpolates between updates
+        if update_flags.contains(UpdateFlags::LERP_FINISH) {
+            reader.read_u8()?;
+        }
+
         Ok(Self {
             ent_id,
             model_id,
@@ -732,24 +1242,38 @@ impl EntityUpdate {
             yaw,
             origin_z,
             roll,
+            alpha,
+            scale,
             no_lerp,
         })
     }
 
-    pub fn write<W>(&self, writer: &mut W) -> io::Result<()>
+    pub fn write<W>(&self, writer: &mut W, protocol: Protocol) -> io::Result<()>
     where
         W: Write,
     {
+        if !protocol.is_extended() && (self.alpha.is_some() || self.scale.is_some()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "entity alpha and scale need protocol {}",
+                    PROTOCOL_FITZQUAKE
+                ),
+            ));
+        }
+
         match u8::try_from(self.ent_id) {
             Ok(byte_entity) => writer.write_u8(byte_entity)?,
             Err(_) => writer.write_u16::<LittleEndian>(self.ent_id)?,
         }
 
         if let Some(model_id) = self.model_id {
-            writer.write_u8(model_id)?;
+            protocol.check_index(model_id, "model")?;
+            writer.write_u8(model_id as u8)?;
         }
         if let Some(frame_id) = self.frame_id {
-            writer.write_u8(frame_id)?;
+            protocol.check_index(frame_id, "frame")?;
+            writer.write_u8(frame_id as u8)?;
         }
         if let Some(colormap) = self.colormap {
             writer.write_u8(colormap)?;
@@ -761,22 +1285,34 @@ impl EntityUpdate {
             writer.write_u8(effects.bits())?;
         }
         if let Some(origin_x) = self.origin_x {
-            write_coord(writer, origin_x)?;
+            protocol.write_coord(writer, origin_x)?;
         }
         if let Some(pitch) = self.pitch {
-            write_angle(writer, pitch)?;
+            protocol.write_angle(writer, pitch)?;
         }
         if let Some(origin_y) = self.origin_y {
-            write_coord(writer, origin_y)?;
+            protocol.write_coord(writer, origin_y)?;
         }
         if let Some(yaw) = self.yaw {
-            write_angle(writer, yaw)?;
+            protocol.write_angle(writer, yaw)?;
         }
         if let Some(origin_z) = self.origin_z {
-            write_coord(writer, origin_z)?;
+            protocol.write_coord(writer, origin_z)?;
         }
         if let Some(roll) = self.roll {
-            write_angle(writer, roll)?;
+            protocol.write_angle(writer, roll)?;
+        }
+        if let Some(alpha) = self.alpha {
+            writer.write_u8(alpha)?;
+        }
+        if let Some(scale) = self.scale {
+            writer.write_u8(scale)?;
+        }
+        if let Some(frame_id) = self.frame_id.filter(|f| *f > u8::MAX as u16) {
+            writer.write_u8((frame_id >> 8) as u8)?;
+        }
+        if let Some(model_id) = self.model_id.filter(|m| *m > u8::MAX as u16) {
+            writer.write_u8((model_id >> 8) as u8)?;
         }
 
         Ok(())
@@ -807,13 +1343,29 @@ impl EntityUpdate {
             (self.origin_z.is_some(), UpdateFlags::ORIGIN_Z),
             (self.roll.is_some(), UpdateFlags::ROLL),
             (self.no_lerp, UpdateFlags::NO_LERP),
+            (self.alpha.is_some(), UpdateFlags::ALPHA),
+            (self.scale.is_some(), UpdateFlags::SCALE),
+            (
+                self.frame_id.is_some_and(|f| f > u8::MAX as u16),
+                UpdateFlags::FRAME_2,
+            ),
+            (
+                self.model_id.is_some_and(|m| m > u8::MAX as u16),
+                UpdateFlags::MODEL_2,
+            ),
         ] {
             if set {
                 out |= flag;
             }
         }
 
-        if out.bits() & !(u8::MAX as u16) != 0 {
+        if out.bits() >> 24 != 0 {
+            out |= UpdateFlags::EXTEND_2;
+        }
+        if out.bits() >> 16 != 0 {
+            out |= UpdateFlags::EXTEND_1;
+        }
+        if out.bits() >> 8 != 0 {
             out |= UpdateFlags::MORE_BITS;
         }
 
@@ -834,15 +1386,15 @@ pub struct PlayerData {
     pub items: ItemFlags,
     pub on_ground: bool,
     pub in_water: bool,
-    pub weapon_frame: Option<u8>,
-    pub armor: Option<u8>,
-    pub weapon: Option<u8>,
+    pub weapon_frame: Option<u16>,
+    pub armor: Option<u16>,
+    pub weapon: Option<u16>,
     pub health: i16,
-    pub ammo: u8,
-    pub ammo_shells: u8,
-    pub ammo_nails: u8,
-    pub ammo_rockets: u8,
-    pub ammo_cells: u8,
+    pub ammo: u16,
+    pub ammo_shells: u16,
+    pub ammo_nails: u16,
+    pub ammo_rockets: u16,
+    pub ammo_cells: u16,
     pub active_weapon: u8,
 }
 
@@ -866,6 +1418,8 @@ impl EntityUpdate {
             skin_id: self.skin_id.map_or(baseline.skin_id, |s| s as usize),
             effects: self.effects.unwrap_or(baseline.effects),
             colormap: self.colormap.unwrap_or(baseline.colormap),
+            alpha: self.alpha.unwrap_or(baseline.alpha),
+            scale: self.scale.unwrap_or(baseline.scale),
         }
     }
 }
@@ -924,6 +1478,12 @@ pub enum BasicServerCmdCode {
     CdTrack = 32,
     SellScreen = 33,
     Cutscene = 34,
+    // 35-40 are used by other engines' extensions
+    /// The same code as QuakeWorld's download message, though the layout differs.
+    Download = 41,
+    SpawnBaseline2 = 42,
+    SpawnStatic2 = 43,
+    SpawnStaticSound2 = 44,
 }
 
 #[derive(Debug)]
@@ -933,24 +1493,28 @@ pub enum ServerCmdCode {
 }
 
 impl ServerCmdCode {
-    pub fn read<R>(reader: &mut R) -> io::Result<Self>
+    pub fn read<R>(reader: &mut R, protocol: Protocol) -> io::Result<Self>
     where
         R: Read,
     {
         let low_bits = reader.read_u8()?;
 
         if low_bits & FAST_UPDATE_FLAG != 0 {
-            let low_bits = (low_bits & !FAST_UPDATE_FLAG) as u16;
-
-            let bits = if UpdateFlags::from_bits(low_bits)
-                .ok_or(io::Error::from(io::ErrorKind::InvalidData))?
-                .contains(UpdateFlags::MORE_BITS)
-            {
-                let high_bits = reader.read_u8()? as u16;
-                high_bits << 8 | low_bits
-            } else {
-                low_bits
-            };
+            let mut bits = (low_bits & !FAST_UPDATE_FLAG) as u32;
+
+            if bits & UpdateFlags::MORE_BITS.bits() != 0 {
+                bits |= (reader.read_u8()? as u32) << 8;
+            }
+
+            // the extension bytes are only understood by extended protocols
+            if protocol.is_extended() {
+                if bits & UpdateFlags::EXTEND_1.bits() != 0 {
+                    bits |= (reader.read_u8()? as u32) << 16;
+                }
+                if bits & UpdateFlags::EXTEND_2.bits() != 0 {
+                    bits |= (reader.read_u8()? as u32) << 24;
+                }
+            }
 
             let update_flags =
                 UpdateFlags::from_bits(bits).ok_or(io::Error::from(io::ErrorKind::InvalidData))?;
@@ -963,24 +1527,37 @@ impl ServerCmdCode {
         }
     }
 
-    pub fn write<W>(&self, writer: &mut W) -> io::Result<usize>
+    pub fn write<W>(&self, writer: &mut W, protocol: Protocol) -> io::Result<usize>
     where
         W: Write,
     {
         match self {
             Self::Basic(basic) => writer.write_u8(*basic as _).map(|()| mem::size_of::<u8>()),
             Self::FastUpdate(update) => {
-                if update.contains(UpdateFlags::MORE_BITS) {
-                    assert!(update.bits() & !(u8::MAX as u16) != 0);
-                    writer
-                        .write_u16::<LittleEndian>(update.bits() | (FAST_UPDATE_FLAG as u16))
-                        .map(|()| mem::size_of::<u16>())
+                let len = if update.contains(UpdateFlags::EXTEND_2) {
+                    4
+                } else if update.contains(UpdateFlags::EXTEND_1) {
+                    3
+                } else if update.contains(UpdateFlags::MORE_BITS) {
+                    2
                 } else {
-                    assert!(update.bits() & !(u8::MAX as u16) == 0);
-                    writer
-                        .write_u8(update.bits() as u8 | FAST_UPDATE_FLAG)
-                        .map(|()| mem::size_of::<u8>())
+                    1
+                };
+
+                assert!(update.bits() >> (len * 8) == 0);
+                if len > 2 && !protocol.is_extended() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "update flags {:?} need protocol {}",
+                            update, PROTOCOL_FITZQUAKE
+                        ),
+                    ));
                 }
+
+                let bits = update.bits() | FAST_UPDATE_FLAG as u32;
+                writer.write_all(&bits.to_le_bytes()[..len])?;
+                Ok(len)
             }
         }
     }
@@ -1012,7 +1589,7 @@ pub enum ServerCmd {
         attenuation: Option<f32>,
         entity_id: u16,
         channel: i8,
-        sound_id: u8,
+        sound_id: u16,
         position: Vector3<f32>,
     },
     Time {
@@ -1032,6 +1609,8 @@ pub enum ServerCmd {
         max_clients: u8,
         game_type: GameType,
         message: QString,
+        /// Only sent with protocol 999.
+        protocol_flags: ProtocolFlags,
         model_precache: Vec<String>,
         sound_precache: Vec<String>,
     },
@@ -1068,8 +1647,8 @@ pub enum ServerCmd {
         source: Vector3<f32>,
     },
     SpawnStatic {
-        model_id: u8,
-        frame_id: u8,
+        model_id: u16,
+        frame_id: u16,
         colormap: u8,
         skin_id: u8,
         origin: Vector3<f32>,
@@ -1078,8 +1657,8 @@ pub enum ServerCmd {
     // SpawnBinary, // unused
     SpawnBaseline {
         ent_id: u16,
-        model_id: u8,
-        frame_id: u8,
+        model_id: u16,
+        frame_id: u16,
         colormap: u8,
         skin_id: u8,
         origin: Vector3<f32>,
@@ -1101,7 +1680,7 @@ pub enum ServerCmd {
     FoundSecret,
     SpawnStaticSound {
         origin: Vector3<f32>,
-        sound_id: u8,
+        sound_id: u16,
         volume: u8,
         attenuation: u8,
     },
@@ -1117,6 +1696,14 @@ pub enum ServerCmd {
     Cutscene {
         text: QString,
     },
+    /// A piece of a file that the client asked for with the `download` command.
+    Download {
+        /// The size of the whole file, or `None` if the server won't send it.
+        size: Option<u32>,
+        /// Where `data` starts in the file.
+        offset: u32,
+        data: Vec<u8>,
+    },
     FastUpdate(EntityUpdate),
 }
 
@@ -1145,7 +1732,17 @@ impl ServerCmd {
             }
             ServerCmd::Particle { .. } => ServerCmdCode::Basic(BasicServerCmdCode::Particle),
             ServerCmd::Damage { .. } => ServerCmdCode::Basic(BasicServerCmdCode::Damage),
+            ServerCmd::SpawnStatic {
+                model_id, frame_id, ..
+            } if *model_id > u8::MAX as u16 || *frame_id > u8::MAX as u16 => {
+                ServerCmdCode::Basic(BasicServerCmdCode::SpawnStatic2)
+            }
             ServerCmd::SpawnStatic { .. } => ServerCmdCode::Basic(BasicServerCmdCode::SpawnStatic),
+            ServerCmd::SpawnBaseline {
+                model_id, frame_id, ..
+            } if *model_id > u8::MAX as u16 || *frame_id > u8::MAX as u16 => {
+                ServerCmdCode::Basic(BasicServerCmdCode::SpawnBaseline2)
+            }
             ServerCmd::SpawnBaseline { .. } => {
                 ServerCmdCode::Basic(BasicServerCmdCode::SpawnBaseline)
             }
@@ -1155,6 +1752,9 @@ impl ServerCmd {
             ServerCmd::CenterPrint { .. } => ServerCmdCode::Basic(BasicServerCmdCode::CenterPrint),
             ServerCmd::KilledMonster => ServerCmdCode::Basic(BasicServerCmdCode::KilledMonster),
             ServerCmd::FoundSecret => ServerCmdCode::Basic(BasicServerCmdCode::FoundSecret),
+            ServerCmd::SpawnStaticSound { sound_id, .. } if *sound_id > u8::MAX as u16 => {
+                ServerCmdCode::Basic(BasicServerCmdCode::SpawnStaticSound2)
+            }
             ServerCmd::SpawnStaticSound { .. } => {
                 ServerCmdCode::Basic(BasicServerCmdCode::SpawnStaticSound)
             }
@@ -1163,11 +1763,23 @@ impl ServerCmd {
             ServerCmd::CdTrack { .. } => ServerCmdCode::Basic(BasicServerCmdCode::CdTrack),
             ServerCmd::SellScreen => ServerCmdCode::Basic(BasicServerCmdCode::SellScreen),
             ServerCmd::Cutscene { .. } => ServerCmdCode::Basic(BasicServerCmdCode::Cutscene),
+            ServerCmd::Download { .. } => ServerCmdCode::Basic(BasicServerCmdCode::Download),
             ServerCmd::FastUpdate(update) => ServerCmdCode::FastUpdate(update.flags()),
         }
     }
 
+    /// Reads a command sent with the original NetQuake protocol.
     pub fn deserialize<R>(reader: &mut R) -> Result<Option<ServerCmd>, NetError>
+    where
+        R: BufRead,
+    {
+        Self::deserialize_with(reader, Protocol::NETQUAKE)
+    }
+
+    pub fn deserialize_with<R>(
+        reader: &mut R,
+        protocol: Protocol,
+    ) -> Result<Option<ServerCmd>, NetError>
     where
         R: BufRead,
     {
@@ -1175,7 +1787,7 @@ impl ServerCmd {
             return Ok(None);
         }
 
-        let code = ServerCmdCode::read(reader)?;
+        let code = ServerCmdCode::read(reader, protocol)?;
 
         let code = match code {
             ServerCmdCode::Basic(basic) => basic,
@@ -1183,6 +1795,7 @@ impl ServerCmd {
                 return Ok(Some(ServerCmd::FastUpdate(EntityUpdate::read(
                     reader,
                     update_flags,
+                    protocol,
                 )?)));
             }
         };
@@ -1240,15 +1853,19 @@ impl ServerCmd {
                     false => None,
                 };
 
-                let entity_channel = reader.read_i16::<LittleEndian>()?;
-                let entity_id = (entity_channel >> 3) as u16;
-                let channel = (entity_channel & 0b111) as i8;
-                let sound_id = reader.read_u8()?;
-                let position = Vector3::new(
-                    read_coord(reader)?,
-                    read_coord(reader)?,
-                    read_coord(reader)?,
-                );
+                let (entity_id, channel) = match flags.contains(SoundFlags::LARGE_ENTITY) {
+                    true => (reader.read_u16::<LittleEndian>()?, reader.read_u8()? as i8),
+                    false => {
+                        let entity_channel = reader.read_u16::<LittleEndian>()?;
+                        (entity_channel >> 3, (entity_channel & 0b111) as i8)
+                    }
+                };
+
+                let sound_id = match flags.contains(SoundFlags::LARGE_SOUND) {
+                    true => reader.read_u16::<LittleEndian>()?,
+                    false => reader.read_u8()? as u16,
+                };
+                let position = protocol.read_coord_vector3(reader)?;
 
                 ServerCmd::Sound {
                     volume,
@@ -1278,11 +1895,7 @@ impl ServerCmd {
             }
 
             BasicServerCmdCode::SetAngle => {
-                let angles = Vector3::new(
-                    read_angle(reader)?,
-                    read_angle(reader)?,
-                    read_angle(reader)?,
-                );
+                let angles = protocol.read_angle_vector3(reader)?;
 
                 ServerCmd::SetAngle { angles }
             }
@@ -1303,6 +1916,22 @@ impl ServerCmd {
 
                 let message = util::read_cstring(reader)?;
 
+                let protocol_flags = match protocol_version {
+                    PROTOCOL_RMQ => {
+                        let flags_bits = reader.read_u32::<LittleEndian>()?;
+                        match ProtocolFlags::from_bits(flags_bits) {
+                            Some(f) => f,
+                            None => {
+                                return Err(NetError::invalid_data(format!(
+                                    "ProtocolFlags: {:b}",
+                                    flags_bits
+                                )))
+                            }
+                        }
+                    }
+                    _ => ProtocolFlags::empty(),
+                };
+
                 let mut model_precache = Vec::new();
                 loop {
                     let model_name = util::read_cstring(reader)?.into_string();
@@ -1326,6 +1955,7 @@ impl ServerCmd {
                     max_clients,
                     game_type,
                     message,
+                    protocol_flags,
                     model_precache,
                     sound_precache,
                 }
@@ -1360,7 +1990,16 @@ impl ServerCmd {
             }
 
             BasicServerCmdCode::PlayerData => {
-                let flags_bits = reader.read_u16::<LittleEndian>()?;
+                let mut flags_bits = reader.read_u16::<LittleEndian>()? as u32;
+                if protocol.is_extended() {
+                    if flags_bits & ClientUpdateFlags::EXTEND_1.bits() != 0 {
+                        flags_bits |= (reader.read_u8()? as u32) << 16;
+                    }
+                    if flags_bits & ClientUpdateFlags::EXTEND_2.bits() != 0 {
+                        flags_bits |= (reader.read_u8()? as u32) << 24;
+                    }
+                }
+
                 let flags = match ClientUpdateFlags::from_bits(flags_bits) {
                     Some(f) => f,
                     None => {
@@ -1425,29 +2064,64 @@ impl ServerCmd {
                 let on_ground = flags.contains(ClientUpdateFlags::ON_GROUND);
                 let in_water = flags.contains(ClientUpdateFlags::IN_WATER);
 
-                let weapon_frame = match flags.contains(ClientUpdateFlags::WEAPON_FRAME) {
-                    true => Some(reader.read_u8()?),
+                let mut weapon_frame = match flags.contains(ClientUpdateFlags::WEAPON_FRAME) {
+                    true => Some(reader.read_u8()? as u16),
                     false => None,
                 };
 
-                let armor = match flags.contains(ClientUpdateFlags::ARMOR) {
-                    true => Some(reader.read_u8()?),
+                let mut armor = match flags.contains(ClientUpdateFlags::ARMOR) {
+                    true => Some(reader.read_u8()? as u16),
                     false => None,
                 };
 
-                let weapon = match flags.contains(ClientUpdateFlags::WEAPON) {
-                    true => Some(reader.read_u8()?),
+                let mut weapon = match flags.contains(ClientUpdateFlags::WEAPON) {
+                    true => Some(reader.read_u8()? as u16),
                     false => None,
                 };
 
                 let health = reader.read_i16::<LittleEndian>()?;
-                let ammo = reader.read_u8()?;
-                let ammo_shells = reader.read_u8()?;
-                let ammo_nails = reader.read_u8()?;
-                let ammo_rockets = reader.read_u8()?;
-                let ammo_cells = reader.read_u8()?;
+                let mut ammo = reader.read_u8()? as u16;
+                let mut ammo_shells = reader.read_u8()? as u16;
+                let mut ammo_nails = reader.read_u8()? as u16;
+                let mut ammo_rockets = reader.read_u8()? as u16;
+                let mut ammo_cells = reader.read_u8()? as u16;
                 let active_weapon = reader.read_u8()?;
 
+                // the high bytes of anything over 255 follow the rest of the update
+                let mut read_high = |low: &mut u16| -> io::Result<()> {
+                    *low |= (reader.read_u8()? as u16) << 8;
+                    Ok(())
+                };
+                if flags.contains(ClientUpdateFlags::WEAPON_2) {
+                    read_high(weapon.get_or_insert(0))?;
+                }
+                if flags.contains(ClientUpdateFlags::ARMOR_2) {
+                    read_high(armor.get_or_insert(0))?;
+                }
+                if flags.contains(ClientUpdateFlags::AMMO_2) {
+                    read_high(&mut ammo)?;
+                }
+                if flags.contains(ClientUpdateFlags::SHELLS_2) {
+                    read_high(&mut ammo_shells)?;
+                }
+                if flags.contains(ClientUpdateFlags::NAILS_2) {
+                    read_high(&mut ammo_nails)?;
+                }
+                if flags.contains(ClientUpdateFlags::ROCKETS_2) {
+                    read_high(&mut ammo_rockets)?;
+                }
+                if flags.contains(ClientUpdateFlags::CELLS_2) {
+                    read_high(&mut ammo_cells)?;
+                }
+                if flags.contains(ClientUpdateFlags::WEAPON_FRAME_2) {
+                    read_high(weapon_frame.get_or_insert(0))?;
+                }
+
+                // weapon transparency isn't supported
+                if flags.contains(ClientUpdateFlags::WEAPON_ALPHA) {
+                    reader.read_u8()?;
+                }
+
                 ServerCmd::PlayerData(PlayerData {
                     view_height,
                     ideal_pitch,
@@ -1493,7 +2167,7 @@ impl ServerCmd {
             }
 
             BasicServerCmdCode::Particle => {
-                let origin = read_coord_vector3(reader)?;
+                let origin = protocol.read_coord_vector3(reader)?;
 
                 let mut direction = Vector3::zero();
                 for i in 0..3 {
@@ -1514,7 +2188,7 @@ impl ServerCmd {
             BasicServerCmdCode::Damage => {
                 let armor = reader.read_u8()?;
                 let blood = reader.read_u8()?;
-                let source = read_coord_vector3(reader)?;
+                let source = protocol.read_coord_vector3(reader)?;
 
                 ServerCmd::Damage {
                     armor,
@@ -1523,17 +2197,26 @@ impl ServerCmd {
                 }
             }
 
-            BasicServerCmdCode::SpawnStatic => {
-                let model_id = reader.read_u8()?;
-                let frame_id = reader.read_u8()?;
+            BasicServerCmdCode::SpawnStatic | BasicServerCmdCode::SpawnStatic2 => {
+                let flags = match code {
+                    BasicServerCmdCode::SpawnStatic2 => read_baseline_flags(reader)?,
+                    _ => BaselineFlags::empty(),
+                };
+                let model_id = read_baseline_index(reader, flags, BaselineFlags::LARGE_MODEL)?;
+                let frame_id = read_baseline_index(reader, flags, BaselineFlags::LARGE_FRAME)?;
                 let colormap = reader.read_u8()?;
                 let skin_id = reader.read_u8()?;
 
                 let mut origin = Vector3::zero();
                 let mut angles = Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0));
                 for i in 0..3 {
-                    origin[i] = read_coord(reader)?;
-                    angles[i] = read_angle(reader)?;
+                    origin[i] = protocol.read_coord(reader)?;
+                    angles[i] = protocol.read_angle(reader)?;
+                }
+
+                // baseline transparency isn't supported
+                if flags.contains(BaselineFlags::ALPHA) {
+                    reader.read_u8()?;
                 }
 
                 ServerCmd::SpawnStatic {
@@ -1546,18 +2229,26 @@ impl ServerCmd {
                 }
             }
 
-            BasicServerCmdCode::SpawnBaseline => {
+            BasicServerCmdCode::SpawnBaseline | BasicServerCmdCode::SpawnBaseline2 => {
                 let ent_id = reader.read_u16::<LittleEndian>()?;
-                let model_id = reader.read_u8()?;
-                let frame_id = reader.read_u8()?;
+                let flags = match code {
+                    BasicServerCmdCode::SpawnBaseline2 => read_baseline_flags(reader)?,
+                    _ => BaselineFlags::empty(),
+                };
+                let model_id = read_baseline_index(reader, flags, BaselineFlags::LARGE_MODEL)?;
+                let frame_id = read_baseline_index(reader, flags, BaselineFlags::LARGE_FRAME)?;
                 let colormap = reader.read_u8()?;
                 let skin_id = reader.read_u8()?;
 
                 let mut origin = Vector3::zero();
                 let mut angles = Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0));
                 for i in 0..3 {
-                    origin[i] = read_coord(reader)?;
-                    angles[i] = read_angle(reader)?;
+                    origin[i] = protocol.read_coord(reader)?;
+                    angles[i] = protocol.read_angle(reader)?;
+                }
+
+                if flags.contains(BaselineFlags::ALPHA) {
+                    reader.read_u8()?;
                 }
 
                 ServerCmd::SpawnBaseline {
@@ -1572,7 +2263,7 @@ impl ServerCmd {
             }
 
             BasicServerCmdCode::TempEntity => {
-                let temp_entity = TempEntity::read_temp_entity(reader)?;
+                let temp_entity = TempEntity::read_temp_entity(reader, protocol)?;
 
                 ServerCmd::TempEntity { temp_entity }
             }
@@ -1611,9 +2302,12 @@ impl ServerCmd {
             BasicServerCmdCode::KilledMonster => ServerCmd::KilledMonster,
             BasicServerCmdCode::FoundSecret => ServerCmd::FoundSecret,
 
-            BasicServerCmdCode::SpawnStaticSound => {
-                let origin = read_coord_vector3(reader)?;
-                let sound_id = reader.read_u8()?;
+            BasicServerCmdCode::SpawnStaticSound | BasicServerCmdCode::SpawnStaticSound2 => {
+                let origin = protocol.read_coord_vector3(reader)?;
+                let sound_id = match code {
+                    BasicServerCmdCode::SpawnStaticSound2 => reader.read_u16::<LittleEndian>()?,
+                    _ => reader.read_u8()? as u16,
+                };
                 let volume = reader.read_u8()?;
                 let attenuation = reader.read_u8()?;
 
@@ -1646,17 +2340,55 @@ impl ServerCmd {
 
                 ServerCmd::Cutscene { text }
             }
+
+            BasicServerCmdCode::Download => {
+                let size = reader.read_i32::<LittleEndian>()?;
+                let offset = reader.read_u32::<LittleEndian>()?;
+                let len = reader.read_u16::<LittleEndian>()?;
+                let mut data = vec![0; len as usize];
+                reader.read_exact(&mut data)?;
+
+                ServerCmd::Download {
+                    // a negative size means the file isn't available
+                    size: u32::try_from(size).ok(),
+                    offset,
+                    data,
+                }
+            }
         };
 
         Ok(Some(cmd))
     }
 
+    /// Writes this command using the original NetQuake protocol.
     #[inline(always)]
     pub fn serialize<W>(&self, writer: &mut W) -> Result<(), NetError>
     where
         W: Write,
     {
-        self.code().write(writer)?;
+        self.serialize_with(writer, Protocol::NETQUAKE)
+    }
+
+    pub fn serialize_with<W>(&self, writer: &mut W, protocol: Protocol) -> Result<(), NetError>
+    where
+        W: Write,
+    {
+        let code = self.code();
+        if let ServerCmdCode::Basic(
+            basic @ (BasicServerCmdCode::SpawnBaseline2
+            | BasicServerCmdCode::SpawnStatic2
+            | BasicServerCmdCode::SpawnStaticSound2),
+        ) = code
+        {
+            if !protocol.is_extended() {
+                return Err(NetError::with_msg(format!(
+                    "{:?} needs protocol {}",
+                    basic, PROTOCOL_FITZQUAKE
+                )));
+            }
+        }
+
+        code.write(writer, protocol)?;
 
         match *self {
             ServerCmd::Bad | ServerCmd::NoOp | ServerCmd::Disconnect => {}
@@ -1692,6 +2424,24 @@ impl ServerCmd {
                     sound_flags |= SoundFlags::ATTENUATION;
                 }
 
+                // only 13 bits of the entity fit alongside the channel
+                if entity_id >= 1 << 13 {
+                    sound_flags |= SoundFlags::LARGE_ENTITY;
+                }
+
+                if sound_id > u8::MAX as u16 {
+                    sound_flags |= SoundFlags::LARGE_SOUND;
+                }
+
+                if !protocol.is_extended()
+                    && sound_flags.intersects(SoundFlags::LARGE_ENTITY | SoundFlags::LARGE_SOUND)
+                {
+                    return Err(NetError::with_msg(format!(
+                        "sound {} on entity {} needs protocol {}",
+                        sound_id, entity_id, PROTOCOL_FITZQUAKE
+                    )));
+                }
+
                 writer.write_u8(sound_flags.bits())?;
 
                 if let Some(v) = volume {
@@ -1702,15 +2452,22 @@ impl ServerCmd {
                     writer.write_u8(a as u8 * SOUND_ATTENUATION_WRITE_FACTOR)?;
                 }
 
-                // TODO: document this better. The entity and channel fields are combined in Sound commands.
-                let ent_channel = (entity_id as i16) << 3 | channel as i16 & 0b111;
-                writer.write_i16::<LittleEndian>(ent_channel)?;
-
-                writer.write_u8(sound_id)?;
+                if sound_flags.contains(SoundFlags::LARGE_ENTITY) {
+                    writer.write_u16::<LittleEndian>(entity_id)?;
+                    writer.write_u8(channel as u8)?;
+                } else {
+                    // TODO: document this better. The entity and channel fields are combined in Sound commands.
+                    let ent_channel = (entity_id as i16) << 3 | channel as i16 & 0b111;
+                    writer.write_i16::<LittleEndian>(ent_channel)?;
+                }
 
-                for component in 0..3 {
-                    write_coord(writer, position[component])?;
+                if sound_flags.contains(SoundFlags::LARGE_SOUND) {
+                    writer.write_u16::<LittleEndian>(sound_id)?;
+                } else {
+                    writer.write_u8(sound_id as u8)?;
                 }
+
+                protocol.write_coord_vector3(writer, position)?;
             }
 
             ServerCmd::Time { time } => writer.write_f32::<LittleEndian>(time)?,
@@ -1725,13 +2482,14 @@ impl ServerCmd {
                 writer.write_u8(0)?;
             }
 
-            ServerCmd::SetAngle { angles } => write_angle_vector3(writer, angles)?,
+            ServerCmd::SetAngle { angles } => protocol.write_angle_vector3(writer, angles)?,
 
             ServerCmd::ServerInfo {
                 protocol_version,
                 max_clients,
                 game_type,
                 ref message,
+                protocol_flags,
                 ref model_precache,
                 ref sound_precache,
             } => {
@@ -1742,6 +2500,10 @@ impl ServerCmd {
                 writer.write_all(&*message.raw)?;
                 writer.write_u8(0)?;
 
+                if protocol_version == PROTOCOL_RMQ {
+                    writer.write_u32::<LittleEndian>(protocol_flags.bits())?;
+                }
+
                 for model_name in model_precache.iter() {
                     writer.write_all(model_name.as_bytes())?;
                     writer.write_u8(0)?;
@@ -1846,50 +2608,103 @@ impl ServerCmd {
                     flags |= ClientUpdateFlags::WEAPON;
                 }
 
+                let large = |v: Option<u16>| v.is_some_and(|v| v > u8::MAX as u16);
+                for (cond, flag) in [
+                    (large(weapon), ClientUpdateFlags::WEAPON_2),
+                    (large(armor), ClientUpdateFlags::ARMOR_2),
+                    (large(Some(ammo)), ClientUpdateFlags::AMMO_2),
+                    (large(Some(ammo_shells)), ClientUpdateFlags::SHELLS_2),
+                    (large(Some(ammo_nails)), ClientUpdateFlags::NAILS_2),
+                    (large(Some(ammo_rockets)), ClientUpdateFlags::ROCKETS_2),
+                    (large(Some(ammo_cells)), ClientUpdateFlags::CELLS_2),
+                    (large(weapon_frame), ClientUpdateFlags::WEAPON_FRAME_2),
+                ] {
+                    if cond {
+                        flags |= flag;
+                    }
+                }
+
+                if flags.bits() >> 24 != 0 {
+                    flags |= ClientUpdateFlags::EXTEND_2;
+                }
+                if flags.bits() >> 16 != 0 {
+                    flags |= ClientUpdateFlags::EXTEND_1;
+                    if !protocol.is_extended() {
+                        return Err(NetError::with_msg(format!(
+                            "client data over 255 needs protocol {}",
+                            PROTOCOL_FITZQUAKE
+                        )));
+                    }
+                }
+
                 // write flags
-                writer.write_u16::<LittleEndian>(flags.bits())?;
+                writer.write_u16::<LittleEndian>(flags.bits() as u16)?;
+                if flags.contains(ClientUpdateFlags::EXTEND_1) {
+                    writer.write_u8((flags.bits() >> 16) as u8)?;
+                }
+                if flags.contains(ClientUpdateFlags::EXTEND_2) {
+                    writer.write_u8((flags.bits() >> 24) as u8)?;
+                }
 
                 if let Some(vh) = view_height {
-                    writer.write_u8(vh as i32 as u8)?;
+                    writer.write_i8(encode_char(vh))?;
                 }
                 if let Some(ip) = ideal_pitch {
-                    writer.write_u8(ip.0 as i32 as u8)?;
+                    writer.write_i8(encode_char(ip.0))?;
                 }
                 if let Some(pp) = punch_pitch {
-                    writer.write_u8(pp.0 as i32 as u8)?;
+                    writer.write_i8(encode_char(pp.0))?;
                 }
                 if let Some(vx) = velocity_x {
-                    writer.write_u8((vx * VELOCITY_WRITE_FACTOR) as i32 as u8)?;
+                    writer.write_i8(encode_velocity(vx))?;
                 }
                 if let Some(py) = punch_yaw {
-                    writer.write_u8(py.0 as i32 as u8)?;
+                    writer.write_i8(encode_char(py.0))?;
                 }
                 if let Some(vy) = velocity_y {
-                    writer.write_u8((vy * VELOCITY_WRITE_FACTOR) as i32 as u8)?;
+                    writer.write_i8(encode_velocity(vy))?;
                 }
                 if let Some(pr) = punch_roll {
-                    writer.write_u8(pr.0 as i32 as u8)?;
+                    writer.write_i8(encode_char(pr.0))?;
                 }
                 if let Some(vz) = velocity_z {
-                    writer.write_u8((vz * VELOCITY_WRITE_FACTOR) as i32 as u8)?;
+                    writer.write_i8(encode_velocity(vz))?;
                 }
                 writer.write_u32::<LittleEndian>(items.bits())?;
                 if let Some(wf) = weapon_frame {
-                    writer.write_u8(wf)?;
+                    writer.write_u8(wf as u8)?;
                 }
                 if let Some(a) = armor {
-                    writer.write_u8(a)?;
+                    writer.write_u8(a as u8)?;
                 }
                 if let Some(w) = weapon {
-                    writer.write_u8(w)?;
+                    writer.write_u8(w as u8)?;
                 }
                 writer.write_i16::<LittleEndian>(health)?;
-                writer.write_u8(ammo)?;
-                writer.write_u8(ammo_shells)?;
-                writer.write_u8(ammo_nails)?;
-                writer.write_u8(ammo_rockets)?;
-                writer.write_u8(ammo_cells)?;
+                writer.write_u8(ammo as u8)?;
+                writer.write_u8(ammo_shells as u8)?;
+                writer.write_u8(ammo_nails as u8)?;
+                writer.write_u8(ammo_rockets as u8)?;
+                writer.write_u8(ammo_cells as u8)?;
                 writer.write_u8(active_weapon)?;
+
+                for (flag, value) in [
+                    (ClientUpdateFlags::WEAPON_2, weapon.unwrap_or_default()),
+                    (ClientUpdateFlags::ARMOR_2, armor.unwrap_or_default()),
+                    (ClientUpdateFlags::AMMO_2, ammo),
+                    (ClientUpdateFlags::SHELLS_2, ammo_shells),
+                    (ClientUpdateFlags::NAILS_2, ammo_nails),
+                    (ClientUpdateFlags::ROCKETS_2, ammo_rockets),
+                    (ClientUpdateFlags::CELLS_2, ammo_cells),
+                    (
+                        ClientUpdateFlags::WEAPON_FRAME_2,
+                        weapon_frame.unwrap_or_default(),
+                    ),
+                ] {
+                    if flags.contains(flag) {
+                        writer.write_u8((value >> 8) as u8)?;
+                    }
+                }
             }
 
             ServerCmd::StopSound { entity_id, channel } => {
@@ -1911,7 +2726,7 @@ impl ServerCmd {
                 count,
                 color,
             } => {
-                write_coord_vector3(writer, origin)?;
+                protocol.write_coord_vector3(writer, origin)?;
 
                 for i in 0..3 {
                     writer.write_i8(match direction[i] * PARTICLE_DIRECTION_WRITE_FACTOR {
@@ -1932,7 +2747,7 @@ impl ServerCmd {
             } => {
                 writer.write_u8(armor)?;
                 writer.write_u8(blood)?;
-                write_coord_vector3(writer, source)?;
+                protocol.write_coord_vector3(writer, source)?;
             }
 
             ServerCmd::SpawnStatic {
@@ -1943,14 +2758,19 @@ impl ServerCmd {
                 origin,
                 angles,
             } => {
-                writer.write_u8(model_id)?;
-                writer.write_u8(frame_id)?;
+                let flags = baseline_flags(model_id, frame_id);
+                if matches!(code, ServerCmdCode::Basic(BasicServerCmdCode::SpawnStatic2)) {
+                    writer.write_u8(flags.bits())?;
+                }
+
+                write_baseline_index(writer, model_id, flags.contains(BaselineFlags::LARGE_MODEL))?;
+                write_baseline_index(writer, frame_id, flags.contains(BaselineFlags::LARGE_FRAME))?;
                 writer.write_u8(colormap)?;
                 writer.write_u8(skin_id)?;
 
                 for i in 0..3 {
-                    write_coord(writer, origin[i])?;
-                    write_angle(writer, angles[i])?;
+                    protocol.write_coord(writer, origin[i])?;
+                    protocol.write_angle(writer, angles[i])?;
                 }
             }
 
@@ -1964,19 +2784,28 @@ impl ServerCmd {
                 angles,
             } => {
                 writer.write_u16::<LittleEndian>(ent_id)?;
-                writer.write_u8(model_id)?;
-                writer.write_u8(frame_id)?;
+
+                let flags = baseline_flags(model_id, frame_id);
+                if matches!(
+                    code,
+                    ServerCmdCode::Basic(BasicServerCmdCode::SpawnBaseline2)
+                ) {
+                    writer.write_u8(flags.bits())?;
+                }
+
+                write_baseline_index(writer, model_id, flags.contains(BaselineFlags::LARGE_MODEL))?;
+                write_baseline_index(writer, frame_id, flags.contains(BaselineFlags::LARGE_FRAME))?;
                 writer.write_u8(colormap)?;
                 writer.write_u8(skin_id)?;
 
                 for i in 0..3 {
-                    write_coord(writer, origin[i])?;
-                    write_angle(writer, angles[i])?;
+                    protocol.write_coord(writer, origin[i])?;
+                    protocol.write_angle(writer, angles[i])?;
                 }
             }
 
             ServerCmd::TempEntity { ref temp_entity } => {
-                temp_entity.write_temp_entity(writer)?;
+                temp_entity.write_temp_entity(writer, protocol)?;
             }
 
             ServerCmd::SetPause { paused } => {
@@ -2003,8 +2832,12 @@ impl ServerCmd {
                 volume,
                 attenuation,
             } => {
-                write_coord_vector3(writer, origin)?;
-                writer.write_u8(sound_id)?;
+                protocol.write_coord_vector3(writer, origin)?;
+                if sound_id > u8::MAX as u16 {
+                    writer.write_u16::<LittleEndian>(sound_id)?;
+                } else {
+                    writer.write_u8(sound_id as u8)?;
+                }
                 writer.write_u8(volume)?;
                 writer.write_u8(attenuation)?;
             }
@@ -2028,8 +2861,28 @@ impl ServerCmd {
                 writer.write_u8(0)?;
             }
 
+            ServerCmd::Download {
+                size,
+                offset,
+                ref data,
+            } => {
+                let size = match size {
+                    Some(s) => i32::try_from(s)
+                        .map_err(|_| NetError::with_msg(format!("Download too large: {}", s)))?,
+                    None => -1,
+                };
+                let len = u16::try_from(data.len()).map_err(|_| {
+                    NetError::with_msg(format!("Download chunk too large: {}", data.len()))
+                })?;
+
+                writer.write_i32::<LittleEndian>(size)?;
+                writer.write_u32::<LittleEndian>(offset)?;
+                writer.write_u16::<LittleEndian>(len)?;
+                writer.write_all(data)?;
+            }
+
             ServerCmd::FastUpdate(ref update) => {
-                update.write(writer)?;
+                update.write(writer, protocol)?;
             }
         }
 
@@ -2183,10 +3036,17 @@ pub enum BlockingMode {
     Timeout(Duration),
 }
 
+/// Converts the timeout of a [`BlockingMode::Timeout`] for the standard library, which can't wait
+/// for a negative time.
+pub(crate) fn std_timeout(timeout: Duration) -> Result<std::time::Duration, NetError> {
+    timeout
+        .to_std()
+        .map_err(|_| NetError::with_msg(format!("Negative timeout: {}", timeout)))
+}
+
 #[derive(Resource)]
 pub struct QSocket {
-    socket: UdpSocket,
-    remote: SocketAddr,
+    transport: Box<dyn Transport>,
 
     unreliable_send_sequence: u32,
     unreliable_recv_sequence: u32,
@@ -2202,13 +3062,20 @@ pub struct QSocket {
 
     recv_sequence: u32,
     recv_buf: [u8; MAX_MESSAGE],
+
+    /// Whether reliable messages are compressed, as agreed when connecting.
+    compress: bool,
 }
 
 impl QSocket {
     pub fn new(socket: UdpSocket, remote: SocketAddr) -> QSocket {
+        QSocket::with_transport(Box::new(UdpTransport::new(socket, remote)))
+    }
+
+    /// Creates a socket which sends its packets over `transport`.
+    pub fn with_transport(transport: Box<dyn Transport>) -> QSocket {
         QSocket {
-            socket,
-            remote,
+            transport,
 
             unreliable_send_sequence: 0,
             unreliable_recv_sequence: 0,
@@ -2224,9 +3091,17 @@ impl QSocket {
 
             recv_sequence: 0,
             recv_buf: [0; MAX_MESSAGE],
+
+            compress: false,
         }
     }
 
+    /// Turns compression of reliable messages on or off. Both ends of the connection must agree,
+    /// so this should only be set as negotiated by the connection request and response.
+    pub fn set_compression(&mut self, compress: bool) {
+        self.compress = compress;
+    }
+
     pub fn can_send(&self) -> bool {
         self.send_queue.is_empty() && self.send_cache.is_empty()
     }
@@ -2247,6 +3122,15 @@ impl QSocket {
             ));
         }
 
+        // compressed messages only have to fit once they're compressed
+        let compressed;
+        let msg = if self.compress {
+            compressed = compress::compress(msg)?;
+            &compressed[..]
+        } else {
+            msg
+        };
+
         // check upper message length bound
         if msg.len() > MAX_MESSAGE {
             return Err(NetError::with_msg(
@@ -2271,7 +3155,7 @@ impl QSocket {
         if self.send_cache.is_empty() {
             Err(NetError::with_msg("Attempted resend with empty send cache"))
         } else {
-            self.socket.send_to(&self.send_cache, self.remote)?;
+            self.transport.send(&self.send_cache)?;
             self.resend_count += 1;
 
             Ok(())
@@ -2306,7 +3190,7 @@ impl QSocket {
         self.send_sequence += 1;
 
         // send the composed packet
-        self.socket.send_to(&self.send_cache, self.remote)?;
+        self.transport.send(&self.send_cache)?;
 
         // TODO: update send time
         // bump send count
@@ -2342,7 +3226,7 @@ impl QSocket {
         self.unreliable_send_sequence += 1;
 
         // send the message
-        self.socket.send_to(&packet, self.remote)?;
+        self.transport.send(&packet)?;
 
         // bump send count
         self.send_count += 1;
@@ -2355,45 +3239,12 @@ impl QSocket {
     pub fn recv_msg(&mut self, block: BlockingMode) -> Result<Vec<u8>, NetError> {
         let mut msg = Vec::new();
 
-        match block {
-            BlockingMode::Blocking => {
-                self.socket.set_nonblocking(false)?;
-                self.socket.set_read_timeout(None)?;
-            }
-
-            BlockingMode::NonBlocking => {
-                self.socket.set_nonblocking(true)?;
-                self.socket.set_read_timeout(None)?;
-            }
-
-            BlockingMode::Timeout(d) => {
-                self.socket.set_nonblocking(false)?;
-                self.socket.set_read_timeout(Some(d.to_std().unwrap()))?;
-            }
-        }
-
         loop {
-            let (packet_len, src_addr) = match self.socket.recv_from(&mut self.recv_buf) {
-                Ok(x) => x,
-                Err(e) => {
-                    use std::io::ErrorKind;
-                    match e.kind() {
-                        // these errors are expected in nonblocking mode
-                        ErrorKind::WouldBlock | ErrorKind::TimedOut => return Ok(Vec::new()),
-                        _ => return Err(NetError::from(e)),
-                    }
-                }
+            let packet_len = match self.transport.recv(&mut self.recv_buf, &block)? {
+                Some(len) => len,
+                None => return Ok(Vec::new()),
             };
 
-            if src_addr != self.remote {
-                // this packet didn't come from remote, drop it
-                debug!(
-                    "forged packet (src_addr was {}, should be {})",
-                    src_addr, self.remote
-                );
-                continue;
-            }
-
             let mut reader = BufReader::new(Cursor::new(&self.recv_buf[..packet_len]));
 
             let msg_kind_code = reader.read_u16::<NetworkEndian>()?;
@@ -2486,7 +3337,7 @@ impl QSocket {
                     ack_curs.write_u16::<NetworkEndian>(MsgKind::Ack as u16)?;
                     ack_curs.write_u16::<NetworkEndian>(HEADER_SIZE as u16)?;
                     ack_curs.write_u32::<NetworkEndian>(sequence)?;
-                    self.socket.send_to(ack_curs.into_inner(), self.remote)?;
+                    self.transport.send(ack_curs.into_inner())?;
 
                     // if this was a duplicate, drop it
                     if sequence != self.recv_sequence {
@@ -2499,6 +3350,9 @@ impl QSocket {
 
                     // if this is the last chunk of a reliable message, break out and return
                     if msg_kind == MsgKind::ReliableEom {
+                        if self.compress {
+                            msg = compress::decompress(&msg)?;
+                        }
                         break;
                     }
                 }
@@ -2513,11 +3367,73 @@ impl QSocket {
     }
 }
 
+// The fixed-point encodings below are shared by every writer and by the `Protocol::quantize_*`
+// functions, so the server can know exactly what its clients will see. They all round to the
+// nearest step, where the original engine truncated and so drew entities up to a step closer to
+// the origin than the server had them.
+
+fn encode_coord(coord: f32) -> i16 {
+    (coord * COORD_STEPS).round() as i16
+}
+
+fn decode_coord(coord: i16) -> f32 {
+    coord as f32 / COORD_STEPS
+}
+
+fn encode_coord_i32(coord: f32) -> i32 {
+    (coord * INT32_COORD_STEPS).round() as i32
+}
+
+fn decode_coord_i32(coord: i32) -> f32 {
+    coord as f32 / INT32_COORD_STEPS
+}
+
+/// Splits a coordinate into whole units and a fraction. The fraction is always added, so
+/// negative coordinates round down first.
+fn encode_coord_24bit(coord: f32) -> (i16, u8) {
+    let whole = coord.floor();
+    (
+        whole as i16,
+        ((coord - whole) * COORD_24BIT_STEPS).round() as u8,
+    )
+}
+
+fn decode_coord_24bit(whole: i16, frac: u8) -> f32 {
+    whole as f32 + frac as f32 / COORD_24BIT_STEPS
+}
+
+/// Angles wrap around, so that e.g. 270 degrees is sent as -90.
+fn encode_angle(angle: Deg<f32>) -> i8 {
+    (angle.0 * ANGLE_STEPS / 360.0).round() as i32 as i8
+}
+
+fn decode_angle(angle: i8) -> Deg<f32> {
+    Deg(angle as f32 * (360.0 / ANGLE_STEPS))
+}
+
+fn encode_angle_i16(angle: Deg<f32>) -> i16 {
+    (angle.0 * SHORT_ANGLE_STEPS / 360.0).round() as i32 as i16
+}
+
+fn decode_angle_i16(angle: i16) -> Deg<f32> {
+    Deg(angle as f32 * (360.0 / SHORT_ANGLE_STEPS))
+}
+
+/// The player's view height, ideal pitch and punch angles are sent as whole numbers. Unlike
+/// angles these don't wrap, so out of range values are clamped.
+fn encode_char(value: f32) -> i8 {
+    value.round().clamp(i8::MIN as f32, i8::MAX as f32) as i8
+}
+
+fn encode_velocity(velocity: f32) -> i8 {
+    encode_char(velocity / VELOCITY_READ_FACTOR)
+}
+
 fn read_coord<R>(reader: &mut R) -> io::Result<f32>
 where
     R: Read,
 {
-    Ok(reader.read_i16::<LittleEndian>()? as f32 / 8.0)
+    Ok(decode_coord(reader.read_i16::<LittleEndian>()?))
 }
 
 fn read_coord_vector3<R>(reader: &mut R) -> io::Result<Vector3<f32>>
@@ -2535,18 +3451,7 @@ fn write_coord<W>(writer: &mut W, coord: f32) -> io::Result<()>
 where
     W: Write,
 {
-    writer.write_i16::<LittleEndian>((coord * 8.0) as i16)?;
-    Ok(())
-}
-
-fn write_coord_vector3<W>(writer: &mut W, coords: Vector3<f32>) -> io::Result<()>
-where
-    W: Write,
-{
-    for coord in &coords[..] {
-        write_coord(writer, *coord)?;
-    }
-
+    writer.write_i16::<LittleEndian>(encode_coord(coord))?;
     Ok(())
 }
 
@@ -2554,14 +3459,14 @@ fn read_angle<R>(reader: &mut R) -> io::Result<Deg<f32>>
 where
     R: Read,
 {
-    Ok(Deg(reader.read_i8()? as f32 * (360.0 / 256.0)))
+    Ok(decode_angle(reader.read_i8()?))
 }
 
 fn write_angle<W>(writer: &mut W, angle: Deg<f32>) -> io::Result<()>
 where
     W: Write,
 {
-    writer.write_u8(((angle.0 as i32 * 256 / 360) & 0xFF) as u8)?;
+    writer.write_i8(encode_angle(angle))?;
     Ok(())
 }
 
@@ -2576,10 +3481,213 @@ where
     Ok(())
 }
 
+fn read_baseline_flags<R>(reader: &mut R) -> Result<BaselineFlags, NetError>
+where
+    R: Read,
+{
+    let flags_bits = reader.read_u8()?;
+    BaselineFlags::from_bits(flags_bits)
+        .ok_or_else(|| NetError::invalid_data(format!("BaselineFlags: {:b}", flags_bits)))
+}
+
+/// Reads a model or frame index, which is a short rather than a byte if `large` is set.
+fn read_baseline_index<R>(
+    reader: &mut R,
+    flags: BaselineFlags,
+    large: BaselineFlags,
+) -> io::Result<u16>
+where
+    R: Read,
+{
+    match flags.contains(large) {
+        true => reader.read_u16::<LittleEndian>(),
+        false => Ok(reader.read_u8()? as u16),
+    }
+}
+
+fn baseline_flags(model_id: u16, frame_id: u16) -> BaselineFlags {
+    let mut flags = BaselineFlags::empty();
+    if model_id > u8::MAX as u16 {
+        flags |= BaselineFlags::LARGE_MODEL;
+    }
+    if frame_id > u8::MAX as u16 {
+        flags |= BaselineFlags::LARGE_FRAME;
+    }
+
+    flags
+}
+
+fn write_baseline_index<W>(writer: &mut W, index: u16, large: bool) -> io::Result<()>
+where
+    W: Write,
+{
+    match large {
+        true => writer.write_u16::<LittleEndian>(index),
+        false => writer.write_u8(index as u8),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn test_negotiate_protocol() {
+        // the server's choice wins when the client can speak it
+        assert_eq!(
+            Protocol::negotiate(Protocol::FITZQUAKE, &SUPPORTED_PROTOCOLS),
+            Some(Protocol::FITZQUAKE)
+        );
+        // otherwise the client's favourite
+        assert_eq!(
+            Protocol::negotiate(Protocol::RMQ, &[1234, PROTOCOL_FITZQUAKE, 15]),
+            Some(Protocol::FITZQUAKE)
+        );
+        assert_eq!(
+            Protocol::negotiate(Protocol::FITZQUAKE, &[]),
+            Some(Protocol::NETQUAKE)
+        );
+        assert_eq!(Protocol::negotiate(Protocol::NETQUAKE, &[1234]), None);
+    }
+
+    #[test]
+    fn test_quantize_matches_read() {
+        let protocols = [
+            Protocol::NETQUAKE,
+            Protocol::RMQ,
+            Protocol::new(PROTOCOL_RMQ, ProtocolFlags::COORD_24BIT).unwrap(),
+            Protocol::new(
+                PROTOCOL_RMQ,
+                ProtocolFlags::FLOAT_COORD | ProtocolFlags::FLOAT_ANGLE,
+            )
+            .unwrap(),
+        ];
+
+        for protocol in protocols {
+            for value in [
+                0.0, 0.06, 0.07, -0.07, 123.456, -1000.3, 179.9, 270.0, -359.5,
+            ] {
+                let mut buf = Vec::new();
+                protocol.write_coord(&mut buf, value).unwrap();
+                protocol.write_angle(&mut buf, Deg(value)).unwrap();
+
+                let mut reader = buf.as_slice();
+                assert_eq!(
+                    protocol.read_coord(&mut reader).unwrap(),
+                    protocol.quantize_coord(value)
+                );
+                assert_eq!(
+                    protocol.read_angle(&mut reader).unwrap(),
+                    protocol.quantize_angle(Deg(value))
+                );
+
+                // rounding is never off by more than half a step
+                let error = (protocol.quantize_coord(value) - value).abs();
+                assert!(error <= protocol.coord_precision() / 2.0 + f32::EPSILON);
+            }
+        }
+    }
+
+    #[test]
+    fn test_alpha_scale() {
+        assert_eq!(encode_alpha(0.), ENTITY_ALPHA_DEFAULT);
+        assert_eq!(decode_alpha(encode_alpha(0.)), 1.);
+        assert_eq!(encode_alpha(1.), 255);
+        assert_eq!(decode_alpha(encode_alpha(0.5)), 0.5);
+        assert_eq!(encode_scale(0.), ENTITY_SCALE_DEFAULT);
+        assert_eq!(decode_scale(encode_scale(2.)), 2.);
+        assert_eq!(encode_scale(100.), 255);
+
+        let state = EntityState {
+            alpha: encode_alpha(0.5),
+            scale: encode_scale(2.),
+            ..EntityState::uninitialized()
+        };
+        let baseline = EntityState::uninitialized();
+
+        let update = state
+            .quantize(Protocol::FITZQUAKE)
+            .make_update(1, &baseline);
+        assert_eq!(update.alpha, Some(128));
+        assert_eq!(update.scale, Some(32));
+        assert_eq!(update.to_entity_state(&baseline).scale, 32);
+
+        // the original protocol can't carry them
+        let update = state.quantize(Protocol::NETQUAKE).make_update(1, &baseline);
+        assert_eq!((update.alpha, update.scale), (None, None));
+    }
+
+    #[test]
+    fn test_quantize_rounds_to_nearest() {
+        let protocol = Protocol::NETQUAKE;
+        assert_eq!(protocol.coord_precision(), 0.125);
+        assert_eq!(protocol.quantize_coord(0.07), 0.125);
+        assert_eq!(protocol.quantize_coord(-0.07), -0.125);
+        assert_eq!(protocol.quantize_coord(10.05), 10.0);
+
+        assert_eq!(protocol.angle_precision(), Deg(1.40625));
+        assert_eq!(protocol.quantize_angle(Deg(1.0)), Deg(1.40625));
+        assert_eq!(protocol.quantize_angle(Deg(270.0)), Deg(-90.0));
+
+        let state = EntityState {
+            origin: Vector3::new(0.05, 0.0, 0.0),
+            ..EntityState::uninitialized()
+        };
+        let baseline = EntityState::uninitialized();
+        // too small a change for the client to see
+        assert!(state
+            .quantize(protocol)
+            .make_update(1, &baseline)
+            .origin_x
+            .is_none());
+        assert!(state
+            .quantize(Protocol::RMQ)
+            .make_update(1, &baseline)
+            .origin_x
+            .is_some());
+    }
+
+    #[test]
+    fn test_player_data_rounds_velocity() {
+        let src = ServerCmd::PlayerData(PlayerData {
+            view_height: Some(22.4),
+            ideal_pitch: None,
+            punch_pitch: Some(Deg(-2.6)),
+            velocity_x: Some(25.0),
+            punch_yaw: None,
+            velocity_y: Some(-8.0),
+            punch_roll: None,
+            velocity_z: Some(10000.0),
+            items: ItemFlags::empty(),
+            on_ground: false,
+            in_water: false,
+            weapon_frame: None,
+            armor: None,
+            weapon: None,
+            health: 100,
+            ammo: 0,
+            ammo_shells: 0,
+            ammo_nails: 0,
+            ammo_rockets: 0,
+            ammo_cells: 0,
+            active_weapon: 0,
+        });
+
+        let mut packet = Vec::new();
+        src.serialize(&mut packet).unwrap();
+        let mut reader = BufReader::new(packet.as_slice());
+        let Some(ServerCmd::PlayerData(data)) = ServerCmd::deserialize(&mut reader).unwrap() else {
+            panic!("Expected PlayerData");
+        };
+
+        assert_eq!(data.view_height, Some(22.0));
+        assert_eq!(data.punch_pitch, Some(Deg(-3.0)));
+        assert_eq!(data.velocity_x, Some(32.0));
+        assert_eq!(data.velocity_y, Some(-16.0));
+        // clamped rather than wrapped around to a negative speed
+        assert_eq!(data.velocity_z, Some(127.0 * 16.0));
+    }
+
     #[test]
     fn test_server_cmd_update_stat_read_write_eq() {
         let src = ServerCmd::UpdateStat {
@@ -2666,8 +3774,9 @@ mod test {
             max_clients: 16,
             game_type: GameType::Deathmatch,
             message: QString::from("Test message"),
-            model_precache: vec![QString::from("test1.bsp"), QString::from("test2.bsp")],
-            sound_precache: vec![QString::from("test1.wav"), QString::from("test2.wav")],
+            protocol_flags: ProtocolFlags::empty(),
+            model_precache: vec![String::from("test1.bsp"), String::from("test2.bsp")],
+            sound_precache: vec![String::from("test1.wav"), String::from("test2.wav")],
         };
 
         let mut packet = Vec::new();
@@ -2827,6 +3936,29 @@ mod test {
         assert_eq!(src, dst);
     }
 
+    #[test]
+    fn test_server_cmd_download_read_write_eq() {
+        for src in [
+            ServerCmd::Download {
+                size: Some(3000),
+                offset: 2048,
+                data: (0..=255).cycle().take(952).collect(),
+            },
+            ServerCmd::Download {
+                size: None,
+                offset: 0,
+                data: Vec::new(),
+            },
+        ] {
+            let mut packet = Vec::new();
+            src.serialize(&mut packet).unwrap();
+            let mut reader = BufReader::new(packet.as_slice());
+            let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+
+            assert_eq!(src, dst);
+        }
+    }
+
     #[test]
     fn test_client_cmd_string_cmd_read_write_eq() {
         let src = ClientCmd::StringCmd {
@@ -2835,7 +3967,7 @@ mod test {
         let mut packet = Vec::new();
         src.serialize(&mut packet).unwrap();
         let mut reader = BufReader::new(packet.as_slice());
-        let dst = ClientCmd::deserialize(&mut reader).unwrap();
+        let dst = ClientCmd::deserialize(&mut reader).unwrap().unwrap();
 
         assert_eq!(src, dst);
     }
@@ -2856,7 +3988,7 @@ mod test {
         let mut packet = Vec::new();
         src.serialize(&mut packet).unwrap();
         let mut reader = BufReader::new(packet.as_slice());
-        let dst = ClientCmd::deserialize(&mut reader).unwrap();
+        let dst = ClientCmd::deserialize(&mut reader).unwrap().unwrap();
 
         assert_eq!(src, dst);
     }
@@ -2886,6 +4018,19 @@ mod test {
         // TODO: assert can_send == true, send_next == false, etc
     }
 
+    #[test]
+    fn test_qsocket_send_msg_compressed() {
+        let (mut src, mut dst) = gen_qsocket_pair();
+        src.set_compression(true);
+        dst.set_compression(true);
+
+        // too long to send uncompressed, but it compresses well enough to fit
+        let message: Vec<u8> = (0..MAX_MESSAGE * 2).map(|i| (i % 16) as u8).collect();
+        src.begin_send_msg(&message).unwrap();
+        let received = dst.recv_msg(BlockingMode::Blocking).unwrap();
+        assert_eq!(message, received);
+    }
+
     #[test]
     fn test_qsocket_send_msg_unreliable_recv_msg_eq() {
         let (mut src, mut dst) = gen_qsocket_pair();
@@ -0,0 +1,88 @@
+//! The ways a [`QSocket`](super::QSocket) can reach its peer.
+//!
+//! A `QSocket` only needs to send a packet to the other end of the connection and receive the
+//! packets that come back, so anything that can carry whole packets in both directions will do.
+//! Native builds use UDP like the original engine, while browsers, which can't open UDP sockets,
+//! use a WebSocket instead (see the `websocket` module).
+
+use std::{
+    io::ErrorKind,
+    net::{SocketAddr, UdpSocket},
+};
+
+use bevy::prelude::*;
+
+use crate::common::net::{std_timeout, BlockingMode, NetError};
+
+/// Carries the packets of a connection to and from a single peer.
+pub trait Transport: Send + Sync {
+    /// Sends a packet to the peer.
+    fn send(&mut self, packet: &[u8]) -> Result<(), NetError>;
+
+    /// Receives a packet from the peer into `buf` and returns its length, or `None` if nothing
+    /// arrived in the time allowed by `block`.
+    ///
+    /// Transports that can't wait for a packet, such as a WebSocket in a browser, may return
+    /// `None` at once whatever `block` says.
+    fn recv(&mut self, buf: &mut [u8], block: &BlockingMode) -> Result<Option<usize>, NetError>;
+}
+
+/// A transport over UDP, which only accepts packets that come from the peer's address.
+pub struct UdpTransport {
+    socket: UdpSocket,
+    remote: SocketAddr,
+}
+
+impl UdpTransport {
+    pub fn new(socket: UdpSocket, remote: SocketAddr) -> UdpTransport {
+        UdpTransport { socket, remote }
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send(&mut self, packet: &[u8]) -> Result<(), NetError> {
+        self.socket.send_to(packet, self.remote)?;
+        Ok(())
+    }
+
+    fn recv(&mut self, buf: &mut [u8], block: &BlockingMode) -> Result<Option<usize>, NetError> {
+        match block {
+            BlockingMode::Blocking => {
+                self.socket.set_nonblocking(false)?;
+                self.socket.set_read_timeout(None)?;
+            }
+
+            BlockingMode::NonBlocking => {
+                self.socket.set_nonblocking(true)?;
+                self.socket.set_read_timeout(None)?;
+            }
+
+            BlockingMode::Timeout(d) => {
+                self.socket.set_nonblocking(false)?;
+                self.socket.set_read_timeout(Some(std_timeout(*d)?))?;
+            }
+        }
+
+        loop {
+            let (len, src_addr) = match self.socket.recv_from(buf) {
+                Ok(x) => x,
+                // these errors are expected in nonblocking mode
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            if src_addr != self.remote {
+                // this packet didn't come from remote, drop it
+                debug!(
+                    "forged packet (src_addr was {}, should be {})",
+                    src_addr, self.remote
+                );
+                continue;
+            }
+
+            return Ok(Some(len));
+        }
+    }
+}
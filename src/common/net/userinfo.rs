@@ -0,0 +1,230 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Userinfo strings describe the user-configurable properties of a client (name, colors, etc.).
+//!
+//! They are sent to the server in full while signing on, after which individual keys are updated
+//! with `setinfo`. The serialized form is the QuakeWorld one: `\key\value\key\value`.
+
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use crate::common::net::{NetError, PlayerColor};
+
+/// The maximum length of a serialized userinfo string.
+pub const MAX_USERINFO_LEN: usize = 512;
+
+pub const USERINFO_NAME: &str = "name";
+pub const USERINFO_COLORS: &str = "colors";
+pub const USERINFO_RATE: &str = "rate";
+pub const USERINFO_SKIN: &str = "skin";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UserInfo {
+    values: BTreeMap<String, String>,
+}
+
+impl UserInfo {
+    pub fn new() -> UserInfo {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|v| &v[..])
+    }
+
+    /// Set a key, returning the previous value if there was one.
+    ///
+    /// Setting a key to an empty string removes it.
+    pub fn set<K, V>(&mut self, key: K, value: V) -> Result<Option<String>, NetError>
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let key = key.into();
+        let value = value.into();
+
+        for s in [&key, &value] {
+            if s.contains(['\\', '"', '\n']) {
+                return Err(NetError::invalid_data(format!(
+                    "Invalid character in userinfo: {:?}",
+                    s
+                )));
+            }
+        }
+
+        if key.is_empty() {
+            return Err(NetError::invalid_data("Empty userinfo key"));
+        }
+
+        if value.is_empty() {
+            return Ok(self.values.remove(&key));
+        }
+
+        let old = self.values.insert(key.clone(), value);
+
+        if self.serialized_len() > MAX_USERINFO_LEN {
+            match old {
+                Some(old) => self.values.insert(key, old),
+                None => self.values.remove(&key),
+            };
+
+            return Err(NetError::invalid_data("Userinfo string too long"));
+        }
+
+        Ok(old)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.values.remove(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> + '_ {
+        self.values.iter().map(|(k, v)| (&k[..], &v[..]))
+    }
+
+    /// Returns the keys whose values differ between `self` and `other`, along with the value in
+    /// `self` (or an empty string if the key was removed).
+    pub fn diff<'a>(&'a self, other: &'a UserInfo) -> impl Iterator<Item = (&'a str, &'a str)> {
+        let changed = self.iter().filter(move |&(k, v)| other.get(k) != Some(v));
+        let removed = other
+            .values
+            .keys()
+            .filter(move |k| !self.values.contains_key(*k))
+            .map(|k| (&k[..], ""));
+
+        changed.chain(removed)
+    }
+
+    pub fn name(&self) -> &str {
+        self.get(USERINFO_NAME).unwrap_or("player")
+    }
+
+    pub fn colors(&self) -> PlayerColor {
+        PlayerColor::from_bits(
+            self.get(USERINFO_COLORS)
+                .and_then(|c| c.parse::<f32>().ok())
+                .unwrap_or(0.) as u8,
+        )
+    }
+
+    pub fn rate(&self) -> Option<u32> {
+        self.get(USERINFO_RATE)
+            .and_then(|r| r.parse::<f32>().ok())
+            .map(|r| r as u32)
+    }
+
+    pub fn skin(&self) -> Option<&str> {
+        self.get(USERINFO_SKIN)
+    }
+
+    fn serialized_len(&self) -> usize {
+        self.values.iter().map(|(k, v)| k.len() + v.len() + 2).sum()
+    }
+}
+
+impl fmt::Display for UserInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (k, v) in self.iter() {
+            write!(f, "\\{}\\{}", k, v)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for UserInfo {
+    type Err = NetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut out = UserInfo::new();
+
+        if s.is_empty() {
+            return Ok(out);
+        }
+
+        let Some(s) = s.strip_prefix('\\') else {
+            return Err(NetError::invalid_data(
+                "Userinfo string must start with a backslash",
+            ));
+        };
+
+        let mut parts = s.split('\\');
+        while let Some(key) = parts.next() {
+            let Some(value) = parts.next() else {
+                return Err(NetError::invalid_data(format!(
+                    "Userinfo key {:?} has no value",
+                    key
+                )));
+            };
+
+            out.set(key, value)?;
+        }
+
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_userinfo_round_trip() {
+        let mut info = UserInfo::new();
+        info.set(USERINFO_NAME, "ranger").unwrap();
+        info.set(USERINFO_COLORS, "77").unwrap();
+        info.set(USERINFO_RATE, "10000").unwrap();
+
+        let parsed = info.to_string().parse::<UserInfo>().unwrap();
+        assert_eq!(info, parsed);
+        assert_eq!(parsed.name(), "ranger");
+        assert_eq!(parsed.colors(), PlayerColor::new(4, 13));
+        assert_eq!(parsed.rate(), Some(10000));
+        assert_eq!(parsed.skin(), None);
+    }
+
+    #[test]
+    fn test_userinfo_invalid() {
+        let mut info = UserInfo::new();
+        assert!(info.set(USERINFO_NAME, "a\\b").is_err());
+        assert!(info.set("", "value").is_err());
+        assert!(info
+            .set(USERINFO_NAME, "x".repeat(MAX_USERINFO_LEN))
+            .is_err());
+        assert!(info.iter().next().is_none());
+
+        assert!("name\\ranger".parse::<UserInfo>().is_err());
+        assert!("\\name".parse::<UserInfo>().is_err());
+    }
+
+    #[test]
+    fn test_userinfo_diff() {
+        let mut old = UserInfo::new();
+        old.set(USERINFO_NAME, "ranger").unwrap();
+        old.set(USERINFO_SKIN, "base").unwrap();
+
+        let mut new = old.clone();
+        new.set(USERINFO_NAME, "shambler").unwrap();
+        new.remove(USERINFO_SKIN);
+
+        let diff = new.diff(&old).collect::<Vec<_>>();
+        assert_eq!(diff, [(USERINFO_NAME, "shambler"), (USERINFO_SKIN, "")]);
+    }
+}
@@ -0,0 +1,563 @@
+//! A transport over WebSockets, for clients running in a browser.
+//!
+//! Each packet that would be a UDP datagram is sent as one binary WebSocket message, so the rest
+//! of the protocol is unchanged. Connecting works as it does over UDP, except that the server
+//! answers on the same WebSocket rather than sending the client to a new port: the client's first
+//! message is its connect request, and the server's first message is its response.
+//!
+//! In a browser the socket is one of the browser's own, which can't block, so a client waiting
+//! for a response has to keep checking for it. Native builds with the `websocket` feature use the
+//! `tungstenite` crate instead, which also provides the listener that servers accept these
+//! clients with on `sv_wsport`. Only browsers can open secure (`wss://`) connections.
+
+use crate::common::net::{
+    connect::{ConnectPacket as _, Request, Response, DEFAULT_PORT},
+    transport::Transport,
+    BlockingMode, NetError,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::native::{WebSocketListener, WebSocketTransport};
+
+#[cfg(target_arch = "wasm32")]
+pub use self::browser::WebSocketTransport;
+
+/// Parses a `ws://host[:port]` or `wss://host[:port]` address into the URL of a WebSocket,
+/// adding the default port if there isn't one.
+pub fn parse_address(remote: &str) -> Option<String> {
+    let (scheme, rest) = remote.split_once("://")?;
+    if scheme != "ws" && scheme != "wss" {
+        return None;
+    }
+
+    let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    if host.is_empty() {
+        return None;
+    }
+
+    // IPv6 addresses have colons of their own, so only a colon after the brackets is a port
+    let has_port = match host.rfind(']') {
+        Some(i) => host[i..].contains(':'),
+        None => host.contains(':'),
+    };
+
+    if has_port {
+        Some(remote.to_owned())
+    } else {
+        Some(format!("{}://{}:{}{}", scheme, host, DEFAULT_PORT, path))
+    }
+}
+
+impl WebSocketTransport {
+    pub fn send_request(&mut self, request: &Request) -> Result<(), NetError> {
+        self.send(&request.to_bytes()?)
+    }
+
+    /// Receives the connect request a client sends once its WebSocket is open.
+    pub fn recv_request(&mut self, block: &BlockingMode) -> Result<Option<Request>, NetError> {
+        let mut buf = [0; MAX_CONNECT_PACKET];
+        match self.recv(&mut buf, block)? {
+            Some(len) => Request::from_bytes(&buf[..len]).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn send_response(&mut self, response: &Response) -> Result<(), NetError> {
+        self.send(&response.to_bytes()?)
+    }
+
+    /// Receives the server's response to a connect request.
+    pub fn recv_response(&mut self, block: &BlockingMode) -> Result<Option<Response>, NetError> {
+        let mut buf = [0; MAX_CONNECT_PACKET];
+        match self.recv(&mut buf, block)? {
+            Some(len) => Response::from_bytes(&buf[..len]).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Connection packets are limited to this length by their header.
+const MAX_CONNECT_PACKET: usize = 0xFFFF;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use std::{
+        collections::VecDeque,
+        io::ErrorKind,
+        mem,
+        net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+        time::{Duration, Instant},
+    };
+
+    use bevy::log::debug;
+
+    use crate::common::net::std_timeout;
+    use tungstenite::{
+        handshake::{server::NoCallback, MidHandshake},
+        http::Uri,
+        HandshakeError, Message, ServerHandshake, WebSocket,
+    };
+
+    use super::*;
+
+    /// How long a new client has to complete the WebSocket handshake.
+    const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn ws_error(e: tungstenite::Error) -> NetError {
+        match e {
+            tungstenite::Error::Io(e) => e.into(),
+            e => NetError::with_msg(format!("WebSocket error: {}", e)),
+        }
+    }
+
+    fn would_block(e: &tungstenite::Error) -> bool {
+        matches!(
+            e,
+            tungstenite::Error::Io(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+        )
+    }
+
+    pub struct WebSocketTransport {
+        socket: WebSocket<TcpStream>,
+    }
+
+    impl WebSocketTransport {
+        /// Opens a WebSocket to the server at `url`, as returned by [`parse_address`].
+        pub fn connect(url: &str) -> Result<WebSocketTransport, NetError> {
+            let uri = url
+                .parse::<Uri>()
+                .map_err(|e| NetError::with_msg(format!("Invalid WebSocket address: {}", e)))?;
+            if uri.scheme_str() != Some("ws") {
+                return Err(NetError::with_msg(
+                    "Only ws:// addresses can be connected to outside a browser",
+                ));
+            }
+            let host = uri
+                .host()
+                .ok_or_else(|| NetError::with_msg("WebSocket address has no host"))?;
+
+            let stream = TcpStream::connect((host, uri.port_u16().unwrap_or(DEFAULT_PORT)))?;
+            stream.set_nodelay(true)?;
+            let (socket, _) = tungstenite::client(url, stream)
+                .map_err(|e| NetError::with_msg(format!("WebSocket handshake failed: {}", e)))?;
+
+            Ok(WebSocketTransport { socket })
+        }
+    }
+
+    impl Transport for WebSocketTransport {
+        fn send(&mut self, packet: &[u8]) -> Result<(), NetError> {
+            match self.socket.send(Message::Binary(packet.to_vec())) {
+                // the message is queued, and goes out with the next one
+                Err(e) if would_block(&e) => Ok(()),
+                res => res.map_err(ws_error),
+            }
+        }
+
+        fn recv(
+            &mut self,
+            buf: &mut [u8],
+            block: &BlockingMode,
+        ) -> Result<Option<usize>, NetError> {
+            let stream = self.socket.get_ref();
+            match block {
+                BlockingMode::Blocking => {
+                    stream.set_nonblocking(false)?;
+                    stream.set_read_timeout(None)?;
+                }
+
+                BlockingMode::NonBlocking => {
+                    stream.set_nonblocking(true)?;
+                    stream.set_read_timeout(None)?;
+                }
+
+                BlockingMode::Timeout(d) => {
+                    stream.set_nonblocking(false)?;
+                    stream.set_read_timeout(Some(std_timeout(*d)?))?;
+                }
+            }
+
+            loop {
+                match self.socket.read() {
+                    Ok(Message::Binary(packet)) => {
+                        let len = packet.len();
+                        if len > buf.len() {
+                            return Err(NetError::invalid_data(format!(
+                                "WebSocket packet too long ({} bytes)",
+                                len
+                            )));
+                        }
+
+                        buf[..len].copy_from_slice(&packet);
+                        return Ok(Some(len));
+                    }
+
+                    Ok(Message::Close(_)) => {
+                        return Err(NetError::with_msg("WebSocket closed by peer"))
+                    }
+
+                    // pings are answered by tungstenite, and nothing is sent as text
+                    Ok(_) => continue,
+
+                    Err(e) if would_block(&e) => return Ok(None),
+                    Err(e) => return Err(ws_error(e)),
+                }
+            }
+        }
+    }
+
+    type Handshake = ServerHandshake<TcpStream, NoCallback>;
+
+    /// A handshake that is waiting on the client.
+    struct PendingHandshake {
+        handshake: MidHandshake<Handshake>,
+        remote: SocketAddr,
+        started: Instant,
+    }
+
+    /// A socket that accepts clients connecting over WebSockets.
+    ///
+    /// Nothing here blocks: a handshake that can't finish yet is carried on by the next call to
+    /// [`accept`](WebSocketListener::accept), so a slow or silent client can't hold up the server.
+    pub struct WebSocketListener {
+        listener: TcpListener,
+        handshakes: Vec<PendingHandshake>,
+        /// Clients whose handshakes have finished but that haven't been returned yet.
+        accepted: VecDeque<(WebSocketTransport, SocketAddr)>,
+    }
+
+    impl WebSocketListener {
+        pub fn bind<A>(addr: A) -> Result<WebSocketListener, NetError>
+        where
+            A: ToSocketAddrs,
+        {
+            let listener = TcpListener::bind(addr)?;
+            listener.set_nonblocking(true)?;
+
+            Ok(WebSocketListener {
+                listener,
+                handshakes: Vec::new(),
+                accepted: VecDeque::new(),
+            })
+        }
+
+        /// The address the listener is bound to, including the port chosen by the OS for port 0.
+        pub fn local_addr(&self) -> Result<SocketAddr, NetError> {
+            Ok(self.listener.local_addr()?)
+        }
+
+        /// Returns a client that has finished its handshake, or `None` at once if there are none.
+        /// The client's connect request can then be read with
+        /// [`WebSocketTransport::recv_request`].
+        pub fn accept(&mut self) -> Result<Option<(WebSocketTransport, SocketAddr)>, NetError> {
+            for PendingHandshake {
+                handshake,
+                remote,
+                started,
+            } in mem::take(&mut self.handshakes)
+            {
+                self.carry_on(handshake.handshake(), remote, started);
+            }
+
+            loop {
+                let (stream, remote) = match self.listener.accept() {
+                    Ok(x) => x,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(e) => return Err(e.into()),
+                };
+
+                // accepted streams don't take after the listener
+                if let Err(e) = stream
+                    .set_nonblocking(true)
+                    .and_then(|()| stream.set_nodelay(true))
+                {
+                    debug!("Dropped WebSocket client {}: {}", remote, e);
+                    continue;
+                }
+
+                self.carry_on(tungstenite::accept(stream), remote, Instant::now());
+            }
+
+            Ok(self.accepted.pop_front())
+        }
+
+        fn carry_on(
+            &mut self,
+            result: Result<WebSocket<TcpStream>, HandshakeError<Handshake>>,
+            remote: SocketAddr,
+            started: Instant,
+        ) {
+            match result {
+                Ok(socket) => self
+                    .accepted
+                    .push_back((WebSocketTransport { socket }, remote)),
+
+                Err(HandshakeError::Interrupted(handshake)) => {
+                    if started.elapsed() < HANDSHAKE_TIMEOUT {
+                        self.handshakes.push(PendingHandshake {
+                            handshake,
+                            remote,
+                            started,
+                        });
+                    } else {
+                        debug!("WebSocket handshake with {} timed out", remote);
+                    }
+                }
+
+                Err(HandshakeError::Failure(e)) => {
+                    debug!("WebSocket handshake with {} failed: {}", remote, e)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod browser {
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+    use js_sys::{ArrayBuffer, Uint8Array};
+    use wasm_bindgen::{closure::Closure, JsCast as _, JsValue};
+    use web_sys::{BinaryType, CloseEvent, MessageEvent, WebSocket};
+
+    use super::*;
+
+    fn js_error(e: JsValue) -> NetError {
+        NetError::with_msg(format!("WebSocket error: {:?}", e))
+    }
+
+    /// What the socket's callbacks have received.
+    #[derive(Default)]
+    struct Received {
+        packets: VecDeque<Vec<u8>>,
+        /// Why the socket closed, once it has.
+        closed: Option<String>,
+    }
+
+    pub struct WebSocketTransport {
+        socket: WebSocket,
+        received: Rc<RefCell<Received>>,
+        /// Packets sent before the socket finished opening.
+        unsent: Vec<Vec<u8>>,
+        _on_message: Closure<dyn FnMut(MessageEvent)>,
+        _on_close: Closure<dyn FnMut(CloseEvent)>,
+    }
+
+    // wasm32 runs on a single thread, so the socket and its callbacks are never shared between
+    // threads
+    unsafe impl Send for WebSocketTransport {}
+    unsafe impl Sync for WebSocketTransport {}
+
+    impl WebSocketTransport {
+        /// Opens a WebSocket to the server at `url`, as returned by [`parse_address`]. The socket
+        /// opens in the background, and anything sent before then is sent when it does.
+        pub fn connect(url: &str) -> Result<WebSocketTransport, NetError> {
+            let socket = WebSocket::new(url).map_err(js_error)?;
+            socket.set_binary_type(BinaryType::Arraybuffer);
+
+            let received = Rc::new(RefCell::new(Received::default()));
+
+            let on_message = {
+                let received = received.clone();
+                Closure::<dyn FnMut(MessageEvent)>::new(move |e: MessageEvent| {
+                    if let Ok(data) = e.data().dyn_into::<ArrayBuffer>() {
+                        let packet = Uint8Array::new(&data).to_vec();
+                        received.borrow_mut().packets.push_back(packet);
+                    }
+                })
+            };
+            socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+            let on_close = {
+                let received = received.clone();
+                Closure::<dyn FnMut(CloseEvent)>::new(move |e: CloseEvent| {
+                    received.borrow_mut().closed =
+                        Some(format!("WebSocket closed ({}) {}", e.code(), e.reason()));
+                })
+            };
+            socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+
+            Ok(WebSocketTransport {
+                socket,
+                received,
+                unsent: Vec::new(),
+                _on_message: on_message,
+                _on_close: on_close,
+            })
+        }
+
+        fn check_closed(&self) -> Result<(), NetError> {
+            match &self.received.borrow().closed {
+                Some(reason) => Err(NetError::with_msg(reason.clone())),
+                None => Ok(()),
+            }
+        }
+
+        /// Sends the packets that were waiting for the socket to open, if it has.
+        fn send_unsent(&mut self) -> Result<(), NetError> {
+            if self.socket.ready_state() == WebSocket::OPEN {
+                for packet in self.unsent.drain(..) {
+                    self.socket.send_with_u8_array(&packet).map_err(js_error)?;
+                }
+            }
+
+            Ok(())
+        }
+    }
+
+    impl Transport for WebSocketTransport {
+        fn send(&mut self, packet: &[u8]) -> Result<(), NetError> {
+            self.check_closed()?;
+
+            if self.socket.ready_state() == WebSocket::CONNECTING {
+                self.unsent.push(packet.to_vec());
+                return Ok(());
+            }
+
+            self.send_unsent()?;
+            self.socket.send_with_u8_array(packet).map_err(js_error)
+        }
+
+        // the browser delivers messages between frames, so there's no point waiting for one
+        fn recv(
+            &mut self,
+            buf: &mut [u8],
+            _block: &BlockingMode,
+        ) -> Result<Option<usize>, NetError> {
+            self.send_unsent()?;
+
+            let Some(packet) = self.received.borrow_mut().packets.pop_front() else {
+                self.check_closed()?;
+                return Ok(None);
+            };
+
+            let len = packet.len();
+            if len > buf.len() {
+                return Err(NetError::invalid_data(format!(
+                    "WebSocket packet too long ({} bytes)",
+                    len
+                )));
+            }
+
+            buf[..len].copy_from_slice(&packet);
+            Ok(Some(len))
+        }
+    }
+
+    impl Drop for WebSocketTransport {
+        fn drop(&mut self) {
+            // the callbacks are about to be freed, so the socket mustn't call them
+            self.socket.set_onmessage(None);
+            self.socket.set_onclose(None);
+            let _ = self.socket.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(
+            parse_address("ws://example.com"),
+            Some(format!("ws://example.com:{}", DEFAULT_PORT))
+        );
+        assert_eq!(
+            parse_address("wss://example.com/quake"),
+            Some(format!("wss://example.com:{}/quake", DEFAULT_PORT))
+        );
+        assert_eq!(
+            parse_address("ws://127.0.0.1:27500"),
+            Some("ws://127.0.0.1:27500".to_owned())
+        );
+        assert_eq!(
+            parse_address("ws://[::1]"),
+            Some(format!("ws://[::1]:{}", DEFAULT_PORT))
+        );
+        assert_eq!(
+            parse_address("ws://[::1]:80"),
+            Some("ws://[::1]:80".to_owned())
+        );
+        assert_eq!(parse_address("qw://example.com"), None);
+        assert_eq!(parse_address("example.com:26000"), None);
+        assert_eq!(parse_address("ws://"), None);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_websocket_connection() {
+        use std::thread;
+
+        use crate::common::net::{
+            connect::{ConnectFlags, ResponseAccept},
+            QSocket,
+        };
+
+        let mut listener = WebSocketListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+
+        let client = thread::spawn(move || {
+            let mut transport = WebSocketTransport::connect(&url).unwrap();
+            transport
+                .send_request(&Request::connect("QUAKE", 3, &[15], ConnectFlags::empty()))
+                .unwrap();
+            let response = transport
+                .recv_response(&BlockingMode::Blocking)
+                .unwrap()
+                .unwrap();
+            assert!(matches!(response, Response::Accept(_)));
+
+            let mut qsock = QSocket::with_transport(Box::new(transport));
+            qsock.begin_send_msg(b"\x04prespawn\0").unwrap();
+            qsock.recv_msg(BlockingMode::Blocking).unwrap()
+        });
+
+        let (mut transport, _) = loop {
+            if let Some(accepted) = listener.accept().unwrap() {
+                break accepted;
+            }
+            thread::yield_now();
+        };
+        let request = transport
+            .recv_request(&BlockingMode::Blocking)
+            .unwrap()
+            .unwrap();
+        assert!(matches!(request, Request::Connect(_)));
+        transport
+            .send_response(&Response::Accept(ResponseAccept {
+                port: 0,
+                protocol: Some(15),
+                flags: ConnectFlags::empty(),
+            }))
+            .unwrap();
+
+        // from here on the connection works just as it does over UDP
+        let mut qsock = QSocket::with_transport(Box::new(transport));
+        assert_eq!(
+            qsock.recv_msg(BlockingMode::Blocking).unwrap(),
+            b"\x04prespawn\0"
+        );
+        qsock.send_msg_unreliable(b"\x01").unwrap();
+
+        // the client skips the ack for its message and gets the unreliable one
+        assert_eq!(client.join().unwrap(), b"\x01");
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_accept_doesnt_wait_for_handshake() {
+        use std::{io::Write as _, net::TcpStream, time::Instant};
+
+        let mut listener = WebSocketListener::bind("127.0.0.1:0").unwrap();
+        let mut silent = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        silent.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+
+        let start = Instant::now();
+        for _ in 0..10 {
+            assert!(listener.accept().unwrap().is_none());
+        }
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+    }
+}
@@ -322,6 +322,11 @@ impl Pak {
             .ok_or(PakError::NoSuchFile(path.to_owned()))
     }
 
+    /// Returns the contents of the whole archive.
+    pub fn bytes(&self) -> &[u8] {
+        self.memory.as_ref()
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (&Path, &[u8])> + '_ {
         self.entries.iter().filter_map(move |(path, e)| {
             if let PakEntry::File(range) = e {
@@ -0,0 +1,3 @@
+//! Physics shared by the client and server.
+
+pub mod pmove;
@@ -0,0 +1,862 @@
+//! Player movement.
+//!
+//! The server moves players with this code, and the client runs the same code to predict where
+//! the server will put them, so the two never disagree about how the player moves. It follows
+//! `SV_ClientThink` and `SV_WalkMove` from the original engine: friction and acceleration on the
+//! ground, a little air control, swimming, and stepping up stairs. Anything the movement needs to
+//! know about the world it asks a [`MoveWorld`] for.
+
+use bitflags::bitflags;
+use cgmath::{Angle as _, Deg, InnerSpace as _, Vector3, Zero as _};
+use serde::Deserialize;
+
+use crate::{
+    common::{
+        bsp::{BspLeafContents, LiquidKind},
+        math::Angles,
+    },
+    server::world::Trace,
+};
+
+/// The smallest corner of the player's bounding box, relative to their origin.
+pub const PLAYER_MIN: Vector3<f32> = Vector3::new(-16.0, -16.0, -24.0);
+
+/// The largest corner of the player's bounding box, relative to their origin.
+pub const PLAYER_MAX: Vector3<f32> = Vector3::new(16.0, 16.0, 32.0);
+
+/// The height of the player's eyes above their origin.
+pub const VIEW_HEIGHT: f32 = 22.0;
+
+/// The tallest step the player can walk up.
+pub const STEP_SIZE: f32 = 18.0;
+
+/// Surfaces at least this close to flat can be stood on. Steeper ones are slid down.
+const MIN_GROUND_NORMAL: f32 = 0.7;
+
+/// Velocity components slower than this after a collision are stopped outright, so that nothing
+/// slides along a surface forever.
+const STOP_EPSILON: f32 = 0.1;
+
+/// The most times one move can hit something and carry on in a new direction.
+const MAX_BUMPS: usize = 4;
+
+/// The most surfaces a move can be held against at once before it gives up.
+const MAX_CLIP_PLANES: usize = 5;
+
+/// The fastest the player can steer themselves while in the air.
+const MAX_AIR_SPEED: f32 = 30.0;
+
+/// How fast the player can swim, compared to how fast they can run.
+const WATER_SPEED_FACTOR: f32 = 0.7;
+
+/// How fast a player who isn't swimming anywhere sinks.
+const WATER_SINK_SPEED: f32 = 60.0;
+
+/// How far a trace steps past a change of contents before it carries on.
+const CONTENTS_EPSILON: f32 = 0.03125;
+
+/// The most changes of contents a trace passes through before it stops.
+const MAX_CONTENTS_CHANGES: usize = 8;
+
+bitflags! {
+    /// What a move ran into.
+    pub struct CollisionFlags: u32 {
+        /// A floor, or something else flat enough to stand on.
+        const HORIZONTAL = 1;
+        /// A wall, or the riser of a step.
+        const VERTICAL = 2;
+        /// Something that stopped the move entirely.
+        const STOPPED = 4;
+    }
+}
+
+/// The server's movement settings.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub struct MoveVars {
+    #[serde(rename(deserialize = "sv_gravity"))]
+    pub gravity: f32,
+    #[serde(rename(deserialize = "sv_stopspeed"))]
+    pub stop_speed: f32,
+    #[serde(rename(deserialize = "sv_maxspeed"))]
+    pub max_speed: f32,
+    #[serde(rename(deserialize = "sv_accelerate"))]
+    pub accelerate: f32,
+    #[serde(rename(deserialize = "sv_friction"))]
+    pub friction: f32,
+}
+
+impl Default for MoveVars {
+    fn default() -> Self {
+        MoveVars {
+            gravity: 800.0,
+            stop_speed: 100.0,
+            max_speed: 320.0,
+            accelerate: 10.0,
+            friction: 4.0,
+        }
+    }
+}
+
+/// The movement the player asked for in one frame.
+#[derive(Copy, Clone, Debug)]
+pub struct MoveCmd {
+    /// The direction the player is looking.
+    pub angles: Angles,
+    pub forward: f32,
+    pub side: f32,
+    pub up: f32,
+}
+
+/// Where the player is and how they're moving.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PlayerState {
+    pub origin: Vector3<f32>,
+    pub velocity: Vector3<f32>,
+    pub on_ground: bool,
+    /// How deep the player is in liquid: 0 for not at all, 1 for up to their feet, 2 for up to
+    /// their waist and 3 for over their head.
+    pub water_level: u8,
+}
+
+impl PlayerState {
+    pub fn new(origin: Vector3<f32>) -> PlayerState {
+        PlayerState {
+            origin,
+            velocity: Vector3::zero(),
+            on_ground: false,
+            water_level: 0,
+        }
+    }
+}
+
+/// Where a trace of the player's bounding box stopped.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MoveTrace {
+    pub end: Vector3<f32>,
+    /// How far along the trace `end` is, from 0 at the start to 1 if nothing was in the way.
+    pub fraction: f32,
+    /// The normal of the surface that was hit, if any.
+    pub normal: Option<Vector3<f32>>,
+    /// The trace started inside something solid, and so couldn't move at all.
+    pub all_solid: bool,
+}
+
+/// The world that the player moves through.
+pub trait MoveWorld {
+    type Error;
+
+    /// Traces the player's bounding box from `start` towards `end`, stopping at the first solid
+    /// surface. Liquids don't stop the trace.
+    fn trace(&mut self, start: Vector3<f32>, end: Vector3<f32>) -> Result<MoveTrace, Self::Error>;
+
+    /// Returns the contents of the world at `point`.
+    fn contents(&mut self, point: Vector3<f32>) -> Result<BspLeafContents, Self::Error>;
+}
+
+/// Traces from `start` to `end` with `trace`, carrying on through liquids and other changes of
+/// contents that aren't solid.
+///
+/// Collision hulls stop a trace wherever the contents change, which is the right thing for
+/// projectiles but not for a player wading into water.
+pub fn solid_trace<F, E>(
+    mut trace: F,
+    start: Vector3<f32>,
+    end: Vector3<f32>,
+) -> Result<MoveTrace, E>
+where
+    F: FnMut(Vector3<f32>, Vector3<f32>) -> Result<Trace, E>,
+{
+    let length = (end - start).magnitude();
+    let fraction_at = |point: Vector3<f32>| match length {
+        l if l > 0.0 => ((point - start).magnitude() / l).min(1.0),
+        _ => 1.0,
+    };
+
+    let first = trace(start, end)?;
+    if first.all_solid() {
+        return Ok(MoveTrace {
+            end: start,
+            fraction: 0.0,
+            normal: None,
+            all_solid: true,
+        });
+    }
+
+    let mut current = first;
+    for _ in 0..MAX_CONTENTS_CHANGES {
+        let Some(plane) = current.plane().cloned() else {
+            // nothing in the way
+            return Ok(MoveTrace {
+                end,
+                fraction: 1.0,
+                normal: None,
+                all_solid: false,
+            });
+        };
+
+        // step over the boundary to find out what's on the other side
+        let boundary = current.end_point();
+        let remaining = end - boundary;
+        if remaining.magnitude() <= CONTENTS_EPSILON {
+            break;
+        }
+
+        let next = trace(boundary + remaining.normalize() * CONTENTS_EPSILON, end)?;
+        if next.all_solid() {
+            return Ok(MoveTrace {
+                end: boundary,
+                fraction: fraction_at(boundary),
+                normal: Some(plane.normal()),
+                all_solid: false,
+            });
+        }
+
+        current = next;
+    }
+
+    let stop = current.end_point();
+    Ok(MoveTrace {
+        end: stop,
+        fraction: fraction_at(stop),
+        normal: current.plane().map(|p| p.normal()),
+        all_solid: false,
+    })
+}
+
+/// Returns the forward, right and up vectors for `angles`, as `AngleVectors` does.
+pub fn angle_vectors(angles: Angles) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+    let (sy, cy) = angles.yaw.sin_cos();
+    let (sp, cp) = angles.pitch.sin_cos();
+    let (sr, cr) = angles.roll.sin_cos();
+
+    let forward = Vector3::new(cp * cy, cp * sy, -sp);
+    let right = Vector3::new(-sr * sp * cy + cr * sy, -sr * sp * sy - cr * cy, -sr * cp);
+    let up = Vector3::new(cr * sp * cy + sr * sy, cr * sp * sy - sr * cy, cr * cp);
+
+    (forward, right, up)
+}
+
+/// Removes the part of `velocity` going into a surface with normal `normal`.
+///
+/// `overbounce` approximates the elasticity of the collision. A value of `1` reduces the
+/// component of `velocity` antiparallel to `normal` to zero, while a value of `2` reflects it.
+pub fn clip_velocity(
+    velocity: Vector3<f32>,
+    normal: Vector3<f32>,
+    overbounce: f32,
+) -> Vector3<f32> {
+    let mut out = velocity - normal * (velocity.dot(normal) * overbounce);
+
+    for i in 0..3 {
+        if out[i].abs() < STOP_EPSILON {
+            out[i] = 0.0;
+        }
+    }
+
+    out
+}
+
+/// Slows down a player on the ground.
+///
+/// Unlike the original, this doesn't add `sv_edgefriction` near drops.
+pub fn friction(velocity: Vector3<f32>, vars: &MoveVars, frame_time: f32) -> Vector3<f32> {
+    let speed = velocity.x.hypot(velocity.y);
+    if speed == 0.0 {
+        return velocity;
+    }
+
+    let control = speed.max(vars.stop_speed);
+    let new_speed = (speed - frame_time * control * vars.friction).max(0.0);
+
+    velocity * (new_speed / speed)
+}
+
+/// Speeds up a player on the ground towards `wish_speed` in the direction `wish_dir`.
+pub fn accelerate(
+    velocity: Vector3<f32>,
+    wish_dir: Vector3<f32>,
+    wish_speed: f32,
+    vars: &MoveVars,
+    frame_time: f32,
+) -> Vector3<f32> {
+    let add_speed = wish_speed - velocity.dot(wish_dir);
+    if add_speed <= 0.0 {
+        return velocity;
+    }
+
+    let accel_speed = (vars.accelerate * frame_time * wish_speed).min(add_speed);
+    velocity + wish_dir * accel_speed
+}
+
+/// Steers a player in the air. The player can only add a little speed of their own, but can
+/// turn as sharply as they like, which is what makes strafe-jumping possible.
+pub fn air_accelerate(
+    velocity: Vector3<f32>,
+    wish_dir: Vector3<f32>,
+    wish_speed: f32,
+    vars: &MoveVars,
+    frame_time: f32,
+) -> Vector3<f32> {
+    let add_speed = wish_speed.min(MAX_AIR_SPEED) - velocity.dot(wish_dir);
+    if add_speed <= 0.0 {
+        return velocity;
+    }
+
+    let accel_speed = (vars.accelerate * wish_speed * frame_time).min(add_speed);
+    velocity + wish_dir * accel_speed
+}
+
+/// Works out the velocity of a player swimming as `cmd` asks. Players who aren't swimming
+/// anywhere slowly sink.
+pub fn water_move(
+    velocity: Vector3<f32>,
+    cmd: &MoveCmd,
+    vars: &MoveVars,
+    frame_time: f32,
+) -> Vector3<f32> {
+    let (forward, right, _) = angle_vectors(cmd.angles);
+    let mut wish_vel = forward * cmd.forward + right * cmd.side;
+    if cmd.forward == 0.0 && cmd.side == 0.0 && cmd.up == 0.0 {
+        wish_vel.z -= WATER_SINK_SPEED;
+    } else {
+        wish_vel.z += cmd.up;
+    }
+
+    let mut wish_speed = wish_vel.magnitude();
+    if wish_speed > vars.max_speed {
+        wish_vel *= vars.max_speed / wish_speed;
+        wish_speed = vars.max_speed;
+    }
+    wish_speed *= WATER_SPEED_FACTOR;
+
+    // water friction
+    let speed = velocity.magnitude();
+    let (velocity, new_speed) = if speed > 0.0 {
+        let new_speed = (speed - frame_time * speed * vars.friction).max(0.0);
+        (velocity * (new_speed / speed), new_speed)
+    } else {
+        (velocity, 0.0)
+    };
+
+    if wish_speed == 0.0 {
+        return velocity;
+    }
+
+    let add_speed = wish_speed - new_speed;
+    if add_speed <= 0.0 {
+        return velocity;
+    }
+
+    let accel_speed = (vars.accelerate * wish_speed * frame_time).min(add_speed);
+    velocity + wish_vel.normalize() * accel_speed
+}
+
+/// Works out the velocity of a player walking or falling as `cmd` asks. Walking players can't
+/// move up or down of their own accord, so only the direction they face matters, not where
+/// they're looking.
+pub fn air_move(
+    state: &PlayerState,
+    cmd: &MoveCmd,
+    vars: &MoveVars,
+    frame_time: f32,
+) -> Vector3<f32> {
+    let (forward, right, _) = angle_vectors(Angles {
+        pitch: Deg(0.0),
+        roll: Deg(0.0),
+        yaw: cmd.angles.yaw,
+    });
+
+    let mut wish_vel = forward * cmd.forward + right * cmd.side;
+    wish_vel.z = 0.0;
+
+    let mut wish_speed = wish_vel.magnitude();
+    if wish_speed == 0.0 {
+        return match state.on_ground {
+            true => friction(state.velocity, vars, frame_time),
+            false => state.velocity,
+        };
+    }
+
+    let wish_dir = wish_vel / wish_speed;
+    wish_speed = wish_speed.min(vars.max_speed);
+
+    if state.on_ground {
+        let velocity = friction(state.velocity, vars, frame_time);
+        accelerate(velocity, wish_dir, wish_speed, vars, frame_time)
+    } else {
+        air_accelerate(state.velocity, wish_dir, wish_speed, vars, frame_time)
+    }
+}
+
+/// Works out how deep in liquid a player at `origin` is.
+pub fn water_level<W>(world: &mut W, origin: Vector3<f32>) -> Result<u8, W::Error>
+where
+    W: MoveWorld,
+{
+    let is_liquid = |contents| LiquidKind::from_contents(contents).is_some();
+
+    // feet, waist and eyes
+    let heights = [
+        PLAYER_MIN.z + 1.0,
+        (PLAYER_MIN.z + PLAYER_MAX.z) / 2.0,
+        VIEW_HEIGHT,
+    ];
+
+    let mut level = 0;
+    for height in heights {
+        if !is_liquid(world.contents(origin + Vector3::unit_z() * height)?) {
+            break;
+        }
+        level += 1;
+    }
+
+    Ok(level)
+}
+
+/// Moves the player along their velocity for `time` seconds, sliding along whatever they hit.
+pub fn fly_move<W>(
+    state: &mut PlayerState,
+    world: &mut W,
+    time: f32,
+) -> Result<CollisionFlags, W::Error>
+where
+    W: MoveWorld,
+{
+    let mut blocked = CollisionFlags::empty();
+    let primal_velocity = state.velocity;
+    let mut original_velocity = state.velocity;
+    let mut planes: Vec<Vector3<f32>> = Vec::with_capacity(MAX_CLIP_PLANES);
+    let mut time_left = time;
+
+    for _ in 0..MAX_BUMPS {
+        if state.velocity.is_zero() {
+            break;
+        }
+
+        let trace = world.trace(state.origin, state.origin + state.velocity * time_left)?;
+        if trace.all_solid {
+            // stuck in a wall
+            state.velocity = Vector3::zero();
+            return Ok(CollisionFlags::HORIZONTAL | CollisionFlags::VERTICAL);
+        }
+
+        if trace.fraction > 0.0 {
+            state.origin = trace.end;
+            original_velocity = state.velocity;
+            planes.clear();
+        }
+
+        let Some(normal) = trace.normal.filter(|_| trace.fraction < 1.0) else {
+            break;
+        };
+
+        if normal.z > MIN_GROUND_NORMAL {
+            blocked |= CollisionFlags::HORIZONTAL;
+            state.on_ground = true;
+        }
+        if normal.z == 0.0 {
+            blocked |= CollisionFlags::VERTICAL;
+        }
+
+        time_left -= time_left * trace.fraction;
+
+        if planes.len() >= MAX_CLIP_PLANES {
+            state.velocity = Vector3::zero();
+            return Ok(CollisionFlags::HORIZONTAL | CollisionFlags::VERTICAL);
+        }
+        planes.push(normal);
+
+        // find a velocity that slides along one plane without going into any of the others
+        let slide = planes.iter().enumerate().find_map(|(i, plane)| {
+            let velocity = clip_velocity(original_velocity, *plane, 1.0);
+            planes
+                .iter()
+                .enumerate()
+                .all(|(j, other)| i == j || velocity.dot(*other) >= 0.0)
+                .then_some(velocity)
+        });
+
+        state.velocity = match slide {
+            Some(velocity) => velocity,
+            // between two planes, so slide along the crease
+            None if planes.len() == 2 => {
+                let dir = planes[0].cross(planes[1]);
+                dir * dir.dot(state.velocity)
+            }
+            // wedged in a corner
+            None => {
+                state.velocity = Vector3::zero();
+                return Ok(blocked | CollisionFlags::STOPPED);
+            }
+        };
+
+        // don't bounce back the way we came, to avoid jittering in corners
+        if state.velocity.dot(primal_velocity) <= 0.0 {
+            state.velocity = Vector3::zero();
+            return Ok(blocked);
+        }
+    }
+
+    Ok(blocked)
+}
+
+/// Moves a walking player along their velocity for `time` seconds, stepping up onto anything
+/// low enough to climb.
+pub fn walk_move<W>(state: &mut PlayerState, world: &mut W, time: f32) -> Result<(), W::Error>
+where
+    W: MoveWorld,
+{
+    let was_on_ground = state.on_ground;
+    state.on_ground = false;
+
+    let old_origin = state.origin;
+    let old_velocity = state.velocity;
+
+    let blocked = fly_move(state, world, time)?;
+    if !blocked.contains(CollisionFlags::VERTICAL) {
+        // didn't run into a step
+        return Ok(());
+    }
+
+    if !was_on_ground && state.water_level == 0 {
+        // can't climb steps while jumping
+        return Ok(());
+    }
+
+    let no_step = *state;
+
+    // go back to the start, move up a step's height and try again
+    state.origin = old_origin;
+    state.on_ground = false;
+    state.origin = world
+        .trace(state.origin, state.origin + Vector3::unit_z() * STEP_SIZE)?
+        .end;
+    state.velocity = Vector3::new(old_velocity.x, old_velocity.y, 0.0);
+    fly_move(state, world, time)?;
+
+    // and back down onto the step
+    let down = Vector3::unit_z() * (-STEP_SIZE + old_velocity.z * time);
+    let trace = world.trace(state.origin, state.origin + down)?;
+    state.origin = trace.end;
+
+    match trace.normal {
+        Some(normal) if normal.z > MIN_GROUND_NORMAL => state.on_ground = true,
+        // didn't land on anything that can be stood on, so don't take the step
+        _ => *state = no_step,
+    }
+
+    Ok(())
+}
+
+/// Moves the player for one frame of `frame_time` seconds as `cmd` asks.
+pub fn player_move<W>(
+    state: &mut PlayerState,
+    cmd: &MoveCmd,
+    vars: &MoveVars,
+    frame_time: f32,
+    world: &mut W,
+) -> Result<(), W::Error>
+where
+    W: MoveWorld,
+{
+    state.water_level = water_level(world, state.origin)?;
+
+    let swimming = state.water_level >= 2;
+    state.velocity = match swimming {
+        true => water_move(state.velocity, cmd, vars, frame_time),
+        false => air_move(state, cmd, vars, frame_time),
+    };
+
+    if !swimming {
+        state.velocity.z -= vars.gravity * frame_time;
+    }
+
+    walk_move(state, world, frame_time)?;
+
+    state.water_level = water_level(world, state.origin)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::Infallible;
+
+    use crate::common::bsp::BspCollisionHull;
+
+    const FRAME_TIME: f32 = 1.0 / 72.0;
+
+    /// How close to a surface counts as touching it, to allow for rounding.
+    const EPSILON: f32 = 0.001;
+
+    /// A world made of boxes, already grown by the size of the player so that the player can be
+    /// traced as a point.
+    #[derive(Clone, Default)]
+    struct BoxWorld {
+        solids: Vec<(Vector3<f32>, Vector3<f32>)>,
+        water: Vec<(Vector3<f32>, Vector3<f32>)>,
+    }
+
+    impl BoxWorld {
+        /// A floor with its top at `z = 0`.
+        fn floor() -> BoxWorld {
+            BoxWorld {
+                solids: vec![(
+                    Vector3::new(-10000.0, -10000.0, -100.0),
+                    Vector3::new(10000.0, 10000.0, 0.0),
+                )],
+                water: Vec::new(),
+            }
+        }
+
+        fn with_solid(mut self, min: Vector3<f32>, max: Vector3<f32>) -> BoxWorld {
+            self.solids.push((min, max));
+            self
+        }
+
+        fn with_water(mut self, min: Vector3<f32>, max: Vector3<f32>) -> BoxWorld {
+            self.water.push((min, max));
+            self
+        }
+    }
+
+    fn inside(point: Vector3<f32>, (min, max): (Vector3<f32>, Vector3<f32>)) -> bool {
+        (0..3).all(|i| min[i] + EPSILON < point[i] && point[i] < max[i] - EPSILON)
+    }
+
+    fn assert_near(a: f32, b: f32) {
+        assert!((a - b).abs() < 0.01, "{} != {}", a, b);
+    }
+
+    impl MoveWorld for BoxWorld {
+        type Error = Infallible;
+
+        fn trace(
+            &mut self,
+            start: Vector3<f32>,
+            end: Vector3<f32>,
+        ) -> Result<MoveTrace, Infallible> {
+            let dir = end - start;
+            let mut hit: Option<(f32, Vector3<f32>)> = None;
+
+            for &(min, max) in &self.solids {
+                if inside(start, (min, max)) {
+                    return Ok(MoveTrace {
+                        end: start,
+                        fraction: 0.0,
+                        normal: None,
+                        all_solid: true,
+                    });
+                }
+
+                // slab test
+                let mut near = (f32::NEG_INFINITY, Vector3::zero());
+                let mut far = f32::INFINITY;
+                let mut misses = false;
+                for i in 0..3 {
+                    if dir[i] == 0.0 {
+                        misses |= start[i] <= min[i] + EPSILON || start[i] >= max[i] - EPSILON;
+                        continue;
+                    }
+
+                    let (t1, t2) = ((min[i] - start[i]) / dir[i], (max[i] - start[i]) / dir[i]);
+                    let (t_near, t_far) = (t1.min(t2), t1.max(t2));
+                    if t_near > near.0 {
+                        let mut normal = Vector3::zero();
+                        normal[i] = -dir[i].signum();
+                        near = (t_near, normal);
+                    }
+                    far = far.min(t_far);
+                }
+
+                // starting on the surface counts as hitting it straight away
+                let (t, normal) = near;
+                let t = match t {
+                    t if t < 0.0 && t * dir.magnitude() > -EPSILON => 0.0,
+                    t => t,
+                };
+                let nearest = hit.is_none_or(|(best, _)| t < best);
+                if !misses && t >= 0.0 && t < far && t <= 1.0 && nearest {
+                    hit = Some((t, normal));
+                }
+            }
+
+            Ok(match hit {
+                Some((fraction, normal)) => MoveTrace {
+                    end: start + dir * fraction,
+                    fraction,
+                    normal: Some(normal),
+                    all_solid: false,
+                },
+                None => MoveTrace {
+                    end,
+                    fraction: 1.0,
+                    normal: None,
+                    all_solid: false,
+                },
+            })
+        }
+
+        fn contents(&mut self, point: Vector3<f32>) -> Result<BspLeafContents, Infallible> {
+            Ok(if self.solids.iter().any(|b| inside(point, *b)) {
+                BspLeafContents::Solid
+            } else if self.water.iter().any(|b| inside(point, *b)) {
+                BspLeafContents::Water
+            } else {
+                BspLeafContents::Empty
+            })
+        }
+    }
+
+    fn forward(speed: f32) -> MoveCmd {
+        MoveCmd {
+            angles: Angles::zero(),
+            forward: speed,
+            side: 0.0,
+            up: 0.0,
+        }
+    }
+
+    /// Runs `cmd` for `frames` frames, returning where the player was after each one.
+    fn run(
+        world: &mut BoxWorld,
+        state: &mut PlayerState,
+        cmd: MoveCmd,
+        frames: usize,
+    ) -> Vec<PlayerState> {
+        (0..frames)
+            .map(|_| {
+                player_move(state, &cmd, &MoveVars::default(), FRAME_TIME, world).unwrap();
+                *state
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_accelerate_to_max_speed() {
+        let mut world = BoxWorld::floor();
+        let mut state = PlayerState::new(Vector3::zero());
+        state.on_ground = true;
+
+        let trajectory = run(&mut world, &mut state, forward(400.0), 144);
+
+        // speed only ever goes up, until it levels out at sv_maxspeed
+        for pair in trajectory.windows(2) {
+            assert!(pair[1].velocity.x >= pair[0].velocity.x);
+            assert!(pair[1].origin.x > pair[0].origin.x);
+        }
+        assert!((state.velocity.x - 320.0).abs() < 1.0);
+        assert_eq!(state.velocity.y, 0.0);
+        assert_eq!(state.velocity.z, 0.0);
+        assert_near(state.origin.z, 0.0);
+        assert!(state.on_ground);
+    }
+
+    #[test]
+    fn test_friction_stops_player() {
+        let mut world = BoxWorld::floor();
+        let mut state = PlayerState::new(Vector3::zero());
+        state.on_ground = true;
+        state.velocity = Vector3::new(320.0, 0.0, 0.0);
+
+        let trajectory = run(&mut world, &mut state, forward(0.0), 72);
+
+        for pair in trajectory.windows(2) {
+            assert!(pair[1].velocity.x <= pair[0].velocity.x);
+        }
+        assert!(state.velocity.is_zero());
+        // friction scales with speed until the player slows down to sv_stopspeed
+        assert!(state.origin.x > 50.0 && state.origin.x < 100.0);
+    }
+
+    #[test]
+    fn test_fall_and_land() {
+        let mut world = BoxWorld::floor();
+        let mut state = PlayerState::new(Vector3::new(0.0, 0.0, 100.0));
+
+        let trajectory = run(&mut world, &mut state, forward(0.0), 72);
+
+        let landed = trajectory.iter().position(|s| s.on_ground).unwrap();
+        // falling 100 units takes half a second
+        assert!((landed as f32 * FRAME_TIME - 0.5).abs() < 0.05);
+        for s in &trajectory[landed..] {
+            assert_near(s.origin.z, 0.0);
+            assert!(s.on_ground);
+        }
+    }
+
+    #[test]
+    fn test_step_up() {
+        // a step low enough to climb
+        let mut world = BoxWorld::floor().with_solid(
+            Vector3::new(100.0, -10000.0, -100.0),
+            Vector3::new(10000.0, 10000.0, 16.0),
+        );
+        let mut state = PlayerState::new(Vector3::zero());
+        state.on_ground = true;
+
+        run(&mut world, &mut state, forward(320.0), 72);
+        assert!(state.origin.x > 150.0);
+        assert_near(state.origin.z, 16.0);
+        assert!(state.on_ground);
+
+        // and a wall too high to climb
+        let mut world = BoxWorld::floor().with_solid(
+            Vector3::new(100.0, -10000.0, -100.0),
+            Vector3::new(10000.0, 10000.0, 32.0),
+        );
+        let mut state = PlayerState::new(Vector3::zero());
+        state.on_ground = true;
+
+        run(&mut world, &mut state, forward(320.0), 72);
+        assert_near(state.origin.x, 100.0);
+        assert_near(state.origin.z, 0.0);
+    }
+
+    #[test]
+    fn test_sink_in_water() {
+        let mut world = BoxWorld::floor().with_water(
+            Vector3::new(-10000.0, -10000.0, -100.0),
+            Vector3::new(10000.0, 10000.0, 1000.0),
+        );
+        let mut state = PlayerState::new(Vector3::new(0.0, 0.0, 500.0));
+
+        let trajectory = run(&mut world, &mut state, forward(0.0), 72);
+
+        assert!(trajectory.iter().all(|s| s.water_level == 3));
+        // sinking is much slower than falling
+        let sink_speed = WATER_SINK_SPEED * WATER_SPEED_FACTOR;
+        assert!(state.velocity.z < 0.0 && state.velocity.z >= -sink_speed);
+        assert!(state.origin.z > 450.0);
+    }
+
+    #[test]
+    fn test_solid_trace() {
+        let hull = BspCollisionHull::for_bounds(
+            Vector3::new(100.0, -100.0, -100.0),
+            Vector3::new(200.0, 100.0, 100.0),
+        )
+        .unwrap();
+
+        let trace = |start, end| hull.trace(start, end);
+
+        let hit = solid_trace(trace, Vector3::zero(), Vector3::new(150.0, 0.0, 0.0)).unwrap();
+        assert_eq!(hit.end, Vector3::new(100.0, 0.0, 0.0));
+        assert!((hit.fraction - 2.0 / 3.0).abs() < 1e-4);
+        assert_eq!(hit.normal, Some(Vector3::new(-1.0, 0.0, 0.0)));
+
+        let clear = solid_trace(trace, Vector3::zero(), Vector3::new(0.0, 150.0, 0.0)).unwrap();
+        assert_eq!(clear.fraction, 1.0);
+        assert_eq!(clear.normal, None);
+
+        let stuck = solid_trace(
+            trace,
+            Vector3::new(150.0, 0.0, 0.0),
+            Vector3::new(160.0, 0.0, 0.0),
+        )
+        .unwrap();
+        assert!(stuck.all_solid);
+    }
+}
@@ -36,14 +36,78 @@ pub enum VfsError {
     Pak(#[from] PakError),
     #[error("File does not exist: {0}")]
     NoSuchFile(String),
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
 }
 
 #[derive(Debug)]
 enum VfsComponent {
-    Pak(Pak),
+    Pak(PathBuf, Pak),
     Directory(PathBuf),
 }
 
+/// MD5 hashes of the paks shipped in `id1` by the final (1.06) release of Quake.
+const KNOWN_PAKS: &[(&str, &str)] = &[
+    ("pak0.pak", "5906e5998fc3d896ddaf5e6a62e03abb"),
+    ("pak1.pak", "d76b3e5678f0b64ac74ce5e340e6a685"),
+];
+
+/// The directories that files may be downloaded into, and the kinds of file that may be.
+const DOWNLOAD_DIRS: &[&str] = &["maps/", "progs/", "sound/"];
+const DOWNLOAD_EXTENSIONS: &[&str] = &[".bsp", ".lit", ".mdl", ".spr", ".wav"];
+
+/// Whether the file at `virtual_path` is content that a server may send to a client.
+///
+/// Only maps, models and sounds can be downloaded, so that a server can't be asked for its
+/// configs and a client can't be made to write anywhere outside the game directory.
+pub fn is_downloadable(virtual_path: &str) -> bool {
+    let lower = virtual_path.to_ascii_lowercase();
+
+    DOWNLOAD_DIRS.iter().any(|dir| lower.starts_with(dir))
+        && DOWNLOAD_EXTENSIONS.iter().any(|ext| lower.ends_with(ext))
+        && !lower
+            .split('/')
+            .any(|part| part.is_empty() || part.starts_with('.'))
+        && !lower.contains(['\\', ':'])
+}
+
+/// Files which change how every map looks or plays, and so are the usual suspects when the game
+/// looks wrong.
+const KEY_FILES: &[&str] = &[
+    "progs.dat",
+    "gfx.wad",
+    "gfx/palette.lmp",
+    "gfx/colormap.lmp",
+];
+
+/// The result of checking one pak or key file with [`Vfs::check_content`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContentStatus {
+    /// A pak matches the release it has the name of, or a key file is loaded from such a pak.
+    Ok,
+
+    /// A pak in `id1` has the name of one from the release but different contents.
+    Modified { expected: &'static str },
+
+    /// A pak which isn't part of the release, e.g. from a mod.
+    Unknown,
+
+    /// A key file is loaded from somewhere other than the release paks.
+    Overridden { by: PathBuf },
+
+    /// A key file can't be found at all.
+    Missing,
+}
+
+#[derive(Debug, Clone)]
+pub struct ContentReport {
+    /// The path of a pak, or the virtual path of a key file.
+    pub name: PathBuf,
+    /// The MD5 hash of the pak or file as a hex string.
+    pub md5: Option<String>,
+    pub status: ContentStatus,
+}
+
 #[derive(Clone, Debug, Resource, ExtractResource)]
 pub struct Vfs {
     components: Vec<Arc<VfsComponent>>,
@@ -142,7 +206,7 @@ impl Vfs {
     {
         let path = path.as_ref();
         self.components
-            .push(VfsComponent::Pak(Pak::new(path)?).into());
+            .push(VfsComponent::Pak(path.to_owned(), Pak::new(path)?).into());
         Ok(())
     }
 
@@ -164,7 +228,7 @@ impl Vfs {
         // iterate in reverse so later PAKs overwrite earlier ones
         for c in self.components.iter().rev() {
             match &**c {
-                VfsComponent::Pak(pak) => {
+                VfsComponent::Pak(_, pak) => {
                     if let Ok(f) = pak.open(vp) {
                         return Ok(VirtualFile::PakBacked(Cursor::new(f)));
                     }
@@ -193,12 +257,17 @@ impl Vfs {
         // iterate in reverse so later PAKs overwrite earlier ones
         for c in self.components.iter().rev() {
             match &**c {
-                VfsComponent::Pak(_) => {}
+                VfsComponent::Pak(..) => {}
                 VfsComponent::Directory(path) => {
                     let mut full_path = path.to_owned();
                     full_path.push(vp);
 
-                    if let Ok(f) = OpenOptions::new().write(true).create(true).open(full_path) {
+                    if let Ok(f) = OpenOptions::new()
+                        .write(true)
+                        .create(true)
+                        .truncate(true)
+                        .open(full_path)
+                    {
                         return Ok(BufWriter::new(f));
                     }
                 }
@@ -208,6 +277,21 @@ impl Vfs {
         Err(VfsError::NoSuchFile(vp.to_owned()))
     }
 
+    /// Writes `data` to `virtual_path` in the last directory added, creating any directories
+    /// needed on the way.
+    pub fn save<S>(&self, virtual_path: S, data: &[u8]) -> Result<(), VfsError>
+    where
+        S: AsRef<str>,
+    {
+        let path = self.find_writable_filename(virtual_path)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, data)?;
+
+        Ok(())
+    }
+
     /// This is somewhat of a hack - `liner::History` doesn't (currently) have a way of saving/loading
     /// from arbitrary `Read`/`Write` types, it needs a specific file path
     pub fn find_writable_filename<S>(&self, virtual_path: S) -> Result<PathBuf, VfsError>
@@ -219,7 +303,7 @@ impl Vfs {
         // iterate in reverse so later PAKs overwrite earlier ones
         for c in self.components.iter().rev() {
             match &**c {
-                VfsComponent::Pak(_) => {}
+                VfsComponent::Pak(..) => {}
                 VfsComponent::Directory(path) => {
                     let mut full_path = path.to_owned();
                     full_path.push(vp);
@@ -231,6 +315,87 @@ impl Vfs {
 
         Err(VfsError::NoSuchFile(vp.to_owned()))
     }
+
+    /// Hash every mounted pak and the key game files, to find content which differs from the
+    /// original release.
+    ///
+    /// Paks in `id1` are compared against the known hashes of the release. Key files such as
+    /// `progs.dat` are reported if they are loaded from anywhere other than an unmodified release
+    /// pak, since a stray loose file or mod pak overriding them is a common cause of a broken game.
+    pub fn check_content(&self) -> Vec<ContentReport> {
+        let mut reports = Vec::new();
+        let mut release_paks = Vec::new();
+
+        for c in self.components.iter() {
+            let VfsComponent::Pak(path, pak) = &**c else {
+                continue;
+            };
+
+            let md5 = format!("{:x}", md5::compute(pak.bytes()));
+            let in_id1 = path
+                .parent()
+                .and_then(Path::file_name)
+                .is_some_and(|dir| dir.eq_ignore_ascii_case("id1"));
+            let known = path.file_name().and_then(|name| {
+                KNOWN_PAKS
+                    .iter()
+                    .find(|(known, _)| name.eq_ignore_ascii_case(known))
+            });
+
+            let status = match known {
+                Some(&(_, expected)) if in_id1 && md5 == expected => {
+                    release_paks.push(path.as_path());
+                    ContentStatus::Ok
+                }
+                Some(&(_, expected)) if in_id1 => ContentStatus::Modified { expected },
+                _ => ContentStatus::Unknown,
+            };
+
+            reports.push(ContentReport {
+                name: path.clone(),
+                md5: Some(md5),
+                status,
+            });
+        }
+
+        for &name in KEY_FILES {
+            // find the component the file is actually loaded from
+            let source = self.components.iter().rev().find_map(|c| match &**c {
+                VfsComponent::Pak(_, pak) => pak.open(name).ok().map(|data| (c, data.to_vec())),
+                VfsComponent::Directory(path) => {
+                    std::fs::read(path.join(name)).ok().map(|data| (c, data))
+                }
+            });
+
+            let (md5, status) = match source {
+                None => (None, ContentStatus::Missing),
+                Some((c, data)) => {
+                    let md5 = format!("{:x}", md5::compute(&data));
+                    let status = match &**c {
+                        VfsComponent::Pak(path, _) if release_paks.contains(&path.as_path()) => {
+                            ContentStatus::Ok
+                        }
+                        VfsComponent::Pak(path, _) => {
+                            ContentStatus::Overridden { by: path.clone() }
+                        }
+                        VfsComponent::Directory(path) => ContentStatus::Overridden {
+                            by: path.join(name),
+                        },
+                    };
+
+                    (Some(md5), status)
+                }
+            };
+
+            reports.push(ContentReport {
+                name: name.into(),
+                md5,
+                status,
+            });
+        }
+
+        reports
+    }
 }
 
 pub enum VirtualFile<'a> {
@@ -255,3 +420,23 @@ impl<'a> Seek for VirtualFile<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_downloadable() {
+        assert!(is_downloadable("maps/custom.bsp"));
+        assert!(is_downloadable("progs/Custom.MDL"));
+        assert!(is_downloadable("sound/misc/door.wav"));
+
+        assert!(!is_downloadable("progs.dat"));
+        assert!(!is_downloadable("maps/custom.cfg"));
+        assert!(!is_downloadable("maps/../autoexec.bsp"));
+        assert!(!is_downloadable("maps//custom.bsp"));
+        assert!(!is_downloadable("maps/.hidden.bsp"));
+        assert!(!is_downloadable("maps\\..\\custom.bsp"));
+        assert!(!is_downloadable("/maps/custom.bsp"));
+    }
+}
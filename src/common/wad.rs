@@ -134,6 +134,15 @@ impl QPic {
         })
     }
 
+    /// A picture filled with a single palette color.
+    pub fn fill(width: u32, height: u32, index: u8) -> QPic {
+        QPic {
+            width,
+            height,
+            indices: vec![index; (width * height) as usize].into_boxed_slice(),
+        }
+    }
+
     pub fn width(&self) -> u32 {
         self.width
     }
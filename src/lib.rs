@@ -28,4 +28,7 @@
 
 pub mod client;
 pub mod common;
+pub mod prelude;
 pub mod server;
+#[cfg(test)]
+pub mod testing;
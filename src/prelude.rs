@@ -0,0 +1,27 @@
+//! The types that an app embedding the client or the server usually needs, gathered in one place
+//! so that it doesn't depend on where they live inside the crate.
+//!
+//! ```no_run
+//! use bevy::prelude::*;
+//! use seismon::prelude::*;
+//!
+//! #[derive(clap::Parser)]
+//! #[command(name = "hello", about = "Say hello")]
+//! struct Hello;
+//!
+//! App::new()
+//!     .add_plugins(DefaultPlugins)
+//!     .add_plugins(SeismonClientPlugin::new().with_base_dir("/usr/share/quake"))
+//!     .add_plugins(SeismonServerPlugin)
+//!     .command(|In(Hello)| -> ExecResult { "Hello!".into() })
+//!     .run();
+//! ```
+
+pub use crate::{
+    client::{SeismonClientPlugin, SeismonGameSettings},
+    common::{
+        console::{Cvar, ExecResult, RegisterCmdExt, Registry, RunCmd},
+        vfs::Vfs,
+    },
+    server::SeismonServerPlugin,
+};
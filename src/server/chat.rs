@@ -0,0 +1,59 @@
+//! Messages that players send to each other with `say` and `say_team`.
+//!
+//! A chat message is sent to each client as a print that starts with [`CHAT_MARKER`], which
+//! clients take as a sign to play the talk sound and show the line highlighted. Messages to a team
+//! go only to players whose pants (bottom) color matches the sender's, and only when `teamplay` is
+//! on; otherwise `say_team` behaves like `say`, as it does in the original server.
+
+use crate::common::net::{PlayerColor, CHAT_MARKER};
+
+/// The longest message a player may send, in bytes. Anything longer is cut off.
+pub const MAX_SAY_LENGTH: usize = 128;
+
+/// Builds the line printed to the clients that receive a message from the player `name`.
+///
+/// Team messages have the sender's name in parentheses.
+pub fn chat_line(name: &str, text: &str, team: bool) -> String {
+    let mut end = text.len().min(MAX_SAY_LENGTH);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    // a message can't add lines of its own
+    let text = text[..end].replace(['\n', '\r'], " ");
+
+    if team {
+        format!("{}({}): {}\n", CHAT_MARKER as char, name, text)
+    } else {
+        format!("{}{}: {}\n", CHAT_MARKER as char, name, text)
+    }
+}
+
+/// Whether a player with the colors `other` should see a team message from a player with the
+/// colors `sender`.
+pub fn same_team(sender: PlayerColor, other: PlayerColor) -> bool {
+    sender.bottom() == other.bottom()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chat_line() {
+        assert_eq!(chat_line("player", "hello", false), "\x01player: hello\n");
+        assert_eq!(chat_line("player", "rush", true), "\x01(player): rush\n");
+        assert_eq!(chat_line("player", "a\nb", false), "\x01player: a b\n");
+
+        let long = "x".repeat(MAX_SAY_LENGTH * 2);
+        assert_eq!(
+            chat_line("p", &long, false).len(),
+            "\x01p: \n".len() + MAX_SAY_LENGTH
+        );
+    }
+
+    #[test]
+    fn test_same_team() {
+        assert!(same_team(PlayerColor::new(1, 4), PlayerColor::new(9, 4)));
+        assert!(!same_team(PlayerColor::new(4, 1), PlayerColor::new(4, 9)));
+    }
+}
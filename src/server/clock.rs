@@ -0,0 +1,139 @@
+//! The server's own clock, which it ticks by rather than by the virtual clock that the client
+//! draws with.
+//!
+//! A listen server and its client run in the same app, but they don't share a clock. The client
+//! draws by Bevy's virtual clock, and the server ticks by its own, which is moved on by real time.
+//! Both run at `host_timescale`, but anything else that changes the client's clock, such as
+//! pausing the virtual clock, leaves the server ticking for its other clients.
+//!
+//! Every frame, [`run_ticks`] moves the server's clock on by the real time the frame took and runs
+//! [`ServerPreTick`], [`ServerTick`] and [`ServerPostTick`] once for each `sv_fps` step that has
+//! passed.
+
+use std::time::Duration;
+
+use bevy::{
+    ecs::{schedule::ScheduleLabel, world::World},
+    time::{Real, Time},
+};
+
+use crate::{common::console::Registry, server::cvars::DEFAULT_FPS};
+
+/// The range of `host_timescale`, beyond which the game is unplayable or the physics unstable.
+const MIN_TIMESCALE: f32 = 0.05;
+const MAX_TIMESCALE: f32 = 10.0;
+
+/// The most real time that one frame can move the server's clock on by. After a longer frame,
+/// such as one spent loading a level, the server carries on from where it was rather than running
+/// every tick it missed at once.
+const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+
+/// Runs at the start of each tick, before the server reads its clients' messages.
+#[derive(Hash, Debug, PartialEq, Eq, Copy, Clone, ScheduleLabel)]
+pub struct ServerPreTick;
+
+/// Runs once for every tick of the server's clock.
+#[derive(Hash, Debug, PartialEq, Eq, Copy, Clone, ScheduleLabel)]
+pub struct ServerTick;
+
+/// Runs at the end of each tick, once everything the tick sends to clients has been written.
+#[derive(Hash, Debug, PartialEq, Eq, Copy, Clone, ScheduleLabel)]
+pub struct ServerPostTick;
+
+/// The context of the server's clock, `Time<ServerClock>`. Its elapsed time moves on by one
+/// `timestep` for every tick run.
+#[derive(Debug, Clone, Copy)]
+pub struct ServerClock {
+    timestep: Duration,
+    /// Time that has passed but isn't yet enough for another tick.
+    overstep: Duration,
+}
+
+impl Default for ServerClock {
+    fn default() -> Self {
+        ServerClock::from_hz(DEFAULT_FPS)
+    }
+}
+
+impl ServerClock {
+    pub fn from_hz(hz: f64) -> ServerClock {
+        ServerClock {
+            timestep: Duration::from_secs_f64(hz.recip()),
+            overstep: Duration::ZERO,
+        }
+    }
+
+    /// How long each tick is.
+    pub fn timestep(&self) -> Duration {
+        self.timestep
+    }
+
+    pub fn set_timestep_hz(&mut self, hz: f64) {
+        self.timestep = Duration::from_secs_f64(hz.recip());
+    }
+
+    /// Adds `delta` to the time waiting to be ticked.
+    fn accumulate(&mut self, delta: Duration) {
+        self.overstep += delta;
+    }
+
+    /// Takes the time of one tick from that waiting to be ticked, if there's enough of it.
+    fn expend(&mut self) -> Option<Duration> {
+        let rest = self.overstep.checked_sub(self.timestep)?;
+        self.overstep = rest;
+        Some(self.timestep)
+    }
+}
+
+/// The speed set by `host_timescale`, which is 1 if it's unset or out of range. Both the client's
+/// clock and the server's run at this speed.
+pub fn timescale(registry: &Registry) -> f32 {
+    registry
+        .read_cvar::<f32>("host_timescale")
+        .ok()
+        .filter(|s| s.is_finite() && *s > 0.)
+        .map_or(1., |s| s.clamp(MIN_TIMESCALE, MAX_TIMESCALE))
+}
+
+/// Moves the server's clock on by the real time of this frame, at `host_timescale`, and runs the
+/// server's schedules once for each tick that has passed.
+pub fn run_ticks(world: &mut World) {
+    let delta = world.resource::<Time<Real>>().delta().min(MAX_FRAME_TIME);
+    let scale = timescale(world.resource::<Registry>());
+    world
+        .resource_mut::<Time<ServerClock>>()
+        .context_mut()
+        .accumulate(delta.mul_f32(scale));
+
+    loop {
+        let mut clock = world.resource_mut::<Time<ServerClock>>();
+        let Some(timestep) = clock.context_mut().expend() else {
+            break;
+        };
+        clock.advance_by(timestep);
+
+        world.run_schedule(ServerPreTick);
+        world.run_schedule(ServerTick);
+        world.run_schedule(ServerPostTick);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expend() {
+        let mut clock = ServerClock::from_hz(10.);
+        clock.accumulate(Duration::from_millis(250));
+
+        // two whole ticks, with the rest left for the next frame
+        assert_eq!(clock.expend(), Some(Duration::from_millis(100)));
+        assert_eq!(clock.expend(), Some(Duration::from_millis(100)));
+        assert_eq!(clock.expend(), None);
+
+        clock.accumulate(Duration::from_millis(50));
+        assert_eq!(clock.expend(), Some(Duration::from_millis(100)));
+        assert_eq!(clock.expend(), None);
+    }
+}
@@ -1,21 +1,20 @@
-use std::path::PathBuf;
+use std::{net::IpAddr, path::PathBuf};
 
 use bevy::prelude::*;
 use clap::Parser;
 use failure::Error;
 
 use crate::{
-    client::{input::InputFocus, Connection, ConnectionState},
+    client::{input::InputFocus, progress::ConnectionProgress, Connection, ConnectionState},
     common::{
         console::{ExecResult, RegisterCmdExt},
-        net::{ClientMessage, ServerMessage, SignOnStage},
+        net::{ClientId, ClientMessage, MessageKind, ServerMessage, SignOnStage},
     },
 };
 
 use super::*;
 
 pub fn register_commands(app: &mut App) {
-    // TODO: Implement `changelevel` (move to new level without resetting persistant state
     app.command(cmd_map.map(|res| -> ExecResult {
         if let Err(e) = res {
             format!("{}", e).into()
@@ -23,6 +22,346 @@ pub fn register_commands(app: &mut App) {
             default()
         }
     }));
+
+    app.command(cmd_changelevel.map(|res| -> ExecResult {
+        if let Err(e) = res {
+            format!("{}", e).into()
+        } else {
+            default()
+        }
+    }));
+
+    app.command(cmd_restart.map(|res| -> ExecResult {
+        if let Err(e) = res {
+            format!("{}", e).into()
+        } else {
+            default()
+        }
+    }));
+
+    app.command(cmd_prvm_reload.map(|res| -> ExecResult {
+        if let Err(e) = res {
+            format!("{}", e).into()
+        } else {
+            default()
+        }
+    }));
+
+    app.command(cmd_kick.map(|res| -> ExecResult {
+        if let Err(e) = res {
+            format!("{}", e).into()
+        } else {
+            default()
+        }
+    }));
+
+    #[derive(Parser)]
+    #[command(name = "ban", about = "Refuse connections from an address")]
+    struct Ban {
+        /// The address to ban. If omitted, lists the banned addresses
+        addr: Option<IpAddr>,
+        /// Lift the ban instead of adding it
+        #[arg(long, requires = "addr")]
+        remove: bool,
+    }
+
+    app.command(
+        |In(Ban { addr, remove }), mut bans: ResMut<BanList>, vfs: Res<Vfs>| -> ExecResult {
+            let Some(addr) = addr else {
+                return bans
+                    .iter()
+                    .map(|addr| format!("{}\n", addr))
+                    .collect::<String>()
+                    .into();
+            };
+
+            let changed = if remove {
+                bans.remove(addr)
+            } else {
+                bans.add(addr)
+            };
+
+            if !changed {
+                return format!(
+                    "{} is {}banned",
+                    addr,
+                    if remove { "not " } else { "already " }
+                )
+                .into();
+            }
+
+            match bans.save(&vfs) {
+                Ok(()) => default(),
+                Err(e) => format!("Failed to save ban list: {}", e).into(),
+            }
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(
+        name = "sv_writeconfig",
+        about = "Save the archived server cvars to server.cfg"
+    )]
+    struct WriteConfig;
+
+    app.command(
+        |In(WriteConfig),
+         server_cvars: Res<ServerCvars>,
+         registry: Res<Registry>,
+         vfs: Res<Vfs>|
+         -> ExecResult {
+            match server_cvars.save(&registry, &vfs) {
+                Ok(()) => format!("Wrote {}", config::CONFIG_PATH).into(),
+                Err(e) => format!("Failed to write {}: {}", config::CONFIG_PATH, e).into(),
+            }
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(
+        name = "sv_record",
+        about = "Record everything the server sends to a demo"
+    )]
+    struct SvRecord {
+        /// The name of the demo, which is saved in the game directory
+        demo: String,
+    }
+
+    app.command(
+        |In(SvRecord { demo }),
+         mut commands: Commands,
+         vfs: Res<Vfs>,
+         session: Option<Res<Session>>,
+         recording: Option<Res<record::ServerDemo>>|
+         -> ExecResult {
+            if let Some(recording) = recording {
+                return format!("already recording {}", recording.path()).into();
+            }
+
+            let demo = match record::ServerDemo::create(&vfs, &demo) {
+                Ok(demo) => demo,
+                Err(e) => return format!("Couldn't create server demo: {}", e).into(),
+            };
+            let output = match session {
+                Some(_) => format!("Recording to {}, starting with the next level", demo.path()),
+                None => format!("Recording to {}", demo.path()),
+            };
+            commands.insert_resource(demo);
+
+            output.into()
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(name = "sv_stoprecord", about = "Finish recording a server demo")]
+    struct SvStopRecord;
+
+    app.command(
+        |In(SvStopRecord),
+         mut commands: Commands,
+         recording: Option<ResMut<record::ServerDemo>>|
+         -> ExecResult {
+            let Some(mut demo) = recording else {
+                return "not recording a server demo".into();
+            };
+
+            commands.remove_resource::<record::ServerDemo>();
+            match demo.finish() {
+                Ok(()) => format!("Completed server demo {}", demo.path()).into(),
+                Err(e) => format!("Couldn't finish {}: {}", demo.path(), e).into(),
+            }
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(name = "status", about = "Show the current map and connected players")]
+    struct Status;
+
+    app.command(|In(Status), session: Option<Res<Session>>| -> ExecResult {
+        let Some(session) = session else {
+            return "No server running".into();
+        };
+
+        let uptime = session.uptime().as_secs();
+        let mut out = format!(
+            "map:     {}\nuptime:  {}:{:02}:{:02}\nplayers: {} active ({} max)\n\n",
+            session.map_name(),
+            uptime / 3600,
+            uptime / 60 % 60,
+            uptime % 60,
+            session.persist.client_slots.active_clients().count(),
+            session.max_clients(),
+        );
+
+        out.push_str(&format!(
+            "{:<3} {:<16} {:>5} {:>5} {:>5}  {}\n",
+            "#", "name", "frags", "ping", "color", "state"
+        ));
+        for slot in session.persist.client_slots.connected_clients() {
+            let Some(client) = session.client(slot) else {
+                continue;
+            };
+            let state = match client.state {
+                ClientState::Connecting => "connecting",
+                ClientState::Active(_) => "active",
+            };
+
+            out.push_str(&format!(
+                "{:<3} {:<16} {:>5} {:>5} {:>5}  {}\n",
+                slot,
+                client.name.to_str(),
+                session.frags(slot).unwrap_or(0),
+                client.ping().num_milliseconds(),
+                format!("{}/{}", client.color >> 4, client.color & 0xF),
+                state,
+            ));
+        }
+
+        out.into()
+    });
+
+    #[derive(Parser)]
+    #[command(name = "who", about = "List the players in the game")]
+    struct Who;
+
+    app.command(|In(Who), session: Option<Res<Session>>| -> ExecResult {
+        let Some(session) = session else {
+            return "No server running".into();
+        };
+
+        session
+            .persist
+            .client_slots
+            .active_clients()
+            .filter_map(|slot| {
+                let client = session.client(slot)?;
+                Some(format!(
+                    "{:<16} {:>5}\n",
+                    client.name.to_str(),
+                    session.frags(slot).unwrap_or(0)
+                ))
+            })
+            .collect::<String>()
+            .into()
+    });
+
+    #[derive(Parser)]
+    #[command(name = "edict", about = "Print the fields of an entity")]
+    struct Edict {
+        num: usize,
+    }
+
+    app.command(
+        |In(Edict { num }), session: Option<Res<Session>>| -> ExecResult {
+            let Some(session) = session else {
+                return "No server running".into();
+            };
+
+            match session.describe_edict(num) {
+                Some(fields) => fields.into(),
+                None => format!("No entity {}", num).into(),
+            }
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(name = "edicts", about = "Print the fields of every entity")]
+    struct Edicts;
+
+    app.command(|In(Edicts), session: Option<Res<Session>>| -> ExecResult {
+        let Some(session) = session else {
+            return "No server running".into();
+        };
+
+        session
+            .edicts()
+            .filter_map(|num| session.describe_edict(num))
+            .collect::<Vec<_>>()
+            .join("\n")
+            .into()
+    });
+
+    #[derive(Parser)]
+    #[command(name = "edictcount", about = "Count the entities in the level")]
+    struct EdictCount;
+
+    app.command(
+        |In(EdictCount), session: Option<Res<Session>>| -> ExecResult {
+            let Some(session) = session else {
+                return "No server running".into();
+            };
+
+            let counts = session.edict_counts();
+            format!(
+                "active: {}\nmodels: {}\nsolid:  {}\nstep:   {}\n",
+                counts.active, counts.models, counts.solid, counts.step
+            )
+            .into()
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(
+        name = "sv_limitcvar",
+        about = "Restrict a client cvar to a range while sv_cheats is disabled",
+        allow_negative_numbers = true
+    )]
+    struct LimitCvar {
+        cvar: String,
+        /// Remove the limit instead of setting it
+        #[arg(long)]
+        remove: bool,
+        #[arg(required_unless_present = "remove")]
+        min: Option<f32>,
+        #[arg(required_unless_present = "remove")]
+        max: Option<f32>,
+    }
+
+    app.command(
+        |In(LimitCvar {
+             cvar,
+             remove,
+             min,
+             max,
+         }),
+         mut limits: ResMut<CvarLimits>|
+         -> ExecResult {
+            if remove {
+                if limits.remove(&cvar) {
+                    default()
+                } else {
+                    format!("{} is not limited", cvar).into()
+                }
+            } else if let (Some(min), Some(max)) = (min, max) {
+                limits.set(cvar, min, max);
+                default()
+            } else {
+                "usage: sv_limitcvar <cvar> <min> <max>".into()
+            }
+        },
+    );
+}
+
+/// The path of the BSP file of a map given by name, like `e1m1`.
+fn map_path(mut map_name: PathBuf) -> String {
+    if map_name.extension().is_none() {
+        map_name.set_extension("bsp");
+    }
+
+    let mut path = PathBuf::from("maps");
+    path.push(map_name);
+
+    format!("{}", path.display())
+}
+
+/// Load the models and entities of a map, and the program that runs it.
+fn load_level(vfs: &Vfs, bsp_name: &str) -> Result<(Vec<Model>, String, LoadProgs), Error> {
+    let bsp = vfs.open(bsp_name)?;
+    let (models, entmap) = crate::common::bsp::load(bsp)?;
+    let progs = vfs.open("progs.dat")?;
+    let progs = crate::server::progs::load(progs)?;
+
+    Ok((models, entmap, progs))
 }
 
 #[derive(Parser)]
@@ -32,7 +371,7 @@ struct Map {
 }
 
 fn cmd_map(
-    In(Map { mut map_name }): In<Map>,
+    In(Map { map_name }): In<Map>,
     mut commands: Commands,
     session: Option<ResMut<Session>>,
     mut focus: ResMut<InputFocus>,
@@ -41,21 +380,11 @@ fn cmd_map(
     mut client_events: ResMut<Events<ClientMessage>>,
     mut server_events: ResMut<Events<ServerMessage>>,
 ) -> Result<(), Error> {
-    if map_name.extension().is_none() {
-        map_name.set_extension("bsp");
-    }
-
-    let mut path = PathBuf::from("maps");
-    path.push(map_name);
-
-    let bsp_name = format!("{}", path.display());
-    let bsp = vfs.open(&bsp_name)?;
-    let (models, entmap) = crate::common::bsp::load(bsp)?;
-    let progs = vfs.open("progs.dat")?;
-    let progs = crate::server::progs::load(progs)?;
+    let bsp_name = map_path(map_name);
+    let (models, entmap, progs) = load_level(&vfs, &bsp_name)?;
 
     // TODO: Make `max_clients` a cvar
-    let new_session = Session::new(
+    let mut new_session = Session::new(
         bsp_name,
         8,
         registry.reborrow(),
@@ -64,6 +393,7 @@ fn cmd_map(
         models,
         entmap,
     );
+    new_session.connect_client(ClientId::LOCAL);
 
     if let Some(mut session) = session {
         *session = new_session;
@@ -77,7 +407,166 @@ fn cmd_map(
     // TODO: This should not be handled here, server and client should be decoupled
     commands.insert_resource(Connection::new_server());
     commands.insert_resource(ConnectionState::SignOn(SignOnStage::Not));
+    commands.insert_resource(ConnectionProgress::new("local"));
     *focus = InputFocus::Game;
 
     Ok(())
 }
+
+#[derive(Parser)]
+#[command(
+    name = "changelevel",
+    about = "Move to another map, keeping the connected players"
+)]
+struct ChangeLevel {
+    map_name: PathBuf,
+}
+
+fn cmd_changelevel(
+    In(ChangeLevel { map_name }): In<ChangeLevel>,
+    session: Option<ResMut<Session>>,
+    vfs: Res<Vfs>,
+    mut registry: ResMut<Registry>,
+) -> Result<(), Error> {
+    let Some(mut session) = session else {
+        bail!("No server running");
+    };
+
+    let bsp_name = map_path(map_name);
+    let (models, entmap, progs) = load_level(&vfs, &bsp_name)?;
+    session.load_level(bsp_name, registry.reborrow(), &*vfs, progs, models, entmap);
+
+    Ok(())
+}
+
+#[derive(Parser)]
+#[command(
+    name = "restart",
+    about = "Start the current map again, keeping the connected players"
+)]
+struct Restart;
+
+fn cmd_restart(
+    In(Restart): In<Restart>,
+    session: Option<ResMut<Session>>,
+    vfs: Res<Vfs>,
+    mut registry: ResMut<Registry>,
+) -> Result<(), Error> {
+    let Some(mut session) = session else {
+        bail!("No server running");
+    };
+
+    let bsp_name = session.level.map_path.clone();
+    let (models, entmap, progs) = load_level(&vfs, &bsp_name)?;
+    session.load_level(bsp_name, registry.reborrow(), &*vfs, progs, models, entmap);
+
+    Ok(())
+}
+
+#[derive(Parser)]
+#[command(name = "kick", about = "Remove a player from the server")]
+struct Kick {
+    /// The slot number or name of the player
+    player: String,
+    /// A message to show the player
+    reason: Vec<String>,
+}
+
+fn cmd_kick(
+    In(Kick { player, reason }): In<Kick>,
+    session: Option<ResMut<Session>>,
+    vfs: Res<Vfs>,
+    mut registry: ResMut<Registry>,
+    mut server_events: EventWriter<ServerMessage>,
+) -> Result<(), Error> {
+    let Some(mut session) = session else {
+        bail!("No server running");
+    };
+
+    let Some(slot) = player
+        .parse::<usize>()
+        .ok()
+        .map(ClientId)
+        .filter(|&slot| session.client(slot).is_some())
+        .or_else(|| session.find_client(&player))
+    else {
+        bail!("No player {}", player);
+    };
+
+    let mut text = String::from("Kicked by server");
+    if !reason.is_empty() {
+        text.push_str(": ");
+        text.push_str(&reason.join(" "));
+    }
+    text.push('\n');
+
+    let mut packet = Vec::new();
+    ServerCmd::Print { text: text.into() }.serialize(&mut packet)?;
+    ServerCmd::Disconnect.serialize(&mut packet)?;
+    server_events.send(ServerMessage {
+        client_id: slot,
+        packet,
+        kind: MessageKind::Reliable,
+    });
+
+    session.drop_client(slot, registry.reborrow(), &*vfs)
+}
+
+#[derive(Parser)]
+#[command(
+    name = "prvm_reload",
+    about = "Reload progs.dat and restart the current map, keeping connected players"
+)]
+struct PrvmReload;
+
+fn cmd_prvm_reload(
+    In(PrvmReload): In<PrvmReload>,
+    mut commands: Commands,
+    session: Option<ResMut<Session>>,
+    vfs: Res<Vfs>,
+    mut registry: ResMut<Registry>,
+    mut client_events: ResMut<Events<ClientMessage>>,
+    mut server_events: ResMut<Events<ServerMessage>>,
+) -> Result<(), Error> {
+    let Some(mut session) = session else {
+        bail!("No server running");
+    };
+
+    let (models, entmap, progs) = load_level(&vfs, &session.level.map_path)?;
+
+    session.reload(registry.reborrow(), &*vfs, progs, models, entmap)?;
+
+    client_events.clear();
+    server_events.clear();
+
+    // every client signs on to the reloaded level from the start. Remote clients are told to
+    // reconnect, as the original server does on a restart, and are then sent the server info
+    let mut reconnect = Vec::new();
+    ServerCmd::StuffText {
+        text: "reconnect\n".into(),
+    }
+    .serialize(&mut reconnect)?;
+
+    let connected = session
+        .persist
+        .client_slots
+        .connected_clients()
+        .collect::<Vec<_>>();
+    for client_id in connected {
+        session.client_mut(client_id).unwrap().signon = SignOnStage::Not;
+        if client_id != ClientId::LOCAL {
+            server_events.send(ServerMessage {
+                client_id,
+                packet: reconnect.clone(),
+                kind: MessageKind::Reliable,
+            });
+        }
+    }
+
+    // TODO: This should not be handled here, server and client should be decoupled
+    commands.insert_resource(Connection::new_server());
+    commands.insert_resource(ConnectionState::SignOn(SignOnStage::Not));
+    commands.insert_resource(ConnectionProgress::new("local"));
+
+    Ok(())
+}
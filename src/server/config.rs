@@ -0,0 +1,85 @@
+//! The server's own config file, kept apart from the client's `config.cfg`.
+//!
+//! The server cvars registered with `archive` are written to `server.cfg` when the game exits or
+//! when an operator runs `sv_writeconfig`, and the file is run as soon as the server starts, before
+//! any map is loaded. A dedicated server therefore keeps its settings between runs without
+//! picking up anything from the config of a client that played on the same install.
+
+use std::io::{Read as _, Write as _};
+
+use bevy::prelude::*;
+
+use crate::common::{
+    console::{Registry, RunCmd},
+    vfs::{Vfs, VfsError},
+};
+
+pub const CONFIG_PATH: &str = "server.cfg";
+
+/// The names of the cvars registered by the server, as opposed to the client.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct ServerCvars {
+    names: Vec<String>,
+}
+
+impl ServerCvars {
+    pub fn new(names: Vec<String>) -> ServerCvars {
+        ServerCvars { names }
+    }
+
+    /// Returns the contents of a config file that sets every archived server cvar to its current
+    /// value.
+    pub fn config(&self, registry: &Registry) -> String {
+        self.names
+            .iter()
+            .filter_map(|name| registry.get_cvar(name).map(|cvar| (name, cvar)))
+            .filter(|(_, cvar)| cvar.archive)
+            .map(|(name, cvar)| format!("{} \"{}\"\n", name, cvar.value()))
+            .collect()
+    }
+
+    /// Writes the archived server cvars to `server.cfg`.
+    pub fn save(&self, registry: &Registry, vfs: &Vfs) -> Result<(), failure::Error> {
+        let mut file = vfs.write(CONFIG_PATH)?;
+        file.write_all(self.config(registry).as_bytes())?;
+        file.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Reads the commands in `server.cfg`, if there is one.
+pub fn load(vfs: &Vfs) -> Result<Vec<RunCmd<'static>>, failure::Error> {
+    let mut text = String::new();
+    match vfs.open(CONFIG_PATH) {
+        Ok(mut file) => file.read_to_string(&mut text)?,
+        Err(VfsError::NoSuchFile(_)) => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let cmds = RunCmd::parse_many(&text)
+        .map_err(|e| failure::format_err!("couldn't parse {}: {}", CONFIG_PATH, e))?;
+
+    Ok(cmds.into_iter().map(RunCmd::into_owned).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::console::Cvar;
+
+    use super::*;
+
+    #[test]
+    fn test_config() {
+        let mut registry = Registry::new();
+        registry.cvar("sv_maxrate", Cvar::new("25000").archive(), None, "");
+        registry.cvar("sv_paused", "0", None, "");
+        registry.cvar("cl_bob", Cvar::new("0.02").archive(), None, "");
+        registry.set_cvar("sv_maxrate", "10000").unwrap();
+
+        let cvars = ServerCvars::new(vec!["sv_maxrate".to_owned(), "sv_paused".to_owned()]);
+
+        // only archived cvars of the server are saved
+        assert_eq!(cvars.config(&registry), "sv_maxrate \"10000\"\n");
+    }
+}
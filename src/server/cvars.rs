@@ -1,12 +1,27 @@
 use bevy::{
     app::App,
-    ecs::system::{Commands, In},
-    time::{Fixed, Time},
+    ecs::system::{In, ResMut},
+    time::Time,
 };
+use hashbrown::HashSet;
 
-use crate::common::console::RegisterCmdExt;
+use crate::{
+    common::console::{Cvar, RegisterCmdExt, Registry},
+    server::{clock::ServerClock, config::ServerCvars},
+};
+
+pub(super) const DEFAULT_FPS: f64 = 72.;
+const MIN_FPS: f64 = 10.;
+const MAX_FPS: f64 = 1000.;
 
 pub fn register_cvars(app: &mut App) {
+    let existing = app
+        .world
+        .resource::<Registry>()
+        .cvar_names()
+        .map(str::to_owned)
+        .collect::<HashSet<_>>();
+
     app.cvar("sv_paused", "0", "1 if the server is paused, 0 otherwise")
         .cvar(
             "teamplay",
@@ -14,16 +29,133 @@ pub fn register_cvars(app: &mut App) {
             "0: deathmatch, 1: co-op (friendly fire disabled), 2: co-op (friendly fire enabled)",
         )
         .cvar("skill", "1", "0: easy, 1: normal, 2: hard, 3: nightmare")
+        .cvar(
+            "deathmatch",
+            Cvar::new("0").notify(),
+            "0: single player or co-op, 1: deathmatch, 2: deathmatch with weapons staying",
+        )
+        .cvar("coop", "0", "1 if the game is co-operative")
+        .cvar(
+            "fraglimit",
+            Cvar::new("0").notify().archive(),
+            "In deathmatch, end the level when a player reaches this many frags (0 for no limit)",
+        )
+        .cvar(
+            "timelimit",
+            Cvar::new("0").notify().archive(),
+            "In deathmatch, end the level after this many minutes (0 for no limit)",
+        )
         .cvar("sv_gravity", "800", "Gravity strength")
         .cvar("sv_maxvelocity", "2000", "Maximum velocity of entities")
+        .cvar(
+            "sv_stopspeed",
+            "100",
+            "Players on the ground slowing down below this speed stop as if moving at it",
+        )
+        .cvar("sv_maxspeed", "320", "Fastest a player can run")
+        .cvar(
+            "sv_accelerate",
+            "10",
+            "How quickly players reach their running speed",
+        )
+        .cvar(
+            "sv_friction",
+            "4",
+            "How quickly players on the ground slow down",
+        )
+        .cvar(
+            "sv_cheats",
+            Cvar::new("0").notify(),
+            "1 if cheat-protected cvars and cheat commands are allowed, 0 otherwise",
+        )
+        .cvar(
+            "sv_maxcmdrate",
+            Cvar::new("40").archive(),
+            "Most string commands and impulses a client may send in a second before it is kicked \
+             (0 for no limit)",
+        )
+        .cvar(
+            "sv_speedcheck",
+            Cvar::new("1").archive(),
+            "1 to ignore the movement of clients whose moves cover more time than has passed or \
+             come faster than any frame rate",
+        )
+        .cvar(
+            "sv_maxrate",
+            Cvar::new("25000").archive(),
+            "Most bytes per second that a client may ask to be sent with its rate (0 for no limit)",
+        )
+        .cvar(
+            "sv_allowdownload",
+            Cvar::new("1").archive(),
+            "1 to let clients download the maps, models and sounds of the level that they don't \
+             have",
+        )
+        .cvar(
+            "sv_protocol",
+            Cvar::new("15").archive(),
+            "Network protocol used from the next level: 15 (NetQuake), 666 (FitzQuake) or 999 \
+             (RMQ). The newer protocols lift the limits on map size and model count",
+        )
+        .cvar(
+            "sv_lagcomp",
+            Cvar::new("0").notify().archive(),
+            "1 to check players' shots against where targets were when they fired, allowing for \
+             their latency",
+        )
+        .cvar(
+            "sv_listen",
+            Cvar::new("0").archive(),
+            "1 to let clients connect over the network",
+        )
+        .cvar(
+            "sv_port",
+            Cvar::new("26000").archive(),
+            "Port to listen for connecting clients on",
+        )
+        .cvar(
+            "sv_wsport",
+            Cvar::new("0").archive(),
+            "Port to listen for clients connecting over WebSockets on, for browsers (0 for none). \
+             Needs a build with the websocket feature",
+        )
+        .cvar(
+            "sv_timeout",
+            Cvar::new("300").archive(),
+            "Seconds a client may go without sending anything before it is dropped (0 for no \
+             limit)",
+        )
         .cvar_on_set(
-            "sys_tickrate",
-            "0.05",
-            |In(new_tickrate), mut commands: Commands| {
-                commands.insert_resource(Time::<Fixed>::from_seconds(
-                    serde_lexpr::from_value(&new_tickrate).unwrap_or(0.05),
-                ));
+            "sv_fps",
+            Cvar::new("72").archive(),
+            |In(new_fps), mut time: ResMut<Time<ServerClock>>| {
+                // physics runs in fixed steps regardless of the frame rate, so that it plays out
+                // the same on every machine
+                let fps = serde_lexpr::from_value::<f64>(&new_fps).unwrap_or(DEFAULT_FPS);
+                time.context_mut()
+                    .set_timestep_hz(fps.clamp(MIN_FPS, MAX_FPS));
             },
-            "Tickrate of server (how often the server updates)",
+            "Number of times per second the server runs physics and sends updates (10-1000)",
         );
+
+    // the setter only runs when the cvar is changed, so the default rate has to be put in place
+    // here rather than left at the clock's default
+    let fps = app
+        .world
+        .resource::<Registry>()
+        .read_cvar::<f64>("sv_fps")
+        .unwrap_or(DEFAULT_FPS);
+    app.insert_resource(Time::new_with(ServerClock::from_hz(
+        fps.clamp(MIN_FPS, MAX_FPS),
+    )));
+
+    // everything registered above belongs to the server, which saves its own config
+    let server_cvars = app
+        .world
+        .resource::<Registry>()
+        .cvar_names()
+        .filter(|name| !existing.contains(*name))
+        .map(str::to_owned)
+        .collect();
+    app.insert_resource(ServerCvars::new(server_cvars));
 }
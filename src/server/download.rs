@@ -0,0 +1,104 @@
+//! Sending clients the maps, models and sounds that they don't have.
+//!
+//! A client that is missing files from the precache lists asks for each of them with
+//! `download <name>` before it answers the sign-on. If `sv_allowdownload` is on and the file is one
+//! that [`is_downloadable`](crate::common::vfs::is_downloadable) allows, the server reads it from
+//! its VFS and sends it in pieces of at most [`MAX_DOWNLOAD_CHUNK`] bytes, as many each frame as
+//! the client's rate allows. Anything else is answered with a download that has no size, which
+//! tells the client that it won't get the file.
+
+use crate::common::net::{ServerCmd, MAX_DOWNLOAD_CHUNK};
+
+/// A file being sent to a client.
+#[derive(Debug, Clone)]
+pub struct Download {
+    data: Vec<u8>,
+    /// How much of `data` has been sent.
+    sent: usize,
+}
+
+impl Download {
+    pub fn new(data: Vec<u8>) -> Download {
+        Download { data, sent: 0 }
+    }
+
+    /// Returns the next piece of the file, or `None` if it has all been sent.
+    ///
+    /// An empty file is sent as a single empty piece, so that the client still hears about it.
+    pub fn next_chunk(&mut self) -> Option<ServerCmd> {
+        if self.is_finished() {
+            return None;
+        }
+
+        let start = self.sent;
+        let end = (start + MAX_DOWNLOAD_CHUNK).min(self.data.len());
+        // an empty file is marked as sent by moving past its end
+        self.sent = end.max(1);
+
+        Some(ServerCmd::Download {
+            size: Some(self.data.len() as u32),
+            offset: start as u32,
+            data: self.data[start..end].to_vec(),
+        })
+    }
+
+    /// Whether the whole file has been sent.
+    pub fn is_finished(&self) -> bool {
+        self.sent >= self.data.len().max(1)
+    }
+}
+
+/// The reply to a request for a file that the server won't send.
+pub fn refusal() -> ServerCmd {
+    ServerCmd::Download {
+        size: None,
+        offset: 0,
+        data: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunks() {
+        let data = (0..=255)
+            .cycle()
+            .take(MAX_DOWNLOAD_CHUNK * 2 + 10)
+            .collect::<Vec<u8>>();
+        let mut download = Download::new(data.clone());
+
+        let mut received = Vec::new();
+        while let Some(ServerCmd::Download {
+            size,
+            offset,
+            data: chunk,
+        }) = download.next_chunk()
+        {
+            assert_eq!(size, Some(data.len() as u32));
+            assert_eq!(offset as usize, received.len());
+            received.extend(chunk);
+        }
+
+        assert!(download.is_finished());
+        assert_eq!(received, data);
+    }
+
+    #[test]
+    fn test_empty_file() {
+        let mut download = Download::new(Vec::new());
+        assert!(!download.is_finished());
+
+        assert_eq!(
+            download.next_chunk(),
+            Some(ServerCmd::Download {
+                size: Some(0),
+                offset: 0,
+                data: Vec::new(),
+            })
+        );
+        assert!(download.is_finished());
+        assert_eq!(download.next_chunk(), None);
+    }
+}
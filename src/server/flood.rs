@@ -0,0 +1,78 @@
+//! Protection against clients that abuse the command channel.
+//!
+//! Every string command and impulse a remote client sends is counted, and a client that sends more
+//! than `sv_maxcmdrate` of them in a second is kicked. Once a game has other players in it, either
+//! as deathmatch or coop or because remote clients have joined, the impulses that hand out weapons,
+//! items and powerups are also refused unless `sv_cheats` is on, just like `god` and `give`.
+
+use std::time::Duration;
+
+/// The length of the window in which commands are counted.
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Impulses that the standard progs treat as cheats: all weapons and ammo, a rune, and quad
+/// damage.
+const CHEAT_IMPULSES: [u8; 3] = [9, 11, 255];
+
+/// Whether `impulse` is one of the cheats in the standard progs.
+pub fn is_cheat_impulse(impulse: u8) -> bool {
+    CHEAT_IMPULSES.contains(&impulse)
+}
+
+/// Counts the commands a client has sent in the current window.
+#[derive(Debug, Clone, Default)]
+pub struct CmdRate {
+    window_start: Duration,
+    count: u32,
+}
+
+impl CmdRate {
+    /// Counts a command sent at `now`. Returns `false` if the client has now sent more than
+    /// `limit` commands within a second, or `true` if it hasn't or `limit` is 0.
+    pub fn record(&mut self, now: Duration, limit: u32) -> bool {
+        if now.saturating_sub(self.window_start) >= WINDOW {
+            self.window_start = now;
+            self.count = 0;
+        }
+
+        self.count = self.count.saturating_add(1);
+
+        limit == 0 || self.count <= limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut rate = CmdRate::default();
+        let start = Duration::from_secs(10);
+
+        for i in 0..5 {
+            assert!(rate.record(start + Duration::from_millis(i * 100), 5));
+        }
+        assert!(!rate.record(start + Duration::from_millis(500), 5));
+
+        // the count starts again once the window has passed
+        assert!(rate.record(start + WINDOW, 5));
+    }
+
+    #[test]
+    fn test_record_unlimited() {
+        let mut rate = CmdRate::default();
+        for _ in 0..1000 {
+            assert!(rate.record(Duration::ZERO, 0));
+        }
+    }
+
+    #[test]
+    fn test_cheat_impulses() {
+        assert!(is_cheat_impulse(9));
+        assert!(is_cheat_impulse(255));
+        // weapon selection
+        assert!(!is_cheat_impulse(1));
+        assert!(!is_cheat_impulse(10));
+    }
+}
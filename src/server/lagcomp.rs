@@ -0,0 +1,190 @@
+//! Lag compensation: a short history of where players and monsters were, so that shots can be
+//! checked against what the shooter saw on their screen rather than where the targets have since
+//! moved.
+//!
+//! Only traces made by QuakeC on behalf of a player (i.e. with `self` set to a client entity) are
+//! rewound, and only while `sv_lagcomp` is enabled.
+
+use std::collections::VecDeque;
+
+use cgmath::Vector3;
+use chrono::Duration;
+use hashbrown::HashMap;
+use serde::Deserialize;
+
+use crate::server::progs::EntityId;
+
+/// How far back positions are kept. Shooters with a higher latency are rewound by this much.
+const MAX_REWIND_MILLIS: i64 = 500;
+
+fn max_rewind() -> Duration {
+    Duration::try_milliseconds(MAX_REWIND_MILLIS).unwrap()
+}
+
+#[derive(Copy, Clone, Default, PartialEq, Deserialize)]
+pub struct LagCompVars {
+    #[serde(rename(deserialize = "sv_lagcomp"))]
+    pub enabled: f32,
+}
+
+#[derive(Debug, Clone)]
+struct Snapshot {
+    time: Duration,
+    origins: Vec<(EntityId, Vector3<f32>)>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LagCompensation {
+    enabled: bool,
+    history: VecDeque<Snapshot>,
+    /// Latency of each client entity, by which its shots are rewound.
+    latencies: HashMap<EntityId, Duration>,
+}
+
+impl LagCompensation {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Enable or disable rewinding. Disabling also forgets the recorded history.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.history.clear();
+            self.latencies.clear();
+        }
+    }
+
+    pub fn set_latency(&mut self, shooter: EntityId, latency: Duration) {
+        self.latencies.insert(shooter, latency);
+    }
+
+    /// Store the positions of the rewindable entities at `time`, discarding anything older than
+    /// the maximum rewind.
+    pub fn record<I>(&mut self, time: Duration, origins: I)
+    where
+        I: IntoIterator<Item = (EntityId, Vector3<f32>)>,
+    {
+        if !self.enabled {
+            return;
+        }
+
+        // a level restart sends time backwards
+        if self.history.back().is_some_and(|s| s.time >= time) {
+            self.history.clear();
+        }
+
+        let oldest = time - max_rewind();
+        while self.history.front().is_some_and(|s| s.time < oldest) {
+            self.history.pop_front();
+        }
+
+        self.history.push_back(Snapshot {
+            time,
+            origins: origins.into_iter().collect(),
+        });
+    }
+
+    /// Returns where every other recorded entity was when `shooter` saw the world, given that it
+    /// is now `now`. Returns `None` if no rewind is needed.
+    pub fn rewind(
+        &self,
+        shooter: EntityId,
+        now: Duration,
+    ) -> Option<impl Iterator<Item = (EntityId, Vector3<f32>)> + '_> {
+        if !self.enabled {
+            return None;
+        }
+
+        let latency = (*self.latencies.get(&shooter)?).min(max_rewind());
+        if latency.is_zero() {
+            return None;
+        }
+
+        // the latest snapshot no newer than the time being rewound to
+        let target = now - latency;
+        let index = self.history.partition_point(|s| s.time <= target);
+        let snapshot = self.history.get(index.checked_sub(1)?)?;
+
+        Some(
+            snapshot
+                .origins
+                .iter()
+                .copied()
+                .filter(move |(id, _)| *id != shooter),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::ping::PingTimes;
+
+    fn ms(millis: i64) -> Duration {
+        Duration::try_milliseconds(millis).unwrap()
+    }
+
+    #[test]
+    fn test_rewind() {
+        let shooter = EntityId(1);
+        let target = EntityId(2);
+
+        let mut lag_comp = LagCompensation::default();
+        lag_comp.set_enabled(true);
+        lag_comp.set_latency(shooter, ms(100));
+        for step in 0..20 {
+            let time = ms(step * 50);
+            lag_comp.record(
+                time,
+                [
+                    (shooter, Vector3::new(0., 0., 0.)),
+                    (target, Vector3::new(step as f32, 0., 0.)),
+                ],
+            );
+        }
+
+        let rewound = lag_comp
+            .rewind(shooter, ms(950))
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(rewound, vec![(target, Vector3::new(17., 0., 0.))]);
+
+        // clients without a latency aren't rewound
+        assert!(lag_comp.rewind(target, ms(950)).is_none());
+
+        // the rewind is capped at the length of the kept history
+        lag_comp.set_latency(shooter, ms(2000));
+        let rewound = lag_comp
+            .rewind(shooter, ms(950))
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(rewound, vec![(target, Vector3::new(9., 0., 0.))]);
+    }
+
+    #[test]
+    fn test_rewind_by_measured_ping() {
+        let shooter = EntityId(1);
+        let target = EntityId(2);
+
+        let mut lag_comp = LagCompensation::default();
+        lag_comp.set_enabled(true);
+        for step in 0..20 {
+            lag_comp.record(ms(step * 50), [(target, Vector3::new(step as f32, 0., 0.))]);
+        }
+
+        // the shooter's moves carry the time of the update it had when it sent them, 150ms before
+        // they arrived
+        let mut pings = PingTimes::default();
+        for step in 10..20 {
+            pings.record(ms(step * 50), ms(step * 50 - 150));
+        }
+        lag_comp.set_latency(shooter, pings.average());
+
+        let rewound = lag_comp
+            .rewind(shooter, ms(950))
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(rewound, vec![(target, Vector3::new(16., 0., 0.))]);
+    }
+}
@@ -0,0 +1,630 @@
+//! Accepting clients that connect over the network.
+//!
+//! While `sv_listen` is on, connection requests are taken on `sv_port`. Each client that is let in
+//! gets a socket of its own, run by a [`SocketThread`], and its messages pass through the same
+//! events as those of the local client.
+//!
+//! Builds with the `websocket` feature also take clients over WebSockets on `sv_wsport`, for
+//! browsers that can't send UDP. These clients stay on the WebSocket they connected with.
+
+use std::{
+    net::{SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+use bevy::prelude::*;
+use hashbrown::HashMap;
+
+use crate::{
+    common::{
+        console::Registry,
+        net::{
+            background::SocketThread,
+            connect::{
+                ConnectFlags, ConnectListener, Request, RequestConnect, Response, ResponseAccept,
+                ResponseReject, CONNECT_PROTOCOL_VERSION, DEFAULT_PORT,
+            },
+            ClientId, ClientMessage, MessageKind, NetError, Protocol, QSocket, ServerMessage,
+            SignOnStage, GAME_NAME,
+        },
+        vfs::Vfs,
+    },
+    server::{BanList, Session},
+};
+
+/// The listening socket, and the sockets of the clients connected through it.
+#[derive(Resource, Default)]
+pub struct Listener {
+    /// The port last asked for by `sv_port`, whether or not it could be bound.
+    port: Option<u16>,
+    socket: Option<ConnectListener>,
+    #[cfg(all(feature = "websocket", not(target_arch = "wasm32")))]
+    websocket: websocket::WebSocketClients,
+    clients: HashMap<ClientId, RemoteClient>,
+}
+
+struct RemoteClient {
+    thread: SocketThread,
+    addr: SocketAddr,
+    /// When the client was last heard from, in real time.
+    last_received: Duration,
+}
+
+impl Listener {
+    /// Opens or closes the listening socket to follow `sv_listen` and `sv_port`.
+    fn update_socket(&mut self, registry: &Registry) {
+        let listen = registry.read_cvar::<f32>("sv_listen").unwrap_or(0.) != 0.;
+        #[cfg(all(feature = "websocket", not(target_arch = "wasm32")))]
+        self.websocket.update_listener(listen, registry);
+        if !listen {
+            if self.socket.take().is_some() {
+                info!("Stopped listening for clients");
+            }
+            self.port = None;
+            return;
+        }
+
+        let port = registry
+            .read_cvar::<f32>("sv_port")
+            .map_or(DEFAULT_PORT, |port| port as u16);
+        if self.port == Some(port) {
+            return;
+        }
+
+        self.port = Some(port);
+        self.socket = match ConnectListener::bind(("0.0.0.0", port))
+            .and_then(|socket| socket.set_nonblocking(true).map(|()| socket))
+        {
+            Ok(socket) => {
+                info!("Listening for clients on port {}", port);
+                Some(socket)
+            }
+            Err(e) => {
+                error!("Couldn't listen on port {}: {}", port, e);
+                None
+            }
+        };
+    }
+}
+
+/// Returns why a connection request from `remote` must be turned down, as the original server
+/// put it, or `None` if it may take a free slot.
+///
+/// Every client is sent the level in the same `protocol`, so a client that can't speak it is
+/// turned away rather than being given a protocol of its own.
+fn refusal(
+    connect: &RequestConnect,
+    remote: SocketAddr,
+    protocol: Protocol,
+    bans: &BanList,
+) -> Option<&'static str> {
+    if connect.proto_ver != CONNECT_PROTOCOL_VERSION {
+        return Some("Incompatible version.\n");
+    }
+
+    if Protocol::negotiate(protocol, &connect.protocols) != Some(protocol) {
+        return Some("Incompatible protocol.\n");
+    }
+
+    if bans.is_banned(remote.ip()) {
+        info!("Rejected banned address {}", remote);
+        return Some("You have been banned.\n");
+    }
+
+    None
+}
+
+fn reject(message: &str) -> Response {
+    Response::Reject(ResponseReject {
+        message: message.into(),
+    })
+}
+
+/// Decides whether the client that sent `connect` from `remote` is let in, and if so which slot it
+/// takes. Returns `None` for requests that aren't meant for this server, and the response to send
+/// for clients that are turned away.
+fn admit(
+    connect: &RequestConnect,
+    remote: SocketAddr,
+    session: &Session,
+    bans: &BanList,
+) -> Option<Result<ClientId, Response>> {
+    if connect.game_name != GAME_NAME {
+        return None;
+    }
+
+    if let Some(message) = refusal(connect, remote, session.level.protocol, bans) {
+        return Some(Err(reject(message)));
+    }
+
+    Some(
+        session
+            .persist
+            .client_slots
+            .free_slot()
+            .ok_or_else(|| reject("Server is full.\n")),
+    )
+}
+
+fn accept_response(port: u16, flags: ConnectFlags, session: &Session) -> Response {
+    Response::Accept(ResponseAccept {
+        port: port as i32,
+        protocol: Some(session.level.protocol.version()),
+        flags,
+    })
+}
+
+/// Puts a client that was let in into its slot, sending it the server info if the level is
+/// already running.
+#[allow(clippy::too_many_arguments)]
+fn add_client(
+    client_id: ClientId,
+    thread: SocketThread,
+    remote: SocketAddr,
+    now: Duration,
+    session: &mut Session,
+    registry: &Registry,
+    clients: &mut HashMap<ClientId, RemoteClient>,
+    server_messages: &mut EventWriter<ServerMessage>,
+) -> Result<(), NetError> {
+    session.connect_client(client_id);
+    if !session.loading() {
+        // the server info for clients that were there when the level started is sent by
+        // `server_spawn`
+        let packet = session
+            .serverinfo(registry)
+            .map_err(|e| NetError::with_msg(format!("{}", e)))?;
+        if let Some(client) = session.client_mut(client_id) {
+            client.signon = SignOnStage::Prespawn;
+        }
+        server_messages.send(ServerMessage {
+            client_id,
+            packet,
+            kind: MessageKind::Reliable,
+        });
+    }
+
+    clients.insert(
+        client_id,
+        RemoteClient {
+            thread,
+            addr: remote,
+            last_received: now,
+        },
+    );
+    info!("Client {} connected from {}", client_id, remote);
+
+    Ok(())
+}
+
+/// Answers a connection request from `remote`, giving the client a free slot and a socket of its
+/// own if it's let in. Returns `None` for requests that aren't meant for this server.
+#[allow(clippy::too_many_arguments)]
+fn accept(
+    connect: RequestConnect,
+    remote: SocketAddr,
+    listen_addr: SocketAddr,
+    now: Duration,
+    session: &mut Session,
+    registry: &Registry,
+    bans: &BanList,
+    clients: &mut HashMap<ClientId, RemoteClient>,
+    server_messages: &mut EventWriter<ServerMessage>,
+) -> Result<Option<Response>, NetError> {
+    let client_id = match admit(&connect, remote, session, bans) {
+        Some(Ok(client_id)) => client_id,
+        Some(Err(response)) => return Ok(Some(response)),
+        None => return Ok(None),
+    };
+
+    let socket = UdpSocket::bind((listen_addr.ip(), 0))?;
+    let port = socket.local_addr()?.port();
+    let flags = connect.flags & ConnectFlags::all();
+    let mut qsock = QSocket::new(socket, remote);
+    qsock.set_compression(flags.contains(ConnectFlags::COMPRESS));
+    let thread = SocketThread::spawn(qsock)?;
+
+    add_client(
+        client_id,
+        thread,
+        remote,
+        now,
+        session,
+        registry,
+        clients,
+        server_messages,
+    )?;
+
+    Ok(Some(accept_response(port, flags, session)))
+}
+
+#[cfg(all(feature = "websocket", not(target_arch = "wasm32")))]
+mod websocket {
+    use std::mem;
+
+    use crate::common::net::{
+        websocket::{WebSocketListener, WebSocketTransport},
+        BlockingMode,
+    };
+
+    use super::*;
+
+    /// How long a client has to send its connect request once its WebSocket is open.
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// A client whose WebSocket is open but that hasn't asked to connect yet.
+    struct WaitingClient {
+        transport: WebSocketTransport,
+        remote: SocketAddr,
+        since: Duration,
+    }
+
+    #[derive(Default)]
+    pub(super) struct WebSocketClients {
+        /// The port last asked for by `sv_wsport`, whether or not it could be bound.
+        port: Option<u16>,
+        listener: Option<WebSocketListener>,
+        waiting: Vec<WaitingClient>,
+    }
+
+    impl WebSocketClients {
+        /// Opens or closes the listener to follow `sv_listen` and `sv_wsport`.
+        pub(super) fn update_listener(&mut self, listen: bool, registry: &Registry) {
+            let port = registry
+                .read_cvar::<f32>("sv_wsport")
+                .map_or(0, |port| port as u16);
+            let port = (listen && port != 0).then_some(port);
+            if self.port == port {
+                return;
+            }
+
+            self.port = port;
+            self.waiting.clear();
+            if self.listener.take().is_some() {
+                info!("Stopped listening for WebSocket clients");
+            }
+
+            let Some(port) = port else {
+                return;
+            };
+            self.listener = match WebSocketListener::bind(("0.0.0.0", port)) {
+                Ok(listener) => {
+                    info!("Listening for WebSocket clients on port {}", port);
+                    Some(listener)
+                }
+                Err(e) => {
+                    error!(
+                        "Couldn't listen for WebSocket clients on port {}: {}",
+                        port, e
+                    );
+                    None
+                }
+            };
+        }
+
+        /// Takes new WebSockets, and answers the connect requests of those that have sent one.
+        #[allow(clippy::too_many_arguments)]
+        pub(super) fn accept(
+            &mut self,
+            now: Duration,
+            session: &mut Session,
+            registry: &Registry,
+            bans: &BanList,
+            clients: &mut HashMap<ClientId, RemoteClient>,
+            server_messages: &mut EventWriter<ServerMessage>,
+        ) {
+            let Some(listener) = &mut self.listener else {
+                return;
+            };
+
+            loop {
+                match listener.accept() {
+                    Ok(Some((transport, remote))) => self.waiting.push(WaitingClient {
+                        transport,
+                        remote,
+                        since: now,
+                    }),
+                    Ok(None) => break,
+                    Err(e) => {
+                        error!("Failed to accept WebSocket client: {}", e);
+                        break;
+                    }
+                }
+            }
+
+            for mut waiting in mem::take(&mut self.waiting) {
+                let remote = waiting.remote;
+                let connect = match waiting.transport.recv_request(&BlockingMode::NonBlocking) {
+                    Ok(Some(Request::Connect(connect))) => connect,
+                    // server and rule queries aren't answered yet
+                    Ok(Some(_)) => continue,
+                    Ok(None) => {
+                        if now.saturating_sub(waiting.since) < REQUEST_TIMEOUT {
+                            self.waiting.push(waiting);
+                        }
+                        continue;
+                    }
+                    Err(e) => {
+                        debug!("Bad connection request from {}: {}", remote, e);
+                        continue;
+                    }
+                };
+
+                if let Err(e) = accept(
+                    waiting.transport,
+                    connect,
+                    remote,
+                    now,
+                    session,
+                    registry,
+                    bans,
+                    clients,
+                    server_messages,
+                ) {
+                    error!("Failed to accept {}: {}", remote, e);
+                }
+            }
+        }
+    }
+
+    /// Answers a connect request sent over a WebSocket, on the same WebSocket. A client that is
+    /// let in keeps using it, so unlike over UDP there's no port to send it to.
+    #[allow(clippy::too_many_arguments)]
+    fn accept(
+        mut transport: WebSocketTransport,
+        connect: RequestConnect,
+        remote: SocketAddr,
+        now: Duration,
+        session: &mut Session,
+        registry: &Registry,
+        bans: &BanList,
+        clients: &mut HashMap<ClientId, RemoteClient>,
+        server_messages: &mut EventWriter<ServerMessage>,
+    ) -> Result<(), NetError> {
+        let client_id = match admit(&connect, remote, session, bans) {
+            Some(Ok(client_id)) => client_id,
+            Some(Err(response)) => return transport.send_response(&response),
+            None => return Ok(()),
+        };
+
+        let flags = connect.flags & ConnectFlags::all();
+        transport.send_response(&accept_response(0, flags, session))?;
+        let mut qsock = QSocket::with_transport(Box::new(transport));
+        qsock.set_compression(flags.contains(ConnectFlags::COMPRESS));
+        let thread = SocketThread::spawn(qsock)?;
+
+        add_client(
+            client_id,
+            thread,
+            remote,
+            now,
+            session,
+            registry,
+            clients,
+            server_messages,
+        )
+    }
+}
+
+pub mod systems {
+    use super::*;
+
+    /// Takes new connections, and passes on the messages that connected clients have sent. Clients
+    /// that stop responding for `sv_timeout` seconds are dropped.
+    pub fn recv_remote_clients(
+        mut listener: ResMut<Listener>,
+        mut session: ResMut<Session>,
+        mut registry: ResMut<Registry>,
+        bans: Res<BanList>,
+        vfs: Res<Vfs>,
+        time: Res<Time<Real>>,
+        mut server_messages: EventWriter<ServerMessage>,
+        mut client_messages: EventWriter<ClientMessage>,
+    ) {
+        let now = time.elapsed();
+        let timeout = registry.read_cvar::<f32>("sv_timeout").unwrap_or(0.);
+        listener.update_socket(&registry);
+        let Listener {
+            socket,
+            clients,
+            #[cfg(all(feature = "websocket", not(target_arch = "wasm32")))]
+            websocket,
+            ..
+        } = &mut *listener;
+
+        // clients that were kicked or left have already been dropped from the session, and a new
+        // client may be about to take their slot
+        clients.retain(|&client_id, _| session.client(client_id).is_some());
+
+        let mut lost = Vec::new();
+        for (&client_id, client) in clients.iter_mut() {
+            loop {
+                match client.thread.try_recv() {
+                    Ok(Some(packet)) => {
+                        client.last_received = now;
+                        client_messages.send(ClientMessage {
+                            client_id,
+                            packet,
+                            kind: MessageKind::Reliable,
+                        });
+                    }
+                    Ok(None) => break,
+                    Err(e) => debug!("Bad message from client {}: {}", client_id, e),
+                }
+            }
+
+            if client.thread.stopped() {
+                lost.push((client_id, "lost connection"));
+            } else if timeout > 0.
+                && now.saturating_sub(client.last_received).as_secs_f32() > timeout
+            {
+                lost.push((client_id, "timed out"));
+            }
+        }
+
+        for (client_id, reason) in lost {
+            if let Some(client) = clients.remove(&client_id) {
+                info!("Client {} ({}) {}", client_id, client.addr, reason);
+            }
+            if let Err(e) = session.drop_client(client_id, registry.reborrow(), &vfs) {
+                error!("Failed to drop client {}: {}", client_id, e);
+            }
+        }
+
+        #[cfg(all(feature = "websocket", not(target_arch = "wasm32")))]
+        websocket.accept(
+            now,
+            &mut session,
+            &registry,
+            &bans,
+            clients,
+            &mut server_messages,
+        );
+
+        let Some(socket) = socket else {
+            return;
+        };
+        let Ok(listen_addr) = socket.local_addr() else {
+            return;
+        };
+
+        loop {
+            let (request, remote) = match socket.try_recv_request() {
+                Ok(Some(request)) => request,
+                Ok(None) => break,
+                Err(e @ NetError::Io { .. }) => {
+                    error!("Failed to receive connection request: {}", e);
+                    break;
+                }
+                Err(e) => {
+                    debug!("Bad connection request: {}", e);
+                    continue;
+                }
+            };
+
+            let response = match request {
+                Request::Connect(connect) => accept(
+                    connect,
+                    remote,
+                    listen_addr,
+                    now,
+                    &mut session,
+                    &registry,
+                    &bans,
+                    clients,
+                    &mut server_messages,
+                ),
+                // server and rule queries aren't answered yet
+                _ => Ok(None),
+            };
+
+            match response {
+                Ok(Some(response)) => {
+                    if let Err(e) = socket.send_response(response, remote) {
+                        warn!("Failed to answer {}: {}", remote, e);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => error!("Failed to accept {}: {}", remote, e),
+            }
+        }
+    }
+
+    /// Sends the server's messages to the clients connected over the network.
+    pub fn send_remote_clients(
+        listener: Res<Listener>,
+        mut server_messages: EventReader<ServerMessage>,
+    ) {
+        for ServerMessage {
+            client_id,
+            packet,
+            kind,
+        } in server_messages.read()
+        {
+            let Some(client) = listener.clients.get(client_id) else {
+                continue;
+            };
+
+            if packet.is_empty() {
+                continue;
+            }
+
+            if let Err(e) = client.thread.send(*kind, packet.clone()) {
+                debug!("Failed to send to client {}: {}", client_id, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use crate::common::net::{PROTOCOL_FITZQUAKE, SUPPORTED_PROTOCOLS};
+
+    use super::*;
+
+    fn connect_request(proto_ver: u8, protocols: &[i32]) -> RequestConnect {
+        RequestConnect {
+            game_name: GAME_NAME.to_owned(),
+            proto_ver,
+            protocols: protocols.to_vec(),
+            flags: ConnectFlags::empty(),
+        }
+    }
+
+    #[test]
+    fn test_refusal() {
+        let remote = "192.168.0.2:27001".parse::<SocketAddr>().unwrap();
+        let connect = connect_request(CONNECT_PROTOCOL_VERSION, &[]);
+        let netquake = Protocol::NETQUAKE;
+        let mut bans = BanList::default();
+        assert_eq!(refusal(&connect, remote, netquake, &bans), None);
+
+        bans.add("192.168.0.2".parse::<IpAddr>().unwrap());
+        assert_eq!(
+            refusal(&connect, remote, netquake, &bans),
+            Some("You have been banned.\n")
+        );
+
+        // the port doesn't matter, only the address
+        let other_port = "192.168.0.2:27002".parse::<SocketAddr>().unwrap();
+        assert!(refusal(&connect, other_port, netquake, &bans).is_some());
+
+        let other = "192.168.0.3:27001".parse::<SocketAddr>().unwrap();
+        assert_eq!(refusal(&connect, other, netquake, &bans), None);
+        assert_eq!(
+            refusal(
+                &connect_request(CONNECT_PROTOCOL_VERSION + 1, &[]),
+                other,
+                netquake,
+                &bans
+            ),
+            Some("Incompatible version.\n")
+        );
+    }
+
+    #[test]
+    fn test_refusal_protocol() {
+        let remote = "192.168.0.2:27001".parse::<SocketAddr>().unwrap();
+        let bans = BanList::default();
+        let modern = connect_request(CONNECT_PROTOCOL_VERSION, &SUPPORTED_PROTOCOLS);
+        let old = connect_request(CONNECT_PROTOCOL_VERSION, &[]);
+
+        assert_eq!(refusal(&modern, remote, Protocol::FITZQUAKE, &bans), None);
+        assert_eq!(refusal(&modern, remote, Protocol::NETQUAKE, &bans), None);
+        assert_eq!(refusal(&old, remote, Protocol::NETQUAKE, &bans), None);
+
+        // clients that predate negotiation only speak protocol 15
+        assert_eq!(
+            refusal(&old, remote, Protocol::FITZQUAKE, &bans),
+            Some("Incompatible protocol.\n")
+        );
+
+        let fitz_only = connect_request(CONNECT_PROTOCOL_VERSION, &[PROTOCOL_FITZQUAKE]);
+        assert_eq!(
+            refusal(&fitz_only, remote, Protocol::RMQ, &bans),
+            Some("Incompatible protocol.\n")
+        );
+    }
+}
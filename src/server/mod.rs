@@ -15,24 +15,50 @@
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
+mod chat;
+pub mod clock;
 mod commands;
+pub mod config;
 mod cvars;
+pub mod download;
+pub mod flood;
+pub mod lagcomp;
+pub mod listen;
+pub mod ping;
 pub mod precache;
 pub mod progs;
+pub mod rate;
+pub mod record;
+pub mod speedcheck;
 pub mod world;
 
-use std::{fmt, io::Write, ops::Bound};
+use std::{
+    collections::BTreeSet,
+    fmt,
+    io::{Read as _, Write},
+    iter, mem,
+    net::IpAddr,
+    ops::Bound,
+    time::Instant,
+};
 
 use crate::{
     common::{
+        bsp,
         console::{Registry, RunCmd},
         engine::{self, duration_from_f32, duration_to_f32},
-        math::Hyperplane,
+        math::{Angles, Hyperplane},
         model::Model,
-        net::{EntityState, ServerCmd},
+        net::{
+            userinfo::{UserInfo, USERINFO_COLORS, USERINFO_NAME},
+            ClientId, EntityState, ItemFlags, NetError, PlayerColor, PlayerData, Protocol,
+            ProtocolFlags, ServerCmd, SignOnStage, CHAT_MARKER, DEFAULT_VIEWHEIGHT,
+            MAX_DOWNLOAD_CHUNK, MAX_MESSAGE, PROTOCOL_RMQ,
+        },
         parse,
+        physics::pmove::{self, MoveCmd, MoveVars, PlayerState},
         util::QString,
-        vfs::Vfs,
+        vfs::{self, Vfs, VfsError},
     },
     server::{
         progs::{functions::FunctionKind, GlobalAddrFunction},
@@ -41,23 +67,37 @@ use crate::{
 };
 
 use self::{
+    clock::{ServerClock, ServerPostTick, ServerPreTick, ServerTick},
+    config::ServerCvars,
+    download::Download,
+    flood::CmdRate,
+    lagcomp::{LagCompVars, LagCompensation},
+    ping::PingTimes,
     precache::Precache,
     progs::{
         globals::{
             GlobalAddr as _, GLOBAL_ADDR_ARG_0, GLOBAL_ADDR_ARG_1, GLOBAL_ADDR_ARG_2,
             GLOBAL_ADDR_ARG_3, GLOBAL_ADDR_ARG_4, GLOBAL_ADDR_RETURN,
         },
-        EntityFieldAddr, EntityId, ExecutionContext, FunctionId, GlobalAddrEntity, GlobalAddrFloat,
-        GlobalAddrVector, Globals, LoadProgs, Opcode, ProgsError, StringId, StringTable,
+        EntityFieldAddr, EntityId, ExecutionContext, FieldDef, FunctionId, GlobalAddrEntity,
+        GlobalAddrFloat, GlobalAddrVector, Globals, LoadProgs, Opcode, ProgsError, StringId,
+        StringTable, Type,
     },
+    rate::RateLimit,
+    speedcheck::{MoveCheck, MoveClock},
     world::{
         phys::{self, CollideKind, CollisionFlags, Trace, TraceEndKind},
-        EntityFlags, EntitySolid, FieldAddrFloat, FieldAddrFunctionId, FieldAddrStringId, World,
+        EntityFlags, EntitySolid, FieldAddrFloat, FieldAddrFunctionId, FieldAddrStringId,
+        PlayerMoveWorld, World,
     },
 };
 
 use arrayvec::ArrayVec;
-use bevy::prelude::*;
+use bevy::{
+    app::{AppExit, RunFixedMainLoop},
+    prelude::*,
+    tasks::{ComputeTaskPool, TaskPool},
+};
 use bitflags::bitflags;
 use byteorder::{LittleEndian, WriteBytesExt as _};
 use cgmath::{Array, Deg, InnerSpace, Matrix3, Vector3, Zero};
@@ -80,10 +120,15 @@ pub struct SeismonServerPlugin;
 impl Plugin for SeismonServerPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(
-            FixedUpdate,
+            ServerTick,
             (
                 systems::recv_client_messages,
+                systems::update_rate_limits
+                    .before(systems::send_downloads)
+                    .before(systems::server_update),
+                systems::send_downloads,
                 systems::server_update,
+                systems::exec_local_cmds,
                 systems::server_spawn.pipe(
                     |In(res), mut commands: Commands, mut runcmd: EventWriter<RunCmd<'static>>| {
                         if let Err(e) = res {
@@ -95,7 +140,36 @@ impl Plugin for SeismonServerPlugin {
                 ),
             )
                 .run_if(resource_exists::<Session>),
-        );
+        )
+        .add_systems(
+            ServerPreTick,
+            listen::systems::recv_remote_clients
+                .run_if(resource_exists::<Session>.and_then(resource_exists::<Vfs>)),
+        )
+        .add_systems(
+            ServerPostTick,
+            listen::systems::send_remote_clients.run_if(resource_exists::<Session>),
+        )
+        .add_systems(RunFixedMainLoop, clock::run_ticks);
+
+        app.init_resource::<CvarLimits>()
+            .init_resource::<BanList>()
+            .init_resource::<listen::Listener>()
+            .init_resource::<DebugBounds>()
+            .add_systems(Update, systems::publish_debug_bounds)
+            .add_systems(
+                PreStartup,
+                systems::exec_server_config.run_if(resource_exists::<Vfs>),
+            )
+            .add_systems(
+                Startup,
+                systems::load_ban_list.run_if(resource_exists::<Vfs>),
+            )
+            .add_systems(
+                Last,
+                systems::save_server_config
+                    .run_if(resource_exists::<Vfs>.and_then(on_event::<AppExit>())),
+            );
 
         commands::register_commands(app);
         cvars::register_cvars(app);
@@ -106,9 +180,26 @@ impl Plugin for SeismonServerPlugin {
 pub struct Client {
     name: QString,
     color: u8,
+    userinfo: UserInfo,
     state: ClientState,
-    // TODO: Per-client send
+    /// The last sign-on stage sent to the client, which decides the sign-on commands it may send.
+    signon: SignOnStage,
+    /// The frag count last sent to clients.
+    old_frags: i32,
+    /// The string commands and impulses sent by the client recently.
+    cmd_rate: CmdRate,
+    /// The bytes the client may be sent.
+    rate_limit: RateLimit,
+    /// The file being sent to the client, if it asked for one.
+    download: Option<Download>,
+    /// Messages for this client alone, sent with its next update.
     buffer: Vec<u8>,
+    /// The time covered by the client's recent moves.
+    move_clock: MoveClock,
+    /// The round trips of the client's recent moves.
+    ping_times: PingTimes,
+    /// Whether the client was last told that the game is paused.
+    paused: bool,
 }
 
 impl Default for Client {
@@ -116,8 +207,17 @@ impl Default for Client {
         Self {
             name: "player".into(),
             color: 0,
+            userinfo: default(),
             state: ClientState::Connecting,
+            signon: SignOnStage::Not,
+            old_frags: 0,
+            cmd_rate: default(),
+            rate_limit: default(),
+            download: None,
             buffer: default(),
+            move_clock: default(),
+            ping_times: default(),
+            paused: false,
         }
     }
 }
@@ -129,6 +229,22 @@ impl Client {
             _ => None,
         }
     }
+
+    /// Whether this client may run any command and should see server diagnostics.
+    pub fn privileged(&self) -> bool {
+        matches!(
+            self.state,
+            ClientState::Active(ClientActive {
+                privileged: true,
+                ..
+            })
+        )
+    }
+
+    /// Round-trip time to the client, averaged over its recent moves.
+    pub fn ping(&self) -> Duration {
+        self.ping_times.average()
+    }
 }
 
 #[derive(Debug)]
@@ -177,15 +293,15 @@ impl ClientSlots {
         ClientSlots { slots }
     }
 
-    pub fn connected_clients(&self) -> impl Iterator<Item = usize> + '_ {
+    pub fn connected_clients(&self) -> impl Iterator<Item = ClientId> + '_ {
         self.slots
             .iter()
             .enumerate()
             .filter(|(_, v)| v.is_some())
-            .map(|(i, _)| i)
+            .map(|(i, _)| ClientId(i))
     }
 
-    pub fn active_clients(&self) -> impl Iterator<Item = usize> + '_ {
+    pub fn active_clients(&self) -> impl Iterator<Item = ClientId> + '_ {
         self.connected_clients()
             .filter(|&i| matches!(self.get(i).map(|c| &c.state), Some(ClientState::Active(_))))
     }
@@ -194,16 +310,16 @@ impl ClientSlots {
     ///
     /// If the slot is unoccupied, or if `id` is greater than `self.limit()`,
     /// returns `None`.
-    pub fn get(&self, id: usize) -> Option<&Client> {
-        self.slots.get(id)?.as_ref()
+    pub fn get(&self, id: ClientId) -> Option<&Client> {
+        self.slots.get(id.0)?.as_ref()
     }
 
     /// Returns a reference to the client in a slot.
     ///
     /// If the slot is unoccupied, or if `id` is greater than `self.limit()`,
     /// returns `None`.
-    pub fn get_mut(&mut self, id: usize) -> Option<&mut Client> {
-        self.slots.get_mut(id)?.as_mut()
+    pub fn get_mut(&mut self, id: ClientId) -> Option<&mut Client> {
+        self.slots.get_mut(id.0)?.as_mut()
     }
 
     /// Returns the maximum number of simultaneous clients.
@@ -211,17 +327,39 @@ impl ClientSlots {
         self.slots.len()
     }
 
-    /// Finds an available connection slot for a new client.
-    pub fn find_available(&mut self) -> Option<&mut Client> {
-        let slot = self.slots.iter_mut().find(|s| s.is_none())?;
+    /// Puts a new client in a slot, replacing any client already there.
+    ///
+    /// Returns `None` if `id` is greater than `self.limit()`.
+    pub fn insert(&mut self, id: ClientId) -> Option<&mut Client> {
+        let slot = self.slots.get_mut(id.0)?;
         Some(slot.insert(Client::default()))
     }
+
+    /// Frees a slot, returning the client which occupied it.
+    pub fn remove(&mut self, id: ClientId) -> Option<Client> {
+        self.slots.get_mut(id.0)?.take()
+    }
+
+    /// Returns the first slot that no client occupies.
+    pub fn free_slot(&self) -> Option<ClientId> {
+        (0..self.limit())
+            .map(ClientId)
+            .find(|&id| self.get(id).is_none())
+    }
+
+    /// Finds the slot of the connected client with the given name.
+    pub fn find_by_name(&self, name: &str) -> Option<ClientId> {
+        self.connected_clients()
+            .find(|&i| self.get(i).is_some_and(|c| c.name.to_str() == name))
+    }
 }
 
 /// Server state that persists between levels.
 pub struct SessionPersistent {
     client_slots: ClientSlots,
     flags: SessionFlags,
+    /// When the server was started.
+    started: Instant,
 }
 
 impl SessionPersistent {
@@ -229,15 +367,16 @@ impl SessionPersistent {
         SessionPersistent {
             client_slots: ClientSlots::new(max_clients),
             flags: SessionFlags::empty(),
+            started: Instant::now(),
         }
     }
 
-    pub fn client(&self, slot: usize) -> Option<&Client> {
-        self.client_slots.get(slot)
+    pub fn client(&self, id: ClientId) -> Option<&Client> {
+        self.client_slots.get(id)
     }
 
-    pub fn client_mut(&mut self, slot: usize) -> Option<&mut Client> {
-        self.client_slots.get_mut(slot)
+    pub fn client_mut(&mut self, id: ClientId) -> Option<&mut Client> {
+        self.client_slots.get_mut(id)
     }
 }
 
@@ -278,269 +417,1702 @@ impl Session {
         }
     }
 
+    /// Replace the running program with a newly-loaded `progs.dat` and restart the level.
+    ///
+    /// Every map entity is respawned from scratch. The entities belonging to active clients are
+    /// carried over, with their fields remapped to the new layout, and those clients must sign on
+    /// again to re-enter the level.
+    pub fn reload(
+        &mut self,
+        mut registry: Mut<Registry>,
+        vfs: &Vfs,
+        progs: LoadProgs,
+        models: Vec<Model>,
+        entmap: String,
+    ) -> Result<(), failure::Error> {
+        let old = mem::replace(
+            &mut self.level,
+            LevelState::new(
+                self.level.map_path.clone(),
+                progs,
+                models,
+                entmap,
+                registry.reborrow(),
+                vfs,
+            ),
+        );
+        self.state = SessionState::Loading;
+
+        let active = self
+            .persist
+            .client_slots
+            .active_clients()
+            .collect::<Vec<_>>();
+        for slot in active {
+            let client = self.persist.client_mut(slot).unwrap();
+            let ClientState::Active(ClientActive {
+                privileged,
+                entity_id: old_id,
+            }) = client.state
+            else {
+                continue;
+            };
+
+            let level = &mut self.level;
+            let remapped = old.world.entities.try_get(old_id)?.remap(
+                &old.world.type_def,
+                &old.string_table,
+                &level.world.type_def,
+                &mut level.string_table,
+            );
+
+            let new_id = level.world.alloc_uninitialized_reserved()?;
+            *level.world.entities.get_mut(new_id)? = remapped;
+
+            client.state = ClientState::Active(ClientActive {
+                privileged,
+                entity_id: new_id,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Load the level at `map_path`, which may be the one being played, in place of the current
+    /// one. The connected clients keep their slots and sign on to the new level, where they
+    /// enter the game as if for the first time.
+    pub fn load_level(
+        &mut self,
+        map_path: String,
+        registry: Mut<Registry>,
+        vfs: &Vfs,
+        progs: LoadProgs,
+        models: Vec<Model>,
+        entmap: String,
+    ) {
+        self.level = LevelState::new(map_path, progs, models, entmap, registry, vfs);
+        self.state = SessionState::Loading;
+
+        let connected = self
+            .persist
+            .client_slots
+            .connected_clients()
+            .collect::<Vec<_>>();
+        for slot in connected {
+            // their entities belonged to the old level
+            self.persist.client_mut(slot).unwrap().state = ClientState::Connecting;
+        }
+    }
+
     /// Returns the maximum number of clients allowed on the server.
     pub fn max_clients(&self) -> usize {
         self.persist.client_slots.limit()
     }
 
+    /// Whether the local player is the only one connected, so that pausing the game can't hold
+    /// anyone else up.
+    pub fn is_single_player(&self) -> bool {
+        self.persist
+            .client_slots
+            .connected_clients()
+            .all(|id| id == ClientId::LOCAL)
+    }
+
     #[inline]
-    pub fn client(&self, slot: usize) -> Option<&Client> {
-        self.persist.client(slot)
+    pub fn client(&self, id: ClientId) -> Option<&Client> {
+        self.persist.client(id)
     }
 
     #[inline]
-    pub fn client_mut(&mut self, slot: usize) -> Option<&mut Client> {
-        self.persist.client_mut(slot)
+    pub fn client_mut(&mut self, id: ClientId) -> Option<&mut Client> {
+        self.persist.client_mut(id)
     }
 
-    pub fn new_client(&mut self) -> Option<&mut Client> {
-        self.persist.client_slots.find_available()
+    /// Gives a newly-connected client the slot `id`. The client is sent the server info once the
+    /// level has loaded.
+    pub fn connect_client(&mut self, id: ClientId) -> Option<&mut Client> {
+        self.persist.client_slots.insert(id)
     }
 
-    pub fn clientcmd_prespawn(&mut self, slot: usize) -> Result<(), failure::Error> {
-        self.new_client().unwrap();
+    /// Returns the server info that starts a client's sign-on to the level: the precache lists,
+    /// the light styles and the command to move on to prespawn.
+    pub fn serverinfo(&self, registry: &Registry) -> Result<Vec<u8>, ProgsError> {
+        let teamplay = registry
+            .get_cvar("teamplay")
+            .and_then(|t| t.value().as_name());
 
-        // TODO: Actually run prespawn routines
+        // Match string with `starts_with` so we can handle `?GameName`
+        let game_type = match teamplay {
+            Some(t) if t.starts_with("0") => GameType::Deathmatch,
+            Some(t) if t.starts_with("1") || t.starts_with("2") => GameType::CoOp,
+            // Invalid game type, default to DM
+            _ => GameType::Deathmatch,
+        };
 
-        Ok(())
-    }
+        let mut packet = Vec::new();
+        let protocol = self.level.protocol;
+        ServerCmd::ServerInfo {
+            protocol_version: protocol.version(),
+            max_clients: self.max_clients() as _,
+            game_type,
+            message: "Seismon server".into(),
+            protocol_flags: protocol.flags(),
+            model_precache: self
+                .level
+                .model_precache
+                .iter()
+                .map(ToOwned::to_owned)
+                .collect(),
+            sound_precache: self
+                .level
+                .sound_precache
+                .iter()
+                .map(ToOwned::to_owned)
+                .collect(),
+        }
+        .serialize_with(&mut packet, protocol)?;
 
-    pub fn clientcmd_name(&mut self, slot: usize, name: QString) -> Result<(), failure::Error> {
-        let Some(client) = self.persist.client_mut(slot) else {
-            bail!("No such client {}", slot);
-        };
+        for (id, style) in self.level.lightstyles.iter().enumerate() {
+            let value = self.level.string_table.get(*style).unwrap_or_default();
+            if !value.is_empty() {
+                ServerCmd::LightStyle {
+                    id: id as _,
+                    value: value.into_owned(),
+                }
+                .serialize(&mut packet)?;
+            }
+        }
 
-        ServerCmd::UpdateName {
-            player_id: slot as _,
-            new_name: name.clone(),
+        ServerCmd::SignOnStage {
+            stage: SignOnStage::Prespawn,
         }
-        .serialize(&mut self.level.broadcast)?;
+        .serialize(&mut packet)?;
 
-        client.name = name;
+        Ok(packet)
+    }
 
-        Ok(())
+    /// Returns the frag count of the client in a slot, if it has entered the game.
+    pub fn frags(&self, id: ClientId) -> Option<i32> {
+        self.level.client_frags(self.client(id)?)
     }
 
-    pub fn clientcmd_color(&mut self, slot: usize, color: u8) -> Result<(), failure::Error> {
-        let Some(client) = self.client_mut(slot) else {
-            bail!("No such client {}", slot);
-        };
+    /// Formats the fields of the entity with the given number for the `edict` command.
+    pub fn describe_edict(&self, id: usize) -> Option<String> {
+        self.level.describe_entity(EntityId(id))
+    }
 
-        client.color = color;
+    /// Reads a float field of the entity with the given number by name, e.g. `health`.
+    pub fn edict_float(&self, id: usize, field: &str) -> Option<f32> {
+        self.level.entity_float(EntityId(id), field)
+    }
 
-        Ok(())
+    /// Reads a vector field of the entity with the given number by name, e.g. `origin`.
+    pub fn edict_vector(&self, id: usize, field: &str) -> Option<[f32; 3]> {
+        self.level.entity_vector(EntityId(id), field)
     }
 
-    // TODO: Spawn parameters
-    pub fn clientcmd_spawn(&mut self, slot: usize) -> Result<(), failure::Error> {
-        let Some(_client) = self.client(slot) else {
-            bail!("No such client {}", slot);
-        };
+    /// Returns the numbers of the solid entities whose centers are within `radius` of `origin`,
+    /// as the `findradius` builtin finds them.
+    pub fn edicts_in_radius(&self, origin: [f32; 3], radius: f32) -> Vec<usize> {
+        self.level
+            .entities_in_radius(origin.into(), radius)
+            .map(|ids| ids.into_iter().map(|id| id.0).collect())
+            .unwrap_or_default()
+    }
 
-        // TODO: Actually run spawn routines
+    /// Returns the numbers of every entity in the level.
+    pub fn edicts(&self) -> impl Iterator<Item = usize> + '_ {
+        self.level.world.entities.iter().map(|id| id.0)
+    }
 
-        Ok(())
+    pub fn edict_counts(&self) -> EntityCounts {
+        self.level.entity_counts()
     }
 
-    pub fn clientcmd_begin(
+    /// Returns how long the server has been running, across level changes.
+    pub fn uptime(&self) -> std::time::Duration {
+        self.persist.started.elapsed()
+    }
+
+    /// Returns the name of the current map, e.g. `e1m1`.
+    pub fn map_name(&self) -> &str {
+        self.level.map_name()
+    }
+
+    /// Returns the slot of the connected client with the given name.
+    pub fn find_client(&self, name: &str) -> Option<ClientId> {
+        self.persist.client_slots.find_by_name(name)
+    }
+
+    /// Removes a client from the server.
+    ///
+    /// If the client had entered the game, `ClientDisconnect` is run for its entity before the
+    /// entity is freed, and the entity and slot are freed even if that fails. The client's
+    /// scoreboard entry is cleared for everyone else.
+    pub fn drop_client(
         &mut self,
-        slot: usize,
+        id: ClientId,
         mut registry: Mut<Registry>,
         vfs: &Vfs,
     ) -> Result<(), failure::Error> {
-        let client_entity = self.level.world.alloc_uninitialized_reserved()?;
+        let Some(entity) = self.persist.client_slots.get(id).map(Client::entity) else {
+            bail!("No such client {}", id);
+        };
 
-        let Some(client) = self.client_mut(slot) else {
-            bail!("No such client {}", slot);
+        // the progs see the player while it's still in the game, and it's freed even if they fail
+        let disconnected = match entity {
+            Some(entity) => self.client_disconnect(entity, registry.reborrow(), vfs),
+            None => Ok(()),
         };
+        if let Some(entity) = entity {
+            self.level.world.remove_client_entity(entity)?;
+        }
+        let client = self.persist.client_slots.remove(id).unwrap();
 
-        // TODO: All players are currently privileged
-        client.state = ClientState::Active(ClientActive {
-            privileged: true,
-            entity_id: client_entity,
-        });
+        ServerCmd::UpdateName {
+            player_id: id.0 as _,
+            new_name: default(),
+        }
+        .serialize(&mut self.level.broadcast)?;
+        ServerCmd::UpdateFrags {
+            player_id: id.0 as _,
+            new_frags: 0,
+        }
+        .serialize(&mut self.level.broadcast)?;
+        ServerCmd::UpdateColors {
+            player_id: id.0 as _,
+            new_colors: PlayerColor::from_bits(0),
+        }
+        .serialize(&mut self.level.broadcast)?;
 
-        self.level
-            .globals
-            .store(GlobalAddrEntity::Self_, client_entity)?;
-        self.level
-            .globals
-            .store(GlobalAddrFloat::Time, duration_to_f32(self.level.time))?;
+        info!("Client {} ({}) disconnected", id, client.name);
 
-        let client_connect = self
-            .level
-            .globals
-            .function_id(GlobalAddrFunction::ClientConnect as i16)?;
-        self.level
-            .execute_program(client_connect, registry.reborrow(), vfs)?;
+        disconnected
+    }
 
-        self.level
-            .globals
-            .store(GlobalAddrEntity::Self_, client_entity)?;
+    /// Runs the progs' `ClientDisconnect` for the player `entity`.
+    fn client_disconnect(
+        &mut self,
+        entity: EntityId,
+        mut registry: Mut<Registry>,
+        vfs: &Vfs,
+    ) -> Result<(), failure::Error> {
+        self.level.globals.store(GlobalAddrEntity::Self_, entity)?;
         self.level
             .globals
             .store(GlobalAddrFloat::Time, duration_to_f32(self.level.time))?;
 
-        let put_client_in_server = self
+        let client_disconnect = self
             .level
             .globals
-            .function_id(GlobalAddrFunction::PutClientInServer as i16)?;
+            .function_id(GlobalAddrFunction::ClientDisconnect as i16)?;
         self.level
-            .execute_program(put_client_in_server, registry.reborrow(), vfs)?;
+            .execute_program(client_disconnect, registry.reborrow(), vfs)?;
 
         Ok(())
     }
 
-    pub fn precache_sound(&mut self, name_id: StringId) {
-        if let SessionState::Loading = self.state {
-            self.level.precache_sound(name_id);
-        } else {
-            panic!("Sounds cannot be precached after loading");
+    /// Counts a string command or impulse that a client sent at `now`. Returns `false` if the
+    /// client has sent more than `limit` of them in the last second and should be kicked.
+    /// The local client is the host's own, and is never kicked.
+    pub fn record_cmd(&mut self, id: ClientId, now: std::time::Duration, limit: u32) -> bool {
+        if id == ClientId::LOCAL {
+            return true;
+        }
+
+        match self.client_mut(id) {
+            Some(client) => client.cmd_rate.record(now, limit),
+            None => true,
         }
     }
 
-    pub fn precache_model(&mut self, name_id: StringId) {
-        if let SessionState::Loading = self.state {
-            self.level.precache_model(name_id);
-        } else {
-            panic!("Models cannot be precached after loading");
+    /// Checks a move that a client stamped with `send_time` and that arrived at the real time
+    /// `now`, logging the first move in a while that gets ahead of the level's clock.
+    pub fn check_move(
+        &mut self,
+        id: ClientId,
+        now: std::time::Duration,
+        send_time: Duration,
+    ) -> MoveCheck {
+        let Some(level_time) = self.time().and_then(|t| t.to_std().ok()) else {
+            return MoveCheck::Accept;
+        };
+        let Some(client) = self.client_mut(id) else {
+            return MoveCheck::Accept;
+        };
+        let Ok(send_time) = send_time.to_std() else {
+            return MoveCheck::Future;
+        };
+
+        let check = client.move_clock.record(now, send_time, level_time);
+        if let MoveCheck::TooFast { flagged: true } = check {
+            warn!(
+                "Client {} is moving faster than the game allows ({} times so far)",
+                id,
+                client.move_clock.violations()
+            );
         }
+
+        check
     }
 
-    pub fn loading(&self) -> bool {
-        if let SessionState::Loading = self.state {
-            true
-        } else {
-            false
+    /// Counts the round trip of a move that a client stamped with `send_time` towards its ping.
+    pub fn record_ping(&mut self, id: ClientId, send_time: Duration) {
+        let now = self.level.time;
+        if let Some(client) = self.client_mut(id) {
+            client.ping_times.record(now, send_time);
         }
     }
 
-    #[inline]
-    fn level(&self) -> &LevelState {
-        &self.level
+    /// Moves a client on to the next stage of signing on, if it has reached the stage that a
+    /// sign-on command belongs to. As in the original server, commands sent out of turn are
+    /// refused.
+    fn advance_signon(
+        &mut self,
+        id: ClientId,
+        expected: SignOnStage,
+        next: SignOnStage,
+    ) -> Result<(), failure::Error> {
+        let Some(client) = self.client_mut(id) else {
+            bail!("No such client {}", id);
+        };
+        if client.signon != expected {
+            bail!("not valid at sign-on stage {:?}", client.signon);
+        }
+
+        client.signon = next;
+
+        Ok(())
     }
 
-    #[inline]
-    fn level_mut(&mut self) -> &mut LevelState {
-        &mut self.level
+    /// Starts sending the file at `path` to a client that is about to sign on. Returns `false` if
+    /// the client can't have the file.
+    pub fn clientcmd_download(
+        &mut self,
+        id: ClientId,
+        path: &str,
+        allowed: bool,
+        vfs: &Vfs,
+    ) -> Result<bool, failure::Error> {
+        let Some(client) = self.client_mut(id) else {
+            bail!("No such client {}", id);
+        };
+
+        // a new request replaces any download that hasn't finished
+        client.download = None;
+
+        // files are only sent while signing on, before the client has a place in the level
+        if !allowed || client.signon != SignOnStage::Prespawn || !vfs::is_downloadable(path) {
+            return Ok(false);
+        }
+
+        let mut data = Vec::new();
+        match vfs.open(path) {
+            Ok(mut file) => file.read_to_end(&mut data)?,
+            Err(VfsError::NoSuchFile(_)) => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+
+        debug!("Sending {} ({} bytes) to client {}", path, data.len(), id);
+        client.download = Some(Download::new(data));
+
+        Ok(true)
+    }
+
+    pub fn clientcmd_prespawn(&mut self, id: ClientId) -> Result<(), failure::Error> {
+        self.advance_signon(id, SignOnStage::Prespawn, SignOnStage::ClientInfo)?;
+
+        // TODO: Actually run prespawn routines
+
+        Ok(())
+    }
+
+    /// Replace the client's userinfo with the full string sent during signon.
+    pub fn clientcmd_userinfo(
+        &mut self,
+        id: ClientId,
+        userinfo: UserInfo,
+    ) -> Result<(), failure::Error> {
+        let Some(client) = self.persist.client_mut(id) else {
+            bail!("No such client {}", id);
+        };
+
+        // Keys starting with `*` are reserved for the server
+        let mut filtered = UserInfo::new();
+        for (key, value) in userinfo.iter().filter(|(k, _)| !k.starts_with('*')) {
+            filtered.set(key, value)?;
+        }
+        client.userinfo = filtered;
+
+        self.update_client_info(id)
+    }
+
+    /// Set a single key in the client's userinfo.
+    pub fn clientcmd_setinfo(
+        &mut self,
+        id: ClientId,
+        key: &str,
+        value: &str,
+    ) -> Result<(), failure::Error> {
+        let Some(client) = self.persist.client_mut(id) else {
+            bail!("No such client {}", id);
+        };
+
+        if key.starts_with('*') {
+            bail!("Can't set reserved userinfo key {}", key);
+        }
+
+        client.userinfo.set(key, value)?;
+
+        self.update_client_info(id)
+    }
+
+    pub fn clientcmd_name(&mut self, id: ClientId, name: QString) -> Result<(), failure::Error> {
+        self.clientcmd_setinfo(id, USERINFO_NAME, &name.to_str())
+    }
+
+    pub fn clientcmd_color(&mut self, id: ClientId, color: u8) -> Result<(), failure::Error> {
+        self.clientcmd_setinfo(id, USERINFO_COLORS, &color.to_string())
+    }
+
+    /// Send a chat message from a client to every player, or with `team` set and `teamplay` on,
+    /// to the players on the same team.
+    pub fn clientcmd_say(
+        &mut self,
+        id: ClientId,
+        text: &str,
+        team: bool,
+        teamplay: bool,
+    ) -> Result<(), failure::Error> {
+        let Some(client) = self.client(id) else {
+            bail!("No such client {}", id);
+        };
+
+        let sender_color = PlayerColor::from_bits(client.color);
+        let text = chat::chat_line(&client.name.to_str(), text, team);
+        info!(
+            "{}",
+            text.trim_start_matches(CHAT_MARKER as char).trim_end()
+        );
+
+        let slots = &mut self.persist.client_slots;
+        for other in slots.active_clients().collect::<Vec<_>>() {
+            let Some(client) = slots.get_mut(other) else {
+                continue;
+            };
+
+            if team
+                && teamplay
+                && !chat::same_team(sender_color, PlayerColor::from_bits(client.color))
+            {
+                continue;
+            }
+
+            ServerCmd::Print {
+                text: text.clone().into(),
+            }
+            .serialize(&mut client.buffer)?;
+        }
+
+        Ok(())
     }
 
-    #[inline]
-    pub fn sound_id(&self, name_id: StringId) -> Option<usize> {
-        self.level().sound_id(name_id)
+    /// Run one of the cheat commands `god`, `notarget`, `noclip`, `fly` or `give` on a client's
+    /// entity, returning a message to show the client.
+    ///
+    /// The caller is responsible for checking that `sv_cheats` is enabled.
+    pub fn clientcmd_cheat(
+        &mut self,
+        id: ClientId,
+        cmd: &str,
+        args: &[&str],
+    ) -> Result<String, failure::Error> {
+        let Some(client) = self.client(id) else {
+            bail!("No such client {}", id);
+        };
+        if !client.privileged() {
+            bail!("{}: not allowed", cmd);
+        }
+        let Some(entity) = client.entity() else {
+            bail!("{}: not in the game", cmd);
+        };
+
+        let type_def = &self.level.world.type_def;
+        let ent = self.level.world.entities.get_mut(entity)?;
+
+        let on_off = |on: bool| if on { "ON" } else { "OFF" };
+        match cmd {
+            "god" | "notarget" => {
+                let (flag, name) = match cmd {
+                    "god" => (EntityFlags::GOD_MODE, "godmode"),
+                    _ => (EntityFlags::NO_TARGET, "notarget"),
+                };
+                let flags = ent.flags(type_def)? ^ flag;
+                ent.store(type_def, FieldAddrFloat::Flags, flags.bits() as f32)?;
+
+                Ok(format!("{} {}\n", name, on_off(flags.contains(flag))))
+            }
+
+            "noclip" | "fly" => {
+                let (kind, name) = match cmd {
+                    "noclip" => (MoveKind::NoClip, "noclip"),
+                    _ => (MoveKind::Fly, "flymode"),
+                };
+                let enable = ent.move_kind(type_def)? != kind;
+                let new_kind = if enable { kind } else { MoveKind::Walk };
+                ent.store(type_def, FieldAddrFloat::MoveKind, new_kind as i32 as f32)?;
+
+                Ok(format!("{} {}\n", name, on_off(enable)))
+            }
+
+            "give" => {
+                let [item, rest @ ..] = args else {
+                    bail!("usage: give <2-8|s|n|r|c|h> [amount]");
+                };
+                let amount = match rest.first() {
+                    Some(amount) => amount.parse::<f32>()?,
+                    None => 100.,
+                };
+
+                let field = match *item {
+                    // weapons are numbered as they are selected, from the shotgun at 2
+                    "2" | "3" | "4" | "5" | "6" | "7" | "8" => {
+                        let weapon = ItemFlags::SHOTGUN.bits() << (item.parse::<u32>()? - 2);
+                        let items = ent.get_float(type_def, FieldAddrFloat::Items as i16)? as u32;
+                        ent.store(type_def, FieldAddrFloat::Items, (items | weapon) as f32)?;
+
+                        return Ok(default());
+                    }
+                    "s" => FieldAddrFloat::AmmoShells,
+                    "n" => FieldAddrFloat::AmmoNails,
+                    "r" => FieldAddrFloat::AmmoRockets,
+                    "c" => FieldAddrFloat::AmmoCells,
+                    "h" => FieldAddrFloat::Health,
+                    other => bail!("give: unknown item {}", other),
+                };
+                ent.store(type_def, field, amount)?;
+
+                Ok(default())
+            }
+
+            other => bail!("{}: not a cheat command", other),
+        }
+    }
+
+    /// Notify other clients of any changes to the name or colors in a client's userinfo.
+    fn update_client_info(&mut self, id: ClientId) -> Result<(), failure::Error> {
+        let Some(client) = self.persist.client_mut(id) else {
+            bail!("No such client {}", id);
+        };
+
+        let name = QString::from(client.userinfo.name().to_owned());
+        if name != client.name {
+            ServerCmd::UpdateName {
+                player_id: id.0 as _,
+                new_name: name.clone(),
+            }
+            .serialize(&mut self.level.broadcast)?;
+
+            client.name = name;
+        }
+
+        let colors = client.userinfo.colors();
+        if colors.bits() != client.color {
+            ServerCmd::UpdateColors {
+                player_id: id.0 as _,
+                new_colors: colors,
+            }
+            .serialize(&mut self.level.broadcast)?;
+
+            client.color = colors.bits();
+        }
+
+        Ok(())
+    }
+
+    // TODO: Spawn parameters
+    pub fn clientcmd_spawn(&mut self, id: ClientId) -> Result<(), failure::Error> {
+        self.advance_signon(id, SignOnStage::ClientInfo, SignOnStage::Begin)?;
+
+        // TODO: Actually run spawn routines
+
+        Ok(())
+    }
+
+    pub fn clientcmd_begin(
+        &mut self,
+        id: ClientId,
+        mut registry: Mut<Registry>,
+        vfs: &Vfs,
+    ) -> Result<(), failure::Error> {
+        self.advance_signon(id, SignOnStage::Begin, SignOnStage::Done)?;
+
+        let client_entity = match self.client(id).and_then(Client::entity) {
+            // Clients carried over from a restarted level keep their entity
+            Some(entity) if self.level.world.entities.exists(entity) => entity,
+            _ => self.level.world.alloc_uninitialized_reserved()?,
+        };
+
+        let spawn_vars = registry.read_cvars::<SpawnVars>().unwrap_or_default();
+        let other_players = self
+            .persist
+            .client_slots
+            .active_clients()
+            .filter(|&other| other != id)
+            .filter_map(|other| self.client(other).and_then(Client::entity))
+            .collect::<Vec<_>>();
+
+        let Some(client) = self.client_mut(id) else {
+            bail!("No such client {}", id);
+        };
+
+        // TODO: All players are currently privileged
+        client.state = ClientState::Active(ClientActive {
+            privileged: true,
+            entity_id: client_entity,
+        });
+
+        self.level
+            .globals
+            .store(GlobalAddrEntity::Self_, client_entity)?;
+        self.level
+            .globals
+            .store(GlobalAddrFloat::Time, duration_to_f32(self.level.time))?;
+
+        let client_connect = self
+            .level
+            .globals
+            .function_id(GlobalAddrFunction::ClientConnect as i16)?;
+        self.level
+            .execute_program(client_connect, registry.reborrow(), vfs)?;
+
+        self.level
+            .globals
+            .store(GlobalAddrEntity::Self_, client_entity)?;
+        self.level
+            .globals
+            .store(GlobalAddrFloat::Time, duration_to_f32(self.level.time))?;
+
+        // the progs normally choose where the player enters in `PutClientInServer`, so a spawn
+        // point is only chosen here for a player that they left where it was
+        let origin = |level: &LevelState| {
+            let ent = level.world.entities.get(client_entity)?;
+            ent.origin(&level.world.type_def).ok()
+        };
+        let origin_before = origin(&self.level);
+
+        let put_client_in_server = self
+            .level
+            .globals
+            .function_id(GlobalAddrFunction::PutClientInServer as i16)?;
+        self.level
+            .execute_program(put_client_in_server, registry.reborrow(), vfs)?;
+
+        if origin(&self.level) != origin_before {
+            return Ok(());
+        }
+
+        match self.level.select_spawn_point(spawn_vars, &other_players) {
+            Some(spot) => self
+                .level
+                .place_at_spawn_point(client_entity, spot, registry, vfs)?,
+            None => self.level.console_error(format!(
+                "No spawn point for {} on {}",
+                spawn_vars.mode(),
+                self.level.map_name()
+            )),
+        }
+
+        Ok(())
+    }
+
+    pub fn precache_sound(&mut self, name_id: StringId) {
+        if let SessionState::Loading = self.state {
+            self.level.precache_sound(name_id);
+        } else {
+            panic!("Sounds cannot be precached after loading");
+        }
+    }
+
+    pub fn precache_model(&mut self, name_id: StringId) {
+        if let SessionState::Loading = self.state {
+            self.level.precache_model(name_id);
+        } else {
+            panic!("Models cannot be precached after loading");
+        }
+    }
+
+    pub fn loading(&self) -> bool {
+        if let SessionState::Loading = self.state {
+            true
+        } else {
+            false
+        }
+    }
+
+    #[inline]
+    fn level(&self) -> &LevelState {
+        &self.level
+    }
+
+    #[inline]
+    fn level_mut(&mut self) -> &mut LevelState {
+        &mut self.level
+    }
+
+    #[inline]
+    pub fn sound_id(&self, name_id: StringId) -> Option<usize> {
+        self.level().sound_id(name_id)
+    }
+
+    #[inline]
+    pub fn model_id(&self, name_id: StringId) -> Option<usize> {
+        self.level().model_id(name_id)
+    }
+
+    #[inline]
+    pub fn set_lightstyle(&mut self, index: usize, val: StringId) {
+        self.level_mut().set_lightstyle(index, val);
+    }
+
+    /// Returns the amount of time the current level has been active.
+    #[inline]
+    pub fn time(&self) -> Option<Duration> {
+        match self.state {
+            SessionState::Loading => None,
+            SessionState::Active => Some(self.level.time),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Deserialize)]
+pub struct ServerVars {
+    #[serde(rename(deserialize = "sv_gravity"))]
+    gravity: f32,
+    #[serde(rename(deserialize = "sv_maxvelocity"))]
+    max_velocity: f32,
+}
+
+#[derive(Copy, Clone, Default, PartialEq, Deserialize)]
+pub struct MatchVars {
+    deathmatch: f32,
+    fraglimit: f32,
+    /// In minutes.
+    timelimit: f32,
+}
+
+#[derive(Copy, Clone, Default, PartialEq, Deserialize)]
+pub struct SpawnVars {
+    deathmatch: f32,
+    coop: f32,
+}
+
+impl SpawnVars {
+    /// Whether this is a deathmatch or coop game.
+    fn multiplayer(&self) -> bool {
+        self.deathmatch != 0. || self.coop != 0.
+    }
+
+    /// Classes of spawn point to use, in order of preference.
+    fn spawn_classes(&self) -> &'static [&'static str] {
+        // `coop` takes priority, as in the original game
+        if self.coop != 0. {
+            &["info_player_coop", "info_player_start"]
+        } else if self.deathmatch != 0. {
+            &["info_player_deathmatch", "info_player_start"]
+        } else {
+            &["info_player_start"]
+        }
+    }
+
+    fn mode(&self) -> &'static str {
+        if self.coop != 0. {
+            "coop"
+        } else if self.deathmatch != 0. {
+            "deathmatch"
+        } else {
+            "single player"
+        }
+    }
+}
+
+/// The bounds of a standing player relative to its origin.
+const PLAYER_MINS: Vector3<f32> = Vector3::new(-16., -16., -24.);
+const PLAYER_MAXS: Vector3<f32> = Vector3::new(16., 16., 32.);
+
+/// The spawn point after `last` in the rotation of `spots`, skipping any that are `blocked`. If
+/// every spawn point is blocked, the one after `last` is used regardless.
+fn next_spawn_point(
+    spots: &[EntityId],
+    last: Option<EntityId>,
+    blocked: impl Fn(EntityId) -> bool,
+) -> Option<EntityId> {
+    let start = last
+        .and_then(|last| spots.iter().position(|&id| id == last))
+        .map_or(0, |i| i + 1);
+    let mut rotation = spots.iter().cycle().skip(start).take(spots.len());
+    rotation
+        .clone()
+        .find(|&&spot| !blocked(spot))
+        .or_else(|| rotation.next())
+        .copied()
+}
+
+/// Returns `true` if a player standing on the spawn point would overlap any of `players`.
+fn spawn_point_blocked(world: &World, spot: EntityId, players: &[EntityId]) -> bool {
+    let type_def = &world.type_def;
+    let Some(spot_origin) = world
+        .entities
+        .get(spot)
+        .and_then(|ent| ent.origin(type_def).ok())
+    else {
+        return false;
+    };
+    let (mins, maxs) = (spot_origin + PLAYER_MINS, spot_origin + PLAYER_MAXS);
+
+    players.iter().any(|&player| {
+        let Some(ent) = world.entities.get(player) else {
+            return false;
+        };
+        let (Ok(origin), Ok(p_mins), Ok(p_maxs)) =
+            (ent.origin(type_def), ent.min(type_def), ent.max(type_def))
+        else {
+            return false;
+        };
+        let (p_mins, p_maxs) = (origin + p_mins, origin + p_maxs);
+
+        (0..3).all(|i| mins[i] < p_maxs[i] && p_mins[i] < maxs[i])
+    })
+}
+
+/// How long the scoreboard is shown at the end of a deathmatch before moving to the next level, in
+/// seconds.
+const INTERMISSION_TIME: i64 = 5;
+
+/// Progress through a deathmatch.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum MatchState {
+    Playing,
+
+    /// The `fraglimit` or `timelimit` was reached at the given level time.
+    Intermission(Duration),
+
+    /// A level change has been queued.
+    Finished,
+}
+
+/// Limits on client cvars which are enforced by the server while `sv_cheats` is disabled.
+///
+/// These are sent to clients during the prespawn stage.
+#[derive(Resource, Debug, Clone)]
+pub struct CvarLimits {
+    limits: HashMap<String, (f32, f32)>,
+}
+
+impl Default for CvarLimits {
+    fn default() -> Self {
+        Self {
+            limits: [("r_fullbright", (0., 0.)), ("r_lightmap", (0., 0.))]
+                .into_iter()
+                .map(|(name, limit)| (name.to_owned(), limit))
+                .collect(),
+        }
+    }
+}
+
+impl CvarLimits {
+    pub fn set(&mut self, name: String, min: f32, max: f32) {
+        self.limits.insert(name, (min, max));
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.limits.remove(name).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, f32, f32)> + '_ {
+        self.limits
+            .iter()
+            .map(|(name, &(min, max))| (&name[..], min, max))
+    }
+
+    /// Write the commands which apply these limits on the client.
+    pub fn serialize<W: Write>(&self, dest: &mut W) -> Result<(), NetError> {
+        for (name, min, max) in self.iter() {
+            ServerCmd::StuffText {
+                text: format!("cvar_limit {} {} {}", name, min, max).into(),
+            }
+            .serialize(dest)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Addresses which may not connect to the server.
+///
+/// The list is stored in the game directory, one address per line.
+#[derive(Resource, Default)]
+pub struct BanList {
+    addrs: BTreeSet<IpAddr>,
+}
+
+impl BanList {
+    const PATH: &'static str = "banlist.txt";
+
+    /// Reads the ban list from the game directory, if it exists.
+    pub fn load(vfs: &Vfs) -> Result<BanList, failure::Error> {
+        let mut text = String::new();
+        match vfs.open(Self::PATH) {
+            Ok(mut file) => {
+                file.read_to_string(&mut text)?;
+            }
+            Err(VfsError::NoSuchFile(_)) => return Ok(default()),
+            Err(e) => return Err(e.into()),
+        }
+
+        let mut addrs = BTreeSet::new();
+        for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            match line.parse() {
+                Ok(addr) => {
+                    addrs.insert(addr);
+                }
+                Err(e) => warn!(
+                    "Ignoring invalid address in {}: {} ({})",
+                    Self::PATH,
+                    line,
+                    e
+                ),
+            }
+        }
+
+        Ok(BanList { addrs })
+    }
+
+    /// Writes the ban list to the game directory.
+    pub fn save(&self, vfs: &Vfs) -> Result<(), failure::Error> {
+        let mut file = vfs.write(Self::PATH)?;
+        for addr in &self.addrs {
+            writeln!(file, "{}", addr)?;
+        }
+        file.flush()?;
+
+        Ok(())
+    }
+
+    /// Adds an address, returning `false` if it was already banned.
+    pub fn add(&mut self, addr: IpAddr) -> bool {
+        self.addrs.insert(addr)
+    }
+
+    /// Removes an address, returning `false` if it was not banned.
+    pub fn remove(&mut self, addr: IpAddr) -> bool {
+        self.addrs.remove(&addr)
+    }
+
+    pub fn is_banned(&self, addr: IpAddr) -> bool {
+        self.addrs.contains(&addr)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = IpAddr> + '_ {
+        self.addrs.iter().copied()
+    }
+}
+
+/// Entity bounds published by a listen server for the local client to visualize.
+///
+/// Only filled in while `r_showbboxes` or `r_showtriggers` is enabled, so remote clients never
+/// receive anything through this channel.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct DebugBounds {
+    pub boxes: Vec<DebugBox>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DebugBox {
+    pub mins: Vector3<f32>,
+    pub maxs: Vector3<f32>,
+    pub trigger: bool,
+}
+
+/// Number of entities handed to each task when physics is split across the compute task pool.
+const PHYSICS_BATCH_SIZE: usize = 64;
+
+/// The first move of a falling `MOVETYPE_STEP` entity, traced ahead of time by
+/// [`LevelState::trace_falls`].
+#[derive(Clone, Debug)]
+struct FallTrace {
+    /// Origin, velocity, mins and maxs the trace started from.
+    from: [Vector3<f32>; 4],
+    owner: EntityId,
+    /// Everything the trace could have hit.
+    move_min: Vector3<f32>,
+    move_max: Vector3<f32>,
+    trace: Trace,
+    hit_entity: Option<EntityId>,
+}
+
+impl FallTrace {
+    fn new(
+        world: &World,
+        string_table: &StringTable,
+        ent_id: EntityId,
+        vars: ServerVars,
+        frame_time: Duration,
+    ) -> Result<Option<FallTrace>, ProgsError> {
+        let ent = world.entities.try_get(ent_id)?;
+        let type_def = &world.type_def;
+        let falling = !ent
+            .flags(type_def)?
+            .intersects(EntityFlags::ON_GROUND | EntityFlags::FLY | EntityFlags::IN_WATER);
+        if ent.move_kind(type_def)? != MoveKind::Step || !falling {
+            return Ok(None);
+        }
+
+        // the same velocity `physics_step` gives the entity before moving it
+        let velocity = ent
+            .velocity_with_gravity(type_def, string_table, vars.gravity, frame_time)?
+            .map(|c| c.clamp(-vars.max_velocity, vars.max_velocity));
+        if velocity.is_zero() {
+            return Ok(None);
+        }
+
+        let origin = ent.origin(type_def)?;
+        let min = ent.min(type_def)?;
+        let max = ent.max(type_def)?;
+        let end = origin + duration_to_f32(frame_time) * velocity;
+        let (move_min, move_max) = phys::bounds_for_move(origin, min, max, end);
+        let (trace, hit_entity) =
+            world.trace_entity_move(ent_id, origin, min, max, end, CollideKind::Normal)?;
+
+        Ok(Some(FallTrace {
+            from: [origin, velocity, min, max],
+            owner: ent.owner(type_def)?,
+            move_min,
+            move_max,
+            trace,
+            hit_entity,
+        }))
+    }
+
+    /// Whether tracing the same move now would give the same result: the entity is where it was
+    /// and nothing has been linked or resized in its path since.
+    fn is_current(&self, world: &World, ent_id: EntityId) -> Result<bool, ProgsError> {
+        let ent = world.entities.try_get(ent_id)?;
+        let type_def = &world.type_def;
+        let state = [
+            ent.origin(type_def)?,
+            ent.velocity(type_def)?,
+            ent.min(type_def)?,
+            ent.max(type_def)?,
+        ];
+
+        Ok(state == self.from
+            && ent.owner(type_def)? == self.owner
+            && !world.relinked_within(ent_id, self.move_min, self.move_max))
+    }
+}
+
+/// Entity totals reported by the `edictcount` command.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EntityCounts {
+    pub active: usize,
+    /// Entities with a model set.
+    pub models: usize,
+    /// Entities which take part in collision.
+    pub solid: usize,
+    /// Entities using `MOVETYPE_STEP`, i.e. most monsters.
+    pub step: usize,
+}
+
+/// Server-side level state.
+#[derive(Debug)]
+pub struct LevelState {
+    /// Path to the BSP file this level was loaded from.
+    map_path: String,
+
+    string_table: StringTable,
+    sound_precache: Precache,
+    model_precache: Precache,
+    lightstyles: [StringId; MAX_LIGHTSTYLES],
+
+    /// Amount of time the current level has been active.
+    time: Duration,
+
+    /// QuakeC bytecode execution context.
+    ///
+    /// This includes the program counter, call stack, and local variables.
+    cx: ExecutionContext,
+
+    /// Global values for QuakeC bytecode.
+    globals: Globals,
+
+    /// The state of the game world.
+    ///
+    /// This contains the entities and world geometry.
+    world: World,
+
+    new_entities: HashSet<EntityId>,
+
+    /// Messages sent to every client once per frame.
+    broadcast: Vec<u8>,
+
+    /// Diagnostics for the server operator, sent to privileged clients with the next update.
+    console: String,
+
+    /// Messages that have already been sent to privileged clients this level, so that errors
+    /// raised every frame don't flood their consoles.
+    console_seen: HashSet<String>,
+
+    /// Console commands queued by QuakeC with `localcmd`, to be run on the server's console.
+    local_cmds: String,
+
+    match_state: MatchState,
+
+    /// Scratch list of entity IDs for `physics`, kept to avoid allocating every frame.
+    physics_ids: Vec<EntityId>,
+    /// Traces made ahead of time for the entities in `physics_ids`, kept for the same reason.
+    fall_traces: Vec<Option<FallTrace>>,
+
+    /// Recent positions of players and monsters, used to rewind player shots when `sv_lagcomp`
+    /// is enabled.
+    lag_comp: LagCompensation,
+
+    /// The spawn point most recently used in deathmatch or coop, which the next spawn rotates on
+    /// from.
+    last_spawn: Option<EntityId>,
+
+    /// Messages sent to each client once during the prespawn stage.
+    ///
+    /// This holds static entities and ambient sounds, which are only created while the level is
+    /// loading and so must be replayed to clients that connect later.
+    signon: Vec<u8>,
+
+    /// How every message in this level is encoded, chosen by `sv_protocol` when it loads.
+    protocol: Protocol,
+}
+
+impl LevelState {
+    pub fn new(
+        map_path: String,
+        progs: LoadProgs,
+        models: Vec<Model>,
+        entmap: String,
+        mut registry: Mut<Registry>,
+        vfs: &Vfs,
+    ) -> LevelState {
+        let LoadProgs {
+            cx,
+            globals,
+            entity_def,
+            mut string_table,
+        } = progs;
+
+        let sound_precache = Precache::new();
+        let mut model_precache = Precache::new();
+
+        model_precache.precache(&map_path);
+
+        for model in models.iter() {
+            let model_name = string_table.find_or_insert(model.name());
+            let name = string_table.get(model_name).unwrap();
+            // the world model is already precached as the map
+            if bsp::submodel_index(name.to_str()) != Some(0) {
+                model_precache.precache(name.to_str());
+            }
+        }
+
+        let world = World::new(models, entity_def, &mut string_table).unwrap();
+        let entity_list = parse::entities(&entmap).unwrap();
+
+        let protocol = match registry.read_cvar::<i32>("sv_protocol") {
+            Ok(PROTOCOL_RMQ) => Ok(Protocol::RMQ),
+            Ok(version) => Protocol::new(version, ProtocolFlags::empty()),
+            Err(_) => Ok(Protocol::NETQUAKE),
+        };
+
+        let mut level = LevelState {
+            map_path,
+            string_table,
+            sound_precache,
+            model_precache,
+            lightstyles: [StringId(0); MAX_LIGHTSTYLES],
+            time: Duration::zero(),
+            new_entities: default(),
+            cx,
+            globals,
+            world,
+
+            broadcast: default(),
+            console: default(),
+            console_seen: default(),
+            local_cmds: default(),
+            match_state: MatchState::Playing,
+            physics_ids: default(),
+            fall_traces: default(),
+            lag_comp: default(),
+            last_spawn: None,
+            signon: default(),
+            protocol: *protocol.as_ref().unwrap_or(&Protocol::NETQUAKE),
+        };
+
+        if let Err(e) = protocol {
+            level.console_error(format!("Bad sv_protocol, using NetQuake: {}", e));
+        }
+
+        // QuakeC reads the game mode from globals, e.g. to remove items in deathmatch
+        let spawn_vars = registry.read_cvars::<SpawnVars>().unwrap_or_default();
+        for (addr, value) in [
+            (GlobalAddrFloat::Deathmatch, spawn_vars.deathmatch),
+            (GlobalAddrFloat::Coop, spawn_vars.coop),
+        ] {
+            if let Err(e) = level.globals.store(addr, value) {
+                level.console_error(format!("Failed to set game mode: {}", e));
+            }
+        }
+
+        for entity in entity_list {
+            if let Err(e) = level.spawn_entity_from_map(entity, registry.reborrow(), vfs) {
+                level.console_error(format!("Failed spawning entity {}", e));
+            }
+        }
+
+        level
+    }
+
+    /// Log an error and mirror it to the consoles of privileged clients.
+    pub fn console_error(&mut self, msg: impl fmt::Display) {
+        error!("{}", msg);
+        self.mirror_to_console(msg);
+    }
+
+    /// Log a warning and mirror it to the consoles of privileged clients.
+    pub fn console_warn(&mut self, msg: impl fmt::Display) {
+        warn!("{}", msg);
+        self.mirror_to_console(msg);
+    }
+
+    fn mirror_to_console(&mut self, msg: impl fmt::Display) {
+        let msg = msg.to_string();
+        if !self.console_seen.contains(&msg) {
+            self.console.push_str(&msg);
+            self.console.push('\n');
+            self.console_seen.insert(msg);
+        }
+    }
+
+    /// Returns the name of the map, e.g. `e1m1`.
+    pub fn map_name(&self) -> &str {
+        let path = &self.map_path;
+        let file = path.rsplit('/').next().unwrap_or(path);
+        file.strip_suffix(".bsp").unwrap_or(file)
+    }
+
+    /// Returns the map that this level's exit leads to, or this map if it has no exit.
+    fn next_map(&self) -> String {
+        let type_def = &self.world.type_def;
+        let map_field = type_def.find(&self.string_table, "map");
+
+        for id in self.world.entities.iter() {
+            let Ok(ent) = self.world.entities.try_get(id) else {
+                continue;
+            };
+            let is_exit = ent
+                .load(type_def, FieldAddrStringId::ClassName)
+                .ok()
+                .and_then(|s| self.string_table.get(s))
+                .is_some_and(|s| s.to_str() == "trigger_changelevel");
+            if !is_exit {
+                continue;
+            }
+
+            let next = map_field
+                .and_then(|f| ent.string_id(type_def, f.offset as i16).ok())
+                .and_then(|s| self.string_table.get(s))
+                .map(|s| s.to_str().into_owned());
+            if let Some(next) = next.filter(|s| !s.is_empty()) {
+                return next;
+            }
+        }
+
+        self.map_name().to_owned()
+    }
+
+    /// Queue a change to another map. Only the first change requested during a level is made.
+    fn change_level(&mut self, map: &str) {
+        if self.match_state != MatchState::Finished {
+            self.match_state = MatchState::Finished;
+            self.local_cmds.push_str(&format!("changelevel {}\n", map));
+        }
+    }
+
+    /// Returns the definition of the entity field with the given name.
+    fn field_def(&self, name: &str) -> Option<&FieldDef> {
+        self.world.type_def.field_defs().iter().find(|def| {
+            self.string_table
+                .get(def.name_id)
+                .is_some_and(|s| s.to_str() == name)
+        })
+    }
+
+    pub fn entity_float(&self, entity_id: EntityId, field: &str) -> Option<f32> {
+        let def = self.field_def(field)?;
+        let ent = self.world.entities.get(entity_id)?;
+
+        ent.get_float(&self.world.type_def, def.offset as i16).ok()
+    }
+
+    pub fn entity_vector(&self, entity_id: EntityId, field: &str) -> Option<[f32; 3]> {
+        let def = self.field_def(field)?;
+        let ent = self.world.entities.get(entity_id)?;
+
+        ent.get_vector(&self.world.type_def, def.offset as i16).ok()
+    }
+
+    /// Formats the non-zero fields of an entity as `name value` lines, or returns `None` if there
+    /// is no entity with the given ID.
+    pub fn describe_entity(&self, entity_id: EntityId) -> Option<String> {
+        let type_def = &self.world.type_def;
+        let ent = self.world.entities.get(entity_id)?;
+
+        let mut out = format!("EDICT {}:\n", entity_id.0);
+        for def in type_def.field_defs() {
+            let name = self
+                .string_table
+                .get(def.name_id)
+                .map(|s| s.to_str().into_owned())
+                .unwrap_or_default();
+            // the components of vectors are also listed as separate float fields
+            if name.is_empty()
+                || name.ends_with("_x")
+                || name.ends_with("_y")
+                || name.ends_with("_z")
+            {
+                continue;
+            }
+
+            let addr = def.offset as i16;
+            let value = match def.type_ {
+                Type::QVoid => continue,
+                Type::QString => match ent.string_id(type_def, addr) {
+                    Ok(StringId(0)) | Err(_) => continue,
+                    Ok(id) => match self.string_table.get(id) {
+                        Some(s) => format!("\"{}\"", s.to_str()),
+                        None => format!("<bad string {}>", id.0),
+                    },
+                },
+                Type::QFloat => match ent.get_float(type_def, addr) {
+                    Ok(f) if f != 0.0 => format!("{}", f),
+                    _ => continue,
+                },
+                Type::QVector => match ent.get_vector(type_def, addr) {
+                    Ok(v) if v != [0.0; 3] => format!("'{} {} {}'", v[0], v[1], v[2]),
+                    _ => continue,
+                },
+                Type::QEntity => match ent.entity_id(type_def, addr) {
+                    Ok(EntityId(0)) | Err(_) => continue,
+                    Ok(id) => format!("entity {}", id.0),
+                },
+                Type::QFunction => match ent.function_id(type_def, addr) {
+                    Ok(FunctionId(0)) | Err(_) => continue,
+                    Ok(id) => match self.cx.function_def(id) {
+                        Ok(def) => format!(
+                            "{}()",
+                            self.string_table
+                                .get(def.name_id)
+                                .map(|s| s.to_str().into_owned())
+                                .unwrap_or_default()
+                        ),
+                        Err(_) => format!("<bad function {}>", id.0),
+                    },
+                },
+                Type::QField | Type::QPointer => match ent.get_int(addr) {
+                    Ok(i) if i != 0 => format!("{}", i),
+                    _ => continue,
+                },
+            };
+
+            out.push_str(&format!("{:<16} {}\n", name, value));
+        }
+
+        Some(out)
+    }
+
+    /// Counts the entities in the level, split up the way `edictcount` reports them.
+    pub fn entity_counts(&self) -> EntityCounts {
+        let type_def = &self.world.type_def;
+
+        let mut counts = EntityCounts::default();
+        for id in self.world.entities.iter() {
+            let Some(ent) = self.world.entities.get(id) else {
+                continue;
+            };
+
+            counts.active += 1;
+            if ent.model_index(type_def).unwrap_or(0) != 0 {
+                counts.models += 1;
+            }
+            if !matches!(ent.solid(type_def), Ok(EntitySolid::Not) | Err(_)) {
+                counts.solid += 1;
+            }
+            if matches!(ent.move_kind(type_def), Ok(MoveKind::Step)) {
+                counts.step += 1;
+            }
+        }
+
+        counts
+    }
+
+    /// Record this frame's positions for lag compensation, and the latency of each player.
+    pub fn update_lag_compensation(&mut self, clients: &ClientSlots, vars: LagCompVars) {
+        self.lag_comp.set_enabled(vars.enabled != 0.);
+        if !self.lag_comp.enabled() {
+            return;
+        }
+
+        for slot in clients.active_clients() {
+            if let Some(client) = clients.get(slot) {
+                if let Some(ent_id) = client.entity() {
+                    self.lag_comp.set_latency(ent_id, client.ping());
+                }
+            }
+        }
+
+        let type_def = &self.world.type_def;
+        let targets = self.world.entities.iter().filter_map(|id| {
+            let ent = self.world.entities.get(id)?;
+            let flags = ent.flags(type_def).ok()?;
+            if !flags.intersects(EntityFlags::CLIENT | EntityFlags::MONSTER)
+                || matches!(ent.solid(type_def), Ok(EntitySolid::Not) | Err(_))
+            {
+                return None;
+            }
+
+            Some((id, ent.origin(type_def).ok()?))
+        });
+        self.lag_comp.record(self.time, targets);
+    }
+
+    /// Move every other player and monster back to where it was when `shooter` saw it. Returns
+    /// their current positions, to be put back with [`Self::move_targets`].
+    fn rewind_targets(
+        &mut self,
+        shooter: EntityId,
+    ) -> Result<Vec<(EntityId, Vector3<f32>)>, ProgsError> {
+        let Some(rewound) = self.lag_comp.rewind(shooter, self.time) else {
+            return Ok(Vec::new());
+        };
+
+        let mut moved = Vec::new();
+        let mut restore = Vec::new();
+        for (id, origin) in rewound {
+            // the entity may have been removed since
+            if let Some(ent) = self.world.entities.get(id) {
+                restore.push((id, ent.origin(&self.world.type_def)?));
+                moved.push((id, origin));
+            }
+        }
+        self.move_targets(moved)?;
+
+        Ok(restore)
     }
 
-    #[inline]
-    pub fn model_id(&self, name_id: StringId) -> Option<usize> {
-        self.level().model_id(name_id)
-    }
+    /// Relink entities at the given positions without touching triggers.
+    fn move_targets(&mut self, origins: Vec<(EntityId, Vector3<f32>)>) -> Result<(), ProgsError> {
+        for (id, origin) in origins {
+            self.world.entities.get_mut(id)?.store(
+                &self.world.type_def,
+                FieldAddrVector::Origin,
+                origin.into(),
+            )?;
+            self.world.link_entity(id)?;
+        }
 
-    #[inline]
-    pub fn set_lightstyle(&mut self, index: usize, val: StringId) {
-        self.level_mut().set_lightstyle(index, val);
+        Ok(())
     }
 
-    /// Returns the amount of time the current level has been active.
-    #[inline]
-    pub fn time(&self) -> Option<Duration> {
-        match self.state {
-            SessionState::Loading => None,
-            SessionState::Active => Some(self.level.time),
-        }
+    /// Returns the frag count of a client's entity, if it has entered the game.
+    pub fn client_frags(&self, client: &Client) -> Option<i32> {
+        let entity = self.world.entities.get(client.entity()?)?;
+        let frags = entity
+            .get_float(&self.world.type_def, FieldAddrFloat::Frags as i16)
+            .ok()?;
+
+        Some(frags as i32)
     }
-}
 
-#[derive(Copy, Clone, PartialEq, Deserialize)]
-pub struct ServerVars {
-    #[serde(rename(deserialize = "sv_gravity"))]
-    gravity: f32,
-    #[serde(rename(deserialize = "sv_maxvelocity"))]
-    max_velocity: f32,
-}
+    /// The state of a player's own entity that only they are sent, for their view and status
+    /// bar, as in `SV_WriteClientdataToMessage`. Like the original, fields at their defaults are
+    /// left out.
+    pub fn player_data(&self, id: EntityId) -> Result<PlayerData, ProgsError> {
+        let type_def = &self.world.type_def;
+        let entity = self.world.entities.try_get(id)?;
+        let float = |addr: FieldAddrFloat| entity.get_float(type_def, addr as i16);
+        let nonzero = |v: f32| Some(v).filter(|v| *v != 0.);
+
+        let view_height = entity.get_vector(type_def, FieldAddrVector::ViewOffset as i16)?[2];
+        let punch = entity.get_vector(type_def, FieldAddrVector::PunchAngle as i16)?;
+        let velocity = entity.get_vector(type_def, FieldAddrVector::Velocity as i16)?;
+
+        // the sigils collected in the episode are kept in `serverflags`
+        let server_flags = self.globals.load(GlobalAddrFloat::ServerFlags)? as u32;
+        let items = ItemFlags::from_bits_truncate(
+            float(FieldAddrFloat::Items)? as u32 | server_flags << 28,
+        );
 
-/// Server-side level state.
-#[derive(Debug)]
-pub struct LevelState {
-    string_table: StringTable,
-    sound_precache: Precache,
-    model_precache: Precache,
-    lightstyles: [StringId; MAX_LIGHTSTYLES],
+        let weapon_model = entity.string_id(type_def, FieldAddrStringId::WeaponModelName as i16)?;
 
-    /// Amount of time the current level has been active.
-    time: Duration,
+        // NetQuake has a byte for each of these, larger values need an extended protocol
+        let limit = if self.protocol.is_extended() {
+            u16::MAX
+        } else {
+            u8::MAX as u16
+        };
+        let stat = |v: f32| (v as u16).min(limit);
+
+        Ok(PlayerData {
+            view_height: Some(view_height).filter(|h| *h != DEFAULT_VIEWHEIGHT),
+            ideal_pitch: nonzero(float(FieldAddrFloat::IdealPitch)?).map(Deg),
+            punch_pitch: nonzero(punch[0]).map(Deg),
+            velocity_x: nonzero(velocity[0]),
+            punch_yaw: nonzero(punch[1]).map(Deg),
+            velocity_y: nonzero(velocity[1]),
+            punch_roll: nonzero(punch[2]).map(Deg),
+            velocity_z: nonzero(velocity[2]),
+            items,
+            on_ground: entity.flags(type_def)?.contains(EntityFlags::ON_GROUND),
+            in_water: float(FieldAddrFloat::WaterLevel)? >= 2.,
+            weapon_frame: nonzero(float(FieldAddrFloat::WeaponFrame)?).map(stat),
+            armor: nonzero(float(FieldAddrFloat::ArmorValue)?).map(stat),
+            // always sent, even when there is no view model
+            weapon: Some(stat(self.model_id(weapon_model).unwrap_or(0) as f32)),
+            health: float(FieldAddrFloat::Health)? as i16,
+            ammo: stat(float(FieldAddrFloat::CurrentAmmo)?),
+            ammo_shells: stat(float(FieldAddrFloat::AmmoShells)?),
+            ammo_nails: stat(float(FieldAddrFloat::AmmoNails)?),
+            ammo_rockets: stat(float(FieldAddrFloat::AmmoRockets)?),
+            ammo_cells: stat(float(FieldAddrFloat::AmmoCells)?),
+            // like `standard_quake`, send the weapon's item flag rather than its number
+            active_weapon: float(FieldAddrFloat::Weapon)? as u32 as u8,
+        })
+    }
 
-    /// QuakeC bytecode execution context.
+    /// Choose where a player should enter the level.
     ///
-    /// This includes the program counter, call stack, and local variables.
-    cx: ExecutionContext,
+    /// In single player this is always the first `info_player_start`. In deathmatch and coop the
+    /// spawn points are used in rotation, skipping any which are blocked by one of
+    /// `other_players` so that nobody is telefragged on entering. If every spawn point is
+    /// blocked, the next one in the rotation is used regardless.
+    pub fn select_spawn_point(
+        &mut self,
+        vars: SpawnVars,
+        other_players: &[EntityId],
+    ) -> Option<EntityId> {
+        let type_def = &self.world.type_def;
 
-    /// Global values for QuakeC bytecode.
-    globals: Globals,
+        let spots = vars.spawn_classes().iter().find_map(|&class| {
+            let spots = self
+                .world
+                .entities
+                .iter()
+                .filter(|&id| {
+                    self.world
+                        .entities
+                        .get(id)
+                        .and_then(|ent| ent.load(type_def, FieldAddrStringId::ClassName).ok())
+                        .and_then(|s| self.string_table.get(s))
+                        .is_some_and(|s| s.to_str() == class)
+                })
+                .collect::<Vec<_>>();
+
+            (!spots.is_empty()).then_some(spots)
+        })?;
 
-    /// The state of the game world.
-    ///
-    /// This contains the entities and world geometry.
-    world: World,
+        if vars.coop == 0. && vars.deathmatch == 0. {
+            return spots.first().copied();
+        }
 
-    new_entities: HashSet<EntityId>,
+        let spot = next_spawn_point(&spots, self.last_spawn, |spot| {
+            spawn_point_blocked(&self.world, spot, other_players)
+        })?;
 
-    broadcast: Vec<u8>,
-}
+        self.last_spawn = Some(spot);
+        Some(spot)
+    }
 
-impl LevelState {
-    pub fn new(
-        map_path: String,
-        progs: LoadProgs,
-        models: Vec<Model>,
-        entmap: String,
-        mut registry: Mut<Registry>,
+    /// Move a player's entity onto a spawn point, facing the same way.
+    pub fn place_at_spawn_point(
+        &mut self,
+        player: EntityId,
+        spot: EntityId,
+        registry: Mut<Registry>,
         vfs: &Vfs,
-    ) -> LevelState {
-        let LoadProgs {
-            cx,
-            globals,
-            entity_def,
-            mut string_table,
-        } = progs;
-
-        let sound_precache = Precache::new();
-        let mut model_precache = Precache::new();
+    ) -> Result<(), ProgsError> {
+        let type_def = &self.world.type_def;
+        let spot_ent = self.world.entities.try_get(spot)?;
+        let origin = spot_ent.origin(type_def)?;
+        let angles = spot_ent.load(type_def, FieldAddrVector::Angles)?;
+
+        let ent = self.world.entities.get_mut(player)?;
+        ent.store(type_def, FieldAddrVector::Angles, angles)?;
+        ent.store(type_def, FieldAddrVector::Velocity, [0.; 3])?;
+        // make the client look the same way as the spawn point
+        ent.store(type_def, FieldAddrFloat::FixAngle, 1.)?;
+
+        self.set_entity_origin(player, origin, registry, vfs)
+    }
 
-        model_precache.precache(&map_path);
+    /// Broadcast changed frag counts, and end the match once `fraglimit` or `timelimit` is
+    /// reached in deathmatch.
+    ///
+    /// Once the match has ended, every client is shown the intermission screen and the next
+    /// level is loaded after a few seconds.
+    pub fn update_scores(
+        &mut self,
+        clients: &mut ClientSlots,
+        vars: MatchVars,
+    ) -> Result<(), NetError> {
+        let mut frag_limit_hit = false;
+        for slot in clients.active_clients().collect::<Vec<_>>() {
+            let Some(client) = clients.get_mut(slot) else {
+                continue;
+            };
+            let Some(frags) = self.client_frags(client) else {
+                continue;
+            };
 
-        for model in models.iter() {
-            let model_name = string_table.find_or_insert(model.name());
-            let name = string_table.get(model_name).unwrap();
-            // "*0" is the null model
-            if &*name.raw != b"*0" {
-                model_precache.precache(name.to_str());
+            if frags != client.old_frags {
+                client.old_frags = frags;
+                ServerCmd::UpdateFrags {
+                    player_id: slot.0 as _,
+                    new_frags: frags as _,
+                }
+                .serialize(&mut self.broadcast)?;
             }
-        }
-
-        let world = World::new(models, entity_def, &mut string_table).unwrap();
-        let entity_list = parse::entities(&entmap).unwrap();
 
-        let mut level = LevelState {
-            string_table,
-            sound_precache,
-            model_precache,
-            lightstyles: [StringId(0); MAX_LIGHTSTYLES],
-            time: Duration::zero(),
-            new_entities: default(),
-            cx,
-            globals,
-            world,
+            frag_limit_hit |= vars.fraglimit > 0. && frags as f32 >= vars.fraglimit;
+        }
 
-            broadcast: default(),
-        };
+        match self.match_state {
+            MatchState::Playing if vars.deathmatch != 0. => {
+                let time_limit_hit =
+                    vars.timelimit > 0. && self.time >= duration_from_f32(vars.timelimit * 60.);
+                if frag_limit_hit || time_limit_hit {
+                    self.match_state = MatchState::Intermission(self.time);
+                    ServerCmd::Intermission.serialize(&mut self.broadcast)?;
+                }
+            }
 
-        for entity in entity_list {
-            if let Err(e) = level.spawn_entity_from_map(entity, registry.reborrow(), vfs) {
-                error!("Failed spawning entity {}", e);
+            MatchState::Intermission(start)
+                if self.time - start >= Duration::try_seconds(INTERMISSION_TIME).unwrap() =>
+            {
+                let next_map = self.next_map();
+                self.change_level(&next_map);
             }
+
+            _ => {}
         }
 
-        level
+        Ok(())
     }
 
     #[inline]
@@ -574,7 +2146,10 @@ impl LevelState {
 
     #[inline]
     pub fn entity_state(&self, id: EntityId) -> Option<EntityState> {
-        self.world.entities.get(id)?.state(&self.world.type_def)
+        self.world
+            .entities
+            .get(id)?
+            .state(&self.world.type_def, &self.string_table)
     }
 
     #[inline]
@@ -685,10 +2260,11 @@ impl LevelState {
                     if def.argc != called_with_args {
                         self.cx.print_backtrace(&self.string_table);
                         let func_name = self.string_table.get(name_id).unwrap();
-                        warn!(
+                        let msg = format!(
                             "Arg count mismatch calling {}: expected {}, found {}",
                             func_name, def.argc, called_with_args,
                         );
+                        self.console_warn(msg);
                     }
 
                     if let FunctionKind::BuiltIn(b) = def.kind {
@@ -714,7 +2290,7 @@ impl LevelState {
                             PrecacheSound => self.builtin_precache_sound()?,
                             PrecacheModel => self.builtin_precache_model(vfs)?,
                             StuffCmd => todo_builtin!(StuffCmd),
-                            FindRadius => todo_builtin!(FindRadius),
+                            FindRadius => self.builtin_find_radius()?,
                             BPrint => self.builtin_bprint()?,
                             SPrint => self.builtin_sprint()?,
                             DPrint => self.builtin_dprint()?,
@@ -736,9 +2312,9 @@ impl LevelState {
                             FAbs => self.globals.builtin_f_abs()?,
                             Aim => todo_builtin!(Aim),
                             Cvar => self.builtin_cvar(&*registry)?,
-                            LocalCmd => todo_builtin!(LocalCmd),
-                            NextEnt => todo_builtin!(NextEnt),
-                            Particle => todo_builtin!(Particle),
+                            LocalCmd => self.builtin_local_cmd()?,
+                            NextEnt => self.builtin_next_ent()?,
+                            Particle => self.builtin_particle()?,
                             ChangeYaw => todo_builtin!(ChangeYaw),
                             VecToAngles => todo_builtin!(VecToAngles),
                             WriteByte => self.builtin_write_byte()?,
@@ -753,7 +2329,7 @@ impl LevelState {
                             // Only used in `qcc`, does nothing at runtime
                             PrecacheFile => {}
                             MakeStatic => self.builtin_make_static()?,
-                            ChangeLevel => todo_builtin!(ChangeLevel),
+                            ChangeLevel => self.builtin_change_level()?,
                             CvarSet => self.builtin_cvar_set(registry.reborrow())?,
                             CenterPrint => self.builtin_center_print()?,
                             AmbientSound => self.builtin_ambient_sound()?,
@@ -1028,9 +2604,93 @@ impl LevelState {
     ) -> Result<(), ProgsError> {
         self.start_frame(registry.reborrow(), vfs)?;
 
-        let server_vars = registry.read_cvars::<ServerVars>()?;
+        // reuse last frame's allocations; the lists are taken out so that entities can be spawned
+        // and removed while they are walked
+        let mut ent_ids = mem::take(&mut self.physics_ids);
+        ent_ids.clear();
+        ent_ids.extend(self.world.entities.iter());
+        let mut fall_traces = mem::take(&mut self.fall_traces);
+        self.trace_falls(
+            &ent_ids,
+            &mut fall_traces,
+            clients.limit(),
+            frame_time,
+            &registry,
+        )?;
+
+        self.world.track_relinks();
+        let result = self.physics_entities(
+            clients,
+            &ent_ids,
+            &mut fall_traces,
+            frame_time,
+            registry,
+            vfs,
+        );
+        self.world.stop_tracking_relinks();
+
+        self.physics_ids = ent_ids;
+        self.fall_traces = fall_traces;
+        result?;
+        self.time += frame_time;
+
+        Ok(())
+    }
+
+    /// Trace the first move of every falling `MOVETYPE_STEP` entity across the compute task pool.
+    ///
+    /// Tracing only reads the world, so these run side by side, which QuakeC and moving entities
+    /// can't. Entities still move one at a time and in order in [`Self::physics_entities`], and
+    /// use these traces only if nothing they could hit has moved since, so the frame plays out
+    /// the same whichever thread traced what.
+    fn trace_falls(
+        &self,
+        ent_ids: &[EntityId],
+        fall_traces: &mut Vec<Option<FallTrace>>,
+        max_clients: usize,
+        frame_time: Duration,
+        registry: &Registry,
+    ) -> Result<(), ProgsError> {
+        let vars: ServerVars = registry.read_cvars()?;
+        let world = &self.world;
+        let string_table = &self.string_table;
+
+        fall_traces.clear();
+        fall_traces.resize(ent_ids.len(), None);
+        ComputeTaskPool::get_or_init(TaskPool::default).scope(|scope| {
+            for (ids, traces) in ent_ids
+                .chunks(PHYSICS_BATCH_SIZE)
+                .zip(fall_traces.chunks_mut(PHYSICS_BATCH_SIZE))
+            {
+                scope.spawn(async move {
+                    for (&ent_id, fall_trace) in ids.iter().zip(traces) {
+                        // players are moved by their own commands
+                        if ent_id.0 == 0 || ent_id.0 >= max_clients {
+                            *fall_trace =
+                                FallTrace::new(world, string_table, ent_id, vars, frame_time)
+                                    .ok()
+                                    .flatten();
+                        }
+                    }
+                });
+            }
+        });
+
+        Ok(())
+    }
 
-        for ent_id in self.world.entities.list() {
+    /// Run physics for each entity in turn, committing the traces from [`Self::trace_falls`]
+    /// which are still current.
+    fn physics_entities(
+        &mut self,
+        clients: &ClientSlots,
+        ent_ids: &[EntityId],
+        fall_traces: &mut [Option<FallTrace>],
+        frame_time: Duration,
+        mut registry: Mut<Registry>,
+        vfs: &Vfs,
+    ) -> Result<(), ProgsError> {
+        for (&ent_id, fall_trace) in ent_ids.iter().zip(fall_traces) {
             if self.globals.load(GlobalAddrFloat::ForceRetouch)? != 0.0 {
                 // Force all entities to touch triggers, even if they didn't
                 // move. This is required when e.g. creating new triggers, as
@@ -1048,7 +2708,7 @@ impl LevelState {
 
             let max_clients = clients.limit();
             if ent_id.0 != 0 && ent_id.0 < max_clients {
-                self.physics_player(clients, ent_id, &server_vars)?;
+                self.physics_player(clients, ent_id, frame_time, registry.reborrow(), vfs)?;
             } else {
                 match self
                     .world
@@ -1066,9 +2726,13 @@ impl LevelState {
                     MoveKind::NoClip => {
                         self.physics_noclip(ent_id, frame_time, registry.reborrow(), vfs)?
                     }
-                    MoveKind::Step => {
-                        self.physics_step(ent_id, frame_time, vfs, registry.reborrow())?
-                    }
+                    MoveKind::Step => self.physics_step(
+                        ent_id,
+                        frame_time,
+                        fall_trace.take(),
+                        vfs,
+                        registry.reborrow(),
+                    )?,
                     // No actual physics for this entity, but still let it think.
                     MoveKind::None => self.think(ent_id, frame_time, registry.reborrow(), vfs)?,
 
@@ -1078,7 +2742,7 @@ impl LevelState {
                     | MoveKind::Toss
                     | MoveKind::FlyMissile
                     | MoveKind::Bounce => {
-                        warn!("TODO: Airborne physics");
+                        self.console_warn("TODO: Airborne physics");
                         self.think(ent_id, frame_time, registry.reborrow(), vfs)?;
                     }
                 }
@@ -1090,8 +2754,6 @@ impl LevelState {
             }
         }
 
-        self.time += frame_time;
-
         Ok(())
     }
 
@@ -1099,20 +2761,89 @@ impl LevelState {
         &mut self,
         clients: &ClientSlots,
         ent_id: EntityId,
-        server_vars: &ServerVars,
+        frame_time: Duration,
+        registry: Mut<Registry>,
+        vfs: &Vfs,
     ) -> Result<(), ProgsError> {
-        let client_id = ent_id.0.checked_sub(1).ok_or_else(|| {
+        // players take the entities after the world, in the order of their slots
+        let client_id = ClientId(ent_id.0.checked_sub(1).ok_or_else(|| {
             ProgsError::with_msg(format!("Invalid client entity ID: {:?}", ent_id))
-        })?;
+        })?);
 
         if clients.get(client_id).is_none() {
             // No client in this slot.
             return Ok(());
         }
 
+        let server_vars = registry.read_cvars::<ServerVars>()?;
+        let move_vars = registry.read_cvars::<MoveVars>()?;
+
         let ent = self.world.entities.get_mut(ent_id)?;
         ent.limit_velocity(&self.world.type_def, server_vars.max_velocity)?;
-        debug!("TODO: Player physics not fully implemented");
+
+        let move_kind = ent.move_kind(&self.world.type_def)?;
+        if move_kind != MoveKind::Walk {
+            debug!("TODO: Player physics for {:?}", move_kind);
+            return Ok(());
+        }
+
+        let [pitch, yaw, roll] = ent.load(&self.world.type_def, FieldAddrVector::ViewAngle)?;
+        let [forward, side, up] = ent.load(&self.world.type_def, FieldAddrVector::MoveDirection)?;
+        let cmd = MoveCmd {
+            angles: Angles {
+                pitch: Deg(pitch),
+                roll: Deg(roll),
+                yaw: Deg(yaw),
+            },
+            forward,
+            side,
+            up,
+        };
+
+        let mut state = PlayerState {
+            origin: ent.origin(&self.world.type_def)?,
+            velocity: ent.velocity(&self.world.type_def)?,
+            on_ground: ent
+                .flags(&self.world.type_def)?
+                .contains(EntityFlags::ON_GROUND),
+            water_level: ent.load(&self.world.type_def, FieldAddrFloat::WaterLevel)? as u8,
+        };
+
+        pmove::player_move(
+            &mut state,
+            &cmd,
+            &move_vars,
+            duration_to_f32(frame_time),
+            &mut PlayerMoveWorld {
+                world: &mut self.world,
+                e_id: ent_id,
+            },
+        )?;
+
+        let ent = self.world.entities.get_mut(ent_id)?;
+        ent.store(
+            &self.world.type_def,
+            FieldAddrVector::Origin,
+            state.origin.into(),
+        )?;
+        ent.store(
+            &self.world.type_def,
+            FieldAddrVector::Velocity,
+            state.velocity.into(),
+        )?;
+        ent.store(
+            &self.world.type_def,
+            FieldAddrFloat::WaterLevel,
+            state.water_level as f32,
+        )?;
+        if state.on_ground {
+            ent.add_flags(&self.world.type_def, EntityFlags::ON_GROUND)?;
+        } else {
+            ent.remove_flags(&self.world.type_def, EntityFlags::ON_GROUND)?;
+        }
+
+        self.link_entity(ent_id, true, registry, vfs)?;
+
         Ok(())
     }
 
@@ -1236,10 +2967,11 @@ impl LevelState {
         Ok(())
     }
 
-    pub fn physics_step(
+    fn physics_step(
         &mut self,
         ent_id: EntityId,
         frame_time: Duration,
+        fall_trace: Option<FallTrace>,
         vfs: &Vfs,
         mut registry: Mut<Registry>,
     ) -> Result<(), ProgsError> {
@@ -1279,7 +3011,7 @@ impl LevelState {
                 .limit_velocity(&self.world.type_def, max_velocity)?;
 
             // Move the entity and relink it.
-            self.move_ballistic(frame_time, ent_id, registry.reborrow(), vfs)?;
+            self.move_ballistic(frame_time, ent_id, fall_trace, registry.reborrow(), vfs)?;
             self.link_entity(ent_id, true, registry.reborrow(), vfs)?;
 
             let ent = self.world.entities.get_mut(ent_id)?;
@@ -1330,17 +3062,19 @@ impl LevelState {
         let move_vector = vel * move_time_f;
         // TODO let mins =
         // todo!()
-        error!("TODO: `move_push`");
+        self.console_error("TODO: `move_push`");
         Ok(())
     }
 
     const MAX_BALLISTIC_COLLISIONS: usize = 4;
 
-    /// Movement function for freefalling entities.
-    pub fn move_ballistic(
+    /// Movement function for freefalling entities. The first trace is taken from `fall_trace`
+    /// when it is still current.
+    fn move_ballistic(
         &mut self,
         sim_time: Duration,
         ent_id: EntityId,
+        fall_trace: Option<FallTrace>,
         mut registry: Mut<Registry>,
         vfs: &Vfs,
     ) -> Result<(CollisionFlags, Option<Trace>), ProgsError> {
@@ -1356,6 +3090,10 @@ impl LevelState {
             .try_get(ent_id)?
             .velocity(&self.world.type_def)?;
         let mut trace_velocity = init_velocity;
+        let mut fall_trace = match fall_trace {
+            Some(fall) if fall.is_current(&self.world, ent_id)? => Some(fall),
+            _ => None,
+        };
 
         // Even when the entity collides with something along its path, it may
         // continue moving. This may occur when bouncing or sliding off a solid
@@ -1389,9 +3127,19 @@ impl LevelState {
                 .try_get(ent_id)?
                 .max(&self.world.type_def)?;
 
-            let (trace, hit_entity) =
-                self.world
-                    .trace_entity_move(ent_id, orig, min, max, end, CollideKind::Normal)?;
+            let (trace, hit_entity) = match fall_trace.take() {
+                Some(FallTrace {
+                    trace, hit_entity, ..
+                }) => (trace, hit_entity),
+                None => self.world.trace_entity_move(
+                    ent_id,
+                    orig,
+                    min,
+                    max,
+                    end,
+                    CollideKind::Normal,
+                )?,
+            };
 
             if trace.all_solid() {
                 // Entity is stuck in a wall.
@@ -1682,10 +3430,11 @@ impl LevelState {
         let volume = (volume * 255.) as _;
 
         let Some(sound_id) = self.sound_id(sound) else {
-            error!(
+            let msg = format!(
                 "Cannot find sound {} in precache",
                 self.string_table.get(sound).unwrap()
             );
+            self.console_error(msg);
             return Ok(());
         };
 
@@ -1695,6 +3444,13 @@ impl LevelState {
             .try_get(entity)?
             .load(&self.world.type_def, FieldAddrVector::Origin)?;
 
+        // like FitzQuake, drop sounds the protocol can't describe
+        let large_entity = entity.0 >= 1 << 13;
+        if !self.protocol.supports_index(sound_id) || (large_entity && !self.protocol.is_extended())
+        {
+            return Ok(());
+        }
+
         ServerCmd::Sound {
             volume: Some(volume),
             attenuation: Some(attenuation),
@@ -1703,7 +3459,7 @@ impl LevelState {
             sound_id: sound_id as _,
             position: position.into(),
         }
-        .serialize(&mut self.broadcast)?;
+        .serialize_with(&mut self.broadcast, self.protocol)?;
 
         Ok(())
     }
@@ -2226,20 +3982,26 @@ impl LevelState {
         let attenuation = (self.globals.get_float(GLOBAL_ADDR_ARG_3 as i16)? * 255.) as _;
 
         let Some(sound_id) = self.sound_id(sample) else {
-            error!(
+            let msg = format!(
                 "Cannot find sound {} in precache",
                 self.string_table.get(sample).unwrap()
             );
+            self.console_error(msg);
             return Ok(());
         };
 
+        if !self.protocol.supports_index(sound_id) {
+            self.console_warn(format!("Ambient sound {} needs sv_protocol 666", sound_id));
+            return Ok(());
+        }
+
         ServerCmd::SpawnStaticSound {
             origin: pos.into(),
             sound_id: sound_id as _,
             volume,
             attenuation,
         }
-        .serialize(&mut self.broadcast)?;
+        .serialize_with(&mut self.signon, self.protocol)?;
 
         Ok(())
     }
@@ -2277,6 +4039,11 @@ impl LevelState {
                 continue;
             };
 
+            // Unset strings never match, even when searching for the empty string
+            if s.is_empty() {
+                continue;
+            }
+
             if s == match_str {
                 self.globals.put_entity_id(ent, GLOBAL_ADDR_RETURN as i16)?;
                 return Ok(());
@@ -2288,6 +4055,69 @@ impl LevelState {
         Ok(())
     }
 
+    /// Returns the solid entities whose centers are within `radius` of `origin`.
+    pub fn entities_in_radius(
+        &self,
+        origin: Vector3<f32>,
+        radius: f32,
+    ) -> Result<Vec<EntityId>, ProgsError> {
+        let type_def = &self.world.type_def;
+        let mut found = Vec::new();
+        for id in self.world.entities.range(1..) {
+            let ent = self.world.entities.try_get(id)?;
+            if let EntitySolid::Not = ent.solid(type_def)? {
+                continue;
+            }
+
+            let center = ent.origin(type_def)? + (ent.min(type_def)? + ent.max(type_def)?) / 2.;
+            if (origin - center).magnitude() <= radius {
+                found.push(id);
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// Return a list of the solid entities whose centers are within a radius of a point.
+    ///
+    /// The entities are linked through their `chain` field, ending with the world entity. The
+    /// standard progs use this to tell whether a deathmatch spawn point is taken.
+    pub fn builtin_find_radius(&mut self) -> Result<(), ProgsError> {
+        let origin = Vector3::from(self.globals.get_vector(GLOBAL_ADDR_ARG_0 as i16)?);
+        let radius = self.globals.get_float(GLOBAL_ADDR_ARG_1 as i16)?;
+
+        let mut chain = EntityId(0);
+        for id in self.entities_in_radius(origin, radius)? {
+            let ent = self.world.entities.get_mut(id)?;
+            ent.store(&self.world.type_def, FieldAddrEntityId::Chain, chain)?;
+            chain = id;
+        }
+
+        self.globals
+            .put_entity_id(chain, GLOBAL_ADDR_RETURN as i16)?;
+
+        Ok(())
+    }
+
+    /// Return the next entity after the argument which is in use, or the world entity if there
+    /// are none left.
+    #[inline]
+    pub fn builtin_next_ent(&mut self) -> Result<(), ProgsError> {
+        let entity = self.globals.entity_id(GLOBAL_ADDR_ARG_0 as i16)?;
+
+        let next = self
+            .world
+            .entities
+            .range((Bound::Excluded(entity.0), Bound::Unbounded))
+            .next()
+            .unwrap_or(EntityId(0));
+
+        self.globals
+            .put_entity_id(next, GLOBAL_ADDR_RETURN as i16)?;
+
+        Ok(())
+    }
+
     #[inline]
     pub fn builtin_walk_move(
         &mut self,
@@ -2309,7 +4139,7 @@ impl LevelState {
             &self.world.type_def,
             Matrix3::from_angle_y(Deg(yaw)) * Vector3::unit_x() * dist,
         )?;
-        self.physics_step(this, Duration::try_seconds(1).unwrap(), vfs, registry)?;
+        self.physics_step(this, Duration::try_seconds(1).unwrap(), None, vfs, registry)?;
         self.world
             .entities
             .get_mut(this)?
@@ -2334,7 +4164,7 @@ impl LevelState {
         let ent = self.globals.entity_id(GLOBAL_ADDR_ARG_0 as i16)?;
 
         let Ok(entity) = self.world.entities.try_get(ent) else {
-            error!("Tried to call `make_static` on a non-existant entity");
+            self.console_error("Tried to call `make_static` on a non-existant entity");
             return Ok(());
         };
 
@@ -2353,19 +4183,28 @@ impl LevelState {
                 "Model not found in precache: {:?}",
                 self.string_table.get(model_name)
             ))
-        })? as _;
+        })?;
 
-        ServerCmd::SpawnStatic {
-            model_id,
-            frame_id,
-            colormap,
-            skin_id,
-            origin: origin.into(),
-            angles: angles.map(Deg).into(),
+        if self.protocol.supports_index(model_id) && self.protocol.supports_index(frame_id) {
+            ServerCmd::SpawnStatic {
+                model_id: model_id as _,
+                frame_id: frame_id as _,
+                colormap,
+                skin_id,
+                origin: origin.into(),
+                angles: angles.map(Deg).into(),
+            }
+            .serialize_with(&mut self.signon, self.protocol)?;
+        } else {
+            self.console_warn(format!(
+                "Static entity with model {} frame {} needs sv_protocol 666",
+                model_id, frame_id
+            ));
         }
-        .serialize(&mut self.broadcast)?;
 
-        self.world.entities.remove(ent)?;
+        // Static entities are entirely client-side, so the edict is no longer needed
+        self.new_entities.remove(&ent);
+        self.world.remove_entity(ent)?;
 
         Ok(())
     }
@@ -2378,7 +4217,50 @@ impl LevelState {
         let volume = self.globals.get_float(GLOBAL_ADDR_ARG_3 as i16)?;
         let attenuation = self.globals.get_float(GLOBAL_ADDR_ARG_4 as i16)?;
 
-        self.sound(entity, channel, sample, volume, attenuation)?;
+        self.sound(entity, channel, sample, volume, attenuation)?;
+
+        Ok(())
+    }
+
+    #[inline]
+    pub fn builtin_local_cmd(&mut self) -> Result<(), ProgsError> {
+        let cmd = self.globals.string_id(GLOBAL_ADDR_ARG_0 as i16)?;
+        let Some(cmd) = self.string_table.get(cmd) else {
+            return Err(ProgsError::with_msg("Invalid string for localcmd"));
+        };
+
+        self.local_cmds.push_str(&cmd.to_str());
+
+        Ok(())
+    }
+
+    pub fn builtin_change_level(&mut self) -> Result<(), ProgsError> {
+        let map = self.globals.string_id(GLOBAL_ADDR_ARG_0 as i16)?;
+        let Some(map) = self.string_table.get(map) else {
+            return Err(ProgsError::with_msg("Invalid string for changelevel"));
+        };
+
+        let map = map.to_str().into_owned();
+        self.change_level(&map);
+
+        Ok(())
+    }
+
+    #[inline]
+    pub fn builtin_particle(&mut self) -> Result<(), ProgsError> {
+        let origin = self.globals.get_vector(GLOBAL_ADDR_ARG_0 as i16)?;
+        let direction = self.globals.get_vector(GLOBAL_ADDR_ARG_1 as i16)?;
+        let color = self.globals.get_float(GLOBAL_ADDR_ARG_2 as i16)?;
+        let count = self.globals.get_float(GLOBAL_ADDR_ARG_3 as i16)?;
+
+        // The count is sent as a single byte, so large bursts are capped rather than wrapping
+        ServerCmd::Particle {
+            origin: origin.into(),
+            direction: direction.into(),
+            count: count.clamp(0., u8::MAX as f32) as u8,
+            color: color as u8,
+        }
+        .serialize_with(&mut self.broadcast, self.protocol)?;
 
         Ok(())
     }
@@ -2387,7 +4269,7 @@ impl LevelState {
     pub fn builtin_write_byte(&mut self) -> Result<(), ProgsError> {
         let dest = self.globals.get_float(GLOBAL_ADDR_ARG_0 as i16)?;
         if dest != 0. {
-            error!("TODO: Non-broadcast write ({})", dest as usize);
+            self.console_error(format!("TODO: Non-broadcast write ({})", dest as usize));
             return Ok(());
         }
         let val = self.globals.get_float(GLOBAL_ADDR_ARG_1 as i16)? as u8;
@@ -2399,7 +4281,7 @@ impl LevelState {
     pub fn builtin_write_char(&mut self) -> Result<(), ProgsError> {
         let dest = self.globals.get_float(GLOBAL_ADDR_ARG_0 as i16)?;
         if dest != 0. {
-            error!("TODO: Non-broadcast write ({})", dest as usize);
+            self.console_error(format!("TODO: Non-broadcast write ({})", dest as usize));
             return Ok(());
         }
         let val = self.globals.get_float(GLOBAL_ADDR_ARG_1 as i16)? as i8;
@@ -2411,7 +4293,7 @@ impl LevelState {
     pub fn builtin_write_short(&mut self) -> Result<(), ProgsError> {
         let dest = self.globals.get_float(GLOBAL_ADDR_ARG_0 as i16)?;
         if dest != 0. {
-            error!("TODO: Non-broadcast write ({})", dest as usize);
+            self.console_error(format!("TODO: Non-broadcast write ({})", dest as usize));
             return Ok(());
         }
         let val = self.globals.get_float(GLOBAL_ADDR_ARG_1 as i16)? as i16;
@@ -2423,7 +4305,7 @@ impl LevelState {
     pub fn builtin_write_long(&mut self) -> Result<(), ProgsError> {
         let dest = self.globals.get_float(GLOBAL_ADDR_ARG_0 as i16)?;
         if dest != 0. {
-            error!("TODO: Non-broadcast write ({})", dest as usize);
+            self.console_error(format!("TODO: Non-broadcast write ({})", dest as usize));
             return Ok(());
         }
         let val = self.globals.get_float(GLOBAL_ADDR_ARG_1 as i16)? as i32;
@@ -2435,11 +4317,11 @@ impl LevelState {
     pub fn builtin_write_coord(&mut self) -> Result<(), ProgsError> {
         let dest = self.globals.get_float(GLOBAL_ADDR_ARG_0 as i16)?;
         if dest != 0. {
-            error!("TODO: Non-broadcast write ({})", dest as usize);
+            self.console_error(format!("TODO: Non-broadcast write ({})", dest as usize));
             return Ok(());
         }
-        let val = (self.globals.get_float(GLOBAL_ADDR_ARG_1 as i16)? * 8.) as i16;
-        self.broadcast.write_i16::<LittleEndian>(val)?;
+        let val = self.globals.get_float(GLOBAL_ADDR_ARG_1 as i16)?;
+        self.protocol.write_coord(&mut self.broadcast, val)?;
         Ok(())
     }
 
@@ -2447,11 +4329,11 @@ impl LevelState {
     pub fn builtin_write_angle(&mut self) -> Result<(), ProgsError> {
         let dest = self.globals.get_float(GLOBAL_ADDR_ARG_0 as i16)?;
         if dest != 0. {
-            error!("TODO: Non-broadcast write ({})", dest as usize);
+            self.console_error(format!("TODO: Non-broadcast write ({})", dest as usize));
             return Ok(());
         }
-        let val = (self.globals.get_float(GLOBAL_ADDR_ARG_1 as i16)? * 256. / 360.) as u8;
-        self.broadcast.write_u8(val)?;
+        let val = self.globals.get_float(GLOBAL_ADDR_ARG_1 as i16)?;
+        self.protocol.write_angle(&mut self.broadcast, Deg(val))?;
         Ok(())
     }
 
@@ -2459,7 +4341,7 @@ impl LevelState {
     pub fn builtin_write_string(&mut self) -> Result<(), ProgsError> {
         let dest = self.globals.get_float(GLOBAL_ADDR_ARG_0 as i16)?;
         if dest != 0. {
-            error!("TODO: Non-broadcast write ({})", dest as usize);
+            self.console_error(format!("TODO: Non-broadcast write ({})", dest as usize));
             return Ok(());
         }
         let val = self.globals.string_id(GLOBAL_ADDR_ARG_1 as i16)?;
@@ -2473,10 +4355,10 @@ impl LevelState {
     pub fn builtin_write_entity(&mut self) -> Result<(), ProgsError> {
         let dest = self.globals.get_float(GLOBAL_ADDR_ARG_0 as i16)?;
         if dest != 0. {
-            error!("TODO: Non-broadcast write ({})", dest as usize);
+            self.console_error(format!("TODO: Non-broadcast write ({})", dest as usize));
             return Ok(());
         }
-        error!("TODO: Broadcast write entity");
+        self.console_error("TODO: Broadcast write entity");
         Ok(())
     }
 
@@ -2497,14 +4379,19 @@ impl LevelState {
         let kind = self.globals.get_float(GLOBAL_ADDR_ARG_2 as i16)?;
         let ent = self.globals.entity_id(GLOBAL_ADDR_ARG_3 as i16)?;
 
-        let (trace, hit_ent) = self.world.trace_entity_move(
+        // QuakeC attack functions run with `self` set to the attacking player
+        let shooter = self.globals.entity_id(GlobalAddrEntity::Self_ as i16)?;
+        let restore = self.rewind_targets(shooter)?;
+        let result = self.world.trace_entity_move(
             ent,
             v1.into(),
             Vector3::zero(),
             Vector3::zero(),
             v2.into(),
             CollideKind::from_f32(kind).unwrap_or_default(),
-        )?;
+        );
+        self.move_targets(restore)?;
+        let (trace, hit_ent) = result?;
 
         self.globals.put_float(
             if trace.all_solid() { 1. } else { 0. },
@@ -2563,21 +4450,53 @@ impl LevelState {
 }
 
 pub mod systems {
-    use crate::common::{
-        console::CmdName,
-        net::{self, ClientCmd, ClientMessage, GameType, ServerMessage, SignOnStage},
+    use crate::{
+        common::{
+            console::CmdName,
+            net::{ClientCmd, ClientMessage, GameType, MessageKind, PlayerColor, ServerMessage},
+        },
+        server::record::{ServerDemo, Target},
     };
 
     use super::*;
 
+    /// Tell a client how a command it sent with the wrong arguments should be used.
+    fn usage(usage: &str, out_packet: &mut Vec<u8>) {
+        ServerCmd::Print {
+            text: format!("usage: {}\n", usage).into(),
+        }
+        .serialize(out_packet)
+        .unwrap();
+    }
+
     pub fn recv_client_messages(
         mut server: ResMut<Session>,
         mut client_msgs: EventReader<ClientMessage>,
         mut server_messages: EventWriter<ServerMessage>,
         mut registry: ResMut<Registry>,
+        cvar_limits: Res<CvarLimits>,
         vfs: Res<Vfs>,
+        time: Res<Time<Real>>,
+        mut demo: Option<ResMut<ServerDemo>>,
     ) {
-        let mut out_packet = Vec::new();
+        let max_cmd_rate = registry.read_cvar::<u32>("sv_maxcmdrate").unwrap_or(0);
+        let cheats = registry.read_cvar::<u8>("sv_cheats").unwrap_or(0) != 0;
+        let others_playing = registry
+            .read_cvars::<SpawnVars>()
+            .is_ok_and(|vars| vars.multiplayer())
+            || server
+                .persist
+                .client_slots
+                .connected_clients()
+                .any(|id| id != ClientId::LOCAL);
+        // like the original game, a single player may use the cheat impulses whatever `sv_cheats`
+        let cheat_impulses = cheats || !others_playing;
+        let allow_download = registry.read_cvar::<u8>("sv_allowdownload").unwrap_or(0) != 0;
+        let teamplay = registry.read_cvar::<f32>("teamplay").unwrap_or(0.) != 0.;
+        let speed_check = registry.read_cvar::<u8>("sv_speedcheck").unwrap_or(0) != 0;
+        let max_speed = registry.read_cvar::<f32>("sv_maxspeed").unwrap_or(320.);
+        let now = time.elapsed();
+
         for ClientMessage {
             client_id,
             packet,
@@ -2586,11 +4505,28 @@ pub mod systems {
         {
             let mut packet = &packet[..];
             let client_id = *client_id;
+            // the client may have been kicked by an earlier message this frame
+            if server.client(client_id).is_none() {
+                continue;
+            }
+
+            let mut out_packet = Vec::new();
             loop {
                 // TODO: Should this be handled by the registry too?
                 match ClientCmd::deserialize(&mut packet) {
                     Ok(Some(cmd)) => match cmd {
                         ClientCmd::StringCmd { cmd } => {
+                            if !server.record_cmd(client_id, now, max_cmd_rate) {
+                                kick_flooding_client(
+                                    &mut server,
+                                    client_id,
+                                    &mut out_packet,
+                                    registry.reborrow(),
+                                    &vfs,
+                                );
+                                break;
+                            }
+
                             let Ok(cmds) = RunCmd::parse_many(&cmd) else {
                                 continue;
                             };
@@ -2602,10 +4538,21 @@ pub mod systems {
 
                                 match &*name {
                                     "prespawn" => {
-                                        // TODO: Error handling
-                                        assert!(args.is_empty());
+                                        if !args.is_empty() {
+                                            usage("prespawn", &mut out_packet);
+                                            continue;
+                                        }
+
+                                        if let Err(e) = server.clientcmd_prespawn(client_id) {
+                                            error!("prespawn: {}", e);
+                                            continue;
+                                        }
+
+                                        out_packet.extend_from_slice(&server.level.signon);
 
-                                        server.clientcmd_prespawn(client_id).unwrap();
+                                        if !cheats {
+                                            cvar_limits.serialize(&mut out_packet).unwrap();
+                                        }
 
                                         ServerCmd::SignOnStage {
                                             stage: SignOnStage::ClientInfo,
@@ -2613,24 +4560,126 @@ pub mod systems {
                                         .serialize(&mut out_packet)
                                         .unwrap();
                                     }
+                                    "download" => {
+                                        let [path] = &*args else {
+                                            error!("download: expected 1 argument");
+                                            continue;
+                                        };
+
+                                        match server.clientcmd_download(
+                                            client_id,
+                                            path,
+                                            allow_download,
+                                            &vfs,
+                                        ) {
+                                            Ok(true) => {}
+                                            Ok(false) => download::refusal()
+                                                .serialize(&mut out_packet)
+                                                .unwrap(),
+                                            Err(e) => {
+                                                error!("download: {}", e);
+                                                download::refusal()
+                                                    .serialize(&mut out_packet)
+                                                    .unwrap();
+                                            }
+                                        }
+                                    }
                                     "name" => {
-                                        // TODO: Error handling
-                                        assert!(args.len() == 1);
-
-                                        server
-                                            .clientcmd_name(
-                                                client_id,
-                                                args.into_iter().next().unwrap().to_owned().into(),
-                                            )
-                                            .unwrap();
+                                        let [new_name] = &*args else {
+                                            usage("name <name>", &mut out_packet);
+                                            continue;
+                                        };
+
+                                        if let Err(e) = server
+                                            .clientcmd_name(client_id, new_name.clone().into())
+                                        {
+                                            error!("name: {}", e);
+                                        }
                                     }
                                     "color" => {
-                                        assert!(args.len() == 2);
+                                        let [top, bottom] = &*args else {
+                                            usage("color <top> <bottom>", &mut out_packet);
+                                            continue;
+                                        };
+                                        let (Ok(top), Ok(bottom)) =
+                                            (top.parse::<u8>(), bottom.parse::<u8>())
+                                        else {
+                                            usage("color <top> <bottom>", &mut out_packet);
+                                            continue;
+                                        };
+
+                                        if let Err(e) = server.clientcmd_color(
+                                            client_id,
+                                            PlayerColor::new(top, bottom).bits(),
+                                        ) {
+                                            error!("color: {}", e);
+                                        }
+                                    }
+                                    "userinfo" => {
+                                        let [userinfo] = &*args else {
+                                            error!("userinfo: expected 1 argument");
+                                            continue;
+                                        };
+
+                                        let res = userinfo
+                                            .parse::<UserInfo>()
+                                            .map_err(failure::Error::from)
+                                            .and_then(|info| {
+                                                server.clientcmd_userinfo(client_id, info)
+                                            });
+                                        if let Err(e) = res {
+                                            error!("userinfo: {}", e);
+                                        }
+                                    }
+                                    "setinfo" => {
+                                        let [key, value] = &*args else {
+                                            error!("setinfo: expected 2 arguments");
+                                            continue;
+                                        };
+
+                                        if let Err(e) =
+                                            server.clientcmd_setinfo(client_id, key, value)
+                                        {
+                                            error!("setinfo: {}", e);
+                                        }
+                                    }
+                                    "say" | "say_team" => {
+                                        if args.is_empty() {
+                                            continue;
+                                        }
 
-                                        warn!("TODO: Set color");
+                                        let text = args.join(" ");
+                                        if let Err(e) = server.clientcmd_say(
+                                            client_id,
+                                            &text,
+                                            name == "say_team",
+                                            teamplay,
+                                        ) {
+                                            error!("{}: {}", name, e);
+                                        }
+                                    }
+                                    "god" | "notarget" | "noclip" | "fly" | "give" => {
+                                        let msg = if !cheats {
+                                            format!("{}: sv_cheats is disabled\n", name)
+                                        } else {
+                                            let args =
+                                                args.iter().map(|a| &**a).collect::<Vec<_>>();
+                                            server
+                                                .clientcmd_cheat(client_id, &name, &args)
+                                                .unwrap_or_else(|e| format!("{}\n", e))
+                                        };
+
+                                        if !msg.is_empty() {
+                                            ServerCmd::Print { text: msg.into() }
+                                                .serialize(&mut out_packet)
+                                                .unwrap();
+                                        }
                                     }
                                     "spawn" => {
-                                        server.clientcmd_spawn(client_id).unwrap();
+                                        if let Err(e) = server.clientcmd_spawn(client_id) {
+                                            error!("spawn: {}", e);
+                                            continue;
+                                        }
 
                                         ServerCmd::SignOnStage {
                                             stage: SignOnStage::Begin,
@@ -2639,12 +4688,19 @@ pub mod systems {
                                         .unwrap();
                                     }
                                     "begin" => {
-                                        // TODO: Error handling
-                                        assert!(args.is_empty());
+                                        if !args.is_empty() {
+                                            usage("begin", &mut out_packet);
+                                            continue;
+                                        }
 
-                                        server
-                                            .clientcmd_begin(client_id, registry.reborrow(), &*vfs)
-                                            .unwrap();
+                                        if let Err(e) = server.clientcmd_begin(
+                                            client_id,
+                                            registry.reborrow(),
+                                            &*vfs,
+                                        ) {
+                                            error!("begin: {}", e);
+                                            continue;
+                                        }
 
                                         let client_ent =
                                             server.client(client_id).unwrap().entity().unwrap();
@@ -2680,6 +4736,46 @@ pub mod systems {
                             button_flags,
                             impulse,
                         } => {
+                            if impulse != 0 && !server.record_cmd(client_id, now, max_cmd_rate) {
+                                kick_flooding_client(
+                                    &mut server,
+                                    client_id,
+                                    &mut out_packet,
+                                    registry.reborrow(),
+                                    &vfs,
+                                );
+                                break;
+                            }
+
+                            match server.check_move(client_id, now, send_time) {
+                                MoveCheck::Accept => {}
+                                MoveCheck::Future => continue,
+                                MoveCheck::TooFast { .. } if speed_check => continue,
+                                MoveCheck::TooFast { .. } => {}
+                            }
+                            server.record_ping(client_id, send_time);
+
+                            let movement = speedcheck::clamp_move(
+                                Vector3::new(fwd_move as f32, side_move as f32, up_move as f32),
+                                max_speed,
+                            );
+
+                            let impulse = if flood::is_cheat_impulse(impulse) && !cheat_impulses {
+                                warn!(
+                                    "Client {} tried cheat impulse {} with sv_cheats disabled",
+                                    client_id, impulse
+                                );
+                                ServerCmd::Print {
+                                    text: format!("impulse {}: sv_cheats is disabled\n", impulse)
+                                        .into(),
+                                }
+                                .serialize(&mut out_packet)
+                                .unwrap();
+                                0
+                            } else {
+                                impulse
+                            };
+
                             let Session { persist, level, .. } = &mut *server;
 
                             if let Some(entity) = persist
@@ -2690,11 +4786,40 @@ pub mod systems {
                                 entity
                                     .put_vector(
                                         &level.world.type_def,
-                                        [fwd_move as _, side_move as _, up_move as _],
+                                        movement.into(),
                                         FieldAddrVector::MoveDirection as _,
                                     )
                                     .unwrap();
+                                entity
+                                    .put_vector(
+                                        &level.world.type_def,
+                                        [angles.x.0, angles.y.0, angles.z.0],
+                                        FieldAddrVector::ViewAngle as _,
+                                    )
+                                    .unwrap();
+
+                                // like the original server, an impulse stays set until the
+                                // progs have handled it
+                                if impulse != 0 {
+                                    entity
+                                        .put_float(
+                                            &level.world.type_def,
+                                            impulse as f32,
+                                            FieldAddrFloat::Impulse as _,
+                                        )
+                                        .unwrap();
+                                }
+                            }
+                        }
+                        ClientCmd::Disconnect => {
+                            if let Err(e) =
+                                server.drop_client(client_id, registry.reborrow(), &*vfs)
+                            {
+                                error!("disconnect: {}", e);
                             }
+
+                            // Nothing after a disconnect is meaningful
+                            break;
                         }
                         other => {
                             warn!("TODO: Unimplemented command {:?}", other);
@@ -2707,14 +4832,45 @@ pub mod systems {
                     }
                 };
             }
+
+            if !out_packet.is_empty() {
+                record(
+                    demo.as_deref_mut(),
+                    server.level.time,
+                    Target::Client(client_id),
+                    &out_packet,
+                );
+                server_messages.send(ServerMessage {
+                    client_id,
+                    packet: out_packet,
+                    kind: MessageKind::Reliable,
+                });
+            }
         }
+    }
 
-        if !out_packet.is_empty() {
-            // TODO: Should not hard-code client id 0
-            server_messages.send(ServerMessage {
-                client_id: 0,
-                packet: out_packet,
-            });
+    /// Kicks a client which sent more string commands and impulses than `sv_maxcmdrate` allows.
+    fn kick_flooding_client(
+        server: &mut Session,
+        client_id: ClientId,
+        out_packet: &mut Vec<u8>,
+        registry: Mut<Registry>,
+        vfs: &Vfs,
+    ) {
+        warn!(
+            "Kicking client {} for flooding the server with commands",
+            client_id
+        );
+
+        ServerCmd::Print {
+            text: "Kicked by server: too many commands\n".into(),
+        }
+        .serialize(out_packet)
+        .unwrap();
+        ServerCmd::Disconnect.serialize(out_packet).unwrap();
+
+        if let Err(e) = server.drop_client(client_id, registry, vfs) {
+            error!("kick: {}", e);
         }
     }
 
@@ -2722,8 +4878,9 @@ pub mod systems {
         mut server: ResMut<Session>,
         mut registry: ResMut<Registry>,
         mut server_messages: EventWriter<ServerMessage>,
-        time: Res<Time<Virtual>>,
+        time: Res<Time<ServerClock>>,
         vfs: Res<Vfs>,
+        mut demo: Option<ResMut<ServerDemo>>,
     ) -> Result<(), ProgsError> {
         if !server.loading() {
             return Ok(());
@@ -2735,7 +4892,7 @@ pub mod systems {
             let server = &mut *server;
             server.level.physics(
                 &server.persist.client_slots,
-                Duration::from_std(time.elapsed())
+                Duration::from_std(time.context().timestep())
                     .map_err(|e| ProgsError::with_msg(format!("{}", e)))?,
                 registry.reborrow(),
                 &*vfs,
@@ -2744,74 +4901,262 @@ pub mod systems {
 
         server.state = SessionState::Active;
 
-        let teamplay = registry
-            .get_cvar("teamplay")
-            .and_then(|t| t.value().as_name());
+        let packet = server.serverinfo(&registry)?;
 
-        // Match string with `starts_with` so we can handle `?GameName`
-        let game_type = match teamplay {
-            Some(t) if t.starts_with("0") => GameType::Deathmatch,
-            Some(t) if t.starts_with("1") || t.starts_with("2") => GameType::CoOp,
-            // Invalid game type, default to DM
-            _ => GameType::Deathmatch,
+        if let Some(demo) = demo.as_deref_mut() {
+            demo.start_level();
+        }
+        record(demo.as_deref_mut(), server.level.time, Target::All, &packet);
+
+        // clients that were already in the game sign on again to the new level. Like the original
+        // server, they're told to reconnect first, which clients that only expect the server info
+        // at the start of a connection need
+        let mut reconnect = Vec::new();
+        ServerCmd::StuffText {
+            text: "reconnect\n".into(),
+        }
+        .serialize(&mut reconnect)?;
+
+        let connected = server
+            .persist
+            .client_slots
+            .connected_clients()
+            .collect::<Vec<_>>();
+        for client_id in connected {
+            let client = server.client_mut(client_id).unwrap();
+            let packet = if client.signon == SignOnStage::Not {
+                packet.clone()
+            } else {
+                [&reconnect[..], &packet[..]].concat()
+            };
+            client.signon = SignOnStage::Prespawn;
+            server_messages.send(ServerMessage {
+                client_id,
+                packet,
+                kind: MessageKind::Reliable,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Run `server.cfg` before anything else, so that its settings apply to the first map.
+    pub fn exec_server_config(vfs: Res<Vfs>, mut runcmd: EventWriter<RunCmd<'static>>) {
+        match config::load(&vfs) {
+            Ok(cmds) => {
+                runcmd.send_batch(cmds);
+            }
+            Err(e) => error!("Failed to load {}: {}", config::CONFIG_PATH, e),
+        }
+    }
+
+    pub fn save_server_config(
+        server_cvars: Res<ServerCvars>,
+        registry: Res<Registry>,
+        vfs: Res<Vfs>,
+    ) {
+        if let Err(e) = server_cvars.save(&registry, &vfs) {
+            error!("Failed to save {}: {}", config::CONFIG_PATH, e);
+        }
+    }
+
+    pub fn load_ban_list(mut bans: ResMut<BanList>, vfs: Res<Vfs>) {
+        match BanList::load(&vfs) {
+            Ok(loaded) => *bans = loaded,
+            Err(e) => error!("Failed to load ban list: {}", e),
+        }
+    }
+
+    /// Collect the bounds of every solid entity for `r_showbboxes`/`r_showtriggers`.
+    pub fn publish_debug_bounds(
+        server: Option<Res<Session>>,
+        registry: Res<Registry>,
+        mut bounds: ResMut<DebugBounds>,
+    ) {
+        bounds.boxes.clear();
+
+        let show_bboxes = registry.read_cvar::<u8>("r_showbboxes").unwrap_or(0) != 0;
+        let show_triggers = registry.read_cvar::<u8>("r_showtriggers").unwrap_or(0) != 0;
+        let Some(server) = server.filter(|_| show_bboxes || show_triggers) else {
+            return;
         };
 
-        let mut packet = Vec::new();
-        ServerCmd::ServerInfo {
-            protocol_version: net::PROTOCOL_VERSION as _,
-            max_clients: server.max_clients() as _,
-            game_type,
-            message: "Seismon server".into(),
-            model_precache: server
-                .level
-                .model_precache
-                .iter()
-                .map(ToOwned::to_owned)
-                .collect(),
-            sound_precache: server
-                .level
-                .sound_precache
-                .iter()
-                .map(ToOwned::to_owned)
-                .collect(),
+        let world = &server.level.world;
+        let type_def = &world.type_def;
+        // skip the world entity, which covers the whole map
+        for id in world.entities.range(1..) {
+            let Some(ent) = world.entities.get(id) else {
+                continue;
+            };
+            let trigger = match ent.solid(type_def) {
+                Ok(EntitySolid::Not) | Err(_) => continue,
+                Ok(EntitySolid::Trigger) => true,
+                Ok(_) => false,
+            };
+            if (trigger && !show_triggers) || (!trigger && !show_bboxes) {
+                continue;
+            }
+
+            let (Ok(origin), Ok(mins), Ok(maxs)) =
+                (ent.origin(type_def), ent.min(type_def), ent.max(type_def))
+            else {
+                continue;
+            };
+            bounds.boxes.push(DebugBox {
+                mins: origin + mins,
+                maxs: origin + maxs,
+                trigger,
+            });
         }
-        .serialize(&mut packet)?;
+    }
 
-        for (id, style) in server.level.lightstyles.iter().enumerate() {
-            let value = server.level.string_table.get(*style).unwrap_or_default();
-            if !value.is_empty() {
-                ServerCmd::LightStyle {
-                    id: id as _,
-                    value: value.into_owned(),
-                }
-                .serialize(&mut packet)?;
+    /// Pass commands queued by QuakeC to the console.
+    pub fn exec_local_cmds(
+        mut server: ResMut<Session>,
+        mut console_commands: EventWriter<RunCmd<'static>>,
+    ) {
+        if server.level.local_cmds.is_empty() {
+            return;
+        }
+
+        let text = mem::take(&mut server.level.local_cmds);
+        match RunCmd::parse_many(&text) {
+            Ok(cmds) => {
+                console_commands.send_batch(cmds.into_iter().map(RunCmd::into_owned));
             }
+            Err(e) => error!("Invalid localcmd \"{}\": {}", text.trim_end(), e),
         }
+    }
 
-        ServerCmd::SignOnStage {
-            stage: SignOnStage::Prespawn,
+    /// Gives each client its allowance of bytes for this frame, before anything is sent to it.
+    pub fn update_rate_limits(
+        mut server: ResMut<Session>,
+        time: Res<Time<ServerClock>>,
+        registry: Res<Registry>,
+    ) {
+        let max_rate = registry.read_cvar::<u32>("sv_maxrate").unwrap_or(0);
+        let persist = &mut server.persist;
+
+        for client_id in persist.client_slots.connected_clients().collect::<Vec<_>>() {
+            if let Some(client) = persist.client_mut(client_id) {
+                let rate = rate::client_rate(client.userinfo.rate(), max_rate);
+                client.rate_limit.begin_frame(time.delta(), rate);
+            }
         }
-        .serialize(&mut packet)?;
+    }
 
-        server_messages.send(ServerMessage {
-            client_id: 0,
-            packet,
-        });
+    /// Sends the next pieces of the files that clients have asked for.
+    pub fn send_downloads(
+        mut server: ResMut<Session>,
+        mut server_messages: EventWriter<ServerMessage>,
+    ) {
+        // as many pieces as fit in a message
+        let max_chunks = MAX_MESSAGE / (MAX_DOWNLOAD_CHUNK * 2);
+        let persist = &mut server.persist;
 
-        Ok(())
+        for client_id in persist.client_slots.connected_clients().collect::<Vec<_>>() {
+            let Some(client) = persist.client_mut(client_id) else {
+                continue;
+            };
+            let Some(download) = &mut client.download else {
+                continue;
+            };
+
+            // the player on a listen server can take as much as fits in a message, while anyone
+            // else gets what their allowance covers. The last piece may take them a little over,
+            // and they wait for the next one until they have caught up
+            let chunks = if client_id == ClientId::LOCAL {
+                max_chunks
+            } else {
+                client
+                    .rate_limit
+                    .remaining()
+                    .div_ceil(MAX_DOWNLOAD_CHUNK)
+                    .min(max_chunks)
+            };
+
+            let mut packet = Vec::new();
+            for chunk in iter::from_fn(|| download.next_chunk()).take(chunks) {
+                chunk.serialize(&mut packet).unwrap();
+            }
+
+            if download.is_finished() {
+                client.download = None;
+            }
+
+            if !packet.is_empty() {
+                client.rate_limit.sent(packet.len());
+                server_messages.send(ServerMessage {
+                    client_id,
+                    packet,
+                    kind: MessageKind::Reliable,
+                });
+            }
+        }
+    }
+
+    /// Add messages to the server demo, if one is being recorded.
+    fn record(demo: Option<&mut ServerDemo>, time: Duration, target: Target, data: &[u8]) {
+        if let Some(demo) = demo {
+            if let Err(e) = demo.write(time, target, data) {
+                error!("Couldn't write to {}: {}", demo.path(), e);
+            }
+        }
+    }
+
+    /// Tell the clients when the game is paused or resumed. Nothing else is sent while the game is
+    /// paused, so this goes in a message of its own.
+    fn send_pause(
+        server: &mut Session,
+        paused: bool,
+        server_messages: &mut EventWriter<ServerMessage>,
+        mut demo: Option<&mut ServerDemo>,
+    ) {
+        let slots = &mut server.persist.client_slots;
+        for client_id in slots.active_clients().collect::<Vec<_>>() {
+            let Some(client) = slots.get_mut(client_id) else {
+                continue;
+            };
+            if client.paused == paused {
+                continue;
+            }
+            client.paused = paused;
+
+            let mut packet = Vec::new();
+            ServerCmd::SetPause { paused }
+                .serialize(&mut packet)
+                .unwrap();
+            record(
+                demo.as_deref_mut(),
+                server.level.time,
+                Target::Client(client_id),
+                &packet,
+            );
+            server_messages.send(ServerMessage {
+                client_id,
+                packet,
+                kind: MessageKind::Reliable,
+            });
+        }
     }
 
     pub fn server_update(
         mut server: ResMut<Session>,
-        time: Res<Time<Fixed>>,
+        time: Res<Time<ServerClock>>,
         mut server_messages: EventWriter<ServerMessage>,
         mut registry: ResMut<Registry>,
         vfs: Res<Vfs>,
+        mut demo: Option<ResMut<ServerDemo>>,
     ) {
-        if server.loading()
-            || registry.read_cvar::<u8>("sv_paused").unwrap() != 0
-            || server.persist.client_slots.active_clients().count() == 0
-        {
+        let paused = registry.read_cvar::<u8>("sv_paused").unwrap() != 0;
+        send_pause(
+            &mut server,
+            paused,
+            &mut server_messages,
+            demo.as_deref_mut(),
+        );
+
+        if server.loading() || paused || server.persist.client_slots.active_clients().count() == 0 {
             return;
         }
 
@@ -2823,13 +5168,19 @@ pub mod systems {
             } => {
                 if let Err(e) = level.physics(
                     &persist.client_slots,
-                    Duration::from_std(time.elapsed()).unwrap(),
+                    Duration::from_std(time.delta()).unwrap(),
                     registry.reborrow(),
                     &*vfs,
                 ) {
-                    error!("Failed running frame: {}", Report::from_error(e));
+                    level.console_error(format!("Failed running frame: {}", Report::from_error(e)));
                     false
                 } else {
+                    let match_vars = registry.read_cvars::<MatchVars>().unwrap_or_default();
+                    if let Err(e) = level.update_scores(&mut persist.client_slots, match_vars) {
+                        level.console_error(format!("Failed updating scores: {}", e));
+                    }
+                    let lag_comp_vars = registry.read_cvars::<LagCompVars>().unwrap_or_default();
+                    level.update_lag_compensation(&persist.client_slots, lag_comp_vars);
                     true
                 }
             }
@@ -2839,45 +5190,99 @@ pub mod systems {
         if send_diff {
             let Session { persist, level, .. } = &mut *server;
 
+            // what every client is sent goes in the demo once, and the rest of each packet apart
+            record(
+                demo.as_deref_mut(),
+                level.time,
+                Target::All,
+                &level.broadcast,
+            );
+
             // TODO: Stop hardcoding `8` for max players
             for client_id in persist
                 .client_slots
                 .active_clients()
-                .collect::<ArrayVec<usize, 8>>()
+                .collect::<ArrayVec<ClientId, 8>>()
             {
-                let mut packet = Vec::new();
-
-                ServerCmd::Time {
-                    time: engine::duration_to_f32(level.time),
+                // as in the original server, the entities and the player's state are sent in a
+                // datagram, which is superseded by the next one, and everything else is sent
+                // reliably
+                let mut datagram = Vec::new();
+                let mut reliable = Vec::new();
+
+                // the player on a listen server isn't limited by a network connection
+                let send_entities = client_id == ClientId::LOCAL
+                    || persist
+                        .client(client_id)
+                        .map_or(true, |client| client.rate_limit.can_send());
+
+                // without a new time the client keeps showing the entities it has, rather than
+                // removing those that are missing from this update
+                if send_entities {
+                    ServerCmd::Time {
+                        time: engine::duration_to_f32(level.time),
+                    }
+                    .serialize(&mut datagram)
+                    .unwrap();
                 }
-                .serialize(&mut packet)
-                .unwrap();
+
+                let protocol = level.protocol;
+                // like FitzQuake, entities the protocol can't describe aren't sent at all
+                let sendable = |state: &EntityState| {
+                    protocol.supports_index(state.model_id)
+                        && protocol.supports_index(state.frame_id)
+                };
 
                 for entity_id in &level.new_entities {
                     if let Some(state) = level.entity_state(*entity_id) {
-                        state
-                            .spawn_baseline(entity_id.0 as _)
-                            .serialize(&mut packet)
-                            .unwrap();
-                        level.world.entities.get_mut(*entity_id).unwrap().baseline = state;
+                        let state = state.quantize(protocol);
+                        if sendable(&state) {
+                            state
+                                .spawn_baseline(entity_id.0 as _)
+                                .serialize_with(&mut reliable, protocol)
+                                .unwrap();
+                        }
+                        // baselines are sent without alpha or scale
+                        level.world.entities.get_mut(*entity_id).unwrap().baseline =
+                            state.baseline();
                     }
                 }
 
-                // Skip world entity
-                for ent in level.world.entities.iter().skip(1) {
-                    // TODO: Handle deletions
-                    let Ok(entity) = level.world.entities.try_get(ent) else {
-                        continue;
-                    };
+                let view_entity = persist.client(client_id).and_then(|c| c.entity());
+
+                if send_entities {
+                    // Skip world entity
+                    for ent in level.world.entities.iter().skip(1) {
+                        // TODO: Handle deletions
+                        let Ok(entity) = level.world.entities.try_get(ent) else {
+                            continue;
+                        };
+
+                        // the client stops drawing entities that are missing from an update, but
+                        // the player's own entity is always sent so the view keeps following it
+                        if Some(ent) != view_entity
+                            && entity.no_draw(&level.world.type_def).unwrap_or(false)
+                        {
+                            continue;
+                        }
 
-                    let update = entity
-                        .state(&level.world.type_def)
-                        .unwrap()
-                        .make_update(ent.0 as _, &entity.baseline);
+                        let state = entity
+                            .state(&level.world.type_def, &level.string_table)
+                            .unwrap()
+                            .quantize(protocol);
+                        if !sendable(&state) {
+                            continue;
+                        }
 
-                    ServerCmd::FastUpdate(update)
-                        .serialize(&mut packet)
-                        .unwrap();
+                        let mut update = state.make_update(ent.0 as _, &entity.baseline);
+                        // as in the original server, walking monsters are marked so that the
+                        // client spreads each step over the time it takes
+                        update.no_lerp =
+                            matches!(entity.move_kind(&level.world.type_def), Ok(MoveKind::Step));
+                        ServerCmd::FastUpdate(update)
+                            .serialize_with(&mut datagram, protocol)
+                            .unwrap();
+                    }
                 }
 
                 if let Some(entity) = persist
@@ -2896,47 +5301,164 @@ pub mod systems {
                                 .map(Deg)
                                 .into(),
                         }
-                        .serialize(&mut packet)
+                        .serialize_with(&mut reliable, protocol)
                         .unwrap();
                         entity
                             .put_float(&level.world.type_def, 0., FieldAddrFloat::FixAngle as i16)
                             .unwrap();
                     }
-                    // ServerCmd::PlayerData(PlayerData {
-                    //     view_height: todo!(),
-                    //     ideal_pitch: todo!(),
-                    //     punch_pitch: todo!(),
-                    //     velocity_x: todo!(),
-                    //     punch_yaw: todo!(),
-                    //     velocity_y: todo!(),
-                    //     punch_roll: todo!(),
-                    //     velocity_z: todo!(),
-                    //     items: todo!(),
-                    //     on_ground: todo!(),
-                    //     in_water: todo!(),
-                    //     weapon_frame: todo!(),
-                    //     armor: todo!(),
-                    //     weapon: todo!(),
-                    //     health: todo!(),
-                    //     ammo: todo!(),
-                    //     ammo_shells: todo!(),
-                    //     ammo_nails: todo!(),
-                    //     ammo_rockets: todo!(),
-                    //     ammo_cells: todo!(),
-                    //     active_weapon: todo!(),
-                    //     // TODO: Send player data
-                    // }).serialize(&mut packet).unwrap()
                 }
 
-                // We add broadcast packets at the end to ensure that entities can spawn before broadcasted
-                // events related to those entities
-                packet.extend_from_slice(&level.broadcast);
+                if let Some(ent_id) = view_entity.filter(|_| send_entities) {
+                    match level.player_data(ent_id) {
+                        Ok(data) => ServerCmd::PlayerData(data)
+                            .serialize_with(&mut datagram, protocol)
+                            .unwrap(),
+                        Err(e) => error!("Failed to read player data: {}", e),
+                    }
+                }
+
+                // We add broadcast packets after the baselines to ensure that entities can spawn
+                // before broadcasted events related to those entities
+                let broadcast_start = reliable.len();
+                reliable.extend_from_slice(&level.broadcast);
+
+                if let Some(client) = persist.client_mut(client_id) {
+                    reliable.append(&mut client.buffer);
+                }
+
+                if !level.console.is_empty()
+                    && persist.client(client_id).is_some_and(Client::privileged)
+                {
+                    ServerCmd::Print {
+                        text: level.console.clone().into(),
+                    }
+                    .serialize(&mut reliable)
+                    .unwrap();
+                }
+
+                if let Some(client) = persist.client_mut(client_id) {
+                    client.rate_limit.sent(reliable.len() + datagram.len());
+                }
+
+                if demo.is_some() {
+                    let broadcast_end = broadcast_start + level.broadcast.len();
+                    let own = [
+                        &reliable[..broadcast_start],
+                        &reliable[broadcast_end..],
+                        &datagram[..],
+                    ]
+                    .concat();
+                    record(
+                        demo.as_deref_mut(),
+                        level.time,
+                        Target::Client(client_id),
+                        &own,
+                    );
+                }
+
+                // the reliable part goes first, so that a local client sees the baselines before
+                // the updates that depend on them
+                for (packet, kind) in [
+                    (reliable, MessageKind::Reliable),
+                    (datagram, MessageKind::Unreliable),
+                ] {
+                    if !packet.is_empty() {
+                        server_messages.send(ServerMessage {
+                            client_id,
+                            packet,
+                            kind,
+                        });
+                    }
+                }
+            }
 
-                server_messages.send(ServerMessage { client_id, packet });
+            for ent in level.world.entities.iter().skip(1).collect::<Vec<_>>() {
+                if let Ok(entity) = level.world.entities.get_mut(ent) {
+                    entity
+                        .clear_transient_effects(&level.world.type_def)
+                        .unwrap();
+                }
             }
 
             level.broadcast.clear();
+            level.console.clear();
             level.new_entities.clear();
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_spawn_point() {
+        let spots = [EntityId(10), EntityId(11), EntityId(12)];
+        let free = |_| false;
+
+        // the rotation starts from the first, and carries on after the last one used
+        assert_eq!(next_spawn_point(&spots, None, free), Some(EntityId(10)));
+        assert_eq!(
+            next_spawn_point(&spots, Some(EntityId(10)), free),
+            Some(EntityId(11))
+        );
+        assert_eq!(
+            next_spawn_point(&spots, Some(EntityId(12)), free),
+            Some(EntityId(10))
+        );
+        // a spawn point that's gone starts the rotation again
+        assert_eq!(
+            next_spawn_point(&spots, Some(EntityId(5)), free),
+            Some(EntityId(10))
+        );
+
+        // blocked spawn points are skipped, unless they all are
+        let blocked = |spot| spot != EntityId(10);
+        assert_eq!(
+            next_spawn_point(&spots, Some(EntityId(10)), blocked),
+            Some(EntityId(10))
+        );
+        assert_eq!(
+            next_spawn_point(&spots, Some(EntityId(10)), |_| true),
+            Some(EntityId(11))
+        );
+        assert_eq!(next_spawn_point(&[], None, free), None);
+    }
+
+    #[test]
+    fn test_spawn_point_blocked() {
+        fn store(world: &mut World, id: EntityId, field: FieldAddrVector, value: [f32; 3]) {
+            world
+                .entities
+                .get_mut(id)
+                .unwrap()
+                .store(&world.type_def, field, value)
+                .unwrap();
+        }
+        use FieldAddrVector::{Maxs, Mins, Origin};
+
+        let mut world = World::from_box(
+            Vector3::new(-512.0, -512.0, -64.0),
+            Vector3::new(512.0, 512.0, 0.0),
+        );
+        let spot = world.alloc_uninitialized().unwrap();
+        let player = world.alloc_uninitialized().unwrap();
+        store(&mut world, spot, Origin, [0.0, 0.0, 24.0]);
+        store(&mut world, player, Mins, PLAYER_MINS.into());
+        store(&mut world, player, Maxs, PLAYER_MAXS.into());
+
+        store(&mut world, player, Origin, [20.0, 0.0, 24.0]);
+        assert!(spawn_point_blocked(&world, spot, &[player]));
+
+        // players that only touch the spawn point's box don't block it
+        store(&mut world, player, Origin, [32.0, 0.0, 24.0]);
+        assert!(!spawn_point_blocked(&world, spot, &[player]));
+        store(&mut world, player, Origin, [0.0, 0.0, 80.0]);
+        assert!(!spawn_point_blocked(&world, spot, &[player]));
+
+        // nor do players that aren't in the game
+        store(&mut world, player, Origin, [0.0, 0.0, 24.0]);
+        assert!(!spawn_point_blocked(&world, spot, &[]));
+    }
+}
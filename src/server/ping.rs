@@ -0,0 +1,81 @@
+//! Measuring each client's latency.
+//!
+//! As in the original server, every move a client sends is stamped with the server time of the
+//! last update it had received, so the server time at which the move arrives less its stamp is one
+//! round trip. A client's ping is the average of its last few round trips.
+
+use chrono::Duration;
+
+/// How many recent round trips a client's ping is averaged over.
+const NUM_PING_TIMES: usize = 16;
+
+/// The recent round trips of a client.
+#[derive(Debug, Clone)]
+pub struct PingTimes {
+    times: [Duration; NUM_PING_TIMES],
+    count: usize,
+}
+
+impl Default for PingTimes {
+    fn default() -> Self {
+        PingTimes {
+            times: [Duration::zero(); NUM_PING_TIMES],
+            count: 0,
+        }
+    }
+}
+
+impl PingTimes {
+    /// Records a move stamped with `send_time` that arrived at the server time `now`. Stamps from
+    /// before a level change can be later than the new level's clock, and are ignored.
+    pub fn record(&mut self, now: Duration, send_time: Duration) {
+        if send_time > now {
+            return;
+        }
+
+        self.times[self.count % NUM_PING_TIMES] = now - send_time;
+        self.count += 1;
+    }
+
+    /// The average of the recorded round trips, or zero if there aren't any yet.
+    pub fn average(&self) -> Duration {
+        let recorded = self.count.min(NUM_PING_TIMES);
+        if recorded == 0 {
+            return Duration::zero();
+        }
+
+        let total = self.times[..recorded]
+            .iter()
+            .fold(Duration::zero(), |total, &time| total + time);
+        total / recorded as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: i64) -> Duration {
+        Duration::try_milliseconds(millis).unwrap()
+    }
+
+    #[test]
+    fn test_average() {
+        let mut pings = PingTimes::default();
+        assert_eq!(pings.average(), Duration::zero());
+
+        pings.record(ms(1100), ms(1000));
+        pings.record(ms(1250), ms(1050));
+        assert_eq!(pings.average(), ms(150));
+
+        // a stamp from the previous level doesn't count
+        pings.record(ms(100), ms(5000));
+        assert_eq!(pings.average(), ms(150));
+
+        // only the most recent round trips are kept
+        for i in 0..NUM_PING_TIMES as i64 {
+            pings.record(ms(2000 + i * 10), ms(1950 + i * 10));
+        }
+        assert_eq!(pings.average(), ms(50));
+    }
+}
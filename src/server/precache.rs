@@ -2,6 +2,8 @@ use std::ops::Range;
 
 use arrayvec::{ArrayString, ArrayVec};
 
+use crate::common::bsp;
+
 /// Maximum permitted length of a precache path.
 const MAX_PRECACHE_PATH: usize = 64;
 
@@ -63,7 +65,7 @@ impl Precache {
     {
         let item = item.as_ref();
 
-        if item.is_empty() || item == "*0" {
+        if item.is_empty() || bsp::submodel_index(item) == Some(0) {
             return;
         }
 
@@ -0,0 +1,106 @@
+//! Throttling of the updates sent to each client, so that a server doesn't send more than a
+//! client's connection can take.
+//!
+//! Clients ask for a number of bytes per second with the `rate` key of their userinfo, which the
+//! server caps at `sv_maxrate`. Each frame earns the client an allowance of bytes at that rate, and
+//! while a client has used up its allowance the entity updates it would have been sent are held
+//! back until the next frame with room for them. Messages that can't be sent again later, such as
+//! baselines and sounds, are always sent.
+
+use std::time::Duration;
+
+/// The rate of clients that don't ask for one, in bytes per second.
+pub const DEFAULT_RATE: u32 = 10000;
+
+/// The lowest rate a client may ask for. Slower than this and the client would hardly ever see an
+/// update.
+const MIN_RATE: u32 = 1000;
+
+/// How much unused allowance a client can build up, in seconds at its rate.
+const MAX_BURST: f32 = 0.1;
+
+/// The rate a client gets, given the `requested` rate from its userinfo and the server's
+/// `max_rate`, where a `max_rate` of 0 means there is no limit.
+pub fn client_rate(requested: Option<u32>, max_rate: u32) -> u32 {
+    let max_rate = match max_rate {
+        0 => u32::MAX,
+        max => max.max(MIN_RATE),
+    };
+
+    requested.unwrap_or(DEFAULT_RATE).clamp(MIN_RATE, max_rate)
+}
+
+/// How many bytes a client may be sent.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimit {
+    /// Bytes that can be sent before the client is throttled. This is negative when the last
+    /// packet took the client over its allowance.
+    budget: f32,
+}
+
+impl RateLimit {
+    /// Adds the allowance for `elapsed` time at `rate` bytes per second. This is done once a
+    /// frame, before anything is sent to the client.
+    pub fn begin_frame(&mut self, elapsed: Duration, rate: u32) {
+        let rate = rate as f32;
+        self.budget = (self.budget + rate * elapsed.as_secs_f32()).min(rate * MAX_BURST);
+    }
+
+    /// Whether entity updates can be sent this frame, or should be held back.
+    pub fn can_send(&self) -> bool {
+        self.budget >= 0.0
+    }
+
+    /// How many bytes are left of the allowance.
+    pub fn remaining(&self) -> usize {
+        self.budget.max(0.0) as usize
+    }
+
+    /// Records a packet of `len` bytes sent to the client.
+    pub fn sent(&mut self, len: usize) {
+        self.budget -= len as f32;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_rate() {
+        assert_eq!(client_rate(None, 25000), DEFAULT_RATE);
+        assert_eq!(client_rate(Some(50000), 25000), 25000);
+        assert_eq!(client_rate(Some(50000), 0), 50000);
+        assert_eq!(client_rate(Some(10), 25000), MIN_RATE);
+    }
+
+    #[test]
+    fn test_throttle() {
+        let frame = Duration::from_millis(10);
+        let mut limit = RateLimit::default();
+
+        // 100 bytes a frame at 10000 bytes per second
+        limit.begin_frame(frame, 10000);
+        assert!(limit.can_send());
+        assert_eq!(limit.remaining(), 100);
+        limit.sent(250);
+        limit.begin_frame(frame, 10000);
+        assert!(!limit.can_send());
+        assert_eq!(limit.remaining(), 0);
+        limit.sent(0);
+        limit.begin_frame(frame, 10000);
+        assert!(limit.can_send());
+
+        // an idle client can't save up more than a burst
+        for _ in 0..100 {
+            limit.begin_frame(frame, 10000);
+        }
+        assert_eq!(limit.remaining(), 1000);
+        limit.sent(1000);
+        limit.begin_frame(frame, 10000);
+        assert!(limit.can_send());
+        limit.sent(1000);
+        limit.begin_frame(frame, 10000);
+        assert!(!limit.can_send());
+    }
+}
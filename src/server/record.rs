@@ -0,0 +1,219 @@
+//! Demos recorded by the server, with `sv_record`.
+//!
+//! A server demo keeps everything the server sends, so that a match can be archived even when no
+//! player records it. Like a QuakeWorld MVD, the messages that every client is sent are written
+//! once, and the rest of each client's messages are written apart and tagged with its slot. The
+//! view of any one player is then the blocks sent to everyone together with those sent to that
+//! player alone.
+//!
+//! The file starts with [`MAGIC`] and a little-endian `u32` version, followed by blocks of:
+//!
+//! ```text
+//! f32  level time in seconds
+//! u8   the client's slot, or 255 for every client
+//! u32  length
+//! ...  server messages
+//! ```
+//!
+//! All numbers are little-endian. The clients need the sign-on of a level before anything else
+//! makes sense, so a demo started during a level only begins with the next one. Downloads aren't
+//! recorded.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+};
+
+use bevy::prelude::*;
+use byteorder::{LittleEndian, ReadBytesExt as _, WriteBytesExt as _};
+use chrono::Duration;
+
+use crate::common::{engine, net::ClientId, vfs::Vfs};
+
+pub const MAGIC: [u8; 4] = *b"SVDM";
+pub const VERSION: u32 = 1;
+pub const EXTENSION: &str = "svd";
+
+/// The slot written for blocks sent to every client.
+const TARGET_ALL: u8 = u8::MAX;
+
+/// Who a block of messages was sent to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Target {
+    All,
+    Client(ClientId),
+}
+
+/// A block of messages read back from a server demo.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub time: Duration,
+    pub target: Target,
+    pub data: Vec<u8>,
+}
+
+/// A server demo being recorded.
+#[derive(Resource)]
+pub struct ServerDemo<W = BufWriter<File>> {
+    path: String,
+    writer: W,
+    /// Whether a level has started since recording began.
+    started: bool,
+    blocks: usize,
+}
+
+impl ServerDemo {
+    /// Create the demo called `name` in the game directory, adding the extension if it's missing.
+    pub fn create(vfs: &Vfs, name: &str) -> Result<ServerDemo, failure::Error> {
+        let path = match name.rsplit_once('.') {
+            Some((_, ext)) if ext == EXTENSION => name.to_owned(),
+            _ => format!("{}.{}", name, EXTENSION),
+        };
+        let file = vfs.write(&path)?;
+
+        Ok(ServerDemo::new(path, file)?)
+    }
+}
+
+impl<W: Write> ServerDemo<W> {
+    pub fn new(path: String, mut writer: W) -> io::Result<ServerDemo<W>> {
+        writer.write_all(&MAGIC)?;
+        writer.write_u32::<LittleEndian>(VERSION)?;
+
+        Ok(ServerDemo {
+            path,
+            writer,
+            started: false,
+            blocks: 0,
+        })
+    }
+
+    /// Mark the start of a level, from which point blocks are written.
+    pub fn start_level(&mut self) {
+        self.started = true;
+    }
+
+    /// Write the messages sent to `target` at level time `time`. Nothing is written before the
+    /// first level starts, or if there are no messages.
+    pub fn write(&mut self, time: Duration, target: Target, data: &[u8]) -> io::Result<()> {
+        if !self.started || data.is_empty() {
+            return Ok(());
+        }
+
+        let target = match target {
+            Target::All => TARGET_ALL,
+            Target::Client(ClientId(slot)) => u8::try_from(slot)
+                .ok()
+                .filter(|slot| *slot != TARGET_ALL)
+                .ok_or_else(|| io::Error::other(format!("No room for client slot {}", slot)))?,
+        };
+
+        self.writer
+            .write_f32::<LittleEndian>(engine::duration_to_f32(time))?;
+        self.writer.write_u8(target)?;
+        self.writer.write_u32::<LittleEndian>(data.len() as u32)?;
+        self.writer.write_all(data)?;
+        self.blocks += 1;
+
+        Ok(())
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The number of blocks written so far.
+    pub fn blocks(&self) -> usize {
+        self.blocks
+    }
+
+    /// Flush what has been written. Nothing more should be written afterwards.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Read every block of a server demo.
+pub fn read_blocks<R: Read>(mut reader: R) -> Result<Vec<Block>, failure::Error> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        failure::bail!("Not a server demo");
+    }
+    let version = reader.read_u32::<LittleEndian>()?;
+    if version != VERSION {
+        failure::bail!("Unsupported server demo version {}", version);
+    }
+
+    let mut blocks = Vec::new();
+    loop {
+        let time = match reader.read_f32::<LittleEndian>() {
+            Ok(time) => engine::duration_from_f32(time),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        };
+        let target = match reader.read_u8()? {
+            TARGET_ALL => Target::All,
+            slot => Target::Client(ClientId(slot as usize)),
+        };
+        let len = reader.read_u32::<LittleEndian>()?;
+        let mut data = Vec::new();
+        (&mut reader).take(len as u64).read_to_end(&mut data)?;
+        if data.len() != len as usize {
+            failure::bail!("Server demo ends in the middle of a block");
+        }
+
+        blocks.push(Block { time, target, data });
+    }
+
+    Ok(blocks)
+}
+
+/// The blocks that `client` was sent, in order.
+pub fn client_view(blocks: &[Block], client: ClientId) -> impl Iterator<Item = &Block> {
+    blocks
+        .iter()
+        .filter(move |block| block.target == Target::All || block.target == Target::Client(client))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(s: i64) -> Duration {
+        Duration::try_seconds(s).unwrap()
+    }
+
+    #[test]
+    fn test_server_demo() {
+        let mut demo = ServerDemo::new("match.svd".into(), Vec::new()).unwrap();
+
+        // nothing is kept until a level starts
+        demo.write(secs(0), Target::All, &[0]).unwrap();
+        demo.start_level();
+        demo.write(secs(1), Target::All, &[1]).unwrap();
+        demo.write(secs(1), Target::Client(ClientId(0)), &[2])
+            .unwrap();
+        demo.write(secs(1), Target::Client(ClientId(1)), &[3, 4])
+            .unwrap();
+        demo.write(secs(2), Target::Client(ClientId(1)), &[])
+            .unwrap();
+        assert_eq!(demo.blocks(), 3);
+        assert!(demo
+            .write(secs(2), Target::Client(ClientId(255)), &[5])
+            .is_err());
+        demo.finish().unwrap();
+
+        let blocks = read_blocks(&demo.writer[..]).unwrap();
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(blocks[0].time, secs(1));
+
+        let view = client_view(&blocks, ClientId(1))
+            .map(|block| block.data.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(view, vec![vec![1], vec![3, 4]]);
+
+        assert!(read_blocks(&b"DEMO"[..]).is_err());
+        assert!(read_blocks(&demo.writer[..demo.writer.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,217 @@
+//! Protection against clients that move faster than time allows.
+//!
+//! Each move a client sends is stamped with the level time of the last update the client had
+//! received. A stamp later than the level's own clock can only come from a tampered client, so
+//! such moves are dropped. The stamps of consecutive moves also tell how much game time each move
+//! stands for; if the moves in a window add up to noticeably more than the level time that has
+//! passed, or the client sends more moves than any frame rate would, it is flagged, and with
+//! `sv_speedcheck` on its moves are ignored until the window is over. The strength of every move
+//! is limited to `sv_maxspeed`, which is as fast as the physics would let the player go anyway.
+//!
+//! NetQuake moves don't say how long they are, so the stamps are the only measure of move time.
+//! They follow the level's clock, which runs at `host_timescale` and stops while the game is
+//! paused, whereas clients send a move every frame they draw, in real time.
+
+use std::time::Duration;
+
+use cgmath::{InnerSpace as _, Vector3};
+
+/// The length of the window in which moves are counted, in real time.
+const WINDOW: Duration = Duration::from_secs(5);
+
+/// How far ahead of the level's clock the moves in a window may get, to allow for network jitter.
+const TOLERANCE: Duration = Duration::from_millis(500);
+
+/// The most game time that a single move can stand for. A client that stalls doesn't get to
+/// spend the time it missed all at once.
+const MAX_MOVE_TIME: Duration = Duration::from_millis(250);
+
+/// The most moves a client may send in a second. Clients send one move a frame, and nobody plays
+/// at a higher frame rate than this.
+const MAX_MOVE_RATE: f32 = 1000.0;
+
+/// What to do with a move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveCheck {
+    Accept,
+    /// The move is stamped with a time the server hasn't reached yet.
+    Future,
+    /// The client's moves cover more time than has passed, or there are too many of them.
+    /// `flagged` is set on the first such move in a window.
+    TooFast {
+        flagged: bool,
+    },
+}
+
+/// Adds up the time covered by a client's moves in the current window, and counts them.
+#[derive(Debug, Clone, Default)]
+pub struct MoveClock {
+    /// When the window started, in real time and in level time.
+    window_start: Option<(Duration, Duration)>,
+    move_time: Duration,
+    moves: u32,
+    last_send_time: Option<Duration>,
+    flagged: bool,
+    /// The number of windows in which the client was too fast.
+    violations: u32,
+}
+
+impl MoveClock {
+    /// Checks a move stamped with `send_time` that arrived at the real time `now`, while the
+    /// level's clock stood at `level_time`.
+    pub fn record(
+        &mut self,
+        now: Duration,
+        send_time: Duration,
+        level_time: Duration,
+    ) -> MoveCheck {
+        if send_time > level_time {
+            return MoveCheck::Future;
+        }
+
+        let (real_start, level_start) = *self.window_start.get_or_insert((now, level_time));
+        // a new level starts its clock again, and the window with it
+        if now.saturating_sub(real_start) >= WINDOW || level_time < level_start {
+            self.window_start = Some((now, level_time));
+            self.move_time = Duration::ZERO;
+            self.moves = 0;
+            self.flagged = false;
+        }
+        let (real_start, level_start) = self.window_start.unwrap_or((now, level_time));
+
+        // stamps go backwards when a new level starts, which begins the count again
+        let move_time = match self.last_send_time {
+            Some(last) if send_time >= last => (send_time - last).min(MAX_MOVE_TIME),
+            _ => Duration::ZERO,
+        };
+        self.last_send_time = Some(send_time);
+        self.move_time += move_time;
+        self.moves = self.moves.saturating_add(1);
+
+        let level_elapsed = level_time - level_start;
+        let max_moves = MAX_MOVE_RATE * (now.saturating_sub(real_start) + TOLERANCE).as_secs_f32();
+        if self.move_time <= level_elapsed + TOLERANCE && self.moves as f32 <= max_moves {
+            return MoveCheck::Accept;
+        }
+
+        let flagged = !self.flagged;
+        if flagged {
+            self.flagged = true;
+            self.violations = self.violations.saturating_add(1);
+        }
+
+        MoveCheck::TooFast { flagged }
+    }
+
+    /// The number of windows in which the client's moves got ahead of the level's clock or came too
+    /// often.
+    pub fn violations(&self) -> u32 {
+        self.violations
+    }
+}
+
+/// Scales the movement of a move down so that it is no stronger than `max_speed`.
+pub fn clamp_move(movement: Vector3<f32>, max_speed: f32) -> Vector3<f32> {
+    let speed = movement.magnitude();
+    if speed > max_speed && speed > 0. {
+        movement * (max_speed / speed)
+    } else {
+        movement
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_record_in_step() {
+        let mut clock = MoveClock::default();
+        let start = Duration::from_secs(10);
+
+        for i in 0..100 {
+            let now = start + ms(i * 50);
+            assert_eq!(clock.record(now, ms(i * 50), ms(i * 50)), MoveCheck::Accept);
+        }
+        assert_eq!(clock.violations(), 0);
+    }
+
+    #[test]
+    fn test_record_too_fast() {
+        let mut clock = MoveClock::default();
+        let start = Duration::from_secs(10);
+
+        // twice as much move time as level time
+        let checks = (0..40)
+            .map(|i| clock.record(start + ms(i * 50), ms(i * 100), start + ms(i * 50)))
+            .collect::<Vec<_>>();
+        assert!(checks[..11].iter().all(|c| *c == MoveCheck::Accept));
+        assert_eq!(checks[11], MoveCheck::TooFast { flagged: true });
+        assert_eq!(checks[12], MoveCheck::TooFast { flagged: false });
+
+        assert_eq!(clock.violations(), 1);
+    }
+
+    #[test]
+    fn test_record_timescale() {
+        let start = Duration::from_secs(10);
+
+        // with `host_timescale 2` the level's clock, and so the stamps, run twice as fast as real
+        // time, and at 0.5 half as fast
+        for (real_step, level_step) in [(7, 14), (28, 14)] {
+            let mut clock = MoveClock::default();
+            for i in 0..1000 {
+                let level_time = ms(i * level_step);
+                assert_eq!(
+                    clock.record(start + ms(i * real_step), level_time, level_time),
+                    MoveCheck::Accept
+                );
+            }
+            assert_eq!(clock.violations(), 0);
+        }
+    }
+
+    #[test]
+    fn test_record_extra_moves() {
+        let mut clock = MoveClock::default();
+
+        // ten moves a millisecond, all stamped with the same update
+        let checks = (0..2000)
+            .map(|i| clock.record(ms(i / 10), ms(1000), ms(1000)))
+            .collect::<Vec<_>>();
+        let first = checks.iter().position(|c| *c != MoveCheck::Accept).unwrap();
+        assert!(first > 500);
+        assert_eq!(checks[first], MoveCheck::TooFast { flagged: true });
+        assert_eq!(clock.violations(), 1);
+    }
+
+    #[test]
+    fn test_record_future() {
+        let mut clock = MoveClock::default();
+        assert_eq!(clock.record(ms(0), ms(2000), ms(1000)), MoveCheck::Future);
+    }
+
+    #[test]
+    fn test_record_new_level() {
+        let mut clock = MoveClock::default();
+        assert_eq!(
+            clock.record(ms(0), ms(60_000), ms(60_000)),
+            MoveCheck::Accept
+        );
+        assert_eq!(clock.record(ms(50), ms(0), ms(0)), MoveCheck::Accept);
+        assert_eq!(clock.record(ms(100), ms(50), ms(50)), MoveCheck::Accept);
+    }
+
+    #[test]
+    fn test_clamp_move() {
+        let fast = clamp_move(Vector3::new(800., 800., 0.), 320.);
+        assert!((fast.magnitude() - 320.).abs() < 0.01);
+
+        let slow = Vector3::new(200., 0., 0.);
+        assert_eq!(clamp_move(slow, 320.), slow);
+    }
+}
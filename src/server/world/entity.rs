@@ -20,7 +20,7 @@ use std::{error::Error, fmt, iter};
 use crate::{
     common::{
         engine::duration_to_f32,
-        net::{EntityEffects, EntityState},
+        net::{self, EntityEffects, EntityState},
     },
     server::{
         progs::{EntityId, FieldDef, FunctionId, ProgsError, StringId, StringTable, Type},
@@ -385,6 +385,10 @@ bitflags! {
     }
 }
 
+/// The `EF_NODRAW` effect from DarkPlaces. Entities with this bit set are still simulated, but the
+/// server leaves them out of updates so clients never see them.
+const EF_NODRAW: u32 = 0b10000;
+
 // TODO: if this never gets used, remove it
 #[allow(dead_code)]
 fn float_addr(addr: usize) -> Result<FieldAddrFloat, ProgsError> {
@@ -539,7 +543,13 @@ impl Entity {
         }
     }
 
-    pub fn state(&self, type_def: &EntityTypeDef) -> Option<EntityState> {
+    /// Returns the entity as it is sent to clients. The `alpha` and `scale` fields of progs that
+    /// define them are included, for protocols that can carry them.
+    pub fn state(
+        &self,
+        type_def: &EntityTypeDef,
+        string_table: &StringTable,
+    ) -> Option<EntityState> {
         let (model_id, frame_id, colormap, skin_id, effects, origin, angles) = (
             self.get_float(type_def, FieldAddrFloat::ModelIndex as i16)
                 .ok()? as _,
@@ -549,10 +559,11 @@ impl Entity {
                 .ok()? as _,
             self.get_float(type_def, FieldAddrFloat::SkinId as i16)
                 .ok()? as _,
-            EntityEffects::from_bits(
-                self.get_float(type_def, FieldAddrFloat::SkinId as i16)
+            // bits the network protocol doesn't have, like `EF_NODRAW`, stay on the server
+            EntityEffects::from_bits_truncate(
+                self.get_float(type_def, FieldAddrFloat::Effects as i16)
                     .ok()? as _,
-            )?,
+            ),
             self.get_vector(type_def, FieldAddrVector::Origin as i16)
                 .ok()?
                 .into(),
@@ -563,6 +574,14 @@ impl Entity {
         let angles: Vector3<f32> = angles.into();
         let angles = angles.map(Deg);
 
+        // these fields are extensions that most progs don't have
+        let extension = |name: &str| {
+            type_def
+                .find(string_table, name)
+                .and_then(|def| self.get_float(type_def, def.offset as i16).ok())
+                .unwrap_or(0.)
+        };
+
         Some(EntityState {
             model_id,
             frame_id,
@@ -571,9 +590,71 @@ impl Entity {
             origin,
             angles,
             effects,
+            alpha: net::encode_alpha(extension("alpha")),
+            scale: net::encode_scale(extension("scale")),
         })
     }
 
+    /// Copies this entity into a new entity laid out according to a different set of field
+    /// definitions, such as those of a reloaded `progs.dat`.
+    ///
+    /// Fields are matched by name and are only copied if their types agree. Strings are interned
+    /// into the new string table. Entity, function and pointer fields are left zeroed, since their
+    /// values are only meaningful to the program that stored them.
+    pub fn remap(
+        &self,
+        old_def: &EntityTypeDef,
+        old_strings: &StringTable,
+        new_def: &EntityTypeDef,
+        new_strings: &mut StringTable,
+    ) -> Entity {
+        let mut remapped = Entity::new(new_def);
+        remapped.baseline = self.baseline.clone();
+
+        for old_field in old_def.field_defs() {
+            let Some(name) = old_strings.get(old_field.name_id) else {
+                continue;
+            };
+            let Some(new_field) = new_def.find(new_strings, name.to_str()) else {
+                continue;
+            };
+
+            if new_field.type_ != old_field.type_ {
+                debug!(
+                    "Not remapping field {}: type changed from {} to {}",
+                    name, old_field.type_, new_field.type_
+                );
+                continue;
+            }
+
+            let (old_ofs, new_ofs) = (old_field.offset as i16, new_field.offset as i16);
+            let res = match old_field.type_ {
+                Type::QFloat => self
+                    .get_bytes(old_ofs)
+                    .and_then(|bytes| remapped.put_bytes(bytes, new_ofs)),
+                Type::QVector => (0..3).try_for_each(|i| {
+                    remapped.put_bytes(self.get_bytes(old_ofs + i)?, new_ofs + i)
+                }),
+                Type::QString => self.string_id(old_def, old_ofs).and_then(|id| {
+                    let value = old_strings.get(id).unwrap_or_default();
+                    let id = if value.is_empty() {
+                        StringId(0)
+                    } else {
+                        new_strings.find_or_insert(value.to_str())
+                    };
+                    remapped.put_string_id(new_def, id, new_ofs)
+                }),
+                _ => Ok(()),
+            };
+
+            if let Err(e) = res {
+                warn!("Failed to remap field {}: {}", name, e);
+            }
+        }
+
+        remapped
+    }
+
     /// Returns a reference to the memory at the given address.
     pub fn get_addr(&self, addr: i16) -> Result<&[u8], EntityError> {
         if addr < 0 {
@@ -905,6 +986,20 @@ impl Entity {
         sv_gravity: f32,
         frame_time: Duration,
     ) -> Result<(), EntityError> {
+        let vel = self.velocity_with_gravity(type_def, string_table, sv_gravity, frame_time)?;
+        self.store(type_def, FieldAddrVector::Velocity, vel.into())?;
+
+        Ok(())
+    }
+
+    /// The velocity [`Self::apply_gravity`] would give the entity, without storing it.
+    pub fn velocity_with_gravity(
+        &self,
+        type_def: &EntityTypeDef,
+        string_table: &StringTable,
+        sv_gravity: f32,
+        frame_time: Duration,
+    ) -> Result<Vector3<f32>, EntityError> {
         let ent_gravity = match type_def.find(string_table, "gravity") {
             Some(def) => self.get_float(type_def, def.offset as i16)?,
             None => 1.0,
@@ -912,9 +1007,8 @@ impl Entity {
 
         let mut vel = self.velocity(type_def)?;
         vel.z -= ent_gravity * sv_gravity * duration_to_f32(frame_time);
-        self.store(type_def, FieldAddrVector::Velocity, vel.into())?;
 
-        Ok(())
+        Ok(vel)
     }
 
     /// Limits the entity's velocity by clamping each component (not the
@@ -945,6 +1039,20 @@ impl Entity {
         }
     }
 
+    /// Whether the progs have hidden this entity from clients with `EF_NODRAW`.
+    pub fn no_draw(&self, type_def: &EntityTypeDef) -> Result<bool, EntityError> {
+        let effects = self.get_float(type_def, FieldAddrFloat::Effects as i16)? as u32;
+        Ok(effects & EF_NODRAW != 0)
+    }
+
+    /// Clears effects that only last for a single update, as `SV_CleanupEnts` does for the muzzle
+    /// flash.
+    pub fn clear_transient_effects(&mut self, type_def: &EntityTypeDef) -> Result<(), EntityError> {
+        let effects = self.get_float(type_def, FieldAddrFloat::Effects as i16)? as u32;
+        let effects = effects & !(EntityEffects::MUZZLE_FLASH.bits() as u32);
+        self.put_float(type_def, effects as f32, FieldAddrFloat::Effects as i16)
+    }
+
     pub fn flags(&self, type_def: &EntityTypeDef) -> Result<EntityFlags, EntityError> {
         let flags_i = self.get_float(type_def, FieldAddrFloat::Flags as i16)? as u16;
         match EntityFlags::from_bits(flags_i) {
@@ -966,6 +1074,16 @@ impl Entity {
         Ok(())
     }
 
+    pub fn remove_flags(
+        &mut self,
+        type_def: &EntityTypeDef,
+        flags: EntityFlags,
+    ) -> Result<(), EntityError> {
+        let result = self.flags(type_def)? - flags;
+        self.put_float(type_def, result.bits() as f32, FieldAddrFloat::Flags as i16)?;
+        Ok(())
+    }
+
     pub fn owner(&self, type_def: &EntityTypeDef) -> Result<EntityId, EntityError> {
         Ok(self.entity_id(type_def, FieldAddrEntityId::Owner as i16)?)
     }
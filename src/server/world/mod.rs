@@ -42,7 +42,9 @@ use crate::{
         bsp::{BspCollisionHull, BspLeafContents},
         mdl,
         model::{Model, ModelKind},
-        parse, sprite,
+        parse,
+        physics::pmove,
+        sprite,
         vfs::Vfs,
     },
     server::progs::{
@@ -217,6 +219,11 @@ pub struct World {
     area_nodes: ArrayVec<AreaNode, NUM_AREA_NODES>,
     pub entities: Entities,
     models: Vec<Model>,
+
+    /// Bounds that entities were moved out of or into while relinks were being tracked, for
+    /// checking traces made before the moves.
+    relinked: Vec<(EntityId, Vector3<f32>, Vector3<f32>)>,
+    tracking_relinks: bool,
 }
 
 #[derive(Default, Debug, Clone)]
@@ -324,10 +331,18 @@ impl Entities {
             Bound::Excluded(bound) => *bound + 1,
             Bound::Included(bound) => *bound,
         };
-        self.slots
-            .focus()
-            .narrow(range)
+        let end = match range.end_bound() {
+            Bound::Unbounded => self.slots.len(),
+            Bound::Excluded(bound) => (*bound).min(self.slots.len()),
+            Bound::Included(bound) => (*bound + 1).min(self.slots.len()),
+        };
+
+        // `narrow` panics on an empty range, which is what continuing a search from the last
+        // entity produces
+        (start < end)
+            .then(|| self.slots.focus().narrow(start..end))
             .into_iter()
+            .flatten()
             .enumerate()
             .filter_map(move |(id, slot)| {
                 if let &AreaEntitySlot::Occupied(_) = slot {
@@ -407,6 +422,21 @@ impl Entities {
         Ok(EntityId(slot_id))
     }
 
+    /// Return a client entity's slot to the pool reserved for clients.
+    fn release_reserved(
+        &mut self,
+        entity_id: EntityId,
+        type_def: &EntityTypeDef,
+    ) -> Result<(), ProgsError> {
+        match self.slots.get(entity_id.0) {
+            Some(AreaEntitySlot::Occupied(_)) => {
+                self.slots[entity_id.0] = AreaEntitySlot::Reserved(Entity::new(type_def));
+                Ok(())
+            }
+            _ => Err(EntityError::Address(entity_id.0 as _).into()),
+        }
+    }
+
     fn free(&mut self, entity_id: EntityId) -> Result<(), ProgsError> {
         if entity_id.0 > self.slots.len() {
             return Err(ProgsError::with_msg(format!(
@@ -512,9 +542,21 @@ impl World {
             type_def,
             entities,
             models,
+            relinked: Vec::new(),
+            tracking_relinks: false,
         })
     }
 
+    /// A world that is nothing but a solid box, with no progs fields beyond the ones every
+    /// entity has.
+    #[cfg(test)]
+    pub(crate) fn from_box(min: Vector3<f32>, max: Vector3<f32>) -> World {
+        let type_def = EntityTypeDef::new(entity::STATIC_ADDRESS_COUNT, Box::default()).unwrap();
+        let model = Model::from_brush_model("maps/box.bsp", bsp::box_model(min, max));
+        let mut strings = StringTable::new(b"\0maps/box.bsp\0".to_vec());
+        World::new(vec![model], type_def, &mut strings).unwrap()
+    }
+
     pub fn diff<'a>(&'a self, other: &'a Entities) -> impl Iterator<Item = EntityId> + 'a {
         self.entities.diff(other)
     }
@@ -780,6 +822,12 @@ impl World {
             None => return Ok(()),
         };
 
+        if self.tracking_relinks {
+            let ent = self.entities.try_get(e_id)?;
+            let (abs_min, abs_max) = (ent.abs_min(&self.type_def)?, ent.abs_max(&self.type_def)?);
+            self.relinked.push((e_id, abs_min, abs_max));
+        }
+
         if self.area_nodes[area_id].triggers.remove(&e_id) {
             debug!("Unlinking entity {} from area triggers", e_id.0);
         } else if self.area_nodes[area_id].solids.remove(&e_id) {
@@ -845,6 +893,10 @@ impl World {
                 FieldAddrVector::AbsMax as i16,
             )?;
 
+            if self.tracking_relinks {
+                self.relinked.push((e_id, abs_min, abs_max));
+            }
+
             // Mark leaves containing entity for PVS.
             ent.leaf_count = 0;
             let model_index = ent.get_float(&self.type_def, FieldAddrFloat::ModelIndex as i16)?;
@@ -890,6 +942,7 @@ impl World {
         Ok(())
     }
 
+    /// Sets the size of an entity to fit its model. Alias models are sized to fit every frame.
     pub fn set_entity_model(&mut self, e_id: EntityId, model_id: usize) -> Result<(), ProgsError> {
         if model_id == 0 {
             self.set_entity_size(e_id, Vector3::zero(), Vector3::zero())?;
@@ -910,9 +963,37 @@ impl World {
     ) -> Result<(), ProgsError> {
         let ent = self.entities.get_mut(e_id)?;
         ent.set_min_max_size(&self.type_def, min, max)?;
+
+        // the entity collides with its new size before it is next linked
+        if self.tracking_relinks {
+            let origin = ent.origin(&self.type_def)?;
+            let margin = Vector3::new(1.0, 1.0, 1.0);
+            self.relinked
+                .push((e_id, origin + min - margin, origin + max + margin));
+        }
+
         Ok(())
     }
 
+    /// Start keeping the bounds of every entity that is linked, unlinked or resized, forgetting
+    /// any kept before.
+    pub fn track_relinks(&mut self) {
+        self.relinked.clear();
+        self.tracking_relinks = true;
+    }
+
+    pub fn stop_tracking_relinks(&mut self) {
+        self.tracking_relinks = false;
+    }
+
+    /// Whether an entity other than `e_id` has been linked, unlinked or resized anywhere within
+    /// `min` and `max` since [`Self::track_relinks`].
+    pub fn relinked_within(&self, e_id: EntityId, min: Vector3<f32>, max: Vector3<f32>) -> bool {
+        self.relinked.iter().any(|&(id, abs_min, abs_max)| {
+            id != e_id && (0..3).all(|i| abs_min[i] <= max[i] && abs_max[i] >= min[i])
+        })
+    }
+
     /// Unlink an entity from the world and remove it.
     pub fn remove_entity(&mut self, e_id: EntityId) -> Result<(), ProgsError> {
         self.unlink_entity(e_id)?;
@@ -920,6 +1001,13 @@ impl World {
         Ok(())
     }
 
+    /// Removes the entity of a disconnecting client, keeping its slot free for the next client.
+    pub fn remove_client_entity(&mut self, e_id: EntityId) -> Result<(), ProgsError> {
+        self.unlink_entity(e_id)?;
+        self.entities.release_reserved(e_id, &self.type_def)?;
+        Ok(())
+    }
+
     // TODO: handle the offset return value internally
     pub fn hull_for_entity(
         &self,
@@ -988,7 +1076,7 @@ impl World {
     }
 
     pub fn trace_entity_move(
-        &mut self,
+        &self,
         e_id: EntityId,
         start: Vector3<f32>,
         min: Vector3<f32>,
@@ -1208,4 +1296,102 @@ impl World {
     ) -> Result<Trace, ProgsError> {
         self.collide_move_with_entity(EntityId(0), start, min, max, end)
     }
+
+    /// Returns the contents of the world model at `point`. Entities are ignored.
+    pub fn point_contents(&self, point: Vector3<f32>) -> Result<BspLeafContents, ProgsError> {
+        let (hull, offset) = self.hull_for_entity(EntityId(0), Vector3::zero(), Vector3::zero())?;
+        hull.contents_at_point(point - offset)
+            .map_err(|e| ProgsError::with_msg(format!("Couldn't get point contents: {}", e)))
+    }
+}
+
+/// Lets the shared player movement code move a player entity through the world.
+pub struct PlayerMoveWorld<'a> {
+    pub world: &'a mut World,
+    pub e_id: EntityId,
+}
+
+impl pmove::MoveWorld for PlayerMoveWorld<'_> {
+    type Error = ProgsError;
+
+    fn trace(
+        &mut self,
+        start: Vector3<f32>,
+        end: Vector3<f32>,
+    ) -> Result<pmove::MoveTrace, ProgsError> {
+        let PlayerMoveWorld { world, e_id } = self;
+        pmove::solid_trace(
+            |start, end| {
+                let (trace, _) = world.trace_entity_move(
+                    *e_id,
+                    start,
+                    pmove::PLAYER_MIN,
+                    pmove::PLAYER_MAX,
+                    end,
+                    CollideKind::Normal,
+                )?;
+                Ok(trace)
+            },
+            start,
+            end,
+        )
+    }
+
+    fn contents(&mut self, point: Vector3<f32>) -> Result<BspLeafContents, ProgsError> {
+        self.world.point_contents(point)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relinked_within() {
+        let mut world = World::from_box(
+            Vector3::new(-512.0, -512.0, -64.0),
+            Vector3::new(512.0, 512.0, 0.0),
+        );
+        let ent_id = world.alloc_uninitialized().unwrap();
+        let other_id = EntityId(ent_id.0 + 1);
+        let near = (
+            Vector3::new(90.0, -10.0, 0.0),
+            Vector3::new(110.0, 10.0, 20.0),
+        );
+        let far = (
+            Vector3::new(-300.0, -300.0, 0.0),
+            Vector3::new(-200.0, -200.0, 20.0),
+        );
+
+        world
+            .entities
+            .get_mut(ent_id)
+            .unwrap()
+            .store(&world.type_def, FieldAddrVector::Origin, [100.0, 0.0, 16.0])
+            .unwrap();
+
+        // links before tracking starts aren't kept
+        world.link_entity(ent_id).unwrap();
+        world.track_relinks();
+        assert!(!world.relinked_within(other_id, near.0, near.1));
+
+        world
+            .set_entity_size(
+                ent_id,
+                Vector3::new(-16.0, -16.0, -16.0),
+                Vector3::new(16.0, 16.0, 16.0),
+            )
+            .unwrap();
+        world.link_entity(ent_id).unwrap();
+        assert!(world.relinked_within(other_id, near.0, near.1));
+        assert!(!world.relinked_within(other_id, far.0, far.1));
+        // an entity doesn't get in its own way
+        assert!(!world.relinked_within(ent_id, near.0, near.1));
+
+        // starting again forgets the earlier moves, but unlinking is kept like linking
+        world.track_relinks();
+        assert!(!world.relinked_within(other_id, near.0, near.1));
+        world.unlink_entity(ent_id).unwrap();
+        assert!(world.relinked_within(other_id, near.0, near.1));
+    }
 }
@@ -18,21 +18,16 @@
 //! Physics and collision detection.
 
 use crate::{
-    common::{bsp::BspLeafContents, math::Hyperplane},
+    common::{bsp::BspLeafContents, math::Hyperplane, physics::pmove},
     server::progs::EntityId,
 };
 
+pub use crate::common::physics::pmove::CollisionFlags;
+
 use bevy::prelude::*;
-use bitflags::bitflags;
 use cgmath::{InnerSpace, Vector3, Zero};
 use num_derive::FromPrimitive;
 
-/// Velocity in units/second under which a *component* (not the entire
-/// velocity!) is instantly reduced to zero.
-///
-/// This prevents objects from sliding indefinitely at low velocity.
-const STOP_THRESHOLD: f32 = 0.1;
-
 #[derive(Copy, Clone, Debug, Eq, FromPrimitive, PartialEq)]
 pub enum MoveKind {
     /// Does not move.
@@ -111,16 +106,10 @@ pub fn velocity_after_collision(
         flags |= CollisionFlags::VERTICAL;
     }
 
-    let change = (overbounce * initial.dot(surface_normal)) * surface_normal;
-    let mut out = initial - change;
-
-    for i in 0..3 {
-        if out[i].abs() < STOP_THRESHOLD {
-            out[i] = 0.0;
-        }
-    }
-
-    (out, flags)
+    (
+        pmove::clip_velocity(initial, surface_normal, overbounce),
+        flags,
+    )
 }
 
 /// Calculates a new velocity after collision with multiple surfaces.
@@ -376,14 +365,6 @@ impl Trace {
     }
 }
 
-bitflags! {
-    pub struct CollisionFlags: u32 {
-        const HORIZONTAL = 1;
-        const VERTICAL = 2;
-        const STOPPED = 4;
-    }
-}
-
 pub fn bounds_for_move(
     start: Vector3<f32>,
     min: Vector3<f32>,
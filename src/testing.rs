@@ -0,0 +1,227 @@
+//! Scripted sessions with a server and a headless client in the same [`App`], for end-to-end tests
+//! of the network protocol and the progs.
+//!
+//! A script drives the session with console commands, just as a player would type them, and then
+//! checks what the server and the client each think is going on. The Quake data files can't be
+//! distributed with the engine, so they are loaded from the directory named by `SEISMON_BASE_DIR`.
+//! The tests that need them only run when asked for, with `cargo test -- --ignored`.
+
+use std::{env, path::PathBuf, time::Duration};
+
+use bevy::{audio::AudioSource, prelude::*, time::TimeUpdateStrategy};
+use cgmath::Vector3;
+
+use crate::{
+    client::{self, Connection, ConnectionState, SeismonClientPlugin},
+    common::{
+        console::RunCmd,
+        net::{ClientId, ClientStat},
+    },
+    server::{SeismonServerPlugin, Session},
+};
+
+/// The environment variable naming the directory that contains `id1/`.
+pub const BASE_DIR_VAR: &str = "SEISMON_BASE_DIR";
+
+/// The time that passes with each update. This is shorter than a server tick at the default
+/// `sv_fps`, so every tick sees fresh input from the client.
+const FRAME_TIME: Duration = Duration::from_millis(10);
+
+/// How long to wait for the session to reach a state before failing the script.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Harness {
+    app: App,
+}
+
+impl Harness {
+    /// Starts the engine with no map loaded, or returns `None` if `SEISMON_BASE_DIR` isn't set.
+    pub fn new() -> Option<Harness> {
+        let base_dir = PathBuf::from(env::var_os(BASE_DIR_VAR)?);
+
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            // these are normally registered by the rendering and audio plugins
+            .init_asset::<Image>()
+            .init_asset::<TextureAtlasLayout>()
+            .init_asset::<AudioSource>()
+            .insert_resource(TimeUpdateStrategy::ManualDuration(FRAME_TIME))
+            .add_plugins(SeismonClientPlugin {
+                base_dir: Some(base_dir),
+                game: None,
+                main_menu: client::build_default,
+                headless: true,
+            })
+            .add_plugins(SeismonServerPlugin);
+
+        // `App::run` would do this before the first update
+        app.finish();
+        app.cleanup();
+        app.update();
+
+        Some(Harness { app })
+    }
+
+    /// Runs a console command, e.g. `map e1m1` or `+forward`, followed by a single frame.
+    pub fn exec(&mut self, cmd: &str) {
+        let run = RunCmd::parse(cmd)
+            .unwrap_or_else(|e| panic!("Bad command {:?}: {:?}", cmd, e))
+            .into_owned();
+        self.app.world.send_event(run);
+        self.app.update();
+    }
+
+    /// Lets the session run for `time`.
+    pub fn run_for(&mut self, time: Duration) {
+        let frames = time.as_micros().div_ceil(FRAME_TIME.as_micros());
+        for _ in 0..frames {
+            self.app.update();
+        }
+    }
+
+    /// Runs the session until `done` returns true, panicking if it takes too long.
+    pub fn wait_until(&mut self, what: &str, mut done: impl FnMut(&Harness) -> bool) {
+        let mut waited = Duration::ZERO;
+        while !done(self) {
+            assert!(waited < TIMEOUT, "Timed out waiting for {}", what);
+            self.app.update();
+            waited += FRAME_TIME;
+        }
+    }
+
+    /// Loads a map and waits for the client to enter it.
+    pub fn start_map(&mut self, map: &str) {
+        self.exec(&format!("map {}", map));
+        self.wait_until(&format!("sign-on to {}", map), |harness| {
+            harness.signed_on() && harness.session().map_name() == map
+        });
+    }
+
+    /// Whether the client has finished signing on to the current level.
+    pub fn signed_on(&self) -> bool {
+        matches!(
+            self.app.world.get_resource::<ConnectionState>(),
+            Some(ConnectionState::Connected(_))
+        )
+    }
+
+    pub fn session(&self) -> &Session {
+        self.app
+            .world
+            .get_resource::<Session>()
+            .expect("No server running")
+    }
+
+    pub fn connection(&self) -> &Connection {
+        self.app
+            .world
+            .get_resource::<Connection>()
+            .expect("Client is not connected")
+    }
+
+    /// A stat of the player, as last sent to the client.
+    pub fn stat(&self, stat: ClientStat) -> i32 {
+        self.connection().state().stats()[stat as usize]
+    }
+
+    /// Where the server has the player, or `None` if they haven't spawned.
+    pub fn server_origin(&self) -> Option<Vector3<f32>> {
+        let session = self.session();
+        let entity = session.client(ClientId::LOCAL)?.entity()?;
+
+        session.edict_vector(entity.0, "origin").map(Vector3::from)
+    }
+
+    /// Where the client has the player, or `None` if they haven't spawned.
+    pub fn client_origin(&self) -> Option<Vector3<f32>> {
+        let conn = self.connection();
+
+        conn.state()
+            .entities
+            .get(conn.view_entity_id())
+            .map(|ent| ent.origin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::InnerSpace as _;
+
+    use super::*;
+
+    fn harness() -> Harness {
+        Harness::new().unwrap_or_else(|| panic!("{} isn't set", BASE_DIR_VAR))
+    }
+
+    #[test]
+    #[ignore = "needs the Quake data files in SEISMON_BASE_DIR"]
+    fn test_sign_on() {
+        let mut harness = harness();
+
+        harness.start_map("e1m1");
+        // give the player time to land
+        harness.run_for(Duration::from_secs(1));
+
+        assert_eq!(harness.stat(ClientStat::Health), 100);
+
+        let server = harness.server_origin().unwrap();
+        let client = harness.client_origin().unwrap();
+        // coordinates are sent to the client in eighths of a unit
+        assert!((server - client).magnitude() < 0.25);
+    }
+
+    #[test]
+    #[ignore = "needs the Quake data files in SEISMON_BASE_DIR"]
+    fn test_find_radius() {
+        let mut harness = harness();
+
+        harness.start_map("e1m1");
+        harness.run_for(Duration::from_secs(1));
+
+        let player = harness.session().client(ClientId::LOCAL).unwrap().entity();
+        let player = player.unwrap().0;
+        let origin = harness.server_origin().unwrap();
+
+        let near = harness.session().edicts_in_radius(origin.into(), 32.);
+        assert!(near.contains(&player));
+
+        let far = harness
+            .session()
+            .edicts_in_radius((origin + Vector3::new(0., 0., 4096.)).into(), 32.);
+        assert!(!far.contains(&player));
+    }
+
+    #[test]
+    #[ignore = "needs the Quake data files in SEISMON_BASE_DIR"]
+    fn test_scripted_session() {
+        let mut harness = harness();
+
+        harness.start_map("e1m1");
+        harness.run_for(Duration::from_secs(1));
+
+        // move
+        let start = harness.server_origin().unwrap();
+        harness.exec("+forward");
+        harness.run_for(Duration::from_millis(500));
+        harness.exec("-forward");
+        harness.run_for(Duration::from_millis(500));
+        let moved = harness.server_origin().unwrap() - start;
+        assert!(moved.x.hypot(moved.y) > 16.);
+
+        // fire the shotgun
+        let shells = harness.stat(ClientStat::Shells);
+        harness.exec("+attack");
+        harness.run_for(Duration::from_millis(100));
+        harness.exec("-attack");
+        harness.run_for(Duration::from_millis(500));
+        assert!(harness.stat(ClientStat::Shells) < shells);
+
+        // change level
+        harness.start_map("e1m2");
+        assert!(harness
+            .connection()
+            .state()
+            .model_names
+            .contains_key("maps/e1m2.bsp"));
+    }
+}